@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Add blobs from local files and readers.
+//!
+//! Data is hashed incrementally while it is read, files of any size are hence added without
+//! loading them into memory. Files are hashed before they are imported: if the store already
+//! holds the same content it is not copied again and the existing blob is pinned instead.
+//!
+//! Content read from a stream can only be hashed while it is imported, the store keeps a single
+//! copy of identical content nonetheless.
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use futures_lite::Stream;
+use futures_util::stream;
+use iroh_blobs::store::{ImportMode, MapEntry, Store};
+use iroh_blobs::util::progress::IgnoreProgressSender;
+use iroh_blobs::{BlobFormat, Hash as IrohHash, HashAndFormat};
+use p2panda_core::Hash;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::download::READ_SIZE;
+use crate::gc::pin;
+use crate::import::pin_and_track;
+use crate::ingest::Ingest;
+
+/// Blob which was added to the store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AddedBlob {
+    pub hash: Hash,
+
+    /// Size of the blob in bytes.
+    pub size: u64,
+}
+
+pub(crate) async fn add_from_path<S: Store>(
+    store: &S,
+    ingest: &Ingest,
+    path: PathBuf,
+) -> Result<AddedBlob> {
+    let file = tokio::fs::File::open(&path).await?;
+    let (hash, size) = hash_reader(file).await?;
+
+    // Content is already stored, only make sure it's pinned like every imported blob.
+    let entry = store.get(&IrohHash::from_bytes(*hash.as_bytes())).await?;
+    if entry.is_some_and(|entry| entry.is_complete()) {
+        pin(store, hash).await?;
+        return Ok(AddedBlob { hash, size });
+    }
+
+    let (tag, size) = store
+        .import_file(
+            path,
+            ImportMode::default(),
+            BlobFormat::Raw,
+            IgnoreProgressSender::default(),
+        )
+        .await?;
+    let HashAndFormat { hash, .. } = *tag.inner();
+    pin_and_track(store, ingest, hash).await?;
+
+    Ok(AddedBlob {
+        hash: Hash::from_bytes(*hash.as_bytes()),
+        size,
+    })
+}
+
+pub(crate) async fn add_from_stream<S, R>(
+    store: &S,
+    ingest: &Ingest,
+    reader: R,
+) -> Result<AddedBlob>
+where
+    S: Store,
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let (tag, size) = store
+        .import_stream(
+            reader_stream(reader),
+            BlobFormat::Raw,
+            IgnoreProgressSender::default(),
+        )
+        .await?;
+    let HashAndFormat { hash, .. } = *tag.inner();
+    pin_and_track(store, ingest, hash).await?;
+
+    Ok(AddedBlob {
+        hash: Hash::from_bytes(*hash.as_bytes()),
+        size,
+    })
+}
+
+/// Hash all data of a reader, returns the hash and number of read bytes.
+async fn hash_reader<R: AsyncRead + Unpin>(mut reader: R) -> Result<(Hash, u64)> {
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0; READ_SIZE];
+    let mut size = 0;
+    loop {
+        let len = reader.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+        size += len as u64;
+    }
+    Ok((hasher.finalize().into(), size))
+}
+
+/// Turn a reader into a stream of chunks of at most `READ_SIZE` bytes.
+fn reader_stream<R>(reader: R) -> impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    Box::pin(stream::unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        let mut buf = BytesMut::with_capacity(READ_SIZE);
+        match reader.read_buf(&mut buf).await {
+            Ok(0) => None,
+            Ok(_) => Some((Ok(buf.freeze()), Some(reader))),
+            Err(err) => Some((Err(err), None)),
+        }
+    }))
+}
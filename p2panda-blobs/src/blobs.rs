@@ -1,36 +1,56 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use std::io;
+use std::ops::Range;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use anyhow::Result;
 use bytes::Bytes;
 use futures_util::Stream;
 use iroh_blobs::Hash as IrohHash;
 use iroh_blobs::downloader::Downloader;
-use iroh_blobs::store::{Map, Store};
-use iroh_blobs::util::local_pool::{Config as LocalPoolConfig, LocalPool};
+use iroh_blobs::store::{Map, MapEntry, Store};
+use iroh_blobs::util::local_pool::LocalPool;
 use p2panda_core::Hash;
 use p2panda_net::{Network, NetworkBuilder, TopicId};
 use p2panda_sync::TopicQuery;
+use tokio::io::AsyncRead;
 
-use crate::DownloadBlobEvent;
+use crate::add::{AddedBlob, add_from_path, add_from_stream};
 use crate::config::Config;
-use crate::download::download_blob;
+use crate::download::{
+    DownloadBlobEvent, DownloadStreamEvent, download_blob, download_blob_stream,
+};
+#[cfg(feature = "encryption")]
+use crate::encryption::{BlobKey, decrypt_blob, encrypt_stream};
 use crate::export::export_blob;
+use crate::gc::{GcPolicy, GcReport, gc, pin, unpin};
 use crate::import::{ImportBlobEvent, import_blob, import_blob_from_stream};
-use crate::protocol::{BLOBS_ALPN, BlobsProtocol};
+use crate::ingest::Ingest;
+use crate::network::{NetworkBuilderExt, NetworkExt};
+use crate::parallel::{ParallelOptions, download_blob_parallel};
+use crate::range::{FetchRangeEvent, fetch_range};
+#[cfg(feature = "s3")]
+use crate::shelf::{BlobShelf, restore_blob, shelve_blob};
 
 /// Blobs service offering storage, retrieval and synchronisation of content-addressed data.
-#[derive(Debug)]
+///
+/// The service can be created together with the network with `Blobs::from_builder` or taken from
+/// a network which was built with the blobs protocol registered, see `NetworkBuilderExt::blobs`.
+#[derive(Clone, Debug)]
 pub struct Blobs<T, S>
 where
     S: Store,
 {
-    downloader: Downloader,
-    network: Network<T>,
-    rt: LocalPool,
-    store: S,
+    pub(crate) downloader: Downloader,
+    pub(crate) ingest: Ingest,
+    pub(crate) network: Network<T>,
+    pub(crate) parallel: ParallelOptions,
+    pub(crate) rt: Arc<LocalPool>,
+    #[cfg(feature = "s3")]
+    pub(crate) shelf: Option<BlobShelf>,
+    pub(crate) store: S,
 }
 
 impl<T, S> Blobs<T, S>
@@ -54,32 +74,13 @@ where
         store: S,
         config: Config,
     ) -> Result<(Network<T>, Self)> {
-        // Calls `num_cpus::get()` to define thread count.
-        let local_pool_config = LocalPoolConfig::default();
-        let local_pool = LocalPool::new(local_pool_config);
-
         let network = network_builder
-            .protocol(
-                BLOBS_ALPN,
-                BlobsProtocol::new(store.clone(), local_pool.handle().clone()),
-            )
+            .blobs_with_config(store, config)
             .build()
             .await?;
-
-        let downloader = Downloader::with_config(
-            store.clone(),
-            network.endpoint().clone(),
-            local_pool.handle().clone(),
-            config.clone().into(),
-            config.into(),
-        );
-
-        let blobs = Self {
-            downloader,
-            network: network.clone(),
-            rt: local_pool,
-            store,
-        };
+        let blobs = network
+            .blobs()
+            .expect("blobs protocol was registered with the same store type");
 
         Ok((network, blobs))
     }
@@ -95,7 +96,13 @@ where
 
     /// Import a blob from the given path.
     pub async fn import_blob(&self, path: PathBuf) -> impl Stream<Item = ImportBlobEvent> {
-        import_blob(self.store.clone(), self.rt.handle().clone(), path).await
+        import_blob(
+            self.store.clone(),
+            self.ingest.clone(),
+            self.rt.handle().clone(),
+            path,
+        )
+        .await
     }
 
     /// Import a blob from the given stream.
@@ -103,7 +110,83 @@ where
     where
         D: Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
     {
-        import_blob_from_stream(self.store.clone(), self.rt.handle().clone(), data).await
+        import_blob_from_stream(
+            self.store.clone(),
+            self.ingest.clone(),
+            self.rt.handle().clone(),
+            data,
+        )
+        .await
+    }
+
+    /// Encrypt a blob from the given stream with the key and import the ciphertext.
+    ///
+    /// The resulting hash addresses the encrypted blob, which can be downloaded and served by
+    /// peers without knowing the key. Share the key with `BlobKey::wrap`.
+    #[cfg(feature = "encryption")]
+    pub async fn import_encrypted_blob_from_stream<D>(
+        &self,
+        data: D,
+        key: &BlobKey,
+    ) -> impl Stream<Item = ImportBlobEvent>
+    where
+        D: Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+    {
+        self.import_blob_from_stream(encrypt_stream(key, data))
+            .await
+    }
+
+    /// Decrypt a complete encrypted blob with the key and stream its plaintext.
+    ///
+    /// Every segment is authenticated before it is returned, the stream ends with an error if the
+    /// key is wrong or the blob was tampered with or truncated.
+    #[cfg(feature = "encryption")]
+    pub async fn decrypt_blob(
+        &self,
+        hash: Hash,
+        key: BlobKey,
+    ) -> impl Stream<Item = io::Result<Bytes>> {
+        decrypt_blob(self.store.clone(), self.rt.handle().clone(), hash, key).await
+    }
+
+    /// Add a blob from a file at the given path.
+    ///
+    /// The file is hashed incrementally without loading it into memory. Content which is already
+    /// in the store is not copied again. Returns the hash and size of the blob.
+    pub async fn add_from_path(&self, path: PathBuf) -> Result<AddedBlob> {
+        add_from_path(&self.store, &self.ingest, path).await
+    }
+
+    /// Add a blob from the given reader.
+    ///
+    /// The data is hashed incrementally while it is imported. Returns the hash and size of the
+    /// blob.
+    pub async fn add_from_stream<R>(&self, reader: R) -> Result<AddedBlob>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        add_from_stream(&self.store, &self.ingest, reader).await
+    }
+
+    /// Announce the given blobs to other peers interested in the topic.
+    ///
+    /// Only blobs which are complete in the local store are announced. Calling this method again
+    /// replaces the previously announced blobs for this topic.
+    ///
+    /// Downloads ask peers which announced a blob first, before falling back to all other known
+    /// peers.
+    pub async fn provide_blobs(&self, topic_id: [u8; 32], hashes: &[Hash]) -> Result<()> {
+        let mut provided = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            if self
+                .get(*hash)
+                .await?
+                .is_some_and(|entry| entry.is_complete())
+            {
+                provided.push(*hash);
+            }
+        }
+        self.network.provide_blobs(topic_id, &provided).await
     }
 
     /// Download a blob from a network peer.
@@ -111,12 +194,121 @@ where
         download_blob(
             self.network.clone(),
             self.downloader.clone(),
+            self.store.clone(),
+            self.ingest.clone(),
             self.rt.handle().clone(),
             hash,
         )
         .await
     }
 
+    /// Download a blob from several providers in parallel.
+    ///
+    /// The blob is split into segments which are requested from different providers at the same
+    /// time, providers which announced the blob are preferred. Segments of providers which fail
+    /// or stall are re-assigned to the others. This is considerably faster than `download_blob`
+    /// for large blobs available from several peers.
+    pub async fn download_blob_parallel(
+        &self,
+        hash: Hash,
+    ) -> impl Stream<Item = DownloadBlobEvent> {
+        download_blob_parallel(
+            self.network.clone(),
+            self.store.clone(),
+            self.ingest.clone(),
+            self.parallel.clone(),
+            self.rt.handle().clone(),
+            hash,
+        )
+        .await
+    }
+
+    /// Download a blob from a network peer and stream its verified data.
+    ///
+    /// Progress is reported while downloading, the data follows in order once the blob is
+    /// complete. Partially downloaded blobs are kept in the store, calling this method again,
+    /// for example after a restart, resumes the download from there.
+    pub async fn download_blob_stream(
+        &self,
+        hash: Hash,
+    ) -> impl Stream<Item = DownloadStreamEvent> {
+        download_blob_stream(
+            self.network.clone(),
+            self.downloader.clone(),
+            self.store.clone(),
+            self.ingest.clone(),
+            self.rt.handle().clone(),
+            hash,
+        )
+        .await
+    }
+
+    /// Fetch a byte range of a blob and stream its verified data.
+    ///
+    /// Only the chunks covering the range are requested from peers, which allows seeking in large
+    /// media files or loading a prefix of them without downloading the whole blob. Ranges are
+    /// read from the local store if the blob is complete there. Fetched data is not stored.
+    pub async fn fetch_range(
+        &self,
+        hash: Hash,
+        range: Range<u64>,
+    ) -> impl Stream<Item = FetchRangeEvent> {
+        fetch_range(
+            self.network.clone(),
+            self.store.clone(),
+            self.rt.handle().clone(),
+            hash,
+            range,
+        )
+        .await
+    }
+
+    /// Pin a blob, pinned blobs are never removed by garbage collection.
+    ///
+    /// Imported blobs are pinned automatically, downloaded blobs need to be pinned explicitly.
+    pub async fn pin(&self, hash: Hash) -> Result<()> {
+        pin(&self.store, hash).await
+    }
+
+    /// Unpin a blob, it can be removed by the next garbage collection.
+    pub async fn unpin(&self, hash: Hash) -> Result<()> {
+        unpin(&self.store, hash).await
+    }
+
+    /// Remove unpinned blobs according to the given policy.
+    ///
+    /// Blobs for which `retain` returns `true` are kept, for example because a document still
+    /// references their hash.
+    pub async fn gc<F>(&self, policy: &GcPolicy, retain: F) -> Result<GcReport>
+    where
+        F: Fn(&Hash) -> bool,
+    {
+        gc(&self.store, policy, retain).await
+    }
+
+    /// Move a complete blob to the object storage configured with `Config::shelf`.
+    ///
+    /// The blob is uploaded and verified against its hash, the local copy is unpinned afterwards
+    /// and removed by the next garbage collection according to its policy. Peers can still
+    /// download the blob from us, it is restored into the local store when they request it.
+    #[cfg(feature = "s3")]
+    pub async fn shelve_blob(&self, hash: Hash) -> Result<()> {
+        shelve_blob(&self.store, self.shelf()?, hash).await
+    }
+
+    /// Restore a shelved blob into the local store, for example before exporting it.
+    #[cfg(feature = "s3")]
+    pub async fn restore_blob(&self, hash: Hash) -> Result<()> {
+        restore_blob(&self.store, self.shelf()?, hash).await
+    }
+
+    #[cfg(feature = "s3")]
+    fn shelf(&self) -> Result<&BlobShelf> {
+        self.shelf
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("no object storage configured"))
+    }
+
     /// Export a blob to the given filesystem path.
     pub async fn export_blob(&self, hash: Hash, path: &PathBuf) -> Result<()> {
         export_blob(&self.store, hash, path).await?;
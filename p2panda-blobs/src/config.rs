@@ -1,10 +1,15 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 //! Alternative configuration API which can be passed into `Blobs::from_builder_with_config` constructor.
+use std::sync::Arc;
 use std::time::Duration;
 
 use iroh_blobs::downloader::{ConcurrencyLimits, RetryConfig};
 
+use crate::ingest::{Ingest, IngestHook};
+#[cfg(feature = "s3")]
+use crate::shelf::BlobShelf;
+
 /// Configuration parameters for the blobs service.
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -21,6 +26,20 @@ pub struct Config {
     /// The initial delay to wait before retrying a node. On subsequent failures, the retry delay
     /// will be multiplied with the number of failed retries.
     pub initial_retry_delay: Duration,
+    /// Maximum size of imported and downloaded blobs in bytes, larger blobs are rejected.
+    pub max_blob_size: Option<u64>,
+    /// Hook to validate imported and downloaded blobs before they are kept in the store.
+    pub ingest_hook: Option<Arc<dyn IngestHook>>,
+    /// Size in bytes of the segments a blob is split into when downloading it from several
+    /// providers in parallel.
+    pub parallel_segment_size: u64,
+    /// Maximum number of providers a single blob is downloaded from in parallel.
+    pub max_parallel_providers: usize,
+    /// Time after which a provider which stopped sending data loses its segment to another one.
+    pub stall_timeout: Duration,
+    /// Object storage blobs can be moved to with `Blobs::shelve_blob`.
+    #[cfg(feature = "s3")]
+    pub shelf: Option<BlobShelf>,
 }
 
 impl Default for Config {
@@ -35,6 +54,13 @@ impl Default for Config {
             max_concurrent_dials_per_hash: concurrency_limits.max_concurrent_dials_per_hash,
             max_retries_per_node: retry_config.max_retries_per_node,
             initial_retry_delay: retry_config.initial_retry_delay,
+            max_blob_size: None,
+            ingest_hook: None,
+            parallel_segment_size: 4 * 1024 * 1024,
+            max_parallel_providers: 4,
+            stall_timeout: Duration::from_secs(10),
+            #[cfg(feature = "s3")]
+            shelf: None,
         }
     }
 }
@@ -50,6 +76,12 @@ impl From<Config> for ConcurrencyLimits {
     }
 }
 
+impl From<Config> for Ingest {
+    fn from(val: Config) -> Self {
+        Ingest::new(val.max_blob_size, val.ingest_hook)
+    }
+}
+
 impl From<Config> for RetryConfig {
     fn from(val: Config) -> Self {
         RetryConfig {
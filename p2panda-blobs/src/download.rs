@@ -1,35 +1,85 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use anyhow::{Result, ensure};
+use anyhow::{Context, Result, ensure};
+use bytes::Bytes;
 use futures_lite::{Stream, StreamExt};
 use iroh::NodeAddr;
 use iroh_blobs::downloader::{DownloadRequest, Downloader};
 use iroh_blobs::get::Stats;
 use iroh_blobs::get::db::DownloadProgress;
+use iroh_blobs::store::{MapEntry, Store};
 use iroh_blobs::util::local_pool::LocalPoolHandle;
 use iroh_blobs::util::progress::{AsyncChannelProgressSender, ProgressSender};
 use iroh_blobs::{BlobFormat, Hash as IrohHash, HashAndFormat};
+use iroh_io::AsyncSliceReader;
 use p2panda_core::Hash;
 use p2panda_net::{Network, TopicId};
 use p2panda_sync::TopicQuery;
 use serde::{Deserialize, Serialize};
 use serde_error::Error as RpcError;
+use tracing::debug;
 
 use crate::from_node_addr;
+use crate::gc::track;
+use crate::ingest::{Ingest, IngestSource};
+
+/// Size of a BLAKE3 chunk, the unit in which blob data is verified.
+pub(crate) const CHUNK_SIZE: u64 = 1024;
+
+/// Number of bytes read from the store at once when streaming blob data.
+pub(crate) const READ_SIZE: usize = 64 * 1024;
 
 /// Status of a blob download attempt.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DownloadBlobEvent {
+    /// Parts of the blob were found in the local store, for example from a download which was
+    /// interrupted by a restart. Only the missing ranges are downloaded.
+    Resumed {
+        size: u64,
+    },
+
+    /// Number of verified bytes and chunks of the blob which are available so far.
+    Progress {
+        bytes: u64,
+        chunks: u64,
+    },
+
     Done,
     Abort(RpcError),
 }
 
-pub(crate) async fn download_blob<T: TopicQuery + TopicId + 'static>(
+/// Event of a streaming blob download.
+#[derive(Debug, Clone)]
+pub enum DownloadStreamEvent {
+    /// Parts of the blob were found in the local store, only the missing ranges are downloaded.
+    Resumed {
+        size: u64,
+    },
+
+    /// Number of verified bytes and chunks of the blob which are available so far.
+    Progress {
+        bytes: u64,
+        chunks: u64,
+    },
+
+    /// Verified data of the blob, in order. Data is only streamed once the download is complete.
+    Data(Bytes),
+
+    Abort(RpcError),
+}
+
+pub(crate) async fn download_blob<T, S>(
     network: Network<T>,
     downloader: Downloader,
+    store: S,
+    ingest: Ingest,
     pool_handle: LocalPoolHandle,
     hash: Hash,
-) -> impl Stream<Item = DownloadBlobEvent> {
+) -> impl Stream<Item = DownloadBlobEvent>
+where
+    T: TopicQuery + TopicId + 'static,
+    S: Store,
+{
     let (sender, receiver) = async_channel::bounded(1024);
     let progress = AsyncChannelProgressSender::new(sender);
     let hash_and_format = HashAndFormat {
@@ -38,7 +88,23 @@ pub(crate) async fn download_blob<T: TopicQuery + TopicId + 'static>(
     };
 
     pool_handle.spawn_detached(move || async move {
-        match download_queued(network, &downloader, hash_and_format, progress.clone()).await {
+        let result = match download_queued(
+            network,
+            &downloader,
+            hash,
+            hash_and_format,
+            progress.clone(),
+        )
+        .await
+        {
+            // Validate downloaded blobs and track them for garbage collection.
+            Ok(stats) => match ingest.validate(&store, hash, IngestSource::Download).await {
+                Ok(()) => track(&store, hash).await.map(|_| stats),
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        match result {
             Ok(stats) => {
                 progress.send(DownloadProgress::AllDone(stats)).await.ok();
             }
@@ -52,6 +118,13 @@ pub(crate) async fn download_blob<T: TopicQuery + TopicId + 'static>(
     });
 
     receiver.filter_map(|event| match event {
+        DownloadProgress::FoundLocal { size, .. } => {
+            Some(DownloadBlobEvent::Resumed { size: size.value() })
+        }
+        DownloadProgress::Progress { offset, .. } => Some(DownloadBlobEvent::Progress {
+            bytes: offset,
+            chunks: offset.div_ceil(CHUNK_SIZE),
+        }),
         DownloadProgress::AllDone(_) => Some(DownloadBlobEvent::Done),
         // @TODO: Use own error type here
         DownloadProgress::Abort(err) => Some(DownloadBlobEvent::Abort(err)),
@@ -62,20 +135,133 @@ pub(crate) async fn download_blob<T: TopicQuery + TopicId + 'static>(
     })
 }
 
+pub(crate) async fn download_blob_stream<T, S>(
+    network: Network<T>,
+    downloader: Downloader,
+    store: S,
+    ingest: Ingest,
+    pool_handle: LocalPoolHandle,
+    hash: Hash,
+) -> impl Stream<Item = DownloadStreamEvent>
+where
+    T: TopicQuery + TopicId + 'static,
+    S: Store,
+{
+    let (sender, receiver) = async_channel::bounded(64);
+    let events = download_blob(
+        network,
+        downloader,
+        store.clone(),
+        ingest,
+        pool_handle.clone(),
+        hash,
+    )
+    .await;
+
+    pool_handle.spawn_detached(move || async move {
+        let mut events = Box::pin(events);
+        while let Some(event) = events.next().await {
+            let event = match event {
+                DownloadBlobEvent::Resumed { size } => DownloadStreamEvent::Resumed { size },
+                DownloadBlobEvent::Progress { bytes, chunks } => {
+                    DownloadStreamEvent::Progress { bytes, chunks }
+                }
+                DownloadBlobEvent::Done => break,
+                DownloadBlobEvent::Abort(err) => {
+                    sender.send(DownloadStreamEvent::Abort(err)).await.ok();
+                    return;
+                }
+            };
+            if sender.send(event).await.is_err() {
+                return;
+            }
+        }
+
+        if let Err(err) = stream_blob(&store, hash, &sender).await {
+            sender
+                .send(DownloadStreamEvent::Abort(RpcError::new(&*err)))
+                .await
+                .ok();
+        }
+    });
+
+    receiver
+}
+
+/// Send the data of a complete blob from the store in order.
+async fn stream_blob<S: Store>(
+    store: &S,
+    hash: Hash,
+    sender: &async_channel::Sender<DownloadStreamEvent>,
+) -> Result<()> {
+    let hash = IrohHash::from_bytes(*hash.as_bytes());
+    let entry = store.get(&hash).await?.context("entry not there")?;
+    let size = entry.size().value();
+    let mut reader = entry.data_reader().await?;
+
+    let mut offset = 0;
+    while offset < size {
+        let bytes = reader.read_at(offset, READ_SIZE).await?;
+        if bytes.is_empty() {
+            break;
+        }
+        offset += bytes.len() as u64;
+        if sender.send(DownloadStreamEvent::Data(bytes)).await.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the addresses of all known peers, split into peers which announced that they provide
+/// the blob and all others.
+///
+/// Peers are left out or only dialed directly if the relay policy of the network refuses bulk
+/// transfers over relayed paths.
+pub(crate) async fn peer_addrs<T: TopicQuery + TopicId + 'static>(
+    network: &Network<T>,
+    hash: Hash,
+) -> Result<(Vec<NodeAddr>, Vec<NodeAddr>)> {
+    let addrs = network.known_peers().await?;
+    ensure!(!addrs.is_empty(), "no way to reach a node for download");
+
+    let providers = network.blob_providers(hash).await?;
+    let relay_usage = network.relay_usage();
+    let (provider_addrs, other_addrs): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .filter_map(|addr| relay_usage.bulk_transfer_addr(addr))
+        .partition(|addr| providers.contains(&addr.public_key));
+    Ok((
+        provider_addrs.into_iter().map(from_node_addr).collect(),
+        other_addrs.into_iter().map(from_node_addr).collect(),
+    ))
+}
+
+/// Download a blob from known peers.
+///
+/// Peers which announced that they provide the blob are asked first. Since announcements can
+/// yield false positives, all other known peers are asked when none of the providers had it.
 async fn download_queued<T: TopicQuery + TopicId + 'static>(
     network: Network<T>,
     downloader: &Downloader,
+    hash: Hash,
     hash_and_format: HashAndFormat,
     progress: AsyncChannelProgressSender<DownloadProgress>,
 ) -> Result<Stats> {
-    let addrs = network.known_peers().await?;
-    ensure!(!addrs.is_empty(), "no way to reach a node for download");
+    let (provider_addrs, other_addrs) = peer_addrs(&network, hash).await?;
+
+    if !provider_addrs.is_empty() {
+        let req =
+            DownloadRequest::new(hash_and_format, provider_addrs).progress_sender(progress.clone());
+        match downloader.queue(req).await.await {
+            Ok(stats) => return Ok(stats),
+            Err(err) => debug!(%hash, "download from announced providers failed: {err}"),
+        }
+    }
 
-    let iroh_addrs: Vec<NodeAddr> = addrs
-        .iter()
-        .map(|addr| from_node_addr(addr.clone()))
-        .collect();
-    let req = DownloadRequest::new(hash_and_format, iroh_addrs).progress_sender(progress);
+    ensure!(!other_addrs.is_empty(), "no peer provides blob {hash}");
+    let req = DownloadRequest::new(hash_and_format, other_addrs).progress_sender(progress);
     let handle = downloader.queue(req).await;
 
     let stats = handle.await?;
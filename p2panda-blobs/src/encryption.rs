@@ -0,0 +1,383 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! End-to-end encryption of blobs.
+//!
+//! Every blob is encrypted with its own `BlobKey` before it is imported, peers storing and serving
+//! the blob only ever see the ciphertext. The key is either random or derived from the blob's
+//! content and a group secret ("convergent encryption"), which results in the same ciphertext
+//! for the same content and allows deduplicating blobs within the group.
+//!
+//! To share the blob, the key is wrapped with a secret shared within the group, for example the
+//! current group secret of an encrypted topic, and carried in the operation referencing the blob.
+//! The wrapped key is bound to the hash of the encrypted blob and can't be moved to another one.
+//!
+//! Blobs are encrypted in segments of 64 KiB with XChaCha20-Poly1305, following the STREAM
+//! construction: every segment is authenticated on its own and the last one is marked, which
+//! detects reordered and truncated ciphertexts while allowing to decrypt large blobs without
+//! holding them in memory.
+use std::io;
+
+use anyhow::{Context, Result, anyhow, ensure};
+use bytes::{Bytes, BytesMut};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use futures_lite::{Stream, StreamExt};
+use futures_util::stream;
+use iroh_blobs::Hash as IrohHash;
+use iroh_blobs::store::{MapEntry, Store};
+use iroh_blobs::util::local_pool::LocalPoolHandle;
+use iroh_io::AsyncSliceReader;
+use p2panda_core::Hash;
+use serde::{Deserialize, Serialize};
+
+/// Number of plaintext bytes in every segment but the last.
+const SEGMENT_LEN: usize = 64 * 1024;
+
+/// Length of the authentication tag appended to every encrypted segment.
+const TAG_LEN: usize = 16;
+
+/// Number of ciphertext bytes of every segment but the last.
+pub(crate) const ENCRYPTED_SEGMENT_LEN: usize = SEGMENT_LEN + TAG_LEN;
+
+/// Context for deriving convergent keys from group secrets.
+const CONVERGENT_KEY_CONTEXT: &str = "p2panda-blobs 2025-04 convergent blob key";
+
+/// Associated data when wrapping blob keys.
+const WRAP_CONTEXT: &[u8] = b"p2panda-blobs wrapped blob key";
+
+/// Symmetric key encrypting a single blob.
+///
+/// A key must only be used to encrypt one blob.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BlobKey([u8; 32]);
+
+impl BlobKey {
+    /// Generate a random key.
+    pub fn random() -> Self {
+        Self(XChaCha20Poly1305::generate_key(&mut OsRng).into())
+    }
+
+    /// Derive the key from the hash of the unencrypted blob and a group secret.
+    ///
+    /// Members of the same group encrypting the same content end up with the same blob. Only
+    /// group members can derive the key, others can't tell if two encrypted blobs have the same
+    /// content.
+    pub fn convergent(group_secret: &[u8; 32], plaintext_hash: &Hash) -> Self {
+        let mut hasher = blake3::Hasher::new_derive_key(CONVERGENT_KEY_CONTEXT);
+        hasher.update(group_secret);
+        hasher.update(plaintext_hash.as_bytes());
+        Self(*hasher.finalize().as_bytes())
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Wrap the key with a group secret to share it in the operation referencing the blob.
+    pub fn wrap(&self, group_secret: &[u8; 32], blob_hash: &Hash) -> Result<WrappedBlobKey> {
+        let cipher = XChaCha20Poly1305::new(group_secret.into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &self.0,
+                    aad: &wrap_aad(blob_hash),
+                },
+            )
+            .map_err(|_| anyhow!("failed to wrap blob key"))?;
+
+        Ok(WrappedBlobKey {
+            nonce: nonce.into(),
+            ciphertext,
+        })
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+impl std::fmt::Debug for BlobKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print secret key material.
+        f.debug_tuple("BlobKey").field(&"***").finish()
+    }
+}
+
+/// Blob key encrypted with a group secret, to be carried in the operation referencing the blob.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrappedBlobKey {
+    nonce: [u8; 24],
+    ciphertext: Vec<u8>,
+}
+
+impl WrappedBlobKey {
+    /// Unwrap the key with the group secret it was wrapped with.
+    ///
+    /// Fails if the secret is wrong, the wrapped key was tampered with or belongs to another
+    /// blob.
+    pub fn unwrap(&self, group_secret: &[u8; 32], blob_hash: &Hash) -> Result<BlobKey> {
+        let cipher = XChaCha20Poly1305::new(group_secret.into());
+        let plaintext = cipher
+            .decrypt(
+                XNonce::from_slice(&self.nonce),
+                Payload {
+                    msg: &self.ciphertext,
+                    aad: &wrap_aad(blob_hash),
+                },
+            )
+            .map_err(|_| anyhow!("failed to unwrap blob key"))?;
+        let key: [u8; 32] = plaintext
+            .try_into()
+            .map_err(|_| anyhow!("invalid blob key length"))?;
+        Ok(BlobKey(key))
+    }
+}
+
+fn wrap_aad(blob_hash: &Hash) -> Vec<u8> {
+    [WRAP_CONTEXT, blob_hash.as_bytes()].concat()
+}
+
+/// Nonce of a segment, made of its index and a flag marking the last segment.
+///
+/// Since every key encrypts only one blob, segment nonces never repeat for the same key.
+fn segment_nonce(index: u32, last: bool) -> XNonce {
+    let mut nonce = [0; 24];
+    nonce[19..23].copy_from_slice(&index.to_be_bytes());
+    nonce[23] = last as u8;
+    nonce.into()
+}
+
+/// Encrypts a blob in segments.
+pub(crate) struct Encryptor {
+    cipher: XChaCha20Poly1305,
+    buffer: BytesMut,
+    index: u32,
+}
+
+impl Encryptor {
+    pub fn new(key: &BlobKey) -> Self {
+        Self {
+            cipher: key.cipher(),
+            buffer: BytesMut::new(),
+            index: 0,
+        }
+    }
+
+    /// Add plaintext to encrypt.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Returns the next encrypted segment as soon as it is complete.
+    ///
+    /// Segments are only encrypted once more data followed them, since we don't know yet if they
+    /// are the last one otherwise.
+    pub fn next_segment(&mut self) -> Option<Result<Bytes>> {
+        if self.buffer.len() <= SEGMENT_LEN {
+            return None;
+        }
+
+        let segment = self.buffer.split_to(SEGMENT_LEN);
+        Some(self.encrypt(&segment, false))
+    }
+
+    /// Encrypt the remaining plaintext as the last segment.
+    pub fn finish(mut self) -> Result<Bytes> {
+        let segment = self.buffer.split();
+        self.encrypt(&segment, true)
+    }
+
+    fn encrypt(&mut self, segment: &[u8], last: bool) -> Result<Bytes> {
+        let ciphertext = self
+            .cipher
+            .encrypt(&segment_nonce(self.index, last), segment)
+            .map_err(|_| anyhow!("failed to encrypt blob"))?;
+        self.index = self
+            .index
+            .checked_add(1)
+            .ok_or_else(|| anyhow!("blob too large to encrypt"))?;
+        Ok(ciphertext.into())
+    }
+}
+
+/// Returns the number of segments of an encrypted blob with the given size.
+pub(crate) fn num_segments(size: u64) -> u64 {
+    size.div_ceil(ENCRYPTED_SEGMENT_LEN as u64).max(1)
+}
+
+/// Decrypt a segment of an encrypted blob.
+pub(crate) fn decrypt_segment(
+    key: &BlobKey,
+    index: u64,
+    last: bool,
+    ciphertext: &[u8],
+) -> Result<Bytes> {
+    ensure!(
+        ciphertext.len() >= TAG_LEN && ciphertext.len() <= ENCRYPTED_SEGMENT_LEN,
+        "invalid encrypted segment length"
+    );
+    let index = u32::try_from(index).map_err(|_| anyhow!("invalid segment index"))?;
+    let plaintext = key
+        .cipher()
+        .decrypt(&segment_nonce(index, last), ciphertext)
+        .map_err(|_| anyhow!("failed to decrypt blob, wrong key or corrupted data"))?;
+    Ok(plaintext.into())
+}
+
+/// Encrypt a stream of plaintext, for example before importing it.
+pub(crate) fn encrypt_stream<D>(
+    key: &BlobKey,
+    data: D,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static
+where
+    D: Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+{
+    let state = (data, Some(Encryptor::new(key)));
+    Box::pin(stream::unfold(state, |(mut data, encryptor)| async move {
+        let mut encryptor = encryptor?;
+        loop {
+            if let Some(segment) = encryptor.next_segment() {
+                return Some((segment.map_err(io::Error::other), (data, Some(encryptor))));
+            }
+
+            match data.next().await {
+                Some(Ok(bytes)) => encryptor.push(&bytes),
+                Some(Err(err)) => return Some((Err(err), (data, None))),
+                None => return Some((encryptor.finish().map_err(io::Error::other), (data, None))),
+            }
+        }
+    }))
+}
+
+/// Decrypt a complete encrypted blob from the store.
+pub(crate) async fn decrypt_blob<S: Store>(
+    store: S,
+    pool_handle: LocalPoolHandle,
+    hash: Hash,
+    key: BlobKey,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let (sender, receiver) = async_channel::bounded(16);
+
+    pool_handle.spawn_detached(move || async move {
+        if let Err(err) = decrypt_segments(&store, hash, &key, &sender).await {
+            sender.send(Err(io::Error::other(err))).await.ok();
+        }
+    });
+
+    receiver
+}
+
+async fn decrypt_segments<S: Store>(
+    store: &S,
+    hash: Hash,
+    key: &BlobKey,
+    sender: &async_channel::Sender<io::Result<Bytes>>,
+) -> Result<()> {
+    let entry = store
+        .get(&IrohHash::from_bytes(*hash.as_bytes()))
+        .await?
+        .context("entry not there")?;
+    ensure!(entry.is_complete(), "blob {hash} is not complete");
+    let size = entry.size().value();
+    let mut reader = entry.data_reader().await?;
+
+    let segments = num_segments(size);
+    for index in 0..segments {
+        let offset = index * ENCRYPTED_SEGMENT_LEN as u64;
+        let ciphertext = reader.read_at(offset, ENCRYPTED_SEGMENT_LEN).await?;
+        let plaintext = decrypt_segment(key, index, index + 1 == segments, &ciphertext)?;
+        if sender.send(Ok(plaintext)).await.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::Hash;
+
+    use super::{
+        BlobKey, ENCRYPTED_SEGMENT_LEN, Encryptor, SEGMENT_LEN, decrypt_segment, num_segments,
+    };
+
+    fn encrypt(key: &BlobKey, data: &[u8]) -> Vec<u8> {
+        let mut encryptor = Encryptor::new(key);
+        let mut ciphertext = Vec::new();
+        // Push data in uneven pieces.
+        for piece in data.chunks(1000) {
+            encryptor.push(piece);
+            while let Some(segment) = encryptor.next_segment() {
+                ciphertext.extend_from_slice(&segment.unwrap());
+            }
+        }
+        ciphertext.extend_from_slice(&encryptor.finish().unwrap());
+        ciphertext
+    }
+
+    fn decrypt(key: &BlobKey, ciphertext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let segments = num_segments(ciphertext.len() as u64);
+        let mut plaintext = Vec::new();
+        for (index, segment) in ciphertext.chunks(ENCRYPTED_SEGMENT_LEN).enumerate() {
+            let last = index as u64 + 1 == segments;
+            plaintext.extend_from_slice(&decrypt_segment(key, index as u64, last, segment)?);
+        }
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let key = BlobKey::random();
+        for len in [0, 1, SEGMENT_LEN, SEGMENT_LEN + 1, 3 * SEGMENT_LEN + 123] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let ciphertext = encrypt(&key, &data);
+            assert_eq!(decrypt(&key, &ciphertext).unwrap(), data);
+            assert!(decrypt(&BlobKey::random(), &ciphertext).is_err());
+        }
+    }
+
+    #[test]
+    fn detect_truncation() {
+        let key = BlobKey::random();
+        let data = vec![7; 2 * SEGMENT_LEN + 10];
+        let ciphertext = encrypt(&key, &data);
+
+        // Dropping the last segment is detected, since the remaining one isn't marked as last.
+        assert!(decrypt(&key, &ciphertext[..2 * ENCRYPTED_SEGMENT_LEN]).is_err());
+    }
+
+    #[test]
+    fn convergent_keys() {
+        let group_secret = [1; 32];
+        let hash = Hash::new("content");
+        assert_eq!(
+            BlobKey::convergent(&group_secret, &hash),
+            BlobKey::convergent(&group_secret, &hash)
+        );
+        assert_ne!(
+            BlobKey::convergent(&group_secret, &hash),
+            BlobKey::convergent(&[2; 32], &hash)
+        );
+    }
+
+    #[test]
+    fn wrap_and_unwrap() {
+        let group_secret = [1; 32];
+        let blob_hash = Hash::new("encrypted blob");
+        let key = BlobKey::random();
+
+        let wrapped = key.wrap(&group_secret, &blob_hash).unwrap();
+        assert_eq!(wrapped.unwrap(&group_secret, &blob_hash).unwrap(), key);
+
+        // Wrong secret or blob.
+        assert!(wrapped.unwrap(&[2; 32], &blob_hash).is_err());
+        assert!(wrapped.unwrap(&group_secret, &Hash::new("other")).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Filesystem-backed blob store, the recommended store for desktop and server deployments.
+//!
+//! Blobs are content-addressed by their BLAKE3 hash. Inside the configured directory the store
+//! keeps the following layout:
+//!
+//! ```text
+//! <root>/
+//! ├── blobs.db    metadata, tags and inlined small blobs
+//! ├── data/       one file per blob, named after its hash
+//! └── temp/       partially written blobs, moved into `data` when complete
+//! ```
+//!
+//! Blobs smaller than the inline threshold are stored directly in the metadata database instead
+//! of a separate file, which avoids many tiny files and the overhead of opening them.
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use iroh_blobs::store::fs::{BatchOptions, InlineOptions, Options, PathOptions};
+
+use crate::FilesystemStore;
+
+/// Blobs up to this size in bytes are inlined into the metadata database by default.
+pub const DEFAULT_INLINE_THRESHOLD: u64 = 16 * 1024;
+
+/// How writes to the metadata database are grouped into transactions.
+///
+/// This doesn't control when data is flushed to disk, which is left to the underlying database.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteBatching {
+    /// Every write is committed in its own transaction.
+    Disabled,
+
+    /// Writes are collected and committed together at least every given duration, which is
+    /// considerably faster when importing many small blobs.
+    ///
+    /// A crash might lose the writes which were not committed yet, but it never corrupts the
+    /// store.
+    #[default]
+    Enabled,
+}
+
+/// Options for opening a `FilesystemStore`.
+#[derive(Clone, Debug)]
+pub struct FilesystemStoreOptions {
+    inline_threshold: u64,
+    write_batching: WriteBatching,
+    max_batch_duration: Duration,
+}
+
+impl Default for FilesystemStoreOptions {
+    fn default() -> Self {
+        Self {
+            inline_threshold: DEFAULT_INLINE_THRESHOLD,
+            write_batching: WriteBatching::default(),
+            max_batch_duration: Duration::from_millis(500),
+        }
+    }
+}
+
+impl FilesystemStoreOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store blobs up to the given size in bytes in the metadata database instead of a separate
+    /// file. Set to `0` to store every blob as a file.
+    pub fn inline_threshold(mut self, bytes: u64) -> Self {
+        self.inline_threshold = bytes;
+        self
+    }
+
+    /// Set how writes to the metadata database are grouped into transactions.
+    pub fn write_batching(mut self, batching: WriteBatching) -> Self {
+        self.write_batching = batching;
+        self
+    }
+
+    /// Set the maximum duration writes are collected before committing them with
+    /// `WriteBatching::Enabled`.
+    pub fn max_batch_duration(mut self, duration: Duration) -> Self {
+        self.max_batch_duration = duration;
+        self
+    }
+
+    fn into_options(self, root: &Path) -> Options {
+        let defaults = BatchOptions::default();
+        let batch = match self.write_batching {
+            WriteBatching::Disabled => BatchOptions {
+                max_write_batch: 1,
+                max_write_duration: Duration::ZERO,
+                ..defaults
+            },
+            WriteBatching::Enabled => BatchOptions {
+                max_write_duration: self.max_batch_duration,
+                ..defaults
+            },
+        };
+
+        Options {
+            path: PathOptions::new(root),
+            inline: InlineOptions {
+                max_data_inlined: self.inline_threshold,
+                ..InlineOptions::default()
+            },
+            batch,
+        }
+    }
+}
+
+/// Open the filesystem blob store in the given directory or create it if it doesn't exist yet.
+pub async fn open_filesystem_store(
+    root: impl AsRef<Path>,
+    options: FilesystemStoreOptions,
+) -> Result<FilesystemStore> {
+    let root = root.as_ref();
+    tokio::fs::create_dir_all(root).await?;
+    let options = options.into_options(root);
+    let store = FilesystemStore::load_with_opts(root.join("blobs.db"), options).await?;
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use iroh_blobs::BlobFormat;
+    use iroh_blobs::store::{Map, MapEntry, Store};
+
+    use super::{FilesystemStoreOptions, WriteBatching, open_filesystem_store};
+
+    #[test]
+    fn write_batching_options() {
+        let options = FilesystemStoreOptions::new()
+            .write_batching(WriteBatching::Disabled)
+            .into_options("blobs".as_ref());
+        assert_eq!(options.batch.max_write_batch, 1);
+        assert_eq!(options.batch.max_write_duration, Duration::ZERO);
+
+        let options = FilesystemStoreOptions::new()
+            .max_batch_duration(Duration::from_secs(2))
+            .into_options("blobs".as_ref());
+        assert_eq!(options.batch.max_write_duration, Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn open_with_write_batching() {
+        for batching in [WriteBatching::Disabled, WriteBatching::Enabled] {
+            let root = std::env::temp_dir()
+                .join(format!("p2panda-blobs-{}-{batching:?}", std::process::id()));
+            let options = FilesystemStoreOptions::new()
+                .write_batching(batching)
+                .inline_threshold(0);
+            let store = open_filesystem_store(&root, options).await.unwrap();
+
+            let tag = store
+                .import_bytes(Bytes::from_static(b"Hello, Sloth!"), BlobFormat::Raw)
+                .await
+                .unwrap();
+            let entry = store.get(tag.hash()).await.unwrap().unwrap();
+            assert!(entry.is_complete());
+            assert_eq!(entry.size().value(), 13);
+
+            drop(tag);
+            store.shutdown().await;
+            tokio::fs::remove_dir_all(&root).await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,264 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Pinning and garbage collection of blobs.
+//!
+//! Every blob which is imported or downloaded is tracked with the time it was added to the store.
+//! Imported blobs are pinned right away, downloaded blobs are only cached until they get pinned
+//! explicitly.
+//!
+//! Garbage collection never removes pinned blobs. All other blobs are removed when they are older
+//! than the configured maximum age, or, oldest first, as long as the store uses more than the
+//! configured disk space. Applications can veto the removal of single blobs, for example while a
+//! document still references their hash.
+//!
+//! Pins and timestamps are stored as tags in the blob store and survive restarts.
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use iroh_blobs::store::{MapEntry, Store};
+use iroh_blobs::{Hash as IrohHash, HashAndFormat, Tag};
+use p2panda_core::Hash;
+
+const PIN_PREFIX: &str = "p2panda/pin/";
+
+const ADDED_PREFIX: &str = "p2panda/added/";
+
+/// Conditions under which unpinned blobs are removed during garbage collection.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcPolicy {
+    max_disk_usage: Option<u64>,
+    max_age: Option<Duration>,
+}
+
+impl GcPolicy {
+    /// Policy which doesn't remove any blobs.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove the oldest unpinned blobs as long as all blobs together are larger than the given
+    /// number of bytes.
+    pub fn max_disk_usage(mut self, bytes: u64) -> Self {
+        self.max_disk_usage = Some(bytes);
+        self
+    }
+
+    /// Remove unpinned blobs which were added longer ago than the given duration.
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+}
+
+/// Summary of a garbage collection run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Hashes of all removed blobs.
+    pub removed: Vec<Hash>,
+
+    /// Number of reclaimed bytes.
+    pub reclaimed: u64,
+}
+
+/// Blob tracked for garbage collection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct GcEntry {
+    hash: Hash,
+    size: u64,
+    added: u64,
+    pinned: bool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_secs()
+}
+
+pub(crate) fn pin_tag(hash: &Hash) -> Tag {
+    Tag::from(format!("{PIN_PREFIX}{hash}"))
+}
+
+fn to_iroh_hash(hash: &Hash) -> IrohHash {
+    IrohHash::from_bytes(*hash.as_bytes())
+}
+
+/// Pin a blob, pinned blobs are never removed by garbage collection.
+pub(crate) async fn pin<S: Store>(store: &S, hash: Hash) -> Result<()> {
+    store
+        .set_tag(
+            pin_tag(&hash),
+            Some(HashAndFormat::raw(to_iroh_hash(&hash))),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Unpin a blob, it can be removed by the next garbage collection.
+pub(crate) async fn unpin<S: Store>(store: &S, hash: Hash) -> Result<()> {
+    store.set_tag(pin_tag(&hash), None).await?;
+    Ok(())
+}
+
+/// Track the time a blob was added to the store.
+///
+/// The tag also keeps the blob from being removed by the store's own garbage collection, only
+/// `gc` removes it.
+pub(crate) async fn track<S: Store>(store: &S, hash: Hash) -> Result<()> {
+    let tag = Tag::from(format!("{ADDED_PREFIX}{hash}/{}", now()));
+    store
+        .set_tag(tag, Some(HashAndFormat::raw(to_iroh_hash(&hash))))
+        .await?;
+    Ok(())
+}
+
+/// Select the blobs to remove, oldest first.
+fn select(mut entries: Vec<GcEntry>, policy: &GcPolicy, now: u64) -> Vec<GcEntry> {
+    entries.sort_by_key(|entry| entry.added);
+    let mut usage: u64 = entries.iter().map(|entry| entry.size).sum();
+
+    let mut removed = Vec::new();
+    for entry in entries {
+        if entry.pinned {
+            continue;
+        }
+
+        let expired = policy
+            .max_age
+            .is_some_and(|max_age| now.saturating_sub(entry.added) > max_age.as_secs());
+        let too_large = policy
+            .max_disk_usage
+            .is_some_and(|max_disk_usage| usage > max_disk_usage);
+        if expired || too_large {
+            usage -= entry.size;
+            removed.push(entry);
+        }
+    }
+    removed
+}
+
+/// Remove unpinned blobs according to the policy.
+///
+/// Blobs for which `retain` returns `true` are kept.
+pub(crate) async fn gc<S, F>(store: &S, policy: &GcPolicy, retain: F) -> Result<GcReport>
+where
+    S: Store,
+    F: Fn(&Hash) -> bool,
+{
+    let mut pinned = HashSet::new();
+    let mut tags = Vec::new();
+    let mut added: HashMap<Hash, u64> = HashMap::new();
+    for item in store.tags(None, None).await? {
+        let (tag, _) = item?;
+        let Ok(name) = std::str::from_utf8(tag.0.as_ref()) else {
+            continue;
+        };
+
+        if let Some(hash) = name.strip_prefix(PIN_PREFIX) {
+            if let Ok(hash) = hash.parse::<Hash>() {
+                pinned.insert(hash);
+            }
+        } else if let Some(rest) = name.strip_prefix(ADDED_PREFIX) {
+            let Some((hash, timestamp)) = rest.split_once('/') else {
+                continue;
+            };
+            if let (Ok(hash), Ok(timestamp)) = (hash.parse::<Hash>(), timestamp.parse::<u64>()) {
+                // Blobs which were added several times count from their first addition.
+                added
+                    .entry(hash)
+                    .and_modify(|added| *added = (*added).min(timestamp))
+                    .or_insert(timestamp);
+                tags.push((tag.clone(), hash));
+            }
+        }
+    }
+
+    let mut entries = Vec::new();
+    for (hash, timestamp) in &added {
+        let Some(entry) = store.get(&to_iroh_hash(hash)).await? else {
+            continue;
+        };
+        entries.push(GcEntry {
+            hash: *hash,
+            size: entry.size().value(),
+            added: *timestamp,
+            pinned: pinned.contains(hash) || retain(hash),
+        });
+    }
+
+    let removed = select(entries, policy, now());
+    if removed.is_empty() {
+        return Ok(GcReport::default());
+    }
+
+    let removed_hashes: HashSet<Hash> = removed.iter().map(|entry| entry.hash).collect();
+    for (tag, hash) in tags {
+        if removed_hashes.contains(&hash) {
+            store.set_tag(tag, None).await?;
+        }
+    }
+    store
+        .delete(removed_hashes.iter().map(to_iroh_hash).collect())
+        .await?;
+
+    Ok(GcReport {
+        reclaimed: removed.iter().map(|entry| entry.size).sum(),
+        removed: removed.into_iter().map(|entry| entry.hash).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use p2panda_core::Hash;
+
+    use super::{GcEntry, GcPolicy, select};
+
+    fn entry(name: &str, size: u64, added: u64, pinned: bool) -> GcEntry {
+        GcEntry {
+            hash: Hash::new(name),
+            size,
+            added,
+            pinned,
+        }
+    }
+
+    fn hashes(entries: Vec<GcEntry>) -> Vec<Hash> {
+        entries.into_iter().map(|entry| entry.hash).collect()
+    }
+
+    #[test]
+    fn keep_everything_by_default() {
+        let entries = vec![entry("a", 100, 0, false), entry("b", 100, 10, false)];
+        assert!(select(entries, &GcPolicy::new(), 1000).is_empty());
+    }
+
+    #[test]
+    fn remove_expired_blobs() {
+        let entries = vec![
+            entry("a", 100, 0, false),
+            entry("b", 100, 0, true),
+            entry("c", 100, 950, false),
+        ];
+        let policy = GcPolicy::new().max_age(Duration::from_secs(100));
+        assert_eq!(hashes(select(entries, &policy, 1000)), vec![Hash::new("a")]);
+    }
+
+    #[test]
+    fn remove_oldest_blobs_above_disk_usage() {
+        let entries = vec![
+            entry("c", 100, 30, false),
+            entry("a", 100, 10, true),
+            entry("b", 100, 20, false),
+            entry("d", 100, 40, false),
+        ];
+        let policy = GcPolicy::new().max_disk_usage(250);
+        assert_eq!(
+            hashes(select(entries, &policy, 1000)),
+            vec![Hash::new("b"), Hash::new("c")]
+        );
+    }
+}
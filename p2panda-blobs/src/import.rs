@@ -13,11 +13,14 @@ use iroh_blobs::provider::AddProgress;
 use iroh_blobs::store::{ImportMode, ImportProgress, Store};
 use iroh_blobs::util::local_pool::LocalPoolHandle;
 use iroh_blobs::util::progress::{AsyncChannelProgressSender, ProgressSender};
-use iroh_blobs::{BlobFormat, HashAndFormat};
+use iroh_blobs::{BlobFormat, Hash as IrohHash, HashAndFormat, Tag};
 use p2panda_core::Hash;
 use serde::{Deserialize, Serialize};
 use serde_error::Error as RpcError;
 
+use crate::gc::{pin, pin_tag, track};
+use crate::ingest::{Ingest, IngestSource};
+
 /// Status of a blob import attempt.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ImportBlobEvent {
@@ -27,6 +30,7 @@ pub enum ImportBlobEvent {
 
 pub(crate) async fn import_blob<S: Store>(
     store: S,
+    ingest: Ingest,
     pool_handle: LocalPoolHandle,
     path: PathBuf,
 ) -> impl Stream<Item = ImportBlobEvent> {
@@ -34,7 +38,7 @@ pub(crate) async fn import_blob<S: Store>(
 
     let sender = sender.clone();
     pool_handle.spawn_detached(|| async move {
-        if let Err(err) = add_from_path(store, path, sender.clone()).await {
+        if let Err(err) = add_from_path(store, ingest, path, sender.clone()).await {
             sender
                 .send(AddProgress::Abort(RpcError::new(&*err)))
                 .await
@@ -59,6 +63,7 @@ pub(crate) async fn import_blob<S: Store>(
 
 pub(crate) async fn import_blob_from_stream<S, T>(
     store: S,
+    ingest: Ingest,
     pool_handle: LocalPoolHandle,
     data: T,
 ) -> impl Stream<Item = ImportBlobEvent>
@@ -70,7 +75,7 @@ where
 
     let sender = sender.clone();
     pool_handle.spawn_detached(|| async move {
-        if let Err(err) = add_from_stream(store, data, sender.clone()).await {
+        if let Err(err) = add_from_stream(store, ingest, data, sender.clone()).await {
             sender
                 .send(AddProgress::Abort(RpcError::new(&*err)))
                 .await
@@ -95,6 +100,7 @@ where
 
 async fn add_from_path<S: Store>(
     store: S,
+    ingest: Ingest,
     path: PathBuf,
     progress: async_channel::Sender<AddProgress>,
 ) -> Result<()> {
@@ -122,9 +128,8 @@ async fn add_from_path<S: Store>(
         .import_file(path, import_mode, BlobFormat::Raw, import_progress)
         .await?;
 
-    let hash_and_format = tag.inner();
-    let HashAndFormat { hash, format } = *hash_and_format;
-    let tag = store.create_tag(*hash_and_format).await?;
+    let HashAndFormat { hash, format } = *tag.inner();
+    let tag = pin_and_track(&store, &ingest, hash).await?;
     progress
         .send(AddProgress::AllDone { hash, format, tag })
         .await?;
@@ -134,6 +139,7 @@ async fn add_from_path<S: Store>(
 
 async fn add_from_stream<T, S>(
     store: S,
+    ingest: Ingest,
     data: T,
     progress: async_channel::Sender<AddProgress>,
 ) -> Result<()>
@@ -164,12 +170,24 @@ where
         .import_stream(data, BlobFormat::Raw, import_progress)
         .await?;
 
-    let hash_and_format = tag.inner();
-    let HashAndFormat { hash, format } = *hash_and_format;
-    let tag = store.create_tag(*hash_and_format).await?;
+    let HashAndFormat { hash, format } = *tag.inner();
+    let tag = pin_and_track(&store, &ingest, hash).await?;
     progress
         .send(AddProgress::AllDone { hash, format, tag })
         .await?;
 
     Ok(())
 }
+
+/// Imported blobs are validated, pinned and tracked for garbage collection, returns the pin tag.
+pub(crate) async fn pin_and_track<S: Store>(
+    store: &S,
+    ingest: &Ingest,
+    hash: IrohHash,
+) -> Result<Tag> {
+    let hash = Hash::from_bytes(*hash.as_bytes());
+    ingest.validate(store, hash, IngestSource::Import).await?;
+    track(store, hash).await?;
+    pin(store, hash).await?;
+    Ok(pin_tag(&hash))
+}
@@ -0,0 +1,119 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Validate blobs as they arrive.
+//!
+//! Applications can reject blobs before they are kept in the store, for example because they are
+//! too large, are of an unwanted file type or were flagged by a virus scanner. Every imported and
+//! downloaded blob is validated as soon as it is complete and before it is pinned or tracked for
+//! garbage collection. Rejected blobs are removed from the store right away, they are hence never
+//! served or announced to other peers.
+//!
+//! A maximum blob size can be set with `Config::max_blob_size`, all other checks are implemented
+//! with an `IngestHook` set with `Config::ingest_hook`.
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, ensure};
+use bytes::Bytes;
+use futures_lite::future::Boxed as BoxedFuture;
+use iroh_blobs::Hash as IrohHash;
+use iroh_blobs::store::{MapEntry, Store};
+use iroh_io::AsyncSliceReader;
+use p2panda_core::Hash;
+
+/// Number of bytes at the beginning of a blob which are passed to the `IngestHook`.
+pub const INGEST_HEAD_LEN: usize = 8 * 1024;
+
+/// How a blob arrived in the store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IngestSource {
+    /// Blob was imported locally.
+    Import,
+
+    /// Blob was downloaded from a peer.
+    Download,
+}
+
+/// Blob which is validated by an `IngestHook`.
+#[derive(Clone, Debug)]
+pub struct IngestBlob {
+    pub hash: Hash,
+
+    /// Size of the blob in bytes.
+    pub size: u64,
+
+    pub source: IngestSource,
+
+    /// First bytes of the blob, for example to detect its MIME type.
+    ///
+    /// Contains the whole blob if it's not larger than `INGEST_HEAD_LEN`.
+    pub head: Bytes,
+}
+
+/// Hook to validate blobs before they are kept in the store.
+pub trait IngestHook: Debug + Send + Sync + 'static {
+    /// Returns an error if the blob should be rejected.
+    fn validate(&self, blob: IngestBlob) -> BoxedFuture<Result<()>>;
+}
+
+/// Validation applied to every blob arriving in the store.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Ingest {
+    max_size: Option<u64>,
+    hook: Option<Arc<dyn IngestHook>>,
+}
+
+impl Ingest {
+    pub fn new(max_size: Option<u64>, hook: Option<Arc<dyn IngestHook>>) -> Self {
+        Self { max_size, hook }
+    }
+
+    /// Validate a complete blob and remove it from the store if it was rejected.
+    pub async fn validate<S: Store>(
+        &self,
+        store: &S,
+        hash: Hash,
+        source: IngestSource,
+    ) -> Result<()> {
+        if self.max_size.is_none() && self.hook.is_none() {
+            return Ok(());
+        }
+
+        let result = self.check(store, hash, source).await;
+        if result.is_err() {
+            store
+                .delete(vec![IrohHash::from_bytes(*hash.as_bytes())])
+                .await?;
+        }
+        result
+    }
+
+    async fn check<S: Store>(&self, store: &S, hash: Hash, source: IngestSource) -> Result<()> {
+        let entry = store
+            .get(&IrohHash::from_bytes(*hash.as_bytes()))
+            .await?
+            .context("entry not there")?;
+        let size = entry.size().value();
+
+        if let Some(max_size) = self.max_size {
+            ensure!(
+                size <= max_size,
+                "blob {hash} with {size} bytes exceeds maximum size of {max_size} bytes"
+            );
+        }
+
+        let Some(hook) = &self.hook else {
+            return Ok(());
+        };
+        let mut reader = entry.data_reader().await?;
+        let head = reader.read_at(0, INGEST_HEAD_LEN).await?;
+        hook.validate(IngestBlob {
+            hash,
+            size,
+            source,
+            head,
+        })
+        .await
+        .with_context(|| format!("blob {hash} was rejected"))
+    }
+}
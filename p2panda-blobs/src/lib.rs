@@ -11,29 +11,79 @@
 //!
 //! The blobs service integrates with `p2panda-net` to provide a means of synchronising files
 //! between devices using BLAKE3 verified streaming. Memory usage is generally low, even when
-//! transferring very large files.
+//! transferring very large files. The blobs protocol is registered with `NetworkBuilderExt::blobs`
+//! and the service is accessed with `NetworkExt::blobs` once the network is built.
+//!
+//! Large blobs can be downloaded from several providers in parallel with
+//! `Blobs::download_blob_parallel`.
+//!
+//! Byte ranges of large blobs can be fetched with `Blobs::fetch_range` without downloading the
+//! whole blob, every chunk of the range is verified against the blob's hash.
+//!
+//! Imported and downloaded blobs can be validated before they are kept in the store, see
+//! `IngestHook`.
+//!
+//! Nodes announce the blobs they provide for a topic with `Blobs::provide_blobs`, downloads ask
+//! these peers first before falling back to all other known peers.
+//!
+//! With the `encryption` feature blobs can be encrypted end-to-end with a `BlobKey` per blob,
+//! which is shared with other group members wrapped by a group secret, see
+//! `Blobs::import_encrypted_blob_from_stream` and `Blobs::decrypt_blob`.
+//!
+//! With the `s3` feature blobs can be archived in S3-compatible object storage with
+//! `Blobs::shelve_blob`, keeping the local store as a cache, see `BlobShelf`. Shelved blobs are
+//! restored into the local store when peers request them.
+//!
+//! Imported blobs are pinned, downloaded blobs are cached until they are pinned. Unpinned blobs
+//! are removed by `Blobs::gc` according to a `GcPolicy`.
+mod add;
 mod blobs;
 mod config;
 mod download;
+#[cfg(feature = "encryption")]
+mod encryption;
 mod export;
+mod fs;
+mod gc;
 mod import;
+mod ingest;
+mod network;
+mod parallel;
 mod protocol;
+mod range;
+#[cfg(feature = "s3")]
+mod shelf;
 
 use iroh::{NodeAddr as IrohNodeAddr, NodeId};
 use iroh_blobs::store;
 
+pub use add::AddedBlob;
 pub use blobs::Blobs;
 pub use config::Config;
-pub use download::DownloadBlobEvent;
+pub use download::{DownloadBlobEvent, DownloadStreamEvent};
+#[cfg(feature = "encryption")]
+pub use encryption::{BlobKey, WrappedBlobKey};
+pub use fs::{
+    DEFAULT_INLINE_THRESHOLD, FilesystemStoreOptions, WriteBatching, open_filesystem_store,
+};
+pub use gc::{GcPolicy, GcReport};
 pub use import::ImportBlobEvent;
+pub use ingest::{INGEST_HEAD_LEN, IngestBlob, IngestHook, IngestSource};
+pub use network::{NetworkBuilderExt, NetworkExt};
 use p2panda_net::NodeAddress;
 pub use protocol::{BLOBS_ALPN, BlobsProtocol};
+pub use range::FetchRangeEvent;
+#[cfg(feature = "s3")]
+pub use shelf::{BlobShelf, BlobUpload, S3Options};
 
 /// In-memory storage database with support for partial blobs.
 pub type MemoryStore = store::mem::Store;
 
 /// Filesystem storage database backed by [redb](https://crates.io/crates/redb) for small blobs and
 /// files for large blobs.
+///
+/// Use `open_filesystem_store` to open it with p2panda's defaults for desktop and server
+/// deployments.
 pub type FilesystemStore = store::fs::Store;
 
 /// Converts a `p2panda-net` node address type to the `iroh` implementation.
@@ -0,0 +1,124 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Extensions to register the blobs protocol on `NetworkBuilder` and access the blobs service on
+//! `Network`.
+//!
+//! Calling `.blobs(store)` on the builder registers the blobs protocol with the network, the
+//! service is then available with `network.blobs()` after the network was built.
+use std::sync::{Arc, OnceLock};
+
+use anyhow::Result;
+use futures_lite::future::Boxed as BoxedFuture;
+use iroh::endpoint::Connecting;
+use iroh_blobs::downloader::Downloader;
+use iroh_blobs::store::Store;
+use iroh_blobs::util::local_pool::{Config as LocalPoolConfig, LocalPool};
+use p2panda_net::{Network, NetworkBuilder, ProtocolHandler, TopicId};
+use p2panda_sync::TopicQuery;
+
+use crate::Blobs;
+use crate::config::Config;
+use crate::protocol::{BLOBS_ALPN, BlobsProtocol};
+
+/// Blobs protocol registered by `NetworkBuilderExt::blobs`.
+///
+/// Next to accepting inbound connections it holds everything needed to create the `Blobs` service
+/// once the network is built.
+#[derive(Debug)]
+struct BlobsHandler<S> {
+    protocol: Arc<BlobsProtocol<S>>,
+    store: S,
+    config: Config,
+    rt: Arc<LocalPool>,
+    downloader: OnceLock<Downloader>,
+}
+
+impl<S: Store> ProtocolHandler for BlobsHandler<S> {
+    fn accept(self: Arc<Self>, conn: Connecting) -> BoxedFuture<Result<()>> {
+        self.protocol.clone().accept(conn)
+    }
+}
+
+/// An extension trait for `NetworkBuilder` to register the blobs protocol.
+pub trait NetworkBuilderExt {
+    /// Registers the blobs protocol using the given store.
+    ///
+    /// After building the network the blobs service can be accessed with `NetworkExt::blobs`.
+    fn blobs<S: Store>(self, store: S) -> Self
+    where
+        Self: Sized,
+    {
+        self.blobs_with_config(store, Config::default())
+    }
+
+    /// Registers the blobs protocol using the given store and configuration.
+    fn blobs_with_config<S: Store>(self, store: S, config: Config) -> Self;
+}
+
+impl<T: TopicQuery> NetworkBuilderExt for NetworkBuilder<T> {
+    fn blobs_with_config<S: Store>(self, store: S, config: Config) -> Self {
+        // Calls `num_cpus::get()` to define thread count.
+        let rt = LocalPool::new(LocalPoolConfig::default());
+
+        let protocol = BlobsProtocol::new(store.clone(), rt.handle().clone());
+        #[cfg(feature = "s3")]
+        let protocol = match &config.shelf {
+            Some(shelf) => protocol.restore_from(shelf.clone()),
+            None => protocol,
+        };
+
+        let handler = BlobsHandler {
+            protocol: Arc::new(protocol),
+            store,
+            config,
+            rt: Arc::new(rt),
+            downloader: OnceLock::new(),
+        };
+
+        // The downloader of `iroh-blobs` dials peers with the plain ALPN identifier, blobs are
+        // content-addressed and can be shared across namespaces.
+        self.global_protocol(BLOBS_ALPN, handler)
+    }
+}
+
+/// An extension trait for `Network` to access the blobs service.
+pub trait NetworkExt<T> {
+    /// Returns the blobs service to add, download and export blobs.
+    ///
+    /// Returns `None` if the blobs protocol wasn't registered with `NetworkBuilderExt::blobs` or
+    /// with a different store type.
+    fn blobs<S: Store>(&self) -> Option<Blobs<T, S>>;
+}
+
+impl<T> NetworkExt<T> for Network<T>
+where
+    T: TopicQuery + TopicId + 'static,
+{
+    fn blobs<S: Store>(&self) -> Option<Blobs<T, S>> {
+        let handler = self.get_protocol::<BlobsHandler<S>>(BLOBS_ALPN)?;
+
+        let downloader = handler
+            .downloader
+            .get_or_init(|| {
+                Downloader::with_config(
+                    handler.store.clone(),
+                    self.endpoint().clone(),
+                    handler.rt.handle().clone(),
+                    handler.config.clone().into(),
+                    handler.config.clone().into(),
+                )
+            })
+            .clone();
+
+        Some(Blobs {
+            downloader,
+            ingest: handler.config.clone().into(),
+            network: self.clone(),
+            parallel: handler.config.clone().into(),
+            rt: handler.rt.clone(),
+            #[cfg(feature = "s3")]
+            shelf: handler.config.shelf.clone(),
+            store: handler.store.clone(),
+        })
+    }
+}
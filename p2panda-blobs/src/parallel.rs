@@ -0,0 +1,404 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Download large blobs from several providers in parallel.
+//!
+//! The blob is split into segments of `Config::parallel_segment_size` bytes which are requested
+//! from different providers at the same time. Every segment is verified against the blob's hash
+//! tree as it arrives and written into the store.
+//!
+//! Providers which fail or stop sending data for longer than `Config::stall_timeout` lose their
+//! segment to the next idle provider, which continues where the failed one stopped. Providers
+//! failing repeatedly are not asked again during this download.
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use futures_lite::Stream;
+use futures_util::future::join_all;
+use iroh::{Endpoint, NodeAddr};
+use iroh_blobs::Hash as IrohHash;
+use iroh_blobs::bao_tree::io::BaoContentItem;
+use iroh_blobs::bao_tree::{ChunkNum, ChunkRanges};
+use iroh_blobs::get::fsm::{self, BlobContentNext, ConnectedNext, EndBlobNext};
+use iroh_blobs::get::request::get_verified_size;
+use iroh_blobs::protocol::{GetRequest, RangeSpecSeq};
+use iroh_blobs::store::{BaoBatchWriter, MapEntry, MapEntryMut, MapMut, Store};
+use iroh_blobs::util::local_pool::LocalPoolHandle;
+use p2panda_core::Hash;
+use p2panda_net::{Network, TopicId};
+use p2panda_sync::TopicQuery;
+use serde_error::Error as RpcError;
+use tracing::debug;
+
+use crate::config::Config;
+use crate::download::{CHUNK_SIZE, DownloadBlobEvent, peer_addrs};
+use crate::gc::track;
+use crate::ingest::{Ingest, IngestSource};
+use crate::protocol::BLOBS_ALPN;
+
+/// Number of chunks iroh groups into one leaf of the hash tree, segments are aligned to it.
+const BLOCK_CHUNKS: u64 = 16;
+
+/// Number of failed or stalled segments after which a provider isn't asked again.
+const MAX_PROVIDER_FAILURES: u32 = 3;
+
+/// Time an idle provider waits before checking again for segments which need to be re-assigned.
+const IDLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Options for parallel downloads, taken from `Config`.
+#[derive(Clone, Debug)]
+pub(crate) struct ParallelOptions {
+    segment_chunks: u64,
+    max_providers: usize,
+    stall_timeout: Duration,
+}
+
+impl From<Config> for ParallelOptions {
+    fn from(val: Config) -> Self {
+        Self {
+            segment_chunks: val
+                .parallel_segment_size
+                .div_ceil(CHUNK_SIZE * BLOCK_CHUNKS)
+                .max(1)
+                * BLOCK_CHUNKS,
+            max_providers: val.max_parallel_providers.max(1),
+            stall_timeout: val.stall_timeout,
+        }
+    }
+}
+
+/// Download statistics of a provider.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct ProviderHealth {
+    completed: u32,
+    failures: u32,
+}
+
+impl ProviderHealth {
+    fn is_healthy(&self) -> bool {
+        self.failures < MAX_PROVIDER_FAILURES
+    }
+}
+
+/// Assigns chunk ranges of a blob to providers.
+#[derive(Debug)]
+struct Scheduler {
+    pending: VecDeque<Range<u64>>,
+    in_flight: usize,
+    providers: Vec<ProviderHealth>,
+}
+
+impl Scheduler {
+    fn new(num_chunks: u64, segment_chunks: u64, num_providers: usize) -> Self {
+        let pending = (0..num_chunks)
+            .step_by(segment_chunks as usize)
+            .map(|start| start..(start + segment_chunks).min(num_chunks))
+            .collect();
+
+        Self {
+            pending,
+            in_flight: 0,
+            providers: vec![ProviderHealth::default(); num_providers],
+        }
+    }
+
+    /// Returns the next segment for a provider, `None` if there is none or the provider failed
+    /// too often.
+    fn next(&mut self, provider: usize) -> Option<Range<u64>> {
+        if !self.providers[provider].is_healthy() {
+            return None;
+        }
+
+        let segment = self.pending.pop_front()?;
+        self.in_flight += 1;
+        Some(segment)
+    }
+
+    fn completed(&mut self, provider: usize) {
+        self.in_flight -= 1;
+        self.providers[provider].completed += 1;
+    }
+
+    /// Puts the remaining chunks of a failed segment back in front of the queue, so the next idle
+    /// provider picks them up.
+    fn failed(&mut self, provider: usize, remaining: Range<u64>) {
+        self.in_flight -= 1;
+        self.providers[provider].failures += 1;
+        if !remaining.is_empty() {
+            self.pending.push_front(remaining);
+        }
+    }
+
+    /// Returns `true` if all segments were downloaded.
+    fn is_done(&self) -> bool {
+        self.pending.is_empty() && self.in_flight == 0
+    }
+
+    /// Returns `true` if a provider should keep waiting for segments, because segments which are
+    /// currently downloaded by others might still fail.
+    fn should_wait(&self, provider: usize) -> bool {
+        self.providers[provider].is_healthy() && !self.is_done()
+    }
+}
+
+pub(crate) async fn download_blob_parallel<T, S>(
+    network: Network<T>,
+    store: S,
+    ingest: Ingest,
+    options: ParallelOptions,
+    pool_handle: LocalPoolHandle,
+    hash: Hash,
+) -> impl Stream<Item = DownloadBlobEvent>
+where
+    T: TopicQuery + TopicId + 'static,
+    S: Store,
+{
+    let (sender, receiver) = async_channel::bounded(1024);
+
+    pool_handle.spawn_detached(move || async move {
+        let result = match download(&network, &store, &options, hash, &sender).await {
+            // Validate downloaded blobs and track them for garbage collection.
+            Ok(()) => match ingest.validate(&store, hash, IngestSource::Download).await {
+                Ok(()) => track(&store, hash).await,
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        let event = match result {
+            Ok(()) => DownloadBlobEvent::Done,
+            Err(err) => DownloadBlobEvent::Abort(RpcError::new(&*err)),
+        };
+        sender.send(event).await.ok();
+    });
+
+    receiver
+}
+
+async fn download<T, S>(
+    network: &Network<T>,
+    store: &S,
+    options: &ParallelOptions,
+    hash: Hash,
+    sender: &async_channel::Sender<DownloadBlobEvent>,
+) -> Result<()>
+where
+    T: TopicQuery + TopicId + 'static,
+    S: Store,
+{
+    let iroh_hash = IrohHash::from_bytes(*hash.as_bytes());
+    let entry = store.get(&iroh_hash).await?;
+    if entry.is_some_and(|entry| entry.is_complete()) {
+        return Ok(());
+    }
+
+    let (provider_addrs, other_addrs) = peer_addrs(network, hash).await?;
+    let mut addrs: Vec<NodeAddr> = provider_addrs.into_iter().chain(other_addrs).collect();
+    addrs.truncate(options.max_providers);
+
+    let size = verified_size(network.endpoint(), &addrs, iroh_hash).await?;
+    let entry = store.get_or_create(iroh_hash, size).await?;
+    let num_chunks = size.div_ceil(CHUNK_SIZE);
+    let scheduler = Arc::new(Mutex::new(Scheduler::new(
+        num_chunks,
+        options.segment_chunks,
+        addrs.len(),
+    )));
+    let progress = Arc::new(Mutex::new(0u64));
+
+    let workers = addrs.into_iter().enumerate().map(|(provider, addr)| {
+        let scheduler = scheduler.clone();
+        let progress = progress.clone();
+        let entry = entry.clone();
+        async move {
+            loop {
+                let segment = scheduler.lock().unwrap().next(provider);
+                let Some(segment) = segment else {
+                    if scheduler.lock().unwrap().should_wait(provider) {
+                        tokio::time::sleep(IDLE_INTERVAL).await;
+                        continue;
+                    }
+                    break;
+                };
+
+                let mut next_chunk = segment.start;
+                let result = fetch_segment(
+                    network.endpoint(),
+                    addr.clone(),
+                    iroh_hash,
+                    size,
+                    segment.clone(),
+                    &entry,
+                    &mut next_chunk,
+                    options.stall_timeout,
+                )
+                .await;
+
+                match result {
+                    Ok(()) => scheduler.lock().unwrap().completed(provider),
+                    Err(err) => {
+                        debug!(%hash, node_id = %addr.node_id, "segment download failed: {err}");
+                        scheduler
+                            .lock()
+                            .unwrap()
+                            .failed(provider, next_chunk..segment.end);
+                    }
+                }
+
+                let chunks = next_chunk.min(segment.end) - segment.start;
+                if chunks > 0 {
+                    let bytes = {
+                        let mut progress = progress.lock().unwrap();
+                        *progress = (*progress + chunks * CHUNK_SIZE).min(size);
+                        *progress
+                    };
+                    let event = DownloadBlobEvent::Progress {
+                        bytes,
+                        chunks: bytes.div_ceil(CHUNK_SIZE),
+                    };
+                    sender.send(event).await.ok();
+                }
+            }
+        }
+    });
+    join_all(workers).await;
+
+    ensure!(
+        scheduler.lock().unwrap().is_done(),
+        "all providers failed to deliver blob {hash}"
+    );
+    store.insert_complete(entry).await?;
+
+    Ok(())
+}
+
+/// Request the verified size of the blob from the first provider answering.
+async fn verified_size(endpoint: &Endpoint, addrs: &[NodeAddr], hash: IrohHash) -> Result<u64> {
+    let mut last_err = None;
+    for addr in addrs {
+        let result = async {
+            let connection = endpoint.connect(addr.clone(), BLOBS_ALPN).await?;
+            let (size, _) = get_verified_size(&connection, &hash).await?;
+            Ok::<u64, anyhow::Error>(size)
+        }
+        .await;
+        match result {
+            Ok(size) => return Ok(size),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow!("no way to reach a node for download")))
+}
+
+/// Download the chunks of a segment from a provider and write them into the store.
+///
+/// `next_chunk` is advanced with every written leaf, a failed segment can be continued from there.
+#[allow(clippy::too_many_arguments)]
+async fn fetch_segment<E: MapEntryMut>(
+    endpoint: &Endpoint,
+    addr: NodeAddr,
+    hash: IrohHash,
+    size: u64,
+    segment: Range<u64>,
+    entry: &E,
+    next_chunk: &mut u64,
+    stall_timeout: Duration,
+) -> Result<()> {
+    let connection = tokio::time::timeout(stall_timeout, endpoint.connect(addr, BLOBS_ALPN))
+        .await
+        .context("connection stalled")??;
+    let chunks = ChunkRanges::from(ChunkNum(segment.start)..ChunkNum(segment.end));
+    let request = GetRequest::new(hash, RangeSpecSeq::from_ranges([chunks]));
+
+    let connected = fsm::start(connection, request).next().await?;
+    let ConnectedNext::StartRoot(start) = connected.next().await? else {
+        bail!("peer did not send the requested blob");
+    };
+    let (mut content, _) = start.next().next().await?;
+    let mut writer = entry.batch_writer().await?;
+
+    let end_blob = loop {
+        let next = tokio::time::timeout(stall_timeout, content.next())
+            .await
+            .context("download stalled")?;
+        match next {
+            BlobContentNext::More((next, item)) => {
+                content = next;
+                let item = item?;
+                let leaf_end = match &item {
+                    BaoContentItem::Leaf(leaf) => {
+                        Some((leaf.offset + leaf.data.len() as u64).div_ceil(CHUNK_SIZE))
+                    }
+                    BaoContentItem::Parent(_) => None,
+                };
+                writer.write_batch(size, vec![item]).await?;
+                if let Some(leaf_end) = leaf_end {
+                    *next_chunk = leaf_end;
+                }
+            }
+            BlobContentNext::Done(end_blob) => break end_blob,
+        }
+    };
+    writer.sync().await?;
+
+    if let EndBlobNext::Closing(closing) = end_blob.next() {
+        closing.next().await?;
+    }
+
+    ensure!(*next_chunk >= segment.end, "peer sent incomplete segment");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_PROVIDER_FAILURES, Scheduler};
+
+    #[test]
+    fn split_into_segments() {
+        let mut scheduler = Scheduler::new(10, 4, 2);
+        assert_eq!(scheduler.next(0), Some(0..4));
+        assert_eq!(scheduler.next(1), Some(4..8));
+        assert_eq!(scheduler.next(0), Some(8..10));
+        assert_eq!(scheduler.next(1), None);
+
+        scheduler.completed(0);
+        scheduler.completed(1);
+        assert!(!scheduler.is_done());
+        assert!(scheduler.should_wait(1));
+
+        scheduler.completed(0);
+        assert!(scheduler.is_done());
+        assert!(!scheduler.should_wait(1));
+    }
+
+    #[test]
+    fn reassign_failed_segments() {
+        let mut scheduler = Scheduler::new(8, 4, 2);
+        assert_eq!(scheduler.next(0), Some(0..4));
+        assert_eq!(scheduler.next(1), Some(4..8));
+
+        // Provider stalled after two chunks, the next idle provider continues from there.
+        scheduler.failed(0, 2..4);
+        scheduler.completed(1);
+        assert_eq!(scheduler.next(1), Some(2..4));
+        scheduler.completed(1);
+        assert!(scheduler.is_done());
+        assert_eq!(scheduler.providers[0].failures, 1);
+        assert_eq!(scheduler.providers[1].completed, 2);
+    }
+
+    #[test]
+    fn retire_failing_providers() {
+        let mut scheduler = Scheduler::new(4, 4, 2);
+        for _ in 0..MAX_PROVIDER_FAILURES {
+            let segment = scheduler.next(0).unwrap();
+            scheduler.failed(0, segment);
+        }
+
+        // Provider isn't asked again, others take over.
+        assert_eq!(scheduler.next(0), None);
+        assert!(!scheduler.should_wait(0));
+        assert_eq!(scheduler.next(1), Some(0..4));
+    }
+}
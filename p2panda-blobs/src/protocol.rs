@@ -12,12 +12,16 @@ use iroh_blobs::store::Store;
 use iroh_blobs::util::local_pool::LocalPoolHandle;
 use p2panda_net::ProtocolHandler;
 
+#[cfg(feature = "s3")]
+use crate::shelf::{BlobShelf, RestoreOnRequest};
+
 /// Application-Layer Protocol Negotiation (ALPN) identifier for blobs.
 pub const BLOBS_ALPN: &[u8] = ALPN;
 
 /// Blobs connection handler.
 #[derive(Debug)]
 pub struct BlobsProtocol<S> {
+    events: EventSender,
     rt: LocalPoolHandle,
     store: S,
 }
@@ -28,7 +32,18 @@ impl<S: Store> BlobsProtocol<S> {
     /// `BlobsProtocol` implements the `ProtocolHandler` trait, allowing it to accept inbound
     /// network connections for the purposes of blob sync.
     pub fn new(store: S, rt: LocalPoolHandle) -> Self {
-        Self { rt, store }
+        Self {
+            events: EventSender::default(),
+            rt,
+            store,
+        }
+    }
+
+    /// Restore shelved blobs from the object storage when peers request them.
+    #[cfg(feature = "s3")]
+    pub(crate) fn restore_from(mut self, shelf: BlobShelf) -> Self {
+        self.events = RestoreOnRequest::new(self.store.clone(), shelf).into();
+        self
     }
 }
 
@@ -38,7 +53,7 @@ impl<S: Store> ProtocolHandler for BlobsProtocol<S> {
             provider::handle_connection(
                 conn.await?,
                 self.store.clone(),
-                EventSender::default(),
+                self.events.clone(),
                 self.rt.clone(),
             )
             .await;
@@ -0,0 +1,263 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Fetch byte ranges of blobs without downloading them completely.
+//!
+//! Applications can ask peers for a range of a blob only, for example when seeking in a video or
+//! to show a thumbnail-sized prefix of an image. Blob data is verified against the BLAKE3 hash
+//! tree of the blob in chunks of 1 KiB, every chunk of the range is hence verified on its own and
+//! streamed as soon as it arrived.
+//!
+//! Ranges are read from the local store if the blob is complete there, fetched ranges are not
+//! added to the store.
+use std::ops::Range;
+
+use anyhow::{Result, anyhow, bail, ensure};
+use bytes::Bytes;
+use futures_lite::Stream;
+use iroh::{Endpoint, NodeAddr};
+use iroh_blobs::Hash as IrohHash;
+use iroh_blobs::bao_tree::io::BaoContentItem;
+use iroh_blobs::bao_tree::{ChunkNum, ChunkRanges};
+use iroh_blobs::get::fsm::{self, BlobContentNext, ConnectedNext, EndBlobNext};
+use iroh_blobs::protocol::{GetRequest, RangeSpecSeq};
+use iroh_blobs::store::{MapEntry, Store};
+use iroh_blobs::util::local_pool::LocalPoolHandle;
+use iroh_io::AsyncSliceReader;
+use p2panda_core::Hash;
+use p2panda_net::{Network, TopicId};
+use p2panda_sync::TopicQuery;
+use serde_error::Error as RpcError;
+use tracing::debug;
+
+use crate::download::{CHUNK_SIZE, READ_SIZE, peer_addrs};
+use crate::protocol::BLOBS_ALPN;
+
+/// Event of a range fetch.
+#[derive(Debug, Clone)]
+pub enum FetchRangeEvent {
+    /// Verified data of the range, in order, starting at the given offset of the blob.
+    Data {
+        offset: u64,
+        bytes: Bytes,
+    },
+
+    /// The range was fetched completely.
+    ///
+    /// Contains the size of the whole blob, ranges reaching beyond it end with its last byte.
+    Done {
+        size: u64,
+    },
+
+    Abort(RpcError),
+}
+
+pub(crate) async fn fetch_range<T, S>(
+    network: Network<T>,
+    store: S,
+    pool_handle: LocalPoolHandle,
+    hash: Hash,
+    range: Range<u64>,
+) -> impl Stream<Item = FetchRangeEvent>
+where
+    T: TopicQuery + TopicId + 'static,
+    S: Store,
+{
+    let (sender, receiver) = async_channel::bounded(64);
+
+    pool_handle.spawn_detached(move || async move {
+        let event = match fetch_range_inner(network, store, hash, range, &sender).await {
+            Ok(size) => FetchRangeEvent::Done { size },
+            Err(err) => FetchRangeEvent::Abort(RpcError::new(&*err)),
+        };
+        sender.send(event).await.ok();
+    });
+
+    receiver
+}
+
+async fn fetch_range_inner<T, S>(
+    network: Network<T>,
+    store: S,
+    hash: Hash,
+    range: Range<u64>,
+    sender: &async_channel::Sender<FetchRangeEvent>,
+) -> Result<u64>
+where
+    T: TopicQuery + TopicId + 'static,
+    S: Store,
+{
+    ensure!(range.start < range.end, "empty range {range:?}");
+    let iroh_hash = IrohHash::from_bytes(*hash.as_bytes());
+
+    let entry = store.get(&iroh_hash).await?;
+    if let Some(entry) = entry.filter(|entry| entry.is_complete()) {
+        return read_range(entry, range, sender).await;
+    }
+
+    // Peers which announced the blob are asked first. If a peer fails in the middle of the range
+    // the next one continues where it stopped.
+    let (provider_addrs, other_addrs) = peer_addrs(&network, hash).await?;
+    let mut offset = range.start;
+    let mut last_err = None;
+    for addr in provider_addrs.into_iter().chain(other_addrs) {
+        match fetch_from_peer(
+            network.endpoint(),
+            addr,
+            iroh_hash,
+            range.end,
+            &mut offset,
+            sender,
+        )
+        .await
+        {
+            Ok(size) => return Ok(size),
+            Err(err) => {
+                debug!(%hash, "fetching range from peer failed: {err}");
+                last_err = Some(err);
+            }
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| anyhow!("no peer provides blob {hash}")))
+}
+
+/// Read a range of a complete blob from the store.
+async fn read_range<E: MapEntry>(
+    entry: E,
+    range: Range<u64>,
+    sender: &async_channel::Sender<FetchRangeEvent>,
+) -> Result<u64> {
+    let size = entry.size().value();
+    let end = range.end.min(size);
+    let mut reader = entry.data_reader().await?;
+
+    let mut offset = range.start;
+    while offset < end {
+        let len = READ_SIZE.min((end - offset) as usize);
+        let bytes = reader.read_at(offset, len).await?;
+        if bytes.is_empty() {
+            break;
+        }
+        let len = bytes.len() as u64;
+        if sender
+            .send(FetchRangeEvent::Data { offset, bytes })
+            .await
+            .is_err()
+        {
+            break;
+        }
+        offset += len;
+    }
+
+    Ok(size)
+}
+
+/// Request the chunks covering `offset..end` from a peer and send their verified data.
+///
+/// `offset` is advanced with every sent byte, so a failed request can be continued with another
+/// peer.
+async fn fetch_from_peer(
+    endpoint: &Endpoint,
+    addr: NodeAddr,
+    hash: IrohHash,
+    end: u64,
+    offset: &mut u64,
+    sender: &async_channel::Sender<FetchRangeEvent>,
+) -> Result<u64> {
+    let connection = endpoint.connect(addr, BLOBS_ALPN).await?;
+    let chunks =
+        ChunkRanges::from(ChunkNum(*offset / CHUNK_SIZE)..ChunkNum(end.div_ceil(CHUNK_SIZE)));
+    let request = GetRequest::new(hash, RangeSpecSeq::from_ranges([chunks]));
+
+    let connected = fsm::start(connection, request).next().await?;
+    let ConnectedNext::StartRoot(start) = connected.next().await? else {
+        bail!("peer did not send the requested blob");
+    };
+    let (mut content, size) = start.next().next().await?;
+    let end = end.min(size);
+
+    let end_blob = loop {
+        match content.next().await {
+            BlobContentNext::More((next, item)) => {
+                content = next;
+
+                // Parent nodes of the hash tree are only used for verification.
+                let BaoContentItem::Leaf(leaf) = item? else {
+                    continue;
+                };
+                let Some((leaf_offset, bytes)) = trim(leaf.offset, leaf.data, *offset..end) else {
+                    continue;
+                };
+
+                let len = bytes.len() as u64;
+                let event = FetchRangeEvent::Data {
+                    offset: leaf_offset,
+                    bytes,
+                };
+                if sender.send(event).await.is_err() {
+                    // Nobody is interested in the range anymore.
+                    return Ok(size);
+                }
+                *offset = leaf_offset + len;
+            }
+            BlobContentNext::Done(end_blob) => break end_blob,
+        }
+    };
+
+    if let EndBlobNext::Closing(closing) = end_blob.next() {
+        closing.next().await?;
+    }
+
+    ensure!(*offset >= end, "peer sent incomplete range");
+    Ok(size)
+}
+
+/// Returns the part of a verified leaf which lies within the range, together with its offset.
+///
+/// Leaves always contain whole chunks, the first and last leaf of a range might hence contain
+/// data before or after it.
+fn trim(leaf_offset: u64, data: Bytes, range: Range<u64>) -> Option<(u64, Bytes)> {
+    let leaf_end = leaf_offset + data.len() as u64;
+    let start = range.start.max(leaf_offset);
+    let end = range.end.min(leaf_end);
+    if start >= end {
+        return None;
+    }
+
+    let bytes = data.slice((start - leaf_offset) as usize..(end - leaf_offset) as usize);
+    Some((start, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::trim;
+
+    #[test]
+    fn trim_leaves_to_range() {
+        let data = Bytes::from_iter(0..=255u8);
+
+        // Leaf covers the whole range.
+        assert_eq!(
+            trim(1024, data.clone(), 1034..1044),
+            Some((1034, data.slice(10..20)))
+        );
+
+        // Leaf starts before and ends within the range.
+        assert_eq!(
+            trim(1024, data.clone(), 1200..2000),
+            Some((1200, data.slice(176..)))
+        );
+
+        // Leaf starts within the range.
+        assert_eq!(
+            trim(1024, data.clone(), 1000..1034),
+            Some((1024, data.slice(..10)))
+        );
+
+        // Leaf lies outside of the range.
+        assert_eq!(trim(1024, data.clone(), 0..1024), None);
+        assert_eq!(trim(1024, data, 1280..2048), None);
+    }
+}
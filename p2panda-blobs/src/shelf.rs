@@ -0,0 +1,410 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Keep blobs in S3-compatible object storage.
+//!
+//! Always-on "shelf" nodes often serve media libraries which are larger than their local disk.
+//! Blobs can be moved to object storage with `Blobs::shelve_blob`, which uploads them in parts and
+//! unpins the local copy so it gets removed by the next garbage collection. The local store then
+//! acts as a cache: `Blobs::restore_blob` brings a shelved blob back, for example when peers are
+//! interested in it again.
+//!
+//! Shelved blobs are verified against their hash while they are uploaded and again when they are
+//! restored. Byte ranges of shelved blobs can be read without restoring them with
+//! `BlobShelf::get_range`.
+//!
+//! Peers are served from the local store. When a peer requests a blob which was shelved and
+//! removed locally, it is restored from the object storage before the request is answered, the
+//! restored copy stays in the local store until it is removed again by garbage collection.
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail, ensure};
+use bytes::Bytes;
+use futures_lite::future::Boxed as BoxedFuture;
+use futures_lite::{Stream, StreamExt};
+use iroh_blobs::provider::{CustomEventSender, Event};
+use iroh_blobs::store::{MapEntry, Store};
+use iroh_blobs::util::progress::IgnoreProgressSender;
+use iroh_blobs::{BlobFormat, Hash as IrohHash, HashAndFormat};
+use iroh_io::AsyncSliceReader;
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path;
+use object_store::{ObjectStore, WriteMultipart};
+use p2panda_core::Hash;
+use tracing::warn;
+
+use crate::download::READ_SIZE;
+use crate::gc::{track, unpin};
+
+/// Maximum number of parts uploaded concurrently.
+const MAX_CONCURRENT_PARTS: usize = 8;
+
+/// Options to connect to an S3-compatible object storage.
+///
+/// Options which are not set are read from the usual `AWS_*` environment variables.
+#[derive(Clone, Default)]
+pub struct S3Options {
+    bucket: String,
+    region: Option<String>,
+    endpoint: Option<String>,
+    credentials: Option<(String, String)>,
+    prefix: Option<String>,
+    allow_http: bool,
+}
+
+impl S3Options {
+    pub fn new(bucket: impl Into<String>) -> Self {
+        Self {
+            bucket: bucket.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn region(mut self, region: impl Into<String>) -> Self {
+        self.region = Some(region.into());
+        self
+    }
+
+    /// Endpoint of S3-compatible services other than AWS, for example Garage or MinIO.
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    pub fn credentials(
+        mut self,
+        access_key_id: impl Into<String>,
+        secret_access_key: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((access_key_id.into(), secret_access_key.into()));
+        self
+    }
+
+    /// Store blobs under the given path prefix in the bucket.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Allow unencrypted connections to the endpoint, for example to a local test instance.
+    pub fn allow_http(mut self, allow_http: bool) -> Self {
+        self.allow_http = allow_http;
+        self
+    }
+}
+
+impl fmt::Debug for S3Options {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print secret credentials.
+        f.debug_struct("S3Options")
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("endpoint", &self.endpoint)
+            .field("prefix", &self.prefix)
+            .field("allow_http", &self.allow_http)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Object storage holding blobs, addressed by their hash.
+#[derive(Clone, Debug)]
+pub struct BlobShelf {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+}
+
+impl BlobShelf {
+    /// Returns a shelf backed by any object storage, with blobs stored under the given prefix.
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: impl Into<Path>) -> Self {
+        Self {
+            store,
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Returns a shelf backed by an S3-compatible object storage.
+    pub fn s3(options: S3Options) -> Result<Self> {
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(options.bucket)
+            .with_allow_http(options.allow_http);
+        if let Some(region) = options.region {
+            builder = builder.with_region(region);
+        }
+        if let Some(endpoint) = options.endpoint {
+            builder = builder.with_endpoint(endpoint);
+        }
+        if let Some((access_key_id, secret_access_key)) = options.credentials {
+            builder = builder
+                .with_access_key_id(access_key_id)
+                .with_secret_access_key(secret_access_key);
+        }
+
+        let store = builder.build().context("invalid S3 options")?;
+        Ok(Self::new(
+            Arc::new(store),
+            options.prefix.unwrap_or_default(),
+        ))
+    }
+
+    /// Start uploading a blob with the given hash.
+    pub async fn upload(&self, hash: Hash) -> Result<BlobUpload> {
+        let upload = self.store.put_multipart(&self.location(&hash)).await?;
+        Ok(BlobUpload {
+            hash,
+            writer: WriteMultipart::new(upload),
+            hasher: blake3::Hasher::new(),
+            size: 0,
+        })
+    }
+
+    /// Returns the size of a shelved blob in bytes or `None` if it isn't shelved.
+    pub async fn size(&self, hash: Hash) -> Result<Option<u64>> {
+        match self.store.head(&self.location(&hash)).await {
+            Ok(meta) => Ok(Some(meta.size)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Read a byte range of a shelved blob.
+    pub async fn get_range(&self, hash: Hash, range: Range<u64>) -> Result<Bytes> {
+        let bytes = self.store.get_range(&self.location(&hash), range).await?;
+        Ok(bytes)
+    }
+
+    /// Stream all data of a shelved blob.
+    pub async fn get(
+        &self,
+        hash: Hash,
+    ) -> Result<impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static> {
+        let result = self.store.get(&self.location(&hash)).await?;
+        Ok(result
+            .into_stream()
+            .map(|bytes| bytes.map_err(io::Error::other)))
+    }
+
+    /// Remove a blob from the shelf.
+    pub async fn delete(&self, hash: Hash) -> Result<()> {
+        self.store.delete(&self.location(&hash)).await?;
+        Ok(())
+    }
+
+    fn location(&self, hash: &Hash) -> Path {
+        self.prefix.child(hash.to_hex())
+    }
+}
+
+/// Multipart upload of a blob to a `BlobShelf`.
+///
+/// The data is hashed while it is uploaded, the upload is only completed if it matches the hash
+/// of the blob.
+pub struct BlobUpload {
+    hash: Hash,
+    writer: WriteMultipart,
+    hasher: blake3::Hasher,
+    size: u64,
+}
+
+impl BlobUpload {
+    /// Append data to the blob.
+    pub async fn write(&mut self, bytes: Bytes) -> Result<()> {
+        self.writer.wait_for_capacity(MAX_CONCURRENT_PARTS).await?;
+        self.hasher.update(&bytes);
+        self.size += bytes.len() as u64;
+        self.writer.put(bytes);
+        Ok(())
+    }
+
+    /// Complete the upload, returns the size of the blob in bytes.
+    pub async fn finish(self) -> Result<u64> {
+        let hash: Hash = self.hasher.finalize().into();
+        if hash != self.hash {
+            self.writer.abort().await?;
+            bail!("uploaded data does not match blob {}", self.hash);
+        }
+
+        self.writer.finish().await?;
+        Ok(self.size)
+    }
+
+    /// Cancel the upload and remove all uploaded parts.
+    pub async fn abort(self) -> Result<()> {
+        self.writer.abort().await?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BlobUpload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlobUpload")
+            .field("hash", &self.hash)
+            .field("size", &self.size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Upload a complete blob to the shelf and unpin the local copy.
+pub(crate) async fn shelve_blob<S: Store>(store: &S, shelf: &BlobShelf, hash: Hash) -> Result<()> {
+    let entry = store
+        .get(&IrohHash::from_bytes(*hash.as_bytes()))
+        .await?
+        .context("entry not there")?;
+    ensure!(entry.is_complete(), "blob {hash} is not complete");
+
+    // Blobs are only written once, shelving them again is a no-op.
+    if shelf.size(hash).await?.is_some() {
+        return unpin(store, hash).await;
+    }
+
+    let size = entry.size().value();
+    let mut reader = entry.data_reader().await?;
+    let mut upload = shelf.upload(hash).await?;
+    let mut offset = 0;
+    while offset < size {
+        let bytes = match reader.read_at(offset, READ_SIZE).await {
+            Ok(bytes) if !bytes.is_empty() => bytes,
+            Ok(_) => {
+                upload.abort().await?;
+                bail!("blob {hash} ended unexpectedly");
+            }
+            Err(err) => {
+                upload.abort().await?;
+                return Err(err.into());
+            }
+        };
+        offset += bytes.len() as u64;
+        upload.write(bytes).await?;
+    }
+    upload.finish().await?;
+
+    unpin(store, hash).await
+}
+
+/// Import a shelved blob back into the local store.
+///
+/// Restored blobs are tracked for garbage collection but not pinned, like downloaded blobs.
+pub(crate) async fn restore_blob<S: Store>(store: &S, shelf: &BlobShelf, hash: Hash) -> Result<()> {
+    let entry = store.get(&IrohHash::from_bytes(*hash.as_bytes())).await?;
+    if entry.is_some_and(|entry| entry.is_complete()) {
+        return Ok(());
+    }
+
+    let data = shelf.get(hash).await?;
+    let (tag, _size) = store
+        .import_stream(data, BlobFormat::Raw, IgnoreProgressSender::default())
+        .await?;
+    let HashAndFormat {
+        hash: restored_hash,
+        ..
+    } = *tag.inner();
+    ensure!(
+        restored_hash.as_bytes() == hash.as_bytes(),
+        "shelved blob {hash} is corrupted"
+    );
+
+    track(store, hash).await
+}
+
+/// Restores shelved blobs requested by peers, so that they can be served from the local store.
+///
+/// The blobs protocol waits for the event of an incoming request to be handled before looking up
+/// the requested blob.
+#[derive(Debug)]
+pub(crate) struct RestoreOnRequest<S> {
+    store: S,
+    shelf: BlobShelf,
+}
+
+impl<S: Store> RestoreOnRequest<S> {
+    pub fn new(store: S, shelf: BlobShelf) -> Self {
+        Self { store, shelf }
+    }
+}
+
+impl<S: Store> CustomEventSender for RestoreOnRequest<S> {
+    fn send(&self, event: Event) -> BoxedFuture<()> {
+        let Event::GetRequestReceived { hash, .. } = event else {
+            return Box::pin(async {});
+        };
+
+        let store = self.store.clone();
+        let shelf = self.shelf.clone();
+        Box::pin(async move {
+            let hash = Hash::from_bytes(*hash.as_bytes());
+            if let Err(err) = restore_requested_blob(&store, &shelf, hash).await {
+                warn!("failed to restore requested blob {hash}: {err}");
+            }
+        })
+    }
+
+    fn try_send(&self, _event: Event) {}
+}
+
+/// Restore a requested blob if it is shelved and missing in the local store.
+async fn restore_requested_blob<S: Store>(store: &S, shelf: &BlobShelf, hash: Hash) -> Result<()> {
+    let entry = store.get(&IrohHash::from_bytes(*hash.as_bytes())).await?;
+    if entry.is_some_and(|entry| entry.is_complete()) || shelf.size(hash).await?.is_none() {
+        return Ok(());
+    }
+
+    restore_blob(store, shelf, hash).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use iroh_blobs::Hash as IrohHash;
+    use iroh_blobs::provider::{CustomEventSender, Event};
+    use iroh_blobs::store::{Map, MapEntry, mem};
+    use object_store::memory::InMemory;
+    use p2panda_core::Hash;
+
+    use super::{BlobShelf, RestoreOnRequest};
+
+    fn request(hash: Hash) -> Event {
+        Event::GetRequestReceived {
+            connection_id: 1,
+            request_id: 1,
+            hash: IrohHash::from_bytes(*hash.as_bytes()),
+        }
+    }
+
+    #[tokio::test]
+    async fn restore_requested_blobs() {
+        let store = mem::Store::new();
+        let shelf = BlobShelf::new(Arc::new(InMemory::new()), "blobs");
+
+        let bytes = Bytes::from(vec![7; 5000]);
+        let hash = Hash::new(&bytes);
+        let mut upload = shelf.upload(hash).await.unwrap();
+        upload.write(bytes).await.unwrap();
+        upload.finish().await.unwrap();
+
+        let restore = RestoreOnRequest::new(store.clone(), shelf);
+
+        // Requests for blobs which are neither local nor shelved are ignored.
+        let unknown = Hash::new(b"unknown");
+        restore.send(request(unknown)).await;
+        assert!(
+            store
+                .get(&IrohHash::from_bytes(*unknown.as_bytes()))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // Shelved blobs are restored before the request is answered from the local store.
+        restore.send(request(hash)).await;
+        let entry = store
+            .get(&IrohHash::from_bytes(*hash.as_bytes()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(entry.is_complete());
+        assert_eq!(entry.size().value(), 5000);
+    }
+}
@@ -0,0 +1,485 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Signed, time- and scope-limited delegations from one key to another.
+//!
+//! A [`Capability`] allows the holder of a key (the "audience") to act on behalf of another key
+//! (the "issuer") within a given scope and time range, for example to write to a certain log of a
+//! user from another device or a service. Capabilities can be delegated further, each delegation
+//! carries the capability it was derived from as a proof and can only narrow down scope and time
+//! range.
+//!
+//! What a scope contains is defined by the application by implementing [`Scope`].
+//!
+//! ## Example
+//!
+//! ```
+//! use p2panda_core::PrivateKey;
+//! use p2panda_core::capability::{Capability, Scope};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+//! enum LogScope {
+//!     AllLogs,
+//!     Log(u64),
+//! }
+//!
+//! impl Scope for LogScope {
+//!     fn contains(&self, other: &Self) -> bool {
+//!         match (self, other) {
+//!             (LogScope::AllLogs, _) => true,
+//!             (LogScope::Log(a), LogScope::Log(b)) => a == b,
+//!             _ => false,
+//!         }
+//!     }
+//! }
+//!
+//! let user = PrivateKey::new();
+//! let device = PrivateKey::new();
+//!
+//! // The user allows their device to write to log 3 until the given time.
+//! let capability = Capability::builder(device.public_key(), LogScope::Log(3))
+//!     .expires_at(1_800_000_000)
+//!     .sign(&user);
+//!
+//! assert!(capability
+//!     .authorize(&device.public_key(), &LogScope::Log(3), 1_700_000_000)
+//!     .is_ok());
+//! assert!(capability
+//!     .authorize(&device.public_key(), &LogScope::Log(4), 1_700_000_000)
+//!     .is_err());
+//! assert_eq!(capability.root_issuer(), user.public_key());
+//! ```
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::cbor::{DecodeError, EncodeError, decode_cbor, encode_cbor};
+use crate::identity::{KeyProvider, PrivateKey, PublicKey, Signature};
+use crate::{Extensions, Header};
+
+/// Scope of a capability, defined by the application.
+pub trait Scope: Clone + Serialize + DeserializeOwned {
+    /// Returns `true` if everything allowed by `other` is also allowed by this scope.
+    fn contains(&self, other: &Self) -> bool;
+}
+
+/// Signed delegation from an issuer to an audience, limited in scope and time.
+///
+/// Time values are UNIX timestamps in the same unit the application uses for the `timestamp` of
+/// its headers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(serialize = "S: Serialize", deserialize = "S: DeserializeOwned"))]
+pub struct Capability<S> {
+    issuer: PublicKey,
+    audience: PublicKey,
+    scope: S,
+    not_before: u64,
+    expires_at: u64,
+    proof: Option<Box<Capability<S>>>,
+    signature: Signature,
+}
+
+impl<S> Capability<S>
+where
+    S: Scope,
+{
+    /// Returns a builder for a capability granted to the given audience.
+    pub fn builder(audience: PublicKey, scope: S) -> CapabilityBuilder<S> {
+        CapabilityBuilder {
+            audience,
+            scope,
+            not_before: 0,
+            expires_at: u64::MAX,
+            proof: None,
+        }
+    }
+
+    /// Key which signed this capability.
+    pub fn issuer(&self) -> PublicKey {
+        self.issuer
+    }
+
+    /// Key which is allowed to act within the scope of this capability.
+    pub fn audience(&self) -> PublicKey {
+        self.audience
+    }
+
+    pub fn scope(&self) -> &S {
+        &self.scope
+    }
+
+    pub fn not_before(&self) -> u64 {
+        self.not_before
+    }
+
+    pub fn expires_at(&self) -> u64 {
+        self.expires_at
+    }
+
+    /// Capability this one was delegated from.
+    pub fn proof(&self) -> Option<&Capability<S>> {
+        self.proof.as_deref()
+    }
+
+    /// Key at the start of the delegation chain, on whose behalf the audience acts.
+    pub fn root_issuer(&self) -> PublicKey {
+        match &self.proof {
+            Some(proof) => proof.root_issuer(),
+            None => self.issuer,
+        }
+    }
+
+    /// Verifies the signatures of the whole delegation chain and that every capability in it is
+    /// valid at the given time.
+    ///
+    /// Every delegation needs to be issued by the audience of its proof and can not extend the
+    /// scope or time range of it.
+    pub fn verify(&self, now: u64) -> Result<(), CapabilityError> {
+        if !self.issuer.verify(&self.signing_bytes(), &self.signature) {
+            return Err(CapabilityError::InvalidSignature);
+        }
+
+        if now < self.not_before {
+            return Err(CapabilityError::NotYetValid(self.not_before));
+        }
+
+        if now >= self.expires_at {
+            return Err(CapabilityError::Expired(self.expires_at));
+        }
+
+        if let Some(proof) = &self.proof {
+            if proof.audience != self.issuer {
+                return Err(CapabilityError::BrokenChain);
+            }
+
+            if !proof.scope.contains(&self.scope) {
+                return Err(CapabilityError::ScopeEscalation);
+            }
+
+            if self.not_before < proof.not_before || self.expires_at > proof.expires_at {
+                return Err(CapabilityError::TimeEscalation);
+            }
+
+            proof.verify(now)?;
+        }
+
+        Ok(())
+    }
+
+    /// Verifies the capability and checks that it allows the given key to act within the given
+    /// scope at the given time.
+    pub fn authorize(
+        &self,
+        author: &PublicKey,
+        scope: &S,
+        now: u64,
+    ) -> Result<(), CapabilityError> {
+        if &self.audience != author {
+            return Err(CapabilityError::WrongAudience);
+        }
+
+        if !self.scope.contains(scope) {
+            return Err(CapabilityError::OutOfScope);
+        }
+
+        self.verify(now)
+    }
+
+    /// Checks that the capability allows the author of the header to write within the given
+    /// scope at the time claimed in the header.
+    pub fn authorize_header<E>(&self, header: &Header<E>, scope: &S) -> Result<(), CapabilityError>
+    where
+        E: Extensions,
+    {
+        self.authorize(&header.public_key, scope, header.timestamp)
+    }
+
+    /// Encodes the capability in CBOR format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_cbor(self)
+            // We can be sure that all values in this module are serializable and _if_ ciborium
+            // still fails then because of something really bad ..
+            .expect("CBOR encoder failed due to an critical IO error")
+    }
+
+    /// Decodes a capability from bytes in CBOR format.
+    ///
+    /// The capability is not verified, see [`verify`](Capability::verify).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        decode_cbor(bytes)
+    }
+
+    fn signing_bytes(&self) -> Vec<u8> {
+        signing_bytes(
+            &self.issuer,
+            &self.audience,
+            &self.scope,
+            self.not_before,
+            self.expires_at,
+            self.proof.as_deref(),
+        )
+        .expect("CBOR encoder failed due to an critical IO error")
+    }
+}
+
+fn signing_bytes<S: Serialize>(
+    issuer: &PublicKey,
+    audience: &PublicKey,
+    scope: &S,
+    not_before: u64,
+    expires_at: u64,
+    proof: Option<&Capability<S>>,
+) -> Result<Vec<u8>, EncodeError> {
+    encode_cbor(&(issuer, audience, scope, not_before, expires_at, proof))
+}
+
+/// Builder for a [`Capability`], see [`Capability::builder`].
+#[derive(Clone, Debug)]
+pub struct CapabilityBuilder<S> {
+    audience: PublicKey,
+    scope: S,
+    not_before: u64,
+    expires_at: u64,
+    proof: Option<Capability<S>>,
+}
+
+impl<S> CapabilityBuilder<S>
+where
+    S: Scope,
+{
+    /// Capability is not valid before this time, defaults to 0.
+    pub fn not_before(mut self, timestamp: u64) -> Self {
+        self.not_before = timestamp;
+        self
+    }
+
+    /// Capability is not valid anymore at this time, defaults to never expiring.
+    pub fn expires_at(mut self, timestamp: u64) -> Self {
+        self.expires_at = timestamp;
+        self
+    }
+
+    /// Delegates the given capability further, the signing key needs to be its audience.
+    pub fn proof(mut self, proof: Capability<S>) -> Self {
+        self.proof = Some(proof);
+        self
+    }
+
+    /// Signs the capability with the issuer's private key.
+    pub fn sign(self, private_key: &PrivateKey) -> Capability<S> {
+        match self.sign_with(private_key) {
+            Ok(capability) => capability,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Signs the capability with a signature requested from the provided `KeyProvider`.
+    pub fn sign_with<K: KeyProvider>(self, provider: &K) -> Result<Capability<S>, K::Error> {
+        let issuer = provider.public_key();
+        let bytes = signing_bytes(
+            &issuer,
+            &self.audience,
+            &self.scope,
+            self.not_before,
+            self.expires_at,
+            self.proof.as_ref(),
+        )
+        .expect("CBOR encoder failed due to an critical IO error");
+        let signature = provider.sign(&bytes)?;
+
+        Ok(Capability {
+            issuer,
+            audience: self.audience,
+            scope: self.scope,
+            not_before: self.not_before,
+            expires_at: self.expires_at,
+            proof: self.proof.map(Box::new),
+            signature,
+        })
+    }
+}
+
+/// Errors which can occur when verifying capabilities.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CapabilityError {
+    /// Capability or one of its proofs was not signed by its issuer.
+    #[error("capability signature is invalid")]
+    InvalidSignature,
+
+    /// Capability or one of its proofs is not valid yet.
+    #[error("capability is not valid before {0}")]
+    NotYetValid(u64),
+
+    /// Capability or one of its proofs expired.
+    #[error("capability expired at {0}")]
+    Expired(u64),
+
+    /// Delegation was not issued by the audience of its proof.
+    #[error("capability was not issued by the audience of its proof")]
+    BrokenChain,
+
+    /// Delegation extends the scope of its proof.
+    #[error("capability scope exceeds the scope of its proof")]
+    ScopeEscalation,
+
+    /// Delegation extends the time range of its proof.
+    #[error("capability time range exceeds the time range of its proof")]
+    TimeEscalation,
+
+    /// Capability was granted to another key.
+    #[error("capability was granted to another key")]
+    WrongAudience,
+
+    /// Requested scope is not contained in the capability.
+    #[error("requested scope is not granted by capability")]
+    OutOfScope,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::{Header, PrivateKey};
+
+    use super::{Capability, CapabilityError, Scope};
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    enum LogScope {
+        AllLogs,
+        Log(u64),
+    }
+
+    impl Scope for LogScope {
+        fn contains(&self, other: &Self) -> bool {
+            match (self, other) {
+                (LogScope::AllLogs, _) => true,
+                (LogScope::Log(a), LogScope::Log(b)) => a == b,
+                _ => false,
+            }
+        }
+    }
+
+    #[test]
+    fn time_limited() {
+        let user = PrivateKey::new();
+        let device = PrivateKey::new();
+
+        let capability = Capability::builder(device.public_key(), LogScope::AllLogs)
+            .not_before(100)
+            .expires_at(200)
+            .sign(&user);
+
+        assert_eq!(
+            capability.verify(99),
+            Err(CapabilityError::NotYetValid(100))
+        );
+        assert!(capability.verify(100).is_ok());
+        assert_eq!(capability.verify(200), Err(CapabilityError::Expired(200)));
+    }
+
+    #[test]
+    fn delegation_chain() {
+        let user = PrivateKey::new();
+        let laptop = PrivateKey::new();
+        let service = PrivateKey::new();
+
+        let root = Capability::builder(laptop.public_key(), LogScope::AllLogs)
+            .expires_at(1000)
+            .sign(&user);
+        let delegation = Capability::builder(service.public_key(), LogScope::Log(1))
+            .expires_at(500)
+            .proof(root.clone())
+            .sign(&laptop);
+
+        assert!(
+            delegation
+                .authorize(&service.public_key(), &LogScope::Log(1), 10)
+                .is_ok()
+        );
+        assert_eq!(delegation.root_issuer(), user.public_key());
+        assert_eq!(
+            delegation.authorize(&service.public_key(), &LogScope::Log(2), 10),
+            Err(CapabilityError::OutOfScope)
+        );
+        assert_eq!(
+            delegation.authorize(&laptop.public_key(), &LogScope::Log(1), 10),
+            Err(CapabilityError::WrongAudience)
+        );
+
+        // Delegations can't extend their proof.
+        let escalation = Capability::builder(service.public_key(), LogScope::AllLogs)
+            .expires_at(500)
+            .proof(
+                Capability::builder(laptop.public_key(), LogScope::Log(1))
+                    .expires_at(1000)
+                    .sign(&user),
+            )
+            .sign(&laptop);
+        assert_eq!(escalation.verify(10), Err(CapabilityError::ScopeEscalation));
+
+        let longer = Capability::builder(service.public_key(), LogScope::Log(1))
+            .expires_at(2000)
+            .proof(root.clone())
+            .sign(&laptop);
+        assert_eq!(longer.verify(10), Err(CapabilityError::TimeEscalation));
+
+        // Only the audience of a capability can delegate it further.
+        let stolen = Capability::builder(service.public_key(), LogScope::Log(1))
+            .expires_at(500)
+            .proof(root)
+            .sign(&service);
+        assert_eq!(stolen.verify(10), Err(CapabilityError::BrokenChain));
+    }
+
+    #[test]
+    fn tampered_capability() {
+        let user = PrivateKey::new();
+        let device = PrivateKey::new();
+
+        let capability = Capability::builder(device.public_key(), LogScope::Log(1))
+            .expires_at(200)
+            .sign(&user);
+        let bytes = capability.to_bytes();
+        assert_eq!(Capability::from_bytes(&bytes).unwrap(), capability);
+
+        let mut tampered = capability;
+        tampered.scope = LogScope::AllLogs;
+        assert_eq!(tampered.verify(10), Err(CapabilityError::InvalidSignature));
+    }
+
+    #[test]
+    fn authorize_header() {
+        let user = PrivateKey::new();
+        let device = PrivateKey::new();
+
+        let capability = Capability::builder(device.public_key(), LogScope::Log(1))
+            .expires_at(200)
+            .sign(&user);
+
+        let mut header = Header::<()> {
+            version: 1,
+            public_key: device.public_key(),
+            signature: None,
+            payload_size: 0,
+            payload_hash: None,
+            timestamp: 100,
+            seq_num: 0,
+            backlink: None,
+            previous: vec![],
+            extensions: None,
+        };
+        header.sign(&device);
+        assert!(
+            capability
+                .authorize_header(&header, &LogScope::Log(1))
+                .is_ok()
+        );
+
+        header.timestamp = 300;
+        header.sign(&device);
+        assert_eq!(
+            capability.authorize_header(&header, &LogScope::Log(1)),
+            Err(CapabilityError::Expired(200))
+        );
+    }
+}
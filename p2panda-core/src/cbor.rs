@@ -5,9 +5,16 @@
 //! As per p2panda specification data-types like operation headers are encoded in the Concise
 //! Binary Object Representation (CBOR) format.
 //!
+//! For byte-for-byte interoperability with other implementations values can be encoded in a
+//! canonical form with [`encode_cbor_canonical`], following the "core deterministic encoding
+//! requirements" of [RFC 8949]: integers and lengths use the shortest possible form, arrays and
+//! maps have definite lengths and map keys are sorted by their encoded bytes.
+//!
 //! [CBOR]: https://cbor.io/
+//! [RFC 8949]: https://www.rfc-editor.org/rfc/rfc8949.html#section-4.2.1
 use std::io::Read;
 
+use ciborium::Value;
 use ciborium::de::Error as DeserializeError;
 use ciborium::ser::Error as SerializeError;
 use serde::{Deserialize, Serialize};
@@ -20,12 +27,79 @@ pub fn encode_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>, EncodeError> {
     Ok(bytes)
 }
 
+/// Serializes a value into canonical CBOR format.
+///
+/// Fails if a map contains the same key twice.
+pub fn encode_cbor_canonical<T: Serialize>(value: &T) -> Result<Vec<u8>, EncodeError> {
+    let value = Value::serialized(value).map_err(|err| EncodeError::Value(err.to_string()))?;
+    encode_cbor(&canonicalize(value)?)
+}
+
+/// Returns `true` if the bytes are a single value in canonical CBOR format.
+pub fn is_canonical_cbor(bytes: &[u8]) -> Result<bool, DecodeError> {
+    let value: Value = decode_cbor(bytes)?;
+    match encode_cbor_canonical(&value) {
+        Ok(canonical) => Ok(canonical == bytes),
+        // Maps with duplicate keys can't be canonical.
+        Err(_) => Ok(false),
+    }
+}
+
+fn canonicalize(value: Value) -> Result<Value, EncodeError> {
+    let value = match value {
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(canonicalize)
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Map(entries) => {
+            let mut entries = entries
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = canonicalize(key)?;
+                    let key_bytes = encode_cbor(&key)?;
+                    Ok((key_bytes, key, canonicalize(value)?))
+                })
+                .collect::<Result<Vec<_>, EncodeError>>()?;
+
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            if entries.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+                return Err(EncodeError::Value("duplicate key in map".into()));
+            }
+
+            Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(_, key, value)| (key, value))
+                    .collect(),
+            )
+        }
+        Value::Tag(tag, inner) => Value::Tag(tag, Box::new(canonicalize(*inner)?)),
+        value => value,
+    };
+    Ok(value)
+}
+
 /// Deserializes a value which was formatted in CBOR.
 pub fn decode_cbor<T: for<'a> Deserialize<'a>, R: Read>(reader: R) -> Result<T, DecodeError> {
     let value = ciborium::from_reader::<T, R>(reader).map_err(Into::<DecodeError>::into)?;
     Ok(value)
 }
 
+/// Deserializes a value which was formatted in CBOR, failing if it is nested deeper than the given
+/// limit.
+///
+/// `decode_cbor` uses a default recursion limit of 256.
+pub fn decode_cbor_with_recursion_limit<T: for<'a> Deserialize<'a>, R: Read>(
+    reader: R,
+    recursion_limit: usize,
+) -> Result<T, DecodeError> {
+    let value = ciborium::de::from_reader_with_recursion_limit::<T, R>(reader, recursion_limit)
+        .map_err(Into::<DecodeError>::into)?;
+    Ok(value)
+}
+
 /// An error occurred during CBOR serialization.
 #[derive(Debug, Error)]
 pub enum EncodeError {
@@ -95,9 +169,15 @@ impl From<DeserializeError<std::io::Error>> for DecodeError {
 
 #[cfg(test)]
 mod tests {
-    use crate::{Body, Header, PrivateKey};
+    use serde::{Deserialize, Serialize};
 
-    use super::{decode_cbor, encode_cbor};
+    use crate::extensions::ExtensionMap;
+    use crate::{Body, Hash, Header, PrivateKey};
+
+    use super::{
+        DecodeError, decode_cbor, decode_cbor_with_recursion_limit, encode_cbor,
+        encode_cbor_canonical, is_canonical_cbor,
+    };
 
     #[test]
     fn encode_decode() {
@@ -116,4 +196,122 @@ mod tests {
 
         assert_eq!(header.hash(), header_again.hash());
     }
+
+    #[test]
+    fn recursion_limit() {
+        let nested = vec![vec![vec![1u8]]];
+        let bytes = encode_cbor(&nested).unwrap();
+
+        let result: Result<Vec<Vec<Vec<u8>>>, _> = decode_cbor_with_recursion_limit(&bytes[..], 3);
+        assert_eq!(result.unwrap(), nested);
+
+        let result: Result<Vec<Vec<Vec<u8>>>, _> = decode_cbor_with_recursion_limit(&bytes[..], 2);
+        assert!(matches!(result, Err(DecodeError::RecursionLimitExceeded)));
+    }
+
+    #[test]
+    fn canonical_map_keys() {
+        #[derive(Serialize, Deserialize)]
+        struct Unsorted {
+            zebra: u64,
+            ant: u64,
+        }
+
+        let value = Unsorted { zebra: 1, ant: 2 };
+        let bytes = encode_cbor(&value).unwrap();
+        assert!(!is_canonical_cbor(&bytes).unwrap());
+
+        let canonical = encode_cbor_canonical(&value).unwrap();
+        assert!(is_canonical_cbor(&canonical).unwrap());
+        assert_ne!(bytes, canonical);
+
+        // Shorter keys are sorted first as their encoding is shorter.
+        let map: Vec<(String, u64)> =
+            decode_cbor::<std::collections::BTreeMap<String, u64>, _>(&canonical[..])
+                .unwrap()
+                .into_iter()
+                .collect();
+        assert_eq!(map, vec![("ant".into(), 2), ("zebra".into(), 1)]);
+        assert_eq!(&canonical[1..5], &[0x63, b'a', b'n', b't']);
+
+        // Integers encoded with more bytes than needed are not canonical.
+        assert!(is_canonical_cbor(&[0x01]).unwrap());
+        assert!(!is_canonical_cbor(&[0x18, 0x01]).unwrap());
+    }
+
+    #[derive(Deserialize)]
+    struct TestVectors {
+        vectors: Vec<HeaderVector>,
+    }
+
+    #[derive(Deserialize)]
+    struct HeaderVector {
+        description: String,
+        private_key: String,
+        version: u64,
+        timestamp: u64,
+        seq_num: u64,
+        backlink: Option<Hash>,
+        previous: Vec<Hash>,
+        body: Option<String>,
+        extensions: Option<String>,
+        header_bytes: String,
+        signature: String,
+        hash: Hash,
+    }
+
+    #[test]
+    fn header_test_vectors() {
+        let test_vectors: TestVectors =
+            serde_json::from_str(include_str!("../test-vectors/header.json")).unwrap();
+
+        for vector in test_vectors.vectors {
+            let private_key: [u8; 32] = hex::decode(&vector.private_key)
+                .unwrap()
+                .try_into()
+                .unwrap();
+            let private_key = PrivateKey::from_bytes(&private_key);
+            let body = vector
+                .body
+                .map(|body| Body::new(&hex::decode(body).unwrap()));
+            let extensions: Option<ExtensionMap> = vector
+                .extensions
+                .map(|bytes| decode_cbor(&hex::decode(bytes).unwrap()[..]).unwrap());
+
+            let mut header = Header {
+                version: vector.version,
+                public_key: private_key.public_key(),
+                signature: None,
+                payload_size: body.as_ref().map_or(0, |body| body.size()),
+                payload_hash: body.as_ref().map(|body| body.hash()),
+                timestamp: vector.timestamp,
+                seq_num: vector.seq_num,
+                backlink: vector.backlink,
+                previous: vector.previous,
+                extensions,
+            };
+            header.sign(&private_key);
+
+            let header_bytes = hex::encode(header.to_bytes());
+            assert_eq!(header_bytes, vector.header_bytes, "{}", vector.description);
+            assert_eq!(
+                hex::encode(header.to_canonical_bytes().unwrap()),
+                vector.header_bytes,
+                "{}",
+                vector.description
+            );
+            assert_eq!(
+                header.signature.unwrap().to_hex(),
+                vector.signature,
+                "{}",
+                vector.description
+            );
+            assert_eq!(header.hash(), vector.hash, "{}", vector.description);
+
+            let decoded: Header<ExtensionMap> =
+                decode_cbor(&hex::decode(&vector.header_bytes).unwrap()[..]).unwrap();
+            assert!(decoded.verify(), "{}", vector.description);
+            assert_eq!(decoded.hash(), vector.hash, "{}", vector.description);
+        }
+    }
 }
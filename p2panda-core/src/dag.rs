@@ -0,0 +1,258 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Utilities to validate the graph formed by the `previous` links of operations.
+//!
+//! Operations of multiple authors can refer to each other with the `previous` field in their
+//! headers, forming a directed acyclic graph (DAG), for example of all changes to a document.
+//! [`OperationGraph`] collects these links and checks the graph for integrity: every operation
+//! needs to be known (or fetched from other peers) before the graph is complete, and links can
+//! not form cycles.
+//!
+//! The latest operations of the graph, those not referred to by any other operation, are its
+//! "tips". New operations usually refer to all current tips in their `previous` field.
+//!
+//! ## Example
+//!
+//! ```
+//! use p2panda_core::Hash;
+//! use p2panda_core::dag::OperationGraph;
+//!
+//! let a = Hash::new(b"a");
+//! let b = Hash::new(b"b");
+//! let c = Hash::new(b"c");
+//!
+//! let mut graph = OperationGraph::new();
+//! graph.insert_links(a, &[]);
+//! graph.insert_links(c, &[a, b]);
+//!
+//! // We still need to fetch "b" from other peers
+//! assert_eq!(graph.missing(), vec![b]);
+//!
+//! graph.insert_links(b, &[a]);
+//! assert!(graph.validate().is_ok());
+//! assert_eq!(graph.tips(), vec![c]);
+//! ```
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use thiserror::Error;
+
+use crate::{Hash, Operation};
+
+/// Graph of operations linked by their `previous` field.
+#[derive(Clone, Debug, Default)]
+pub struct OperationGraph {
+    links: HashMap<Hash, Vec<Hash>>,
+}
+
+impl OperationGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an operation and its previous links to the graph.
+    pub fn insert<E>(&mut self, operation: &Operation<E>) {
+        self.insert_links(operation.hash, &operation.header.previous);
+    }
+
+    /// Add an operation by its hash and the hashes it refers to.
+    ///
+    /// This is useful for stores or orderers which only keep the links and not the whole
+    /// operation.
+    pub fn insert_links(&mut self, hash: Hash, previous: &[Hash]) {
+        self.links.insert(hash, previous.to_vec());
+    }
+
+    /// Returns `true` if the operation is part of the graph.
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.links.contains_key(hash)
+    }
+
+    /// Returns the previous links of an operation in the graph.
+    pub fn previous(&self, hash: &Hash) -> Option<&[Hash]> {
+        self.links.get(hash).map(Vec::as_slice)
+    }
+
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Returns all operations which are referred to but not part of the graph yet, sorted by
+    /// hash.
+    ///
+    /// These operations need to be fetched before the graph is complete.
+    pub fn missing(&self) -> Vec<Hash> {
+        let missing: BTreeSet<Hash> = self
+            .links
+            .values()
+            .flatten()
+            .filter(|hash| !self.links.contains_key(hash))
+            .copied()
+            .collect();
+        missing.into_iter().collect()
+    }
+
+    /// Returns all operations which are not referred to by any other operation in the graph,
+    /// sorted by hash.
+    pub fn tips(&self) -> Vec<Hash> {
+        let referenced: HashSet<&Hash> = self.links.values().flatten().collect();
+        let tips: BTreeSet<Hash> = self
+            .links
+            .keys()
+            .filter(|hash| !referenced.contains(hash))
+            .copied()
+            .collect();
+        tips.into_iter().collect()
+    }
+
+    /// Checks that the previous links do not form any cycles.
+    ///
+    /// Operations which were not inserted yet are ignored, use [`OperationGraph::validate`] to
+    /// also require all of them to be known.
+    pub fn validate_acyclic(&self) -> Result<(), DagError> {
+        // Hashes of operations which are known to not be part of a cycle.
+        let mut visited: HashSet<Hash> = HashSet::new();
+
+        for start in self.links.keys() {
+            if visited.contains(start) {
+                continue;
+            }
+
+            // Depth-first search, remembering the operations on the current path.
+            let mut path: HashSet<Hash> = HashSet::from([*start]);
+            let mut stack: Vec<(Hash, usize)> = vec![(*start, 0)];
+
+            while let Some((hash, index)) = stack.last_mut() {
+                let hash = *hash;
+                let next = self
+                    .links
+                    .get(&hash)
+                    .and_then(|previous| previous.get(*index))
+                    .copied();
+                *index += 1;
+
+                match next {
+                    Some(next) if next == hash => return Err(DagError::SelfReference(next)),
+                    Some(next) if path.contains(&next) => return Err(DagError::Cycle(next)),
+                    Some(next) if visited.contains(&next) || !self.links.contains_key(&next) => {}
+                    Some(next) => {
+                        path.insert(next);
+                        stack.push((next, 0));
+                    }
+                    None => {
+                        path.remove(&hash);
+                        visited.insert(hash);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the graph is complete and the previous links do not form any cycles.
+    pub fn validate(&self) -> Result<(), DagError> {
+        self.validate_acyclic()?;
+
+        let missing = self.missing();
+        if !missing.is_empty() {
+            return Err(DagError::MissingOperations(missing));
+        }
+
+        Ok(())
+    }
+}
+
+/// Errors which can occur when validating the previous links of operations.
+#[derive(Clone, Debug, Error)]
+pub enum DagError {
+    #[error("operation {0} refers to itself")]
+    SelfReference(Hash),
+
+    #[error("previous links form a cycle containing operation {0}")]
+    Cycle(Hash),
+
+    #[error("{} operations referred to in previous links are unknown", .0.len())]
+    MissingOperations(Vec<Hash>),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Body, Hash, Header, Operation, PrivateKey};
+
+    use super::{DagError, OperationGraph};
+
+    fn create_operation(private_key: &PrivateKey, previous: Vec<Hash>) -> Operation<()> {
+        let body = Body::new(b"Hello, Sloth!");
+        let mut header = Header {
+            public_key: private_key.public_key(),
+            payload_size: body.size(),
+            payload_hash: Some(body.hash()),
+            previous,
+            ..Default::default()
+        };
+        header.sign(private_key);
+        Operation {
+            hash: header.hash(),
+            header,
+            body: Some(body),
+        }
+    }
+
+    #[test]
+    fn multi_author_tips() {
+        let panda = PrivateKey::new();
+        let penguin = PrivateKey::new();
+
+        // A <-- B1 <-- C
+        //   \-- B2
+        let operation_a = create_operation(&panda, vec![]);
+        let operation_b1 = create_operation(&panda, vec![operation_a.hash]);
+        let operation_b2 = create_operation(&penguin, vec![operation_a.hash]);
+        let operation_c = create_operation(&penguin, vec![operation_b1.hash]);
+
+        let mut graph = OperationGraph::new();
+        graph.insert(&operation_c);
+        graph.insert(&operation_b2);
+        graph.insert(&operation_a);
+        assert_eq!(graph.missing(), vec![operation_b1.hash]);
+        assert!(matches!(
+            graph.validate(),
+            Err(DagError::MissingOperations(_))
+        ));
+        assert!(graph.validate_acyclic().is_ok());
+
+        graph.insert(&operation_b1);
+        assert!(graph.validate().is_ok());
+        assert_eq!(graph.len(), 4);
+
+        let mut tips = vec![operation_b2.hash, operation_c.hash];
+        tips.sort();
+        assert_eq!(graph.tips(), tips);
+    }
+
+    #[test]
+    fn cycles() {
+        let a = Hash::new(b"a");
+        let b = Hash::new(b"b");
+        let c = Hash::new(b"c");
+
+        let mut graph = OperationGraph::new();
+        graph.insert_links(a, &[a]);
+        assert!(matches!(
+            graph.validate_acyclic(),
+            Err(DagError::SelfReference(hash)) if hash == a
+        ));
+
+        let mut graph = OperationGraph::new();
+        graph.insert_links(a, &[c]);
+        graph.insert_links(b, &[a]);
+        graph.insert_links(c, &[b]);
+        assert!(matches!(graph.validate(), Err(DagError::Cycle(_))));
+        assert!(graph.tips().is_empty());
+    }
+}
@@ -92,11 +92,62 @@
 //! assert_eq!(header.hash(), log_id.0);
 //! assert_eq!(extensions.expires.0, expiry.0);
 //! ```
+//!
+//! ## Registry
+//!
+//! Instead of defining their own extensions struct, applications can use [`ExtensionMap`] which
+//! holds any number of typed extensions, each identified by a numeric id. Values are encoded one
+//! by one and stored sorted by their id, so peers always agree on the bytes of the same
+//! extensions. An [`ExtensionRegistry`] declares which extensions an application knows about and
+//! checks received headers against them.
+//!
+//! ```
+//! use p2panda_core::{Header, PrivateKey};
+//! use p2panda_core::extensions::{ExtensionMap, ExtensionRegistry, RegisteredExtension};
+//! use serde::{Serialize, Deserialize};
+//!
+//! #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+//! struct DocumentId(String);
+//!
+//! impl RegisteredExtension for DocumentId {
+//!     const ID: u64 = 1;
+//! }
+//!
+//! let registry = ExtensionRegistry::new().register::<DocumentId>();
+//!
+//! let mut extensions = ExtensionMap::new();
+//! extensions.insert(&DocumentId("garden".into())).unwrap();
+//!
+//! let private_key = PrivateKey::new();
+//! let mut header = Header {
+//!     version: 1,
+//!     public_key: private_key.public_key(),
+//!     signature: None,
+//!     payload_size: 0,
+//!     payload_hash: None,
+//!     timestamp: 0,
+//!     seq_num: 0,
+//!     backlink: None,
+//!     previous: vec![],
+//!     extensions: Some(extensions),
+//! };
+//! header.sign(&private_key);
+//!
+//! registry.validate(&header).unwrap();
+//!
+//! let document_id: Option<DocumentId> = header.typed_extension().unwrap();
+//! assert_eq!(document_id, Some(DocumentId("garden".into())));
+//! ```
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use thiserror::Error;
 
 use crate::Header;
+use crate::cbor::{DecodeError, EncodeError, decode_cbor, encode_cbor};
 
 /// Trait definition of a single header extension type.
 pub trait Extension<T>: Extensions {
@@ -111,3 +162,289 @@ pub trait Extensions: Clone + Debug + for<'de> Deserialize<'de> + Serialize {}
 
 /// Blanket implementation of `Extensions` trait any type with the required bounds satisfied.
 impl<T> Extensions for T where T: Clone + Debug + for<'de> Deserialize<'de> + Serialize {}
+
+/// Typed extension which can be stored in an [`ExtensionMap`].
+pub trait RegisteredExtension: Serialize + DeserializeOwned {
+    /// Identifier of this extension, unique within an application.
+    const ID: u64;
+}
+
+/// Collection of typed extensions, identified by their numeric id.
+///
+/// Every value is encoded in CBOR on insertion and kept sorted by its id, which gives a
+/// deterministic encoding of the whole map.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ExtensionMap(BTreeMap<u64, ByteBuf>);
+
+impl ExtensionMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encodes and inserts an extension value, replacing any previous value with the same id.
+    pub fn insert<T: RegisteredExtension>(&mut self, value: &T) -> Result<(), EncodeError> {
+        let bytes = encode_cbor(value)?;
+        self.0.insert(T::ID, ByteBuf::from(bytes));
+        Ok(())
+    }
+
+    /// Decodes the value of an extension, returns `None` if it is not present.
+    pub fn get<T: RegisteredExtension>(&self) -> Result<Option<T>, DecodeError> {
+        self.0
+            .get(&T::ID)
+            .map(|bytes| decode_cbor(&bytes[..]))
+            .transpose()
+    }
+
+    /// Removes an extension, returns `true` if it was present.
+    pub fn remove<T: RegisteredExtension>(&mut self) -> bool {
+        self.0.remove(&T::ID).is_some()
+    }
+
+    pub fn contains<T: RegisteredExtension>(&self) -> bool {
+        self.0.contains_key(&T::ID)
+    }
+
+    /// Returns the ids of all contained extensions in ascending order.
+    pub fn ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.0.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<T> Extension<T> for ExtensionMap
+where
+    T: RegisteredExtension,
+{
+    fn extract(header: &Header<Self>) -> Option<T> {
+        header.extensions.as_ref()?.get().ok().flatten()
+    }
+}
+
+impl Header<ExtensionMap> {
+    /// Decodes a typed extension value from the header.
+    ///
+    /// In contrast to [`extension`](Header::extension) this returns an error if the value is
+    /// present but can not be decoded into the requested type.
+    pub fn typed_extension<T: RegisteredExtension>(&self) -> Result<Option<T>, DecodeError> {
+        match self.extensions.as_ref() {
+            Some(extensions) => extensions.get(),
+            None => Ok(None),
+        }
+    }
+}
+
+type CheckFn = fn(&[u8]) -> Result<(), DecodeError>;
+
+#[derive(Clone, Debug)]
+struct RegistryEntry {
+    name: &'static str,
+    check: CheckFn,
+}
+
+/// Declares the typed extensions an application knows about.
+///
+/// Headers of received operations can be checked against the registry to make sure all their
+/// extensions are known and decode into the declared types.
+#[derive(Clone, Debug, Default)]
+pub struct ExtensionRegistry {
+    entries: BTreeMap<u64, RegistryEntry>,
+}
+
+impl ExtensionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a typed extension.
+    ///
+    /// # Panics
+    ///
+    /// Panics if another type was registered with the same id already.
+    pub fn register<T: RegisteredExtension>(mut self) -> Self {
+        let name = std::any::type_name::<T>();
+        let entry = RegistryEntry {
+            name,
+            check: |bytes| decode_cbor::<T, _>(bytes).map(|_| ()),
+        };
+        if let Some(existing) = self.entries.insert(T::ID, entry) {
+            panic!(
+                "extension id {} registered twice for {} and {}",
+                T::ID,
+                existing.name,
+                name
+            );
+        }
+        self
+    }
+
+    /// Returns `true` if an extension with this id was declared.
+    pub fn is_registered(&self, id: u64) -> bool {
+        self.entries.contains_key(&id)
+    }
+
+    /// Checks that all extensions of the header are declared and decode into their types.
+    pub fn validate(&self, header: &Header<ExtensionMap>) -> Result<(), ExtensionError> {
+        let Some(extensions) = header.extensions.as_ref() else {
+            return Ok(());
+        };
+
+        for (id, bytes) in &extensions.0 {
+            let entry = self
+                .entries
+                .get(id)
+                .ok_or(ExtensionError::UnknownExtension(*id))?;
+            (entry.check)(bytes).map_err(|error| ExtensionError::InvalidExtension {
+                id: *id,
+                name: entry.name,
+                error,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Errors which can occur when checking extensions against a registry.
+#[derive(Debug, Error)]
+pub enum ExtensionError {
+    /// Header contains an extension which was not registered.
+    #[error("unknown extension with id {0}")]
+    UnknownExtension(u64),
+
+    /// Extension value can not be decoded into its registered type.
+    #[error("invalid extension {name} with id {id}: {error}")]
+    InvalidExtension {
+        id: u64,
+        name: &'static str,
+        error: DecodeError,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::cbor::encode_cbor;
+    use crate::{Header, PrivateKey};
+
+    use super::{ExtensionError, ExtensionMap, ExtensionRegistry, RegisteredExtension};
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct DocumentId(String);
+
+    impl RegisteredExtension for DocumentId {
+        const ID: u64 = 1;
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Expiry(u64);
+
+    impl RegisteredExtension for Expiry {
+        const ID: u64 = 2;
+    }
+
+    fn header(extensions: ExtensionMap) -> Header<ExtensionMap> {
+        let private_key = PrivateKey::new();
+        let mut header = Header {
+            version: 1,
+            public_key: private_key.public_key(),
+            signature: None,
+            payload_size: 0,
+            payload_hash: None,
+            timestamp: 0,
+            seq_num: 0,
+            backlink: None,
+            previous: vec![],
+            extensions: Some(extensions),
+        };
+        header.sign(&private_key);
+        header
+    }
+
+    #[test]
+    fn deterministic_encoding() {
+        let mut extensions_a = ExtensionMap::new();
+        extensions_a.insert(&Expiry(12)).unwrap();
+        extensions_a.insert(&DocumentId("garden".into())).unwrap();
+
+        let mut extensions_b = ExtensionMap::new();
+        extensions_b.insert(&DocumentId("garden".into())).unwrap();
+        extensions_b.insert(&Expiry(12)).unwrap();
+
+        assert_eq!(
+            encode_cbor(&extensions_a).unwrap(),
+            encode_cbor(&extensions_b).unwrap()
+        );
+        assert_eq!(extensions_a.ids().collect::<Vec<u64>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn typed_accessors() {
+        let mut extensions = ExtensionMap::new();
+        extensions.insert(&Expiry(12)).unwrap();
+        let header = header(extensions);
+
+        let expiry: Option<Expiry> = header.extension();
+        assert_eq!(expiry, Some(Expiry(12)));
+        assert_eq!(header.typed_extension::<DocumentId>().unwrap(), None);
+
+        // Bytes of the expiry can't be decoded into a different type with the same id.
+        #[derive(Debug, Serialize, Deserialize)]
+        struct WrongType(String);
+
+        impl RegisteredExtension for WrongType {
+            const ID: u64 = 2;
+        }
+
+        assert!(header.typed_extension::<WrongType>().is_err());
+        assert!(header.extension::<WrongType>().is_none());
+    }
+
+    #[test]
+    fn validate_against_registry() {
+        let registry = ExtensionRegistry::new().register::<DocumentId>();
+
+        let mut extensions = ExtensionMap::new();
+        extensions.insert(&DocumentId("garden".into())).unwrap();
+        assert!(registry.validate(&header(extensions.clone())).is_ok());
+
+        extensions.insert(&Expiry(12)).unwrap();
+        assert!(matches!(
+            registry.validate(&header(extensions)),
+            Err(ExtensionError::UnknownExtension(2))
+        ));
+
+        let registry = registry.register::<Expiry>();
+        let mut extensions = ExtensionMap::new();
+        extensions.insert(&DocumentId("garden".into())).unwrap();
+        extensions.0.insert(2, encode_cbor(&"soon").unwrap().into());
+        assert!(matches!(
+            registry.validate(&header(extensions)),
+            Err(ExtensionError::InvalidExtension { id: 2, .. })
+        ));
+    }
+
+    #[test]
+    #[should_panic]
+    fn duplicate_ids() {
+        #[derive(Serialize, Deserialize)]
+        struct Other;
+
+        impl RegisteredExtension for Other {
+            const ID: u64 = 1;
+        }
+
+        let _ = ExtensionRegistry::new()
+            .register::<DocumentId>()
+            .register::<Other>();
+    }
+}
@@ -20,13 +20,54 @@
 //!
 //! assert!(public_key.verify(bytes, &signature))
 //! ```
+//!
+//! Applications keeping private keys outside of the process, for example in an OS keystore,
+//! hardware token or remote key management service, implement `KeyProvider` to only hand out
+//! signatures instead of raw key bytes.
+//!
+//! ## Key derivation
+//!
+//! With the `derivation` feature enabled separate keys, for example per network or application,
+//! can be derived from one secret seed with `ExtendedPrivateKey`, following [SLIP-0010] for
+//! Ed25519. With the `mnemonic` feature enabled the seed can be backed up as a [BIP-39] mnemonic
+//! phrase instead of raw key files.
+//!
+//! ```
+//! # #[cfg(feature = "derivation")]
+//! # {
+//! use p2panda_core::identity::ExtendedPrivateKey;
+//!
+//! let seed = [7; 32];
+//! let root = ExtendedPrivateKey::from_seed(&seed).unwrap();
+//!
+//! // Derive a key for one application, the same path always leads to the same key.
+//! let app_key = root.derive_path(&[44, 0, 1]).private_key().clone();
+//! assert_eq!(
+//!     app_key.public_key(),
+//!     ExtendedPrivateKey::from_seed(&seed)
+//!         .unwrap()
+//!         .derive_path(&[44, 0, 1])
+//!         .public_key()
+//! );
+//! # }
+//! ```
+//!
+//! [SLIP-0010]: https://github.com/satoshilabs/slips/blob/master/slip-0010.md
+//! [BIP-39]: https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki
+use std::convert::Infallible;
 use std::fmt;
 use std::str::FromStr;
 
 #[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
 use ed25519_dalek::Signer;
+#[cfg(feature = "derivation")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "mnemonic")]
+use rand::RngCore;
 use rand::rngs::OsRng;
+#[cfg(feature = "derivation")]
+use sha2::Sha512;
 use thiserror::Error;
 
 /// The length of an Ed25519 `Signature`, in bytes.
@@ -136,6 +177,34 @@ impl<'a> Arbitrary<'a> for PrivateKey {
     }
 }
 
+/// Source of Ed25519 signatures for a key pair.
+///
+/// Implementations can keep the private key outside of the process, for example in an OS
+/// keystore, hardware token or remote key management service, and only perform sign operations
+/// when requested. `PrivateKey` is a key provider holding the key in memory.
+pub trait KeyProvider {
+    /// Error returned when a signature could not be created.
+    type Error;
+
+    /// Returns the public key of the provided key pair.
+    fn public_key(&self) -> PublicKey;
+
+    /// Sign the provided bytestring returning a digital signature.
+    fn sign(&self, bytes: &[u8]) -> Result<Signature, Self::Error>;
+}
+
+impl KeyProvider for PrivateKey {
+    type Error = Infallible;
+
+    fn public_key(&self) -> PublicKey {
+        PrivateKey::public_key(self)
+    }
+
+    fn sign(&self, bytes: &[u8]) -> Result<Signature, Self::Error> {
+        Ok(PrivateKey::sign(self, bytes))
+    }
+}
+
 /// Public Ed25519 key used for identifying peers and verifying signed data.
 #[derive(Default, Hash, PartialEq, Eq, Copy, Clone)]
 pub struct PublicKey(ed25519_dalek::VerifyingKey);
@@ -343,6 +412,117 @@ impl<'a> Arbitrary<'a> for Signature {
     }
 }
 
+/// Private key which can derive child keys, following SLIP-0010 for Ed25519.
+///
+/// Ed25519 only supports hardened derivation, child indices are hardened automatically and child
+/// public keys can not be derived from a parent public key.
+#[cfg(feature = "derivation")]
+#[derive(Clone)]
+pub struct ExtendedPrivateKey {
+    private_key: PrivateKey,
+    chain_code: [u8; 32],
+}
+
+#[cfg(feature = "derivation")]
+impl ExtendedPrivateKey {
+    /// Index offset of hardened child keys.
+    const HARDENED_OFFSET: u32 = 0x8000_0000;
+
+    /// Minimum and maximum length of a seed in bytes.
+    const SEED_LEN: std::ops::RangeInclusive<usize> = 16..=64;
+
+    /// Derive the master key from a secret seed of 16 to 64 bytes.
+    ///
+    /// Returns an error if the seed is shorter or longer.
+    pub fn from_seed(seed: &[u8]) -> Result<Self, IdentityError> {
+        if !Self::SEED_LEN.contains(&seed.len()) {
+            return Err(IdentityError::InvalidSeedLength(seed.len()));
+        }
+        Ok(Self::from_hmac(b"ed25519 seed", &[seed]))
+    }
+
+    /// Derive the master key from a BIP-39 mnemonic phrase and an optional passphrase.
+    #[cfg(feature = "mnemonic")]
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<Self, IdentityError> {
+        let mnemonic = bip39::Mnemonic::parse_normalized(phrase)
+            .map_err(|err| IdentityError::InvalidMnemonic(err.to_string()))?;
+        Self::from_seed(&mnemonic.to_seed_normalized(passphrase))
+    }
+
+    /// Derive the hardened child key with the given index.
+    pub fn derive(&self, index: u32) -> Self {
+        let index = index | Self::HARDENED_OFFSET;
+        Self::from_hmac(
+            &self.chain_code,
+            &[&[0], self.private_key.as_bytes(), &index.to_be_bytes()],
+        )
+    }
+
+    /// Derive the key at the given path of child indices, starting from this key.
+    pub fn derive_path(&self, path: &[u32]) -> Self {
+        path.iter()
+            .fold(self.clone(), |key, index| key.derive(*index))
+    }
+
+    pub fn private_key(&self) -> &PrivateKey {
+        &self.private_key
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.private_key.public_key()
+    }
+
+    pub fn chain_code(&self) -> &[u8; 32] {
+        &self.chain_code
+    }
+
+    fn from_hmac(key: &[u8], data: &[&[u8]]) -> Self {
+        let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC can take keys of any size");
+        for bytes in data {
+            mac.update(bytes);
+        }
+        let result = mac.finalize().into_bytes();
+
+        let mut private_key = [0; PRIVATE_KEY_LEN];
+        private_key.copy_from_slice(&result[..32]);
+        let mut chain_code = [0; 32];
+        chain_code.copy_from_slice(&result[32..]);
+
+        Self {
+            private_key: PrivateKey::from_bytes(&private_key),
+            chain_code,
+        }
+    }
+}
+
+#[cfg(feature = "derivation")]
+impl fmt::Debug for ExtendedPrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtendedPrivateKey")
+            .field("public_key", &self.public_key())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Generate a new random BIP-39 mnemonic phrase with the given number of words.
+///
+/// Valid word counts are 12, 15, 18, 21 and 24.
+#[cfg(feature = "mnemonic")]
+pub fn generate_mnemonic(word_count: usize) -> Result<String, IdentityError> {
+    if !(12..=24).contains(&word_count) || !word_count.is_multiple_of(3) {
+        return Err(IdentityError::InvalidMnemonic(format!(
+            "invalid word count {word_count}"
+        )));
+    }
+
+    // Every three words encode 32 bits of entropy.
+    let mut entropy = vec![0; word_count / 3 * 4];
+    OsRng.fill_bytes(&mut entropy);
+    let mnemonic = bip39::Mnemonic::from_entropy(&entropy)
+        .map_err(|err| IdentityError::InvalidMnemonic(err.to_string()))?;
+    Ok(mnemonic.to_string())
+}
+
 #[derive(Error, Debug)]
 pub enum IdentityError {
     /// Invalid number of bytes.
@@ -365,11 +545,42 @@ pub enum IdentityError {
     /// * Failure of a signature to satisfy the verification equation.
     #[error("invalid signature: {0}")]
     InvalidSignature(#[from] ed25519_dalek::SignatureError),
+
+    /// Seed for key derivation is shorter than 16 or longer than 64 bytes.
+    #[cfg(feature = "derivation")]
+    #[error("invalid seed length of {0} bytes, expected 16 to 64 bytes")]
+    InvalidSeedLength(usize),
+
+    /// Mnemonic phrase has an invalid word count, unknown words or a wrong checksum.
+    #[cfg(feature = "mnemonic")]
+    #[error("invalid mnemonic: {0}")]
+    InvalidMnemonic(String),
 }
 
 #[cfg(test)]
 mod tests {
-    use super::PrivateKey;
+    use super::{KeyProvider, PrivateKey, PublicKey, Signature};
+
+    /// Key provider which refuses to sign after it got locked, similar to a hardware token.
+    struct Token {
+        private_key: PrivateKey,
+        locked: bool,
+    }
+
+    impl KeyProvider for Token {
+        type Error = &'static str;
+
+        fn public_key(&self) -> PublicKey {
+            self.private_key.public_key()
+        }
+
+        fn sign(&self, bytes: &[u8]) -> Result<Signature, Self::Error> {
+            if self.locked {
+                return Err("token is locked");
+            }
+            Ok(self.private_key.sign(bytes))
+        }
+    }
 
     #[test]
     fn signing() {
@@ -386,4 +597,94 @@ mod tests {
         let public_key_2 = PrivateKey::new().public_key();
         assert!(!public_key_2.verify(bytes, &signature));
     }
+
+    #[test]
+    fn key_provider() {
+        let mut token = Token {
+            private_key: PrivateKey::new(),
+            locked: false,
+        };
+        let signature = KeyProvider::sign(&token, b"test").unwrap();
+        assert!(KeyProvider::public_key(&token).verify(b"test", &signature));
+
+        token.locked = true;
+        assert_eq!(KeyProvider::sign(&token, b"test"), Err("token is locked"));
+    }
+
+    #[cfg(feature = "derivation")]
+    #[test]
+    fn slip10_derivation() {
+        use super::ExtendedPrivateKey;
+
+        // Test vector 1 for Ed25519 from SLIP-0010.
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedPrivateKey::from_seed(&seed).unwrap();
+        assert_eq!(
+            master.private_key().to_hex(),
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"
+        );
+        assert_eq!(
+            hex::encode(master.chain_code()),
+            "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb"
+        );
+        assert_eq!(
+            master.public_key().to_hex(),
+            "a4b2856bfec510abab89753fac1ac0e1112364e7d250545963f135f2a33188ed"
+        );
+
+        let child = master.derive(0);
+        assert_eq!(
+            child.private_key().to_hex(),
+            "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3"
+        );
+        assert_eq!(
+            hex::encode(child.chain_code()),
+            "8b59aa11380b624e81507a27fedda59fea6d0b779a778918a2fd3590e16e9c69"
+        );
+        assert_eq!(
+            child.public_key().to_hex(),
+            "8c8a13df77a28f3445213a0f432fde644acaa215fc72dcdf300d5efaa85d350c"
+        );
+
+        // Already hardened indices lead to the same key.
+        assert_eq!(master.derive(0x8000_0000).public_key(), child.public_key());
+        assert_eq!(master.derive_path(&[0]).public_key(), child.public_key());
+        assert_ne!(master.derive(1).public_key(), child.public_key());
+    }
+
+    #[cfg(feature = "derivation")]
+    #[test]
+    fn reject_invalid_seed_length() {
+        use super::{ExtendedPrivateKey, IdentityError};
+
+        assert!(matches!(
+            ExtendedPrivateKey::from_seed(&[1; 15]),
+            Err(IdentityError::InvalidSeedLength(15))
+        ));
+        assert!(matches!(
+            ExtendedPrivateKey::from_seed(&[1; 65]),
+            Err(IdentityError::InvalidSeedLength(65))
+        ));
+        assert!(ExtendedPrivateKey::from_seed(&[1; 16]).is_ok());
+        assert!(ExtendedPrivateKey::from_seed(&[1; 64]).is_ok());
+    }
+
+    #[cfg(feature = "mnemonic")]
+    #[test]
+    fn mnemonic_backup() {
+        use super::{ExtendedPrivateKey, generate_mnemonic};
+
+        let phrase = generate_mnemonic(24).unwrap();
+        assert_eq!(phrase.split_whitespace().count(), 24);
+
+        let key = ExtendedPrivateKey::from_mnemonic(&phrase, "").unwrap();
+        let restored = ExtendedPrivateKey::from_mnemonic(&phrase, "").unwrap();
+        assert_eq!(key.public_key(), restored.public_key());
+
+        let with_passphrase = ExtendedPrivateKey::from_mnemonic(&phrase, "panda").unwrap();
+        assert_ne!(key.public_key(), with_passphrase.public_key());
+
+        assert!(generate_mnemonic(13).is_err());
+        assert!(ExtendedPrivateKey::from_mnemonic("panda panda panda", "").is_err());
+    }
 }
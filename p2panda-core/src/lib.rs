@@ -77,21 +77,30 @@
 //! // Sign the header with the author's private key. From now on it's ready to be sent!
 //! header.sign(&private_key);
 //! ```
+pub mod capability;
 pub mod cbor;
+pub mod dag;
 pub mod extensions;
 pub mod hash;
 pub mod identity;
 pub mod operation;
+pub mod proof;
 #[cfg(feature = "prune")]
 pub mod prune;
 mod serde;
+#[cfg(feature = "tombstone")]
+pub mod tombstone;
 
-pub use extensions::{Extension, Extensions};
+pub use extensions::{
+    Extension, ExtensionError, ExtensionMap, ExtensionRegistry, Extensions, RegisteredExtension,
+};
 pub use hash::{Hash, HashError};
-pub use identity::{IdentityError, PrivateKey, PublicKey, Signature};
+pub use identity::{IdentityError, KeyProvider, PrivateKey, PublicKey, Signature};
 pub use operation::{
-    Body, Header, Operation, OperationError, RawOperation, validate_backlink, validate_header,
-    validate_operation,
+    Body, BodyHasher, Header, Operation, OperationError, OperationLimits, RawOperation, SignError,
+    validate, validate_backlink, validate_header, validate_limits, validate_operation,
 };
 #[cfg(feature = "prune")]
 pub use prune::PruneFlag;
+#[cfg(feature = "tombstone")]
+pub use tombstone::TombstoneFlag;
@@ -97,11 +97,42 @@
 //! let prune_flag: PruneFlag = header.extension().unwrap();
 //! assert!(prune_flag.is_set())
 //! ```
+//!
+//! ### Sign large payloads
+//!
+//! Headers only contain the hash and size of the payload. For very large payloads, for example
+//! files of multiple gigabytes, these can be calculated incrementally with a [`BodyHasher`]
+//! without holding the whole body in memory.
+//!
+//! ```
+//! use std::io::{self, Read};
+//!
+//! use p2panda_core::{BodyHasher, Header, PrivateKey};
+//!
+//! let private_key = PrivateKey::new();
+//!
+//! // Any reader can be used here, for example a file
+//! let mut reader = io::repeat(7).take(1024 * 1024);
+//! let mut hasher = BodyHasher::new();
+//! io::copy(&mut reader, &mut hasher).unwrap();
+//!
+//! let mut header = Header::<()> {
+//!     public_key: private_key.public_key(),
+//!     timestamp: 1733170247,
+//!     ..Default::default()
+//! };
+//! hasher.apply(&mut header);
+//! header.sign(&private_key);
+//!
+//! assert_eq!(header.payload_size, 1024 * 1024);
+//! ```
+use std::io;
+
 use thiserror::Error;
 
-use crate::cbor::{DecodeError, decode_cbor, encode_cbor};
+use crate::cbor::{DecodeError, EncodeError, decode_cbor, encode_cbor, encode_cbor_canonical};
 use crate::hash::Hash;
-use crate::identity::{PrivateKey, PublicKey, Signature};
+use crate::identity::{KeyProvider, PrivateKey, PublicKey, Signature};
 use crate::{Extension, Extensions};
 
 /// Encoded bytes of an operation header and optional body.
@@ -236,6 +267,22 @@ where
             .expect("CBOR encoder failed due to an critical IO error")
     }
 
+    /// Header encoded to bytes in canonical CBOR format.
+    ///
+    /// Headers are signed and hashed over the bytes of [`to_bytes`](Header::to_bytes), which are
+    /// already canonical as long as the extensions encode canonically, for example when using
+    /// `ExtensionMap` or structs with fields declared in sorted order. Check with
+    /// [`is_canonical`](Header::is_canonical) before relying on them matching.
+    pub fn to_canonical_bytes(&self) -> Result<Vec<u8>, EncodeError> {
+        encode_cbor_canonical(self)
+    }
+
+    /// Returns `true` if the regular encoding of this header is canonical.
+    pub fn is_canonical(&self) -> bool {
+        self.to_canonical_bytes()
+            .is_ok_and(|canonical| canonical == self.to_bytes())
+    }
+
     /// Add a signature to the header using the provided `PrivateKey`.
     ///
     /// This method signs the byte representation of a header with any existing signature removed
@@ -248,6 +295,24 @@ where
         self.signature = Some(private_key.sign(&bytes));
     }
 
+    /// Add a signature to the header requested from the provided `KeyProvider`.
+    ///
+    /// Like `sign` but without access to the raw private key, for example when it's kept in a
+    /// hardware token. The header stays unsigned if the provider holds a different key than the
+    /// header's public key or failed creating a signature.
+    pub fn sign_with<K: KeyProvider>(&mut self, provider: &K) -> Result<(), SignError<K::Error>> {
+        // Make sure the signature is not already set before we encode
+        self.signature = None;
+
+        if provider.public_key() != self.public_key {
+            return Err(SignError::PublicKeyMismatch);
+        }
+
+        let bytes = self.to_bytes();
+        self.signature = Some(provider.sign(&bytes).map_err(SignError::Provider)?);
+        Ok(())
+    }
+
     /// Verify that the signature contained in this `Header` was generated by the claimed
     /// public key.
     pub fn verify(&self) -> bool {
@@ -352,6 +417,78 @@ impl From<Vec<u8>> for Body {
     }
 }
 
+/// Incremental hasher calculating the payload hash and size of a body.
+///
+/// Bytes can be added in chunks, for example while reading a file or receiving a payload from the
+/// network. This allows signing and verifying operations with very large payloads without
+/// buffering them in memory.
+#[derive(Clone, Debug, Default)]
+pub struct BodyHasher {
+    hasher: blake3::Hasher,
+    size: u64,
+}
+
+impl BodyHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next chunk of body bytes.
+    pub fn update(&mut self, bytes: &[u8]) -> &mut Self {
+        self.hasher.update(bytes);
+        self.size += bytes.len() as u64;
+        self
+    }
+
+    /// BLAKE3 hash of all body bytes added so far.
+    pub fn hash(&self) -> Hash {
+        self.hasher.finalize().into()
+    }
+
+    /// Size of all body bytes added so far.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn payload_hash(&self) -> Option<Hash> {
+        if self.size == 0 {
+            None
+        } else {
+            Some(self.hash())
+        }
+    }
+
+    /// Set the payload hash and size of the header to the body bytes added so far.
+    ///
+    /// This needs to be called before the header gets signed. An empty body results in no
+    /// payload hash, as required by header validation.
+    pub fn apply<E>(&self, header: &mut Header<E>) {
+        header.payload_size = self.size;
+        header.payload_hash = self.payload_hash();
+    }
+
+    /// Checks that the body bytes added so far match the payload hash and size claimed in the
+    /// header.
+    pub fn verify<E>(&self, header: &Header<E>) -> Result<(), OperationError> {
+        if header.payload_hash != self.payload_hash() || header.payload_size != self.size {
+            return Err(OperationError::PayloadMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+impl io::Write for BodyHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Error)]
 pub enum OperationError {
     #[error("operation version {0} is not supported, needs to be <= {1}")]
@@ -386,6 +523,171 @@ pub enum OperationError {
 
     #[error("given backlink did not match previous operation")]
     BacklinkMismatch,
+
+    #[error("header size of {0} bytes exceeds limit of {1} bytes")]
+    HeaderTooLarge(usize, usize),
+
+    #[error("payload size of {0} bytes exceeds limit of {1} bytes")]
+    PayloadTooLarge(u64, u64),
+
+    #[error("{0} previous links exceed limit of {1}")]
+    TooManyPreviousLinks(usize, usize),
+
+    #[error("extensions size of {0} bytes exceeds limit of {1} bytes")]
+    ExtensionsTooLarge(usize, usize),
+
+    #[error("timestamp {0} is outside of accepted range {1}..={2}")]
+    TimestampOutOfRange(u64, u64, u64),
+
+    #[error("tombstone operations can not contain a payload")]
+    TombstoneWithPayload,
+
+    #[error("body is required for operations which are not tombstoned")]
+    MissingBody,
+}
+
+/// Error returned when signing a header with a [`KeyProvider`].
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum SignError<E> {
+    #[error("key provider holds a different public key than claimed in header")]
+    PublicKeyMismatch,
+
+    #[error("key provider failed creating signature: {0}")]
+    Provider(E),
+}
+
+/// Configurable limits for operations, checked by [`validate`].
+///
+/// All limits are disabled by default. Applications usually want to set them to protect
+/// themselves against very large or otherwise malicious operations, the same limits should be
+/// used wherever operations are received, for example during sync, via gossip and when writing
+/// them into a store.
+///
+/// ```
+/// use p2panda_core::OperationLimits;
+///
+/// let limits = OperationLimits::new()
+///     .max_payload_size(1024 * 1024)
+///     .max_previous_links(32)
+///     .max_extensions_size(512)
+///     .timestamp_range(1733170247, 1733170247 + 60);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OperationLimits {
+    max_header_size: Option<usize>,
+    max_payload_size: Option<u64>,
+    max_previous_links: Option<usize>,
+    max_extensions_size: Option<usize>,
+    timestamp_range: Option<(u64, u64)>,
+}
+
+impl OperationLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum size of the encoded header in bytes.
+    pub fn max_header_size(mut self, size: usize) -> Self {
+        self.max_header_size = Some(size);
+        self
+    }
+
+    /// Maximum payload size in bytes, as claimed in the header.
+    pub fn max_payload_size(mut self, size: u64) -> Self {
+        self.max_payload_size = Some(size);
+        self
+    }
+
+    /// Maximum number of `previous` links.
+    pub fn max_previous_links(mut self, count: usize) -> Self {
+        self.max_previous_links = Some(count);
+        self
+    }
+
+    /// Maximum size of the encoded extensions in bytes.
+    pub fn max_extensions_size(mut self, size: usize) -> Self {
+        self.max_extensions_size = Some(size);
+        self
+    }
+
+    /// Accepted range of timestamps, inclusive.
+    ///
+    /// The range is usually derived from the current time, for example to reject operations
+    /// claiming to be from the future.
+    pub fn timestamp_range(mut self, min: u64, max: u64) -> Self {
+        self.timestamp_range = Some((min, max));
+        self
+    }
+}
+
+/// Validate an operation given by its header and body (when provided) against the configured
+/// limits.
+///
+/// All limits are checked first, before the more expensive validation identical to
+/// [`validate_operation`] is performed.
+pub fn validate<E>(
+    header: &Header<E>,
+    body: Option<&Body>,
+    limits: &OperationLimits,
+) -> Result<(), OperationError>
+where
+    E: Extensions,
+{
+    validate_limits(header, limits)?;
+    validate_header(header)?;
+    validate_payload(header, body)
+}
+
+/// Validate that a header stays within the configured limits.
+pub fn validate_limits<E>(
+    header: &Header<E>,
+    limits: &OperationLimits,
+) -> Result<(), OperationError>
+where
+    E: Extensions,
+{
+    if let Some(max) = limits.max_payload_size
+        && header.payload_size > max
+    {
+        return Err(OperationError::PayloadTooLarge(header.payload_size, max));
+    }
+
+    if let Some(max) = limits.max_previous_links
+        && header.previous.len() > max
+    {
+        return Err(OperationError::TooManyPreviousLinks(
+            header.previous.len(),
+            max,
+        ));
+    }
+
+    if let Some((min, max)) = limits.timestamp_range
+        && !(min..=max).contains(&header.timestamp)
+    {
+        return Err(OperationError::TimestampOutOfRange(
+            header.timestamp,
+            min,
+            max,
+        ));
+    }
+
+    if let (Some(max), Some(extensions)) = (limits.max_extensions_size, &header.extensions) {
+        let size = encode_cbor(extensions)
+            .expect("CBOR encoder failed due to an critical IO error")
+            .len();
+        if size > max {
+            return Err(OperationError::ExtensionsTooLarge(size, max));
+        }
+    }
+
+    if let Some(max) = limits.max_header_size {
+        let size = header.to_bytes().len();
+        if size > max {
+            return Err(OperationError::HeaderTooLarge(size, max));
+        }
+    }
+
+    Ok(())
 }
 
 /// Validate the header and body (when provided) of a single operation. All basic header
@@ -403,23 +705,26 @@ where
     E: Extensions,
 {
     validate_header(&operation.header)?;
+    validate_payload(&operation.header, operation.body.as_ref())
+}
 
-    let claimed_payload_size = operation.header.payload_size;
+/// Check the body bytes hash and size (when provided) against those claimed in the header.
+fn validate_payload<E>(header: &Header<E>, body: Option<&Body>) -> Result<(), OperationError> {
+    let claimed_payload_size = header.payload_size;
     let claimed_payload_hash: Option<Hash> = match claimed_payload_size {
         0 => None,
         _ => {
-            let hash = operation
-                .header
+            let hash = header
                 .payload_hash
                 .ok_or(OperationError::MissingPayloadHash)?;
             Some(hash)
         }
     };
 
-    if let Some(body) = &operation.body {
-        if claimed_payload_hash != Some(body.hash()) || claimed_payload_size != body.size() {
-            return Err(OperationError::PayloadMismatch);
-        }
+    if let Some(body) = body
+        && (claimed_payload_hash != Some(body.hash()) || claimed_payload_size != body.size())
+    {
+        return Err(OperationError::PayloadMismatch);
     }
 
     Ok(())
@@ -559,6 +864,34 @@ mod tests {
         assert!(validate_operation(&operation).is_ok());
     }
 
+    #[test]
+    fn sign_with_key_provider() {
+        let private_key = PrivateKey::new();
+        let mut header = Header::<()> {
+            version: 1,
+            public_key: private_key.public_key(),
+            signature: None,
+            payload_size: 0,
+            payload_hash: None,
+            timestamp: 0,
+            seq_num: 0,
+            backlink: None,
+            previous: vec![],
+            extensions: None,
+        };
+
+        header.sign_with(&private_key).unwrap();
+        assert!(header.verify());
+
+        // Providers holding another key than the one claimed in the header are rejected.
+        let other_key = PrivateKey::new();
+        assert_eq!(
+            header.sign_with(&other_key),
+            Err(SignError::PublicKeyMismatch)
+        );
+        assert!(header.signature.is_none());
+    }
+
     #[test]
     fn valid_backlink_header() {
         let private_key = PrivateKey::new();
@@ -745,4 +1078,111 @@ mod tests {
         assert_eq!(header.hash(), log_id.0);
         assert_eq!(extensions.expires.0, expiry.0);
     }
+
+    #[test]
+    fn operation_limits() {
+        let private_key = PrivateKey::new();
+        let body = Body::new(&[0; 128]);
+        let mut header = Header {
+            version: 1,
+            public_key: private_key.public_key(),
+            signature: None,
+            payload_size: body.size(),
+            payload_hash: Some(body.hash()),
+            timestamp: 1000,
+            seq_num: 0,
+            backlink: None,
+            previous: vec![Hash::new(b"a"), Hash::new(b"b")],
+            extensions: Some(true),
+        };
+        header.sign(&private_key);
+
+        assert!(validate(&header, Some(&body), &OperationLimits::new()).is_ok());
+
+        let limits = OperationLimits::new()
+            .max_payload_size(128)
+            .max_previous_links(2)
+            .max_extensions_size(1)
+            .timestamp_range(900, 1100)
+            .max_header_size(512);
+        assert!(validate(&header, Some(&body), &limits).is_ok());
+
+        assert!(matches!(
+            validate(&header, Some(&body), &limits.clone().max_payload_size(127)),
+            Err(OperationError::PayloadTooLarge(128, 127))
+        ));
+        assert!(matches!(
+            validate(&header, None, &limits.clone().max_previous_links(1)),
+            Err(OperationError::TooManyPreviousLinks(2, 1))
+        ));
+        assert!(matches!(
+            validate(&header, None, &limits.clone().max_extensions_size(0)),
+            Err(OperationError::ExtensionsTooLarge(1, 0))
+        ));
+        assert!(matches!(
+            validate(&header, None, &limits.clone().timestamp_range(0, 999)),
+            Err(OperationError::TimestampOutOfRange(1000, 0, 999))
+        ));
+        assert!(matches!(
+            validate(&header, None, &limits.clone().max_header_size(64)),
+            Err(OperationError::HeaderTooLarge(_, 64))
+        ));
+
+        // Regular validation still happens after the limits were checked.
+        assert!(matches!(
+            validate(&header, Some(&Body::new(&[1; 128])), &limits),
+            Err(OperationError::PayloadMismatch)
+        ));
+    }
+
+    #[test]
+    fn body_hasher() {
+        let private_key = PrivateKey::new();
+        let bytes = vec![7; 100_000];
+
+        let mut hasher = BodyHasher::new();
+        for chunk in bytes.chunks(4096) {
+            hasher.update(chunk);
+        }
+
+        let mut header = Header::<()> {
+            public_key: private_key.public_key(),
+            ..Default::default()
+        };
+        hasher.apply(&mut header);
+        header.sign(&private_key);
+
+        // Hashing in chunks gives the same result as hashing the whole body.
+        let body = Body::new(&bytes);
+        assert_eq!(header.payload_hash, Some(body.hash()));
+        assert_eq!(header.payload_size, body.size());
+        assert!(
+            validate_operation(&Operation {
+                hash: header.hash(),
+                header: header.clone(),
+                body: Some(body),
+            })
+            .is_ok()
+        );
+
+        assert!(hasher.verify(&header).is_ok());
+        hasher.update(&[7]);
+        assert!(matches!(
+            hasher.verify(&header),
+            Err(OperationError::PayloadMismatch)
+        ));
+
+        // An empty body results in a header without payload.
+        let hasher = BodyHasher::new();
+        let mut header = Header::<()> {
+            public_key: private_key.public_key(),
+            ..Default::default()
+        };
+        hasher.apply(&mut header);
+        header.sign(&private_key);
+        assert_eq!(header.payload_hash, None);
+        assert_eq!(header.payload_size, 0);
+        assert!(validate_header(&header).is_ok());
+        assert!(hasher.verify(&header).is_ok());
+    }
 }
@@ -0,0 +1,265 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Compact proofs that an operation is part of an author's log.
+//!
+//! Light clients often don't sync whole logs but query single operations from other nodes, for
+//! example from always-online "shelf" nodes they don't trust. A [`LogProof`] allows them to check
+//! that an operation returned by such a node is really part of the author's log.
+//!
+//! The proof consists of the encoded headers of the log, starting with the operation and ending
+//! with the log head. Every header is linked to the one before it by its backlink, the head is
+//! signed by the author. Verifying the signature of the head and the chain of hashes down to the
+//! operation proves that the author appended the operation to its log, without the need to trust
+//! the node or to download the payloads.
+//!
+//! ## Example
+//!
+//! ```
+//! use p2panda_core::proof::LogProof;
+//! use p2panda_core::{Hash, Header, PrivateKey};
+//!
+//! let private_key = PrivateKey::new();
+//!
+//! let mut headers: Vec<Vec<u8>> = Vec::new();
+//! let mut backlink = None;
+//! for seq_num in 0..3 {
+//!     let mut header = Header::<()> {
+//!         public_key: private_key.public_key(),
+//!         seq_num,
+//!         backlink,
+//!         ..Default::default()
+//!     };
+//!     header.sign(&private_key);
+//!     backlink = Some(header.hash());
+//!     headers.push(header.to_bytes());
+//! }
+//!
+//! // Prove that the second operation is part of the log
+//! let hash = Hash::new(&headers[1]);
+//! let proof = LogProof::new(headers[1..].to_vec());
+//!
+//! let head = proof.verify::<()>(&hash).unwrap();
+//! assert_eq!(head.public_key, private_key.public_key());
+//! assert_eq!(head.seq_num, 2);
+//! ```
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use thiserror::Error;
+
+use crate::cbor::{DecodeError, EncodeError, decode_cbor, encode_cbor};
+use crate::{Extensions, Hash, Header, OperationError, PublicKey, validate_header};
+
+/// Proof that an operation is part of an author's log up to a signed log head.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LogProof {
+    headers: Vec<ByteBuf>,
+}
+
+impl LogProof {
+    /// Create a proof from the encoded headers of a log segment.
+    ///
+    /// The headers start with the operation to prove and end with the log head, without any gaps
+    /// in between.
+    pub fn new(headers: Vec<Vec<u8>>) -> Self {
+        Self {
+            headers: headers.into_iter().map(ByteBuf::from).collect(),
+        }
+    }
+
+    /// Returns the number of headers in the proof.
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Verify that the operation with the given hash is part of the log and return the log head
+    /// it is included in.
+    ///
+    /// Applications should check that the head is authored by the expected public key and is
+    /// recent enough, for example by comparing it with heads received from other peers.
+    pub fn verify<E>(&self, hash: &Hash) -> Result<LogProofHead, ProofError>
+    where
+        E: Extensions,
+    {
+        let mut past: Option<(Hash, Header<E>)> = None;
+
+        for header_bytes in &self.headers {
+            let header: Header<E> = decode_cbor(&header_bytes[..])?;
+            let header_hash = Hash::new(header_bytes);
+
+            match &past {
+                None => {
+                    if &header_hash != hash {
+                        return Err(ProofError::OperationMismatch);
+                    }
+                }
+                Some((past_hash, past_header)) => {
+                    if past_header.public_key != header.public_key {
+                        return Err(ProofError::InvalidChain(OperationError::TooManyAuthors));
+                    }
+
+                    if past_header.seq_num + 1 != header.seq_num {
+                        return Err(ProofError::InvalidChain(
+                            OperationError::SeqNumNonIncremental(
+                                past_header.seq_num + 1,
+                                header.seq_num,
+                            ),
+                        ));
+                    }
+
+                    if header.backlink != Some(*past_hash) {
+                        return Err(ProofError::InvalidChain(OperationError::BacklinkMismatch));
+                    }
+                }
+            }
+
+            past = Some((header_hash, header));
+        }
+
+        let Some((head_hash, head)) = past else {
+            return Err(ProofError::Empty);
+        };
+
+        // The signature of the head covers all hashes down to the operation.
+        validate_header(&head).map_err(ProofError::InvalidHead)?;
+
+        Ok(LogProofHead {
+            public_key: head.public_key,
+            seq_num: head.seq_num,
+            hash: head_hash,
+            timestamp: head.timestamp,
+        })
+    }
+
+    /// Encode the proof to bytes in CBOR format.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodeError> {
+        encode_cbor(self)
+    }
+
+    /// Decode a proof from bytes in CBOR format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        decode_cbor(bytes)
+    }
+}
+
+/// Signed log head an operation was proven to be included in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogProofHead {
+    pub public_key: PublicKey,
+    pub seq_num: u64,
+    pub hash: Hash,
+    pub timestamp: u64,
+}
+
+/// Errors which can occur when verifying a log proof.
+#[derive(Debug, Error)]
+pub enum ProofError {
+    #[error("proof does not contain any headers")]
+    Empty,
+
+    #[error("could not decode header in proof: {0}")]
+    Decode(#[from] DecodeError),
+
+    #[error("proof does not start with the given operation")]
+    OperationMismatch,
+
+    #[error("headers in proof are not linked: {0}")]
+    InvalidChain(OperationError),
+
+    #[error("invalid log head: {0}")]
+    InvalidHead(OperationError),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Hash, Header, OperationError, PrivateKey};
+
+    use super::{LogProof, ProofError};
+
+    fn create_log(private_key: &PrivateKey, len: u64) -> Vec<Header<()>> {
+        let mut headers: Vec<Header<()>> = Vec::new();
+        for seq_num in 0..len {
+            let mut header = Header::<()> {
+                public_key: private_key.public_key(),
+                seq_num,
+                timestamp: seq_num,
+                backlink: headers.last().map(|header| header.hash()),
+                ..Default::default()
+            };
+            header.sign(private_key);
+            headers.push(header);
+        }
+        headers
+    }
+
+    fn create_proof(headers: &[Header<()>]) -> LogProof {
+        LogProof::new(headers.iter().map(|header| header.to_bytes()).collect())
+    }
+
+    #[test]
+    fn verify_inclusion() {
+        let private_key = PrivateKey::new();
+        let headers = create_log(&private_key, 5);
+
+        let proof = LogProof::from_bytes(&create_proof(&headers[2..]).to_bytes().unwrap()).unwrap();
+        assert_eq!(proof.len(), 3);
+
+        let head = proof.verify::<()>(&headers[2].hash()).unwrap();
+        assert_eq!(head.public_key, private_key.public_key());
+        assert_eq!(head.seq_num, 4);
+        assert_eq!(head.hash, headers[4].hash());
+
+        // The head itself is proven by a proof with only one header
+        let head = create_proof(&headers[4..])
+            .verify::<()>(&headers[4].hash())
+            .unwrap();
+        assert_eq!(head.seq_num, 4);
+    }
+
+    #[test]
+    fn invalid_proofs() {
+        let private_key = PrivateKey::new();
+        let headers = create_log(&private_key, 5);
+
+        assert!(matches!(
+            LogProof::new(vec![]).verify::<()>(&headers[0].hash()),
+            Err(ProofError::Empty)
+        ));
+
+        assert!(matches!(
+            create_proof(&headers[1..]).verify::<()>(&headers[0].hash()),
+            Err(ProofError::OperationMismatch)
+        ));
+
+        // Gap in the log segment
+        let mut segment = headers[1..].to_vec();
+        segment.remove(1);
+        assert!(matches!(
+            create_proof(&segment).verify::<()>(&headers[1].hash()),
+            Err(ProofError::InvalidChain(
+                OperationError::SeqNumNonIncremental(2, 3)
+            ))
+        ));
+
+        // Header of another log
+        let mut segment = headers[1..3].to_vec();
+        segment.push(create_log(&PrivateKey::new(), 4).remove(3));
+        assert!(matches!(
+            create_proof(&segment).verify::<()>(&headers[1].hash()),
+            Err(ProofError::InvalidChain(OperationError::TooManyAuthors))
+        ));
+
+        // Head with an invalid signature
+        let mut segment = headers[3..].to_vec();
+        segment[1].timestamp = 1000;
+        segment[1].backlink = Some(Hash::new(segment[0].to_bytes()));
+        assert!(matches!(
+            create_proof(&segment).verify::<()>(&headers[3].hash()),
+            Err(ProofError::InvalidHead(OperationError::SignatureMismatch))
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! [`Extension`](crate::Extension) representing points in a log where the payloads of all
+//! preceding operations were deleted.
+//!
+//! `TombstoneFlag` is a header extension similar to [`PruneFlag`](crate::PruneFlag). While
+//! pruning removes whole operations, tombstones only remove their payloads: the headers stay in
+//! the log and keep the chain of backlinks intact. This is useful when applications need to
+//! delete user data but still want to verify the integrity of a log, or keep the operations'
+//! metadata for ordering.
+//!
+//! Tombstone operations don't carry a payload themselves. Operations before a tombstone are
+//! transferred and stored without their bodies, all other operations are expected to arrive with
+//! the body they claim in their header.
+use std::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Body, Extensions, Header, OperationError};
+
+/// Flag indicating that the payloads of all preceding operations in a log can be deleted.
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TombstoneFlag(bool);
+
+impl TombstoneFlag {
+    pub fn new(flag: bool) -> Self {
+        Self(flag)
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0
+    }
+
+    pub fn is_not_set(&self) -> bool {
+        !self.0
+    }
+}
+
+impl From<bool> for TombstoneFlag {
+    fn from(value: bool) -> Self {
+        Self(value)
+    }
+}
+
+impl Deref for TombstoneFlag {
+    type Target = bool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Checks that a tombstone operation does not claim a payload.
+pub fn validate_tombstone<E>(header: &Header<E>, tombstone_flag: bool) -> Result<(), OperationError>
+where
+    E: Extensions,
+{
+    if tombstone_flag && (header.payload_size > 0 || header.payload_hash.is_some()) {
+        return Err(OperationError::TombstoneWithPayload);
+    }
+
+    Ok(())
+}
+
+/// Returns `true` if the payload of the operation was deleted by a later tombstone in the same
+/// log.
+///
+/// `tombstone_seq_num` is the sequence number of the latest known tombstone in the log.
+pub fn is_tombstoned<E>(header: &Header<E>, tombstone_seq_num: Option<u64>) -> bool {
+    tombstone_seq_num.is_some_and(|seq_num| header.seq_num < seq_num)
+}
+
+/// Checks that operations which claim a payload come with a body, unless they were tombstoned.
+///
+/// ```text
+/// Log of Author A with six Operations:
+///
+/// [ 0 ] <-- body can be missing
+/// [ 1 ] <-- body can be missing
+/// [ 2 ] <-- body can be missing
+/// [ 3 ] <-- tombstone flag = true, no payload
+/// [ 4 ] <-- body required
+/// [ 5 ] <-- body required
+/// ...
+/// ```
+///
+/// Use this method in addition to [`validate_operation`](crate::validate_operation), which
+/// checks given bodies against the claimed payload hash and size, when payloads should be
+/// required for all operations which were not tombstoned.
+pub fn validate_tombstoned_body<E>(
+    header: &Header<E>,
+    body: Option<&Body>,
+    tombstone_seq_num: Option<u64>,
+) -> Result<(), OperationError>
+where
+    E: Extensions,
+{
+    if body.is_none() && header.payload_size > 0 && !is_tombstoned(header, tombstone_seq_num) {
+        return Err(OperationError::MissingBody);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cbor::encode_cbor;
+    use crate::{Body, Header, OperationError, PrivateKey};
+
+    use super::{TombstoneFlag, validate_tombstone, validate_tombstoned_body};
+
+    #[test]
+    fn tombstone_without_payload() {
+        let private_key = PrivateKey::new();
+        let body = Body::new(b"Hello, Sloth!");
+        let mut header = Header::<()> {
+            public_key: private_key.public_key(),
+            seq_num: 3,
+            payload_size: body.size(),
+            payload_hash: Some(body.hash()),
+            ..Default::default()
+        };
+        header.sign(&private_key);
+
+        assert!(validate_tombstone(&header, false).is_ok());
+        assert!(matches!(
+            validate_tombstone(&header, true),
+            Err(OperationError::TombstoneWithPayload)
+        ));
+
+        header.payload_size = 0;
+        header.payload_hash = None;
+        assert!(validate_tombstone(&header, true).is_ok());
+    }
+
+    #[test]
+    fn missing_bodies() {
+        let body = Body::new(b"Hello, Sloth!");
+        let header = Header::<()> {
+            seq_num: 2,
+            payload_size: body.size(),
+            payload_hash: Some(body.hash()),
+            ..Default::default()
+        };
+
+        // Bodies are only optional for operations before the latest tombstone
+        assert!(validate_tombstoned_body(&header, Some(&body), None).is_ok());
+        assert!(validate_tombstoned_body(&header, None, Some(3)).is_ok());
+        assert!(matches!(
+            validate_tombstoned_body(&header, None, Some(2)),
+            Err(OperationError::MissingBody)
+        ));
+        assert!(matches!(
+            validate_tombstoned_body(&header, None, None),
+            Err(OperationError::MissingBody)
+        ));
+
+        // Operations without payload never need a body
+        let header = Header::<()>::default();
+        assert!(validate_tombstoned_body(&header, None, None).is_ok());
+    }
+
+    #[test]
+    fn tombstone_flag_encoding_is_short() {
+        let tombstone_flag = TombstoneFlag::default();
+        let bytes = encode_cbor(&tombstone_flag).unwrap();
+        assert_eq!(bytes.len(), 1);
+    }
+}
@@ -0,0 +1,22 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use thiserror::Error;
+
+/// Errors returned to foreign code.
+///
+/// Errors are passed on as their message, foreign code receives them as exceptions.
+#[derive(Debug, Error, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum FfiError {
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+
+    #[error("network error: {0}")]
+    Network(String),
+
+    #[error("store error: {0}")]
+    Store(String),
+
+    #[error("blobs error: {0}")]
+    Blobs(String),
+}
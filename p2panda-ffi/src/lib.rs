@@ -0,0 +1,34 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Foreign function interface to embed p2panda nodes in Kotlin and Swift apps.
+//!
+//! `p2panda-ffi` exposes a `p2panda-node` with its network, operation store and blobs service via
+//! [UniFFI](https://mozilla.github.io/uniffi-rs/). Nodes are configured with a `NetworkBuilder`
+//! and started with `NetworkBuilder::build`, the resulting `Network` publishes, subscribes to
+//! and queries operations of topics and manages blobs.
+//!
+//! All async methods run on a Tokio runtime and are exposed as `suspend` functions in Kotlin and
+//! `async` functions in Swift. Operations of subscribed topics are delivered to an
+//! `OperationCallback` implemented in foreign code.
+//!
+//! Topics are identified by their name, binary values like hashes and public keys are passed as
+//! hex-encoded strings.
+//!
+//! ## Generating bindings
+//!
+//! Build the library and generate bindings with the bundled `uniffi-bindgen`:
+//!
+//! ```text
+//! cargo build --release -p p2panda-ffi
+//! cargo run -p p2panda-ffi --features cli --bin uniffi-bindgen -- generate \
+//!     --library target/release/libp2panda_ffi.so --language kotlin --out-dir out
+//! ```
+mod error;
+mod network;
+mod types;
+
+pub use error::FfiError;
+pub use network::{Network, NetworkBuilder, OperationCallback, Subscription};
+pub use types::{Operation, QueryCursor, QueryPage};
+
+uniffi::setup_scaffolding!();
@@ -0,0 +1,274 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use futures_util::StreamExt;
+use p2panda_blobs::DownloadBlobEvent;
+use p2panda_core::PrivateKey;
+use p2panda_net::{RelayUrl, TopicId};
+use p2panda_node::{Node, NodeConfig, NodeOperation, log_id};
+use p2panda_store::query::OperationQuery;
+use tokio::task::JoinHandle;
+
+use crate::FfiError;
+use crate::types::{Operation, QueryCursor, QueryPage, Topic, parse_hash, parse_public_key};
+
+/// Callback receiving the operations of a subscribed topic.
+///
+/// The callback is invoked from a background thread for every operation received from other
+/// peers, after it was validated and persisted.
+#[uniffi::export(with_foreign)]
+pub trait OperationCallback: Send + Sync {
+    fn on_operation(&self, operation: Operation);
+}
+
+/// Builder to configure and start a network node.
+///
+/// Only the network id and a data directory are required, peers are discovered on the local
+/// network via mDNS by default.
+#[derive(Debug, uniffi::Object)]
+pub struct NetworkBuilder {
+    config: Mutex<NodeConfig>,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl NetworkBuilder {
+    /// Returns a builder for a node in the network with the given 32 byte id, keeping all data in
+    /// `data_dir`.
+    #[uniffi::constructor]
+    pub fn new(network_id: Vec<u8>, data_dir: String) -> Result<Arc<Self>, FfiError> {
+        let network_id: [u8; 32] = network_id.try_into().map_err(|_| {
+            FfiError::InvalidArgument("network id needs to be 32 bytes long".into())
+        })?;
+
+        Ok(Arc::new(Self {
+            config: Mutex::new(NodeConfig::new(network_id, data_dir)),
+        }))
+    }
+
+    /// Sets the 32 byte private key of the node.
+    ///
+    /// When no key is given the node generates one on first start and keeps it in the data
+    /// directory.
+    pub fn private_key(self: Arc<Self>, private_key: Vec<u8>) -> Result<Arc<Self>, FfiError> {
+        let private_key = PrivateKey::try_from(&private_key[..])
+            .map_err(|err| FfiError::InvalidArgument(err.to_string()))?;
+        self.update(|config| config.private_key(private_key));
+        Ok(self)
+    }
+
+    /// Enables or disables discovery of peers on the local network via mDNS.
+    pub fn mdns(self: Arc<Self>, enabled: bool) -> Arc<Self> {
+        self.update(|config| config.mdns(enabled));
+        self
+    }
+
+    /// Connects to a relay server to reach peers outside of the local network.
+    pub fn relay(self: Arc<Self>, url: String) -> Result<Arc<Self>, FfiError> {
+        let url: RelayUrl = url
+            .parse()
+            .map_err(|err| FfiError::InvalidArgument(format!("invalid relay url: {err}")))?;
+        self.update(|config| config.relay(url));
+        Ok(self)
+    }
+
+    /// Runs the node as a bootstrap node other peers can join the network through.
+    pub fn bootstrap(self: Arc<Self>) -> Arc<Self> {
+        self.update(|config| config.bootstrap());
+        self
+    }
+
+    /// Adds a known peer, given by its hex-encoded public key, to join the network through.
+    pub fn bootstrap_peer(self: Arc<Self>, public_key: String) -> Result<Arc<Self>, FfiError> {
+        let public_key = parse_public_key(&public_key)?;
+        self.update(|config| config.bootstrap_peer(public_key));
+        Ok(self)
+    }
+
+    /// Starts the node with the current configuration.
+    pub async fn build(&self) -> Result<Arc<Network>, FfiError> {
+        let config = self
+            .config
+            .lock()
+            .expect("config lock was poisoned")
+            .clone();
+        let node = Node::spawn(config)
+            .await
+            .map_err(|err| FfiError::Network(err.to_string()))?;
+        Ok(Arc::new(Network { node }))
+    }
+}
+
+impl NetworkBuilder {
+    fn update(&self, f: impl FnOnce(NodeConfig) -> NodeConfig) {
+        let mut config = self.config.lock().expect("config lock was poisoned");
+        *config = f(config.clone());
+    }
+}
+
+/// Running network node with its operation store and blobs service.
+#[derive(Debug, uniffi::Object)]
+pub struct Network {
+    node: Node<Topic>,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl Network {
+    /// Returns the hex-encoded public key of this node.
+    pub fn node_id(&self) -> String {
+        self.node.public_key().to_hex()
+    }
+
+    /// Subscribes to a topic and delivers all operations received from other peers to the
+    /// callback until the subscription is cancelled.
+    pub async fn subscribe(
+        &self,
+        topic: String,
+        callback: Arc<dyn OperationCallback>,
+    ) -> Result<Arc<Subscription>, FfiError> {
+        let mut rx = self
+            .node
+            .subscribe(Topic::new(&topic))
+            .await
+            .map_err(|err| FfiError::Network(err.to_string()))?;
+
+        let handle = tokio::spawn(async move {
+            while let Some(operation) = rx.recv().await {
+                callback.on_operation(operation.into());
+            }
+        });
+
+        Ok(Arc::new(Subscription {
+            handle: Mutex::new(Some(handle)),
+        }))
+    }
+
+    /// Publishes a payload to a topic and returns the signed operation.
+    pub async fn publish(&self, topic: String, payload: Vec<u8>) -> Result<Operation, FfiError> {
+        let operation = self
+            .node
+            .publish(&Topic::new(&topic), &payload)
+            .await
+            .map_err(|err| FfiError::Store(err.to_string()))?;
+        Ok(operation.into())
+    }
+
+    /// Queries a page of the persisted operations of a topic, ordered by timestamp.
+    ///
+    /// Pass the cursor of the previous page to request the next one.
+    pub async fn query(
+        &self,
+        topic: String,
+        after: Option<QueryCursor>,
+        limit: u32,
+    ) -> Result<QueryPage, FfiError> {
+        let mut query = OperationQuery::new()
+            .log_ids([log_id(&Topic::new(&topic))])
+            .limit(limit as usize);
+        if let Some(cursor) = after {
+            query = query.after(cursor.try_into()?);
+        }
+
+        let page = self
+            .node
+            .query(&query)
+            .await
+            .map_err(|err| FfiError::Store(err.to_string()))?;
+
+        Ok(QueryPage {
+            operations: page
+                .operations
+                .into_iter()
+                .map(|(header, body)| {
+                    Operation::from(NodeOperation {
+                        hash: header.hash(),
+                        header,
+                        body,
+                    })
+                })
+                .collect(),
+            next: page.next.map(QueryCursor::from),
+        })
+    }
+
+    /// Adds the file at the given path to the blob store and returns the hex-encoded hash of the
+    /// blob.
+    pub async fn add_blob(&self, path: String) -> Result<String, FfiError> {
+        let blob = self
+            .node
+            .blobs()
+            .add_from_path(PathBuf::from(path))
+            .await
+            .map_err(|err| FfiError::Blobs(err.to_string()))?;
+        Ok(blob.hash.to_hex())
+    }
+
+    /// Announces blobs to other peers interested in the topic.
+    pub async fn provide_blobs(&self, topic: String, hashes: Vec<String>) -> Result<(), FfiError> {
+        let hashes = hashes
+            .iter()
+            .map(|hash| parse_hash(hash))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.node
+            .blobs()
+            .provide_blobs(Topic::new(&topic).id(), &hashes)
+            .await
+            .map_err(|err| FfiError::Blobs(err.to_string()))
+    }
+
+    /// Downloads a blob from other peers, returns when the download completed.
+    pub async fn download_blob(&self, hash: String) -> Result<(), FfiError> {
+        let hash = parse_hash(&hash)?;
+        let mut events = Box::pin(self.node.blobs().download_blob(hash).await);
+        while let Some(event) = events.next().await {
+            match event {
+                DownloadBlobEvent::Done => return Ok(()),
+                DownloadBlobEvent::Abort(err) => return Err(FfiError::Blobs(err.to_string())),
+                _ => (),
+            }
+        }
+        Err(FfiError::Blobs("download ended unexpectedly".into()))
+    }
+
+    /// Exports a blob to the given filesystem path.
+    pub async fn export_blob(&self, hash: String, path: String) -> Result<(), FfiError> {
+        let hash = parse_hash(&hash)?;
+        self.node
+            .blobs()
+            .export_blob(hash, &PathBuf::from(path))
+            .await
+            .map_err(|err| FfiError::Blobs(err.to_string()))
+    }
+
+    /// Gracefully shuts the node down.
+    pub async fn shutdown(&self) -> Result<(), FfiError> {
+        self.node
+            .clone()
+            .shutdown()
+            .await
+            .map_err(|err| FfiError::Network(err.to_string()))
+    }
+}
+
+/// Handle of a topic subscription.
+#[derive(Debug, uniffi::Object)]
+pub struct Subscription {
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+#[uniffi::export]
+impl Subscription {
+    /// Stops delivering operations to the callback.
+    pub fn cancel(&self) {
+        if let Some(handle) = self.handle.lock().expect("handle lock was poisoned").take() {
+            handle.abort();
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
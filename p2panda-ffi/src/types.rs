@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use p2panda_core::{Hash, PublicKey};
+use p2panda_net::TopicId;
+use p2panda_node::NodeOperation;
+use p2panda_store::query::Cursor;
+use p2panda_sync::TopicQuery;
+use serde::{Deserialize, Serialize};
+
+use crate::FfiError;
+
+/// Topic identified by the hash of its name.
+///
+/// Foreign code refers to topics by their name, for example "chat/me-and-my-friends".
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct Topic(Hash);
+
+impl Topic {
+    pub(crate) fn new(name: &str) -> Self {
+        Self(Hash::new(name.as_bytes()))
+    }
+}
+
+impl TopicQuery for Topic {}
+
+impl TopicId for Topic {
+    fn id(&self) -> [u8; 32] {
+        self.0.into()
+    }
+}
+
+/// Operation published or received by a node.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct Operation {
+    /// Hash of the operation, hex-encoded.
+    pub hash: String,
+
+    /// Public key of the author, hex-encoded.
+    pub public_key: String,
+
+    pub seq_num: u64,
+
+    /// Time of creation in microseconds since the UNIX epoch, as claimed by the author.
+    pub timestamp: u64,
+
+    /// Payload of the operation, `None` if it was deleted or not synced.
+    pub payload: Option<Vec<u8>>,
+}
+
+impl From<NodeOperation> for Operation {
+    fn from(operation: NodeOperation) -> Self {
+        Self {
+            hash: operation.hash.to_hex(),
+            public_key: operation.header.public_key.to_hex(),
+            seq_num: operation.header.seq_num,
+            timestamp: operation.header.timestamp,
+            payload: operation.body.map(|body| body.to_bytes()),
+        }
+    }
+}
+
+/// Position in the result of a query to request the next page with.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct QueryCursor {
+    pub timestamp: u64,
+
+    /// Hash of the last returned operation, hex-encoded.
+    pub hash: String,
+}
+
+impl From<Cursor> for QueryCursor {
+    fn from(cursor: Cursor) -> Self {
+        Self {
+            timestamp: cursor.timestamp,
+            hash: cursor.hash.to_hex(),
+        }
+    }
+}
+
+impl TryFrom<QueryCursor> for Cursor {
+    type Error = FfiError;
+
+    fn try_from(cursor: QueryCursor) -> Result<Self, Self::Error> {
+        Ok(Cursor::new(cursor.timestamp, parse_hash(&cursor.hash)?))
+    }
+}
+
+/// Page of operations returned by a query.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct QueryPage {
+    pub operations: Vec<Operation>,
+
+    /// Cursor to request the next page with, `None` if there are no more results.
+    pub next: Option<QueryCursor>,
+}
+
+pub(crate) fn parse_hash(value: &str) -> Result<Hash, FfiError> {
+    value
+        .parse()
+        .map_err(|err| FfiError::InvalidArgument(format!("invalid hash {value}: {err}")))
+}
+
+pub(crate) fn parse_public_key(value: &str) -> Result<PublicKey, FfiError> {
+    value
+        .parse()
+        .map_err(|err| FfiError::InvalidArgument(format!("invalid public key {value}: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::{Body, Hash, Header, PrivateKey};
+    use p2panda_node::{NodeExtensions, NodeOperation};
+    use p2panda_store::query::Cursor;
+
+    use crate::FfiError;
+
+    use super::{Operation, QueryCursor, parse_hash};
+
+    #[test]
+    fn convert_operation() {
+        let private_key = PrivateKey::new();
+        let body = Body::new(b"Hello, Sloth!");
+        let mut header = Header::<NodeExtensions> {
+            public_key: private_key.public_key(),
+            payload_size: body.size(),
+            payload_hash: Some(body.hash()),
+            seq_num: 4,
+            ..Default::default()
+        };
+        header.sign(&private_key);
+
+        let operation: Operation = NodeOperation {
+            hash: header.hash(),
+            header: header.clone(),
+            body: Some(body),
+        }
+        .into();
+
+        assert_eq!(operation.hash, header.hash().to_hex());
+        assert_eq!(operation.public_key, private_key.public_key().to_hex());
+        assert_eq!(operation.seq_num, 4);
+        assert_eq!(operation.payload, Some(b"Hello, Sloth!".to_vec()));
+    }
+
+    #[test]
+    fn convert_cursor() {
+        let cursor = Cursor::new(12, Hash::new(b"operation"));
+        let converted: Cursor = QueryCursor::from(cursor).try_into().unwrap();
+        assert_eq!(converted, cursor);
+
+        assert!(matches!(
+            parse_hash("not a hash"),
+            Err(FfiError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,5 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+fn main() {
+    uniffi::uniffi_bindgen_main()
+}
@@ -0,0 +1,632 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Transparent compression of gossip messages and sync sessions.
+//!
+//! Compression is disabled by default and enabled with `NetworkBuilder::compression`. Messages
+//! and frames smaller than the configured threshold are always sent uncompressed, larger ones are
+//! compressed if this actually reduces their size. Text-heavy payloads usually compress very well.
+//!
+//! The compression algorithm of a sync session is negotiated with the remote peer together with
+//! the sync protocol, peers without compression or without a mutually supported algorithm sync
+//! uncompressed data.
+//!
+//! Gossip messages are broadcast to many peers at once and can't be negotiated per connection.
+//! Every gossip message carries a tag naming the algorithm it was compressed with instead, so all
+//! nodes of a network need to enable compression to understand each other's messages.
+//!
+//! Algorithms are enabled with the `zstd` and `lz4` features.
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, ready};
+
+use futures_util::{AsyncRead, AsyncWrite};
+use serde::{Deserialize, Serialize};
+
+/// Default size in bytes from which on messages and frames are compressed.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Maximum size in bytes of uncompressed data in a single sync frame.
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Maximum size in bytes of decompressed data, protecting against decompression bombs.
+#[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(dead_code))]
+const MAX_DECOMPRESSED_SIZE: usize = 4 * 1024 * 1024;
+
+/// Tag of uncompressed messages and frames.
+const TAG_UNCOMPRESSED: u8 = 0;
+
+/// Compression algorithms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum CompressionAlgorithm {
+    /// Zstandard, good compression ratios at moderate speed.
+    ///
+    /// Requires the `zstd` feature.
+    Zstd,
+
+    /// LZ4, very fast with lower compression ratios.
+    ///
+    /// Requires the `lz4` feature.
+    Lz4,
+}
+
+impl CompressionAlgorithm {
+    /// Returns `true` if the algorithm was enabled with its feature.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Self::Zstd => cfg!(feature = "zstd"),
+            Self::Lz4 => cfg!(feature = "lz4"),
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            Self::Zstd => 1,
+            Self::Lz4 => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(Self::Zstd),
+            2 => Some(Self::Lz4),
+            _ => None,
+        }
+    }
+
+    fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Zstd => zstd_codec::compress(bytes),
+            Self::Lz4 => lz4_codec::compress(bytes),
+        }
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Zstd => zstd_codec::decompress(bytes),
+            Self::Lz4 => lz4_codec::decompress(bytes),
+        }
+    }
+}
+
+/// Configuration of compression for gossip messages and sync sessions.
+#[derive(Clone, Debug)]
+pub struct CompressionConfig {
+    /// Supported algorithms, ordered by preference.
+    algorithms: Vec<CompressionAlgorithm>,
+
+    /// Size in bytes from which on messages and frames are compressed.
+    threshold: usize,
+
+    metrics: CompressionMetrics,
+}
+
+impl CompressionConfig {
+    /// Returns a configuration using all algorithms enabled by features, preferring zstd.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define the supported algorithms, ordered by preference.
+    ///
+    /// Algorithms not enabled by their feature are ignored. Gossip messages are compressed with
+    /// the first algorithm.
+    pub fn algorithms(
+        mut self,
+        algorithms: impl IntoIterator<Item = CompressionAlgorithm>,
+    ) -> Self {
+        self.algorithms = algorithms
+            .into_iter()
+            .filter(CompressionAlgorithm::is_supported)
+            .collect();
+        self
+    }
+
+    /// Define the size in bytes from which on messages and frames are compressed.
+    ///
+    /// Default: 1024 bytes.
+    pub fn threshold(mut self, bytes: usize) -> Self {
+        self.threshold = bytes;
+        self
+    }
+
+    /// Returns the metrics of all data compressed with this configuration.
+    pub fn metrics(&self) -> CompressionMetrics {
+        self.metrics.clone()
+    }
+
+    /// Returns the supported algorithms, ordered by preference.
+    pub(crate) fn supported(&self) -> &[CompressionAlgorithm] {
+        &self.algorithms
+    }
+
+    /// Returns a compressor using the given algorithm, `None` sends all data uncompressed.
+    pub(crate) fn compressor(&self, algorithm: Option<CompressionAlgorithm>) -> Compressor {
+        Compressor {
+            algorithm,
+            threshold: self.threshold,
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    /// Returns the compressor for gossip messages.
+    pub(crate) fn gossip_compressor(&self) -> Compressor {
+        self.compressor(self.algorithms.first().copied())
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithms: [CompressionAlgorithm::Zstd, CompressionAlgorithm::Lz4]
+                .into_iter()
+                .filter(CompressionAlgorithm::is_supported)
+                .collect(),
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            metrics: CompressionMetrics::default(),
+        }
+    }
+}
+
+/// Metrics of the compression of sent gossip messages and sync frames.
+///
+/// Only messages and frames which were sent compressed are taken into account.
+#[derive(Clone, Debug, Default)]
+pub struct CompressionMetrics(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    messages: AtomicU64,
+    uncompressed_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+}
+
+impl CompressionMetrics {
+    /// Returns the number of compressed messages and frames.
+    pub fn compressed_messages(&self) -> u64 {
+        self.0.messages.load(Ordering::Relaxed)
+    }
+
+    /// Returns the size in bytes of all compressed messages and frames before compression.
+    pub fn uncompressed_bytes(&self) -> u64 {
+        self.0.uncompressed_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the size in bytes of all compressed messages and frames after compression.
+    pub fn compressed_bytes(&self) -> u64 {
+        self.0.compressed_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the achieved compression ratio, the uncompressed divided by the compressed size.
+    ///
+    /// Returns 1.0 if nothing was compressed yet.
+    pub fn ratio(&self) -> f64 {
+        let compressed = self.compressed_bytes();
+        if compressed == 0 {
+            return 1.0;
+        }
+        self.uncompressed_bytes() as f64 / compressed as f64
+    }
+
+    fn record(&self, uncompressed: usize, compressed: usize) {
+        self.0.messages.fetch_add(1, Ordering::Relaxed);
+        self.0
+            .uncompressed_bytes
+            .fetch_add(uncompressed as u64, Ordering::Relaxed);
+        self.0
+            .compressed_bytes
+            .fetch_add(compressed as u64, Ordering::Relaxed);
+    }
+}
+
+/// Compresses messages with an algorithm, if they exceed the threshold.
+#[derive(Clone, Debug)]
+pub(crate) struct Compressor {
+    algorithm: Option<CompressionAlgorithm>,
+    threshold: usize,
+    metrics: CompressionMetrics,
+}
+
+impl Compressor {
+    /// Returns the message prefixed with a tag naming the algorithm it was compressed with.
+    ///
+    /// Messages are sent uncompressed if they are below the threshold or if compression does not
+    /// reduce their size.
+    pub(crate) fn encode(&self, bytes: &[u8]) -> Vec<u8> {
+        if let Some(algorithm) = self.algorithm
+            && bytes.len() >= self.threshold
+            && let Ok(compressed) = algorithm.compress(bytes)
+            && compressed.len() < bytes.len()
+        {
+            self.metrics.record(bytes.len(), compressed.len());
+            return tagged(algorithm.tag(), &compressed);
+        }
+
+        tagged(TAG_UNCOMPRESSED, bytes)
+    }
+}
+
+fn tagged(tag: u8, bytes: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(bytes.len() + 1);
+    message.push(tag);
+    message.extend_from_slice(bytes);
+    message
+}
+
+/// Decodes a tagged message, decompressing it if necessary.
+pub(crate) fn decode(message: &[u8]) -> io::Result<Vec<u8>> {
+    let Some((tag, bytes)) = message.split_first() else {
+        return Err(invalid_data("empty compressed message"));
+    };
+
+    if *tag == TAG_UNCOMPRESSED {
+        return Ok(bytes.to_vec());
+    }
+
+    let algorithm = CompressionAlgorithm::from_tag(*tag)
+        .ok_or_else(|| invalid_data("unknown compression algorithm"))?;
+    algorithm.decompress(bytes)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg_attr(all(feature = "zstd", feature = "lz4"), allow(dead_code))]
+fn unsupported(algorithm: CompressionAlgorithm) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{algorithm:?} compression is not enabled"),
+    )
+}
+
+#[cfg(feature = "zstd")]
+mod zstd_codec {
+    use std::io;
+
+    use super::MAX_DECOMPRESSED_SIZE;
+
+    const LEVEL: i32 = 3;
+
+    pub(super) fn compress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::bulk::compress(bytes, LEVEL)
+    }
+
+    pub(super) fn decompress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::bulk::decompress(bytes, MAX_DECOMPRESSED_SIZE)
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+mod zstd_codec {
+    use std::io;
+
+    use super::{CompressionAlgorithm, unsupported};
+
+    pub(super) fn compress(_bytes: &[u8]) -> io::Result<Vec<u8>> {
+        Err(unsupported(CompressionAlgorithm::Zstd))
+    }
+
+    pub(super) fn decompress(_bytes: &[u8]) -> io::Result<Vec<u8>> {
+        Err(unsupported(CompressionAlgorithm::Zstd))
+    }
+}
+
+#[cfg(feature = "lz4")]
+mod lz4_codec {
+    use std::io;
+
+    use super::{MAX_DECOMPRESSED_SIZE, invalid_data};
+
+    pub(super) fn compress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(lz4_flex::block::compress_prepend_size(bytes))
+    }
+
+    pub(super) fn decompress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        // Check the prepended size before allocating the buffer for the decompressed data.
+        let size = bytes
+            .first_chunk::<4>()
+            .map(|size| u32::from_le_bytes(*size) as usize)
+            .ok_or_else(|| invalid_data("missing size of lz4 compressed data"))?;
+        if size > MAX_DECOMPRESSED_SIZE {
+            return Err(invalid_data("decompressed data exceeds maximum size"));
+        }
+
+        lz4_flex::block::decompress_size_prepended(bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+#[cfg(not(feature = "lz4"))]
+mod lz4_codec {
+    use std::io;
+
+    use super::{CompressionAlgorithm, unsupported};
+
+    pub(super) fn compress(_bytes: &[u8]) -> io::Result<Vec<u8>> {
+        Err(unsupported(CompressionAlgorithm::Lz4))
+    }
+
+    pub(super) fn decompress(_bytes: &[u8]) -> io::Result<Vec<u8>> {
+        Err(unsupported(CompressionAlgorithm::Lz4))
+    }
+}
+
+/// Wrapper around a send or receive stream compressing the data in frames, if a compressor is
+/// given.
+///
+/// Written data is buffered until the stream is flushed or the buffer reached the maximum frame
+/// size, every frame is prefixed with its length. Without a compressor all data is passed
+/// through unchanged.
+pub(crate) struct Compressed<'a, S> {
+    inner: &'a mut S,
+    compressor: Option<Compressor>,
+
+    /// Data written since the last frame was sent.
+    write_buffer: Vec<u8>,
+
+    /// Frame which is currently sent.
+    frame: Vec<u8>,
+    frame_written: usize,
+
+    /// Length prefix of the frame which is currently received.
+    header: [u8; 4],
+    header_read: usize,
+
+    /// Frame which is currently received.
+    read_frame: Vec<u8>,
+    frame_read: usize,
+
+    /// Decoded data of the last received frame.
+    decoded: Vec<u8>,
+    decoded_read: usize,
+}
+
+impl<'a, S> Compressed<'a, S> {
+    pub fn new(inner: &'a mut S, compressor: Option<Compressor>) -> Self {
+        Self {
+            inner,
+            compressor,
+            write_buffer: Vec::new(),
+            frame: Vec::new(),
+            frame_written: 0,
+            header: [0; 4],
+            header_read: 0,
+            read_frame: Vec::new(),
+            frame_read: 0,
+            decoded: Vec::new(),
+            decoded_read: 0,
+        }
+    }
+}
+
+impl<S> Compressed<'_, S>
+where
+    S: AsyncWrite + Unpin,
+{
+    /// Sends all buffered data as a frame.
+    fn poll_write_frame(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.frame.is_empty() {
+            let Some(compressor) = &self.compressor else {
+                return Poll::Ready(Ok(()));
+            };
+            if self.write_buffer.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+
+            let message = compressor.encode(&self.write_buffer);
+            self.write_buffer.clear();
+            self.frame
+                .extend_from_slice(&(message.len() as u32).to_be_bytes());
+            self.frame.extend_from_slice(&message);
+        }
+
+        while self.frame_written < self.frame.len() {
+            let bytes = ready!(
+                Pin::new(&mut *self.inner).poll_write(cx, &self.frame[self.frame_written..])
+            )?;
+            if bytes == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.frame_written += bytes;
+        }
+
+        self.frame.clear();
+        self.frame_written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncWrite for Compressed<'_, S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.compressor.is_none() {
+            return Pin::new(&mut *self.inner).poll_write(cx, buf);
+        }
+
+        if self.write_buffer.len() >= MAX_FRAME_SIZE {
+            ready!(self.poll_write_frame(cx))?;
+        }
+
+        let bytes = buf.len().min(MAX_FRAME_SIZE - self.write_buffer.len());
+        self.write_buffer.extend_from_slice(&buf[..bytes]);
+        Poll::Ready(Ok(bytes))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_write_frame(cx))?;
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_write_frame(cx))?;
+        Pin::new(&mut *self.inner).poll_close(cx)
+    }
+}
+
+impl<S> AsyncRead for Compressed<'_, S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.compressor.is_none() {
+            return Pin::new(&mut *this.inner).poll_read(cx, buf);
+        }
+
+        loop {
+            if this.decoded_read < this.decoded.len() {
+                let remaining = &this.decoded[this.decoded_read..];
+                let bytes = remaining.len().min(buf.len());
+                buf[..bytes].copy_from_slice(&remaining[..bytes]);
+                this.decoded_read += bytes;
+                return Poll::Ready(Ok(bytes));
+            }
+
+            // Receive the length prefix of the next frame.
+            while this.header_read < this.header.len() {
+                let bytes = ready!(
+                    Pin::new(&mut *this.inner).poll_read(cx, &mut this.header[this.header_read..])
+                )?;
+                if bytes == 0 {
+                    // The stream ended cleanly if it ended between two frames.
+                    if this.header_read == 0 {
+                        return Poll::Ready(Ok(0));
+                    }
+                    return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                }
+                this.header_read += bytes;
+            }
+
+            // Uncompressed frames are one tag byte larger than their data.
+            let len = u32::from_be_bytes(this.header) as usize;
+            if len == 0 || len > MAX_FRAME_SIZE + 1 {
+                return Poll::Ready(Err(invalid_data("invalid compressed frame size")));
+            }
+
+            this.read_frame.resize(len, 0);
+            while this.frame_read < len {
+                let bytes = ready!(
+                    Pin::new(&mut *this.inner)
+                        .poll_read(cx, &mut this.read_frame[this.frame_read..])
+                )?;
+                if bytes == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                }
+                this.frame_read += bytes;
+            }
+
+            this.decoded = decode(&this.read_frame)?;
+            this.decoded_read = 0;
+            this.header_read = 0;
+            this.frame_read = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{
+        Compressed, CompressionAlgorithm, CompressionConfig, DEFAULT_COMPRESSION_THRESHOLD, decode,
+    };
+
+    fn text(len: usize) -> Vec<u8> {
+        b"Hello, Panda! "
+            .iter()
+            .copied()
+            .cycle()
+            .take(len)
+            .collect()
+    }
+
+    #[test]
+    fn small_messages_stay_uncompressed() {
+        let config = CompressionConfig::new().algorithms([CompressionAlgorithm::Lz4]);
+        let compressor = config.gossip_compressor();
+
+        let message = text(DEFAULT_COMPRESSION_THRESHOLD - 1);
+        let encoded = compressor.encode(&message);
+        assert_eq!(encoded.len(), message.len() + 1);
+        assert_eq!(decode(&encoded).unwrap(), message);
+        assert_eq!(config.metrics().compressed_messages(), 0);
+        assert_eq!(config.metrics().ratio(), 1.0);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn compress_large_messages() {
+        let config = CompressionConfig::new().algorithms([CompressionAlgorithm::Lz4]);
+        let compressor = config.gossip_compressor();
+
+        let message = text(16 * 1024);
+        let encoded = compressor.encode(&message);
+        assert!(encoded.len() < message.len());
+        assert_eq!(decode(&encoded).unwrap(), message);
+
+        let metrics = config.metrics();
+        assert_eq!(metrics.compressed_messages(), 1);
+        assert_eq!(metrics.uncompressed_bytes(), message.len() as u64);
+        assert!(metrics.ratio() > 10.0);
+    }
+
+    #[test]
+    fn reject_invalid_messages() {
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[42, 1, 2, 3]).is_err());
+        assert!(decode(&[2, 255, 255, 255, 255]).is_err());
+    }
+
+    #[cfg(feature = "lz4")]
+    #[tokio::test]
+    async fn compressed_stream() {
+        let config = CompressionConfig::new()
+            .algorithms([CompressionAlgorithm::Lz4])
+            .threshold(64);
+        let first = text(32);
+        let second = text(200 * 1024);
+
+        let mut wire = Vec::new();
+        {
+            let mut send = Compressed::new(
+                &mut wire,
+                Some(config.compressor(Some(CompressionAlgorithm::Lz4))),
+            );
+            send.write_all(&first).await.unwrap();
+            send.flush().await.unwrap();
+            send.write_all(&second).await.unwrap();
+            send.close().await.unwrap();
+        }
+        assert!(wire.len() < first.len() + second.len());
+        assert!(config.metrics().compressed_messages() >= 4);
+
+        let mut recv = &wire[..];
+        let mut recv = Compressed::new(&mut recv, Some(config.compressor(None)));
+        let mut received = Vec::new();
+        recv.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, [first, second].concat());
+    }
+
+    #[tokio::test]
+    async fn pass_through_without_compressor() {
+        let mut wire = Vec::new();
+        {
+            let mut send = Compressed::new(&mut wire, None);
+            send.write_all(&text(4096)).await.unwrap();
+        }
+        assert_eq!(wire, text(4096));
+    }
+}
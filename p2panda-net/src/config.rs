@@ -7,6 +7,9 @@
 //!
 //! `GossipConfig` allows configuration of swarm membership, gossip broadcast and maximum message
 //! size. It is passed into `Network::gossip`.
+//!
+//! `PanicPolicy` defines how the node reacts to panics in internal tasks. It is passed into
+//! `NetworkBuilder::panic_policy`.
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::path::PathBuf;
 
@@ -69,17 +72,66 @@ impl Default for Config {
     }
 }
 
+/// Policy defining how the node reacts to panics in internal tasks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PanicPolicy {
+    /// Shut down the node as soon as any internal task panicked.
+    #[default]
+    FailFast,
+
+    /// Keep the node running and restart the failed subsystem with an exponential backoff.
+    ///
+    /// Supervised subsystems are the engine, gossip and sync actors as well as the task updating
+    /// discovery services with our local addresses. Panics while handling a single incoming
+    /// connection only affect that connection. Restarts are reported as
+    /// `SystemEvent::SubsystemRestarted`.
+    Isolate,
+}
+
+/// Default maximum size in bytes of gossip messages which are split into chunks.
+pub const DEFAULT_MAX_CHUNKED_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Maximum number of gossip messages retained per topic for late subscribers.
+///
+/// Matches the capacity of the channel of every subscription, so replayed messages never wait for
+/// the application to consume them.
+pub const MAX_RETAINED_MESSAGES: usize = 128;
+
 /// Configuration parameters for gossip overlays.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GossipConfig {
     /// Maximum gossip message size in bytes.
+    ///
+    /// Larger messages are split into chunks and reassembled by the receiving peers.
     pub max_message_size: usize,
+
+    /// Maximum size in bytes of messages which are split into chunks.
+    ///
+    /// Larger messages are dropped and reported with `SystemEvent::GossipMessageTooLarge`, chunked
+    /// messages exceeding this size are rejected by receivers.
+    #[serde(default = "default_max_chunked_message_size")]
+    pub max_chunked_message_size: usize,
+
+    /// Number of the latest gossip messages retained per topic and replayed to new local
+    /// subscribers, capped at `MAX_RETAINED_MESSAGES`.
+    ///
+    /// This helps applications which subscribe again to a topic, for example after a UI route
+    /// change, to not miss messages received shortly before. Messages are kept for a minute after
+    /// the last subscription to the topic ended, only retained in memory and disabled with `0`.
+    #[serde(default)]
+    pub retained_messages: usize,
 }
 
 impl Default for GossipConfig {
     fn default() -> Self {
         Self {
             max_message_size: 4096,
+            max_chunked_message_size: DEFAULT_MAX_CHUNKED_MESSAGE_SIZE,
+            retained_messages: 0,
         }
     }
 }
+
+fn default_max_chunked_message_size() -> usize {
+    DEFAULT_MAX_CHUNKED_MESSAGE_SIZE
+}
@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Explicit dialing of peers.
+//!
+//! Connections are usually established implicitly, when joining gossip overlays or starting sync
+//! sessions. `Network::connect` dials a peer explicitly instead, for example to pre-warm the
+//! connection to a contact before the user opens a conversation, and reports how the peer was
+//! reached.
+//!
+//! Dialing establishes a short-lived connection using the `DIAL_ALPN` protocol which is accepted
+//! by every node. The endpoint keeps the discovered path to the peer afterwards, later
+//! connections of other protocols reuse it.
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures_lite::future::Boxed as BoxedFuture;
+use iroh::Endpoint;
+use iroh::endpoint::Connecting;
+use p2panda_core::PublicKey;
+use thiserror::Error;
+use tokio::time::{Duration, Instant, timeout};
+
+use crate::from_public_key;
+use crate::protocols::ProtocolHandler;
+use crate::relay_usage::{ConnectionPath, RelayUsage};
+
+pub const DIAL_ALPN: &[u8] = b"/p2panda-net-dial/1";
+
+/// Maximum duration of dialing a peer.
+pub const DIAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reason why dialing a peer failed.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum ConnectError {
+    /// Our own node was dialed.
+    #[error("can't connect to own node")]
+    OwnNode,
+
+    /// The peer did not answer in time.
+    #[error("connecting to peer timed out")]
+    Timeout,
+
+    /// No connection could be established, for example because no address of the peer is known.
+    #[error("connecting to peer failed: {0}")]
+    Connection(String),
+}
+
+/// Result of dialing a peer with `Network::connect`.
+///
+/// Connections often start out relayed and switch to a direct path once hole punching succeeded,
+/// the result reports the path at the time the connection was established. The current path is
+/// returned by `RelayUsage::path`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectResult {
+    /// The peer was reached directly.
+    Direct { elapsed: Duration },
+
+    /// The peer was reached via a relay.
+    Relayed { elapsed: Duration },
+
+    /// The peer could not be reached.
+    Failed {
+        reason: ConnectError,
+        elapsed: Duration,
+    },
+}
+
+impl ConnectResult {
+    /// Returns `true` if the peer was reached.
+    pub fn is_connected(&self) -> bool {
+        !matches!(self, ConnectResult::Failed { .. })
+    }
+
+    /// Returns the time it took to reach the peer or to fail.
+    pub fn elapsed(&self) -> Duration {
+        match self {
+            ConnectResult::Direct { elapsed }
+            | ConnectResult::Relayed { elapsed }
+            | ConnectResult::Failed { elapsed, .. } => *elapsed,
+        }
+    }
+}
+
+/// Dials the peer using the namespaced `DIAL_ALPN` and reports the path it was reached on.
+pub(crate) async fn dial(
+    endpoint: &Endpoint,
+    alpn: &[u8],
+    relay_usage: &RelayUsage,
+    peer: PublicKey,
+    dial_timeout: Duration,
+) -> ConnectResult {
+    let started = Instant::now();
+    let failed = |reason| ConnectResult::Failed {
+        reason,
+        elapsed: started.elapsed(),
+    };
+
+    if endpoint.node_id() == from_public_key(peer) {
+        return failed(ConnectError::OwnNode);
+    }
+
+    let connection =
+        match timeout(dial_timeout, endpoint.connect(from_public_key(peer), alpn)).await {
+            Ok(Ok(connection)) => connection,
+            Ok(Err(err)) => return failed(ConnectError::Connection(err.to_string())),
+            Err(_) => return failed(ConnectError::Timeout),
+        };
+    let elapsed = started.elapsed();
+    let path = relay_usage.path(&peer);
+    connection.close(0u32.into(), b"dialed");
+
+    match path {
+        Some(ConnectionPath::Direct) => ConnectResult::Direct { elapsed },
+        _ => ConnectResult::Relayed { elapsed },
+    }
+}
+
+/// Accepts connections of peers dialing us and waits until they close them.
+#[derive(Debug, Default)]
+pub(crate) struct DialHandler;
+
+impl ProtocolHandler for DialHandler {
+    fn accept(self: Arc<Self>, connecting: Connecting) -> BoxedFuture<Result<()>> {
+        Box::pin(async move {
+            let connection = connecting.await?;
+            timeout(DIAL_TIMEOUT, connection.closed()).await.ok();
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Duration;
+
+    use super::{ConnectError, ConnectResult};
+
+    #[test]
+    fn connect_results() {
+        let elapsed = Duration::from_millis(120);
+        assert!(ConnectResult::Direct { elapsed }.is_connected());
+        assert!(ConnectResult::Relayed { elapsed }.is_connected());
+
+        let failed = ConnectResult::Failed {
+            reason: ConnectError::Timeout,
+            elapsed,
+        };
+        assert!(!failed.is_connected());
+        assert_eq!(failed.elapsed(), elapsed);
+    }
+}
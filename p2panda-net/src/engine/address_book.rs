@@ -3,10 +3,12 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
-use p2panda_core::PublicKey;
+use p2panda_core::{Hash, PublicKey};
 use rand::seq::IteratorRandom;
 use tokio::sync::RwLock;
 
+use crate::providers::BlobFilter;
+use crate::roles::{NodeRoles, blob_provider_rank};
 use crate::{NetworkId, NodeAddress};
 
 /// Address book with peer addresses and their topic ids.
@@ -14,6 +16,9 @@ use crate::{NetworkId, NodeAddress};
 /// Manages a list of all peer addresses which are known to us (usually populated by a "peer
 /// discovery" process) and a list of all topic id's peers in this network are interested in
 /// (usually populated by a "topic discovery" process).
+///
+/// Next to topic ids, peers can advertise the roles they take on for each topic (for example
+/// being an archive) and the blobs they provide which are kept here as well.
 #[derive(Debug, Clone)]
 pub struct AddressBook {
     network_id: NetworkId,
@@ -24,6 +29,8 @@ pub struct AddressBook {
 struct AddressBookInner {
     known_peer_topic_ids: HashMap<PublicKey, HashSet<[u8; 32]>>,
     known_peer_addresses: HashMap<PublicKey, HashSet<NodeAddress>>,
+    known_peer_roles: HashMap<PublicKey, HashMap<[u8; 32], NodeRoles>>,
+    known_peer_blobs: HashMap<PublicKey, HashMap<[u8; 32], BlobFilter>>,
 }
 
 impl AddressBook {
@@ -34,6 +41,8 @@ impl AddressBook {
             inner: Arc::new(RwLock::new(AddressBookInner {
                 known_peer_topic_ids: HashMap::new(),
                 known_peer_addresses: HashMap::new(),
+                known_peer_roles: HashMap::new(),
+                known_peer_blobs: HashMap::new(),
             })),
         }
     }
@@ -70,6 +79,93 @@ impl AddressBook {
             });
     }
 
+    /// Return `true` if the peer is known to be interested in the topic id.
+    pub async fn has_topic_id(&self, public_key: PublicKey, topic_id: [u8; 32]) -> bool {
+        let inner = self.inner.read().await;
+        inner
+            .known_peer_topic_ids
+            .get(&public_key)
+            .is_some_and(|topics| topics.contains(&topic_id))
+    }
+
+    /// Set the roles a peer advertised for a topic id, overwriting previously known ones.
+    pub async fn set_roles(&mut self, public_key: PublicKey, topic_id: [u8; 32], roles: NodeRoles) {
+        let mut inner = self.inner.write().await;
+        let peer_roles = inner.known_peer_roles.entry(public_key).or_default();
+        if roles.is_empty() {
+            peer_roles.remove(&topic_id);
+        } else {
+            peer_roles.insert(topic_id, roles);
+        }
+    }
+
+    /// Return the roles a peer advertised for a topic id.
+    pub async fn roles(&self, public_key: PublicKey, topic_id: [u8; 32]) -> NodeRoles {
+        let inner = self.inner.read().await;
+        inner
+            .known_peer_roles
+            .get(&public_key)
+            .and_then(|peer_roles| peer_roles.get(&topic_id))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Set the filter of blobs a peer provides for a topic id, overwriting previously known ones.
+    pub async fn set_blob_filter(
+        &mut self,
+        public_key: PublicKey,
+        topic_id: [u8; 32],
+        filter: Option<BlobFilter>,
+    ) {
+        let mut inner = self.inner.write().await;
+        let peer_blobs = inner.known_peer_blobs.entry(public_key).or_default();
+        match filter {
+            Some(filter) if !filter.is_empty() => {
+                peer_blobs.insert(topic_id, filter);
+            }
+            _ => {
+                peer_blobs.remove(&topic_id);
+            }
+        }
+    }
+
+    /// Return all peers which likely provide the blob with the given hash in any topic.
+    ///
+    /// Peers are ordered by the roles they advertised for the topics they provide the blob in,
+    /// dedicated blob providers and archives come first.
+    pub async fn blob_providers(&self, hash: &Hash) -> Vec<PublicKey> {
+        let inner = self.inner.read().await;
+        let mut providers: Vec<(u8, PublicKey)> = inner
+            .known_peer_blobs
+            .iter()
+            .filter_map(|(public_key, filters)| {
+                let topic_ids: Vec<&[u8; 32]> = filters
+                    .iter()
+                    .filter(|(_, filter)| filter.contains(hash))
+                    .map(|(topic_id, _)| topic_id)
+                    .collect();
+                if topic_ids.is_empty() {
+                    return None;
+                }
+
+                let mut roles = NodeRoles::new();
+                if let Some(peer_roles) = inner.known_peer_roles.get(public_key) {
+                    for topic_id in topic_ids {
+                        if let Some(topic_roles) = peer_roles.get(topic_id) {
+                            roles.extend(topic_roles.iter().copied());
+                        }
+                    }
+                }
+                Some((blob_provider_rank(&roles), *public_key))
+            })
+            .collect();
+        providers.sort_by_key(|(rank, _)| *rank);
+        providers
+            .into_iter()
+            .map(|(_, public_key)| public_key)
+            .collect()
+    }
+
     /// Return list of all currently known peer addresses.
     pub async fn known_peers(&self) -> Vec<NodeAddress> {
         let inner = self.inner.read().await;
@@ -81,20 +177,24 @@ impl AddressBook {
             .collect()
     }
 
-    /// Return random set of known peers with an interest in the given topic.
-    pub async fn random_set(&self, topic_id: [u8; 32], sample_len: usize) -> Vec<PublicKey> {
+    /// Return all known peers with an interest in the given topic.
+    pub async fn topic_peers(&self, topic_id: [u8; 32]) -> Vec<PublicKey> {
         let inner = self.inner.read().await;
 
-        let nodes_interested_in_topic =
-            inner
-                .known_peer_topic_ids
-                .iter()
-                .fold(Vec::new(), |mut acc, (node_id, topics)| {
-                    if topics.contains(&topic_id) {
-                        acc.push(*node_id);
-                    }
-                    acc
-                });
+        inner
+            .known_peer_topic_ids
+            .iter()
+            .fold(Vec::new(), |mut acc, (node_id, topics)| {
+                if topics.contains(&topic_id) {
+                    acc.push(*node_id);
+                }
+                acc
+            })
+    }
+
+    /// Return random set of known peers with an interest in the given topic.
+    pub async fn random_set(&self, topic_id: [u8; 32], sample_len: usize) -> Vec<PublicKey> {
+        let nodes_interested_in_topic = self.topic_peers(topic_id).await;
 
         nodes_interested_in_topic
             .iter()
@@ -109,9 +209,11 @@ impl AddressBook {
 mod tests {
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
-    use p2panda_core::PrivateKey;
+    use p2panda_core::{Hash, PrivateKey};
 
     use crate::NodeAddress;
+    use crate::providers::BlobFilter;
+    use crate::roles::{NodeRole, NodeRoles};
 
     use super::AddressBook;
 
@@ -147,4 +249,73 @@ mod tests {
         let known_peers = address_book.known_peers().await;
         assert_eq!(known_peers.len(), 2);
     }
+
+    #[tokio::test]
+    async fn overwrite_roles() {
+        let public_key = PrivateKey::new().public_key();
+        let topic_id = [1; 32];
+
+        let mut address_book = AddressBook::new([3; 32]);
+        assert!(address_book.roles(public_key, topic_id).await.is_empty());
+
+        let roles = NodeRoles::from([NodeRole::Archive, NodeRole::BlobProvider]);
+        address_book
+            .set_roles(public_key, topic_id, roles.clone())
+            .await;
+        assert_eq!(address_book.roles(public_key, topic_id).await, roles);
+
+        // Peers which stop advertising roles are forgotten.
+        address_book
+            .set_roles(public_key, topic_id, NodeRoles::new())
+            .await;
+        assert!(address_book.roles(public_key, topic_id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn find_blob_providers() {
+        let provider = PrivateKey::new().public_key();
+        let other_provider = PrivateKey::new().public_key();
+        let blob = Hash::new("blob");
+
+        let mut address_book = AddressBook::new([3; 32]);
+        address_book
+            .set_blob_filter(provider, [1; 32], Some(BlobFilter::new([&blob])))
+            .await;
+        address_book
+            .set_blob_filter(
+                other_provider,
+                [2; 32],
+                Some(BlobFilter::new([&Hash::new("other blob")])),
+            )
+            .await;
+        assert_eq!(address_book.blob_providers(&blob).await, vec![provider]);
+
+        // Peers which stop announcing blobs are forgotten.
+        address_book.set_blob_filter(provider, [1; 32], None).await;
+        assert!(address_book.blob_providers(&blob).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn prefer_blob_providers_by_role() {
+        let live_only = PrivateKey::new().public_key();
+        let archive = PrivateKey::new().public_key();
+        let blob = Hash::new("blob");
+
+        let mut address_book = AddressBook::new([3; 32]);
+        for public_key in [live_only, archive] {
+            address_book
+                .set_blob_filter(public_key, [1; 32], Some(BlobFilter::new([&blob])))
+                .await;
+        }
+        address_book
+            .set_roles(live_only, [1; 32], NodeRoles::from([NodeRole::LiveOnly]))
+            .await;
+        address_book
+            .set_roles(archive, [1; 32], NodeRoles::from([NodeRole::Archive]))
+            .await;
+        assert_eq!(
+            address_book.blob_providers(&blob).await,
+            vec![archive, live_only]
+        );
+    }
 }
@@ -0,0 +1,411 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Splitting of gossip messages exceeding the maximum message size into chunks.
+//!
+//! Gossip overlays reject messages larger than the configured maximum message size. Larger
+//! messages are split into chunks which fit into single gossip messages and reassembled by the
+//! receiving peers before they are handed to the application.
+//!
+//! Every chunk is prefixed with a magic byte sequence, followed by the public key of the author,
+//! the hash of the complete message, the index of the chunk, the total number of chunks and a
+//! signature of the author over the topic id, these fields and the hash of the chunk payload.
+//! Chunks are forwarded by other peers of the overlay, the signature makes sure that they can't be
+//! forged or mixed into messages of other authors. Reassembled messages are checked against their
+//! hash.
+//!
+//! Messages which fit into a single gossip message are sent unchanged, unless they happen to start
+//! with the magic byte sequence themselves. These are sent as a single chunk to keep them
+//! distinguishable.
+use std::collections::HashMap;
+
+use p2panda_core::{Hash, PrivateKey, PublicKey, Signature};
+use thiserror::Error;
+use tokio::time::{Duration, Instant};
+
+/// Bytes reserved for the framing of gossip messages in the overlay.
+const GOSSIP_FRAMING_LEN: usize = 128;
+
+/// Magic byte sequence every chunk starts with.
+const CHUNK_MAGIC: &[u8] = b"p2panda-chunk";
+
+/// Length of the chunk header: magic, author, message hash, index, count and signature.
+const CHUNK_HEADER_LEN: usize = CHUNK_MAGIC.len() + 32 + 32 + 2 + 2 + 64;
+
+/// Duration after which incomplete messages are dropped.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximum number of incomplete messages kept for reassembly, the oldest are dropped first.
+const MAX_PENDING_MESSAGES: usize = 128;
+
+/// Message exceeds the maximum size of chunked messages.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("message of {size} bytes exceeds maximum size of {max_size} bytes")]
+pub struct MessageTooLarge {
+    pub size: usize,
+    pub max_size: usize,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ChunkError {
+    #[error(transparent)]
+    MessageTooLarge(#[from] MessageTooLarge),
+
+    #[error("invalid chunk header")]
+    InvalidHeader,
+
+    #[error("invalid chunk signature")]
+    InvalidSignature,
+
+    #[error("reassembled message does not match its hash")]
+    InvalidHash,
+}
+
+/// Splits outgoing gossip messages into signed chunks if they exceed the maximum message size.
+#[derive(Debug)]
+pub struct Chunker {
+    private_key: PrivateKey,
+    max_gossip_message_size: usize,
+    max_message_size: usize,
+}
+
+impl Chunker {
+    /// Returns a chunker for gossip overlays with the given maximum message size, splitting
+    /// messages up to `max_message_size` bytes.
+    pub fn new(
+        private_key: PrivateKey,
+        max_gossip_message_size: usize,
+        max_message_size: usize,
+    ) -> Self {
+        Self {
+            private_key,
+            max_gossip_message_size,
+            max_message_size,
+        }
+    }
+
+    /// Returns the gossip messages to broadcast for the given message.
+    pub fn split(
+        &self,
+        topic_id: [u8; 32],
+        bytes: Vec<u8>,
+    ) -> Result<Vec<Vec<u8>>, MessageTooLarge> {
+        let max_size = self
+            .max_gossip_message_size
+            .saturating_sub(GOSSIP_FRAMING_LEN);
+        if bytes.len() <= max_size && !bytes.starts_with(CHUNK_MAGIC) {
+            return Ok(vec![bytes]);
+        }
+
+        let too_large = MessageTooLarge {
+            size: bytes.len(),
+            max_size: self.max_message_size,
+        };
+        if bytes.len() > self.max_message_size {
+            return Err(too_large);
+        }
+
+        let chunk_size = max_size.saturating_sub(CHUNK_HEADER_LEN).max(1);
+        let count = u16::try_from(bytes.len().div_ceil(chunk_size)).or(Err(too_large))?;
+        let message_hash = Hash::new(&bytes);
+        let author = self.private_key.public_key();
+
+        let chunks = bytes
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(index, payload)| {
+                let index = index as u16;
+                let signature = self.private_key.sign(&signed_bytes(
+                    topic_id,
+                    &author,
+                    &message_hash,
+                    index,
+                    count,
+                    payload,
+                ));
+
+                let mut chunk = Vec::with_capacity(CHUNK_HEADER_LEN + payload.len());
+                chunk.extend_from_slice(CHUNK_MAGIC);
+                chunk.extend_from_slice(author.as_bytes());
+                chunk.extend_from_slice(message_hash.as_bytes());
+                chunk.extend_from_slice(&index.to_be_bytes());
+                chunk.extend_from_slice(&count.to_be_bytes());
+                chunk.extend_from_slice(&signature.to_bytes());
+                chunk.extend_from_slice(payload);
+                chunk
+            })
+            .collect();
+
+        Ok(chunks)
+    }
+}
+
+/// Bytes covered by the signature of a chunk.
+fn signed_bytes(
+    topic_id: [u8; 32],
+    author: &PublicKey,
+    message_hash: &Hash,
+    index: u16,
+    count: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(32 * 4 + 4);
+    bytes.extend_from_slice(&topic_id);
+    bytes.extend_from_slice(author.as_bytes());
+    bytes.extend_from_slice(message_hash.as_bytes());
+    bytes.extend_from_slice(&index.to_be_bytes());
+    bytes.extend_from_slice(&count.to_be_bytes());
+    bytes.extend_from_slice(Hash::new(payload).as_bytes());
+    bytes
+}
+
+/// Chunk received from a gossip overlay.
+struct Chunk<'a> {
+    author: PublicKey,
+    message_hash: Hash,
+    index: u16,
+    count: u16,
+    signature: Signature,
+    payload: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    /// Parses a chunk, returns `None` if the bytes are a regular message.
+    fn parse(bytes: &'a [u8]) -> Option<Result<Self, ChunkError>> {
+        let header = bytes.strip_prefix(CHUNK_MAGIC)?;
+        if header.len() < CHUNK_HEADER_LEN - CHUNK_MAGIC.len() {
+            return Some(Err(ChunkError::InvalidHeader));
+        }
+
+        let (author, rest) = header.split_at(32);
+        let (message_hash, rest) = rest.split_at(32);
+        let (index, rest) = rest.split_at(2);
+        let (count, rest) = rest.split_at(2);
+        let (signature, payload) = rest.split_at(64);
+
+        let Ok(author) = PublicKey::try_from(author) else {
+            return Some(Err(ChunkError::InvalidHeader));
+        };
+        let index = u16::from_be_bytes([index[0], index[1]]);
+        let count = u16::from_be_bytes([count[0], count[1]]);
+        if index >= count {
+            return Some(Err(ChunkError::InvalidHeader));
+        }
+
+        Some(Ok(Self {
+            author,
+            message_hash: Hash::try_from(message_hash).expect("hash has correct length"),
+            index,
+            count,
+            signature: Signature::try_from(signature).expect("signature has correct length"),
+            payload,
+        }))
+    }
+
+    fn verify(&self, topic_id: [u8; 32]) -> bool {
+        self.author.verify(
+            &signed_bytes(
+                topic_id,
+                &self.author,
+                &self.message_hash,
+                self.index,
+                self.count,
+                self.payload,
+            ),
+            &self.signature,
+        )
+    }
+}
+
+/// Incomplete message waiting for its remaining chunks.
+#[derive(Debug)]
+struct PendingMessage {
+    chunks: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    size: usize,
+    started: Instant,
+}
+
+/// Reassembles chunked messages received from gossip overlays.
+#[derive(Debug)]
+pub struct Reassembler {
+    max_message_size: usize,
+    pending: HashMap<([u8; 32], PublicKey, Hash), PendingMessage>,
+}
+
+impl Reassembler {
+    /// Returns a reassembler accepting messages up to `max_message_size` bytes.
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            max_message_size,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Handles a message received from the gossip overlay of the topic id.
+    ///
+    /// Returns regular messages right away and reassembled messages once their last chunk was
+    /// received, `None` if chunks are still missing.
+    pub fn on_message(
+        &mut self,
+        topic_id: [u8; 32],
+        bytes: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, ChunkError> {
+        let chunk = match Chunk::parse(&bytes) {
+            None => return Ok(Some(bytes)),
+            Some(chunk) => chunk?,
+        };
+        if !chunk.verify(topic_id) {
+            return Err(ChunkError::InvalidSignature);
+        }
+
+        self.evict_expired();
+
+        let key = (topic_id, chunk.author, chunk.message_hash);
+        if !self.pending.contains_key(&key) && self.pending.len() >= MAX_PENDING_MESSAGES {
+            self.evict_oldest();
+        }
+        let pending = self.pending.entry(key).or_insert_with(|| PendingMessage {
+            chunks: vec![None; chunk.count as usize],
+            missing: chunk.count as usize,
+            size: 0,
+            started: Instant::now(),
+        });
+        if pending.chunks.len() != chunk.count as usize {
+            return Err(ChunkError::InvalidHeader);
+        }
+
+        let slot = &mut pending.chunks[chunk.index as usize];
+        if slot.is_some() {
+            return Ok(None);
+        }
+        pending.size += chunk.payload.len();
+        if pending.size > self.max_message_size {
+            let size = pending.size;
+            self.pending.remove(&key);
+            return Err(MessageTooLarge {
+                size,
+                max_size: self.max_message_size,
+            }
+            .into());
+        }
+        *slot = Some(chunk.payload.to_vec());
+        pending.missing -= 1;
+        if pending.missing > 0 {
+            return Ok(None);
+        }
+
+        let pending = self.pending.remove(&key).expect("pending message exists");
+        let message: Vec<u8> = pending.chunks.into_iter().flatten().flatten().collect();
+        if Hash::new(&message) != key.2 {
+            return Err(ChunkError::InvalidHash);
+        }
+
+        Ok(Some(message))
+    }
+
+    fn evict_expired(&mut self) {
+        self.pending
+            .retain(|_, pending| pending.started.elapsed() < REASSEMBLY_TIMEOUT);
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .pending
+            .iter()
+            .min_by_key(|(_, pending)| pending.started)
+            .map(|(key, _)| *key);
+        if let Some(key) = oldest {
+            self.pending.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::PrivateKey;
+
+    use super::{CHUNK_MAGIC, ChunkError, Chunker, MessageTooLarge, Reassembler};
+
+    const TOPIC_ID: [u8; 32] = [1; 32];
+
+    #[test]
+    fn small_messages_are_unchanged() {
+        let chunker = Chunker::new(PrivateKey::new(), 1024, 64 * 1024);
+        let mut reassembler = Reassembler::new(64 * 1024);
+
+        let messages = chunker.split(TOPIC_ID, b"hello".to_vec()).unwrap();
+        assert_eq!(messages, vec![b"hello".to_vec()]);
+        assert_eq!(
+            reassembler.on_message(TOPIC_ID, messages[0].clone()),
+            Ok(Some(b"hello".to_vec()))
+        );
+    }
+
+    #[test]
+    fn split_and_reassemble() {
+        let chunker = Chunker::new(PrivateKey::new(), 1024, 64 * 1024);
+        let mut reassembler = Reassembler::new(64 * 1024);
+
+        let message: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        let chunks = chunker.split(TOPIC_ID, message.clone()).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 1024));
+
+        // Chunks can arrive in any order and more than once.
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest.iter().rev().chain(rest.iter()) {
+            assert_eq!(reassembler.on_message(TOPIC_ID, chunk.clone()), Ok(None));
+        }
+        assert_eq!(
+            reassembler.on_message(TOPIC_ID, last.clone()),
+            Ok(Some(message))
+        );
+    }
+
+    #[test]
+    fn escape_messages_starting_with_magic() {
+        let chunker = Chunker::new(PrivateKey::new(), 1024, 64 * 1024);
+        let mut reassembler = Reassembler::new(64 * 1024);
+
+        let message = [CHUNK_MAGIC, b" but not a chunk"].concat();
+        let chunks = chunker.split(TOPIC_ID, message.clone()).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_ne!(chunks[0], message);
+        assert_eq!(
+            reassembler.on_message(TOPIC_ID, chunks[0].clone()),
+            Ok(Some(message))
+        );
+    }
+
+    #[test]
+    fn reject_messages_exceeding_limit() {
+        let chunker = Chunker::new(PrivateKey::new(), 1024, 4096);
+        assert_eq!(
+            chunker.split(TOPIC_ID, vec![0; 5000]),
+            Err(MessageTooLarge {
+                size: 5000,
+                max_size: 4096
+            })
+        );
+    }
+
+    #[test]
+    fn reject_tampered_chunks() {
+        let chunker = Chunker::new(PrivateKey::new(), 1024, 64 * 1024);
+        let mut reassembler = Reassembler::new(64 * 1024);
+
+        let mut chunks = chunker.split(TOPIC_ID, vec![7; 5000]).unwrap();
+        let last = chunks[0].len() - 1;
+        chunks[0][last] ^= 1;
+        assert_eq!(
+            reassembler.on_message(TOPIC_ID, chunks[0].clone()),
+            Err(ChunkError::InvalidSignature)
+        );
+
+        // Chunks are only valid in the overlay they were sent to.
+        assert_eq!(
+            reassembler.on_message([2; 32], chunks[1].clone()),
+            Err(ChunkError::InvalidSignature)
+        );
+    }
+}
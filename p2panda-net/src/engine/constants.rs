@@ -16,3 +16,22 @@ pub const ANNOUNCE_TOPICS_INTERVAL: Duration = Duration::from_millis(2200);
 
 /// Frequency of attempts to join gossip overlays for application-defined topic ids.
 pub const JOIN_TOPICS_INTERVAL: Duration = Duration::from_millis(1200);
+
+/// Frequency of checks for peers which didn't send a presence heartbeat in time.
+pub const EXPIRE_PRESENCE_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Frequency of checks for partitioned gossip overlays.
+pub const CHECK_OVERLAY_HEALTH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Duration retained gossip messages of a topic are kept after its last subscription ended.
+pub const RETAINED_MESSAGES_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// Duration gossip neighbours of private topics have to prove access, before we disconnect from
+/// them.
+///
+/// Proofs are part of the topic announcements, which might arrive after a peer joined the overlay.
+pub const UNPROVEN_NEIGHBOR_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay between leaving a gossip overlay and joining it again, to give the gossip protocol time
+/// to disconnect from all previous neighbours.
+pub const REJOIN_DELAY: Duration = Duration::from_secs(1);
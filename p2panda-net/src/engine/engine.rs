@@ -1,27 +1,47 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
 use anyhow::{Context, Result};
 use futures_lite::FutureExt;
 use iroh::Endpoint;
+#[cfg(not(target_arch = "wasm32"))]
 use netwatch::netmon::Monitor;
-use p2panda_core::{PrivateKey, PublicKey};
-use p2panda_sync::TopicQuery;
+use p2panda_core::{Hash, PrivateKey, PublicKey};
+use p2panda_sync::{SyncFilter, TopicQuery};
 use tokio::sync::{broadcast, mpsc, oneshot};
-use tokio::time::interval;
+use tokio::time::{Instant, interval};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, instrument, warn};
 
 use crate::addrs::{from_node_addr, to_relay_url};
+use crate::config::PanicPolicy;
 use crate::engine::address_book::AddressBook;
 use crate::engine::constants::{
-    ANNOUNCE_TOPICS_INTERVAL, JOIN_NETWORK_INTERVAL, JOIN_TOPICS_INTERVAL,
+    ANNOUNCE_TOPICS_INTERVAL, CHECK_OVERLAY_HEALTH_INTERVAL, EXPIRE_PRESENCE_INTERVAL,
+    JOIN_NETWORK_INTERVAL, JOIN_PEERS_SAMPLE_LEN, JOIN_TOPICS_INTERVAL, UNPROVEN_NEIGHBOR_TIMEOUT,
 };
 use crate::engine::gossip::{GossipActor, ToGossipActor};
 use crate::engine::topic_discovery::TopicDiscovery;
 use crate::engine::topic_streams::TopicStreams;
 use crate::events::SystemEvent;
+use crate::faults::FaultInjector;
+use crate::mailbox::Mailbox;
 use crate::network::{FromNetwork, ToNetwork};
+use crate::overlay_health::{OverlayHealth, OverlayMonitor};
+use crate::presence::{
+    MAX_PRESENCE_STATUS_LEN, PeerPresence, PresenceChange, PresenceConfig, PresenceSet,
+};
+use crate::providers::BlobFilter;
+use crate::reputation::Reputation;
+use crate::roles::RolesConfig;
+use crate::supervisor::{ENGINE_SUBSYSTEM, GOSSIP_SUBSYSTEM, SYNC_SUBSYSTEM, Supervisor};
+use crate::sync::LogHeightsProvider;
 use crate::sync::manager::{SyncActor, ToSyncActor};
+use crate::telemetry::HexId;
+use crate::topic_auth::TopicAuthenticator;
+use crate::topic_tree::ChildTopics;
 use crate::{NetworkId, NodeAddress, TopicId, from_public_key, to_public_key};
 
 #[derive(Debug)]
@@ -35,12 +55,50 @@ pub enum ToEngineActor<T> {
     KnownPeers {
         reply: oneshot::Sender<Vec<NodeAddress>>,
     },
+    ProvideBlobs {
+        topic_id: [u8; 32],
+        filter: BlobFilter,
+    },
+    BlobProviders {
+        hash: Hash,
+        reply: oneshot::Sender<Vec<PublicKey>>,
+    },
+    SetPresenceStatus {
+        topic_id: [u8; 32],
+        status: Vec<u8>,
+    },
+    Presence {
+        topic_id: [u8; 32],
+        reply: oneshot::Sender<Vec<PeerPresence>>,
+    },
+    ChildTopics {
+        parent_id: [u8; 32],
+        reply: oneshot::Sender<Vec<[u8; 32]>>,
+    },
+    OverlayHealth {
+        topic_id: [u8; 32],
+        reply: oneshot::Sender<Option<OverlayHealth>>,
+    },
+    MailboxCollected {
+        mailbox: PublicKey,
+        collected: Mailbox,
+        reply: oneshot::Sender<bool>,
+    },
     SubscribeTopic {
         topic: T,
+        filter: SyncFilter,
         from_network_tx: mpsc::Sender<FromNetwork>,
         to_network_rx: mpsc::Receiver<ToNetwork>,
         gossip_ready_tx: oneshot::Sender<()>,
     },
+    ObserveTopic {
+        topic: T,
+        filter: SyncFilter,
+        from_network_tx: mpsc::Sender<FromNetwork>,
+    },
+    StopObserving {
+        topic: T,
+    },
     GossipJoined {
         topic_id: [u8; 32],
         peers: Vec<PublicKey>,
@@ -58,6 +116,15 @@ pub enum ToEngineActor<T> {
         delivered_from: PublicKey,
         topic_id: [u8; 32],
     },
+    GossipMessageTooLarge {
+        topic_id: [u8; 32],
+        size: usize,
+        max_size: usize,
+    },
+    SubsystemRestarted {
+        subsystem: &'static str,
+        attempt: u32,
+    },
     SyncStart {
         topic: Option<T>,
         peer: PublicKey,
@@ -89,14 +156,22 @@ pub enum ToEngineActor<T> {
 pub struct EngineActor<T> {
     private_key: PrivateKey,
     address_book: AddressBook,
+    child_topics: ChildTopics,
     endpoint: Endpoint,
+    faults: Option<FaultInjector>,
     gossip_actor_tx: mpsc::Sender<ToGossipActor>,
     inbox: mpsc::Receiver<ToEngineActor<T>>,
     network_id: NetworkId,
+    overlay_health: OverlayMonitor,
+    panic_policy: PanicPolicy,
+    presence: Option<PresenceSet>,
+    reputation: Reputation,
     sync_actor_tx: Option<mpsc::Sender<ToSyncActor<T>>>,
     system_event_tx: Option<broadcast::Sender<SystemEvent<T>>>,
     topic_discovery: TopicDiscovery,
+    topic_auth: Option<Arc<dyn TopicAuthenticator>>,
     topic_streams: TopicStreams<T>,
+    unproven_neighbors: HashMap<([u8; 32], PublicKey), Instant>,
 }
 
 impl<T> EngineActor<T>
@@ -111,63 +186,119 @@ where
         inbox: mpsc::Receiver<ToEngineActor<T>>,
         gossip_actor_tx: mpsc::Sender<ToGossipActor>,
         sync_actor_tx: Option<mpsc::Sender<ToSyncActor<T>>>,
+        delta_announcements: Option<Arc<dyn LogHeightsProvider<T>>>,
+        newest_first: bool,
+        retained_messages: usize,
         network_id: NetworkId,
         bootstrap: bool,
+        roles: RolesConfig,
+        presence: Option<PresenceConfig>,
+        topic_auth: Option<Arc<dyn TopicAuthenticator>>,
+        reputation: Reputation,
+        faults: Option<FaultInjector>,
+        panic_policy: PanicPolicy,
     ) -> Self {
         let topic_discovery = TopicDiscovery::new(
             network_id,
             gossip_actor_tx.clone(),
             address_book.clone(),
             bootstrap,
+            roles,
+            presence.is_some(),
+            topic_auth.clone(),
         );
         let topic_streams = TopicStreams::new(
             gossip_actor_tx.clone(),
             address_book.clone(),
+            private_key.clone(),
             sync_actor_tx.clone(),
+            delta_announcements,
+            topic_auth.clone(),
+            newest_first,
+            retained_messages,
         );
 
         Self {
             private_key,
             address_book,
+            child_topics: ChildTopics::new(),
             endpoint,
+            faults,
             gossip_actor_tx,
             inbox,
             network_id,
+            overlay_health: OverlayMonitor::new(),
+            panic_policy,
+            presence: presence.map(PresenceSet::new),
+            reputation,
             sync_actor_tx,
             system_event_tx: None,
             topic_discovery,
+            topic_auth,
             topic_streams,
+            unproven_neighbors: HashMap::new(),
         }
     }
 
     /// Runs the sync manager and gossip actor, sets up shutdown handlers and spawns the engine
     /// event loop.
+    ///
+    /// All three actors are supervised according to the panic policy. Restarts of the sync
+    /// manager and gossip actor are reported back to the engine actor over the given sender.
     pub async fn run(
         mut self,
         mut gossip_actor: GossipActor<T>,
         sync_actor: Option<SyncActor<T>>,
+        engine_actor_tx: mpsc::Sender<ToEngineActor<T>>,
     ) -> Result<()> {
         // Used to shutdown the sync manager.
         let shutdown_token = CancellationToken::new();
 
-        if let Some(sync_actor) = sync_actor {
+        if let Some(mut sync_actor) = sync_actor {
             let shutdown_token = shutdown_token.clone();
+            let engine_actor_tx = engine_actor_tx.clone();
+            let mut supervisor = Supervisor::new(SYNC_SUBSYSTEM, self.panic_policy);
             tokio::task::spawn(async move {
-                if let Err(err) = sync_actor.run(shutdown_token).await {
-                    error!("sync manager failed to run: {err:?}");
+                loop {
+                    match supervisor.run(sync_actor.run(shutdown_token.clone())).await {
+                        Some(Ok(())) => break,
+                        Some(Err(err)) => {
+                            error!("sync manager failed to run: {err:?}");
+                            break;
+                        }
+                        None => report_restart(&engine_actor_tx, &supervisor).await,
+                    }
                 }
             });
         }
 
+        let mut supervisor = Supervisor::new(GOSSIP_SUBSYSTEM, self.panic_policy);
         let gossip_handle = tokio::task::spawn(async move {
-            if let Err(err) = gossip_actor.run().await {
-                error!("gossip recv actor failed: {err:?}");
+            loop {
+                match supervisor.run(gossip_actor.run()).await {
+                    Some(Ok(())) => break,
+                    Some(Err(err)) => {
+                        error!("gossip recv actor failed: {err:?}");
+                        break;
+                    }
+                    None => report_restart(&engine_actor_tx, &supervisor).await,
+                }
             }
         });
 
         // Take oneshot sender from outside API awaited by `shutdown` call and fire it as soon as
         // shutdown completed.
-        let shutdown_completed_signal = self.run_inner().await;
+        let mut supervisor = Supervisor::new(ENGINE_SUBSYSTEM, self.panic_policy);
+        let shutdown_completed_signal = loop {
+            if let Some(result) = supervisor.run(self.run_inner()).await {
+                break result;
+            }
+            if let Err(err) =
+                self.subsystem_restarted(supervisor.subsystem(), supervisor.restarts())
+            {
+                error!(?err, "failed to report engine restart");
+            }
+        };
         if let Err(err) = self.shutdown().await {
             error!(?err, "error during shutdown");
         }
@@ -193,20 +324,30 @@ where
         let mut join_network_interval = interval(JOIN_NETWORK_INTERVAL);
         let mut join_topics_interval = interval(JOIN_TOPICS_INTERVAL);
         let mut announce_topics_interval = interval(ANNOUNCE_TOPICS_INTERVAL);
+        let mut expire_presence_interval = interval(EXPIRE_PRESENCE_INTERVAL);
+        let mut check_overlay_health_interval = interval(CHECK_OVERLAY_HEALTH_INTERVAL);
 
         // Setup network monitoring. This allows us to detect major interface changes and reset
         // topic discovery and sync state.
-        let network_monitor = Monitor::new().await?;
         let (interface_change_tx, mut interface_change_rx) = mpsc::channel(8);
-        let _token = network_monitor
-            .subscribe(move |is_major| {
-                let interface_change_tx = interface_change_tx.clone();
-                async move {
-                    interface_change_tx.send(is_major).await.ok();
-                }
-                .boxed()
-            })
-            .await?;
+        #[cfg(not(target_arch = "wasm32"))]
+        let (_network_monitor, _token) = {
+            let network_monitor = Monitor::new().await?;
+            let token = network_monitor
+                .subscribe(move |is_major| {
+                    let interface_change_tx = interface_change_tx.clone();
+                    async move {
+                        interface_change_tx.send(is_major).await.ok();
+                    }
+                    .boxed()
+                })
+                .await?;
+            (network_monitor, token)
+        };
+
+        // Browsers don't expose network interfaces, keep the channel open without any changes.
+        #[cfg(target_arch = "wasm32")]
+        let _interface_change_tx = interface_change_tx;
 
         loop {
             tokio::select! {
@@ -245,14 +386,26 @@ where
                 _ = join_network_interval.tick() => {
                     self.topic_discovery.start().await?;
                 },
-                // Attempt announcing our currently subscribed topics to other peers.
+                // Attempt announcing our currently subscribed topics to other peers, after removing
+                // subscriptions which ended.
                 _ = announce_topics_interval.tick() => {
+                    self.on_prune_subscriptions().await?;
                     let my_topic_ids = self.topic_streams.topic_ids();
                     self.topic_discovery.announce(my_topic_ids, &self.private_key).await?;
                 },
-                // Attempt joining the application's topic gossips if we haven't yet.
+                // Attempt joining the application's topic gossips if we haven't yet and disconnect
+                // from neighbours which didn't prove access to private topics.
                 _ = join_topics_interval.tick() => {
                     self.topic_streams.try_join_pending_gossips().await?;
+                    self.on_check_unproven_neighbors().await?;
+                },
+                // Remove peers from the presence set which didn't send a heartbeat in time.
+                _ = expire_presence_interval.tick(), if self.presence.is_some() => {
+                    self.on_expire_presence()?;
+                },
+                // Raise an alarm for gossip overlays which are suspected to be partitioned.
+                _ = check_overlay_health_interval.tick() => {
+                    self.on_check_overlay_health().await?;
                 },
             }
         }
@@ -260,6 +413,12 @@ where
 
     /// Processes a message received by the actor; these messages represent gossip and sync events.
     async fn on_actor_message(&mut self, msg: ToEngineActor<T>) -> Result<()> {
+        if let Some(faults) = &self.faults
+            && faults.take_engine_panic()
+        {
+            panic!("fault injector panicked the engine actor");
+        }
+
         match msg {
             ToEngineActor::AddPeer { node_addr } => {
                 self.add_peer(node_addr).await?;
@@ -268,18 +427,90 @@ where
                 let event_rx = self.events();
                 reply.send(event_rx).ok();
             }
+            ToEngineActor::SubsystemRestarted { subsystem, attempt } => {
+                self.subsystem_restarted(subsystem, attempt)?;
+            }
             ToEngineActor::KnownPeers { reply } => {
                 let list = self.address_book.known_peers().await;
                 reply.send(list).ok();
             }
+            ToEngineActor::ProvideBlobs { topic_id, filter } => {
+                self.topic_discovery.provide_blobs(topic_id, filter);
+
+                // Announce the changed blobs right away instead of waiting for the next interval.
+                let my_topic_ids = self.topic_streams.topic_ids();
+                self.topic_discovery
+                    .announce(my_topic_ids, &self.private_key)
+                    .await?;
+            }
+            ToEngineActor::BlobProviders { hash, reply } => {
+                let providers = self.address_book.blob_providers(&hash).await;
+                reply.send(providers).ok();
+            }
+            ToEngineActor::SetPresenceStatus { topic_id, status } => {
+                self.topic_discovery.set_presence_status(topic_id, status);
+
+                // Announce the changed status right away instead of waiting for the next interval.
+                let my_topic_ids = self.topic_streams.topic_ids();
+                self.topic_discovery
+                    .announce(my_topic_ids, &self.private_key)
+                    .await?;
+            }
+            ToEngineActor::Presence { topic_id, reply } => {
+                let peers = self
+                    .presence
+                    .as_ref()
+                    .map(|presence| presence.peers(&topic_id))
+                    .unwrap_or_default();
+                reply.send(peers).ok();
+            }
+            ToEngineActor::ChildTopics { parent_id, reply } => {
+                reply.send(self.child_topics.children(&parent_id)).ok();
+            }
+            ToEngineActor::OverlayHealth { topic_id, reply } => {
+                let health = match self.topic_streams.overlay_id(&topic_id) {
+                    Some(overlay_id) => {
+                        let known_peers = self.address_book.topic_peers(overlay_id).await;
+                        self.overlay_health
+                            .health(&overlay_id, &known_peers, Instant::now())
+                    }
+                    None => None,
+                };
+                reply.send(health).ok();
+            }
+            ToEngineActor::MailboxCollected {
+                mailbox,
+                collected,
+                reply,
+            } => {
+                let delivered = self.on_mailbox_collected(mailbox, collected)?;
+                reply.send(delivered).ok();
+            }
             ToEngineActor::SubscribeTopic {
                 topic,
+                filter,
                 from_network_tx,
                 to_network_rx,
                 gossip_ready_tx,
             } => {
-                self.on_subscribe(topic, from_network_tx, to_network_rx, gossip_ready_tx)
-                    .await?;
+                self.on_subscribe(
+                    topic,
+                    filter,
+                    from_network_tx,
+                    to_network_rx,
+                    gossip_ready_tx,
+                )
+                .await?;
+            }
+            ToEngineActor::ObserveTopic {
+                topic,
+                filter,
+                from_network_tx,
+            } => {
+                self.on_observe(topic, filter, from_network_tx).await?;
+            }
+            ToEngineActor::StopObserving { topic } => {
+                self.on_stop_observing(topic).await?;
             }
             ToEngineActor::GossipJoined { topic_id, peers } => {
                 self.on_gossip_joined(topic_id, peers).await?;
@@ -298,6 +529,19 @@ where
                 self.on_gossip_message(bytes, delivered_from, topic_id)
                     .await?;
             }
+            ToEngineActor::GossipMessageTooLarge {
+                topic_id,
+                size,
+                max_size,
+            } => {
+                if let Some(event_tx) = &self.system_event_tx {
+                    event_tx.send(SystemEvent::GossipMessageTooLarge {
+                        topic_id,
+                        size,
+                        max_size,
+                    })?;
+                }
+            }
             ToEngineActor::SyncStart { topic, peer } => {
                 self.on_sync_start(topic, peer).await?;
             }
@@ -367,12 +611,28 @@ where
         }
     }
 
+    /// Inform subscribers of system events about a restarted subsystem.
+    fn subsystem_restarted(&self, subsystem: &'static str, attempt: u32) -> Result<()> {
+        if let Some(event_tx) = &self.system_event_tx {
+            event_tx.send(SystemEvent::SubsystemRestarted { subsystem, attempt })?;
+        }
+        Ok(())
+    }
+
     /// Update the join status for the given gossip overlay.
+    #[instrument(level = "debug", skip_all, fields(topic_id = %HexId(&topic_id)))]
     async fn on_gossip_joined(&mut self, topic_id: [u8; 32], peers: Vec<PublicKey>) -> Result<()> {
         if topic_id == self.network_id {
             self.topic_discovery.on_gossip_joined();
         } else {
             self.topic_streams.on_gossip_joined(topic_id).await;
+
+            let now = Instant::now();
+            for peer in &peers {
+                if self.is_unproven(topic_id, *peer).await {
+                    self.unproven_neighbors.insert((topic_id, *peer), now);
+                }
+            }
         }
 
         if let Some(event_tx) = &self.system_event_tx {
@@ -386,8 +646,20 @@ where
     /// gossip overlay.
     ///
     /// Through this we can use gossip algorithms also as an additional "peer discovery" mechanism.
+    /// Neighbours of private topics are only registered after they proved access to the topic in
+    /// their announcements.
+    #[instrument(level = "debug", skip_all, fields(topic_id = %HexId(&topic_id), %peer))]
     async fn on_peer_connected(&mut self, topic_id: [u8; 32], peer: PublicKey) -> Result<()> {
+        if self.is_unproven(topic_id, peer).await {
+            debug!("neighbor did not prove access to private topic yet");
+            self.unproven_neighbors
+                .insert((topic_id, peer), Instant::now());
+            return Ok(());
+        }
+
         self.address_book.add_topic_id(peer, topic_id).await;
+        self.overlay_health
+            .on_neighbor_up(topic_id, peer, Instant::now());
 
         // At this point we only have the public key of the peer, which is not enough to establish
         // direct connections, luckily iroh has handled storing networking information for us
@@ -419,7 +691,15 @@ where
     }
 
     /// The given peer is no longer our direct neighbor in the gossip overlay.
+    #[instrument(level = "debug", skip_all, fields(topic_id = %HexId(&topic_id), %peer))]
     async fn on_peer_disconnected(&mut self, topic_id: [u8; 32], peer: PublicKey) -> Result<()> {
+        if self.unproven_neighbors.remove(&(topic_id, peer)).is_some() {
+            return Ok(());
+        }
+
+        self.overlay_health
+            .on_neighbor_down(topic_id, peer, Instant::now());
+
         // Notify any system event subscribers.
         if let Some(event_tx) = &self.system_event_tx {
             event_tx.send(SystemEvent::GossipNeighborDown { topic_id, peer })?;
@@ -428,27 +708,81 @@ where
         Ok(())
     }
 
+    /// Returns `true` if the peer did not prove access to the private topic of a gossip overlay.
+    async fn is_unproven(&self, topic_id: [u8; 32], peer: PublicKey) -> bool {
+        self.topic_auth
+            .as_ref()
+            .is_some_and(|topic_auth| topic_auth.is_private(&topic_id))
+            && !self.address_book.has_topic_id(peer, topic_id).await
+    }
+
+    /// Register neighbours of private topics which proved access in the meantime and disconnect
+    /// from the ones which didn't in time.
+    ///
+    /// iroh-gossip doesn't allow closing the connection to a single neighbour, instead we leave
+    /// the overlay and join it again through authorised peers only.
+    async fn on_check_unproven_neighbors(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let mut rejoin = HashSet::new();
+        for ((topic_id, peer), since) in self.unproven_neighbors.clone() {
+            if !self.is_unproven(topic_id, peer).await {
+                self.unproven_neighbors.remove(&(topic_id, peer));
+                self.on_peer_connected(topic_id, peer).await?;
+            } else if now.duration_since(since) > UNPROVEN_NEIGHBOR_TIMEOUT {
+                warn!(topic_id = %HexId(&topic_id), %peer, "disconnect neighbor without proof for private topic");
+                rejoin.insert(topic_id);
+            }
+        }
+
+        for topic_id in rejoin {
+            self.unproven_neighbors.retain(|(id, _), _| id != &topic_id);
+            let peers = self
+                .address_book
+                .random_set(topic_id, JOIN_PEERS_SAMPLE_LEN)
+                .await;
+            self.gossip_actor_tx
+                .send(ToGossipActor::Rejoin { topic_id, peers })
+                .await?;
+        }
+
+        Ok(())
+    }
+
     /// Handle a topic subscription.
     ///
     /// - Mark the given topic as being of interest to our node.
-    /// - Attempt to join a gossip overlay for the topic if one has not already been joined.
+    /// - Inform the sync manager about the filter to apply during sync sessions over this topic,
+    ///   combined with the filters of all other subscriptions to it.
+    /// - Attempt to join a gossip overlay for the topic if one has not already been joined, child
+    ///   topics share the overlay of their parent.
     /// - Broadcast messages to the gossip overlay.
     /// - Announce our topics of interest to the network.
     async fn on_subscribe(
         &mut self,
         topic: T,
+        filter: SyncFilter,
         from_network_tx: mpsc::Sender<FromNetwork>,
         to_network_rx: mpsc::Receiver<ToNetwork>,
         gossip_ready_tx: oneshot::Sender<()>,
     ) -> Result<()> {
+        // Child topics are announced together with their parent.
+        if let Some(parent_id) = topic.parent_id() {
+            self.topic_discovery.add_child_topic(topic.id(), parent_id);
+        }
+
         self.topic_streams
             .subscribe(
                 topic.clone(),
+                filter,
                 from_network_tx,
                 to_network_rx,
                 gossip_ready_tx,
             )
             .await?;
+        if let Some(overlay_id) = self.topic_streams.overlay_id(&topic.id()) {
+            self.overlay_health
+                .track(overlay_id, topic.id(), Instant::now());
+        }
 
         // Hot path: Announce our "topics of interest" into the network, hopefully this will speed
         // up finding other peers.
@@ -460,7 +794,52 @@ where
         Ok(())
     }
 
+    /// Remove subscriptions whose receivers were dropped.
+    ///
+    /// Gossip overlays are left and no longer monitored after their last subscription ended.
+    async fn on_prune_subscriptions(&mut self) -> Result<()> {
+        for overlay_id in self.topic_streams.prune_closed_streams().await? {
+            self.overlay_health.untrack(&overlay_id);
+            self.unproven_neighbors
+                .retain(|(id, _), _| id != &overlay_id);
+        }
+        Ok(())
+    }
+
+    /// Handle a read-only subscription.
+    ///
+    /// - Inform the sync manager about the filter to apply during sync sessions over this topic,
+    ///   combined with the filters of all other subscriptions to it.
+    /// - Receive data from peers announcing the topic via sync, without announcing the topic
+    ///   ourselves or joining its gossip overlay.
+    async fn on_observe(
+        &mut self,
+        topic: T,
+        filter: SyncFilter,
+        from_network_tx: mpsc::Sender<FromNetwork>,
+    ) -> Result<()> {
+        self.topic_streams
+            .observe(topic, filter, from_network_tx)
+            .await?;
+
+        Ok(())
+    }
+
+    /// End all read-only subscriptions to the given topic.
+    ///
+    /// Gossip overlays are left and no longer monitored if an observer was their last
+    /// subscription.
+    async fn on_stop_observing(&mut self, topic: T) -> Result<()> {
+        for overlay_id in self.topic_streams.stop_observing(&topic).await? {
+            self.overlay_health.untrack(&overlay_id);
+            self.unproven_neighbors
+                .retain(|(id, _), _| id != &overlay_id);
+        }
+        Ok(())
+    }
+
     /// Process sync session starting.
+    #[instrument(level = "debug", skip_all, fields(?topic, %peer))]
     pub async fn on_sync_start(&mut self, topic: Option<T>, peer: PublicKey) -> Result<()> {
         self.topic_streams.on_sync_start(topic.clone(), peer);
 
@@ -472,6 +851,7 @@ where
     }
 
     /// Process sync session finishing.
+    #[instrument(level = "debug", skip_all, fields(?topic, %peer))]
     pub async fn on_sync_done(&mut self, topic: T, peer: PublicKey) -> Result<()> {
         self.topic_streams.on_sync_done(topic.clone(), peer).await?;
 
@@ -484,6 +864,7 @@ where
     }
 
     /// Process sync session failure.
+    #[instrument(level = "debug", skip_all, fields(?topic, %peer))]
     pub async fn on_sync_failed(&mut self, topic: Option<T>, peer: PublicKey) -> Result<()> {
         self.topic_streams
             .on_sync_failed(topic.clone(), peer)
@@ -501,17 +882,40 @@ where
     /// If the message comes from the "network-wide" gossip overlay (determined by the "network
     /// id"), then it gets handled by the "topic discovery" mechanism. Otherwise it is from a regular,
     /// custom application-related gossip overlay around a "topic id".
+    #[instrument(
+        level = "trace",
+        skip_all,
+        fields(topic_id = %HexId(&topic_id), peer = %delivered_from)
+    )]
     async fn on_gossip_message(
         &mut self,
         bytes: Vec<u8>,
         delivered_from: PublicKey,
         topic_id: [u8; 32],
     ) -> Result<()> {
+        if let Some(faults) = &self.faults
+            && faults.drops_gossip(&delivered_from)
+        {
+            return Ok(());
+        }
+
+        // Gossip messages and announcements of banned peers are ignored.
+        if self.reputation.is_banned(&delivered_from) {
+            return Ok(());
+        }
+
         if topic_id == self.network_id {
             match self.topic_discovery.on_gossip_message(&bytes).await {
-                Ok((topic_ids, peer)) => {
+                Ok(announcement) => {
+                    let peer = announcement.public_key;
+                    if self.reputation.is_banned(&peer) {
+                        return Ok(());
+                    }
+
+                    self.on_presence_heartbeats(peer, announcement.presence)?;
+                    self.on_child_topics(announcement.parents)?;
                     self.topic_streams
-                        .on_discovered_topic_ids(topic_ids, peer)
+                        .on_discovered_topic_ids(announcement.topic_ids, peer)
                         .await?;
 
                     if let Some(event_tx) = &self.system_event_tx {
@@ -527,6 +931,21 @@ where
                 }
             }
         } else {
+            self.overlay_health.on_message(topic_id, Instant::now());
+
+            // Messages of child topics are received on the shared overlay of their parent.
+            let (topic_id, bytes) = match self.topic_streams.open_gossip_message(topic_id, bytes) {
+                Ok(Some(message)) => message,
+                Ok(None) => return Ok(()),
+                Err(err) => {
+                    warn!(
+                        "could not parse child topic message from {}: {}",
+                        delivered_from, err
+                    );
+                    return Ok(());
+                }
+            };
+
             self.topic_streams
                 .on_gossip_message(topic_id, bytes, delivered_from)
                 .await?;
@@ -535,6 +954,124 @@ where
         Ok(())
     }
 
+    /// Report messages and delivery receipts collected from a mailbox node as system events.
+    ///
+    /// Returns `false` if there are no receivers of system events, the collection should not be
+    /// acknowledged in this case, so the mailbox node keeps the messages until they can be handed
+    /// to the application.
+    fn on_mailbox_collected(&mut self, mailbox: PublicKey, collected: Mailbox) -> Result<bool> {
+        let Some(event_tx) = &self.system_event_tx else {
+            return Ok(false);
+        };
+        if event_tx.receiver_count() == 0 {
+            return Ok(false);
+        }
+
+        for message in collected.messages {
+            event_tx.send(SystemEvent::MailboxMessage { mailbox, message })?;
+        }
+        for receipt in collected.receipts {
+            event_tx.send(SystemEvent::MailboxMessageDelivered { mailbox, receipt })?;
+        }
+
+        Ok(true)
+    }
+
+    /// Process presence heartbeats announced by a peer for the topics we're subscribed to.
+    fn on_presence_heartbeats(
+        &mut self,
+        peer: PublicKey,
+        heartbeats: BTreeMap<[u8; 32], Vec<u8>>,
+    ) -> Result<()> {
+        let Some(presence) = &mut self.presence else {
+            return Ok(());
+        };
+
+        let my_topic_ids = self.topic_streams.topic_ids();
+        for (topic_id, status) in heartbeats {
+            if !my_topic_ids.contains(&topic_id) || status.len() > MAX_PRESENCE_STATUS_LEN {
+                continue;
+            }
+
+            let event = match presence.on_heartbeat(topic_id, peer, status.clone()) {
+                Some(PresenceChange::Online) => SystemEvent::PeerOnline {
+                    topic_id,
+                    peer,
+                    status,
+                },
+                Some(PresenceChange::StatusChanged) => SystemEvent::PeerStatusChanged {
+                    topic_id,
+                    peer,
+                    status,
+                },
+                None => continue,
+            };
+            if let Some(event_tx) = &self.system_event_tx {
+                event_tx.send(event)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Process child topics announced by a peer for the parent topics we're subscribed to.
+    fn on_child_topics(&mut self, parents: BTreeMap<[u8; 32], [u8; 32]>) -> Result<()> {
+        let my_topic_ids = self.topic_streams.topic_ids();
+        for (topic_id, parent_id) in parents {
+            if !my_topic_ids.contains(&parent_id) || !self.child_topics.insert(parent_id, topic_id)
+            {
+                continue;
+            }
+
+            if let Some(event_tx) = &self.system_event_tx {
+                event_tx.send(SystemEvent::ChildTopicDiscovered {
+                    parent_id,
+                    topic_id,
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the health of all subscribed gossip overlays and report suspected partitions.
+    async fn on_check_overlay_health(&mut self) -> Result<()> {
+        let now = Instant::now();
+        for overlay_id in self.overlay_health.overlay_ids() {
+            let known_peers = self.address_book.topic_peers(overlay_id).await;
+            let Some(health) = self.overlay_health.health(&overlay_id, &known_peers, now) else {
+                continue;
+            };
+
+            let topic_ids = self
+                .overlay_health
+                .set_alarm(&overlay_id, health.partition_suspected);
+            for topic_id in topic_ids {
+                warn!(topic_id = %HexId(&topic_id), "gossip overlay suspected to be partitioned");
+                if let Some(event_tx) = &self.system_event_tx {
+                    event_tx.send(SystemEvent::OverlayPartitionSuspected { topic_id })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Process peers which didn't send a presence heartbeat within the timeout.
+    fn on_expire_presence(&mut self) -> Result<()> {
+        let Some(presence) = &mut self.presence else {
+            return Ok(());
+        };
+
+        for (topic_id, peer) in presence.expire() {
+            if let Some(event_tx) = &self.system_event_tx {
+                event_tx.send(SystemEvent::PeerOffline { topic_id, peer })?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Shutdown the engine.
     async fn shutdown(&mut self) -> Result<()> {
         self.gossip_actor_tx
@@ -544,3 +1081,17 @@ where
         Ok(())
     }
 }
+
+/// Inform the engine actor about a restarted subsystem.
+async fn report_restart<T>(
+    engine_actor_tx: &mpsc::Sender<ToEngineActor<T>>,
+    supervisor: &Supervisor,
+) {
+    engine_actor_tx
+        .send(ToEngineActor::SubsystemRestarted {
+            subsystem: supervisor.subsystem(),
+            attempt: supervisor.restarts(),
+        })
+        .await
+        .ok();
+}
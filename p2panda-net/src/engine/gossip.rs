@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use futures_lite::StreamExt;
@@ -14,7 +15,10 @@ use tokio::task::JoinSet;
 use tokio_stream::StreamMap;
 use tracing::{error, warn};
 
+use crate::compression::{self, Compressor};
 use crate::engine::ToEngineActor;
+use crate::engine::chunking::{Chunker, MessageTooLarge, Reassembler};
+use crate::engine::constants::REJOIN_DELAY;
 use crate::{from_public_key, to_public_key};
 
 #[derive(Debug)]
@@ -27,10 +31,13 @@ pub enum ToGossipActor {
         topic_id: [u8; 32],
         peers: Vec<PublicKey>,
     },
-    #[allow(dead_code)]
     Leave {
         topic_id: [u8; 32],
     },
+    Rejoin {
+        topic_id: [u8; 32],
+        peers: Vec<PublicKey>,
+    },
     Reset,
     Shutdown,
 }
@@ -39,6 +46,8 @@ pub enum ToGossipActor {
 /// facilitates flows of messages into and out of individual gossip overlays.
 pub struct GossipActor<T> {
     bootstrap: bool,
+    chunker: Chunker,
+    compressor: Option<Compressor>,
     engine_actor_tx: mpsc::Sender<ToEngineActor<T>>,
     gossip: Gossip,
     gossip_events: StreamMap<[u8; 32], GossipReceiver>,
@@ -46,6 +55,7 @@ pub struct GossipActor<T> {
     inbox: mpsc::Receiver<ToGossipActor>,
     joined: HashSet<[u8; 32]>,
     pending_joins: JoinSet<([u8; 32], Result<GossipTopic, GossipError>)>,
+    reassembler: Reassembler,
     want_join: HashSet<[u8; 32]>,
 }
 
@@ -57,10 +67,15 @@ where
         bootstrap: bool,
         inbox: mpsc::Receiver<ToGossipActor>,
         gossip: Gossip,
+        chunker: Chunker,
+        reassembler: Reassembler,
+        compressor: Option<Compressor>,
         engine_actor_tx: mpsc::Sender<ToEngineActor<T>>,
     ) -> Self {
         Self {
             bootstrap,
+            chunker,
+            compressor,
             engine_actor_tx,
             gossip,
             gossip_events: Default::default(),
@@ -68,6 +83,7 @@ where
             inbox,
             joined: Default::default(),
             pending_joins: Default::default(),
+            reassembler,
             want_join: Default::default(),
         }
     }
@@ -110,11 +126,35 @@ where
         match msg {
             ToGossipActor::Broadcast { topic_id, bytes } => {
                 if let Some(gossip_tx) = self.gossip_senders.get(&topic_id) {
-                    if let Err(err) = gossip_tx.broadcast(bytes.into()).await {
-                        error!(
-                            topic_id = "{topic_id:?}",
-                            "failed to broadcast gossip msg: {}", err
-                        )
+                    let bytes = match &self.compressor {
+                        Some(compressor) => compressor.encode(&bytes),
+                        None => bytes,
+                    };
+
+                    // Messages exceeding the maximum gossip message size are split into chunks.
+                    let messages = match self.chunker.split(topic_id, bytes) {
+                        Ok(messages) => messages,
+                        Err(MessageTooLarge { size, max_size }) => {
+                            warn!(?topic_id, "dropped gossip msg of {size} bytes: too large");
+                            self.engine_actor_tx
+                                .send(ToEngineActor::GossipMessageTooLarge {
+                                    topic_id,
+                                    size,
+                                    max_size,
+                                })
+                                .await?;
+                            return Ok(true);
+                        }
+                    };
+
+                    for bytes in messages {
+                        if let Err(err) = gossip_tx.broadcast(bytes.into()).await {
+                            error!(
+                                topic_id = "{topic_id:?}",
+                                "failed to broadcast gossip msg: {}", err
+                            );
+                            break;
+                        }
                     }
                 }
             }
@@ -125,24 +165,14 @@ where
                     return Ok(true);
                 }
 
-                let gossip = self.gossip.clone();
-                let peers = peers
-                    .iter()
-                    .map(|key: &p2panda_core::PublicKey| from_public_key(*key))
-                    .collect();
-                let fut = async move {
-                    let stream = gossip.subscribe_and_join(topic_id.into(), peers).await;
-                    (topic_id, stream)
-                };
-
-                self.want_join.insert(topic_id);
-                self.pending_joins.spawn(fut);
+                self.join(topic_id, peers, None);
             }
-            ToGossipActor::Leave { topic_id } => {
-                // Quit the topic by dropping all handles to `GossipTopic` for the given topic id.
-                let _handle = self.gossip_events.remove(&topic_id);
-                self.joined.remove(&topic_id);
-                self.want_join.remove(&topic_id);
+            ToGossipActor::Leave { topic_id } => self.leave(topic_id),
+            ToGossipActor::Rejoin { topic_id, peers } => {
+                // Leaving disconnects us from all neighbours of the overlay, we wait a moment
+                // before joining again to not re-use the previous state of the topic.
+                self.leave(topic_id);
+                self.join(topic_id, peers, Some(REJOIN_DELAY));
             }
             ToGossipActor::Reset => self.want_join.clear(),
             ToGossipActor::Shutdown => {
@@ -156,6 +186,32 @@ where
         Ok(true)
     }
 
+    fn join(&mut self, topic_id: [u8; 32], peers: Vec<PublicKey>, delay: Option<Duration>) {
+        let gossip = self.gossip.clone();
+        let peers = peers
+            .iter()
+            .map(|key: &p2panda_core::PublicKey| from_public_key(*key))
+            .collect();
+        let fut = async move {
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+            let stream = gossip.subscribe_and_join(topic_id.into(), peers).await;
+            (topic_id, stream)
+        };
+
+        self.want_join.insert(topic_id);
+        self.pending_joins.spawn(fut);
+    }
+
+    fn leave(&mut self, topic_id: [u8; 32]) {
+        // Quit the topic by dropping all handles to `GossipTopic` for the given topic id.
+        let _handle = self.gossip_events.remove(&topic_id);
+        self.gossip_senders.remove(&topic_id);
+        self.joined.remove(&topic_id);
+        self.want_join.remove(&topic_id);
+    }
+
     async fn on_gossip_event(
         &mut self,
         event: Option<([u8; 32], Result<Event, GossipError>)>,
@@ -199,9 +255,28 @@ where
     ) -> Result<()> {
         match event {
             GossipEvent::Received(msg) => {
+                let bytes = match self.reassembler.on_message(topic_id, msg.content.into()) {
+                    Ok(Some(bytes)) => bytes,
+                    Ok(None) => return Ok(()),
+                    Err(err) => {
+                        warn!(?topic_id, "dropped gossip msg chunk: {}", err);
+                        return Ok(());
+                    }
+                };
+                let bytes = if self.compressor.is_some() {
+                    match compression::decode(&bytes) {
+                        Ok(bytes) => bytes,
+                        Err(err) => {
+                            warn!(?topic_id, "failed to decompress gossip msg: {}", err);
+                            return Ok(());
+                        }
+                    }
+                } else {
+                    bytes
+                };
                 self.engine_actor_tx
                     .send(ToEngineActor::GossipMessage {
-                        bytes: msg.content.into(),
+                        bytes,
                         delivered_from: to_public_key(msg.delivered_from),
                         topic_id,
                     })
@@ -9,9 +9,21 @@ use tracing::{debug, warn};
 pub struct GossipBuffer {
     buffers: HashMap<(PublicKey, [u8; 32]), Vec<Vec<u8>>>,
     counters: HashMap<(PublicKey, [u8; 32]), usize>,
+    disabled: bool,
 }
 
 impl GossipBuffer {
+    /// Returns a gossip buffer which never holds back messages.
+    ///
+    /// Locks are still tracked, but `buffer` never returns a buffer and draining it after the
+    /// last unlock returns no messages.
+    pub fn disabled() -> Self {
+        Self {
+            disabled: true,
+            ..Default::default()
+        }
+    }
+
     pub fn lock(&mut self, peer: PublicKey, topic_id: [u8; 32]) {
         let counter = self.counters.entry((peer, topic_id)).or_default();
         *counter += 1;
@@ -50,6 +62,9 @@ impl GossipBuffer {
     }
 
     pub fn buffer(&mut self, peer: PublicKey, topic_id: [u8; 32]) -> Option<&mut Vec<Vec<u8>>> {
+        if self.disabled {
+            return None;
+        }
         self.buffers.get_mut(&(peer, topic_id))
     }
 }
@@ -102,4 +117,16 @@ mod tests {
         let counter = buffer.counters.get(&(peer, unknown_topic_id));
         assert!(counter.is_none());
     }
+
+    #[tokio::test]
+    async fn disabled_buffer() {
+        let peer = PrivateKey::new().public_key();
+        let topic_id = [9; 32];
+
+        let mut buffer = GossipBuffer::disabled();
+        buffer.lock(peer, topic_id);
+        assert!(buffer.buffer(peer, topic_id).is_none());
+        assert_eq!(buffer.unlock(peer, topic_id), Some(0));
+        assert_eq!(buffer.drain(peer, topic_id), Some(vec![]));
+    }
 }
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 mod address_book;
+mod chunking;
 mod constants;
 #[allow(clippy::module_inception)]
 mod engine;
@@ -10,26 +11,38 @@ mod topic_discovery;
 mod topic_streams;
 
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use anyhow::Result;
 use futures_util::future::{MapErr, Shared};
 use futures_util::{FutureExt, TryFutureExt};
 use iroh::Endpoint;
 use iroh_gossip::net::Gossip;
-use p2panda_core::PrivateKey;
-use p2panda_sync::TopicQuery;
+use p2panda_core::{Hash, PrivateKey, PublicKey};
+use p2panda_sync::{SyncFilter, TopicQuery};
 use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio::task::JoinError;
 use tokio_util::task::AbortOnDropHandle;
 use tracing::{debug, error};
 
+use crate::compression::Compressor;
+use crate::config::{GossipConfig, MAX_RETAINED_MESSAGES, PanicPolicy};
 pub use crate::engine::address_book::AddressBook;
+use crate::engine::chunking::{Chunker, Reassembler};
 use crate::engine::engine::EngineActor;
 use crate::engine::gossip::GossipActor;
 use crate::events::SystemEvent;
+use crate::faults::FaultInjector;
+use crate::mailbox::Mailbox;
 use crate::network::{FromNetwork, JoinErrToStr, ToNetwork};
+use crate::overlay_health::OverlayHealth;
+use crate::presence::{PeerPresence, PresenceConfig};
+use crate::providers::BlobFilter;
+use crate::reputation::Reputation;
+use crate::roles::RolesConfig;
 use crate::sync::manager::SyncActor;
 use crate::sync::{SyncConfiguration, SyncConnection};
+use crate::topic_auth::TopicAuthenticator;
 use crate::{NetworkId, NodeAddress, TopicId};
 pub use engine::ToEngineActor;
 
@@ -47,13 +60,22 @@ impl<T> Engine<T>
 where
     T: TopicQuery + TopicId + 'static,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         bootstrap: bool,
         private_key: PrivateKey,
         network_id: NetworkId,
         endpoint: Endpoint,
         gossip: Gossip,
+        gossip_config: GossipConfig,
         sync_config: Option<SyncConfiguration<T>>,
+        roles: RolesConfig,
+        presence: Option<PresenceConfig>,
+        topic_auth: Option<Arc<dyn TopicAuthenticator>>,
+        reputation: Reputation,
+        faults: Option<FaultInjector>,
+        gossip_compressor: Option<Compressor>,
+        panic_policy: PanicPolicy,
     ) -> Self {
         let address_book = AddressBook::new(network_id);
 
@@ -71,6 +93,20 @@ where
             (None, None)
         };
 
+        let delta_announcements = sync_config
+            .as_ref()
+            .and_then(|sync_config| sync_config.delta_announcements.clone());
+        let newest_first = sync_config
+            .as_ref()
+            .is_some_and(|sync_config| sync_config.newest_first());
+
+        let chunker = Chunker::new(
+            private_key.clone(),
+            gossip_config.max_message_size,
+            gossip_config.max_chunked_message_size,
+        );
+        let reassembler = Reassembler::new(gossip_config.max_chunked_message_size);
+
         let engine_actor = EngineActor::new(
             private_key,
             endpoint,
@@ -78,14 +114,34 @@ where
             engine_actor_rx,
             gossip_actor_tx,
             sync_actor_tx,
+            delta_announcements,
+            newest_first,
+            gossip_config.retained_messages.min(MAX_RETAINED_MESSAGES),
             network_id,
             bootstrap,
+            roles,
+            presence,
+            topic_auth,
+            reputation,
+            faults,
+            panic_policy,
+        );
+        let gossip_actor = GossipActor::new(
+            bootstrap,
+            gossip_actor_rx,
+            gossip,
+            chunker,
+            reassembler,
+            gossip_compressor,
+            engine_actor_tx.clone(),
         );
-        let gossip_actor =
-            GossipActor::new(bootstrap, gossip_actor_rx, gossip, engine_actor_tx.clone());
 
+        let actor_engine_actor_tx = engine_actor_tx.clone();
         let actor_handle = tokio::task::spawn(async move {
-            if let Err(err) = engine_actor.run(gossip_actor, sync_actor).await {
+            if let Err(err) = engine_actor
+                .run(gossip_actor, sync_actor, actor_engine_actor_tx)
+                .await
+            {
                 error!("engine actor failed: {err:?}");
             }
         });
@@ -115,6 +171,14 @@ where
         Ok(())
     }
 
+    /// Informs the engine about a restarted subsystem to report it as a system event.
+    pub async fn subsystem_restarted(&self, subsystem: &'static str, attempt: u32) -> Result<()> {
+        self.engine_actor_tx
+            .send(ToEngineActor::SubsystemRestarted { subsystem, attempt })
+            .await?;
+        Ok(())
+    }
+
     /// Returns a receiver for system events.
     pub async fn events(&self) -> Result<broadcast::Receiver<SystemEvent<T>>> {
         let (reply, reply_rx) = oneshot::channel();
@@ -133,10 +197,82 @@ where
         Ok(reply_rx.await?)
     }
 
+    /// Sets the filter of blobs we provide for the given topic id and announces it to other peers.
+    pub async fn provide_blobs(&self, topic_id: [u8; 32], filter: BlobFilter) -> Result<()> {
+        self.engine_actor_tx
+            .send(ToEngineActor::ProvideBlobs { topic_id, filter })
+            .await?;
+        Ok(())
+    }
+
+    /// Retrieves all peers which announced that they likely provide the blob with the given hash.
+    pub async fn blob_providers(&self, hash: Hash) -> Result<Vec<PublicKey>> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.engine_actor_tx
+            .send(ToEngineActor::BlobProviders { hash, reply })
+            .await?;
+        Ok(reply_rx.await?)
+    }
+
+    /// Sets the presence status announced for the given topic id.
+    pub async fn set_presence_status(&self, topic_id: [u8; 32], status: Vec<u8>) -> Result<()> {
+        self.engine_actor_tx
+            .send(ToEngineActor::SetPresenceStatus { topic_id, status })
+            .await?;
+        Ok(())
+    }
+
+    /// Retrieves all peers currently present on the given topic id.
+    pub async fn presence(&self, topic_id: [u8; 32]) -> Result<Vec<PeerPresence>> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.engine_actor_tx
+            .send(ToEngineActor::Presence { topic_id, reply })
+            .await?;
+        Ok(reply_rx.await?)
+    }
+
+    /// Retrieves the health of the gossip overlay of the given topic id, `None` if we're not
+    /// subscribed to it.
+    pub async fn overlay_health(&self, topic_id: [u8; 32]) -> Result<Option<OverlayHealth>> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.engine_actor_tx
+            .send(ToEngineActor::OverlayHealth { topic_id, reply })
+            .await?;
+        Ok(reply_rx.await?)
+    }
+
+    /// Retrieves all known child topic ids of the given parent topic id.
+    pub async fn child_topics(&self, parent_id: [u8; 32]) -> Result<Vec<[u8; 32]>> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.engine_actor_tx
+            .send(ToEngineActor::ChildTopics { parent_id, reply })
+            .await?;
+        Ok(reply_rx.await?)
+    }
+
+    /// Hands messages and delivery receipts collected from a mailbox node to the application.
+    ///
+    /// Returns `true` if they were reported as system events and can be acknowledged.
+    pub async fn mailbox_collected(&self, mailbox: PublicKey, collected: Mailbox) -> Result<bool> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.engine_actor_tx
+            .send(ToEngineActor::MailboxCollected {
+                mailbox,
+                collected,
+                reply,
+            })
+            .await?;
+        Ok(reply_rx.await?)
+    }
+
     /// Subscribes to the given topic and provides a channel for network message passing.
+    ///
+    /// The filter is used to narrow down the data requested from peers during sync sessions over
+    /// this topic.
     pub async fn subscribe(
         &self,
         topic: T,
+        filter: SyncFilter,
         from_network_tx: mpsc::Sender<FromNetwork>,
         to_network_rx: mpsc::Receiver<ToNetwork>,
         gossip_ready_tx: oneshot::Sender<()>,
@@ -144,6 +280,7 @@ where
         self.engine_actor_tx
             .send(ToEngineActor::SubscribeTopic {
                 topic,
+                filter,
                 from_network_tx,
                 to_network_rx,
                 gossip_ready_tx,
@@ -152,6 +289,31 @@ where
         Ok(())
     }
 
+    /// Observes a topic, receiving its data via sync only.
+    pub async fn observe(
+        &self,
+        topic: T,
+        filter: SyncFilter,
+        from_network_tx: mpsc::Sender<FromNetwork>,
+    ) -> Result<()> {
+        self.engine_actor_tx
+            .send(ToEngineActor::ObserveTopic {
+                topic,
+                filter,
+                from_network_tx,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Stops observing a topic, removing all of its read-only streams.
+    pub async fn stop_observing(&self, topic: T) -> Result<()> {
+        self.engine_actor_tx
+            .send(ToEngineActor::StopObserving { topic })
+            .await?;
+        Ok(())
+    }
+
     /// Sends a shutdown signal to the engine actor and waits for a confirmation reply.
     pub async fn shutdown(&self) -> Result<()> {
         let (reply, reply_rx) = oneshot::channel();
@@ -167,8 +329,8 @@ where
     // @TODO: This method feels like the odd-one-out in this module. Could we move it somewhere
     // else?
     pub(super) fn sync_handler(&self) -> Option<SyncConnection<T>> {
-        self.sync_config.as_ref().map(|sync_config| {
-            SyncConnection::new(sync_config.protocol(), self.engine_actor_tx.clone())
-        })
+        self.sync_config
+            .as_ref()
+            .map(|sync_config| SyncConnection::new(sync_config, self.engine_actor_tx.clone()))
     }
 }
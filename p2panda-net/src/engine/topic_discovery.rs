@@ -1,5 +1,8 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
 use anyhow::{Context, Result, bail};
 use p2panda_core::{PrivateKey, PublicKey, Signature};
 use rand::random;
@@ -11,6 +14,10 @@ use crate::bytes::{FromBytes, ToBytes};
 use crate::engine::address_book::AddressBook;
 use crate::engine::constants::JOIN_PEERS_SAMPLE_LEN;
 use crate::engine::gossip::ToGossipActor;
+use crate::providers::BlobFilter;
+use crate::roles::{NodeRole, NodeRoles, RolesConfig};
+use crate::topic_auth::TopicAuthenticator;
+use crate::topic_tree::child_overlay_id;
 
 #[derive(Debug, Default, PartialEq, Eq)]
 enum Status {
@@ -34,10 +41,25 @@ enum Status {
 // `Discovery` trait (for peer discovery), adjusted to work with topics.
 pub struct TopicDiscovery {
     address_book: AddressBook,
+    blob_filters: HashMap<[u8; 32], BlobFilter>,
     bootstrap: bool,
     gossip_actor_tx: mpsc::Sender<ToGossipActor>,
     network_id: NetworkId,
+    parents: HashMap<[u8; 32], [u8; 32]>,
+    presence: Option<HashMap<[u8; 32], Vec<u8>>>,
+    roles: RolesConfig,
     status: Status,
+    topic_auth: Option<Arc<dyn TopicAuthenticator>>,
+}
+
+/// Announcement received from another peer.
+///
+/// Private topics the peer did not prove access to are left out.
+pub struct Announcement {
+    pub topic_ids: Vec<[u8; 32]>,
+    pub public_key: PublicKey,
+    pub presence: BTreeMap<[u8; 32], Vec<u8>>,
+    pub parents: BTreeMap<[u8; 32], [u8; 32]>,
 }
 
 impl TopicDiscovery {
@@ -46,13 +68,21 @@ impl TopicDiscovery {
         gossip_actor_tx: mpsc::Sender<ToGossipActor>,
         address_book: AddressBook,
         bootstrap: bool,
+        roles: RolesConfig,
+        presence: bool,
+        topic_auth: Option<Arc<dyn TopicAuthenticator>>,
     ) -> Self {
         Self {
             address_book,
+            blob_filters: HashMap::new(),
             bootstrap,
             gossip_actor_tx,
             network_id,
+            parents: HashMap::new(),
+            presence: presence.then(HashMap::new),
+            roles,
             status: Status::default(),
+            topic_auth,
         }
     }
 
@@ -93,6 +123,30 @@ impl TopicDiscovery {
         self.status = Status::Idle;
     }
 
+    /// Sets the filter of blobs we provide for a topic id, announced with our next topic
+    /// announcement.
+    ///
+    /// An empty filter stops announcing blobs for this topic.
+    pub fn provide_blobs(&mut self, topic_id: [u8; 32], filter: BlobFilter) {
+        if filter.is_empty() {
+            self.blob_filters.remove(&topic_id);
+        } else {
+            self.blob_filters.insert(topic_id, filter);
+        }
+    }
+
+    /// Sets the presence status we announce for a topic id, if presence is enabled.
+    pub fn set_presence_status(&mut self, topic_id: [u8; 32], status: Vec<u8>) {
+        if let Some(presence) = &mut self.presence {
+            presence.insert(topic_id, status);
+        }
+    }
+
+    /// Registers a subscribed child topic, announced together with its parent topic id.
+    pub fn add_child_topic(&mut self, topic_id: [u8; 32], parent_id: [u8; 32]) {
+        self.parents.insert(topic_id, parent_id);
+    }
+
     pub fn on_gossip_joined(&mut self) {
         if self.status == Status::Active {
             return;
@@ -105,7 +159,7 @@ impl TopicDiscovery {
         self.status = Status::Active;
     }
 
-    pub async fn on_gossip_message(&mut self, bytes: &[u8]) -> Result<(Vec<[u8; 32]>, PublicKey)> {
+    pub async fn on_gossip_message(&mut self, bytes: &[u8]) -> Result<Announcement> {
         let topic_discovery_message =
             TopicDiscoveryMessage::from_bytes(bytes).context("decode topic discovery message")?;
         if !topic_discovery_message.verify() {
@@ -113,10 +167,65 @@ impl TopicDiscovery {
         }
 
         let public_key = topic_discovery_message.public_key();
-        for topic_id in &topic_discovery_message.topic_ids {
+
+        // Ignore the peer on private topics it did not prove access to.
+        let topic_ids: Vec<[u8; 32]> = topic_discovery_message
+            .topic_ids
+            .iter()
+            .filter(|topic_id| self.is_authorised(&topic_discovery_message, topic_id))
+            .copied()
+            .collect();
+        let mut presence = topic_discovery_message.presence;
+        presence.retain(|topic_id, _| topic_ids.contains(topic_id));
+        let mut parents = topic_discovery_message.parents;
+        parents.retain(|topic_id, _| topic_ids.contains(topic_id));
+
+        // Child topics share the gossip overlay of their parent, the peer can help us joining it.
+        for parent_id in parents.values() {
+            self.address_book
+                .add_topic_id(public_key, child_overlay_id(parent_id))
+                .await;
+        }
+
+        for topic_id in &topic_ids {
             self.address_book.add_topic_id(public_key, *topic_id).await;
+
+            // Peers might have changed their roles since their last announcement, this is why we
+            // always overwrite them, even if none were given.
+            let roles = topic_discovery_message
+                .roles
+                .get(topic_id)
+                .cloned()
+                .unwrap_or_default();
+            self.address_book
+                .set_roles(public_key, *topic_id, roles)
+                .await;
+
+            let filter = topic_discovery_message.blobs.get(topic_id).cloned();
+            self.address_book
+                .set_blob_filter(public_key, *topic_id, filter)
+                .await;
         }
-        Ok((topic_discovery_message.topic_ids, public_key))
+        Ok(Announcement {
+            topic_ids,
+            public_key,
+            presence,
+            parents,
+        })
+    }
+
+    /// Returns `true` if the topic is public or the announcing peer proved access to it.
+    fn is_authorised(&self, message: &TopicDiscoveryMessage, topic_id: &[u8; 32]) -> bool {
+        let Some(topic_auth) = &self.topic_auth else {
+            return true;
+        };
+        if !topic_auth.is_private(topic_id) {
+            return true;
+        }
+        message
+            .proofs
+            .get(topic_id)
+            .is_some_and(|proof| topic_auth.verify(topic_id, &message.public_key, proof))
     }
 
     pub async fn announce(&self, topic_ids: Vec<[u8; 32]>, private_key: &PrivateKey) -> Result<()> {
@@ -124,7 +233,59 @@ impl TopicDiscovery {
             return Ok(());
         }
 
-        let message = TopicDiscoveryMessage::new(topic_ids, private_key);
+        let roles = topic_ids
+            .iter()
+            .filter_map(|topic_id| {
+                let mut roles = self.roles.for_topic(topic_id);
+                if self.blob_filters.contains_key(topic_id) {
+                    roles.insert(NodeRole::BlobProvider);
+                }
+                (!roles.is_empty()).then_some((*topic_id, roles))
+            })
+            .collect();
+        let blobs = topic_ids
+            .iter()
+            .filter_map(|topic_id| {
+                self.blob_filters
+                    .get(topic_id)
+                    .map(|filter| (*topic_id, filter.clone()))
+            })
+            .collect();
+        let presence = match &self.presence {
+            Some(statuses) => topic_ids
+                .iter()
+                .map(|topic_id| {
+                    let status = statuses.get(topic_id).cloned().unwrap_or_default();
+                    (*topic_id, status)
+                })
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        let proofs = match &self.topic_auth {
+            Some(topic_auth) => topic_ids
+                .iter()
+                .filter(|topic_id| topic_auth.is_private(topic_id))
+                .filter_map(|topic_id| Some((*topic_id, topic_auth.proof(topic_id)?)))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        let parents = topic_ids
+            .iter()
+            .filter_map(|topic_id| {
+                self.parents
+                    .get(topic_id)
+                    .map(|parent_id| (*topic_id, *parent_id))
+            })
+            .collect();
+        let message = TopicDiscoveryMessage::new(
+            topic_ids,
+            roles,
+            blobs,
+            presence,
+            proofs,
+            parents,
+            private_key,
+        );
 
         self.gossip_actor_tx
             .send(ToGossipActor::Broadcast {
@@ -143,23 +304,47 @@ type MessageId = [u8; 32];
 pub struct TopicDiscoveryMessage {
     pub id: MessageId,
     pub topic_ids: Vec<[u8; 32]>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub roles: BTreeMap<[u8; 32], NodeRoles>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub blobs: BTreeMap<[u8; 32], BlobFilter>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub presence: BTreeMap<[u8; 32], Vec<u8>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub proofs: BTreeMap<[u8; 32], Vec<u8>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub parents: BTreeMap<[u8; 32], [u8; 32]>,
     pub public_key: PublicKey,
     pub signature: Signature,
 }
 
 impl TopicDiscoveryMessage {
-    pub fn new(topic_ids: Vec<[u8; 32]>, private_key: &PrivateKey) -> Self {
+    pub fn new(
+        topic_ids: Vec<[u8; 32]>,
+        roles: BTreeMap<[u8; 32], NodeRoles>,
+        blobs: BTreeMap<[u8; 32], BlobFilter>,
+        presence: BTreeMap<[u8; 32], Vec<u8>>,
+        proofs: BTreeMap<[u8; 32], Vec<u8>>,
+        parents: BTreeMap<[u8; 32], [u8; 32]>,
+        private_key: &PrivateKey,
+    ) -> Self {
         // Message id is used to make every message unique, as duplicates get otherwise dropped
         // during gossip broadcast.
         let id = random();
 
         let public_key = private_key.public_key();
-        let raw_message = (id, topic_ids.clone(), public_key);
-        let signature = private_key.sign(&raw_message.to_bytes());
+        let signature = private_key.sign(&Self::signed_bytes(
+            id, &topic_ids, &roles, &blobs, &presence, &proofs, &parents, public_key,
+        ));
 
         Self {
             id,
             topic_ids,
+            roles,
+            blobs,
+            presence,
+            proofs,
+            parents,
             public_key,
             signature,
         }
@@ -167,11 +352,53 @@ impl TopicDiscoveryMessage {
 
     pub fn verify(&self) -> bool {
         self.public_key.verify(
-            &(self.id, &self.topic_ids, self.public_key).to_bytes(),
+            &Self::signed_bytes(
+                self.id,
+                &self.topic_ids,
+                &self.roles,
+                &self.blobs,
+                &self.presence,
+                &self.proofs,
+                &self.parents,
+                self.public_key,
+            ),
             &self.signature,
         )
     }
 
+    /// Bytes covered by the signature.
+    ///
+    /// Roles, blob filters, presence heartbeats, proofs and parent topics are only included when
+    /// given, this keeps messages of peers which don't advertise any compatible with older versions.
+    #[allow(clippy::too_many_arguments)]
+    fn signed_bytes(
+        id: MessageId,
+        topic_ids: &[[u8; 32]],
+        roles: &BTreeMap<[u8; 32], NodeRoles>,
+        blobs: &BTreeMap<[u8; 32], BlobFilter>,
+        presence: &BTreeMap<[u8; 32], Vec<u8>>,
+        proofs: &BTreeMap<[u8; 32], Vec<u8>>,
+        parents: &BTreeMap<[u8; 32], [u8; 32]>,
+        public_key: PublicKey,
+    ) -> Vec<u8> {
+        if !parents.is_empty() {
+            (
+                id, topic_ids, public_key, roles, blobs, presence, proofs, parents,
+            )
+                .to_bytes()
+        } else if !proofs.is_empty() {
+            (id, topic_ids, public_key, roles, blobs, presence, proofs).to_bytes()
+        } else if !presence.is_empty() {
+            (id, topic_ids, public_key, roles, blobs, presence).to_bytes()
+        } else if !blobs.is_empty() {
+            (id, topic_ids, public_key, roles, blobs).to_bytes()
+        } else if !roles.is_empty() {
+            (id, topic_ids, public_key, roles).to_bytes()
+        } else {
+            (id, topic_ids, public_key).to_bytes()
+        }
+    }
+
     pub fn public_key(&self) -> PublicKey {
         self.public_key
     }
@@ -179,10 +406,19 @@ impl TopicDiscoveryMessage {
 
 #[cfg(test)]
 mod tests {
-    use p2panda_core::PrivateKey;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use p2panda_core::{Hash, PrivateKey};
     use tokio::sync::mpsc;
 
+    use crate::bytes::FromBytes;
     use crate::engine::AddressBook;
+    use crate::engine::gossip::ToGossipActor;
+    use crate::providers::BlobFilter;
+    use crate::roles::{NodeRole, NodeRoles, RolesConfig};
+    use crate::topic_auth::{TopicAuthenticator, TopicOwners, sign_subscribe_proof};
+    use crate::topic_tree::{child_overlay_id, derive_child_topic_id};
     use crate::{NodeAddress, bytes::ToBytes};
 
     use super::{Status, TopicDiscovery, TopicDiscoveryMessage};
@@ -197,8 +433,15 @@ mod tests {
         address_book.add_peer(node_addr).await;
 
         let (gossip_actor_tx, _gossip_actor_rx) = mpsc::channel(64);
-        let mut topic_discovery =
-            TopicDiscovery::new(network_id, gossip_actor_tx, address_book, true);
+        let mut topic_discovery = TopicDiscovery::new(
+            network_id,
+            gossip_actor_tx,
+            address_book,
+            true,
+            RolesConfig::default(),
+            false,
+            None,
+        );
 
         // We expect the status to transition from `Idle` to `Pending` when topic discovery is
         // started, since we already added a peer to the address book.
@@ -214,7 +457,15 @@ mod tests {
     fn verify_message() {
         let private_key = PrivateKey::new();
         let topic_ids = vec![[0; 32]];
-        let message = TopicDiscoveryMessage::new(topic_ids.clone(), &private_key);
+        let message = TopicDiscoveryMessage::new(
+            topic_ids.clone(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            &private_key,
+        );
         assert!(message.verify());
 
         let wrong_public_key = PrivateKey::new();
@@ -223,4 +474,322 @@ mod tests {
         message.signature = wrong_signature;
         assert!(!message.verify())
     }
+
+    #[tokio::test]
+    async fn roles_are_signed_and_registered() {
+        let network_id = [7; 32];
+        let topic_id = [1; 32];
+
+        let private_key = PrivateKey::new();
+        let roles = BTreeMap::from([(topic_id, NodeRoles::from([NodeRole::Archive]))]);
+        let message = TopicDiscoveryMessage::new(
+            vec![topic_id],
+            roles,
+            BTreeMap::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            &private_key,
+        );
+        assert!(message.verify());
+
+        // Tampering with the advertised roles invalidates the signature.
+        let mut tampered = message.clone();
+        tampered.roles.clear();
+        assert!(!tampered.verify());
+
+        let address_book = AddressBook::new(network_id);
+        let (gossip_actor_tx, _gossip_actor_rx) = mpsc::channel(64);
+        let mut topic_discovery = TopicDiscovery::new(
+            network_id,
+            gossip_actor_tx,
+            address_book.clone(),
+            false,
+            RolesConfig::default(),
+            false,
+            None,
+        );
+
+        topic_discovery
+            .on_gossip_message(&message.to_bytes())
+            .await
+            .unwrap();
+        assert_eq!(
+            address_book.roles(private_key.public_key(), topic_id).await,
+            NodeRoles::from([NodeRole::Archive])
+        );
+    }
+
+    #[tokio::test]
+    async fn announce_provided_blobs() {
+        let network_id = [7; 32];
+        let topic_id = [1; 32];
+        let blob = Hash::new("blob");
+
+        let (gossip_actor_tx, mut gossip_actor_rx) = mpsc::channel(64);
+        let mut topic_discovery = TopicDiscovery::new(
+            network_id,
+            gossip_actor_tx,
+            AddressBook::new(network_id),
+            true,
+            RolesConfig::default(),
+            false,
+            None,
+        );
+        topic_discovery.status = Status::Active;
+        topic_discovery.provide_blobs(topic_id, BlobFilter::new([&blob]));
+
+        let private_key = PrivateKey::new();
+        topic_discovery
+            .announce(vec![topic_id], &private_key)
+            .await
+            .unwrap();
+        let Some(ToGossipActor::Broadcast { bytes, .. }) = gossip_actor_rx.recv().await else {
+            panic!("expected broadcast");
+        };
+
+        // Providing blobs implies the blob provider role.
+        let message = TopicDiscoveryMessage::from_bytes(&bytes).unwrap();
+        assert!(message.verify());
+        assert_eq!(
+            message.roles.get(&topic_id),
+            Some(&NodeRoles::from([NodeRole::BlobProvider]))
+        );
+
+        // Tampering with the announced blobs invalidates the signature.
+        let mut tampered = message.clone();
+        tampered.blobs.clear();
+        assert!(!tampered.verify());
+
+        let address_book = AddressBook::new(network_id);
+        let (gossip_actor_tx, _gossip_actor_rx) = mpsc::channel(64);
+        let mut topic_discovery = TopicDiscovery::new(
+            network_id,
+            gossip_actor_tx,
+            address_book.clone(),
+            false,
+            RolesConfig::default(),
+            false,
+            None,
+        );
+        topic_discovery.on_gossip_message(&bytes).await.unwrap();
+        assert_eq!(
+            address_book.blob_providers(&blob).await,
+            vec![private_key.public_key()]
+        );
+    }
+
+    #[tokio::test]
+    async fn announce_presence() {
+        let network_id = [7; 32];
+        let topic_id = [1; 32];
+        let other_topic_id = [2; 32];
+
+        let (gossip_actor_tx, mut gossip_actor_rx) = mpsc::channel(64);
+        let mut topic_discovery = TopicDiscovery::new(
+            network_id,
+            gossip_actor_tx,
+            AddressBook::new(network_id),
+            true,
+            RolesConfig::default(),
+            true,
+            None,
+        );
+        topic_discovery.status = Status::Active;
+        topic_discovery.set_presence_status(topic_id, b"online".to_vec());
+
+        let private_key = PrivateKey::new();
+        topic_discovery
+            .announce(vec![topic_id, other_topic_id], &private_key)
+            .await
+            .unwrap();
+        let Some(ToGossipActor::Broadcast { bytes, .. }) = gossip_actor_rx.recv().await else {
+            panic!("expected broadcast");
+        };
+
+        // Heartbeats are sent for every announced topic, with an empty status if none was set.
+        let message = TopicDiscoveryMessage::from_bytes(&bytes).unwrap();
+        assert!(message.verify());
+        assert_eq!(
+            message.presence,
+            BTreeMap::from([(topic_id, b"online".to_vec()), (other_topic_id, Vec::new())])
+        );
+
+        // Tampering with the announced status invalidates the signature.
+        let mut tampered = message.clone();
+        tampered.presence.insert(topic_id, b"offline".to_vec());
+        assert!(!tampered.verify());
+
+        let announcement = topic_discovery.on_gossip_message(&bytes).await.unwrap();
+        assert_eq!(announcement.public_key, private_key.public_key());
+        assert_eq!(announcement.presence, message.presence);
+    }
+
+    #[tokio::test]
+    async fn announce_child_topics() {
+        let network_id = [7; 32];
+        let parent_id = [1; 32];
+        let child_id = derive_child_topic_id(&parent_id, b"general");
+
+        let (gossip_actor_tx, mut gossip_actor_rx) = mpsc::channel(64);
+        let mut topic_discovery = TopicDiscovery::new(
+            network_id,
+            gossip_actor_tx,
+            AddressBook::new(network_id),
+            true,
+            RolesConfig::default(),
+            false,
+            None,
+        );
+        topic_discovery.status = Status::Active;
+        topic_discovery.add_child_topic(child_id, parent_id);
+
+        let private_key = PrivateKey::new();
+        topic_discovery
+            .announce(vec![parent_id, child_id], &private_key)
+            .await
+            .unwrap();
+        let Some(ToGossipActor::Broadcast { bytes, .. }) = gossip_actor_rx.recv().await else {
+            panic!("expected broadcast");
+        };
+
+        let message = TopicDiscoveryMessage::from_bytes(&bytes).unwrap();
+        assert!(message.verify());
+        assert_eq!(message.parents, BTreeMap::from([(child_id, parent_id)]));
+
+        // Tampering with the announced parents invalidates the signature.
+        let mut tampered = message.clone();
+        tampered.parents.clear();
+        assert!(!tampered.verify());
+
+        // The peer is registered for the shared overlay of the child topics.
+        let address_book = AddressBook::new(network_id);
+        let (gossip_actor_tx, _gossip_actor_rx) = mpsc::channel(64);
+        let mut topic_discovery = TopicDiscovery::new(
+            network_id,
+            gossip_actor_tx,
+            address_book.clone(),
+            false,
+            RolesConfig::default(),
+            false,
+            None,
+        );
+        let announcement = topic_discovery.on_gossip_message(&bytes).await.unwrap();
+        assert_eq!(announcement.parents, message.parents);
+        assert!(
+            address_book
+                .has_topic_id(private_key.public_key(), child_overlay_id(&parent_id))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn ignore_private_topics_without_proof() {
+        let network_id = [7; 32];
+        let private_topic_id = [1; 32];
+        let public_topic_id = [2; 32];
+
+        let owner = PrivateKey::new();
+        let member = PrivateKey::new();
+        let stranger = PrivateKey::new();
+        let authenticator = |peer: &PrivateKey| -> Arc<dyn TopicAuthenticator> {
+            let proof = sign_subscribe_proof(&owner, &private_topic_id, &peer.public_key());
+            Arc::new(
+                TopicOwners::new()
+                    .private_topic(private_topic_id, owner.public_key())
+                    .own_proof(private_topic_id, proof),
+            )
+        };
+
+        let (gossip_actor_tx, mut gossip_actor_rx) = mpsc::channel(64);
+        let mut topic_discovery = TopicDiscovery::new(
+            network_id,
+            gossip_actor_tx,
+            AddressBook::new(network_id),
+            true,
+            RolesConfig::default(),
+            false,
+            Some(authenticator(&member)),
+        );
+        topic_discovery.status = Status::Active;
+        topic_discovery
+            .announce(vec![private_topic_id, public_topic_id], &member)
+            .await
+            .unwrap();
+        let Some(ToGossipActor::Broadcast { bytes, .. }) = gossip_actor_rx.recv().await else {
+            panic!("expected broadcast");
+        };
+
+        // Proofs are only attached for private topics.
+        let message = TopicDiscoveryMessage::from_bytes(&bytes).unwrap();
+        assert!(message.verify());
+        assert_eq!(
+            message.proofs.keys().collect::<Vec<_>>(),
+            vec![&private_topic_id]
+        );
+
+        // Tampering with the announced proofs invalidates the signature.
+        let mut tampered = message.clone();
+        tampered.proofs.clear();
+        assert!(!tampered.verify());
+
+        let address_book = AddressBook::new(network_id);
+        let (gossip_actor_tx, _gossip_actor_rx) = mpsc::channel(64);
+        let mut topic_discovery = TopicDiscovery::new(
+            network_id,
+            gossip_actor_tx,
+            address_book.clone(),
+            false,
+            RolesConfig::default(),
+            false,
+            Some(authenticator(&stranger)),
+        );
+        let announcement = topic_discovery.on_gossip_message(&bytes).await.unwrap();
+        assert_eq!(
+            announcement.topic_ids,
+            vec![private_topic_id, public_topic_id]
+        );
+        assert!(
+            address_book
+                .has_topic_id(member.public_key(), private_topic_id)
+                .await
+        );
+
+        // Proofs are bound to the peer they were signed for.
+        let stolen = TopicDiscoveryMessage::new(
+            vec![private_topic_id, public_topic_id],
+            BTreeMap::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            message.proofs.clone(),
+            BTreeMap::new(),
+            &stranger,
+        );
+        let announcement = topic_discovery
+            .on_gossip_message(&stolen.to_bytes())
+            .await
+            .unwrap();
+        assert_eq!(announcement.topic_ids, vec![public_topic_id]);
+        assert!(
+            !address_book
+                .has_topic_id(stranger.public_key(), private_topic_id)
+                .await
+        );
+
+        // Peers without a proof are ignored on private topics as well.
+        let unproven = TopicDiscoveryMessage::new(
+            vec![private_topic_id],
+            BTreeMap::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            &stranger,
+        );
+        let announcement = topic_discovery
+            .on_gossip_message(&unproven.to_bytes())
+            .await
+            .unwrap();
+        assert!(announcement.topic_ids.is_empty());
+    }
 }
@@ -1,21 +1,26 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 
 use anyhow::Result;
-use p2panda_core::PublicKey;
-use p2panda_sync::TopicQuery;
+use p2panda_core::{PrivateKey, PublicKey};
+use p2panda_sync::{SyncFilter, TopicQuery};
 use tokio::sync::{RwLock, mpsc, oneshot};
+use tokio::time::Instant;
 use tracing::{debug, error, warn};
 
 use crate::TopicId;
+use crate::bytes::{FromBytes, ToBytes};
 use crate::engine::address_book::AddressBook;
-use crate::engine::constants::JOIN_PEERS_SAMPLE_LEN;
+use crate::engine::constants::{JOIN_PEERS_SAMPLE_LEN, RETAINED_MESSAGES_GRACE_PERIOD};
 use crate::engine::gossip::ToGossipActor;
 use crate::engine::gossip_buffer::GossipBuffer;
 use crate::network::{FromNetwork, ToNetwork};
 use crate::sync::manager::ToSyncActor;
+use crate::sync::{DeltaAnnouncement, LogHeightsProvider, is_behind};
+use crate::topic_auth::{PrivateTopicMessage, TopicAuthenticator};
+use crate::topic_tree::{ChildTopicMessage, child_overlay_id};
 
 /// Managed data stream over an application-defined topic.
 type TopicStream<T> = (T, mpsc::Sender<FromNetwork>);
@@ -31,44 +36,96 @@ type TopicStreamId = usize;
 ///    peers for syncing up state with them.
 /// 3. Intercept and temporarily buffer incoming gossip messages of a peer when we're currently in
 ///    a sync session with them. As soon as this sync session has finished we can re-play the
-///    messages. This helps reducing the number of out-of-order messages.
+///    messages. This helps reducing the number of out-of-order messages. If sync delivers data
+///    newest-first, gossip messages are delivered right away instead.
 /// 4. Applications can subscribe to topics multiple times, or to different topics but with the
 ///    same topic ids. This stream handler multiplexes messages to the right place, even when
-///    there's duplicates.
+///    there's duplicates. All subscribers share one gossip overlay and one sync schedule per
+///    topic, subscriptions end when their receiver is dropped and the overlay is left after the
+///    last one ended.
+/// 5. If delta announcements are enabled, wrap outgoing gossip messages with our signed log heights
+///    and schedule a sync session with the author as soon as incoming gossip messages show that
+///    we're behind.
+/// 6. Child topics share the gossip overlay of their parent, outgoing messages are tagged with the
+///    child topic id and incoming messages are only routed to subscribers of the same child topic.
+/// 7. If enabled, retain the latest gossip messages per topic id and replay them to new
+///    subscribers. They are kept for a grace period after the last subscription ended, to be
+///    replayed when the application subscribes again shortly after.
+/// 8. Observers receive data of a topic via sync only. Their topics are not announced and no gossip
+///    overlay is joined for them.
+/// 9. Every stream can ask for a sync filter. Sync sessions over a topic use the union of the
+///    filters of all its streams, any stream without a filter means no filter is used at all.
+/// 10. Messages on private topics are signed with our private key and carry our proof, incoming
+///     messages are only delivered if their author proved access to the topic.
 #[derive(Debug)]
 pub struct TopicStreams<T> {
     address_book: AddressBook,
+    delta_announcements: Option<Arc<dyn LogHeightsProvider<T>>>,
+    filters: HashMap<TopicStreamId, SyncFilter>,
     gossip_actor_tx: mpsc::Sender<ToGossipActor>,
     gossip_buffer: GossipBuffer,
     gossip_joined: Arc<RwLock<HashSet<[u8; 32]>>>,
-    gossip_pending: HashMap<[u8; 32], oneshot::Sender<()>>,
+    gossip_pending: HashMap<[u8; 32], Vec<oneshot::Sender<()>>>,
     next_stream_id: usize,
+    observers: HashSet<TopicStreamId>,
+    private_key: PrivateKey,
+    retained: HashMap<[u8; 32], VecDeque<(Vec<u8>, PublicKey)>>,
+    retained_messages: usize,
+    retained_released: HashMap<[u8; 32], Instant>,
     subscribed: HashMap<TopicStreamId, TopicStream<T>>,
+    child_overlays: HashSet<[u8; 32]>,
+    topic_id_to_overlay: HashMap<[u8; 32], [u8; 32]>,
     topic_id_to_stream: HashMap<[u8; 32], Vec<TopicStreamId>>,
     topic_to_stream: HashMap<T, Vec<TopicStreamId>>,
     sync_actor_tx: Option<mpsc::Sender<ToSyncActor<T>>>,
+    topic_auth: Option<Arc<dyn TopicAuthenticator>>,
 }
 
 impl<T> TopicStreams<T>
 where
     T: TopicQuery + TopicId + 'static,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         gossip_actor_tx: mpsc::Sender<ToGossipActor>,
         address_book: AddressBook,
+        private_key: PrivateKey,
         sync_actor_tx: Option<mpsc::Sender<ToSyncActor<T>>>,
+        delta_announcements: Option<Arc<dyn LogHeightsProvider<T>>>,
+        topic_auth: Option<Arc<dyn TopicAuthenticator>>,
+        newest_first: bool,
+        retained_messages: usize,
     ) -> Self {
+        // If sync sessions deliver data newest-first, gossip messages carry the most recent data
+        // and holding them back until the sync session finished would reverse the order of
+        // delivery.
+        let gossip_buffer = if newest_first {
+            GossipBuffer::disabled()
+        } else {
+            GossipBuffer::default()
+        };
+
         Self {
             address_book,
+            delta_announcements,
+            filters: HashMap::new(),
             gossip_actor_tx,
-            gossip_buffer: Default::default(),
+            gossip_buffer,
             gossip_joined: Arc::new(RwLock::new(HashSet::new())),
             gossip_pending: HashMap::new(),
             next_stream_id: 1,
+            observers: HashSet::new(),
+            private_key,
+            retained: HashMap::new(),
+            retained_messages,
+            retained_released: HashMap::new(),
             subscribed: HashMap::new(),
+            child_overlays: HashSet::new(),
+            topic_id_to_overlay: HashMap::new(),
             topic_id_to_stream: HashMap::new(),
             topic_to_stream: HashMap::new(),
             sync_actor_tx,
+            topic_auth,
         }
     }
 
@@ -85,41 +142,70 @@ where
     pub async fn subscribe(
         &mut self,
         topic: T,
+        filter: SyncFilter,
         from_network_tx: mpsc::Sender<FromNetwork>,
         mut to_network_rx: mpsc::Receiver<ToNetwork>,
         gossip_ready_tx: oneshot::Sender<()>,
     ) -> Result<()> {
-        // Every subscription stream receives its own unique identifier.
-        let stream_id = self.next_stream_id;
-        self.next_stream_id += 1;
+        // Child topics share the gossip overlay of their parent.
+        let parent_id = topic.parent_id();
+        let overlay_id = match &parent_id {
+            Some(parent_id) => {
+                let overlay_id = child_overlay_id(parent_id);
+                self.child_overlays.insert(overlay_id);
+                overlay_id
+            }
+            None => topic.id(),
+        };
+        self.topic_id_to_overlay.insert(topic.id(), overlay_id);
+
+        // Replay the latest gossip messages on this topic id, in case the application subscribed
+        // again shortly after receiving them.
+        self.retained_released.remove(&topic.id());
+        if let Some(retained) = self.retained.get(&topic.id()) {
+            for (bytes, delivered_from) in retained {
+                let message = FromNetwork::GossipMessage {
+                    bytes: bytes.clone(),
+                    delivered_from: *delivered_from,
+                };
+                if from_network_tx.try_send(message).is_err() {
+                    warn!("could not replay retained gossip message for topic {topic:?}");
+                    break;
+                }
+            }
+        }
 
         // Prepare all relevant earmarks and data streams to aid other processes dealing with
         // gossip, buffering or sync.
-        self.subscribed
-            .insert(stream_id, (topic.clone(), from_network_tx));
-        self.gossip_pending.insert(topic.id(), gossip_ready_tx);
-        self.topic_to_stream
-            .entry(topic.clone())
-            .and_modify(|stream_ids| stream_ids.push(stream_id))
-            .or_insert(vec![stream_id]);
-        self.topic_id_to_stream
-            .entry(topic.id())
-            .and_modify(|stream_ids| stream_ids.push(stream_id))
-            .or_insert(vec![stream_id]);
+        self.add_stream(topic.clone(), filter, from_network_tx);
+        self.update_sync_filter(&topic).await?;
+        if self.has_joined_gossip(overlay_id).await {
+            // The overlay might have been joined already for another topic, for example a sibling
+            // child topic.
+            gossip_ready_tx.send(()).ok();
+        } else {
+            self.gossip_pending
+                .entry(overlay_id)
+                .or_default()
+                .push(gossip_ready_tx);
+        }
 
         // Hot path: If we haven't joined a gossip overlay for this topic yet, optimistically try
         // to do it now. If this fails we should re-try sometime later using the
         // "try_join_pending_gossips" method.
-        self.join_gossip(topic.id()).await?;
+        self.join_gossip(overlay_id).await?;
 
         // Spawn task to establish a channel for sending messages into gossip overlay.
         {
             let gossip_actor_tx = self.gossip_actor_tx.clone();
             let gossip_joined = self.gossip_joined.clone();
+            let delta_announcements = self.delta_announcements.clone();
+            let private_key = self.private_key.clone();
+            let topic_auth = self.topic_auth.clone();
             tokio::task::spawn(async move {
                 while let Some(event) = to_network_rx.recv().await {
                     let gossip_joined = gossip_joined.read().await;
-                    if !gossip_joined.contains(&topic.id()) {
+                    if !gossip_joined.contains(&overlay_id) {
                         // If we haven't joined the gossip yet messages will be silently dropped
                         // here.
                         //
@@ -136,9 +222,60 @@ where
 
                     let result = match event {
                         ToNetwork::Message { bytes } => {
+                            // Announce our current log heights alongside the message. Messages
+                            // which could be mistaken for an announcement are wrapped as well.
+                            let bytes = match &delta_announcements {
+                                Some(provider) => DeltaAnnouncement::new(
+                                    &private_key,
+                                    topic.id(),
+                                    provider.log_heights(&topic),
+                                    bytes,
+                                )
+                                .encode(),
+                                None if DeltaAnnouncement::is_ambiguous(&bytes) => {
+                                    DeltaAnnouncement::new(
+                                        &private_key,
+                                        topic.id(),
+                                        Vec::new(),
+                                        bytes,
+                                    )
+                                    .encode()
+                                }
+                                None => bytes,
+                            };
+
+                            // Sign messages on private topics, so that peers can check that we're
+                            // allowed to publish on it, even when the message got forwarded.
+                            let bytes = match &topic_auth {
+                                Some(topic_auth) if topic_auth.is_private(&topic.id()) => {
+                                    let Some(proof) = topic_auth.proof(&topic.id()) else {
+                                        warn!("no proof to publish on private topic {topic:?}");
+                                        continue;
+                                    };
+                                    PrivateTopicMessage::new(
+                                        &private_key,
+                                        &topic.id(),
+                                        proof,
+                                        bytes,
+                                    )
+                                    .to_bytes()
+                                }
+                                _ => bytes,
+                            };
+
+                            // Tag messages of child topics on the shared overlay.
+                            let bytes = match parent_id {
+                                Some(_) => ChildTopicMessage {
+                                    topic_id: topic.id(),
+                                    bytes,
+                                }
+                                .to_bytes(),
+                                None => bytes,
+                            };
+
                             gossip_actor_tx
                                 .send(ToGossipActor::Broadcast {
-                                    topic_id: topic.id(),
+                                    topic_id: overlay_id,
                                     bytes,
                                 })
                                 .await
@@ -158,26 +295,224 @@ where
         Ok(())
     }
 
-    /// Returns a list of all gossip topic ids we're interested in.
+    /// Returns the id of the gossip overlay a subscribed topic id is broadcast on.
+    pub fn overlay_id(&self, topic_id: &[u8; 32]) -> Option<[u8; 32]> {
+        self.topic_id_to_overlay.get(topic_id).copied()
+    }
+
+    /// Returns a list of all gossip topic ids we're interested in, excluding observed topics.
     pub fn topic_ids(&self) -> Vec<[u8; 32]> {
-        self.subscribed
-            .values()
-            .map(|(topic, _)| topic.id())
+        self.topic_id_to_stream
+            .iter()
+            .filter(|(_, stream_ids)| {
+                stream_ids
+                    .iter()
+                    .any(|stream_id| !self.observers.contains(stream_id))
+            })
+            .map(|(topic_id, _)| *topic_id)
             .collect()
     }
 
     /// Moves all gossip topics which were previously joined into the set of pending joins.
     ///
     /// This is useful for rejoining gossip topic overlays after an extended loss of network
-    /// connectivity. One important consideration is that the ready receiver is immediately
-    /// dropped, meaning that the application layer is never made aware when the topic has been
+    /// connectivity. One important consideration is that no ready signal is kept for these
+    /// overlays, meaning that the application layer is never made aware when the topic has been
     /// rejoined.
     pub async fn move_joined_to_pending(&mut self) {
         let mut gossip_joined = self.gossip_joined.write().await;
         for topic in gossip_joined.drain() {
-            let (ready_tx, _ready_rx) = oneshot::channel();
-            self.gossip_pending.insert(topic, ready_tx);
+            self.gossip_pending.entry(topic).or_default();
+        }
+    }
+
+    /// Establishes a read-only stream receiving data of a topic via sync.
+    ///
+    /// The topic id is not announced to other peers and no gossip overlay is joined for it, we
+    /// only initiate sync sessions with peers announcing the topic.
+    pub async fn observe(
+        &mut self,
+        topic: T,
+        filter: SyncFilter,
+        from_network_tx: mpsc::Sender<FromNetwork>,
+    ) -> Result<()> {
+        let stream_id = self.add_stream(topic.clone(), filter, from_network_tx);
+        self.observers.insert(stream_id);
+        self.update_sync_filter(&topic).await
+    }
+
+    /// Removes all read-only streams of the topic and returns the ids of the gossip overlays
+    /// we've left as nobody is subscribed to them anymore.
+    ///
+    /// Regular subscriptions to the same topic are kept.
+    pub async fn stop_observing(&mut self, topic: &T) -> Result<Vec<[u8; 32]>> {
+        let observing = self
+            .topic_to_stream
+            .get(topic)
+            .map(|stream_ids| {
+                stream_ids
+                    .iter()
+                    .filter(|stream_id| self.observers.contains(*stream_id))
+                    .copied()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let mut left_overlays = Vec::new();
+        for stream_id in observing {
+            if let Some(overlay_id) = self.remove_stream(stream_id).await? {
+                left_overlays.push(overlay_id);
+            }
         }
+
+        Ok(left_overlays)
+    }
+
+    /// Registers a stream for the topic, returns its unique identifier.
+    fn add_stream(
+        &mut self,
+        topic: T,
+        filter: SyncFilter,
+        from_network_tx: mpsc::Sender<FromNetwork>,
+    ) -> TopicStreamId {
+        // Every subscription stream receives its own unique identifier.
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 1;
+
+        self.filters.insert(stream_id, filter);
+        self.subscribed
+            .insert(stream_id, (topic.clone(), from_network_tx));
+        self.topic_to_stream
+            .entry(topic.clone())
+            .and_modify(|stream_ids| stream_ids.push(stream_id))
+            .or_insert(vec![stream_id]);
+        self.topic_id_to_stream
+            .entry(topic.id())
+            .and_modify(|stream_ids| stream_ids.push(stream_id))
+            .or_insert(vec![stream_id]);
+
+        stream_id
+    }
+
+    /// Informs the sync manager about the union of the filters of all streams of the topic.
+    ///
+    /// An empty filter is sent if any stream has no filter or no streams are left, it clears the
+    /// filter of the topic.
+    async fn update_sync_filter(&self, topic: &T) -> Result<()> {
+        let Some(sync_actor_tx) = &self.sync_actor_tx else {
+            return Ok(());
+        };
+
+        let filter = self
+            .topic_to_stream
+            .get(topic)
+            .into_iter()
+            .flatten()
+            .filter_map(|stream_id| self.filters.get(stream_id))
+            .fold(None, |union: Option<SyncFilter>, filter| match union {
+                Some(union) => Some(union.union(filter)),
+                None => Some(filter.clone()),
+            })
+            .unwrap_or_default();
+        sync_actor_tx
+            .send(ToSyncActor::Filter {
+                topic: topic.clone(),
+                filter,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes all streams whose receivers were dropped and returns the ids of the gossip overlays
+    /// we've left as nobody is subscribed to them anymore.
+    ///
+    /// Retained gossip messages are removed once their topic had no subscription for the grace
+    /// period.
+    pub async fn prune_closed_streams(&mut self) -> Result<Vec<[u8; 32]>> {
+        self.retained_released.retain(|topic_id, released_at| {
+            let keep = released_at.elapsed() < RETAINED_MESSAGES_GRACE_PERIOD;
+            if !keep {
+                self.retained.remove(topic_id);
+            }
+            keep
+        });
+
+        let closed = self
+            .subscribed
+            .iter()
+            .filter(|(_, (_, from_network_tx))| from_network_tx.is_closed())
+            .map(|(stream_id, _)| *stream_id)
+            .collect::<Vec<_>>();
+
+        let mut left_overlays = Vec::new();
+        for stream_id in closed {
+            if let Some(overlay_id) = self.remove_stream(stream_id).await? {
+                left_overlays.push(overlay_id);
+            }
+        }
+
+        Ok(left_overlays)
+    }
+
+    /// Removes the stream, leaves the gossip overlay of its topic if it was the last subscriber.
+    ///
+    /// Observers don't take part in the gossip overlay, it is left after the last regular
+    /// subscriber ended even if observers of the topic remain.
+    async fn remove_stream(&mut self, stream_id: TopicStreamId) -> Result<Option<[u8; 32]>> {
+        let Some((topic, _)) = self.subscribed.remove(&stream_id) else {
+            return Ok(None);
+        };
+        self.observers.remove(&stream_id);
+        self.filters.remove(&stream_id);
+        debug!("subscription to topic {topic:?} ended");
+
+        if let Some(stream_ids) = self.topic_to_stream.get_mut(&topic) {
+            stream_ids.retain(|id| *id != stream_id);
+            if stream_ids.is_empty() {
+                self.topic_to_stream.remove(&topic);
+            }
+        }
+        self.update_sync_filter(&topic).await?;
+
+        let topic_id = topic.id();
+        let Some(stream_ids) = self.topic_id_to_stream.get_mut(&topic_id) else {
+            return Ok(None);
+        };
+        stream_ids.retain(|id| *id != stream_id);
+        if stream_ids.is_empty() {
+            self.topic_id_to_stream.remove(&topic_id);
+        } else if stream_ids.iter().any(|id| !self.observers.contains(id)) {
+            return Ok(None);
+        }
+        if self.retained.contains_key(&topic_id) {
+            self.retained_released.insert(topic_id, Instant::now());
+        }
+
+        // Child topics share the overlay of their parent, it is only left after all of them were
+        // unsubscribed.
+        let Some(overlay_id) = self.topic_id_to_overlay.remove(&topic_id) else {
+            return Ok(None);
+        };
+        if self
+            .topic_id_to_overlay
+            .values()
+            .any(|id| *id == overlay_id)
+        {
+            return Ok(None);
+        }
+
+        debug!("leaving gossip overlay {overlay_id:?}");
+        self.child_overlays.remove(&overlay_id);
+        self.gossip_pending.remove(&overlay_id);
+        self.gossip_joined.write().await.remove(&overlay_id);
+        self.gossip_actor_tx
+            .send(ToGossipActor::Leave {
+                topic_id: overlay_id,
+            })
+            .await?;
+
+        Ok(Some(overlay_id))
     }
 
     /// Re-attempts joining pending gossip overlays for topic id's we haven't succeeded joining yet
@@ -195,14 +530,16 @@ where
 
     /// Mark that we've successfully joined a gossip overlay for this topic.
     pub async fn on_gossip_joined(&mut self, topic_id: [u8; 32]) {
-        if let Some(ready_tx) = self.gossip_pending.remove(&topic_id) {
+        if let Some(ready_txs) = self.gossip_pending.remove(&topic_id) {
             let mut gossip_joined = self.gossip_joined.write().await;
             gossip_joined.insert(topic_id);
 
             // Inform local topic subscribers that the gossip overlay has been joined and is ready
             // for messages.
-            if ready_tx.send(()).is_err() {
-                warn!("gossip topic oneshot ready receiver dropped")
+            for ready_tx in ready_txs {
+                if ready_tx.send(()).is_err() {
+                    warn!("gossip topic oneshot ready receiver dropped")
+                }
             }
         }
     }
@@ -232,20 +569,111 @@ where
         gossip_joined.contains(&topic_id)
     }
 
+    /// Resolves the topic id and payload of a message received on a gossip overlay.
+    ///
+    /// Messages on the shared overlay of child topics are unwrapped, `None` is returned for
+    /// messages of child topics we're not subscribed to.
+    pub fn open_gossip_message(
+        &self,
+        overlay_id: [u8; 32],
+        bytes: Vec<u8>,
+    ) -> Result<Option<([u8; 32], Vec<u8>)>> {
+        if !self.child_overlays.contains(&overlay_id) {
+            return Ok(Some((overlay_id, bytes)));
+        }
+
+        let message = ChildTopicMessage::from_bytes(&bytes)?;
+        if self.topic_id_to_overlay.get(&message.topic_id) != Some(&overlay_id) {
+            return Ok(None);
+        }
+
+        Ok(Some((message.topic_id, message.bytes)))
+    }
+
     /// Handle incoming messages from gossip.
     ///
-    /// This method forwards messages to the subscribers for the given topic id.
+    /// This method forwards messages to the subscribers for the given topic id. Messages on
+    /// private topics are dropped if their author can't prove access to the topic. Delta
+    /// announcements are unwrapped next and, if enabled, the announced log heights of their author
+    /// are compared with our own.
     pub async fn on_gossip_message(
         &mut self,
         topic_id: [u8; 32],
         bytes: Vec<u8>,
         delivered_from: PublicKey,
     ) -> Result<()> {
-        if !self.has_joined_gossip(topic_id).await {
+        let overlay_id = self
+            .topic_id_to_overlay
+            .get(&topic_id)
+            .copied()
+            .unwrap_or(topic_id);
+        if !self.has_joined_gossip(overlay_id).await {
             warn!("received message for unknown topic {topic_id:?}");
             return Ok(());
         }
 
+        // The peer which delivered the message might only have forwarded it, we check the proof
+        // of its author instead.
+        let bytes = match &self.topic_auth {
+            Some(topic_auth) if topic_auth.is_private(&topic_id) => {
+                match PrivateTopicMessage::decode(topic_auth.as_ref(), &topic_id, &bytes) {
+                    Ok(message) => message.bytes,
+                    Err(err) => {
+                        debug!("drop private topic message delivered from {delivered_from}: {err}");
+                        return Ok(());
+                    }
+                }
+            }
+            _ => bytes,
+        };
+
+        let bytes = match DeltaAnnouncement::decode(topic_id, &bytes) {
+            None => bytes,
+            Some(Err(err)) => {
+                warn!("invalid delta announcement delivered from {delivered_from}: {err}");
+                return Ok(());
+            }
+            Some(Ok(announcement)) => {
+                // The heights describe the logs of the author, the peer which delivered the
+                // message might only have forwarded it.
+                if let (Some(provider), Some(sync_actor_tx)) =
+                    (&self.delta_announcements, &self.sync_actor_tx)
+                {
+                    let author = announcement.author;
+                    for topic in self.topic_to_stream.keys() {
+                        if topic.id() != topic_id {
+                            continue;
+                        }
+
+                        let local_heights = provider.log_heights(topic);
+                        if is_behind(&local_heights, &announcement.heights) {
+                            debug!("behind {author} on topic {topic:?}, scheduling sync");
+                            sync_actor_tx
+                                .send(ToSyncActor::Behind {
+                                    peer: author,
+                                    topic: topic.clone(),
+                                })
+                                .await?;
+                        }
+                    }
+                }
+
+                announcement.bytes
+            }
+        };
+
+        self.deliver_gossip_message(topic_id, bytes, delivered_from)
+            .await
+    }
+
+    /// Forward a gossip message to all subscribers for the given topic id, or buffer it while a
+    /// sync session with the sender is running.
+    async fn deliver_gossip_message(
+        &mut self,
+        topic_id: [u8; 32],
+        bytes: Vec<u8>,
+        delivered_from: PublicKey,
+    ) -> Result<()> {
         // If there's currently a sync session running with that peer over that topic id we're
         // delaying delivery of these gossip messages and re-play them later after the session
         // finished.
@@ -257,20 +685,35 @@ where
             return Ok(());
         }
 
+        if self.retained_messages > 0 {
+            let retained = self.retained.entry(topic_id).or_default();
+            if retained.len() >= self.retained_messages {
+                retained.pop_front();
+            }
+            retained.push_back((bytes.clone(), delivered_from));
+        }
+
         // Different topics can be subscribed to the same gossip overlay, this is why we need to
         // multiplex the gossip message to potentially multiple streams.
-        let stream_ids = self
-            .topic_id_to_stream
-            .get(&topic_id)
-            .expect("consistent topic id to stream id mapping");
+        //
+        // Subscribers which dropped their receiver are skipped, they're removed with the next call
+        // to `prune_closed_streams`. Observers only receive data via sync.
+        let Some(stream_ids) = self.topic_id_to_stream.get(&topic_id) else {
+            return Ok(());
+        };
         for stream_id in stream_ids {
+            if self.observers.contains(stream_id) {
+                continue;
+            }
+
             let (_, from_network_tx) = self.subscribed.get(stream_id).expect("stream should exist");
-            from_network_tx
-                .send(FromNetwork::GossipMessage {
-                    bytes: bytes.clone(),
-                    delivered_from,
-                })
-                .await?;
+            let message = FromNetwork::GossipMessage {
+                bytes: bytes.clone(),
+                delivered_from,
+            };
+            if from_network_tx.send(message).await.is_err() {
+                debug!("subscriber of topic id {topic_id:?} dropped, skip gossip message");
+            }
         }
 
         Ok(())
@@ -288,13 +731,15 @@ where
         // Inform the sync manager about any peer-topic combinations which are of interest to us.
         //
         // This queues up a sync session which will eventually request the data we are interested
-        // in from that peer.
+        // in from that peer. Topics with multiple subscribers are only scheduled once, the synced
+        // data is fanned out to all of them.
         let mut found_common_topic = false;
         if let Some(sync_actor_tx) = &self.sync_actor_tx {
-            for (topic, _) in self.subscribed.values() {
+            for topic in self.topic_to_stream.keys() {
                 if their_topic_ids.contains(&topic.id()) {
                     found_common_topic = true;
-                    let peer_topic = ToSyncActor::new_discovery(peer, topic.clone());
+                    let roles = self.address_book.roles(peer, topic.id()).await;
+                    let peer_topic = ToSyncActor::new_discovery(peer, topic.clone(), roles);
                     sync_actor_tx.send(peer_topic).await?
                 }
             }
@@ -333,20 +778,21 @@ where
         payload: Option<Vec<u8>>,
         delivered_from: PublicKey,
     ) -> Result<()> {
-        let stream_ids = self
-            .topic_to_stream
-            .get(&topic)
-            .expect("consistent topic to stream id mapping");
+        // All subscribers might have ended while the sync session was still running.
+        let Some(stream_ids) = self.topic_to_stream.get(&topic) else {
+            return Ok(());
+        };
 
         for stream_id in stream_ids {
             let (_, from_network_tx) = self.subscribed.get(stream_id).expect("stream should exist");
-            from_network_tx
-                .send(FromNetwork::SyncMessage {
-                    header: header.clone(),
-                    payload: payload.clone(),
-                    delivered_from,
-                })
-                .await?;
+            let message = FromNetwork::SyncMessage {
+                header: header.clone(),
+                payload: payload.clone(),
+                delivered_from,
+            };
+            if from_network_tx.send(message).await.is_err() {
+                debug!("subscriber of topic {topic:?} dropped, skip sync message");
+            }
         }
 
         Ok(())
@@ -365,7 +811,7 @@ where
                     .expect("missing expected gossip buffer");
 
                 for bytes in buffer {
-                    self.on_gossip_message(topic_id, bytes, peer).await?;
+                    self.deliver_gossip_message(topic_id, bytes, peer).await?;
                 }
             }
         }
@@ -397,15 +843,23 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use futures_util::{FutureExt, StreamExt};
     use p2panda_core::PrivateKey;
-    use p2panda_sync::TopicQuery;
+    use p2panda_sync::{SyncFilter, TopicQuery};
     use serde::{Deserialize, Serialize};
     use tokio::sync::{mpsc, oneshot};
     use tokio_stream::wrappers::ReceiverStream;
 
+    use crate::bytes::{FromBytes, ToBytes};
     use crate::engine::AddressBook;
-    use crate::network::FromNetwork;
+    use crate::engine::constants::RETAINED_MESSAGES_GRACE_PERIOD;
+    use crate::engine::gossip::ToGossipActor;
+    use crate::network::{FromNetwork, ToNetwork};
+    use crate::sync::manager::ToSyncActor;
+    use crate::sync::{DeltaAnnouncement, LogHeights, LogHeightsProvider};
+    use crate::topic_auth::{PrivateTopicMessage, TopicOwners, sign_subscribe_proof};
     use crate::{NodeAddress, TopicId};
 
     use super::TopicStreams;
@@ -424,6 +878,15 @@ mod tests {
         }
     }
 
+    #[derive(Debug)]
+    struct TestHeights(LogHeights);
+
+    impl LogHeightsProvider<TestTopic> for TestHeights {
+        fn log_heights(&self, _topic: &TestTopic) -> LogHeights {
+            self.0.clone()
+        }
+    }
+
     fn generate_node_addr() -> NodeAddress {
         let private_key = PrivateKey::new();
         NodeAddress::from_public_key(private_key.public_key())
@@ -449,12 +912,21 @@ mod tests {
             .add_topic_id(peer_1.public_key, topic.id())
             .await;
 
-        let mut topic_streams =
-            TopicStreams::<TestTopic>::new(gossip_actor_tx, address_book, Some(sync_actor_tx));
+        let mut topic_streams = TopicStreams::<TestTopic>::new(
+            gossip_actor_tx,
+            address_book,
+            PrivateKey::new(),
+            Some(sync_actor_tx),
+            None,
+            None,
+            false,
+            0,
+        );
 
         topic_streams
             .subscribe(
                 topic.clone(),
+                SyncFilter::default(),
                 from_network_tx,
                 to_network_rx,
                 gossip_ready_tx,
@@ -501,4 +973,720 @@ mod tests {
             }
         );
     }
+
+    #[tokio::test]
+    async fn replay_retained_messages() {
+        let (gossip_actor_tx, _gossip_actor_rx) = mpsc::channel(128);
+        let topic = TestTopic::Primary;
+        let topic_id = topic.id();
+        let peer = PrivateKey::new().public_key();
+
+        let mut topic_streams = TopicStreams::<TestTopic>::new(
+            gossip_actor_tx,
+            AddressBook::new([1; 32]),
+            PrivateKey::new(),
+            None,
+            None,
+            None,
+            false,
+            2,
+        );
+
+        let (from_network_tx, mut from_network_rx) = mpsc::channel(128);
+        let (_to_network_tx, to_network_rx) = mpsc::channel(128);
+        let (gossip_ready_tx, _) = oneshot::channel();
+        topic_streams
+            .subscribe(
+                topic.clone(),
+                SyncFilter::default(),
+                from_network_tx,
+                to_network_rx,
+                gossip_ready_tx,
+            )
+            .await
+            .unwrap();
+        topic_streams.on_gossip_joined(topic_id).await;
+
+        for bytes in ["one", "two", "three"] {
+            topic_streams
+                .on_gossip_message(topic_id, bytes.as_bytes().to_vec(), peer)
+                .await
+                .unwrap();
+        }
+        for _ in 0..3 {
+            from_network_rx.recv().await.unwrap();
+        }
+
+        // Subscribing again replays the latest messages, up to the configured number.
+        let (from_network_tx, mut from_network_rx) = mpsc::channel(128);
+        let (_to_network_tx, to_network_rx) = mpsc::channel(128);
+        let (gossip_ready_tx, _) = oneshot::channel();
+        topic_streams
+            .subscribe(
+                TestTopic::Secondary,
+                SyncFilter::default(),
+                from_network_tx,
+                to_network_rx,
+                gossip_ready_tx,
+            )
+            .await
+            .unwrap();
+
+        for bytes in [b"two".to_vec(), b"three".to_vec()] {
+            assert_eq!(
+                from_network_rx.recv().await.unwrap(),
+                FromNetwork::GossipMessage {
+                    bytes,
+                    delivered_from: peer,
+                }
+            );
+        }
+        assert!(from_network_rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replay_retained_messages_after_unsubscribe() {
+        let (gossip_actor_tx, _gossip_actor_rx) = mpsc::channel(128);
+        let topic = TestTopic::Primary;
+        let topic_id = topic.id();
+        let peer = PrivateKey::new().public_key();
+
+        let mut topic_streams = TopicStreams::<TestTopic>::new(
+            gossip_actor_tx,
+            AddressBook::new([1; 32]),
+            PrivateKey::new(),
+            None,
+            None,
+            None,
+            false,
+            2,
+        );
+
+        async fn subscribe(
+            topic_streams: &mut TopicStreams<TestTopic>,
+            topic: TestTopic,
+        ) -> mpsc::Receiver<FromNetwork> {
+            let (from_network_tx, from_network_rx) = mpsc::channel(128);
+            let (_to_network_tx, to_network_rx) = mpsc::channel(128);
+            let (gossip_ready_tx, _) = oneshot::channel();
+            topic_streams
+                .subscribe(
+                    topic,
+                    SyncFilter::default(),
+                    from_network_tx,
+                    to_network_rx,
+                    gossip_ready_tx,
+                )
+                .await
+                .unwrap();
+            from_network_rx
+        }
+
+        let mut from_network_rx = subscribe(&mut topic_streams, topic.clone()).await;
+        topic_streams.on_gossip_joined(topic_id).await;
+        topic_streams
+            .on_gossip_message(topic_id, b"hello".to_vec(), peer)
+            .await
+            .unwrap();
+        from_network_rx.recv().await.unwrap();
+
+        // Messages are replayed when subscribing again after the last subscription ended.
+        drop(from_network_rx);
+        assert_eq!(
+            topic_streams.prune_closed_streams().await.unwrap(),
+            vec![topic_id]
+        );
+        let mut from_network_rx = subscribe(&mut topic_streams, topic.clone()).await;
+        assert_eq!(
+            from_network_rx.recv().await.unwrap(),
+            FromNetwork::GossipMessage {
+                bytes: b"hello".to_vec(),
+                delivered_from: peer,
+            }
+        );
+
+        // They are removed after the grace period.
+        drop(from_network_rx);
+        topic_streams.prune_closed_streams().await.unwrap();
+        tokio::time::advance(RETAINED_MESSAGES_GRACE_PERIOD).await;
+        topic_streams.prune_closed_streams().await.unwrap();
+        let mut from_network_rx = subscribe(&mut topic_streams, topic.clone()).await;
+        assert!(from_network_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn share_subscriptions() {
+        let (gossip_actor_tx, mut gossip_actor_rx) = mpsc::channel(128);
+        let (sync_actor_tx, mut sync_actor_rx) = mpsc::channel(128);
+        let topic = TestTopic::Primary;
+        let topic_id = topic.id();
+        let peer = PrivateKey::new().public_key();
+
+        let mut topic_streams = TopicStreams::<TestTopic>::new(
+            gossip_actor_tx,
+            AddressBook::new([1; 32]),
+            PrivateKey::new(),
+            Some(sync_actor_tx),
+            None,
+            None,
+            false,
+            0,
+        );
+
+        let mut receivers = Vec::new();
+        for _ in 0..2 {
+            let (from_network_tx, from_network_rx) = mpsc::channel(128);
+            let (_to_network_tx, to_network_rx) = mpsc::channel(128);
+            let (gossip_ready_tx, _) = oneshot::channel();
+            topic_streams
+                .subscribe(
+                    topic.clone(),
+                    SyncFilter::default(),
+                    from_network_tx,
+                    to_network_rx,
+                    gossip_ready_tx,
+                )
+                .await
+                .unwrap();
+            receivers.push(from_network_rx);
+        }
+        topic_streams.on_gossip_joined(topic_id).await;
+        for _ in 0..2 {
+            assert!(matches!(
+                sync_actor_rx.recv().await,
+                Some(ToSyncActor::Filter { .. })
+            ));
+        }
+
+        // Both subscriptions share one announcement and one sync schedule.
+        assert_eq!(topic_streams.topic_ids(), vec![topic_id]);
+        topic_streams
+            .on_discovered_topic_ids(vec![topic_id], peer)
+            .await
+            .unwrap();
+        assert!(sync_actor_rx.recv().await.is_some());
+        assert!(sync_actor_rx.try_recv().is_err());
+
+        // Messages are fanned out to all subscribers.
+        topic_streams
+            .on_gossip_message(topic_id, b"hello".to_vec(), peer)
+            .await
+            .unwrap();
+        for from_network_rx in receivers.iter_mut() {
+            assert!(matches!(
+                from_network_rx.recv().await,
+                Some(FromNetwork::GossipMessage { .. })
+            ));
+        }
+
+        // The overlay is kept as long as one subscription is left.
+        let mut from_network_rx = receivers.pop().unwrap();
+        drop(receivers);
+        assert!(
+            topic_streams
+                .prune_closed_streams()
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        topic_streams
+            .on_gossip_message(topic_id, b"still here".to_vec(), peer)
+            .await
+            .unwrap();
+        assert!(from_network_rx.recv().await.is_some());
+
+        // The overlay is left after the last subscription ended.
+        drop(from_network_rx);
+        assert_eq!(
+            topic_streams.prune_closed_streams().await.unwrap(),
+            vec![topic_id]
+        );
+        assert!(topic_streams.topic_ids().is_empty());
+        assert!(matches!(
+            gossip_actor_rx.recv().await,
+            Some(ToGossipActor::Leave { topic_id: left }) if left == topic_id
+        ));
+    }
+
+    #[tokio::test]
+    async fn observe_topics() {
+        let (gossip_actor_tx, mut gossip_actor_rx) = mpsc::channel(128);
+        let (sync_actor_tx, mut sync_actor_rx) = mpsc::channel(128);
+        let (from_network_tx, mut from_network_rx) = mpsc::channel(128);
+        let topic = TestTopic::Primary;
+        let peer = PrivateKey::new().public_key();
+
+        let mut topic_streams = TopicStreams::<TestTopic>::new(
+            gossip_actor_tx,
+            AddressBook::new([1; 32]),
+            PrivateKey::new(),
+            Some(sync_actor_tx),
+            None,
+            None,
+            false,
+            0,
+        );
+        topic_streams
+            .observe(topic.clone(), SyncFilter::default(), from_network_tx)
+            .await
+            .unwrap();
+        assert!(matches!(
+            sync_actor_rx.recv().await,
+            Some(ToSyncActor::Filter { .. })
+        ));
+
+        // Observed topics are neither announced nor is their gossip overlay joined.
+        assert!(topic_streams.topic_ids().is_empty());
+        assert!(topic_streams.overlay_id(&topic.id()).is_none());
+        topic_streams.try_join_pending_gossips().await.unwrap();
+        assert!(gossip_actor_rx.try_recv().is_err());
+
+        // Sync sessions are scheduled with peers announcing the topic and deliver its data.
+        topic_streams
+            .on_discovered_topic_ids(vec![topic.id()], peer)
+            .await
+            .unwrap();
+        assert!(matches!(
+            sync_actor_rx.recv().await,
+            Some(ToSyncActor::Discovery {
+                topic: TestTopic::Primary,
+                ..
+            })
+        ));
+
+        topic_streams
+            .on_sync_message(topic.clone(), b"header".to_vec(), None, peer)
+            .await
+            .unwrap();
+        assert_eq!(
+            from_network_rx.recv().await.unwrap(),
+            FromNetwork::SyncMessage {
+                header: b"header".to_vec(),
+                payload: None,
+                delivered_from: peer,
+            }
+        );
+
+        // Stopping to observe removes the stream and closes its receiver.
+        let left_overlays = topic_streams.stop_observing(&topic).await.unwrap();
+        assert!(left_overlays.is_empty());
+        assert!(topic_streams.subscribed.is_empty());
+        assert!(topic_streams.observers.is_empty());
+        assert!(topic_streams.topic_to_stream.is_empty());
+        assert!(topic_streams.topic_id_to_stream.is_empty());
+        assert!(from_network_rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn leave_overlay_with_remaining_observers() {
+        let (gossip_actor_tx, mut gossip_actor_rx) = mpsc::channel(128);
+        let topic = TestTopic::Primary;
+        let topic_id = topic.id();
+        let peer = PrivateKey::new().public_key();
+
+        let mut topic_streams = TopicStreams::<TestTopic>::new(
+            gossip_actor_tx,
+            AddressBook::new([1; 32]),
+            PrivateKey::new(),
+            None,
+            None,
+            None,
+            false,
+            0,
+        );
+
+        let (from_network_tx, from_network_rx) = mpsc::channel(128);
+        let (_to_network_tx, to_network_rx) = mpsc::channel(128);
+        let (gossip_ready_tx, _) = oneshot::channel();
+        topic_streams
+            .subscribe(
+                topic.clone(),
+                SyncFilter::default(),
+                from_network_tx,
+                to_network_rx,
+                gossip_ready_tx,
+            )
+            .await
+            .unwrap();
+        topic_streams.on_gossip_joined(topic_id).await;
+
+        let (observer_tx, mut observer_rx) = mpsc::channel(128);
+        topic_streams
+            .observe(topic.clone(), SyncFilter::default(), observer_tx)
+            .await
+            .unwrap();
+
+        // Observers don't receive gossip messages.
+        topic_streams
+            .on_gossip_message(topic_id, b"hello".to_vec(), peer)
+            .await
+            .unwrap();
+        assert!(observer_rx.try_recv().is_err());
+
+        // The overlay is left after the subscriber ended, even though the observer remains.
+        drop(from_network_rx);
+        assert_eq!(
+            topic_streams.prune_closed_streams().await.unwrap(),
+            vec![topic_id]
+        );
+        assert!(topic_streams.topic_ids().is_empty());
+        assert!(topic_streams.overlay_id(&topic_id).is_none());
+        assert!(matches!(
+            gossip_actor_rx.recv().await,
+            Some(ToGossipActor::Leave { topic_id: left }) if left == topic_id
+        ));
+
+        // The observer keeps receiving data via sync.
+        topic_streams
+            .on_sync_message(topic.clone(), b"header".to_vec(), None, peer)
+            .await
+            .unwrap();
+        assert!(matches!(
+            observer_rx.recv().await,
+            Some(FromNetwork::SyncMessage { .. })
+        ));
+
+        // Removing the observer doesn't leave the overlay again.
+        assert!(
+            topic_streams
+                .stop_observing(&topic)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(gossip_actor_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn combine_sync_filters() {
+        let (gossip_actor_tx, _gossip_actor_rx) = mpsc::channel(128);
+        let (sync_actor_tx, mut sync_actor_rx) = mpsc::channel(128);
+        let topic = TestTopic::Primary;
+
+        let mut topic_streams = TopicStreams::<TestTopic>::new(
+            gossip_actor_tx,
+            AddressBook::new([1; 32]),
+            PrivateKey::new(),
+            Some(sync_actor_tx),
+            None,
+            None,
+            false,
+            0,
+        );
+
+        let mut receivers = Vec::new();
+        for (author, since) in [([1; 32], 10), ([2; 32], 5)] {
+            let (from_network_tx, from_network_rx) = mpsc::channel(128);
+            let (_to_network_tx, to_network_rx) = mpsc::channel(128);
+            let (gossip_ready_tx, _) = oneshot::channel();
+            let filter = SyncFilter {
+                since: Some(since),
+                authors: Some(vec![author]),
+            };
+            topic_streams
+                .subscribe(
+                    topic.clone(),
+                    filter,
+                    from_network_tx,
+                    to_network_rx,
+                    gossip_ready_tx,
+                )
+                .await
+                .unwrap();
+            receivers.push(from_network_rx);
+        }
+
+        // Sync sessions use the union of the filters of all subscriptions.
+        let Some(ToSyncActor::Filter { .. }) = sync_actor_rx.recv().await else {
+            panic!("expected filter");
+        };
+        let Some(ToSyncActor::Filter { filter, .. }) = sync_actor_rx.recv().await else {
+            panic!("expected filter");
+        };
+        assert_eq!(
+            filter,
+            SyncFilter {
+                since: Some(5),
+                authors: Some(vec![[1; 32], [2; 32]]),
+            }
+        );
+
+        // An unfiltered stream lifts the filter.
+        let (observer_tx, observer_rx) = mpsc::channel(128);
+        topic_streams
+            .observe(topic.clone(), SyncFilter::default(), observer_tx)
+            .await
+            .unwrap();
+        let Some(ToSyncActor::Filter { filter, .. }) = sync_actor_rx.recv().await else {
+            panic!("expected filter");
+        };
+        assert_eq!(filter, SyncFilter::default());
+
+        // The filter is narrowed down again after the unfiltered stream ended.
+        drop(observer_rx);
+        drop(receivers.remove(0));
+        topic_streams.prune_closed_streams().await.unwrap();
+        let mut filter = None;
+        while let Ok(ToSyncActor::Filter { filter: latest, .. }) = sync_actor_rx.try_recv() {
+            filter = Some(latest);
+        }
+        assert_eq!(
+            filter,
+            Some(SyncFilter {
+                since: Some(5),
+                authors: Some(vec![[2; 32]]),
+            })
+        );
+
+        // The filter is cleared after the last stream ended.
+        drop(receivers);
+        topic_streams.prune_closed_streams().await.unwrap();
+        let Some(ToSyncActor::Filter { filter, .. }) = sync_actor_rx.recv().await else {
+            panic!("expected filter");
+        };
+        assert_eq!(filter, SyncFilter::default());
+    }
+
+    #[tokio::test]
+    async fn delta_announcements() {
+        let (gossip_actor_tx, _gossip_actor_rx) = mpsc::channel(128);
+        let (sync_actor_tx, mut sync_actor_rx) = mpsc::channel(128);
+        let topic = TestTopic::Primary;
+        let topic_id = topic.id();
+        let author = PrivateKey::new();
+        let forwarder = PrivateKey::new().public_key();
+
+        let mut topic_streams = TopicStreams::<TestTopic>::new(
+            gossip_actor_tx,
+            AddressBook::new([1; 32]),
+            PrivateKey::new(),
+            Some(sync_actor_tx),
+            Some(Arc::new(TestHeights(Vec::new()))),
+            None,
+            false,
+            0,
+        );
+
+        let (from_network_tx, mut from_network_rx) = mpsc::channel(128);
+        let (_to_network_tx, to_network_rx) = mpsc::channel(128);
+        let (gossip_ready_tx, _) = oneshot::channel();
+        topic_streams
+            .subscribe(
+                topic.clone(),
+                SyncFilter::default(),
+                from_network_tx,
+                to_network_rx,
+                gossip_ready_tx,
+            )
+            .await
+            .unwrap();
+        topic_streams.on_gossip_joined(topic_id).await;
+        assert!(matches!(
+            sync_actor_rx.recv().await,
+            Some(ToSyncActor::Filter { .. })
+        ));
+
+        // We sync with the author of the announcement, not the peer which forwarded it.
+        let announcement = DeltaAnnouncement::new(
+            &author,
+            topic_id,
+            vec![(author.public_key(), 3)],
+            b"hello".to_vec(),
+        );
+        topic_streams
+            .on_gossip_message(topic_id, announcement.encode(), forwarder)
+            .await
+            .unwrap();
+        assert!(matches!(
+            sync_actor_rx.recv().await,
+            Some(ToSyncActor::Behind { peer, .. }) if peer == author.public_key()
+        ));
+        assert_eq!(
+            from_network_rx.recv().await.unwrap(),
+            FromNetwork::GossipMessage {
+                bytes: b"hello".to_vec(),
+                delivered_from: forwarder,
+            }
+        );
+
+        // Messages without announcement are delivered unchanged.
+        topic_streams
+            .on_gossip_message(topic_id, b"plain".to_vec(), forwarder)
+            .await
+            .unwrap();
+        assert_eq!(
+            from_network_rx.recv().await.unwrap(),
+            FromNetwork::GossipMessage {
+                bytes: b"plain".to_vec(),
+                delivered_from: forwarder,
+            }
+        );
+        assert!(sync_actor_rx.try_recv().is_err());
+
+        // Announcements with an invalid signature are dropped.
+        let announcement = DeltaAnnouncement::new(
+            &author,
+            [2; 32],
+            vec![(author.public_key(), 3)],
+            b"hello".to_vec(),
+        );
+        topic_streams
+            .on_gossip_message(topic_id, announcement.encode(), forwarder)
+            .await
+            .unwrap();
+        assert!(from_network_rx.try_recv().is_err());
+        assert!(sync_actor_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn unwrap_delta_announcements_when_disabled() {
+        let (gossip_actor_tx, _gossip_actor_rx) = mpsc::channel(128);
+        let (sync_actor_tx, _sync_actor_rx) = mpsc::channel(128);
+        let topic = TestTopic::Primary;
+        let topic_id = topic.id();
+        let author = PrivateKey::new();
+
+        let mut topic_streams = TopicStreams::<TestTopic>::new(
+            gossip_actor_tx,
+            AddressBook::new([1; 32]),
+            PrivateKey::new(),
+            Some(sync_actor_tx),
+            None,
+            None,
+            false,
+            0,
+        );
+
+        let (from_network_tx, mut from_network_rx) = mpsc::channel(128);
+        let (_to_network_tx, to_network_rx) = mpsc::channel(128);
+        let (gossip_ready_tx, _) = oneshot::channel();
+        topic_streams
+            .subscribe(
+                topic.clone(),
+                SyncFilter::default(),
+                from_network_tx,
+                to_network_rx,
+                gossip_ready_tx,
+            )
+            .await
+            .unwrap();
+        topic_streams.on_gossip_joined(topic_id).await;
+
+        // Peers without delta announcements receive the message of an announcement.
+        let announcement = DeltaAnnouncement::new(
+            &author,
+            topic_id,
+            vec![(author.public_key(), 3)],
+            b"hello".to_vec(),
+        );
+        topic_streams
+            .on_gossip_message(topic_id, announcement.encode(), author.public_key())
+            .await
+            .unwrap();
+        assert_eq!(
+            from_network_rx.recv().await.unwrap(),
+            FromNetwork::GossipMessage {
+                bytes: b"hello".to_vec(),
+                delivered_from: author.public_key(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn authenticate_private_topic_messages() {
+        let (gossip_actor_tx, mut gossip_actor_rx) = mpsc::channel(128);
+        let topic = TestTopic::Primary;
+        let topic_id = topic.id();
+        let owner = PrivateKey::new();
+        let private_key = PrivateKey::new();
+        let member = PrivateKey::new();
+        let stranger = PrivateKey::new();
+
+        let authenticator = TopicOwners::new()
+            .private_topic(topic_id, owner.public_key())
+            .own_proof(
+                topic_id,
+                sign_subscribe_proof(&owner, &topic_id, &private_key.public_key()),
+            );
+        let member_proof = sign_subscribe_proof(&owner, &topic_id, &member.public_key())
+            .to_bytes()
+            .to_vec();
+
+        let mut topic_streams = TopicStreams::<TestTopic>::new(
+            gossip_actor_tx,
+            AddressBook::new([1; 32]),
+            private_key.clone(),
+            None,
+            None,
+            Some(Arc::new(authenticator.clone())),
+            false,
+            0,
+        );
+
+        let (from_network_tx, mut from_network_rx) = mpsc::channel(128);
+        let (to_network_tx, to_network_rx) = mpsc::channel(128);
+        let (gossip_ready_tx, _) = oneshot::channel();
+        topic_streams
+            .subscribe(
+                topic.clone(),
+                SyncFilter::default(),
+                from_network_tx,
+                to_network_rx,
+                gossip_ready_tx,
+            )
+            .await
+            .unwrap();
+        topic_streams.on_gossip_joined(topic_id).await;
+
+        // Our messages are signed and carry our proof.
+        to_network_tx
+            .send(ToNetwork::Message {
+                bytes: b"hello".to_vec(),
+            })
+            .await
+            .unwrap();
+        let bytes = loop {
+            if let Some(ToGossipActor::Broadcast { bytes, .. }) = gossip_actor_rx.recv().await {
+                break bytes;
+            }
+        };
+        let message = PrivateTopicMessage::decode(&authenticator, &topic_id, &bytes).unwrap();
+        assert_eq!(message.author, private_key.public_key());
+        assert_eq!(message.bytes, b"hello".to_vec());
+
+        // The stranger is our neighbour in the overlay but can't prove access to the topic. Its own
+        // messages are dropped, unsigned or presenting the proof of the member.
+        let forged =
+            PrivateTopicMessage::new(&stranger, &topic_id, member_proof.clone(), b"spam".to_vec());
+        for bytes in [b"spam".to_vec(), forged.to_bytes()] {
+            topic_streams
+                .on_gossip_message(topic_id, bytes, stranger.public_key())
+                .await
+                .unwrap();
+        }
+
+        // Messages of the member are accepted, even when they are forwarded by the stranger ..
+        let message =
+            PrivateTopicMessage::new(&member, &topic_id, member_proof.clone(), b"hi".to_vec());
+        topic_streams
+            .on_gossip_message(topic_id, message.to_bytes(), stranger.public_key())
+            .await
+            .unwrap();
+        assert_eq!(
+            from_network_rx.recv().await.unwrap(),
+            FromNetwork::GossipMessage {
+                bytes: b"hi".to_vec(),
+                delivered_from: stranger.public_key(),
+            }
+        );
+
+        // .. unless the stranger tampered with them.
+        let mut tampered = PrivateTopicMessage::from_bytes(&message.to_bytes()).unwrap();
+        tampered.bytes = b"spam".to_vec();
+        topic_streams
+            .on_gossip_message(topic_id, tampered.to_bytes(), stranger.public_key())
+            .await
+            .unwrap();
+        assert!(from_network_rx.try_recv().is_err());
+    }
 }
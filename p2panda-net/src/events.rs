@@ -3,6 +3,8 @@
 //! System events API.
 use p2panda_core::PublicKey;
 
+use crate::mailbox::{DeliveryReceipt, MailboxMessage};
+
 /// Network system events.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum SystemEvent<T> {
@@ -25,9 +27,57 @@ pub enum SystemEvent<T> {
     /// This event will be emitted approximately 30 seconds after the connection is lost.
     GossipNeighborDown { topic_id: [u8; 32], peer: PublicKey },
 
+    /// The gossip overlay of a subscribed topic is suspected to be partitioned, see
+    /// `Network::overlay_health`.
+    OverlayPartitionSuspected { topic_id: [u8; 32] },
+
+    /// Dropped a gossip message which exceeded the maximum size of chunked messages, see
+    /// `GossipConfig::max_chunked_message_size`.
+    GossipMessageTooLarge {
+        topic_id: [u8; 32],
+        size: usize,
+        max_size: usize,
+    },
+
+    /// Collected a message addressed to us from a mailbox node, see `NetworkBuilder::mailbox`.
+    MailboxMessage {
+        mailbox: PublicKey,
+        message: MailboxMessage,
+    },
+
+    /// A message we've deposited at a mailbox node was collected by its recipient.
+    MailboxMessageDelivered {
+        mailbox: PublicKey,
+        receipt: DeliveryReceipt,
+    },
+
     /// Discovered a new peer in the network.
     PeerDiscovered { peer: PublicKey },
 
+    /// Received the first presence heartbeat of a peer on a topic, see `NetworkBuilder::presence`.
+    PeerOnline {
+        topic_id: [u8; 32],
+        peer: PublicKey,
+        status: Vec<u8>,
+    },
+
+    /// A present peer announced a new status on a topic.
+    PeerStatusChanged {
+        topic_id: [u8; 32],
+        peer: PublicKey,
+        status: Vec<u8>,
+    },
+
+    /// A peer didn't send a presence heartbeat on a topic within the timeout.
+    PeerOffline { topic_id: [u8; 32], peer: PublicKey },
+
+    /// A peer announced a child topic of a parent topic we're subscribed to, see
+    /// `TopicId::parent_id`.
+    ChildTopicDiscovered {
+        parent_id: [u8; 32],
+        topic_id: [u8; 32],
+    },
+
     /// Started a sync session.
     SyncStarted { topic: Option<T>, peer: PublicKey },
 
@@ -36,4 +86,12 @@ pub enum SystemEvent<T> {
 
     /// Failed to complete a sync session.
     SyncFailed { topic: Option<T>, peer: PublicKey },
+
+    /// Restarted an internal subsystem after it panicked.
+    ///
+    /// This event is only emitted when the node is configured with `PanicPolicy::Isolate`.
+    SubsystemRestarted {
+        subsystem: &'static str,
+        attempt: u32,
+    },
 }
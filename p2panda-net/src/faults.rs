@@ -0,0 +1,326 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Fault injection for integration tests.
+//!
+//! A [`FaultInjector`] is handed to a node with `NetworkBuilder::fault_injector` and controls
+//! failures of that node at runtime: dropping a percentage of inbound gossip messages, delaying
+//! the frames of sync sessions, killing the connections to a specific peer and panicking the
+//! engine actor. This allows tests to exercise failure paths deterministically instead of relying
+//! on sleeps and timing.
+//!
+//! Random gossip drops are decided by a seedable random number generator, use
+//! [`FaultInjector::with_seed`] to get the same decisions in every run.
+//!
+//! Only available with the `fault-injection` feature, which is not meant to be enabled in
+//! production builds.
+#![cfg_attr(not(feature = "fault-injection"), allow(dead_code))]
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, ready};
+
+use futures_util::{AsyncRead, AsyncWrite};
+use iroh::endpoint::{Connection, VarInt};
+use p2panda_core::PublicKey;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use thiserror::Error;
+use tokio::time::{Duration, Sleep, sleep};
+
+/// Error code used when closing connections to a partitioned peer.
+const KILLED_ERROR_CODE: u32 = 1;
+
+/// Handle injecting faults into a node.
+///
+/// The handle can be cloned, all clones control the same node.
+#[derive(Clone, Debug)]
+pub struct FaultInjector {
+    inner: Arc<Mutex<Faults>>,
+}
+
+#[derive(Debug)]
+struct Faults {
+    rng: StdRng,
+    gossip_drop_percent: u8,
+    sync_frame_delay: Option<Duration>,
+    killed_peers: HashSet<PublicKey>,
+    connections: Vec<(PublicKey, Connection)>,
+    panic_engine: bool,
+}
+
+impl FaultInjector {
+    /// Returns a fault injector without any active faults.
+    pub fn new() -> Self {
+        Self::from_rng(StdRng::from_entropy())
+    }
+
+    /// Returns a fault injector without any active faults, deciding random faults with a random
+    /// number generator initialised from the given seed.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::from_rng(StdRng::seed_from_u64(seed))
+    }
+
+    fn from_rng(rng: StdRng) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Faults {
+                rng,
+                gossip_drop_percent: 0,
+                sync_frame_delay: None,
+                killed_peers: HashSet::new(),
+                connections: Vec::new(),
+                panic_engine: false,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Faults> {
+        self.inner.lock().expect("fault injector lock was poisoned")
+    }
+
+    /// Drops the given percentage of inbound gossip messages, values above 100 drop all messages.
+    ///
+    /// Use 0 to stop dropping messages.
+    pub fn drop_gossip(&self, percent: u8) {
+        self.lock().gossip_drop_percent = percent.min(100);
+    }
+
+    /// Delays every frame read or written in sync sessions by the given duration.
+    pub fn delay_sync_frames(&self, delay: Duration) {
+        self.lock().sync_frame_delay = (!delay.is_zero()).then_some(delay);
+    }
+
+    /// Closes all sync connections to the given peer and partitions it from the node.
+    ///
+    /// Until the peer is healed, new sync connections to and from the peer are closed right away
+    /// and gossip messages delivered by the peer are dropped.
+    pub fn kill_connections(&self, peer: PublicKey) {
+        let mut faults = self.lock();
+        faults.killed_peers.insert(peer);
+        faults.connections.retain(|(connected_peer, connection)| {
+            if *connected_peer == peer {
+                close(connection);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Allows connections to the given peer again.
+    pub fn heal(&self, peer: PublicKey) {
+        self.lock().killed_peers.remove(&peer);
+    }
+
+    /// Panics the engine actor the next time it handles a message.
+    ///
+    /// Used to test how the node reacts to panics, see `PanicPolicy`.
+    pub fn panic_engine(&self) {
+        self.lock().panic_engine = true;
+    }
+
+    /// Removes all active faults.
+    pub fn reset(&self) {
+        let mut faults = self.lock();
+        faults.gossip_drop_percent = 0;
+        faults.sync_frame_delay = None;
+        faults.killed_peers.clear();
+        faults.panic_engine = false;
+    }
+
+    /// Returns `true` if an inbound gossip message delivered by the given peer should be dropped.
+    pub(crate) fn drops_gossip(&self, delivered_from: &PublicKey) -> bool {
+        let mut faults = self.lock();
+        if faults.killed_peers.contains(delivered_from) {
+            return true;
+        }
+        let percent = faults.gossip_drop_percent;
+        percent > 0 && faults.rng.gen_range(0..100) < percent
+    }
+
+    /// Returns `true` once if the engine actor should panic, see `panic_engine`.
+    pub(crate) fn take_engine_panic(&self) -> bool {
+        std::mem::take(&mut self.lock().panic_engine)
+    }
+
+    /// Returns the delay applied to every frame of sync sessions.
+    pub(crate) fn sync_frame_delay(&self) -> Option<Duration> {
+        self.lock().sync_frame_delay
+    }
+
+    /// Registers an established connection to the given peer, so it can be killed later.
+    ///
+    /// Returns an error and closes the connection right away if connections to the peer are
+    /// currently killed.
+    pub(crate) fn register_connection(
+        &self,
+        peer: PublicKey,
+        connection: &Connection,
+    ) -> Result<(), ConnectionKilled> {
+        let mut faults = self.lock();
+        if faults.killed_peers.contains(&peer) {
+            close(connection);
+            return Err(ConnectionKilled(Box::new(peer)));
+        }
+        faults
+            .connections
+            .retain(|(_, connection)| connection.close_reason().is_none());
+        faults.connections.push((peer, connection.clone()));
+        Ok(())
+    }
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn close(connection: &Connection) {
+    connection.close(
+        VarInt::from_u32(KILLED_ERROR_CODE),
+        b"killed by fault injector",
+    );
+}
+
+/// Connection was closed by the fault injector.
+#[derive(Debug, Error)]
+#[error("connections to {0} are killed by fault injector")]
+pub(crate) struct ConnectionKilled(Box<PublicKey>);
+
+/// Wrapper around a send or receive stream delaying every read and write by the given duration,
+/// if a delay is given.
+pub(crate) struct Delayed<'a, S> {
+    inner: &'a mut S,
+    delay: Option<Duration>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<'a, S> Delayed<'a, S> {
+    pub fn new(inner: &'a mut S, delay: Option<Duration>) -> Self {
+        Self {
+            inner,
+            delay,
+            sleep: None,
+        }
+    }
+
+    /// Waits for the delay of the next frame.
+    fn poll_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(delay) = self.delay else {
+            return Poll::Ready(());
+        };
+        let timer = self.sleep.get_or_insert_with(|| Box::pin(sleep(delay)));
+        ready!(timer.as_mut().poll(cx));
+        Poll::Ready(())
+    }
+
+    /// Resets the delay once a frame was transferred.
+    fn consume<T>(&mut self, result: &Poll<std::io::Result<T>>) {
+        if result.is_ready() {
+            self.sleep = None;
+        }
+    }
+}
+
+impl<S> AsyncWrite for Delayed<'_, S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.poll_delay(cx));
+        let result = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        self.consume(&result);
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_close(cx)
+    }
+}
+
+impl<S> AsyncRead for Delayed<'_, S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.poll_delay(cx));
+        let result = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        self.consume(&result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::AsyncWriteExt;
+    use p2panda_core::PrivateKey;
+    use tokio::time::{Duration, Instant};
+
+    use super::{Delayed, FaultInjector};
+
+    #[test]
+    fn drop_gossip_deterministically() {
+        let peer = PrivateKey::new().public_key();
+
+        let decisions = |seed| {
+            let faults = FaultInjector::with_seed(seed);
+            faults.drop_gossip(30);
+            (0..100)
+                .map(|_| faults.drops_gossip(&peer))
+                .collect::<Vec<bool>>()
+        };
+
+        let dropped = decisions(7);
+        assert_eq!(dropped, decisions(7));
+        assert!(dropped.iter().any(|dropped| *dropped));
+        assert!(dropped.iter().any(|dropped| !*dropped));
+
+        let faults = FaultInjector::with_seed(7);
+        assert!(!faults.drops_gossip(&peer));
+        faults.drop_gossip(100);
+        assert!(faults.drops_gossip(&peer));
+        faults.reset();
+        assert!(!faults.drops_gossip(&peer));
+    }
+
+    #[test]
+    fn killed_peers_are_partitioned() {
+        let peer = PrivateKey::new().public_key();
+        let other_peer = PrivateKey::new().public_key();
+
+        let faults = FaultInjector::new();
+        faults.kill_connections(peer);
+        assert!(faults.drops_gossip(&peer));
+        assert!(!faults.drops_gossip(&other_peer));
+
+        faults.heal(peer);
+        assert!(!faults.drops_gossip(&peer));
+    }
+
+    #[tokio::test]
+    async fn delay_frames() {
+        let mut buf = Vec::new();
+        let mut delayed = Delayed::new(&mut buf, Some(Duration::from_millis(20)));
+
+        let started = Instant::now();
+        for _ in 0..3 {
+            delayed.write_all(b"frame").await.unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(60));
+        assert_eq!(buf.len(), 15);
+    }
+}
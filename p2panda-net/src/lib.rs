@@ -48,6 +48,14 @@
 //! topologies on top of BLE (Bluetooth Low Energy), LoRa or even Digital Radio Communication
 //! infrastructure.
 //!
+//! ## Browsers
+//!
+//! `p2panda-net` can be compiled to WebAssembly to run nodes in the browser. Browsers can't open
+//! UDP sockets, web clients therefore connect to other peers via a relay server over a WebSocket,
+//! see `Transport`. Gossip overlays and sync protocols work the same as on native platforms, web
+//! clients join the same networks as native peers using the same relay. The `mdns-discovery`
+//! feature is not available in browsers.
+//!
 //! ## Extensions
 //!
 //! `p2panda-net` is agnostic to any data type (sending and receiving raw byte streams) and can
@@ -142,22 +150,62 @@
 //! ```
 mod addrs;
 mod bytes;
+pub mod compression;
 pub mod config;
+mod dial;
 mod engine;
 mod events;
+mod faults;
+mod mailbox;
 pub mod network;
+mod overlay_health;
+mod presence;
 mod protocols;
+mod providers;
+mod relay_usage;
+mod reputation;
+mod roles;
+mod supervisor;
 mod sync;
+pub mod telemetry;
+mod topic_auth;
+mod topic_tree;
+pub mod transport;
+mod typed;
 
 pub use addrs::{NodeAddress, RelayUrl};
-pub use config::Config;
+pub use compression::{CompressionAlgorithm, CompressionConfig, CompressionMetrics};
+pub use config::{Config, PanicPolicy};
+pub use dial::{ConnectError, ConnectResult, DIAL_ALPN, DIAL_TIMEOUT};
 pub use events::SystemEvent;
+#[cfg(feature = "fault-injection")]
+pub use faults::FaultInjector;
+pub use mailbox::{
+    DeliveryReceipt, MAILBOX_ALPN, MAX_MAILBOX_MESSAGE_SIZE, Mailbox, MailboxConfig, MailboxMessage,
+};
 pub use network::{FromNetwork, Network, NetworkBuilder, RelayMode, ToNetwork};
-pub use protocols::ProtocolHandler;
-pub use sync::{ResyncConfiguration, SyncConfiguration};
+pub use overlay_health::OverlayHealth;
+pub use presence::{MAX_PRESENCE_STATUS_LEN, PeerPresence, PresenceConfig};
+pub use protocols::{AlpnNamespace, ProtocolHandler};
+pub use providers::{BlobFilter, MAX_BLOB_FILTER_LEN};
+pub use relay_usage::{ConnectionPath, PeerRelayUsage, RelayPolicy, RelayUsage};
+pub use reputation::{
+    DEFAULT_BAN_THRESHOLD, MAX_REPUTATION_SCORE, Misbehaviour, Reputation, ReputationConfig,
+    ReputationStore,
+};
+pub use roles::{NodeRole, NodeRoles};
+pub use sync::{
+    LogHeights, LogHeightsProvider, QuotaExemptions, ResyncConfiguration, SyncConfiguration,
+    SyncOutcome, SyncQuotas, SyncRole, SyncTranscript, TranscriptEntry, TranscriptSink,
+};
+pub use topic_auth::{TopicAuthenticator, TopicOwners, sign_subscribe_proof};
+pub use topic_tree::{MAX_CHILD_TOPICS, derive_child_topic_id};
+pub use transport::Transport;
+pub use typed::{TypedFromNetwork, TypedReceiver, TypedSender};
 
 #[cfg(feature = "log-sync")]
 pub use p2panda_sync::log_sync::LogSyncProtocol;
+pub use p2panda_sync::{SyncEstimate, SyncFilter};
 
 /// Unique 32 byte identifier for a network.
 ///
@@ -201,8 +249,21 @@ pub type NetworkId = [u8; 32];
 /// `TopicQuery` is a query for sync protocols to ask for a specific piece of information.
 ///
 /// Consult the `TopicQuery` documentation in `p2panda-sync` for further information.
+///
+/// ## Hierarchical topics
+///
+/// Topics can be child topics of a parent topic by returning the parent id from `parent_id`, for
+/// example to express many small channels within one community. All child topics of a parent
+/// share a single gossip overlay instead of one overlay per channel and are announced together
+/// with their parent, nodes subscribed to the parent can enumerate them with
+/// `Network::child_topics`. Ids of child topics can be derived with `derive_child_topic_id`.
 pub trait TopicId {
     fn id(&self) -> [u8; 32];
+
+    /// Returns the id of the parent topic if this is a child topic.
+    fn parent_id(&self) -> Option<[u8; 32]> {
+        None
+    }
 }
 
 /// Converts an `iroh` public key type to the `p2panda-core` implementation.
@@ -0,0 +1,672 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Store-and-forward mailboxes for direct messages to offline peers.
+//!
+//! Gossip and sync replicate the data of topics between all interested peers which are online at
+//! the same time. To deliver a message to a single peer which might currently be offline, nodes
+//! can deposit it at an always-on "mailbox" node instead. The mailbox node stores the message
+//! addressed to the public key of the recipient until the recipient collects it.
+//!
+//! 1. A node enables the mailbox protocol with `NetworkBuilder::mailbox_server` and keeps
+//!    messages for offline peers, within the configured limits.
+//! 2. Senders deposit messages with `Network::send_to_mailbox` and receive the id of the stored
+//!    message.
+//! 3. Recipients collect their messages from mailbox nodes registered with
+//!    `NetworkBuilder::mailbox`, every few seconds and as soon as they reconnect. Collected
+//!    messages are reported as `SystemEvent::MailboxMessage` and acknowledged to the mailbox node,
+//!    which removes them. Messages can also be collected manually with `Network::collect_mailbox`.
+//! 4. Acknowledged messages turn into delivery receipts for the sender, which are collected the
+//!    same way and reported as `SystemEvent::MailboxMessageDelivered`.
+//!
+//! Senders and recipients are authenticated by their connection to the mailbox node, only the
+//! recipient can collect its messages. The mailbox node can read all payloads though, applications
+//! should encrypt them for the recipient before depositing them.
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, anyhow, bail};
+use futures_lite::future::Boxed as BoxedFuture;
+use iroh::Endpoint;
+use iroh::endpoint::{Connecting, Connection};
+use p2panda_core::{Hash, PublicKey};
+use rand::random;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::bytes::{FromBytes, ToBytes};
+use crate::protocols::ProtocolHandler;
+use crate::{from_public_key, to_public_key};
+
+/// ALPN identifier of the mailbox protocol.
+pub const MAILBOX_ALPN: &[u8] = b"/p2panda-net-mailbox/1";
+
+/// Maximum size of a single message payload in bytes, configurations can only lower it.
+pub const MAX_MAILBOX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Default maximum size of a single message payload in bytes.
+pub const DEFAULT_MAX_MAILBOX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Default maximum number of messages stored for a single recipient.
+pub const DEFAULT_MAX_MAILBOX_MESSAGES: usize = 256;
+
+/// Default maximum size of all stored message payloads in bytes.
+pub const DEFAULT_MAX_MAILBOX_SIZE: usize = 64 * 1024 * 1024;
+
+/// Default maximum size of all stored message payloads of a single sender in bytes.
+pub const DEFAULT_MAX_MAILBOX_SENDER_SIZE: usize = 4 * 1024 * 1024;
+
+/// Default duration after which uncollected messages are removed.
+pub const DEFAULT_MAILBOX_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Interval in which registered mailbox nodes are checked for new messages.
+pub(crate) const MAILBOX_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum size of all payloads returned from a single collection, further messages are returned
+/// with the next one.
+const MAX_COLLECTED_SIZE: usize = 1024 * 1024;
+
+/// Maximum size of an encoded response, covering a full collection and its encoding overhead.
+const MAX_RESPONSE_SIZE: usize = 4 * MAX_COLLECTED_SIZE;
+
+/// Encoding overhead of a request next to its payload.
+const MAX_REQUEST_OVERHEAD: usize = 1024;
+
+/// Configuration of the messages a mailbox node keeps for offline peers.
+#[derive(Clone, Debug)]
+pub struct MailboxConfig {
+    max_messages: usize,
+    max_message_size: usize,
+    max_size: usize,
+    max_sender_size: usize,
+    retention: Duration,
+}
+
+impl MailboxConfig {
+    /// Returns a configuration with the default limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define the maximum number of messages stored for a single recipient.
+    ///
+    /// Deposits are rejected when the mailbox of the recipient is full.
+    ///
+    /// Default: 256.
+    pub fn max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = max_messages;
+        self
+    }
+
+    /// Define the maximum size of a single message payload in bytes, capped at
+    /// `MAX_MAILBOX_MESSAGE_SIZE`.
+    ///
+    /// Default: 64 KiB.
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size.min(MAX_MAILBOX_MESSAGE_SIZE);
+        self
+    }
+
+    /// Define the maximum size of all stored message payloads in bytes.
+    ///
+    /// Deposits are rejected when storing the message would exceed it, regardless of sender and
+    /// recipient. This bounds the memory a mailbox node spends on messages for offline peers.
+    ///
+    /// Default: 64 MiB.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Define the maximum size of all stored message payloads of a single sender in bytes.
+    ///
+    /// Deposits are rejected when storing the message would exceed the quota of its sender, no
+    /// matter to how many recipients it sends messages. Collected and expired messages free up
+    /// the quota again.
+    ///
+    /// Default: 4 MiB.
+    pub fn max_sender_size(mut self, max_sender_size: usize) -> Self {
+        self.max_sender_size = max_sender_size;
+        self
+    }
+
+    /// Define the duration after which uncollected messages and delivery receipts are removed.
+    ///
+    /// Default: 7 days.
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+}
+
+impl Default for MailboxConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: DEFAULT_MAX_MAILBOX_MESSAGES,
+            max_message_size: DEFAULT_MAX_MAILBOX_MESSAGE_SIZE,
+            max_size: DEFAULT_MAX_MAILBOX_SIZE,
+            max_sender_size: DEFAULT_MAX_MAILBOX_SENDER_SIZE,
+            retention: DEFAULT_MAILBOX_RETENTION,
+        }
+    }
+}
+
+/// Message stored at a mailbox node for a recipient.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MailboxMessage {
+    /// Id assigned by the mailbox node when the message was deposited.
+    pub id: Hash,
+    pub sender: PublicKey,
+    pub payload: Vec<u8>,
+}
+
+/// Receipt of a message collected by its recipient.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeliveryReceipt {
+    /// Id of the delivered message.
+    pub id: Hash,
+    pub recipient: PublicKey,
+}
+
+/// Messages and delivery receipts collected from a mailbox node.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mailbox {
+    /// Messages addressed to us.
+    pub messages: Vec<MailboxMessage>,
+
+    /// Receipts of messages we've sent which were collected by their recipients.
+    pub receipts: Vec<DeliveryReceipt>,
+}
+
+impl Mailbox {
+    /// Returns `true` if neither messages nor receipts were collected.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.receipts.is_empty()
+    }
+
+    /// Returns the ids of all collected messages and receipts.
+    pub(crate) fn ids(&self) -> Vec<Hash> {
+        self.messages
+            .iter()
+            .map(|message| message.id)
+            .chain(self.receipts.iter().map(|receipt| receipt.id))
+            .collect()
+    }
+}
+
+/// Deposit rejected by the mailbox node.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(crate) enum DepositError {
+    #[error("message of {size} bytes exceeds maximum size of {max_size} bytes")]
+    MessageTooLarge { size: usize, max_size: usize },
+
+    #[error("mailbox is full")]
+    MailboxFull,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum MailboxRequest {
+    /// Store a message for the given recipient.
+    Deposit {
+        recipient: PublicKey,
+        payload: Vec<u8>,
+    },
+
+    /// Return messages addressed to the requesting peer and receipts of messages it has sent.
+    Collect,
+
+    /// Remove the collected messages and receipts with the given ids.
+    Ack(Vec<Hash>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum MailboxResponse {
+    Deposited(Hash),
+    Rejected(String),
+    Collected(Mailbox),
+    Acked,
+}
+
+#[derive(Debug)]
+struct StoredMessage {
+    message: MailboxMessage,
+    stored_at: Instant,
+}
+
+#[derive(Debug)]
+struct StoredReceipt {
+    receipt: DeliveryReceipt,
+    stored_at: Instant,
+}
+
+/// Messages and delivery receipts kept by a mailbox node, per peer.
+#[derive(Debug)]
+pub(crate) struct MailboxStore {
+    config: MailboxConfig,
+    messages: HashMap<PublicKey, VecDeque<StoredMessage>>,
+    receipts: HashMap<PublicKey, VecDeque<StoredReceipt>>,
+
+    /// Size of all stored message payloads in bytes.
+    size: usize,
+
+    /// Size of all stored message payloads per sender in bytes.
+    sender_sizes: HashMap<PublicKey, usize>,
+}
+
+impl MailboxStore {
+    pub fn new(config: MailboxConfig) -> Self {
+        Self {
+            config,
+            messages: HashMap::new(),
+            receipts: HashMap::new(),
+            size: 0,
+            sender_sizes: HashMap::new(),
+        }
+    }
+
+    /// Stores a message for the recipient and returns its id.
+    ///
+    /// Deposits are rejected when the mailbox of the recipient, the quota of the sender or the
+    /// storage of the mailbox node is full.
+    pub fn deposit(
+        &mut self,
+        sender: PublicKey,
+        recipient: PublicKey,
+        payload: Vec<u8>,
+    ) -> Result<Hash, DepositError> {
+        if payload.len() > self.config.max_message_size {
+            return Err(DepositError::MessageTooLarge {
+                size: payload.len(),
+                max_size: self.config.max_message_size,
+            });
+        }
+
+        let size = payload.len();
+        let sender_size = self.sender_sizes.get(&sender).copied().unwrap_or_default();
+        if self.size + size > self.config.max_size
+            || sender_size + size > self.config.max_sender_size
+        {
+            return Err(DepositError::MailboxFull);
+        }
+
+        let messages = self.messages.entry(recipient).or_default();
+        if messages.len() >= self.config.max_messages {
+            return Err(DepositError::MailboxFull);
+        }
+
+        // Ids are unique, even when the same payload is sent twice.
+        let nonce: [u8; 16] = random();
+        let id = Hash::new((sender, recipient, &payload, nonce).to_bytes());
+        messages.push_back(StoredMessage {
+            message: MailboxMessage {
+                id,
+                sender,
+                payload,
+            },
+            stored_at: Instant::now(),
+        });
+        self.size += size;
+        *self.sender_sizes.entry(sender).or_default() += size;
+
+        Ok(id)
+    }
+
+    /// Returns the messages addressed to the peer and the receipts of messages it has sent, in
+    /// the order they were stored.
+    ///
+    /// Nothing is removed until the peer acknowledges the collection. Collections are limited in
+    /// size, remaining messages are returned once the previous ones were acknowledged.
+    pub fn collect(&self, peer: &PublicKey) -> Mailbox {
+        let mut size = 0;
+        let messages = self
+            .messages
+            .get(peer)
+            .into_iter()
+            .flatten()
+            .take_while(|stored| {
+                let fits = size == 0 || size + stored.message.payload.len() <= MAX_COLLECTED_SIZE;
+                size += stored.message.payload.len();
+                fits
+            })
+            .map(|stored| stored.message.clone())
+            .collect();
+        let receipts = self
+            .receipts
+            .get(peer)
+            .into_iter()
+            .flatten()
+            .map(|stored| stored.receipt.clone())
+            .collect();
+        Mailbox { messages, receipts }
+    }
+
+    /// Removes the acknowledged messages addressed to the peer and receipts of messages it has
+    /// sent.
+    ///
+    /// Every acknowledged message turns into a delivery receipt for its sender.
+    pub fn ack(&mut self, peer: PublicKey, ids: &[Hash]) {
+        if let Some(messages) = self.messages.get_mut(&peer) {
+            let mut delivered = Vec::new();
+            messages.retain(|stored| {
+                let acked = ids.contains(&stored.message.id);
+                if acked {
+                    delivered.push((
+                        stored.message.sender,
+                        stored.message.id,
+                        stored.message.payload.len(),
+                    ));
+                }
+                !acked
+            });
+
+            for (sender, id, size) in delivered {
+                self.release(sender, size);
+                let receipts = self.receipts.entry(sender).or_default();
+                // Receipts are informational, the oldest ones are dropped when the sender
+                // doesn't collect them.
+                if receipts.len() >= self.config.max_messages {
+                    receipts.pop_front();
+                }
+                receipts.push_back(StoredReceipt {
+                    receipt: DeliveryReceipt {
+                        id,
+                        recipient: peer,
+                    },
+                    stored_at: Instant::now(),
+                });
+            }
+        }
+
+        if let Some(receipts) = self.receipts.get_mut(&peer) {
+            receipts.retain(|stored| !ids.contains(&stored.receipt.id));
+        }
+
+        self.remove_empty();
+    }
+
+    /// Removes all messages and receipts stored longer than the retention period.
+    pub fn expire(&mut self) {
+        let retention = self.config.retention;
+        let mut expired = Vec::new();
+        for messages in self.messages.values_mut() {
+            messages.retain(|stored| {
+                let keep = stored.stored_at.elapsed() < retention;
+                if !keep {
+                    expired.push((stored.message.sender, stored.message.payload.len()));
+                }
+                keep
+            });
+        }
+        for (sender, size) in expired {
+            self.release(sender, size);
+        }
+        for receipts in self.receipts.values_mut() {
+            receipts.retain(|stored| stored.stored_at.elapsed() < retention);
+        }
+        self.remove_empty();
+    }
+
+    /// Frees up the storage of a removed message.
+    fn release(&mut self, sender: PublicKey, size: usize) {
+        self.size -= size;
+        if let Some(sender_size) = self.sender_sizes.get_mut(&sender) {
+            *sender_size -= size;
+            if *sender_size == 0 {
+                self.sender_sizes.remove(&sender);
+            }
+        }
+    }
+
+    fn remove_empty(&mut self) {
+        self.messages.retain(|_, messages| !messages.is_empty());
+        self.receipts.retain(|_, receipts| !receipts.is_empty());
+    }
+}
+
+/// Handler of the mailbox protocol, storing messages for offline peers.
+#[derive(Debug)]
+pub(crate) struct MailboxServer {
+    store: Mutex<MailboxStore>,
+    max_request_size: usize,
+}
+
+impl MailboxServer {
+    pub fn new(config: MailboxConfig) -> Self {
+        Self {
+            max_request_size: config.max_message_size + MAX_REQUEST_OVERHEAD,
+            store: Mutex::new(MailboxStore::new(config)),
+        }
+    }
+
+    /// Handles all requests of a peer, one per bi-directional stream.
+    async fn handle_connection(&self, connection: Connection, peer: PublicKey) -> Result<()> {
+        while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+            let bytes = recv.read_to_end(self.max_request_size).await?;
+            let request = MailboxRequest::from_bytes(&bytes)?;
+            let response = self.handle_request(peer, request);
+            send.write_all(&response.to_bytes()).await?;
+            send.finish()?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_request(&self, peer: PublicKey, request: MailboxRequest) -> MailboxResponse {
+        let mut store = self.store.lock().expect("mailbox store lock was poisoned");
+        store.expire();
+
+        match request {
+            MailboxRequest::Deposit { recipient, payload } => {
+                match store.deposit(peer, recipient, payload) {
+                    Ok(id) => {
+                        debug!(%recipient, "stored mailbox message from {peer}");
+                        MailboxResponse::Deposited(id)
+                    }
+                    Err(err) => MailboxResponse::Rejected(err.to_string()),
+                }
+            }
+            MailboxRequest::Collect => MailboxResponse::Collected(store.collect(&peer)),
+            MailboxRequest::Ack(ids) => {
+                store.ack(peer, &ids);
+                MailboxResponse::Acked
+            }
+        }
+    }
+}
+
+impl ProtocolHandler for MailboxServer {
+    fn accept(self: Arc<Self>, connecting: Connecting) -> BoxedFuture<Result<()>> {
+        Box::pin(async move {
+            let connection = connecting.await?;
+            let peer = to_public_key(connection.remote_node_id()?);
+            self.handle_connection(connection, peer).await
+        })
+    }
+}
+
+/// Connection to a mailbox node.
+pub(crate) struct MailboxClient {
+    connection: Connection,
+}
+
+impl MailboxClient {
+    /// Connects to the given mailbox node using the namespaced `MAILBOX_ALPN`.
+    pub async fn connect(endpoint: &Endpoint, alpn: &[u8], mailbox: PublicKey) -> Result<Self> {
+        let connection = endpoint.connect(from_public_key(mailbox), alpn).await?;
+        Ok(Self { connection })
+    }
+
+    /// Deposits a message for the recipient and returns its id.
+    pub async fn deposit(&self, recipient: PublicKey, payload: Vec<u8>) -> Result<Hash> {
+        match self
+            .request(MailboxRequest::Deposit { recipient, payload })
+            .await?
+        {
+            MailboxResponse::Deposited(id) => Ok(id),
+            MailboxResponse::Rejected(reason) => bail!("mailbox rejected message: {reason}"),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Collects messages addressed to us and receipts of messages we've sent, without removing
+    /// them from the mailbox node.
+    pub async fn collect(&self) -> Result<Mailbox> {
+        match self.request(MailboxRequest::Collect).await? {
+            MailboxResponse::Collected(mailbox) => Ok(mailbox),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Acknowledges collected messages and receipts, the mailbox node removes them.
+    pub async fn ack(&self, ids: Vec<Hash>) -> Result<()> {
+        match self.request(MailboxRequest::Ack(ids)).await? {
+            MailboxResponse::Acked => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn request(&self, request: MailboxRequest) -> Result<MailboxResponse> {
+        let (mut send, mut recv) = self.connection.open_bi().await?;
+        send.write_all(&request.to_bytes()).await?;
+        send.finish()?;
+        let bytes = recv.read_to_end(MAX_RESPONSE_SIZE).await?;
+        MailboxResponse::from_bytes(&bytes)
+    }
+}
+
+fn unexpected(response: MailboxResponse) -> anyhow::Error {
+    anyhow!("unexpected mailbox response: {response:?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::PrivateKey;
+    use tokio::time::Duration;
+
+    use super::{
+        DeliveryReceipt, DepositError, MAX_COLLECTED_SIZE, Mailbox, MailboxConfig, MailboxStore,
+    };
+
+    #[test]
+    fn deliver_messages_with_receipts() {
+        let sender = PrivateKey::new().public_key();
+        let recipient = PrivateKey::new().public_key();
+
+        let mut store = MailboxStore::new(MailboxConfig::new());
+        let id_1 = store.deposit(sender, recipient, b"hello".to_vec()).unwrap();
+        let id_2 = store.deposit(sender, recipient, b"hello".to_vec()).unwrap();
+        assert_ne!(id_1, id_2);
+
+        // Only the recipient receives the messages, collecting doesn't remove them.
+        assert_eq!(store.collect(&sender), Mailbox::default());
+        let mailbox = store.collect(&recipient);
+        assert_eq!(mailbox.messages.len(), 2);
+        assert_eq!(mailbox.messages[0].id, id_1);
+        assert_eq!(mailbox.messages[0].sender, sender);
+        assert_eq!(store.collect(&recipient), mailbox);
+
+        // Acknowledged messages turn into receipts for the sender.
+        store.ack(recipient, &mailbox.ids());
+        assert!(store.collect(&recipient).is_empty());
+        let receipts = store.collect(&sender);
+        assert_eq!(
+            receipts.receipts,
+            vec![
+                DeliveryReceipt {
+                    id: id_1,
+                    recipient
+                },
+                DeliveryReceipt {
+                    id: id_2,
+                    recipient
+                }
+            ]
+        );
+
+        // Receipts are removed once the sender acknowledged them.
+        store.ack(sender, &receipts.ids());
+        assert!(store.collect(&sender).is_empty());
+    }
+
+    #[test]
+    fn enforce_limits() {
+        let sender = PrivateKey::new().public_key();
+        let recipient = PrivateKey::new().public_key();
+
+        let mut store = MailboxStore::new(
+            MailboxConfig::new()
+                .max_messages(2)
+                .max_message_size(MAX_COLLECTED_SIZE),
+        );
+        assert_eq!(
+            store.deposit(sender, recipient, vec![0; MAX_COLLECTED_SIZE + 1]),
+            Err(DepositError::MessageTooLarge {
+                size: MAX_COLLECTED_SIZE + 1,
+                max_size: MAX_COLLECTED_SIZE
+            })
+        );
+
+        store
+            .deposit(sender, recipient, vec![0; MAX_COLLECTED_SIZE])
+            .unwrap();
+        store.deposit(sender, recipient, vec![1]).unwrap();
+        assert_eq!(
+            store.deposit(sender, recipient, vec![2]),
+            Err(DepositError::MailboxFull)
+        );
+
+        // Collections are limited in size.
+        let mailbox = store.collect(&recipient);
+        assert_eq!(mailbox.messages.len(), 1);
+        store.ack(recipient, &mailbox.ids());
+        assert_eq!(store.collect(&recipient).messages[0].payload, vec![1]);
+    }
+
+    #[test]
+    fn enforce_storage_limits() {
+        let sender = PrivateKey::new().public_key();
+        let other_sender = PrivateKey::new().public_key();
+
+        let mut store = MailboxStore::new(MailboxConfig::new().max_size(8).max_sender_size(5));
+
+        // Senders can't exceed their quota by depositing into many mailboxes.
+        for _ in 0..5 {
+            let recipient = PrivateKey::new().public_key();
+            store.deposit(sender, recipient, vec![0]).unwrap();
+        }
+        let recipient = PrivateKey::new().public_key();
+        assert_eq!(
+            store.deposit(sender, recipient, vec![0]),
+            Err(DepositError::MailboxFull)
+        );
+
+        // The storage of the mailbox node is full, even though the sender has quota left.
+        store.deposit(other_sender, recipient, vec![0; 3]).unwrap();
+        assert_eq!(
+            store.deposit(other_sender, recipient, vec![0]),
+            Err(DepositError::MailboxFull)
+        );
+
+        // Collected messages free up the storage and the quota of their sender.
+        let mailbox = store.collect(&recipient);
+        store.ack(recipient, &mailbox.ids());
+        store.deposit(other_sender, recipient, vec![0; 3]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn expire_messages() {
+        let sender = PrivateKey::new().public_key();
+        let recipient = PrivateKey::new().public_key();
+
+        let mut store =
+            MailboxStore::new(MailboxConfig::new().retention(Duration::from_millis(20)));
+        store.deposit(sender, recipient, b"hello".to_vec()).unwrap();
+        store.expire();
+        assert_eq!(store.collect(&recipient).messages.len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        store.expire();
+        assert!(store.collect(&recipient).is_empty());
+    }
+}
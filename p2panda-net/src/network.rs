@@ -116,6 +116,7 @@
 //!
 //! Next to blob sync, data sync or discovery protocols it is also possible to register any other
 //! low-level bi-directional communication protocol to the node when necessary.
+use std::any::Any;
 use std::fmt::Debug;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::sync::Arc;
@@ -128,21 +129,39 @@ use futures_util::{FutureExt, TryFutureExt};
 use iroh::{Endpoint, RelayMap, RelayNode};
 use iroh_gossip::net::{GOSSIP_ALPN, Gossip};
 use iroh_quinn::TransportConfig;
-use p2panda_core::{PrivateKey, PublicKey};
+use p2panda_core::{Hash, PrivateKey, PublicKey};
 use p2panda_discovery::{Discovery, DiscoveryMap};
-use p2panda_sync::TopicQuery;
+use p2panda_sync::{SyncEstimate, SyncFilter, TopicQuery};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use tokio::sync::{broadcast, mpsc, oneshot};
-use tokio::task::{JoinError, JoinSet};
+use tokio::task::{Id, JoinError, JoinSet};
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::AbortOnDropHandle;
 use tracing::{Instrument, debug, error, error_span, warn};
 
 use crate::addrs::{DEFAULT_STUN_PORT, to_node_addr, to_relay_url};
-use crate::config::{Config, DEFAULT_BIND_PORT, GossipConfig};
+use crate::compression::{CompressionConfig, CompressionMetrics};
+use crate::config::{Config, DEFAULT_BIND_PORT, GossipConfig, PanicPolicy};
+use crate::dial::{self, ConnectResult, DIAL_ALPN, DIAL_TIMEOUT, DialHandler};
 use crate::engine::Engine;
 use crate::events::SystemEvent;
-use crate::protocols::{ProtocolHandler, ProtocolMap};
-use crate::sync::{SYNC_CONNECTION_ALPN, SyncConfiguration};
+use crate::faults::FaultInjector;
+use crate::mailbox::{
+    MAILBOX_ALPN, MAILBOX_POLL_INTERVAL, Mailbox, MailboxClient, MailboxConfig, MailboxServer,
+};
+use crate::overlay_health::OverlayHealth;
+use crate::presence::{MAX_PRESENCE_STATUS_LEN, PeerPresence, PresenceConfig};
+use crate::protocols::{AlpnNamespace, IntoArcAny, ProtocolHandler, ProtocolMap, namespaced_alpn};
+use crate::providers::BlobFilter;
+use crate::relay_usage::{RelayPolicy, RelayUsage};
+use crate::reputation::Reputation;
+use crate::roles::{NodeRole, RolesConfig};
+use crate::supervisor::{ADDRESS_UPDATES_SUBSYSTEM, restart_backoff};
+use crate::sync::{self, SYNC_CONNECTION_ALPN, SyncConfiguration};
+use crate::topic_auth::TopicAuthenticator;
+use crate::transport::Transport;
+use crate::typed::{TypedReceiver, TypedSender};
 use crate::{NetworkId, NodeAddress, RelayUrl, TopicId, from_private_key};
 
 /// Maximum number of streams accepted on a QUIC connection.
@@ -177,19 +196,31 @@ pub enum RelayMode {
 /// topic where they'll send and receive data.
 #[derive(Debug)]
 pub struct NetworkBuilder<T> {
+    alpn_namespace: AlpnNamespace,
     bind_ip_v4: Option<Ipv4Addr>,
     bind_port_v4: Option<u16>,
     bind_ip_v6: Option<Ipv6Addr>,
     bind_port_v6: Option<u16>,
     bootstrap: bool,
+    compression: Option<CompressionConfig>,
     direct_node_addresses: Vec<NodeAddress>,
     discovery: DiscoveryMap,
+    faults: Option<FaultInjector>,
     gossip_config: Option<GossipConfig>,
+    mailbox_server: Option<MailboxConfig>,
+    mailboxes: Vec<PublicKey>,
     network_id: NetworkId,
+    panic_policy: PanicPolicy,
+    presence: Option<PresenceConfig>,
     protocols: ProtocolMap,
     relay_mode: RelayMode,
+    relay_policy: RelayPolicy,
     private_key: Option<PrivateKey>,
+    reputation: Reputation,
+    roles: RolesConfig,
     sync_config: Option<SyncConfiguration<T>>,
+    topic_auth: Option<Arc<dyn TopicAuthenticator>>,
+    transport: Transport,
 }
 
 impl<T> NetworkBuilder<T>
@@ -202,19 +233,31 @@ where
     /// data.
     pub fn new(network_id: NetworkId) -> Self {
         Self {
+            alpn_namespace: AlpnNamespace::default(),
             bind_ip_v4: None,
             bind_port_v4: None,
             bind_ip_v6: None,
             bind_port_v6: None,
             bootstrap: false,
+            compression: None,
             direct_node_addresses: Vec::new(),
             discovery: DiscoveryMap::default(),
+            faults: None,
             gossip_config: None,
+            mailbox_server: None,
+            mailboxes: Vec::new(),
             network_id,
+            panic_policy: PanicPolicy::default(),
+            presence: None,
             protocols: Default::default(),
             relay_mode: RelayMode::Disabled,
+            relay_policy: RelayPolicy::default(),
             private_key: None,
+            reputation: Reputation::default(),
+            roles: RolesConfig::default(),
             sync_config: None,
+            topic_auth: None,
+            transport: Transport::default(),
         }
     }
 
@@ -309,6 +352,97 @@ where
         self
     }
 
+    /// Sets the policy for bulk transfers with peers only reachable via a relay.
+    ///
+    /// Relays are often run by communities on limited resources. The policy can refuse sync
+    /// sessions and blob downloads over relayed paths or cap their bandwidth, see `RelayPolicy`.
+    /// Defaults to transferring data over relayed paths like over direct ones.
+    pub fn relay_policy(mut self, policy: RelayPolicy) -> Self {
+        self.relay_policy = policy;
+        self
+    }
+
+    /// Sets the transport used to reach other peers.
+    ///
+    /// Defaults to direct QUIC connections on native platforms and to relay-only connections when
+    /// compiling to WebAssembly. Relay-only transports require a relay, building the network fails
+    /// otherwise. The configured bind addresses are ignored by relay-only transports.
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Enables presence heartbeats on all subscribed topics.
+    ///
+    /// The node announces its presence together with a small status blob, set with
+    /// `Network::set_presence_status`, and keeps track of other present peers on the topics it is
+    /// subscribed to. Peers are listed by `Network::presence` and changes are reported as system
+    /// events.
+    pub fn presence(mut self, config: PresenceConfig) -> Self {
+        self.presence = Some(config);
+        self
+    }
+
+    /// Enables compression of gossip messages and sync sessions.
+    ///
+    /// The compression algorithm of sync sessions is negotiated with every peer, sessions with
+    /// peers not supporting compression are sent uncompressed. Gossip messages are compressed
+    /// with the preferred algorithm and can only be understood by nodes which enabled compression
+    /// as well, all nodes of a network should use the same setting.
+    pub fn compression(mut self, config: CompressionConfig) -> Self {
+        self.compression = Some(config);
+        self
+    }
+
+    /// Enables the mailbox protocol, storing messages for offline peers until they collect them.
+    ///
+    /// Mailbox nodes should be always-on, see `NetworkBuilder::mailbox` on how peers collect their
+    /// messages.
+    pub fn mailbox_server(mut self, config: MailboxConfig) -> Self {
+        self.mailbox_server = Some(config);
+        self
+    }
+
+    /// Registers a mailbox node to collect messages addressed to us from.
+    ///
+    /// Registered mailbox nodes are checked every few seconds, messages deposited while we were
+    /// offline are collected as soon as we reconnect. Collected messages and delivery receipts of
+    /// our own messages are reported as system events and only acknowledged while there are
+    /// receivers of system events, see `Network::events`. Messages might be reported more than once
+    /// if acknowledging them fails.
+    ///
+    /// This method can be called multiple times to register multiple mailbox nodes.
+    pub fn mailbox(mut self, mailbox: PublicKey) -> Self {
+        self.mailboxes.push(mailbox);
+        self
+    }
+
+    /// Restricts who can join private topics.
+    ///
+    /// Peers need to present a proof, verified by the given authenticator, to join the gossip
+    /// overlay or sync the data of topics it marks as private. Proofs are checked by both sides
+    /// before any data flows, see `TopicAuthenticator` for details.
+    pub fn topic_authenticator(mut self, authenticator: impl TopicAuthenticator) -> Self {
+        self.topic_auth = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Sets the reputation of peers, for example to persist scores or change the ban threshold.
+    ///
+    /// Keep a clone of the handle to report misbehaviour detected by the application or custom
+    /// protocol handlers, see `Reputation` for details. Defaults to scores kept in memory.
+    pub fn reputation(mut self, reputation: Reputation) -> Self {
+        self.reputation = reputation;
+        self
+    }
+
+    /// Sets a fault injector to simulate failures of this node in tests.
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_injector(mut self, faults: FaultInjector) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
     /// Sets the direct address of a peer, identified by their public key (node id).
     ///
     /// The direct address should be reachable without the aid of a STUN or TURN-based relay node.
@@ -333,12 +467,35 @@ where
         self
     }
 
+    /// Adds a role which is advertised to other peers for all topics we're interested in.
+    ///
+    /// Roles help other peers to pick the most appropriate nodes in heterogeneous networks, for
+    /// example by preferring archival peers over "live-only" mobile clients when syncing.
+    pub fn role(mut self, role: NodeRole) -> Self {
+        self.roles.add(role);
+        self
+    }
+
+    /// Adds a role which is only advertised to other peers for the given topic id.
+    pub fn topic_role(mut self, topic_id: [u8; 32], role: NodeRole) -> Self {
+        self.roles.add_for_topic(topic_id, role);
+        self
+    }
+
     /// Adds one or more discovery strategy, such as mDNS.
     pub fn discovery(mut self, handler: impl Discovery + 'static) -> Self {
         self.discovery.add(handler);
         self
     }
 
+    /// Sets the policy defining how the node reacts to panics in internal tasks.
+    ///
+    /// Default is `PanicPolicy::FailFast`.
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.panic_policy = policy;
+        self
+    }
+
     /// Sets the sync protocol and configuration.
     ///
     /// Sync sessions will be automatically initiated with any known peers with whom we share
@@ -351,13 +508,30 @@ where
     /// Sets the gossip configuration.
     ///
     /// Configuration parameters define the behavior of the swarm membership (HyParView) and gossip
-    /// broadcast (Plumtree) layers, as well as the maximum message size.
+    /// broadcast (Plumtree) layers, as well as the maximum message size. Messages exceeding the
+    /// maximum size are split into chunks transparently.
     pub fn gossip(mut self, config: GossipConfig) -> Self {
         self.gossip_config = Some(config);
         self
     }
 
+    /// Sets the namespace of the ALPN identifiers of all protocols.
+    ///
+    /// The ALPN identifiers of gossip, sync and custom protocols are prefixed with the namespace,
+    /// peers only accept connections over protocols of the same namespace. Applications sharing a
+    /// network identifier use different namespaces to not accidentally interoperate. Custom
+    /// protocols dial peers with the namespaced identifier returned by `Network::alpn`.
+    ///
+    /// Defaults to no namespace, using the plain ALPN identifiers.
+    pub fn alpn_namespace(mut self, namespace: AlpnNamespace) -> Self {
+        self.alpn_namespace = namespace;
+        self
+    }
+
     /// Adds additional, custom protocols for communication between two peers.
+    ///
+    /// The ALPN identifier of the protocol is prefixed with the namespace set by
+    /// `alpn_namespace`.
     pub fn protocol(
         mut self,
         protocol_name: &'static [u8],
@@ -367,6 +541,21 @@ where
         self
     }
 
+    /// Adds a custom protocol like `protocol`, without prefixing its ALPN identifier with the
+    /// namespace.
+    ///
+    /// This is required for protocols whose peers dial with a fixed ALPN identifier, for example
+    /// because dialing is implemented by a third-party crate.
+    pub fn global_protocol(
+        mut self,
+        protocol_name: &'static [u8],
+        handler: impl ProtocolHandler + 'static,
+    ) -> Self {
+        self.protocols
+            .insert_global(protocol_name, Arc::new(handler));
+        self
+    }
+
     /// Returns a handle to a newly-spawned instance of `Network`.
     ///
     /// A peer-to-peer endpoint is created and bound to a QUIC socket, after which the gossip,
@@ -389,6 +578,11 @@ where
             RelayMode::Custom(ref node) => Some(node.clone()),
         };
 
+        let transport = self.transport;
+        if transport.requires_relay() && relay.is_none() {
+            return Err(anyhow!("{transport:?} transport requires a relay"));
+        }
+
         // Build p2p endpoint and bind the QUIC socket.
         let endpoint = {
             let mut transport_config = TransportConfig::default();
@@ -404,57 +598,109 @@ where
                 ),
             };
 
-            let bind_ip_v4 = self.bind_ip_v4.unwrap_or(Ipv4Addr::UNSPECIFIED);
-            let bind_port_v4 = self.bind_port_v4.unwrap_or(DEFAULT_BIND_PORT);
-            let bind_ip_v6 = self.bind_ip_v6.unwrap_or(Ipv6Addr::UNSPECIFIED);
-            let bind_port_v6 = self.bind_port_v6.unwrap_or(DEFAULT_BIND_PORT + 1);
-            let socket_address_v4 = SocketAddrV4::new(bind_ip_v4, bind_port_v4);
-            let socket_address_v6 = SocketAddrV6::new(bind_ip_v6, bind_port_v6, 0, 0);
-
-            Endpoint::builder()
+            let builder = Endpoint::builder()
                 .transport_config(transport_config)
                 .secret_key(from_private_key(private_key.clone()))
-                .relay_mode(relay_mode)
-                .bind_addr_v4(socket_address_v4)
-                .bind_addr_v6(socket_address_v6)
-                .bind()
-                .await?
+                .relay_mode(relay_mode);
+
+            // Browsers can't bind sockets, all packets are sent over the relay's WebSocket.
+            #[cfg(not(target_arch = "wasm32"))]
+            let builder = if transport.is_direct() {
+                let bind_ip_v4 = self.bind_ip_v4.unwrap_or(Ipv4Addr::UNSPECIFIED);
+                let bind_port_v4 = self.bind_port_v4.unwrap_or(DEFAULT_BIND_PORT);
+                let bind_ip_v6 = self.bind_ip_v6.unwrap_or(Ipv6Addr::UNSPECIFIED);
+                let bind_port_v6 = self.bind_port_v6.unwrap_or(DEFAULT_BIND_PORT + 1);
+                let socket_address_v4 = SocketAddrV4::new(bind_ip_v4, bind_port_v4);
+                let socket_address_v6 = SocketAddrV6::new(bind_ip_v6, bind_port_v6, 0, 0);
+
+                builder
+                    .bind_addr_v4(socket_address_v4)
+                    .bind_addr_v6(socket_address_v6)
+            } else {
+                // Without a routable socket no direct paths to other peers are discovered or
+                // holepunched, all packets are sent over the relay.
+                builder
+                    .bind_addr_v4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+                    .bind_addr_v6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0))
+            };
+
+            builder.bind().await?
         };
 
         let node_addr = endpoint.node_addr().await?;
 
+        let alpn_prefix = self.alpn_namespace.prefix(&self.network_id);
+        self.protocols.set_prefix(alpn_prefix.clone());
+
+        let gossip_config = self.gossip_config.unwrap_or_default();
         let gossip = Gossip::builder()
-            .max_message_size(self.gossip_config.unwrap_or_default().max_message_size)
+            .max_message_size(gossip_config.max_message_size)
+            .alpn(namespaced_alpn(&alpn_prefix, GOSSIP_ALPN))
             .spawn(endpoint.clone())
             .await?;
 
+        let relay_usage = RelayUsage::new(endpoint.clone(), self.relay_policy);
+
+        if let Some(sync_config) = &mut self.sync_config {
+            sync_config.alpn = namespaced_alpn(&alpn_prefix, SYNC_CONNECTION_ALPN);
+            sync_config.relay_usage = Some(relay_usage.clone());
+            sync_config.faults = self.faults.clone();
+            sync_config.compression = self.compression.clone();
+            sync_config.topic_auth = self.topic_auth.clone();
+            sync_config.reputation = self.reputation.clone();
+        }
+
         let engine = Engine::new(
             self.bootstrap,
             private_key.clone(),
             self.network_id,
             endpoint.clone(),
             gossip.clone(),
-            self.sync_config,
+            gossip_config,
+            self.sync_config.clone(),
+            self.roles,
+            self.presence.clone(),
+            self.topic_auth,
+            self.reputation.clone(),
+            self.faults,
+            self.compression
+                .as_ref()
+                .map(CompressionConfig::gossip_compressor),
+            self.panic_policy,
         );
 
         let sync_handler = engine.sync_handler();
 
         let inner = Arc::new(NetworkInner {
+            alpn_prefix,
             cancel_token: CancellationToken::new(),
             relay: relay.clone(),
+            compression: self.compression,
             discovery: self.discovery,
             endpoint: endpoint.clone(),
             engine,
             gossip: gossip.clone(),
+            mailboxes: self.mailboxes,
             network_id: self.network_id,
+            panic_policy: self.panic_policy,
+            presence: self.presence,
             private_key,
+            relay_usage,
+            reputation: self.reputation,
+            sync_config: self.sync_config,
+            transport,
         });
 
         self.protocols.insert(GOSSIP_ALPN, Arc::new(gossip.clone()));
+        self.protocols.insert(DIAL_ALPN, Arc::new(DialHandler));
         if let Some(sync_handler) = sync_handler {
             self.protocols
                 .insert(SYNC_CONNECTION_ALPN, Arc::new(sync_handler));
         };
+        if let Some(config) = self.mailbox_server {
+            self.protocols
+                .insert(MAILBOX_ALPN, Arc::new(MailboxServer::new(config)));
+        }
         let protocols = Arc::new(self.protocols.clone());
         let alpns = self.protocols.alpns();
         if let Err(err) = inner.endpoint.set_alpns(alpns) {
@@ -492,7 +738,10 @@ where
             }
         };
 
-        if let Err(err) = wait_for_endpoints.await {
+        // Relay-only nodes don't have any direct addresses.
+        if transport.is_direct()
+            && let Err(err) = wait_for_endpoints.await
+        {
             network.shutdown().await.ok();
             return Err(err);
         }
@@ -521,16 +770,25 @@ where
 
 #[derive(Debug)]
 struct NetworkInner<T> {
+    alpn_prefix: Vec<u8>,
     cancel_token: CancellationToken,
     relay: Option<RelayNode>,
+    compression: Option<CompressionConfig>,
     discovery: DiscoveryMap,
     endpoint: Endpoint,
     engine: Engine<T>,
     #[allow(dead_code)]
     gossip: Gossip,
+    mailboxes: Vec<PublicKey>,
     network_id: NetworkId,
+    panic_policy: PanicPolicy,
+    presence: Option<PresenceConfig>,
     #[allow(dead_code)]
     private_key: PrivateKey,
+    relay_usage: RelayUsage,
+    reputation: Reputation,
+    sync_config: Option<SyncConfiguration<T>>,
+    transport: Transport,
 }
 
 impl<T> NetworkInner<T>
@@ -557,50 +815,13 @@ where
         let mut join_set = JoinSet::<Result<()>>::new();
 
         // Spawn a task that updates discovery services as our local addresses change.
-        {
-            let inner = self.clone();
-            join_set.spawn(async move {
-                // Wait for the first set of local direct addresses to be discovered.
-                let local_direct_addresses =
-                    inner.endpoint.direct_addresses().initialized().await?;
-
-                // Build the local address from these parts:
-                //
-                // - Public key
-                // - Relay URL
-                // - Direct addresses (IP & port)
-                let mut local_address = iroh::NodeAddr::from(inner.endpoint.node_id());
-                if let Some(relay) = &inner.relay {
-                    local_address = local_address.with_relay_url(relay.url.clone());
-                }
-                let direct_addresses: Vec<SocketAddr> = local_direct_addresses
-                    .iter()
-                    .map(|endpoint| endpoint.addr)
-                    .collect();
-                local_address = local_address.with_direct_addresses(direct_addresses);
-
-                // Update the discovery service with the local address.
-                if let Err(err) = inner.discovery.update_local_address(&local_address) {
-                    warn!("failed to update direct addresses for discovery: {err:?}");
-                }
+        let mut address_updates_id = self.spawn_address_updates(&mut join_set, None);
+        let mut address_updates_restarts = 0;
 
-                // Now we can subscribe to a stream of direct address updates for our endpoint.
-                let mut direct_addresses_stream = inner.endpoint.direct_addresses().stream();
-
-                // Update the discovery service as we learn of address changes.
-                while let Some(endpoints) = direct_addresses_stream.next().await {
-                    let direct_addresses: Option<Vec<SocketAddr>> = endpoints
-                        .map(|endpoints| endpoints.iter().map(|endpoint| endpoint.addr).collect());
-                    if let Some(addresses) = direct_addresses {
-                        local_address = local_address.with_direct_addresses(addresses);
-                        if let Err(err) = inner.discovery.update_local_address(&local_address) {
-                            warn!("failed to update direct addresses for discovery: {err:?}");
-                        }
-                    }
-                }
-
-                Ok(())
-            });
+        // Collect messages from our mailbox nodes, as soon as they're reachable.
+        if !self.mailboxes.is_empty() {
+            let inner = self.clone();
+            join_set.spawn(async move { inner.poll_mailboxes().await });
         }
 
         // Subscribe to all discovery channels where we might find new peers.
@@ -639,7 +860,8 @@ where
                 Some(event) = discovery_stream.next() => {
                     match event {
                         Ok(event) => {
-                            if let Err(err) = self.engine.add_peer(to_node_addr(event.node_addr)).await {
+                            let node_addr = self.transport.peer_address(to_node_addr(event.node_addr));
+                            if let Err(err) = self.engine.add_peer(node_addr).await {
                                 error!("engine failed on add_peer: {err:?}");
                                 break;
                             }
@@ -650,13 +872,37 @@ where
                         },
                     }
                 },
-                // Handle task terminations and quit on panics.
-                res = join_set.join_next(), if !join_set.is_empty() => {
+                // Handle task terminations and quit or restart on panics, depending on the panic
+                // policy.
+                res = join_set.join_next_with_id(), if !join_set.is_empty() => {
                     match res {
                         Some(Err(outer)) => {
                             if outer.is_panic() {
                                 error!("task panicked: {outer:?}");
-                                break;
+                                if self.panic_policy == PanicPolicy::FailFast {
+                                    break;
+                                }
+
+                                // Panicked connection handlers only affect a single connection
+                                // and are not restarted.
+                                if outer.id() == address_updates_id {
+                                    address_updates_restarts += 1;
+                                    address_updates_id = self.spawn_address_updates(
+                                        &mut join_set,
+                                        Some(restart_backoff(address_updates_restarts)),
+                                    );
+                                    if let Err(err) = self
+                                        .engine
+                                        .subsystem_restarted(
+                                            ADDRESS_UPDATES_SUBSYSTEM,
+                                            address_updates_restarts,
+                                        )
+                                        .await
+                                    {
+                                        error!("engine failed on subsystem_restarted: {err:?}");
+                                        break;
+                                    }
+                                }
                             } else if outer.is_cancelled() {
                                 debug!("task cancelled: {outer:?}");
                             } else {
@@ -664,7 +910,7 @@ where
                                 break;
                             }
                         }
-                        Some(Ok(Err(inner))) => {
+                        Some(Ok((_, Err(inner)))) => {
                             debug!("task errored: {inner:?}");
                         }
                         _ => {}
@@ -680,6 +926,117 @@ where
         join_set.shutdown().await;
     }
 
+    /// Spawns a task that updates discovery services as our local addresses change.
+    ///
+    /// An optional delay can be given to start the task with a backoff, for example after it
+    /// panicked. Returns the id of the spawned task.
+    fn spawn_address_updates(
+        self: &Arc<Self>,
+        join_set: &mut JoinSet<Result<()>>,
+        delay: Option<Duration>,
+    ) -> Id {
+        let inner = self.clone();
+        join_set
+            .spawn(async move {
+                if let Some(delay) = delay {
+                    tokio::time::sleep(delay).await;
+                }
+                inner.update_local_addresses().await
+            })
+            .id()
+    }
+
+    /// Updates discovery services with our local addresses, as long as the endpoint is running.
+    async fn update_local_addresses(&self) -> Result<()> {
+        // Build the local address from these parts:
+        //
+        // - Public key
+        // - Relay URL
+        // - Direct addresses (IP & port)
+        let mut local_address = iroh::NodeAddr::from(self.endpoint.node_id());
+        if let Some(relay) = &self.relay {
+            local_address = local_address.with_relay_url(relay.url.clone());
+        }
+
+        // Relay-only nodes can only be reached via the relay.
+        if !self.transport.is_direct() {
+            if let Err(err) = self.discovery.update_local_address(&local_address) {
+                warn!("failed to update relay address for discovery: {err:?}");
+            }
+            return Ok(());
+        }
+
+        // Wait for the first set of local direct addresses to be discovered.
+        let local_direct_addresses = self.endpoint.direct_addresses().initialized().await?;
+        let direct_addresses: Vec<SocketAddr> = local_direct_addresses
+            .iter()
+            .map(|endpoint| endpoint.addr)
+            .collect();
+        local_address = local_address.with_direct_addresses(direct_addresses);
+
+        // Update the discovery service with the local address.
+        if let Err(err) = self.discovery.update_local_address(&local_address) {
+            warn!("failed to update direct addresses for discovery: {err:?}");
+        }
+
+        // Now we can subscribe to a stream of direct address updates for our endpoint.
+        let mut direct_addresses_stream = self.endpoint.direct_addresses().stream();
+
+        // Update the discovery service as we learn of address changes.
+        while let Some(endpoints) = direct_addresses_stream.next().await {
+            let direct_addresses: Option<Vec<SocketAddr>> =
+                endpoints.map(|endpoints| endpoints.iter().map(|endpoint| endpoint.addr).collect());
+            if let Some(addresses) = direct_addresses {
+                local_address = local_address.with_direct_addresses(addresses);
+                if let Err(err) = self.discovery.update_local_address(&local_address) {
+                    warn!("failed to update direct addresses for discovery: {err:?}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collects messages from the registered mailbox nodes in an interval, as long as the network
+    /// is running.
+    async fn poll_mailboxes(&self) -> Result<()> {
+        let mut interval = tokio::time::interval(MAILBOX_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            for mailbox in &self.mailboxes {
+                // Mailbox nodes are unreachable while we're offline, we try again with the next
+                // tick.
+                if let Err(err) = self.collect_mailbox(*mailbox).await {
+                    debug!(%mailbox, "failed collecting messages from mailbox: {err:#}");
+                }
+            }
+        }
+    }
+
+    /// Returns the ALPN identifier of a protocol, prefixed with the namespace of the network.
+    fn alpn(&self, protocol_name: &[u8]) -> Vec<u8> {
+        namespaced_alpn(&self.alpn_prefix, protocol_name)
+    }
+
+    /// Collects all messages and delivery receipts from a mailbox node and reports them as system
+    /// events.
+    async fn collect_mailbox(&self, mailbox: PublicKey) -> Result<()> {
+        let client =
+            MailboxClient::connect(&self.endpoint, &self.alpn(MAILBOX_ALPN), mailbox).await?;
+        loop {
+            let collected = client.collect().await?;
+            if collected.is_empty() {
+                return Ok(());
+            }
+
+            let ids = collected.ids();
+            if !self.engine.mailbox_collected(mailbox, collected).await? {
+                return Ok(());
+            }
+            client.ack(ids).await?;
+        }
+    }
+
     /// Closes all connections and shuts down the network engine.
     async fn shutdown(&self, protocols: Arc<ProtocolMap>) {
         // We ignore all errors during shutdown.
@@ -709,7 +1066,6 @@ where
 #[derive(Clone, Debug)]
 pub struct Network<T> {
     inner: Arc<NetworkInner<T>>,
-    #[allow(dead_code)]
     protocols: Arc<ProtocolMap>,
     // `Network` needs to be `Clone + Send` and we need to `task.await` in its `shutdown()` impl.
     // - `Shared` allows us to `task.await` from all `Network` clones
@@ -725,6 +1081,7 @@ where
 {
     /// Adds a peer to the address book.
     pub async fn add_peer(&self, node_addr: NodeAddress) -> Result<()> {
+        let node_addr = self.inner.transport.peer_address(node_addr);
         self.inner.engine.add_peer(node_addr).await
     }
 
@@ -736,11 +1093,137 @@ where
         self.inner.engine.events().await
     }
 
+    /// Dials the peer and reports whether it was reached directly or via a relay.
+    ///
+    /// Connections are usually established implicitly when joining gossip overlays or starting
+    /// sync sessions. Dialing explicitly pre-warms the connection to a peer, for example a contact
+    /// the user is about to message, and can be used to display its connection status. Fails
+    /// after `DIAL_TIMEOUT`.
+    pub async fn connect(&self, peer: PublicKey) -> ConnectResult {
+        dial::dial(
+            &self.inner.endpoint,
+            &self.alpn(DIAL_ALPN),
+            &self.inner.relay_usage,
+            peer,
+            DIAL_TIMEOUT,
+        )
+        .await
+    }
+
     /// Returns the addresses of all known peers.
     pub async fn known_peers(&self) -> Result<Vec<NodeAddress>> {
         self.inner.engine.known_peers().await
     }
 
+    /// Announces the blobs this node provides for the given topic id to other peers.
+    ///
+    /// A compact filter of the hashes is attached to our "topic discovery" announcements, together
+    /// with the `BlobProvider` role for this topic. Calling this method again replaces the
+    /// previously announced blobs for the topic, passing no hashes stops announcing blobs.
+    ///
+    /// Blobs are only announced for topics we're subscribed to.
+    pub async fn provide_blobs<'a>(
+        &self,
+        topic_id: [u8; 32],
+        hashes: impl IntoIterator<Item = &'a Hash>,
+    ) -> Result<()> {
+        self.inner
+            .engine
+            .provide_blobs(topic_id, BlobFilter::new(hashes))
+            .await
+    }
+
+    /// Returns all known peers which likely provide the blob with the given hash.
+    ///
+    /// Peers are found through the blob filters they announced, some of them might not have the
+    /// blob after all.
+    pub async fn blob_providers(&self, hash: Hash) -> Result<Vec<PublicKey>> {
+        self.inner.engine.blob_providers(hash).await
+    }
+
+    /// Sets the status announced with our presence heartbeats on the given topic.
+    ///
+    /// The status is visible to all peers of the network. Fails if presence is not enabled or the
+    /// status exceeds `MAX_PRESENCE_STATUS_LEN` bytes.
+    pub async fn set_presence_status(&self, topic: &T, status: Vec<u8>) -> Result<()> {
+        if self.inner.presence.is_none() {
+            return Err(anyhow!("presence is not enabled"));
+        }
+        if status.len() > MAX_PRESENCE_STATUS_LEN {
+            return Err(anyhow!(
+                "presence status exceeds maximum size of {MAX_PRESENCE_STATUS_LEN} bytes"
+            ));
+        }
+
+        self.inner
+            .engine
+            .set_presence_status(topic.id(), status)
+            .await
+    }
+
+    /// Returns all peers currently present on the given topic, together with their status.
+    ///
+    /// Only topics we're subscribed to are tracked. Fails if presence is not enabled.
+    pub async fn presence(&self, topic: &T) -> Result<Vec<PeerPresence>> {
+        if self.inner.presence.is_none() {
+            return Err(anyhow!("presence is not enabled"));
+        }
+
+        self.inner.engine.presence(topic.id()).await
+    }
+
+    /// Returns the health of the gossip overlay of the given topic, `None` if we're not subscribed
+    /// to it.
+    ///
+    /// This includes the number of neighbours, an estimate of the overlay size, the neighbour
+    /// churn rate and whether the overlay is suspected to be partitioned. Suspected partitions are
+    /// also reported as `SystemEvent::OverlayPartitionSuspected` events.
+    pub async fn overlay_health(&self, topic: &T) -> Result<Option<OverlayHealth>> {
+        self.inner.engine.overlay_health(topic.id()).await
+    }
+
+    /// Returns the ids of all child topics of the given parent topic announced by other peers.
+    ///
+    /// Child topics are only tracked while we're subscribed to the parent topic, new ones are
+    /// reported as `SystemEvent::ChildTopicDiscovered` events.
+    pub async fn child_topics(&self, parent: &T) -> Result<Vec<[u8; 32]>> {
+        self.inner.engine.child_topics(parent.id()).await
+    }
+
+    /// Deposits a message for the recipient at the given mailbox node and returns its id.
+    ///
+    /// The recipient collects the message from the mailbox node the next time it is online, we
+    /// receive a `SystemEvent::MailboxMessageDelivered` event afterwards when collecting from the
+    /// same mailbox node. The payload is readable by the mailbox node and should be encrypted for
+    /// the recipient.
+    pub async fn send_to_mailbox(
+        &self,
+        mailbox: PublicKey,
+        recipient: PublicKey,
+        payload: Vec<u8>,
+    ) -> Result<Hash> {
+        MailboxClient::connect(&self.inner.endpoint, &self.alpn(MAILBOX_ALPN), mailbox)
+            .await?
+            .deposit(recipient, payload)
+            .await
+    }
+
+    /// Collects messages addressed to us and receipts of messages we've sent from the given
+    /// mailbox node.
+    ///
+    /// The collection is acknowledged right away, the mailbox node removes all returned messages
+    /// and receipts. Large collections are split up, call this method again until an empty
+    /// mailbox is returned to collect all of them.
+    pub async fn collect_mailbox(&self, mailbox: PublicKey) -> Result<Mailbox> {
+        let client =
+            MailboxClient::connect(&self.inner.endpoint, &self.alpn(MAILBOX_ALPN), mailbox).await?;
+        let collected = client.collect().await?;
+        if !collected.is_empty() {
+            client.ack(collected.ids()).await?;
+        }
+        Ok(collected)
+    }
+
     /// Returns the direct addresses of this node.
     pub async fn direct_addresses(&self) -> Option<Vec<SocketAddr>> {
         match self
@@ -756,6 +1239,15 @@ where
         }
     }
 
+    /// Returns the metrics of compressed gossip messages and sync sessions, `None` if compression
+    /// is not enabled.
+    pub fn compression_metrics(&self) -> Option<CompressionMetrics> {
+        self.inner
+            .compression
+            .as_ref()
+            .map(CompressionConfig::metrics)
+    }
+
     /// Returns a handle to the network endpoint.
     ///
     /// The `Endpoint` exposes low-level networking functionality such as the ability to connect to
@@ -767,6 +1259,36 @@ where
         &self.inner.endpoint
     }
 
+    /// Returns the protocol handler registered for the given ALPN if it is of type `P`.
+    ///
+    /// This allows crates extending the network with their own protocols, like `p2panda-blobs`,
+    /// to access their handler after the network was built.
+    pub fn get_protocol<P: ProtocolHandler>(&self, alpn: &[u8]) -> Option<Arc<P>> {
+        let protocol: Arc<dyn ProtocolHandler> = self.protocols.get(alpn)?;
+        let protocol: Arc<dyn Any + Send + Sync> = protocol.into_arc_any();
+        protocol.downcast().ok()
+    }
+
+    /// Returns the ALPN identifier to dial peers with over the given protocol.
+    ///
+    /// Protocols registered with `NetworkBuilder::protocol` are accepted under their name prefixed
+    /// with the namespace set by `NetworkBuilder::alpn_namespace`, custom protocols use the
+    /// returned identifier to connect to other peers.
+    pub fn alpn(&self, protocol_name: &[u8]) -> Vec<u8> {
+        self.inner.alpn(protocol_name)
+    }
+
+    /// Returns the usage of relayed paths in sync sessions per peer and the relay policy of the
+    /// node.
+    pub fn relay_usage(&self) -> &RelayUsage {
+        &self.inner.relay_usage
+    }
+
+    /// Returns the handle to the reputation of peers, to report their misbehaviour.
+    pub fn reputation(&self) -> &Reputation {
+        &self.inner.reputation
+    }
+
     /// Returns the public key of the node.
     pub fn node_id(&self) -> PublicKey {
         PublicKey::from_bytes(self.inner.endpoint.node_id().as_bytes())
@@ -784,8 +1306,27 @@ where
         Ok(())
     }
 
+    /// Estimates the data a sync session with the given peer over the topic would transfer,
+    /// without transferring it.
+    ///
+    /// Applications can use this to ask users before large downloads, for example on metered
+    /// connections. Only sync protocols supporting estimates, like `LogSyncProtocol`, can be
+    /// used. Fails if sync is not enabled or the peer can't be reached.
+    pub async fn sync_diff(&self, topic: T, peer: PublicKey) -> Result<SyncEstimate> {
+        let Some(sync_config) = &self.inner.sync_config else {
+            return Err(anyhow!("sync is not enabled"));
+        };
+
+        sync::sync_diff(&self.inner.endpoint, sync_config, peer, topic).await
+    }
+
     /// Subscribes to a topic and returns a bi-directional stream that can be read from and written
     /// to, along with a oneshot receiver to be informed when the gossip overlay has been joined.
+    ///
+    /// A topic can be subscribed to multiple times, for example by different parts of an
+    /// application. All subscriptions share one gossip overlay and one sync schedule, received
+    /// messages are delivered to each of them. A subscription ends when its receiver is dropped,
+    /// the gossip overlay is left after the last subscription ended.
     pub async fn subscribe(
         &self,
         topic: T,
@@ -793,6 +1334,25 @@ where
         mpsc::Sender<ToNetwork>,
         mpsc::Receiver<FromNetwork>,
         oneshot::Receiver<()>,
+    )> {
+        self.subscribe_with_filter(topic, SyncFilter::default())
+            .await
+    }
+
+    /// Subscribes to a topic like `subscribe` while asking peers to only send data matching the
+    /// given filter during sync sessions.
+    ///
+    /// This is useful for "light" clients which don't need the full history of a topic. Filters
+    /// are only respected by sync protocols supporting them, like `LogSyncProtocol`, others will
+    /// sync all data.
+    pub async fn subscribe_with_filter(
+        &self,
+        topic: T,
+        filter: SyncFilter,
+    ) -> Result<(
+        mpsc::Sender<ToNetwork>,
+        mpsc::Receiver<FromNetwork>,
+        oneshot::Receiver<()>,
     )> {
         let (to_network_tx, to_network_rx) = mpsc::channel::<ToNetwork>(128);
         let (from_network_tx, from_network_rx) = mpsc::channel::<FromNetwork>(128);
@@ -800,11 +1360,69 @@ where
 
         self.inner
             .engine
-            .subscribe(topic, from_network_tx, to_network_rx, gossip_ready_tx)
+            .subscribe(
+                topic,
+                filter,
+                from_network_tx,
+                to_network_rx,
+                gossip_ready_tx,
+            )
             .await?;
 
         Ok((to_network_tx, from_network_rx, gossip_ready_rx))
     }
+
+    /// Observes a topic, receiving its data via sync without taking part in its gossip overlay.
+    ///
+    /// Observers don't announce the topic to other peers and don't join its gossip overlay, so
+    /// they never relay gossip messages for it. Data is only received in sync sessions we initiate
+    /// with peers announcing the topic, messages broadcast over gossip arrive with the next
+    /// resync. This suits privacy-sensitive observers and resource-constrained devices. Fails if
+    /// sync is not enabled.
+    pub async fn observe(
+        &self,
+        topic: T,
+        filter: SyncFilter,
+    ) -> Result<mpsc::Receiver<FromNetwork>> {
+        if self.inner.sync_config.is_none() {
+            return Err(anyhow!("sync is not enabled"));
+        }
+
+        let (from_network_tx, from_network_rx) = mpsc::channel::<FromNetwork>(128);
+        self.inner
+            .engine
+            .observe(topic, filter, from_network_tx)
+            .await?;
+        Ok(from_network_rx)
+    }
+
+    /// Stops observing a topic.
+    ///
+    /// All receivers returned by `observe` for the topic are closed and no further data is
+    /// delivered to them. Subscriptions made with `subscribe` are not affected.
+    pub async fn stop_observing(&self, topic: T) -> Result<()> {
+        self.inner.engine.stop_observing(topic).await
+    }
+
+    /// Subscribes to a topic like `subscribe`, encoding and decoding gossip messages of type `M`
+    /// as CBOR.
+    ///
+    /// Gossip messages which can't be decoded are reported as `TypedFromNetwork::DecodeError`,
+    /// operations received via sync are handed over unchanged.
+    pub async fn subscribe_typed<M>(
+        &self,
+        topic: T,
+    ) -> Result<(TypedSender<M>, TypedReceiver<M>, oneshot::Receiver<()>)>
+    where
+        M: Serialize + DeserializeOwned,
+    {
+        let (to_network_tx, from_network_rx, gossip_ready_rx) = self.subscribe(topic).await?;
+        Ok((
+            TypedSender::new(to_network_tx),
+            TypedReceiver::new(from_network_rx),
+            gossip_ready_rx,
+        ))
+    }
 }
 
 /// An event to be broadcast to the network.
@@ -843,7 +1461,7 @@ async fn handle_connection(
             return;
         }
     };
-    let Some(handler) = protocols.get(&alpn) else {
+    let Some(handler) = protocols.resolve(&alpn) else {
         warn!("ignoring connection: unsupported alpn protocol");
         return;
     };
@@ -861,10 +1479,15 @@ mod tests {
     use std::collections::HashMap;
     use std::net::{Ipv4Addr, Ipv6Addr};
     use std::path::PathBuf;
+    use std::sync::Arc;
     use std::time::Duration;
 
+    use anyhow::Result;
     use async_trait::async_trait;
+    use futures_lite::future::Boxed as BoxedFuture;
+    use iroh::endpoint::Connecting;
     use iroh::{RelayNode, RelayUrl as IrohRelayUrl};
+    use iroh_gossip::net::{GOSSIP_ALPN, Gossip};
     use p2panda_core::{Body, Extensions, Hash, Header, PrivateKey, PublicKey};
     use p2panda_discovery::mdns::LocalDiscovery;
     use p2panda_store::{MemoryStore, OperationStore};
@@ -877,10 +1500,17 @@ mod tests {
 
     use crate::addrs::{DEFAULT_STUN_PORT, to_node_addr};
     use crate::bytes::ToBytes;
-    use crate::config::Config;
+    use crate::config::{Config, PanicPolicy};
+    use crate::dial::{ConnectError, ConnectResult, DIAL_ALPN};
     use crate::events::SystemEvent;
+    use crate::faults::FaultInjector;
+    use crate::protocols::AlpnNamespace;
     use crate::sync::SyncConfiguration;
-    use crate::{NetworkBuilder, NodeAddress, RelayMode, RelayUrl, TopicId, to_public_key};
+    use crate::transport::Transport;
+    use crate::{
+        NetworkBuilder, NodeAddress, ProtocolHandler, RelayMode, RelayUrl, TopicId, TopicOwners,
+        sign_subscribe_proof, to_public_key,
+    };
 
     use super::{FromNetwork, Network, ToNetwork};
 
@@ -978,6 +1608,120 @@ mod tests {
         assert_eq!(builder.relay_mode, RelayMode::Custom(relay_node));
     }
 
+    #[derive(Debug)]
+    struct NoopProtocol;
+
+    impl ProtocolHandler for NoopProtocol {
+        fn accept(self: Arc<Self>, _conn: Connecting) -> BoxedFuture<Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn relay_only_transport() {
+        let result = NetworkBuilder::<TestTopic>::new([1; 32])
+            .transport(Transport::RelayOnly)
+            .build()
+            .await;
+        assert!(result.is_err());
+
+        let builder = NetworkBuilder::<TestTopic>::new([1; 32]);
+        assert_eq!(builder.transport, Transport::Direct);
+
+        // Relay-only nodes can't be reached directly.
+        let relay_url: RelayUrl = "https://wasser.liebechaos.org/".parse().unwrap();
+        let network = NetworkBuilder::<TestTopic>::new([1; 32])
+            .transport(Transport::RelayOnly)
+            .relay(relay_url, false, 0)
+            .build()
+            .await
+            .unwrap();
+        let (ipv4, ipv6) = network.endpoint().bound_sockets();
+        assert!(ipv4.ip().is_loopback());
+        assert!(ipv6.is_none_or(|addr| addr.ip().is_loopback()));
+        network.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_protocol() {
+        let network = NetworkBuilder::<TestTopic>::new([1; 32])
+            .protocol(b"noop", NoopProtocol)
+            .build()
+            .await
+            .unwrap();
+
+        assert!(network.get_protocol::<NoopProtocol>(b"noop").is_some());
+        assert!(network.get_protocol::<NoopProtocol>(b"unknown").is_none());
+        assert!(network.get_protocol::<NoopProtocol>(GOSSIP_ALPN).is_none());
+        assert!(network.get_protocol::<Gossip>(GOSSIP_ALPN).is_some());
+
+        network.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn connect_to_peer() {
+        let node_1 = NetworkBuilder::<TestTopic>::new([1; 32])
+            .build()
+            .await
+            .unwrap();
+        let node_2 = NetworkBuilder::<TestTopic>::new([1; 32])
+            .build()
+            .await
+            .unwrap();
+
+        let node_2_addr = node_2.endpoint().node_addr().await.unwrap();
+        node_1.endpoint().add_node_addr(node_2_addr).unwrap();
+
+        let result = node_1.connect(node_2.node_id()).await;
+        assert!(matches!(result, ConnectResult::Direct { .. }));
+
+        let result = node_1.connect(node_1.node_id()).await;
+        assert!(matches!(
+            result,
+            ConnectResult::Failed {
+                reason: ConnectError::OwnNode,
+                ..
+            }
+        ));
+
+        node_1.shutdown().await.unwrap();
+        node_2.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn alpn_namespaces() {
+        let node_1 = NetworkBuilder::<TestTopic>::new([1; 32])
+            .alpn_namespace(AlpnNamespace::App("chat".into()))
+            .build()
+            .await
+            .unwrap();
+        let node_2 = NetworkBuilder::<TestTopic>::new([1; 32])
+            .alpn_namespace(AlpnNamespace::App("chat".into()))
+            .build()
+            .await
+            .unwrap();
+        let node_3 = NetworkBuilder::<TestTopic>::new([1; 32])
+            .alpn_namespace(AlpnNamespace::App("notes".into()))
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(node_1.alpn(DIAL_ALPN), b"/chat/p2panda-net-dial/1".to_vec());
+
+        for node in [&node_2, &node_3] {
+            let node_addr = node.endpoint().node_addr().await.unwrap();
+            node_1.endpoint().add_node_addr(node_addr).unwrap();
+        }
+
+        // Peers of other applications on the same network don't accept our connections.
+        assert!(node_1.connect(node_2.node_id()).await.is_connected());
+        assert!(!node_1.connect(node_3.node_id()).await.is_connected());
+
+        node_1.shutdown().await.unwrap();
+        node_2.shutdown().await.unwrap();
+        node_3.shutdown().await.unwrap();
+    }
+
     #[tokio::test]
     async fn join_gossip_overlay() {
         let network_id = [1; 32];
@@ -1020,6 +1764,48 @@ mod tests {
         node_2.shutdown().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn broadcast_chunked_gossip_messages() {
+        let network_id = [1; 32];
+        let topic = TestTopic::new("chat");
+
+        let node_1 = NetworkBuilder::new(network_id).build().await.unwrap();
+        let node_2 = NetworkBuilder::new(network_id).build().await.unwrap();
+
+        let node_1_addr = node_1.endpoint().node_addr().await.unwrap();
+        let node_2_addr = node_2.endpoint().node_addr().await.unwrap();
+
+        node_1.add_peer(to_node_addr(node_2_addr)).await.unwrap();
+        node_2.add_peer(to_node_addr(node_1_addr)).await.unwrap();
+
+        let (tx_1, _rx_1, ready_1) = node_1.subscribe(topic.clone()).await.unwrap();
+        let (_tx_2, mut rx_2, ready_2) = node_2.subscribe(topic).await.unwrap();
+
+        assert!(ready_2.await.is_ok());
+        assert!(ready_1.await.is_ok());
+
+        // Broadcast a message exceeding the default maximum gossip message size, it is split
+        // into chunks and reassembled by the other node
+        let bytes: Vec<u8> = (0..20_000).map(|i| (i % 251) as u8).collect();
+        tx_1.send(ToNetwork::Message {
+            bytes: bytes.clone(),
+        })
+        .await
+        .unwrap();
+
+        let rx_2_msg = rx_2.recv().await.unwrap();
+        assert_eq!(
+            rx_2_msg,
+            FromNetwork::GossipMessage {
+                bytes,
+                delivered_from: node_1.node_id(),
+            }
+        );
+
+        node_1.shutdown().await.unwrap();
+        node_2.shutdown().await.unwrap();
+    }
+
     #[tokio::test]
     async fn join_gossip_overlay_with_local_discovery() {
         let network_id = [1; 32];
@@ -1382,6 +2168,94 @@ mod tests {
         node_3.shutdown().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn private_topic_with_unauthorised_neighbor() {
+        let network_id = [1; 32];
+        let topic = TestTopic::new("chat");
+        let owner = PrivateKey::new();
+        let authenticator = |private_key: &PrivateKey| {
+            let proof = sign_subscribe_proof(&owner, &topic.id(), &private_key.public_key());
+            TopicOwners::new()
+                .private_topic(topic.id(), owner.public_key())
+                .own_proof(topic.id(), proof)
+        };
+
+        let private_key_1 = PrivateKey::new();
+        let private_key_2 = PrivateKey::new();
+        let node_1 = NetworkBuilder::new(network_id)
+            .private_key(private_key_1.clone())
+            .topic_authenticator(authenticator(&private_key_1))
+            .build()
+            .await
+            .unwrap();
+        let node_2 = NetworkBuilder::new(network_id)
+            .private_key(private_key_2.clone())
+            .topic_authenticator(authenticator(&private_key_2))
+            .build()
+            .await
+            .unwrap();
+
+        // Node 3 doesn't have a proof for the private topic and joins its overlay anyway.
+        let node_3 = NetworkBuilder::new(network_id).build().await.unwrap();
+
+        let node_1_addr = node_1.endpoint().node_addr().await.unwrap();
+        let node_2_addr = node_2.endpoint().node_addr().await.unwrap();
+        let node_3_addr = node_3.endpoint().node_addr().await.unwrap();
+
+        node_1
+            .add_peer(to_node_addr(node_2_addr.clone()))
+            .await
+            .unwrap();
+        node_1
+            .add_peer(to_node_addr(node_3_addr.clone()))
+            .await
+            .unwrap();
+        node_2
+            .add_peer(to_node_addr(node_1_addr.clone()))
+            .await
+            .unwrap();
+        node_2.add_peer(to_node_addr(node_3_addr)).await.unwrap();
+        node_3.add_peer(to_node_addr(node_1_addr)).await.unwrap();
+        node_3.add_peer(to_node_addr(node_2_addr)).await.unwrap();
+
+        let (tx_1, _rx_1, ready_1) = node_1.subscribe(topic.clone()).await.unwrap();
+        let (_tx_2, mut rx_2, ready_2) = node_2.subscribe(topic.clone()).await.unwrap();
+        let (tx_3, _rx_3, ready_3) = node_3.subscribe(topic).await.unwrap();
+
+        assert!(ready_3.await.is_ok());
+        assert!(ready_2.await.is_ok());
+        assert!(ready_1.await.is_ok());
+
+        // Messages of the unauthorised neighbour are dropped ..
+        tx_3.send(ToNetwork::Message {
+            bytes: "Spam".to_bytes(),
+        })
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // .. while messages of authorised peers are delivered.
+        tx_1.send(ToNetwork::Message {
+            bytes: "Hello, Node".to_bytes(),
+        })
+        .await
+        .unwrap();
+
+        let Some(FromNetwork::GossipMessage { bytes, .. }) = rx_2.recv().await else {
+            panic!("expected gossip message");
+        };
+        assert_eq!(bytes, "Hello, Node".to_bytes());
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), rx_2.recv())
+                .await
+                .is_err()
+        );
+
+        node_1.shutdown().await.unwrap();
+        node_2.shutdown().await.unwrap();
+        node_3.shutdown().await.unwrap();
+    }
+
     #[tokio::test]
     async fn multi_hop_topic_discovery_and_sync() {
         let network_id = [1; 32];
@@ -1662,4 +2536,36 @@ mod tests {
         node_1.shutdown().await.unwrap();
         node_2.shutdown().await.unwrap();
     }
+
+    #[tokio::test]
+    async fn restart_panicked_engine() {
+        let faults = FaultInjector::new();
+        let mut builder =
+            NetworkBuilder::<TestTopic>::new([18; 32]).panic_policy(PanicPolicy::Isolate);
+        builder.faults = Some(faults.clone());
+        let node = builder.build().await.unwrap();
+
+        let mut event_rx = node.events().await.unwrap();
+
+        // The engine actor panics while handling the request, the reply never arrives.
+        faults.panic_engine();
+        assert!(node.known_peers().await.is_err());
+
+        let event = tokio::time::timeout(Duration::from_secs(5), event_rx.recv())
+            .await
+            .expect("restart is reported")
+            .unwrap();
+        assert_eq!(
+            event,
+            SystemEvent::SubsystemRestarted {
+                subsystem: "engine",
+                attempt: 1,
+            }
+        );
+
+        // The restarted engine actor handles requests again.
+        assert!(node.known_peers().await.is_ok());
+
+        node.shutdown().await.unwrap();
+    }
 }
@@ -0,0 +1,264 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Health of gossip overlays, to detect overlays which silently split.
+//!
+//! Gossip overlays (HyParView and PlumTree) heal themselves, but can still split into partitions
+//! which don't hear from each other, for example after network outages. The engine tracks the
+//! neighbours of every subscribed overlay and when it last received a message, the resulting
+//! [`OverlayHealth`] is returned by `Network::overlay_health`:
+//!
+//! 1. Neighbours are the active view of the overlay, the passive view is not exposed. The size of
+//!    the overlay is estimated from all peers known to be interested in the topic, including our
+//!    neighbours and ourselves.
+//! 2. The churn rate counts neighbours coming up or going down per minute.
+//! 3. A partition is suspected if we have no neighbours while other interested peers are known,
+//!    or if we know more peers than our neighbours but did not receive any message for a long
+//!    time although the overlay was active before. This is a heuristic, very quiet topics can
+//!    trigger it as well. Suspected partitions are reported as
+//!    `SystemEvent::OverlayPartitionSuspected` events.
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use p2panda_core::PublicKey;
+use tokio::time::{Duration, Instant};
+
+/// Time window of the churn rate.
+const CHURN_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Time after subscribing during which no partition is suspected, giving the overlay time to form.
+const PARTITION_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// Time without any message after which an active overlay is suspected to be partitioned.
+const PARTITION_SILENCE: Duration = Duration::from_secs(10 * 60);
+
+/// Health of the gossip overlay of a topic.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OverlayHealth {
+    /// Number of our direct neighbours in the overlay.
+    pub neighbors: usize,
+
+    /// Estimated number of peers in the overlay, including ourselves.
+    pub estimated_size: usize,
+
+    /// Neighbours coming up or going down per minute, averaged over the last five minutes.
+    pub churn_rate: f64,
+
+    /// Time since we last received a message on the overlay, `None` if we never received one.
+    pub since_last_message: Option<Duration>,
+
+    /// The overlay is suspected to be partitioned.
+    pub partition_suspected: bool,
+}
+
+#[derive(Debug)]
+struct Overlay {
+    topic_ids: BTreeSet<[u8; 32]>,
+    tracked_since: Instant,
+    neighbors: HashSet<PublicKey>,
+    changes: VecDeque<Instant>,
+    last_message: Option<Instant>,
+    alarmed: bool,
+}
+
+impl Overlay {
+    fn record_change(&mut self, now: Instant) {
+        self.changes.push_back(now);
+        while self
+            .changes
+            .front()
+            .is_some_and(|change| now.duration_since(*change) > CHURN_WINDOW)
+        {
+            self.changes.pop_front();
+        }
+    }
+}
+
+/// Health of all subscribed gossip overlays, per overlay id.
+#[derive(Debug, Default)]
+pub(crate) struct OverlayMonitor {
+    overlays: HashMap<[u8; 32], Overlay>,
+}
+
+impl OverlayMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking the overlay of a subscribed topic.
+    pub fn track(&mut self, overlay_id: [u8; 32], topic_id: [u8; 32], now: Instant) {
+        self.overlays
+            .entry(overlay_id)
+            .or_insert_with(|| Overlay {
+                topic_ids: BTreeSet::new(),
+                tracked_since: now,
+                neighbors: HashSet::new(),
+                changes: VecDeque::new(),
+                last_message: None,
+                alarmed: false,
+            })
+            .topic_ids
+            .insert(topic_id);
+    }
+
+    /// Stops tracking the overlay after it was left.
+    pub fn untrack(&mut self, overlay_id: &[u8; 32]) {
+        self.overlays.remove(overlay_id);
+    }
+
+    pub fn on_neighbor_up(&mut self, overlay_id: [u8; 32], peer: PublicKey, now: Instant) {
+        if let Some(overlay) = self.overlays.get_mut(&overlay_id)
+            && overlay.neighbors.insert(peer)
+        {
+            overlay.record_change(now);
+        }
+    }
+
+    pub fn on_neighbor_down(&mut self, overlay_id: [u8; 32], peer: PublicKey, now: Instant) {
+        if let Some(overlay) = self.overlays.get_mut(&overlay_id)
+            && overlay.neighbors.remove(&peer)
+        {
+            overlay.record_change(now);
+        }
+    }
+
+    pub fn on_message(&mut self, overlay_id: [u8; 32], now: Instant) {
+        if let Some(overlay) = self.overlays.get_mut(&overlay_id) {
+            overlay.last_message = Some(now);
+        }
+    }
+
+    /// Returns the ids of all tracked overlays.
+    pub fn overlay_ids(&self) -> Vec<[u8; 32]> {
+        self.overlays.keys().copied().collect()
+    }
+
+    /// Returns the health of the overlay, given all other peers known to be interested in it.
+    pub fn health(
+        &self,
+        overlay_id: &[u8; 32],
+        known_peers: &[PublicKey],
+        now: Instant,
+    ) -> Option<OverlayHealth> {
+        let overlay = self.overlays.get(overlay_id)?;
+
+        let neighbors = overlay.neighbors.len();
+        let others = known_peers
+            .iter()
+            .chain(overlay.neighbors.iter())
+            .collect::<HashSet<_>>()
+            .len();
+        let changes = overlay
+            .changes
+            .iter()
+            .filter(|change| now.duration_since(**change) <= CHURN_WINDOW)
+            .count();
+        let since_last_message = overlay
+            .last_message
+            .map(|last_message| now.duration_since(last_message));
+
+        let partition_suspected = now.duration_since(overlay.tracked_since)
+            >= PARTITION_GRACE_PERIOD
+            && others > 0
+            && (neighbors == 0
+                || (others > neighbors
+                    && since_last_message.is_some_and(|silence| silence >= PARTITION_SILENCE)));
+
+        Some(OverlayHealth {
+            neighbors,
+            estimated_size: others + 1,
+            churn_rate: changes as f64 / (CHURN_WINDOW.as_secs_f64() / 60.0),
+            since_last_message,
+            partition_suspected,
+        })
+    }
+
+    /// Marks whether a partition of the overlay is suspected, returns the ids of its topics if
+    /// the suspicion is new.
+    pub fn set_alarm(&mut self, overlay_id: &[u8; 32], suspected: bool) -> Vec<[u8; 32]> {
+        let Some(overlay) = self.overlays.get_mut(overlay_id) else {
+            return Vec::new();
+        };
+        let is_new = suspected && !overlay.alarmed;
+        overlay.alarmed = suspected;
+        if is_new {
+            overlay.topic_ids.iter().copied().collect()
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::PrivateKey;
+    use tokio::time::{Duration, Instant};
+
+    use super::{OverlayMonitor, PARTITION_GRACE_PERIOD, PARTITION_SILENCE};
+
+    #[test]
+    fn track_neighbors_and_messages() {
+        let overlay_id = [1; 32];
+        let peer_1 = PrivateKey::new().public_key();
+        let peer_2 = PrivateKey::new().public_key();
+        let start = Instant::now();
+
+        let mut monitor = OverlayMonitor::new();
+        assert!(monitor.health(&overlay_id, &[], start).is_none());
+
+        monitor.track(overlay_id, overlay_id, start);
+        monitor.on_neighbor_up(overlay_id, peer_1, start);
+        monitor.on_neighbor_up(overlay_id, peer_2, start);
+        monitor.on_neighbor_down(overlay_id, peer_2, start);
+        monitor.on_message(overlay_id, start);
+
+        let now = start + Duration::from_secs(30);
+        let health = monitor.health(&overlay_id, &[peer_1, peer_2], now).unwrap();
+        assert_eq!(health.neighbors, 1);
+        assert_eq!(health.estimated_size, 3);
+        assert_eq!(health.churn_rate, 3.0 / 5.0);
+        assert_eq!(health.since_last_message, Some(Duration::from_secs(30)));
+        assert!(!health.partition_suspected);
+
+        // Changes outside of the window don't count towards the churn rate.
+        let now = start + Duration::from_secs(10 * 60);
+        let health = monitor.health(&overlay_id, &[], now).unwrap();
+        assert_eq!(health.churn_rate, 0.0);
+    }
+
+    #[test]
+    fn suspect_partitions() {
+        let overlay_id = [1; 32];
+        let topic_id = [2; 32];
+        let peer_1 = PrivateKey::new().public_key();
+        let peer_2 = PrivateKey::new().public_key();
+        let start = Instant::now();
+
+        let mut monitor = OverlayMonitor::new();
+        monitor.track(overlay_id, topic_id, start);
+
+        // No neighbours although other peers are interested in the topic, but the overlay is
+        // still forming.
+        let health = monitor.health(&overlay_id, &[peer_1], start).unwrap();
+        assert!(!health.partition_suspected);
+
+        let now = start + PARTITION_GRACE_PERIOD;
+        let health = monitor.health(&overlay_id, &[peer_1], now).unwrap();
+        assert!(health.partition_suspected);
+
+        // Alarms are only raised once per suspicion.
+        assert_eq!(monitor.set_alarm(&overlay_id, true), vec![topic_id]);
+        assert!(monitor.set_alarm(&overlay_id, true).is_empty());
+        assert!(monitor.set_alarm(&overlay_id, false).is_empty());
+
+        // The overlay went silent while more peers than our neighbours are known.
+        monitor.on_neighbor_up(overlay_id, peer_1, now);
+        monitor.on_message(overlay_id, now);
+        let health = monitor.health(&overlay_id, &[peer_1, peer_2], now).unwrap();
+        assert!(!health.partition_suspected);
+
+        let now = now + PARTITION_SILENCE;
+        let health = monitor.health(&overlay_id, &[peer_1, peer_2], now).unwrap();
+        assert!(health.partition_suspected);
+        let health = monitor.health(&overlay_id, &[peer_1], now).unwrap();
+        assert!(!health.partition_suspected);
+    }
+}
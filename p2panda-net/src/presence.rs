@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Presence of peers on topics, for example to show which peers are currently online.
+//!
+//! Nodes with presence enabled attach a heartbeat with a small, application-defined status to
+//! their "topic discovery" announcements for every topic they are subscribed to. Receiving nodes
+//! keep a set of present peers per topic and drop peers which didn't send a heartbeat within the
+//! configured timeout. Changes of the set are reported as `SystemEvent::PeerOnline`,
+//! `SystemEvent::PeerStatusChanged` and `SystemEvent::PeerOffline` events.
+//!
+//! Announcements are broadcast to the whole network, status blobs are visible to every peer and
+//! should not contain any private information.
+use std::collections::HashMap;
+
+use p2panda_core::PublicKey;
+use tokio::time::{Duration, Instant};
+
+/// Maximum size of a status blob in bytes.
+///
+/// Status blobs are sent in "topic discovery" gossip messages for every subscribed topic and need
+/// to stay small.
+pub const MAX_PRESENCE_STATUS_LEN: usize = 256;
+
+/// Default duration after which peers without a heartbeat are considered offline.
+///
+/// Heartbeats are sent with every topic announcement, roughly every two seconds.
+pub const DEFAULT_PRESENCE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Configuration of presence heartbeats, see `NetworkBuilder::presence`.
+#[derive(Clone, Debug)]
+pub struct PresenceConfig {
+    timeout: Duration,
+}
+
+impl PresenceConfig {
+    /// Returns a configuration with the default timeout.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define the duration after which peers without a heartbeat are considered offline.
+    ///
+    /// Default: 10 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_PRESENCE_TIMEOUT,
+        }
+    }
+}
+
+/// Peer present on a topic together with the status it announced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerPresence {
+    pub peer: PublicKey,
+    pub status: Vec<u8>,
+}
+
+/// Change of the presence set caused by a heartbeat.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum PresenceChange {
+    Online,
+    StatusChanged,
+}
+
+#[derive(Debug)]
+struct Heartbeat {
+    status: Vec<u8>,
+    last_seen: Instant,
+}
+
+/// Expiring set of present peers per topic id.
+#[derive(Debug)]
+pub(crate) struct PresenceSet {
+    timeout: Duration,
+    topics: HashMap<[u8; 32], HashMap<PublicKey, Heartbeat>>,
+}
+
+impl PresenceSet {
+    pub fn new(config: PresenceConfig) -> Self {
+        Self {
+            timeout: config.timeout,
+            topics: HashMap::new(),
+        }
+    }
+
+    /// Records a heartbeat of a peer on a topic id, returns the change of the set if any.
+    pub fn on_heartbeat(
+        &mut self,
+        topic_id: [u8; 32],
+        peer: PublicKey,
+        status: Vec<u8>,
+    ) -> Option<PresenceChange> {
+        let peers = self.topics.entry(topic_id).or_default();
+        let last_seen = Instant::now();
+        match peers.insert(peer, Heartbeat { status, last_seen }) {
+            None => Some(PresenceChange::Online),
+            Some(previous) if previous.status != peers[&peer].status => {
+                Some(PresenceChange::StatusChanged)
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// Removes all peers without a heartbeat within the timeout and returns them.
+    pub fn expire(&mut self) -> Vec<([u8; 32], PublicKey)> {
+        let mut expired = Vec::new();
+        self.topics.retain(|topic_id, peers| {
+            peers.retain(|peer, heartbeat| {
+                let present = heartbeat.last_seen.elapsed() < self.timeout;
+                if !present {
+                    expired.push((*topic_id, *peer));
+                }
+                present
+            });
+            !peers.is_empty()
+        });
+        expired
+    }
+
+    /// Returns all peers currently present on the topic id.
+    pub fn peers(&self, topic_id: &[u8; 32]) -> Vec<PeerPresence> {
+        self.topics
+            .get(topic_id)
+            .map(|peers| {
+                peers
+                    .iter()
+                    .map(|(peer, heartbeat)| PeerPresence {
+                        peer: *peer,
+                        status: heartbeat.status.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::PrivateKey;
+    use tokio::time::Duration;
+
+    use super::{PeerPresence, PresenceChange, PresenceConfig, PresenceSet};
+
+    #[test]
+    fn track_heartbeats() {
+        let topic_id = [1; 32];
+        let peer = PrivateKey::new().public_key();
+
+        let mut presence = PresenceSet::new(PresenceConfig::new());
+        assert_eq!(
+            presence.on_heartbeat(topic_id, peer, b"away".to_vec()),
+            Some(PresenceChange::Online)
+        );
+        assert_eq!(
+            presence.on_heartbeat(topic_id, peer, b"away".to_vec()),
+            None
+        );
+        assert_eq!(
+            presence.on_heartbeat(topic_id, peer, b"typing".to_vec()),
+            Some(PresenceChange::StatusChanged)
+        );
+
+        assert_eq!(
+            presence.peers(&topic_id),
+            vec![PeerPresence {
+                peer,
+                status: b"typing".to_vec()
+            }]
+        );
+        assert!(presence.peers(&[2; 32]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn expire_peers_without_heartbeat() {
+        let topic_id = [1; 32];
+        let peer = PrivateKey::new().public_key();
+
+        let mut presence =
+            PresenceSet::new(PresenceConfig::new().timeout(Duration::from_millis(20)));
+        presence.on_heartbeat(topic_id, peer, Vec::new());
+        assert!(presence.expire().is_empty());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(presence.expire(), vec![(topic_id, peer)]);
+        assert!(presence.peers(&topic_id).is_empty());
+    }
+}
@@ -1,7 +1,7 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use std::any::Any;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::sync::Arc;
 
@@ -11,6 +11,9 @@ use futures_util::future::join_all;
 use iroh::endpoint::Connecting;
 use tracing::debug;
 
+use crate::NetworkId;
+use crate::telemetry::HexId;
+
 /// Interface to accept incoming connections for custom protocol implementations.
 ///
 /// A node can accept connections for custom protocols. By default, the node only accepts
@@ -40,30 +43,108 @@ impl<T: Send + Sync + 'static> IntoArcAny for T {
     }
 }
 
+/// Namespace of the ALPN identifiers of all protocols, see `NetworkBuilder::alpn_namespace`.
+///
+/// Peers only accept connections over protocols of the same namespace. This keeps different
+/// applications using the same network identifier from accidentally interoperating.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum AlpnNamespace {
+    /// Protocols use their plain ALPN identifiers.
+    #[default]
+    None,
+
+    /// ALPN identifiers are prefixed with the hex-encoded network identifier.
+    NetworkId,
+
+    /// ALPN identifiers are prefixed with the given application name.
+    App(String),
+}
+
+impl AlpnNamespace {
+    /// Returns the prefix of ALPN identifiers in this namespace.
+    pub(crate) fn prefix(&self, network_id: &NetworkId) -> Vec<u8> {
+        match self {
+            AlpnNamespace::None => Vec::new(),
+            AlpnNamespace::NetworkId => format!("/{}", HexId(network_id)).into_bytes(),
+            AlpnNamespace::App(app) => format!("/{app}").into_bytes(),
+        }
+    }
+}
+
+/// Returns the ALPN identifier of a protocol with the given namespace prefix.
+pub(crate) fn namespaced_alpn(prefix: &[u8], alpn: &[u8]) -> Vec<u8> {
+    [prefix, alpn].concat()
+}
+
 #[derive(Debug, Clone, Default)]
-pub(super) struct ProtocolMap(BTreeMap<&'static [u8], Arc<dyn ProtocolHandler>>);
+pub(super) struct ProtocolMap {
+    handlers: BTreeMap<&'static [u8], Arc<dyn ProtocolHandler>>,
+    global: BTreeSet<&'static [u8]>,
+    prefix: Vec<u8>,
+}
 
 impl ProtocolMap {
     /// Returns the registered protocol handler for an ALPN as a [`Arc<dyn ProtocolHandler>`].
     pub(super) fn get(&self, alpn: &[u8]) -> Option<Arc<dyn ProtocolHandler>> {
-        self.0.get(alpn).cloned()
+        self.handlers.get(alpn).cloned()
     }
 
-    /// Inserts a protocol handler.
+    /// Returns the protocol handler for the ALPN negotiated by an incoming connection.
+    pub(super) fn resolve(&self, alpn: &[u8]) -> Option<Arc<dyn ProtocolHandler>> {
+        if let Some(alpn) = alpn.strip_prefix(self.prefix.as_slice())
+            && !self.global.contains(alpn)
+            && let Some(handler) = self.get(alpn)
+        {
+            return Some(handler);
+        }
+
+        if self.global.contains(alpn) {
+            self.get(alpn)
+        } else {
+            None
+        }
+    }
+
+    /// Inserts a protocol handler, its ALPN is namespaced.
     pub(super) fn insert(&mut self, alpn: &'static [u8], handler: Arc<dyn ProtocolHandler>) {
-        self.0.insert(alpn, handler);
+        self.global.remove(alpn);
+        self.handlers.insert(alpn, handler);
+    }
+
+    /// Inserts a protocol handler, its ALPN is used as is in all namespaces.
+    pub(super) fn insert_global(&mut self, alpn: &'static [u8], handler: Arc<dyn ProtocolHandler>) {
+        self.global.insert(alpn);
+        self.handlers.insert(alpn, handler);
+    }
+
+    /// Sets the namespace prefix of the ALPN identifiers.
+    pub(super) fn set_prefix(&mut self, prefix: Vec<u8>) {
+        self.prefix = prefix;
     }
 
     /// Returns an iterator of all registered ALPN protocol identifiers.
     pub(super) fn alpns(&self) -> Vec<Vec<u8>> {
-        self.0.keys().map(|alpn| alpn.to_vec()).collect::<Vec<_>>()
+        self.handlers
+            .keys()
+            .map(|alpn| {
+                if self.global.contains(alpn) {
+                    alpn.to_vec()
+                } else {
+                    namespaced_alpn(&self.prefix, alpn)
+                }
+            })
+            .collect::<Vec<_>>()
     }
 
     /// Shuts down all protocol handlers.
     ///
     /// Calls and awaits [`ProtocolHandler::shutdown`] for all registered handlers concurrently.
     pub(super) async fn shutdown(&self) {
-        let handlers = self.0.values().cloned().map(ProtocolHandler::shutdown);
+        let handlers = self
+            .handlers
+            .values()
+            .cloned()
+            .map(ProtocolHandler::shutdown);
         debug!("await all handler shutdown handles");
         join_all(handlers).await;
         debug!("all handlers closed");
@@ -78,3 +159,56 @@ impl ProtocolHandler for iroh_gossip::net::Gossip {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use futures_lite::future::Boxed as BoxedFuture;
+    use iroh::endpoint::Connecting;
+
+    use super::{AlpnNamespace, ProtocolHandler, ProtocolMap};
+
+    #[derive(Debug)]
+    struct NoopProtocol;
+
+    impl ProtocolHandler for NoopProtocol {
+        fn accept(self: Arc<Self>, _conn: Connecting) -> BoxedFuture<Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[test]
+    fn namespaced_alpns() {
+        assert!(AlpnNamespace::None.prefix(&[1; 32]).is_empty());
+        assert_eq!(
+            AlpnNamespace::NetworkId.prefix(&[1; 32]),
+            format!("/{}", "01".repeat(32)).into_bytes()
+        );
+        assert_eq!(
+            AlpnNamespace::App("chat".into()).prefix(&[1; 32]),
+            b"/chat".to_vec()
+        );
+
+        let mut protocols = ProtocolMap::default();
+        protocols.insert(b"/noop/1", Arc::new(NoopProtocol));
+        protocols.insert_global(b"/global/1", Arc::new(NoopProtocol));
+        protocols.set_prefix(b"/chat".to_vec());
+
+        assert_eq!(
+            protocols.alpns(),
+            vec![b"/global/1".to_vec(), b"/chat/noop/1".to_vec()]
+        );
+        assert!(protocols.resolve(b"/chat/noop/1").is_some());
+        assert!(protocols.resolve(b"/global/1").is_some());
+
+        // Protocols of other namespaces are not accepted.
+        assert!(protocols.resolve(b"/noop/1").is_none());
+        assert!(protocols.resolve(b"/other/noop/1").is_none());
+        assert!(protocols.resolve(b"/chat/global/1").is_none());
+
+        // Handlers are still looked up by their plain ALPN.
+        assert!(protocols.get(b"/noop/1").is_some());
+    }
+}
@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Compact announcements of the blobs a node can provide for a topic.
+//!
+//! Nodes providing blobs attach a bloom filter of their blob hashes to their "topic discovery"
+//! announcements. Downloaders check the filters of all known peers to pick likely providers
+//! instead of asking every peer one by one.
+//!
+//! Bloom filters can yield false positives but never false negatives: a peer which is picked as a
+//! provider might not have the blob after all, while a peer whose filter doesn't contain a hash
+//! definitely didn't announce it.
+use p2panda_core::Hash;
+use serde::{Deserialize, Serialize};
+
+/// Maximum size of a blob filter in bytes.
+///
+/// Filters are sent in "topic discovery" gossip messages and need to stay well below the maximum
+/// gossip message size. With the default false-positive rate of 1% this fits around 430 hashes,
+/// filters for more hashes get less precise.
+pub const MAX_BLOB_FILTER_LEN: usize = 512;
+
+/// Number of bits per hash in a filter, resulting in a false-positive rate of around 1%.
+const BITS_PER_HASH: usize = 10;
+
+/// Number of bit positions set for every hash.
+const NUM_HASHES: u8 = 7;
+
+/// Bloom filter over the hashes of blobs a node provides.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobFilter {
+    bits: Vec<u8>,
+    num_hashes: u8,
+}
+
+impl BlobFilter {
+    /// Returns a filter containing all given blob hashes.
+    pub fn new<'a>(hashes: impl IntoIterator<Item = &'a Hash>) -> Self {
+        let hashes: Vec<&Hash> = hashes.into_iter().collect();
+        let len = (hashes.len() * BITS_PER_HASH)
+            .div_ceil(8)
+            .clamp(1, MAX_BLOB_FILTER_LEN);
+
+        let mut filter = Self {
+            bits: vec![0; len],
+            num_hashes: NUM_HASHES,
+        };
+        for hash in hashes {
+            filter.insert(hash);
+        }
+        filter
+    }
+
+    /// Adds a blob hash to the filter.
+    pub fn insert(&mut self, hash: &Hash) {
+        for index in self.indices(hash).collect::<Vec<usize>>() {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    /// Returns `true` if the blob hash is likely contained in the filter and `false` if it is
+    /// definitely not.
+    pub fn contains(&self, hash: &Hash) -> bool {
+        // Filters without any bits or hash functions would contain every hash, they can only be
+        // received from misbehaving peers.
+        if self.bits.is_empty() || self.num_hashes == 0 {
+            return false;
+        }
+
+        self.indices(hash)
+            .all(|index| self.bits[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    /// Returns `true` if no hash was added to the filter.
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|byte| *byte == 0)
+    }
+
+    /// Bit positions of a hash in the filter.
+    ///
+    /// BLAKE3 hashes are uniformly distributed already, the positions are derived from two parts
+    /// of the hash with double hashing.
+    fn indices(&self, hash: &Hash) -> impl Iterator<Item = usize> {
+        let bytes = hash.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[0..8].try_into().expect("hash has 32 bytes"));
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().expect("hash has 32 bytes"));
+        let num_bits = self.bits.len() as u64 * 8;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::Hash;
+
+    use super::{BlobFilter, MAX_BLOB_FILTER_LEN};
+
+    #[test]
+    fn contains_inserted_hashes() {
+        let hashes: Vec<Hash> = (0..100).map(|i| Hash::new(format!("blob {i}"))).collect();
+        let filter = BlobFilter::new(&hashes);
+        assert!(!filter.is_empty());
+        assert!(hashes.iter().all(|hash| filter.contains(hash)));
+
+        // Some false positives are expected, but not many.
+        let false_positives = (100..1100)
+            .filter(|i| filter.contains(&Hash::new(format!("blob {i}"))))
+            .count();
+        assert!(false_positives < 50);
+    }
+
+    #[test]
+    fn bounded_size() {
+        let hashes: Vec<Hash> = (0..10_000)
+            .map(|i| Hash::new(format!("blob {i}")))
+            .collect();
+        let filter = BlobFilter::new(&hashes);
+        assert_eq!(filter.bits.len(), MAX_BLOB_FILTER_LEN);
+        assert!(hashes.iter().all(|hash| filter.contains(hash)));
+
+        let filter = BlobFilter::new(&[]);
+        assert!(filter.is_empty());
+        assert!(!filter.contains(&Hash::new("blob")));
+    }
+
+    #[test]
+    fn invalid_filters() {
+        let filter = BlobFilter {
+            bits: Vec::new(),
+            num_hashes: 7,
+        };
+        assert!(!filter.contains(&Hash::new("blob")));
+
+        let filter = BlobFilter {
+            bits: vec![u8::MAX; 8],
+            num_hashes: 0,
+        };
+        assert!(!filter.contains(&Hash::new("blob")));
+    }
+}
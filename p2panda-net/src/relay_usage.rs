@@ -0,0 +1,388 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Accounting of traffic over relayed connections.
+//!
+//! Peers which can't establish a direct connection, for example because both are behind NATs,
+//! exchange all data over a relay server. Relays are often run by communities on limited
+//! resources, bulk transfers like large sync sessions or blob downloads can easily exhaust them.
+//!
+//! Every sync session is accounted per peer, depending on whether its connection currently uses a
+//! direct or a relayed path. The counters are exposed by `Network::relay_usage`. A
+//! [`RelayPolicy`], set with `NetworkBuilder::relay_policy`, additionally restricts bulk transfers
+//! over relayed paths:
+//!
+//! 1. Sync sessions with peers only reachable via a relay are refused or their bandwidth is
+//!    capped. Refused sessions are retried later, by then a direct path might have been found.
+//! 2. Blob downloads refused by the policy are only attempted from peers with a direct path or
+//!    known direct addresses, see [`RelayUsage::bulk_transfer_addr`].
+//!
+//! Gossip messages and other small control messages are never restricted.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use iroh::Endpoint;
+use iroh::endpoint::ConnectionType;
+use p2panda_core::PublicKey;
+use thiserror::Error;
+
+use crate::{NodeAddress, from_public_key};
+
+/// Policy for bulk transfers with peers only reachable via a relay.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RelayPolicy {
+    /// Transfer data over relayed paths like over direct ones.
+    #[default]
+    Allow,
+
+    /// Refuse sync sessions and blob downloads over relayed paths.
+    Refuse,
+
+    /// Limit the bandwidth of sync sessions over relayed paths to the given number of bytes per
+    /// second, applied separately to sent and received data.
+    ///
+    /// Blob downloads can't be throttled and are allowed.
+    Limit(u64),
+}
+
+/// Path of the connection to a peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionPath {
+    /// Data is sent directly to the peer.
+    Direct,
+
+    /// Data is sent via a relay server, at least partially.
+    Relayed,
+}
+
+/// Bulk transfer refused by the relay policy.
+#[derive(Debug, Error)]
+#[error("bulk transfer over relayed path refused by policy")]
+pub(crate) struct RelayRefused;
+
+/// Sync session admitted by the relay policy.
+#[derive(Debug, Default)]
+pub(crate) struct RelaySession {
+    /// Maximum bandwidth of the session in bytes per second (`None` represents no limit).
+    pub bandwidth: Option<u64>,
+
+    /// Counter of bytes sent and received over the path of the session.
+    pub bytes: Option<Arc<AtomicU64>>,
+}
+
+/// Traffic with a peer over direct and relayed paths.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerRelayUsage {
+    /// Number of sync sessions over a direct path.
+    pub direct_sessions: u64,
+
+    /// Number of sync sessions over a relayed path.
+    pub relayed_sessions: u64,
+
+    /// Number of sync sessions refused by the relay policy.
+    pub refused_sessions: u64,
+
+    /// Number of bytes sent and received in sync sessions over a direct path.
+    pub direct_bytes: u64,
+
+    /// Number of bytes sent and received in sync sessions over a relayed path.
+    pub relayed_bytes: u64,
+}
+
+impl PeerRelayUsage {
+    fn add(mut self, other: PeerRelayUsage) -> Self {
+        self.direct_sessions += other.direct_sessions;
+        self.relayed_sessions += other.relayed_sessions;
+        self.refused_sessions += other.refused_sessions;
+        self.direct_bytes += other.direct_bytes;
+        self.relayed_bytes += other.relayed_bytes;
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    direct_sessions: AtomicU64,
+    relayed_sessions: AtomicU64,
+    refused_sessions: AtomicU64,
+    direct_bytes: Arc<AtomicU64>,
+    relayed_bytes: Arc<AtomicU64>,
+}
+
+impl Counters {
+    fn usage(&self) -> PeerRelayUsage {
+        PeerRelayUsage {
+            direct_sessions: self.direct_sessions.load(Ordering::Relaxed),
+            relayed_sessions: self.relayed_sessions.load(Ordering::Relaxed),
+            refused_sessions: self.refused_sessions.load(Ordering::Relaxed),
+            direct_bytes: self.direct_bytes.load(Ordering::Relaxed),
+            relayed_bytes: self.relayed_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Handle to the relay usage of all peers, see the module documentation.
+#[derive(Clone, Debug)]
+pub struct RelayUsage {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    endpoint: Option<Endpoint>,
+    policy: RelayPolicy,
+    peers: Mutex<HashMap<PublicKey, Arc<Counters>>>,
+}
+
+impl RelayUsage {
+    /// Returns the relay usage of the endpoint's connections.
+    pub(crate) fn new(endpoint: Endpoint, policy: RelayPolicy) -> Self {
+        Self::with_endpoint(Some(endpoint), policy)
+    }
+
+    fn with_endpoint(endpoint: Option<Endpoint>, policy: RelayPolicy) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                endpoint,
+                policy,
+                peers: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<PublicKey, Arc<Counters>>> {
+        self.inner
+            .peers
+            .lock()
+            .expect("relay usage lock was poisoned")
+    }
+
+    fn counters(&self, peer: PublicKey) -> Arc<Counters> {
+        self.lock().entry(peer).or_default().clone()
+    }
+
+    /// Returns the relay policy of the node.
+    pub fn policy(&self) -> RelayPolicy {
+        self.inner.policy
+    }
+
+    /// Returns the current path of the connection to the peer, `None` if we're not connected.
+    pub fn path(&self, peer: &PublicKey) -> Option<ConnectionPath> {
+        let info = self
+            .inner
+            .endpoint
+            .as_ref()?
+            .remote_info(from_public_key(*peer))?;
+        match info.conn_type {
+            ConnectionType::Direct(_) => Some(ConnectionPath::Direct),
+            ConnectionType::None => None,
+            // Mixed connections are still relayed while a direct path is being established.
+            _ => Some(ConnectionPath::Relayed),
+        }
+    }
+
+    /// Returns the traffic with the peer.
+    pub fn peer(&self, peer: &PublicKey) -> PeerRelayUsage {
+        self.lock()
+            .get(peer)
+            .map(|counters| counters.usage())
+            .unwrap_or_default()
+    }
+
+    /// Returns the traffic with all peers we had sync sessions with.
+    pub fn peers(&self) -> Vec<(PublicKey, PeerRelayUsage)> {
+        self.lock()
+            .iter()
+            .map(|(peer, counters)| (*peer, counters.usage()))
+            .collect()
+    }
+
+    /// Returns the traffic with all peers combined.
+    pub fn total(&self) -> PeerRelayUsage {
+        self.lock()
+            .values()
+            .fold(PeerRelayUsage::default(), |total, counters| {
+                total.add(counters.usage())
+            })
+    }
+
+    /// Returns the address to download bulk data like blobs from, `None` if the policy refuses
+    /// transfers with the peer.
+    ///
+    /// With `RelayPolicy::Refuse` peers currently connected over a relayed path are refused and
+    /// the relay url is removed from the address of all others, so they are only dialed directly.
+    pub fn bulk_transfer_addr(&self, mut node_addr: NodeAddress) -> Option<NodeAddress> {
+        if self.inner.policy != RelayPolicy::Refuse {
+            return Some(node_addr);
+        }
+        if self.path(&node_addr.public_key) == Some(ConnectionPath::Relayed) {
+            return None;
+        }
+        node_addr.relay_url = None;
+        if node_addr.direct_addresses.is_empty() {
+            return None;
+        }
+        Some(node_addr)
+    }
+
+    /// Admits a sync session with the peer over the current path of its connection, capping the
+    /// given bandwidth according to the policy.
+    pub(crate) fn admit(
+        &self,
+        peer: PublicKey,
+        bandwidth: Option<u64>,
+    ) -> Result<RelaySession, RelayRefused> {
+        // Connections without any known path yet are accounted as direct.
+        let path = self.path(&peer).unwrap_or(ConnectionPath::Direct);
+        self.admit_path(peer, path, bandwidth)
+    }
+
+    fn admit_path(
+        &self,
+        peer: PublicKey,
+        path: ConnectionPath,
+        bandwidth: Option<u64>,
+    ) -> Result<RelaySession, RelayRefused> {
+        let counters = self.counters(peer);
+        if path == ConnectionPath::Direct {
+            counters.direct_sessions.fetch_add(1, Ordering::Relaxed);
+            return Ok(RelaySession {
+                bandwidth,
+                bytes: Some(counters.direct_bytes.clone()),
+            });
+        }
+
+        let bandwidth = match self.inner.policy {
+            RelayPolicy::Allow => bandwidth,
+            RelayPolicy::Refuse => {
+                counters.refused_sessions.fetch_add(1, Ordering::Relaxed);
+                return Err(RelayRefused);
+            }
+            RelayPolicy::Limit(limit) => Some(bandwidth.map_or(limit, |max| max.min(limit))),
+        };
+        counters.relayed_sessions.fetch_add(1, Ordering::Relaxed);
+        Ok(RelaySession {
+            bandwidth,
+            bytes: Some(counters.relayed_bytes.clone()),
+        })
+    }
+}
+
+/// Admits a sync session with the peer, without relay accounting if `relay_usage` is `None`.
+pub(crate) fn admit_session(
+    relay_usage: Option<&RelayUsage>,
+    peer: PublicKey,
+    bandwidth: Option<u64>,
+) -> Result<RelaySession, RelayRefused> {
+    match relay_usage {
+        Some(relay_usage) => relay_usage.admit(peer, bandwidth),
+        None => Ok(RelaySession {
+            bandwidth,
+            bytes: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use p2panda_core::PrivateKey;
+
+    use crate::NodeAddress;
+
+    use super::{ConnectionPath, PeerRelayUsage, RelayPolicy, RelayUsage};
+
+    #[test]
+    fn account_sessions_per_path() {
+        let peer = PrivateKey::new().public_key();
+        let relay_usage = RelayUsage::with_endpoint(None, RelayPolicy::Allow);
+
+        let session = relay_usage
+            .admit_path(peer, ConnectionPath::Direct, Some(1000))
+            .unwrap();
+        assert_eq!(session.bandwidth, Some(1000));
+        session.bytes.unwrap().fetch_add(100, Ordering::Relaxed);
+
+        let session = relay_usage
+            .admit_path(peer, ConnectionPath::Relayed, None)
+            .unwrap();
+        assert_eq!(session.bandwidth, None);
+        session.bytes.unwrap().fetch_add(50, Ordering::Relaxed);
+
+        let usage = PeerRelayUsage {
+            direct_sessions: 1,
+            relayed_sessions: 1,
+            refused_sessions: 0,
+            direct_bytes: 100,
+            relayed_bytes: 50,
+        };
+        assert_eq!(relay_usage.peer(&peer), usage);
+        assert_eq!(relay_usage.peers(), vec![(peer, usage)]);
+
+        let other = PrivateKey::new().public_key();
+        relay_usage
+            .admit_path(other, ConnectionPath::Direct, None)
+            .unwrap();
+        assert_eq!(relay_usage.total().direct_sessions, 2);
+    }
+
+    #[test]
+    fn apply_policy_to_relayed_paths() {
+        let peer = PrivateKey::new().public_key();
+
+        let relay_usage = RelayUsage::with_endpoint(None, RelayPolicy::Refuse);
+        assert!(
+            relay_usage
+                .admit_path(peer, ConnectionPath::Relayed, None)
+                .is_err()
+        );
+        assert!(
+            relay_usage
+                .admit_path(peer, ConnectionPath::Direct, None)
+                .is_ok()
+        );
+        assert_eq!(relay_usage.peer(&peer).refused_sessions, 1);
+        assert_eq!(relay_usage.peer(&peer).relayed_sessions, 0);
+
+        // The lower of both limits applies.
+        let relay_usage = RelayUsage::with_endpoint(None, RelayPolicy::Limit(500));
+        let bandwidth = |bandwidth| {
+            relay_usage
+                .admit_path(peer, ConnectionPath::Relayed, bandwidth)
+                .unwrap()
+                .bandwidth
+        };
+        assert_eq!(bandwidth(None), Some(500));
+        assert_eq!(bandwidth(Some(200)), Some(200));
+        assert_eq!(bandwidth(Some(1000)), Some(500));
+    }
+
+    #[test]
+    fn refuse_relayed_blob_transfers() {
+        let peer = PrivateKey::new().public_key();
+        let node_addr = NodeAddress {
+            public_key: peer,
+            direct_addresses: vec!["127.0.0.1:2022".parse().unwrap()],
+            relay_url: Some("https://relay.example.org".parse().unwrap()),
+        };
+
+        let relay_usage = RelayUsage::with_endpoint(None, RelayPolicy::Allow);
+        assert_eq!(
+            relay_usage.bulk_transfer_addr(node_addr.clone()),
+            Some(node_addr.clone())
+        );
+
+        // Peers are only dialed directly.
+        let relay_usage = RelayUsage::with_endpoint(None, RelayPolicy::Refuse);
+        let direct = relay_usage.bulk_transfer_addr(node_addr.clone()).unwrap();
+        assert_eq!(direct.relay_url, None);
+        assert_eq!(direct.direct_addresses, node_addr.direct_addresses);
+
+        let relay_only = NodeAddress {
+            direct_addresses: Vec::new(),
+            ..node_addr
+        };
+        assert_eq!(relay_usage.bulk_transfer_addr(relay_only), None);
+    }
+}
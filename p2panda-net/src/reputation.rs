@@ -0,0 +1,323 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Reputation of peers, lowered by reported misbehaviour.
+//!
+//! Every node keeps a reputation score per peer. Scores start at zero, are lowered whenever a peer
+//! misbehaves and slowly recover with every successful sync session:
+//!
+//! 1. Sync sessions failing because of the remote peer, for example by sending invalid data, are
+//!    reported automatically. Applications and custom protocol handlers report misbehaviour they
+//!    detect themselves with [`Reputation::report`], for example spam or operations with invalid
+//!    signatures.
+//! 2. Peers with a negative score are deferred by the sync scheduler, other peers are synced with
+//!    first.
+//! 3. Peers with a score at or below the ban threshold are banned: we don't sync with them, reject
+//!    their sync connections and drop their gossip messages and topic announcements.
+//!
+//! Scores are kept in memory by default. A [`ReputationStore`] persists them, so banned peers stay
+//! banned after a restart.
+//!
+//! ```rust
+//! use p2panda_core::PrivateKey;
+//! use p2panda_net::{Misbehaviour, Reputation, ReputationConfig};
+//!
+//! let peer = PrivateKey::new().public_key();
+//! let reputation = Reputation::new(ReputationConfig::new().ban_threshold(-40));
+//!
+//! reputation.report(peer, Misbehaviour::InvalidSignature);
+//! assert!(!reputation.is_banned(&peer));
+//! reputation.report(peer, Misbehaviour::InvalidSignature);
+//! assert!(reputation.is_banned(&peer));
+//! ```
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use p2panda_core::PublicKey;
+use p2panda_sync::SyncError;
+use tracing::debug;
+
+/// Default score at or below which peers are banned.
+pub const DEFAULT_BAN_THRESHOLD: i32 = -100;
+
+/// Maximum score of a peer.
+pub const MAX_REPUTATION_SCORE: i32 = 100;
+
+/// Score gained with every successful sync session.
+const SYNC_SUCCESS_REWARD: i32 = 5;
+
+/// Misbehaviour of a peer, lowering its reputation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Misbehaviour {
+    /// Sent data with an invalid signature.
+    InvalidSignature,
+
+    /// Sent data which failed validation.
+    InvalidData,
+
+    /// Did not follow the protocol, for example by sending unexpected messages.
+    ProtocolViolation,
+
+    /// Sent an excessive amount of messages or too large messages.
+    Spam,
+}
+
+impl Misbehaviour {
+    /// Returns the score a peer loses for this misbehaviour.
+    pub fn penalty(&self) -> i32 {
+        match self {
+            Misbehaviour::InvalidSignature => 50,
+            Misbehaviour::InvalidData => 20,
+            Misbehaviour::ProtocolViolation => 20,
+            Misbehaviour::Spam => 10,
+        }
+    }
+
+    /// Returns the misbehaviour of the remote peer which caused the sync error, if any.
+    pub(crate) fn from_sync_error(err: &SyncError) -> Option<Self> {
+        match err {
+            SyncError::Validation(_) => Some(Misbehaviour::InvalidData),
+            SyncError::UnexpectedBehaviour(_) | SyncError::InvalidEncoding(_) => {
+                Some(Misbehaviour::ProtocolViolation)
+            }
+            SyncError::MessageTooLarge(_) => Some(Misbehaviour::Spam),
+            _ => None,
+        }
+    }
+}
+
+/// Persistence of reputation scores.
+pub trait ReputationStore: Debug + Send + Sync + 'static {
+    /// Returns all persisted scores, called once when the reputation is created.
+    fn load(&self) -> Vec<(PublicKey, i32)>;
+
+    /// Persists the changed score of a peer.
+    fn save(&self, peer: &PublicKey, score: i32);
+}
+
+/// Configuration of peer reputations, see `NetworkBuilder::reputation`.
+#[derive(Clone, Debug)]
+pub struct ReputationConfig {
+    ban_threshold: i32,
+    store: Option<Arc<dyn ReputationStore>>,
+}
+
+impl ReputationConfig {
+    /// Returns a configuration with the default ban threshold, keeping scores in memory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define the score at or below which peers are banned.
+    ///
+    /// Default: -100.
+    pub fn ban_threshold(mut self, ban_threshold: i32) -> Self {
+        self.ban_threshold = ban_threshold;
+        self
+    }
+
+    /// Persist scores in the given store.
+    pub fn store(mut self, store: impl ReputationStore) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            ban_threshold: DEFAULT_BAN_THRESHOLD,
+            store: None,
+        }
+    }
+}
+
+/// Handle to the reputation scores of peers.
+///
+/// The handle can be cloned, for example to hand it to custom protocol handlers, all clones share
+/// the same scores.
+#[derive(Clone, Debug)]
+pub struct Reputation {
+    inner: Arc<Mutex<Scores>>,
+}
+
+#[derive(Debug)]
+struct Scores {
+    config: ReputationConfig,
+    scores: HashMap<PublicKey, i32>,
+}
+
+impl Reputation {
+    /// Returns a reputation with the given configuration, loading persisted scores.
+    pub fn new(config: ReputationConfig) -> Self {
+        let scores = config
+            .store
+            .as_ref()
+            .map(|store| store.load().into_iter().collect())
+            .unwrap_or_default();
+        Self {
+            inner: Arc::new(Mutex::new(Scores { config, scores })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Scores> {
+        self.inner.lock().expect("reputation lock was poisoned")
+    }
+
+    /// Reports misbehaviour of a peer and returns its lowered score.
+    pub fn report(&self, peer: PublicKey, misbehaviour: Misbehaviour) -> i32 {
+        debug!(%peer, ?misbehaviour, "peer misbehaved");
+        self.lock().add(peer, -misbehaviour.penalty())
+    }
+
+    /// Rewards a peer for a successful interaction, up to `MAX_REPUTATION_SCORE`.
+    pub(crate) fn reward(&self, peer: PublicKey) {
+        self.lock().add(peer, SYNC_SUCCESS_REWARD);
+    }
+
+    /// Resets the score of a peer, lifting a ban.
+    pub fn forgive(&self, peer: PublicKey) {
+        self.lock().set(peer, 0);
+    }
+
+    /// Returns the score of a peer, zero for unknown peers.
+    pub fn score(&self, peer: &PublicKey) -> i32 {
+        self.lock().scores.get(peer).copied().unwrap_or_default()
+    }
+
+    /// Returns `true` if the score of the peer is at or below the ban threshold.
+    pub fn is_banned(&self, peer: &PublicKey) -> bool {
+        let scores = self.lock();
+        scores.scores.get(peer).copied().unwrap_or_default() <= scores.config.ban_threshold
+    }
+
+    /// Returns `true` if the peer has a negative score and should be synced with last.
+    pub(crate) fn is_deprioritised(&self, peer: &PublicKey) -> bool {
+        self.score(peer) < 0
+    }
+}
+
+impl Default for Reputation {
+    fn default() -> Self {
+        Self::new(ReputationConfig::default())
+    }
+}
+
+impl Scores {
+    fn add(&mut self, peer: PublicKey, delta: i32) -> i32 {
+        let score = self.scores.get(&peer).copied().unwrap_or_default();
+        let score = score.saturating_add(delta).min(MAX_REPUTATION_SCORE);
+        self.set(peer, score);
+        score
+    }
+
+    fn set(&mut self, peer: PublicKey, score: i32) {
+        if self.scores.get(&peer).copied().unwrap_or_default() == score {
+            return;
+        }
+
+        if score == 0 {
+            self.scores.remove(&peer);
+        } else {
+            self.scores.insert(peer, score);
+        }
+        if let Some(store) = &self.config.store {
+            store.save(&peer, score);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use p2panda_core::{PrivateKey, PublicKey};
+    use p2panda_sync::SyncError;
+
+    use super::{
+        MAX_REPUTATION_SCORE, Misbehaviour, Reputation, ReputationConfig, ReputationStore,
+    };
+
+    #[derive(Clone, Debug, Default)]
+    struct MemoryStore(Arc<Mutex<Vec<(PublicKey, i32)>>>);
+
+    impl ReputationStore for MemoryStore {
+        fn load(&self) -> Vec<(PublicKey, i32)> {
+            self.0.lock().unwrap().clone()
+        }
+
+        fn save(&self, peer: &PublicKey, score: i32) {
+            let mut scores = self.0.lock().unwrap();
+            scores.retain(|(stored, _)| stored != peer);
+            scores.push((*peer, score));
+        }
+    }
+
+    #[test]
+    fn ban_misbehaving_peers() {
+        let peer = PrivateKey::new().public_key();
+        let reputation = Reputation::default();
+        assert_eq!(reputation.score(&peer), 0);
+        assert!(!reputation.is_deprioritised(&peer));
+
+        assert_eq!(reputation.report(peer, Misbehaviour::Spam), -10);
+        assert!(reputation.is_deprioritised(&peer));
+        assert!(!reputation.is_banned(&peer));
+
+        // Successful interactions recover the score.
+        reputation.reward(peer);
+        reputation.reward(peer);
+        assert_eq!(reputation.score(&peer), 0);
+        assert!(!reputation.is_deprioritised(&peer));
+
+        reputation.report(peer, Misbehaviour::InvalidSignature);
+        reputation.report(peer, Misbehaviour::InvalidSignature);
+        assert!(reputation.is_banned(&peer));
+
+        reputation.forgive(peer);
+        assert!(!reputation.is_banned(&peer));
+
+        for _ in 0..100 {
+            reputation.reward(peer);
+        }
+        assert_eq!(reputation.score(&peer), MAX_REPUTATION_SCORE);
+    }
+
+    #[test]
+    fn persist_scores() {
+        let peer = PrivateKey::new().public_key();
+        let store = MemoryStore::default();
+
+        let reputation = Reputation::new(
+            ReputationConfig::new()
+                .ban_threshold(-20)
+                .store(store.clone()),
+        );
+        reputation.report(peer, Misbehaviour::ProtocolViolation);
+        assert!(reputation.is_banned(&peer));
+
+        // Scores are loaded again after a restart.
+        let reputation = Reputation::new(ReputationConfig::new().store(store));
+        assert_eq!(reputation.score(&peer), -20);
+    }
+
+    #[test]
+    fn misbehaviour_of_sync_errors() {
+        assert_eq!(
+            Misbehaviour::from_sync_error(&SyncError::Validation("invalid".into())),
+            Some(Misbehaviour::InvalidData)
+        );
+        assert_eq!(
+            Misbehaviour::from_sync_error(&SyncError::UnexpectedBehaviour("bang".into())),
+            Some(Misbehaviour::ProtocolViolation)
+        );
+        assert_eq!(
+            Misbehaviour::from_sync_error(&SyncError::Critical("local".into())),
+            None
+        );
+        assert_eq!(
+            Misbehaviour::from_sync_error(&SyncError::Disconnected("broken pipe".into())),
+            None
+        );
+    }
+}
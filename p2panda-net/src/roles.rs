@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Roles a node can advertise for the topics it is interested in.
+//!
+//! Networks are often heterogeneous: mobile clients with limited storage and connectivity share
+//! topics with always-on, well-provisioned archival peers. Nodes announce their roles per topic
+//! during "topic discovery" which allows other peers to prefer the most appropriate ones, for
+//! example by syncing with archival peers first.
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+/// Role a node takes on for a topic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum NodeRole {
+    /// Node keeps the full history of the topic and can serve it to others.
+    Archive,
+
+    /// Node only participates in "live mode" and keeps little to no history.
+    LiveOnly,
+
+    /// Node provides blobs for this topic.
+    BlobProvider,
+
+    /// Node volunteers to relay traffic for other peers in this topic.
+    RelayVolunteer,
+}
+
+/// Set of roles a node advertises for a single topic.
+pub type NodeRoles = BTreeSet<NodeRole>;
+
+/// Local role configuration, consisting of roles applying to all topics and roles for specific
+/// topic ids.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RolesConfig {
+    default: NodeRoles,
+    topics: HashMap<[u8; 32], NodeRoles>,
+}
+
+impl RolesConfig {
+    /// Adds a role which is advertised for all topics.
+    pub fn add(&mut self, role: NodeRole) {
+        self.default.insert(role);
+    }
+
+    /// Adds a role which is only advertised for the given topic id.
+    pub fn add_for_topic(&mut self, topic_id: [u8; 32], role: NodeRole) {
+        self.topics.entry(topic_id).or_default().insert(role);
+    }
+
+    /// Returns all roles we advertise for the given topic id.
+    pub fn for_topic(&self, topic_id: &[u8; 32]) -> NodeRoles {
+        let mut roles = self.default.clone();
+        if let Some(topic_roles) = self.topics.get(topic_id) {
+            roles.extend(topic_roles.iter().copied());
+        }
+        roles
+    }
+}
+
+/// Returns true if a peer with the given roles should be deprioritised when scheduling a sync
+/// session with them.
+///
+/// Peers which only participate in "live mode" are unlikely to hold the history we're after,
+/// unless they also advertise themselves as an archive.
+pub(crate) fn is_deprioritised_for_sync(roles: &NodeRoles) -> bool {
+    roles.contains(&NodeRole::LiveOnly) && !roles.contains(&NodeRole::Archive)
+}
+
+/// Returns the rank of a peer with the given roles when picking blob providers, peers with a
+/// lower rank are asked first.
+///
+/// Dedicated blob providers come first, followed by archives and peers without any roles.
+/// Peers which only participate in "live mode" are asked last.
+pub(crate) fn blob_provider_rank(roles: &NodeRoles) -> u8 {
+    if roles.contains(&NodeRole::BlobProvider) {
+        0
+    } else if roles.contains(&NodeRole::Archive) {
+        1
+    } else if roles.contains(&NodeRole::LiveOnly) {
+        3
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NodeRole, NodeRoles, RolesConfig, blob_provider_rank, is_deprioritised_for_sync};
+
+    #[test]
+    fn roles_for_topic() {
+        let mut config = RolesConfig::default();
+        config.add(NodeRole::RelayVolunteer);
+        config.add_for_topic([1; 32], NodeRole::Archive);
+
+        assert_eq!(
+            config.for_topic(&[1; 32]),
+            NodeRoles::from([NodeRole::Archive, NodeRole::RelayVolunteer])
+        );
+        assert_eq!(
+            config.for_topic(&[2; 32]),
+            NodeRoles::from([NodeRole::RelayVolunteer])
+        );
+    }
+
+    #[test]
+    fn deprioritise_live_only() {
+        assert!(!is_deprioritised_for_sync(&NodeRoles::new()));
+        assert!(is_deprioritised_for_sync(&NodeRoles::from([
+            NodeRole::LiveOnly
+        ])));
+        assert!(!is_deprioritised_for_sync(&NodeRoles::from([
+            NodeRole::LiveOnly,
+            NodeRole::Archive
+        ])));
+    }
+
+    #[test]
+    fn rank_blob_providers() {
+        let provider = blob_provider_rank(&NodeRoles::from([NodeRole::BlobProvider]));
+        let archive = blob_provider_rank(&NodeRoles::from([NodeRole::Archive]));
+        let other = blob_provider_rank(&NodeRoles::new());
+        let live_only = blob_provider_rank(&NodeRoles::from([NodeRole::LiveOnly]));
+        assert!(provider < archive);
+        assert!(archive < other);
+        assert!(other < live_only);
+    }
+}
@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Supervision of internal subsystems according to the configured `PanicPolicy`.
+//!
+//! With `PanicPolicy::Isolate` a panic in one of the long-running actors of the node (engine,
+//! gossip and sync) or in the task updating our local addresses is caught, logged and the
+//! subsystem is run again after an exponential backoff. With `PanicPolicy::FailFast` the panic
+//! is passed on unchanged.
+//!
+//! Restarted actors keep their state and channels, so messages sent to them while they were
+//! restarting are handled after the restart.
+use std::future::Future;
+use std::panic::{AssertUnwindSafe, resume_unwind};
+use std::time::Duration;
+
+use futures_lite::FutureExt;
+use tracing::error;
+
+use crate::config::PanicPolicy;
+
+/// Initial delay before restarting a panicked subsystem, doubled on every further attempt.
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Maximum delay before restarting a panicked subsystem.
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Name of the subsystem updating discovery services with our local addresses.
+pub(crate) const ADDRESS_UPDATES_SUBSYSTEM: &str = "address-updates";
+
+/// Name of the engine actor subsystem.
+pub(crate) const ENGINE_SUBSYSTEM: &str = "engine";
+
+/// Name of the gossip actor subsystem.
+pub(crate) const GOSSIP_SUBSYSTEM: &str = "gossip";
+
+/// Name of the sync actor subsystem.
+pub(crate) const SYNC_SUBSYSTEM: &str = "sync";
+
+/// Returns the delay before restarting a panicked subsystem for the given attempt.
+pub(crate) fn restart_backoff(attempt: u32) -> Duration {
+    RESTART_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_RESTART_BACKOFF)
+}
+
+/// Runs a subsystem and catches its panics, depending on the panic policy.
+#[derive(Debug)]
+pub(crate) struct Supervisor {
+    subsystem: &'static str,
+    policy: PanicPolicy,
+    restarts: u32,
+}
+
+impl Supervisor {
+    pub(crate) fn new(subsystem: &'static str, policy: PanicPolicy) -> Self {
+        Self {
+            subsystem,
+            policy,
+            restarts: 0,
+        }
+    }
+
+    /// Name of the supervised subsystem.
+    pub(crate) fn subsystem(&self) -> &'static str {
+        self.subsystem
+    }
+
+    /// Number of times the subsystem was restarted so far.
+    pub(crate) fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    /// Runs the given future to completion and returns its output.
+    ///
+    /// Returns `None` if the future panicked and the panic was isolated. The restart backoff has
+    /// elapsed by then and the caller is expected to run the subsystem again.
+    pub(crate) async fn run<F: Future>(&mut self, future: F) -> Option<F::Output> {
+        match AssertUnwindSafe(future).catch_unwind().await {
+            Ok(output) => Some(output),
+            Err(payload) => {
+                if self.policy == PanicPolicy::FailFast {
+                    resume_unwind(payload);
+                }
+
+                self.restarts = self.restarts.saturating_add(1);
+                error!(
+                    subsystem = self.subsystem,
+                    attempt = self.restarts,
+                    "subsystem panicked, restarting"
+                );
+                tokio::time::sleep(restart_backoff(self.restarts)).await;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::config::PanicPolicy;
+
+    use super::{Supervisor, restart_backoff};
+
+    #[test]
+    fn restart_backoff_is_capped() {
+        assert_eq!(restart_backoff(1), Duration::from_secs(1));
+        assert_eq!(restart_backoff(2), Duration::from_secs(2));
+        assert_eq!(restart_backoff(4), Duration::from_secs(8));
+        assert_eq!(restart_backoff(7), Duration::from_secs(60));
+        assert_eq!(restart_backoff(u32::MAX), Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn isolate_panics() {
+        let mut supervisor = Supervisor::new("test", PanicPolicy::Isolate);
+        let mut runs = 0;
+
+        let output = loop {
+            let run = async {
+                runs += 1;
+                if runs == 1 {
+                    panic!("first run panics");
+                }
+                runs
+            };
+            if let Some(output) = supervisor.run(run).await {
+                break output;
+            }
+        };
+
+        assert_eq!(output, 2);
+        assert_eq!(supervisor.restarts(), 1);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "fail fast")]
+    async fn fail_fast_on_panics() {
+        let mut supervisor = Supervisor::new("test", PanicPolicy::FailFast);
+        supervisor.run(async { panic!("fail fast") }).await;
+    }
+}
@@ -3,15 +3,18 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use futures_util::{AsyncRead, AsyncWrite, SinkExt};
+use futures_util::{AsyncRead, AsyncWrite, SinkExt, future};
 use p2panda_core::PublicKey;
 use p2panda_sync::{FromSync, SyncError, SyncProtocol, TopicQuery};
 use tokio::sync::{mpsc, oneshot};
 use tokio::task::JoinHandle;
 use tokio_util::sync::PollSender;
-use tracing::{debug, error};
+use tracing::{Instrument, debug, error};
 
+use crate::TopicId;
 use crate::engine::ToEngineActor;
+use crate::sync::{TopicGrants, TranscriptRecorder};
+use crate::telemetry::HexId;
 
 /// Accept a sync protocol session over the provided bi-directional stream for the given peer and
 /// topic.
@@ -22,30 +25,43 @@ use crate::engine::ToEngineActor;
 ///
 /// 1. `SyncStart`: The sync session just began, we don't know the topic yet.
 /// 2. `SyncHandshakeSuccess`: We've successfully completed the I. "Handshake" phase, as we've
-///    received the topic from the initiator.
+///    received the topic from the initiator. Batch sessions send this message for every topic.
 /// 3. `SyncMessage` (optional): The actual data we've exchanged with the other peer, this message
 ///    can occur never or multiple times, depending on how much data was sent.
-/// 4. `SyncDone` We've successfully finished this session.
+/// 4. `SyncDone` We've successfully finished this session, sent for every topic.
 ///
 /// In case of a detected failure (either through an critical error on our end or an unexpected
 /// behaviour from the remote peer), the acceptor will send an `SyncFailed` message instead of the
 /// `SyncDone`.
 ///
+/// Handshakes over private topics the peer did not prove access to are rejected with an
+/// "unexpected behaviour" error before any data flows, see `authenticate_acceptor`.
+///
+/// All messages passed from the sync protocol to the engine are recorded in the transcript, if
+/// one is given.
+///
+/// Sessions with peers which are exempt from quotas are accepted with
+/// `SyncProtocol::accept_exempt`, lifting the limits of the sync protocol as well.
+///
 /// Errors can be roughly categorized by:
 ///
 /// 1. Critical system failures (bug in p2panda code or sync implementation, sync implementation
 ///    did not follow "2. Phase Flow" requirements, lack of system resources, etc.)
 /// 2. Unexpected Behaviour (remote peer abruptly disconnected, error which got correctly handled
 ///    in sync implementation, etc.)
+#[allow(clippy::too_many_arguments)]
 pub async fn accept_sync<T, S, R>(
     mut send: &mut S,
     mut recv: &mut R,
     peer: PublicKey,
+    grants: TopicGrants,
+    exempt: bool,
     sync_protocol: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
     engine_actor_tx: mpsc::Sender<ToEngineActor<T>>,
+    transcript: Option<TranscriptRecorder<T>>,
 ) -> Result<(), SyncError>
 where
-    T: TopicQuery + 'static,
+    T: TopicQuery + TopicId + 'static,
     S: AsyncWrite + Send + Unpin,
     R: AsyncRead + Send + Unpin,
 {
@@ -60,7 +76,22 @@ where
 
     // Set up a channel for receiving messages from the sync session.
     let (tx, mut rx) = mpsc::channel::<FromSync<T>>(128);
-    let mut sink = PollSender::new(tx).sink_map_err(|e| SyncError::Critical(e.to_string()));
+    let mut sink = PollSender::new(tx)
+        .sink_map_err(|e| SyncError::Critical(e.to_string()))
+        .with(move |message: FromSync<T>| {
+            // Reject private topics the peer is not allowed to sync before the sync protocol
+            // sends any data.
+            let result = match &message {
+                FromSync::HandshakeSuccess(topic) if !grants.allows(&topic.id()) => {
+                    Err(SyncError::UnexpectedBehaviour(format!(
+                        "remote peer did not prove access to private topic {}",
+                        HexId(&topic.id())
+                    )))
+                }
+                _ => Ok(message),
+            };
+            future::ready(result)
+        });
 
     // Set up a channel for sending over errors to the "glue" task which occurred during sync.
     let (sync_error_tx, mut sync_error_rx) = oneshot::channel::<SyncError>();
@@ -77,24 +108,32 @@ where
     //
     // Additionally, the task forwards any synced application data straight to the engine.
     let glue_task_handle: JoinHandle<Result<(), SyncError>> = tokio::spawn(async move {
-        let mut topic = None;
+        // Topics learned during the handshake phase, batch sessions can contain multiple.
+        let mut topics: Vec<T> = Vec::new();
 
         loop {
             tokio::select! {
                 biased;
 
                 Ok(err) = &mut sync_error_rx => {
-                    engine_actor_tx
-                        .send(ToEngineActor::SyncFailed {
-                            peer,
-                            topic: topic.clone(),
-                        })
-                        .await
-                        .map_err(|err| {
-                            SyncError::Critical(
-                                format!("engine_actor_tx failed sending sync failed: {err}")
-                            )
-                        })?;
+                    // Inform the engine about the failure of every topic we've learned about, or
+                    // about the failed session in general if we didn't learn any.
+                    let failed_topics = if topics.is_empty() {
+                        vec![None]
+                    } else {
+                        topics.iter().cloned().map(Some).collect()
+                    };
+
+                    for topic in failed_topics {
+                        engine_actor_tx
+                            .send(ToEngineActor::SyncFailed { peer, topic })
+                            .await
+                            .map_err(|err| {
+                                SyncError::Critical(
+                                    format!("engine_actor_tx failed sending sync failed: {err}")
+                                )
+                            })?;
+                    }
 
                     // If we're observing an error we terminate the task here and propagate that
                     // error further up.
@@ -110,10 +149,11 @@ where
                     //
                     // At the beginning of every sync session the "accepting" peer needs to learn
                     // the topic of the "initiating" peer during the handshake phase. This is
-                    // _always_ the first message we're expecting:
+                    // _always_ the first message we're expecting. Batch sessions announce multiple
+                    // topics, one handshake message each:
                     if let FromSync::HandshakeSuccess(handshake_topic) = message {
-                        // It should only be sent once so topic should be `None` now.
-                        if topic.is_some() {
+                        // Every topic should only be sent once.
+                        if topics.contains(&handshake_topic) {
                             return Err(
                                 SyncError::Critical(
                                     "received topic twice from sync session in handshake phase"
@@ -122,7 +162,12 @@ where
                             );
                         }
 
-                        topic = Some(handshake_topic.clone());
+                        debug!(topic = ?handshake_topic, "sync handshake success");
+                        topics.push(handshake_topic.clone());
+
+                        if let Some(transcript) = &transcript {
+                            transcript.handshake_success(&handshake_topic);
+                        }
 
                         // Inform the engine that we are expecting sync messages from the peer on
                         // this topic.
@@ -154,32 +199,51 @@ where
                     // Any sync protocol implementation should have already failed with an
                     // "unexpected behaviour" error if the topic wasn't learned. If this didn't
                     // happen (due to an incorrect implementation) we will critically fail now.
-                    let Some(topic) = &topic else {
-                        return Err(
-                            SyncError::Critical(
-                                "never received topic from sync session in handshake phase"
-                                .into()
-                            )
-                        );
+                    let (topic, header, payload) = match message {
+                        // Data of single-topic sessions belongs to the only topic we've learned.
+                        FromSync::Data { header, payload } => {
+                            let [topic] = topics.as_slice() else {
+                                return Err(
+                                    SyncError::Critical(
+                                        "expected exactly one topic from sync session for untagged data messages"
+                                        .into()
+                                    )
+                                );
+                            };
+                            (topic.clone(), header, payload)
+                        }
+                        // Data of batch sessions is tagged with one of the learned topics.
+                        FromSync::TopicData { topic, header, payload } => {
+                            if !topics.contains(&topic) {
+                                return Err(
+                                    SyncError::Critical(
+                                        "never received topic from sync session in handshake phase"
+                                        .into()
+                                    )
+                                );
+                            }
+                            (topic, header, payload)
+                        }
+                        _ => {
+                            return Err(
+                                SyncError::Critical(
+                                    "expected only data messages from sync session in data sync phase"
+                                    .into()
+                                )
+                            );
+                        }
                     };
 
-                    // From this point on we are only expecting "data" messages from the sync
-                    // session.
-                    let FromSync::Data { header, payload } = message else {
-                        return Err(
-                            SyncError::Critical(
-                                "expected only data messages from sync session in data sync phase"
-                                .into()
-                            )
-                        );
-                    };
+                    if let Some(transcript) = &transcript {
+                        transcript.data(&header, payload.as_ref());
+                    }
 
                     engine_actor_tx
                         .send(ToEngineActor::SyncMessage {
                             header,
                             payload,
                             delivered_from: peer,
-                            topic: topic.clone(),
+                            topic,
                         })
                         .await
                         .map_err(|err| {
@@ -191,30 +255,35 @@ where
             }
         }
 
-        // If topic was never set then we didn't receive any messages. In that case, the engine
-        // wasn't ever informed, so we can return here silently.
-        let Some(topic) = topic else {
-            return Ok(());
-        };
-
-        engine_actor_tx
-            .send(ToEngineActor::SyncDone { peer, topic })
-            .await
-            .map_err(|err| {
-                SyncError::Critical(format!("engine_actor_tx failed sending sync done: {err}"))
-            })?;
+        // If no topic was learned then we didn't receive any messages. In that case, the engine
+        // wasn't ever informed and there's nothing to finish.
+        for topic in topics {
+            engine_actor_tx
+                .send(ToEngineActor::SyncDone { peer, topic })
+                .await
+                .map_err(|err| {
+                    SyncError::Critical(format!("engine_actor_tx failed sending sync done: {err}"))
+                })?;
+        }
 
         Ok(())
-    });
+    }.in_current_span());
 
     // Run the "accepting peer" side of the sync protocol.
-    let result = sync_protocol
-        .accept(
+    let result = if exempt {
+        sync_protocol.accept_exempt(
             Box::new(&mut send),
             Box::new(&mut recv),
             Box::new(&mut sink),
         )
-        .await;
+    } else {
+        sync_protocol.accept(
+            Box::new(&mut send),
+            Box::new(&mut recv),
+            Box::new(&mut sink),
+        )
+    }
+    .await;
 
     // Drop the tx, so the rx in the glue task receives the closing event.
     drop(sink);
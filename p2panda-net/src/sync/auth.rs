@@ -0,0 +1,261 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use futures_util::{AsyncRead, AsyncWrite};
+use p2panda_core::PublicKey;
+use p2panda_sync::SyncError;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::sync::negotiation::{read_message, write_message};
+use crate::telemetry::HexId;
+use crate::topic_auth::TopicAuthenticator;
+
+/// Proofs for joining private topics, exchanged after negotiation and before a sync session
+/// begins.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Proofs(Vec<([u8; 32], Vec<u8>)>);
+
+impl Proofs {
+    /// Returns our own proofs for the given private topic ids we have a proof for.
+    fn for_topic_ids(authenticator: &dyn TopicAuthenticator, topic_ids: &[[u8; 32]]) -> Self {
+        Self(
+            topic_ids
+                .iter()
+                .filter_map(|topic_id| Some((*topic_id, authenticator.proof(topic_id)?)))
+                .collect(),
+        )
+    }
+
+    /// Returns all topic ids with a valid proof for the given peer.
+    fn verify(
+        &self,
+        authenticator: &dyn TopicAuthenticator,
+        peer: &PublicKey,
+    ) -> HashSet<[u8; 32]> {
+        self.0
+            .iter()
+            .filter(|(topic_id, proof)| authenticator.verify(topic_id, peer, proof))
+            .map(|(topic_id, _)| *topic_id)
+            .collect()
+    }
+}
+
+/// Topic ids a remote peer proved to be allowed to sync.
+#[derive(Clone, Debug, Default)]
+pub struct TopicGrants {
+    authenticator: Option<Arc<dyn TopicAuthenticator>>,
+    granted: HashSet<[u8; 32]>,
+}
+
+impl TopicGrants {
+    /// Returns `true` if the topic is public or the peer proved access to it.
+    pub fn allows(&self, topic_id: &[u8; 32]) -> bool {
+        match &self.authenticator {
+            Some(authenticator) => {
+                !authenticator.is_private(topic_id) || self.granted.contains(topic_id)
+            }
+            None => true,
+        }
+    }
+}
+
+/// Authenticate the peers of a sync session as the "initiator".
+///
+/// We present our proofs for all private topics of the session and verify the proofs the acceptor
+/// presents in return. Fails if we don't have a proof for one of the private topics ourselves or
+/// if the acceptor can't prove access to them.
+///
+/// The exchange takes place in every session, even if no topic is private.
+pub async fn authenticate_initiator<S, R>(
+    send: &mut S,
+    recv: &mut R,
+    authenticator: Option<&Arc<dyn TopicAuthenticator>>,
+    peer: PublicKey,
+    topic_ids: &[[u8; 32]],
+) -> Result<(), SyncError>
+where
+    S: AsyncWrite + Send + Unpin,
+    R: AsyncRead + Send + Unpin,
+{
+    let Some(authenticator) = authenticator else {
+        write_message(send, &Proofs::default()).await?;
+        let _: Proofs = read_message(recv).await?;
+        return Ok(());
+    };
+
+    let private_topic_ids: Vec<[u8; 32]> = topic_ids
+        .iter()
+        .filter(|topic_id| authenticator.is_private(topic_id))
+        .copied()
+        .collect();
+    let proofs = Proofs::for_topic_ids(authenticator.as_ref(), &private_topic_ids);
+    if proofs.0.len() < private_topic_ids.len() {
+        return Err(SyncError::Critical(
+            "missing own proof for private topic".into(),
+        ));
+    }
+    write_message(send, &proofs).await?;
+
+    let proofs: Proofs = read_message(recv).await?;
+    let granted = proofs.verify(authenticator.as_ref(), &peer);
+    if let Some(topic_id) = private_topic_ids
+        .iter()
+        .find(|topic_id| !granted.contains(*topic_id))
+    {
+        return Err(SyncError::UnexpectedBehaviour(format!(
+            "remote peer did not prove access to private topic {}",
+            HexId(topic_id)
+        )));
+    }
+
+    Ok(())
+}
+
+/// Authenticate the peers of a sync session as the "acceptor".
+///
+/// We verify the proofs presented by the initiator and present our own proofs for the same topics
+/// in return. Returns the topics the initiator is allowed to sync, handshakes over other private
+/// topics need to be rejected.
+pub async fn authenticate_acceptor<S, R>(
+    send: &mut S,
+    recv: &mut R,
+    authenticator: Option<&Arc<dyn TopicAuthenticator>>,
+    peer: PublicKey,
+) -> Result<TopicGrants, SyncError>
+where
+    S: AsyncWrite + Send + Unpin,
+    R: AsyncRead + Send + Unpin,
+{
+    let proofs: Proofs = read_message(recv).await?;
+
+    let Some(authenticator) = authenticator else {
+        write_message(send, &Proofs::default()).await?;
+        return Ok(TopicGrants::default());
+    };
+
+    let granted = proofs.verify(authenticator.as_ref(), &peer);
+    if granted.len() < proofs.0.len() {
+        debug!("initiator presented invalid proofs for private topics");
+    }
+
+    let topic_ids: Vec<[u8; 32]> = proofs.0.iter().map(|(topic_id, _)| *topic_id).collect();
+    write_message(
+        send,
+        &Proofs::for_topic_ids(authenticator.as_ref(), &topic_ids),
+    )
+    .await?;
+
+    Ok(TopicGrants {
+        authenticator: Some(authenticator.clone()),
+        granted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use p2panda_core::PrivateKey;
+    use p2panda_sync::SyncError;
+    use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+    use crate::topic_auth::{TopicAuthenticator, TopicOwners, sign_subscribe_proof};
+
+    use super::{TopicGrants, authenticate_acceptor, authenticate_initiator};
+
+    async fn authenticate(
+        initiator: (PrivateKey, Option<TopicOwners>),
+        acceptor: (PrivateKey, Option<TopicOwners>),
+        topic_ids: Vec<[u8; 32]>,
+    ) -> (Result<(), SyncError>, Result<TopicGrants, SyncError>) {
+        let (initiator_stream, acceptor_stream) = tokio::io::duplex(64 * 1024);
+        let (initiator_read, initiator_write) = tokio::io::split(initiator_stream);
+        let (acceptor_read, acceptor_write) = tokio::io::split(acceptor_stream);
+
+        let initiator_peer = initiator.0.public_key();
+        let acceptor_peer = acceptor.0.public_key();
+        let initiator_auth = initiator
+            .1
+            .map(|auth| Arc::new(auth) as Arc<dyn TopicAuthenticator>);
+        let acceptor_auth = acceptor
+            .1
+            .map(|auth| Arc::new(auth) as Arc<dyn TopicAuthenticator>);
+
+        let acceptor_handle = tokio::spawn(async move {
+            authenticate_acceptor(
+                &mut acceptor_write.compat_write(),
+                &mut acceptor_read.compat(),
+                acceptor_auth.as_ref(),
+                initiator_peer,
+            )
+            .await
+        });
+
+        let initiator_result = authenticate_initiator(
+            &mut initiator_write.compat_write(),
+            &mut initiator_read.compat(),
+            initiator_auth.as_ref(),
+            acceptor_peer,
+            &topic_ids,
+        )
+        .await;
+
+        (initiator_result, acceptor_handle.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn exchange_proofs() {
+        let private_topic_id = [1; 32];
+        let public_topic_id = [2; 32];
+        let owner = PrivateKey::new();
+        let initiator = PrivateKey::new();
+        let acceptor = PrivateKey::new();
+
+        let authenticator = |member: &PrivateKey| {
+            let proof = sign_subscribe_proof(&owner, &private_topic_id, &member.public_key());
+            TopicOwners::new()
+                .private_topic(private_topic_id, owner.public_key())
+                .own_proof(private_topic_id, proof)
+        };
+
+        // Both peers prove access to the private topic.
+        let (initiator_result, acceptor_result) = authenticate(
+            (initiator.clone(), Some(authenticator(&initiator))),
+            (acceptor.clone(), Some(authenticator(&acceptor))),
+            vec![private_topic_id, public_topic_id],
+        )
+        .await;
+        assert!(initiator_result.is_ok());
+        let grants = acceptor_result.unwrap();
+        assert!(grants.allows(&private_topic_id));
+        assert!(grants.allows(&public_topic_id));
+
+        // The acceptor can't prove access to the private topic.
+        let stranger = TopicOwners::new().private_topic(private_topic_id, owner.public_key());
+        let (initiator_result, _) = authenticate(
+            (initiator.clone(), Some(authenticator(&initiator))),
+            (acceptor.clone(), Some(stranger)),
+            vec![private_topic_id],
+        )
+        .await;
+        assert!(matches!(
+            initiator_result,
+            Err(SyncError::UnexpectedBehaviour(_))
+        ));
+
+        // The initiator doesn't have a proof, the acceptor doesn't grant it access either way.
+        let (initiator_result, acceptor_result) = authenticate(
+            (initiator.clone(), None),
+            (acceptor.clone(), Some(authenticator(&acceptor))),
+            vec![private_topic_id],
+        )
+        .await;
+        assert!(initiator_result.is_ok());
+        let grants = acceptor_result.unwrap();
+        assert!(!grants.allows(&private_topic_id));
+        assert!(grants.allows(&public_topic_id));
+    }
+}
@@ -1,18 +1,25 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use tokio::time::Duration;
 
 use p2panda_sync::{SyncProtocol, TopicQuery};
 
+use crate::compression::{CompressionAlgorithm, CompressionConfig, Compressor};
+use crate::faults::FaultInjector;
+use crate::relay_usage::RelayUsage;
+use crate::reputation::Reputation;
+use crate::sync::{LogHeightsProvider, QuotaExemptions, SYNC_CONNECTION_ALPN, TranscriptSink};
+use crate::topic_auth::TopicAuthenticator;
+
 const MAX_CONCURRENT_SYNC_SESSIONS: usize = 128;
 const MAX_RETRY_ATTEMPTS: u8 = 5;
 const RESYNC_INTERVAL: Duration = Duration::from_secs(60);
 const RESYNC_POLL_INTERVAL: Duration = Duration::from_secs(3);
 const RETRY_INTERVAL: Duration = Duration::from_secs(5);
 const RETRY_POLL_INTERVAL: Duration = Duration::from_secs(3);
-const SYNC_QUEUE_SEND_TIMEOUT: Duration = Duration::from_millis(100);
 pub(crate) const FALLBACK_RESYNC_INTERVAL_SEC: u64 = 3600;
 
 /// Configuration parameters for resync behaviour.
@@ -58,19 +65,90 @@ impl Default for ResyncConfiguration {
     }
 }
 
+/// Limits for sync sessions accepted from other peers.
+///
+/// All limits are disabled by default.
+#[derive(Clone, Debug, Default)]
+pub struct SyncQuotas {
+    /// Maximum number of concurrently accepted sync sessions (`None` represents no limit).
+    pub(crate) max_accepted_sessions: Option<usize>,
+
+    /// Maximum number of bytes sent to a single peer per day in accepted sync sessions (`None`
+    /// represents no limit).
+    pub(crate) daily_bytes_per_peer: Option<u64>,
+
+    /// Peers which are not subject to any quota (`None` represents no exemptions).
+    pub(crate) exemptions: Option<Arc<dyn QuotaExemptions>>,
+}
+
+impl SyncQuotas {
+    /// Return a default instance of `SyncQuotas`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Define the maximum number of concurrently accepted sync sessions.
+    ///
+    /// Further sessions are rejected until running ones have finished. This is independent of
+    /// `SyncConfiguration::max_concurrent_sync_sessions`, which limits the sessions we initiate.
+    pub fn max_accepted_sessions(mut self, sessions: usize) -> Self {
+        self.max_accepted_sessions = Some(sessions);
+        self
+    }
+
+    /// Define the maximum number of bytes sent to a single peer per day in accepted sync
+    /// sessions.
+    ///
+    /// Sessions exceeding the quota are aborted, further sessions of that peer are rejected until
+    /// the next day (UTC).
+    pub fn daily_bytes_per_peer(mut self, bytes: u64) -> Self {
+        self.daily_bytes_per_peer = Some(bytes);
+        self
+    }
+
+    /// Exempt peers from all quotas, for example trusted peers of the application.
+    ///
+    /// Sessions with exempt peers are also not limited by the sync protocol, for example by
+    /// `LogSyncProtocol::max_operations_per_session`.
+    pub fn exemptions(mut self, exemptions: impl QuotaExemptions + 'static) -> Self {
+        self.exemptions = Some(Arc::new(exemptions));
+        self
+    }
+}
+
 /// Configuration parameters for data synchronisation between peers.
 #[derive(Clone, Debug)]
 pub struct SyncConfiguration<T> {
-    protocol: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
+    /// Supported sync protocols, ordered by preference.
+    ///
+    /// The protocol used for a sync session is negotiated with the remote peer at the beginning
+    /// of every session.
+    protocols: Vec<Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>>,
 
     /// Resync configuration (`None` represents no resync).
     pub(crate) resync: Option<ResyncConfiguration>,
 
+    /// Source of local log heights announced alongside gossip messages (`None` represents no
+    /// delta announcements).
+    pub(crate) delta_announcements: Option<Arc<dyn LogHeightsProvider<T>>>,
+
     /// Maximum number of concurrent sync sessions.
     ///
     /// Default: 128.
     pub(crate) max_concurrent_sync_sessions: usize,
 
+    /// Maximum number of topics synced with the same peer in a single session.
+    ///
+    /// Default: 1.
+    pub(crate) max_batch_topics: usize,
+
+    /// Limits for sync sessions accepted from other peers.
+    pub(crate) quotas: SyncQuotas,
+
+    /// Maximum bandwidth of a single sync session in bytes per second, applied separately to
+    /// sent and received data (`None` represents no limit).
+    pub(crate) max_session_bandwidth: Option<u64>,
+
     /// Maximum number of attempts at successfully completing a sync session with a specific peer.
     ///
     /// Default: 5.
@@ -86,10 +164,35 @@ pub struct SyncConfiguration<T> {
     /// Default: 3 seconds.
     pub(crate) retry_poll_interval: Duration,
 
-    /// Maximum time to wait for sync attempt queue to have an open slot before failing.
+    /// Scheduling weights of topics, topics without a weight have a weight of 1.
     ///
-    /// Default: 100 milliseconds.
-    pub(crate) sync_queue_send_timeout: Duration,
+    /// Sync attempts are scheduled round-robin across topics, each topic can take as many
+    /// consecutive turns as its weight.
+    pub(crate) topic_priorities: HashMap<T, u32>,
+
+    /// Receiver of sync session transcripts (`None` represents no recording).
+    pub(crate) transcripts: Option<Arc<dyn TranscriptSink<T>>>,
+
+    /// Faults injected into sync sessions, set by `NetworkBuilder::fault_injector`.
+    pub(crate) faults: Option<FaultInjector>,
+
+    /// Compression of sync sessions, set by `NetworkBuilder::compression` (`None` represents no
+    /// compression).
+    pub(crate) compression: Option<CompressionConfig>,
+
+    /// Authentication of peers syncing private topics, set by `NetworkBuilder::topic_authenticator`
+    /// (`None` represents no private topics).
+    pub(crate) topic_auth: Option<Arc<dyn TopicAuthenticator>>,
+
+    /// Reputation of peers, set by `NetworkBuilder::reputation`.
+    pub(crate) reputation: Reputation,
+
+    /// Accounting and policy of sessions over relayed paths, set by `NetworkBuilder::build`
+    /// (`None` represents no accounting).
+    pub(crate) relay_usage: Option<RelayUsage>,
+
+    /// ALPN identifier of sync connections, namespaced by `NetworkBuilder::build`.
+    pub(crate) alpn: Vec<u8>,
 }
 
 impl<T> SyncConfiguration<T>
@@ -99,13 +202,24 @@ where
     /// Return a default instance of `SyncConfiguration`.
     pub fn new(protocol: impl for<'a> SyncProtocol<'a, T> + 'static) -> Self {
         Self {
-            protocol: Arc::new(protocol),
+            protocols: vec![Arc::new(protocol)],
+            delta_announcements: None,
+            max_batch_topics: 1,
             max_concurrent_sync_sessions: MAX_CONCURRENT_SYNC_SESSIONS,
+            max_session_bandwidth: None,
             max_retry_attempts: MAX_RETRY_ATTEMPTS,
+            quotas: SyncQuotas::default(),
             resync: None,
             retry_interval: RETRY_INTERVAL,
             retry_poll_interval: RETRY_POLL_INTERVAL,
-            sync_queue_send_timeout: SYNC_QUEUE_SEND_TIMEOUT,
+            topic_priorities: HashMap::new(),
+            transcripts: None,
+            faults: None,
+            compression: None,
+            topic_auth: None,
+            reputation: Reputation::default(),
+            relay_usage: None,
+            alpn: SYNC_CONNECTION_ALPN.to_vec(),
         }
     }
 
@@ -115,6 +229,25 @@ where
         self
     }
 
+    /// Sync up to the given number of topics with the same peer in a single session.
+    ///
+    /// Sync attempts queued for the same peer are batched together instead of opening a new
+    /// stream and running a handshake for every topic. Batching is only used if the negotiated
+    /// sync protocol supports it (see `SyncProtocol::supports_batch`) and for topics without a
+    /// filter. All peers need to understand batch sessions.
+    pub fn batch_topics(mut self, topics: usize) -> Self {
+        self.max_batch_topics = topics.max(1);
+        self
+    }
+
+    /// Define the maximum bandwidth of a single sync session in bytes per second.
+    ///
+    /// The limit applies separately to sent and received data.
+    pub fn max_session_bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.max_session_bandwidth = Some(bytes_per_second);
+        self
+    }
+
     /// Define the maximum number of attempts at successfully completing a sync session with a
     /// specific peer.
     pub fn max_retry_attempts(mut self, attempts: u8) -> Self {
@@ -122,9 +255,67 @@ where
         self
     }
 
-    /// Return the sync protocol from the given configuration.
+    /// Add a sync protocol which is used as a fallback when the remote peer does not support the
+    /// preferred ones.
+    ///
+    /// Protocols are identified by their name during negotiation. Adding a protocol with the same
+    /// name as an already registered one replaces it.
+    pub fn fallback_protocol(
+        mut self,
+        protocol: impl for<'a> SyncProtocol<'a, T> + 'static,
+    ) -> Self {
+        let protocol: Arc<dyn for<'a> SyncProtocol<'a, T>> = Arc::new(protocol);
+        match self
+            .protocols
+            .iter_mut()
+            .find(|registered| registered.name() == protocol.name())
+        {
+            Some(registered) => *registered = protocol,
+            None => self.protocols.push(protocol),
+        }
+        self
+    }
+
+    /// Return the preferred sync protocol from the given configuration.
     pub fn protocol(&self) -> Arc<dyn for<'a> SyncProtocol<'a, T>> {
-        self.protocol.clone()
+        self.protocols
+            .first()
+            .expect("at least one sync protocol is always given")
+            .clone()
+    }
+
+    /// Return all supported sync protocols, ordered by preference.
+    pub fn protocols(&self) -> Vec<Arc<dyn for<'a> SyncProtocol<'a, T>>> {
+        self.protocols.clone()
+    }
+
+    /// Returns `true` if any of the supported sync protocols delivers data newest-first.
+    ///
+    /// Gossip messages of a peer are usually held back while a sync session with that peer is
+    /// running and replayed afterwards, so they arrive after the older data received via sync.
+    /// With protocols sending the most recent data first (for example `LogSyncProtocol` with
+    /// `DeliveryOrder::NewestFirst`) gossip messages are delivered right away instead.
+    pub(crate) fn newest_first(&self) -> bool {
+        self.protocols
+            .iter()
+            .any(|protocol| protocol.newest_first())
+    }
+
+    /// Returns the compression algorithms offered during negotiation, ordered by preference.
+    pub(crate) fn compression_algorithms(&self) -> &[CompressionAlgorithm] {
+        self.compression
+            .as_ref()
+            .map(CompressionConfig::supported)
+            .unwrap_or_default()
+    }
+
+    /// Returns the compressor for a session with the negotiated compression algorithm, `None` if
+    /// the session is not compressed.
+    pub(crate) fn compressor(&self, algorithm: Option<CompressionAlgorithm>) -> Option<Compressor> {
+        let algorithm = algorithm?;
+        self.compression
+            .as_ref()
+            .map(|config| config.compressor(Some(algorithm)))
     }
 
     /// Provide the resync configuration for the sync scheduler.
@@ -153,8 +344,54 @@ where
 
     /// Define the maximum number of seconds to wait for sync attempt queue to have an open slot
     /// before failing.
-    pub fn sync_queue_send_timeout(mut self, seconds: u64) -> Self {
-        self.sync_queue_send_timeout = Duration::from_secs(seconds);
+    ///
+    /// Sync attempts are not queued on a channel anymore, scheduling fails right away once
+    /// `max_concurrent_sync_sessions` attempts are pending. The option has no effect.
+    #[deprecated(
+        since = "0.4.0",
+        note = "scheduling fails right away when the sync queue is full, use `max_concurrent_sync_sessions` to size the queue"
+    )]
+    pub fn sync_queue_send_timeout(self, _seconds: u64) -> Self {
+        self
+    }
+
+    /// Define the scheduling priority of a topic.
+    ///
+    /// Sync attempts are scheduled round-robin across topics, so a topic with many pending
+    /// attempts does not starve other topics. A topic with priority `n` takes up to `n`
+    /// consecutive turns, topics without a configured priority have a priority of 1.
+    pub fn topic_priority(mut self, topic: T, priority: u32) -> Self {
+        self.topic_priorities.insert(topic, priority);
+        self
+    }
+
+    /// Announce local log heights alongside every gossip message.
+    ///
+    /// Peers receiving a gossip message compare the announced heights with their own and schedule
+    /// a sync session with its author right away if they are missing operations, instead of
+    /// waiting for the next resync. Announcements are tagged and signed by their author, peers
+    /// without delta announcements still receive the messages but ignore the heights.
+    pub fn delta_announcements(mut self, provider: impl LogHeightsProvider<T> + 'static) -> Self {
+        self.delta_announcements = Some(Arc::new(provider));
+        self
+    }
+
+    /// Limit the sync sessions accepted from other peers.
+    ///
+    /// See `SyncQuotas` for the available limits. Use
+    /// `LogSyncProtocol::max_operations_per_session` to additionally limit the number of
+    /// operations served per session.
+    pub fn quotas(mut self, quotas: SyncQuotas) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    /// Record a transcript of every sync session and hand it over to the given sink.
+    ///
+    /// Transcripts contain the messages exchanged, number of bytes sent and received, timings and
+    /// the outcome of each session. This is useful for debugging sync protocols in production.
+    pub fn transcripts(mut self, sink: impl TranscriptSink<T> + 'static) -> Self {
+        self.transcripts = Some(Arc::new(sink));
         self
     }
 }
@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Delta announcements piggy-backed on gossip messages.
+//!
+//! When a `LogHeightsProvider` is configured via `SyncConfiguration::delta_announcements`, every
+//! gossip message broadcast over a topic carries the author's current log heights for that topic.
+//! Receivers compare them against their own heights and, if they are behind, schedule a sync
+//! session with the author right away instead of waiting for the next resync.
+//!
+//! Announcements are prefixed with a magic byte sequence, followed by the encoded author, heights,
+//! message and a signature of the author over the topic id, the heights and the hash of the
+//! message. Gossip messages are forwarded by other peers of the overlay, the signature makes sure
+//! that the heights are announced by the author we'll sync with. Messages without the prefix are
+//! delivered unchanged, peers without delta announcements only unwrap the message of
+//! announcements they receive. Messages which happen to start with the magic byte sequence
+//! themselves are sent as an announcement without heights to keep them distinguishable.
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use anyhow::{Result, bail};
+use p2panda_core::{Hash, PrivateKey, PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+
+use crate::bytes::{FromBytes, ToBytes};
+
+/// Magic byte sequence every delta announcement starts with.
+const DELTA_MAGIC: &[u8] = b"p2panda-delta";
+
+/// Number of operations per author which are stored locally for a topic.
+pub type LogHeights = Vec<(PublicKey, u64)>;
+
+/// Source of the local log heights announced alongside gossip messages.
+///
+/// Implementations are called for every sent and received gossip message and should not block,
+/// for example by serving heights from a cache which gets updated whenever operations are
+/// ingested.
+pub trait LogHeightsProvider<T>: Debug + Send + Sync {
+    /// Returns the number of operations per author stored locally for the given topic, summed up
+    /// across all logs of that author which are associated with the topic.
+    fn log_heights(&self, topic: &T) -> LogHeights;
+}
+
+/// Gossip message carrying the log heights of its author.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DeltaAnnouncement {
+    pub author: PublicKey,
+    pub heights: LogHeights,
+    pub bytes: Vec<u8>,
+    signature: Signature,
+}
+
+impl DeltaAnnouncement {
+    /// Returns the announcement of our log heights for the topic, signed with our private key.
+    pub fn new(
+        private_key: &PrivateKey,
+        topic_id: [u8; 32],
+        heights: LogHeights,
+        bytes: Vec<u8>,
+    ) -> Self {
+        let author = private_key.public_key();
+        let signature = private_key.sign(&signed_bytes(topic_id, &author, &heights, &bytes));
+        Self {
+            author,
+            heights,
+            bytes,
+            signature,
+        }
+    }
+
+    /// Returns the gossip message to broadcast, prefixed with the magic byte sequence.
+    pub fn encode(&self) -> Vec<u8> {
+        [DELTA_MAGIC, &self.to_bytes()].concat()
+    }
+
+    /// Parses and verifies an announcement, returns `None` if the bytes are a regular message.
+    pub fn decode(topic_id: [u8; 32], bytes: &[u8]) -> Option<Result<Self>> {
+        let bytes = bytes.strip_prefix(DELTA_MAGIC)?;
+        Some(Self::from_bytes(bytes).and_then(|announcement| {
+            if !announcement.author.verify(
+                &signed_bytes(
+                    topic_id,
+                    &announcement.author,
+                    &announcement.heights,
+                    &announcement.bytes,
+                ),
+                &announcement.signature,
+            ) {
+                bail!("invalid delta announcement signature");
+            }
+            Ok(announcement)
+        }))
+    }
+
+    /// Returns `true` if the message needs to be sent as an announcement to keep it
+    /// distinguishable from one.
+    pub fn is_ambiguous(bytes: &[u8]) -> bool {
+        bytes.starts_with(DELTA_MAGIC)
+    }
+}
+
+/// Bytes covered by the signature of an announcement.
+fn signed_bytes(
+    topic_id: [u8; 32],
+    author: &PublicKey,
+    heights: &LogHeights,
+    bytes: &[u8],
+) -> Vec<u8> {
+    (topic_id, author, heights, Hash::new(bytes)).to_bytes()
+}
+
+/// Returns `true` if the remote peer holds operations of any author which we do not have yet.
+pub(crate) fn is_behind(local: &[(PublicKey, u64)], remote: &[(PublicKey, u64)]) -> bool {
+    let local: HashMap<&PublicKey, &u64> = local.iter().map(|(k, v)| (k, v)).collect();
+    remote
+        .iter()
+        .any(|(public_key, remote_height)| match local.get(public_key) {
+            Some(local_height) => remote_height > local_height,
+            None => *remote_height > 0,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::PrivateKey;
+
+    use super::{DELTA_MAGIC, DeltaAnnouncement, is_behind};
+
+    #[test]
+    fn detect_missing_operations() {
+        let author_a = PrivateKey::new().public_key();
+        let author_b = PrivateKey::new().public_key();
+
+        let local = [(author_a, 5)];
+        assert!(!is_behind(&local, &[(author_a, 5)]));
+        assert!(!is_behind(&local, &[(author_a, 3)]));
+        assert!(!is_behind(&local, &[(author_b, 0)]));
+        assert!(is_behind(&local, &[(author_a, 6)]));
+        assert!(is_behind(&local, &[(author_a, 5), (author_b, 1)]));
+    }
+
+    #[test]
+    fn encode_decode() {
+        let private_key = PrivateKey::new();
+        let topic_id = [1; 32];
+        let announcement = DeltaAnnouncement::new(
+            &private_key,
+            topic_id,
+            vec![(PrivateKey::new().public_key(), 12)],
+            b"hello".to_vec(),
+        );
+        let bytes = announcement.encode();
+        assert_eq!(
+            DeltaAnnouncement::decode(topic_id, &bytes)
+                .unwrap()
+                .unwrap(),
+            announcement
+        );
+
+        // Regular messages are not announcements.
+        assert!(DeltaAnnouncement::decode(topic_id, b"hello").is_none());
+        assert!(!DeltaAnnouncement::is_ambiguous(b"hello"));
+        assert!(DeltaAnnouncement::is_ambiguous(
+            &[DELTA_MAGIC, b"hello"].concat()
+        ));
+    }
+
+    #[test]
+    fn reject_forged_announcements() {
+        let private_key = PrivateKey::new();
+        let topic_id = [1; 32];
+        let announcement = DeltaAnnouncement::new(
+            &private_key,
+            topic_id,
+            vec![(private_key.public_key(), 12)],
+            b"hello".to_vec(),
+        );
+
+        // Announcements are bound to their topic.
+        assert!(
+            DeltaAnnouncement::decode([2; 32], &announcement.encode())
+                .unwrap()
+                .is_err()
+        );
+
+        // Forwarding peers can't claim to be the author or change the heights.
+        let mut forged = announcement.clone();
+        forged.author = PrivateKey::new().public_key();
+        assert!(
+            DeltaAnnouncement::decode(topic_id, &forged.encode())
+                .unwrap()
+                .is_err()
+        );
+        let mut forged = announcement.clone();
+        forged.heights = vec![(private_key.public_key(), 13)];
+        assert!(
+            DeltaAnnouncement::decode(topic_id, &forged.encode())
+                .unwrap()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use anyhow::Result;
+use iroh::Endpoint;
+use p2panda_core::PublicKey;
+use p2panda_sync::{SyncEstimate, TopicQuery};
+use tracing::debug;
+
+use crate::sync::{self, SyncConfiguration};
+use crate::{TopicId, from_public_key};
+
+/// Connect to the given peer and estimate the data a sync session over the topic would transfer,
+/// without transferring it.
+///
+/// The sync protocol is negotiated and peers are authenticated like for regular sessions, the
+/// negotiated protocol needs to support estimates. No data is forwarded to the engine.
+pub(crate) async fn sync_diff<T>(
+    endpoint: &Endpoint,
+    config: &SyncConfiguration<T>,
+    peer: PublicKey,
+    topic: T,
+) -> Result<SyncEstimate>
+where
+    T: TopicQuery + TopicId + 'static,
+{
+    debug!("estimate sync with peer {} over topic {:?}", peer, topic);
+
+    let connection = endpoint
+        .connect(from_public_key(peer), &config.alpn)
+        .await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+
+    // Agree with the acceptor on the sync protocol, just like before a regular session. Estimates
+    // are small and never compressed.
+    let negotiated =
+        match sync::negotiate_initiator(&mut send, &mut recv, &config.protocols(), &[]).await {
+            Ok(negotiated) => negotiated,
+            Err(err) => {
+                send.finish().ok();
+                return Err(err.into());
+            }
+        };
+    sync::authenticate_initiator(
+        &mut send,
+        &mut recv,
+        config.topic_auth.as_ref(),
+        peer,
+        &[topic.id()],
+    )
+    .await?;
+    let estimate = negotiated
+        .protocol
+        .estimate(topic, Box::new(&mut send), Box::new(&mut recv))
+        .await?;
+
+    // Clean-up the streams.
+    send.finish()?;
+    send.stopped().await?;
+    recv.read_to_end(0).await?;
+
+    Ok(estimate)
+}
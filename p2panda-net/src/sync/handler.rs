@@ -2,58 +2,184 @@
 
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use futures_lite::future::Boxed as BoxedFuture;
+use futures_util::AsyncWriteExt;
 use iroh::endpoint::{Connecting, Connection};
-use p2panda_sync::{SyncProtocol, TopicQuery};
+use p2panda_core::PublicKey;
+use p2panda_sync::{SyncError, SyncProtocol, TopicQuery};
 use tokio::sync::mpsc;
-use tracing::{debug, debug_span};
+use tracing::{Instrument, Span, debug};
 
+use crate::compression::{Compressed, CompressionConfig};
 use crate::engine::ToEngineActor;
+use crate::faults::{Delayed, FaultInjector};
 use crate::protocols::ProtocolHandler;
-use crate::{sync, to_public_key};
-
-pub const SYNC_CONNECTION_ALPN: &[u8] = b"/p2panda-net-sync/0";
+use crate::relay_usage::{self, RelayUsage};
+use crate::reputation::{Misbehaviour, Reputation};
+use crate::sync::{
+    Counted, Metered, QuotaTracker, SyncConfiguration, SyncRole, Throttled, TranscriptRecorder,
+    TranscriptSink,
+};
+use crate::telemetry::sync_session_span;
+use crate::topic_auth::TopicAuthenticator;
+use crate::{TopicId, sync, to_public_key};
+
+pub const SYNC_CONNECTION_ALPN: &[u8] = b"/p2panda-net-sync/1";
 
 #[derive(Debug)]
 pub struct SyncConnection<T> {
-    sync_protocol: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
+    sync_protocols: Vec<Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>>,
+    transcripts: Option<Arc<dyn TranscriptSink<T>>>,
+    max_session_bandwidth: Option<u64>,
+    quotas: QuotaTracker,
+    faults: Option<FaultInjector>,
+    compression: Option<CompressionConfig>,
+    topic_auth: Option<Arc<dyn TopicAuthenticator>>,
+    reputation: Reputation,
+    relay_usage: Option<RelayUsage>,
     engine_actor_tx: mpsc::Sender<ToEngineActor<T>>,
 }
 
 impl<T> SyncConnection<T>
 where
-    T: TopicQuery + 'static,
+    T: TopicQuery + TopicId + 'static,
 {
     pub fn new(
-        sync_protocol: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
+        sync_config: &SyncConfiguration<T>,
         engine_actor_tx: mpsc::Sender<ToEngineActor<T>>,
     ) -> Self {
         Self {
-            sync_protocol,
+            sync_protocols: sync_config.protocols(),
+            transcripts: sync_config.transcripts.clone(),
+            max_session_bandwidth: sync_config.max_session_bandwidth,
+            quotas: QuotaTracker::new(sync_config.quotas.clone()),
+            faults: sync_config.faults.clone(),
+            compression: sync_config.compression.clone(),
+            topic_auth: sync_config.topic_auth.clone(),
+            reputation: sync_config.reputation.clone(),
+            relay_usage: sync_config.relay_usage.clone(),
             engine_actor_tx,
         }
     }
 
     /// Handle an inbound connection using the `SYNC_CONNECTION_ALPN` and accept a sync session.
-    async fn handle_connection(&self, connection: Connection) -> Result<()> {
-        let peer = to_public_key(connection.remote_node_id()?);
-        let connection_id = connection.stable_id() as u64;
+    ///
+    /// Runs inside the `sync_session` span of the connection.
+    async fn handle_connection(&self, connection: Connection, peer: PublicKey) -> Result<()> {
+        debug!("handling inbound sync connection...");
+
+        if self.reputation.is_banned(&peer) {
+            bail!("rejected inbound sync connection from banned peer");
+        }
 
-        let _span = debug_span!("connection", connection_id);
-        debug!(parent: &_span, "handling inbound sync connection...");
+        // Reject sessions exceeding our quotas before accepting any streams. The permit is held
+        // until the session has finished.
+        let permit = self.quotas.admit(&peer).inspect_err(|err| {
+            debug!("rejected inbound sync connection: {err}");
+        })?;
+        let relay_session =
+            relay_usage::admit_session(self.relay_usage.as_ref(), peer, self.max_session_bandwidth)
+                .inspect_err(|err| {
+                    debug!("rejected inbound sync connection: {err}");
+                })?;
+
+        if let Some(faults) = &self.faults {
+            faults.register_connection(peer, &connection)?;
+        }
 
         let (mut send, mut recv) = connection.accept_bi().await?;
 
-        let sync_protocol = self.sync_protocol.clone();
+        // Agree with the initiator on the sync protocol and compression for this session before
+        // it begins.
+        let compression = self
+            .compression
+            .as_ref()
+            .map(CompressionConfig::supported)
+            .unwrap_or_default();
+        let negotiated =
+            match sync::negotiate_acceptor(&mut send, &mut recv, &self.sync_protocols, compression)
+                .await
+            {
+                Ok(negotiated) => negotiated,
+                Err(err) => {
+                    // Finish the stream, the initiator would otherwise wait for the session to
+                    // begin until it times out.
+                    send.finish().ok();
+                    return Err(err.into());
+                }
+            };
+        let sync_protocol = negotiated.protocol;
+        let compressor = negotiated
+            .compression
+            .zip(self.compression.as_ref())
+            .map(|(algorithm, config)| config.compressor(Some(algorithm)));
+        Span::current().record("protocol", sync_protocol.name());
+
+        // Verify which private topics the initiator is allowed to sync and prove our own access.
+        let grants =
+            sync::authenticate_acceptor(&mut send, &mut recv, self.topic_auth.as_ref(), peer)
+                .await?;
         let engine_actor_tx = self.engine_actor_tx.clone();
+        let transcript = self.transcripts.clone().map(|sink| {
+            TranscriptRecorder::new(sink, peer, SyncRole::Acceptor, sync_protocol.name())
+        });
 
         // Run a sync session as the "acceptor" (aka. "responder").
         //
         // Sync failure or successful completion is reported to the engine actor internally, so
         // there's no need for us to do that in the context of handling the connection.
-        let result =
-            sync::accept_sync(&mut send, &mut recv, peer, sync_protocol, engine_actor_tx).await;
+        let delay = self
+            .faults
+            .as_ref()
+            .and_then(FaultInjector::sync_frame_delay);
+        let result = {
+            let mut send = Delayed::new(&mut send, delay);
+            let mut send = Metered::new(&mut send, &permit);
+            let mut send = Throttled::new(&mut send, relay_session.bandwidth);
+            let mut send = Counted::new(&mut send, relay_session.bytes.clone());
+            let mut send = Counted::new(
+                &mut send,
+                transcript.as_ref().map(TranscriptRecorder::bytes_sent),
+            );
+            let mut send = Compressed::new(&mut send, compressor.clone());
+            let mut recv = Delayed::new(&mut recv, delay);
+            let mut recv = Throttled::new(&mut recv, relay_session.bandwidth);
+            let mut recv = Counted::new(&mut recv, relay_session.bytes);
+            let mut recv = Counted::new(
+                &mut recv,
+                transcript.as_ref().map(TranscriptRecorder::bytes_received),
+            );
+            let mut recv = Compressed::new(&mut recv, compressor);
+
+            let result = sync::accept_sync(
+                &mut send,
+                &mut recv,
+                peer,
+                grants,
+                permit.is_exempt(),
+                sync_protocol,
+                engine_actor_tx,
+                transcript.clone(),
+            )
+            .await;
+
+            // Send the last buffered frame of a compressed session.
+            match result {
+                Ok(()) => send.flush().await.map_err(SyncError::from),
+                Err(err) => Err(err),
+            }
+        };
+
+        if let Some(transcript) = transcript {
+            transcript.finish(&result);
+        }
+
+        if let Err(err) = &result
+            && let Some(misbehaviour) = Misbehaviour::from_sync_error(err)
+        {
+            self.reputation.report(peer, misbehaviour);
+        }
 
         send.finish()?;
         send.stopped().await?;
@@ -63,7 +189,7 @@ where
         recv.read_to_end(0).await?;
 
         if result.is_ok() {
-            debug!(parent: &_span, "sync success as acceptor")
+            debug!("sync success as acceptor")
         }
 
         Ok(())
@@ -72,9 +198,16 @@ where
 
 impl<T> ProtocolHandler for SyncConnection<T>
 where
-    T: TopicQuery + 'static,
+    T: TopicQuery + TopicId + 'static,
 {
     fn accept(self: Arc<Self>, connecting: Connecting) -> BoxedFuture<Result<()>> {
-        Box::pin(async move { self.handle_connection(connecting.await?).await })
+        Box::pin(async move {
+            let connection = connecting.await?;
+            let peer = to_public_key(connection.remote_node_id()?);
+            let span = sync_session_span(&connection, peer, SyncRole::Acceptor);
+            self.handle_connection(connection, peer)
+                .instrument(span)
+                .await
+        })
     }
 }
@@ -5,17 +5,24 @@ use std::sync::Arc;
 use anyhow::Result;
 use futures_util::{AsyncRead, AsyncWrite, SinkExt};
 use p2panda_core::PublicKey;
-use p2panda_sync::{FromSync, SyncError, SyncProtocol, TopicQuery};
+use p2panda_sync::{FromSync, SyncError, SyncFilter, SyncProtocol, TopicQuery};
 use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 use tokio_util::sync::PollSender;
-use tracing::{debug, error, warn};
+use tracing::{Instrument, debug, error, warn};
 
 use crate::engine::ToEngineActor;
+use crate::sync::TranscriptRecorder;
 
 /// Initiate a sync protocol session over the provided bi-directional stream for the given peer and
 /// topic.
 ///
+/// The given filter is sent to the remote peer to narrow down the data we're interested in. An
+/// empty filter requests all data of that topic.
+///
+/// All messages passed from the sync protocol to the engine are recorded in the transcript, if
+/// one is given.
+///
 /// While this method "drives" the sync protocol implementation it also follows the "2-Phase
 /// Protocol Flow" required for the engine to work efficiently. We're expecting the following
 /// messages from this "initiator" flow:
@@ -38,13 +45,82 @@ use crate::engine::ToEngineActor;
 ///    did not follow "2. Phase Flow" requirements, lack of system resources, etc.)
 /// 2. Unexpected Behaviour (remote peer abruptly disconnected, error which got correctly handled
 ///    in sync implementation, etc.)
+#[allow(clippy::too_many_arguments)]
 pub async fn initiate_sync<T, S, R>(
+    send: &mut S,
+    recv: &mut R,
+    peer: PublicKey,
+    topic: T,
+    filter: SyncFilter,
+    sync_protocol: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
+    engine_actor_tx: mpsc::Sender<ToEngineActor<T>>,
+    transcript: Option<TranscriptRecorder<T>>,
+) -> Result<(), SyncError>
+where
+    T: TopicQuery + 'static,
+    S: AsyncWrite + Send + Unpin,
+    R: AsyncRead + Send + Unpin,
+{
+    run_initiator(
+        send,
+        recv,
+        peer,
+        vec![topic],
+        filter,
+        sync_protocol,
+        engine_actor_tx,
+        transcript,
+    )
+    .await
+}
+
+/// Initiate a batch sync protocol session over the provided bi-directional stream for the given
+/// peer and multiple topics.
+///
+/// All topics are negotiated in a single session instead of opening a new stream and running a
+/// handshake for each of them. The sync protocol needs to support batch sessions (see
+/// `SyncProtocol::supports_batch`), a batch of a single topic runs a regular sync session.
+///
+/// The engine is informed about every topic separately, following the same "2-Phase Protocol
+/// Flow" as `initiate_sync`: `SyncStart`, `SyncHandshakeSuccess` and `SyncDone` are sent for each
+/// topic and every `SyncMessage` is tagged with the topic it was synced for.
+pub async fn initiate_batch_sync<T, S, R>(
+    send: &mut S,
+    recv: &mut R,
+    peer: PublicKey,
+    topics: Vec<T>,
+    sync_protocol: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
+    engine_actor_tx: mpsc::Sender<ToEngineActor<T>>,
+    transcript: Option<TranscriptRecorder<T>>,
+) -> Result<(), SyncError>
+where
+    T: TopicQuery + 'static,
+    S: AsyncWrite + Send + Unpin,
+    R: AsyncRead + Send + Unpin,
+{
+    run_initiator(
+        send,
+        recv,
+        peer,
+        topics,
+        SyncFilter::default(),
+        sync_protocol,
+        engine_actor_tx,
+        transcript,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_initiator<T, S, R>(
     mut send: &mut S,
     mut recv: &mut R,
     peer: PublicKey,
-    topic: T,
+    topics: Vec<T>,
+    filter: SyncFilter,
     sync_protocol: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
     engine_actor_tx: mpsc::Sender<ToEngineActor<T>>,
+    transcript: Option<TranscriptRecorder<T>>,
 ) -> Result<(), SyncError>
 where
     T: TopicQuery + 'static,
@@ -52,19 +128,21 @@ where
     R: AsyncRead + Send + Unpin,
 {
     debug!(
-        "initiate sync session with peer {} over topic {:?}",
-        peer, topic
+        "initiate sync session with peer {} over topics {:?}",
+        peer, topics
     );
 
-    engine_actor_tx
-        .send(ToEngineActor::SyncStart {
-            topic: Some(topic.clone()),
-            peer,
-        })
-        .await
-        .map_err(|err| {
-            SyncError::Critical(format!("engine_actor_tx failed sending sync start: {err}"))
-        })?;
+    for topic in &topics {
+        engine_actor_tx
+            .send(ToEngineActor::SyncStart {
+                topic: Some(topic.clone()),
+                peer,
+            })
+            .await
+            .map_err(|err| {
+                SyncError::Critical(format!("engine_actor_tx failed sending sync start: {err}"))
+            })?;
+    }
 
     // Set up a channel for receiving messages from the sync session.
     let (tx, mut rx) = mpsc::channel::<FromSync<T>>(128);
@@ -83,8 +161,8 @@ where
     // Additionally, the task forwards any synced application data straight to the engine.
     let glue_task_handle: JoinHandle<Result<(), SyncError>> = {
         let engine_actor_tx = engine_actor_tx.clone();
-        let mut sync_handshake_success = false;
-        let topic = topic.clone();
+        let mut handshake_topics: Vec<T> = Vec::new();
+        let topics = topics.clone();
 
         tokio::spawn(async move {
             while let Some(message) = rx.recv().await {
@@ -92,24 +170,31 @@ where
                 //
                 // At the beginning of every sync session the "initiating" peer needs to send over
                 // the topic to the "accepting" peer during the handshake phase. This is the first
-                // message we're expecting:
-                if let FromSync::HandshakeSuccess(_) = message {
-                    // Receiving the handshake message twice is a protocol violation.
-                    if sync_handshake_success {
+                // message we're expecting, batch sessions send one for every topic:
+                if let FromSync::HandshakeSuccess(topic) = message {
+                    // Receiving the handshake message twice or for other topics is a protocol
+                    // violation.
+                    if handshake_topics.contains(&topic) {
                         return Err(SyncError::Critical(
                             "received handshake message twice from sync session in handshake phase"
                                 .into(),
                         ));
                     }
-                    sync_handshake_success = true;
+                    if !topics.contains(&topic) {
+                        return Err(SyncError::Critical(
+                            "received handshake message for unknown topic from sync session".into(),
+                        ));
+                    }
+                    handshake_topics.push(topic.clone());
+
+                    if let Some(transcript) = &transcript {
+                        transcript.handshake_success(&topic);
+                    }
 
                     // Inform the engine that we are expecting sync messages from the peer on this
                     // topic.
                     engine_actor_tx
-                        .send(ToEngineActor::SyncHandshakeSuccess {
-                            peer,
-                            topic: topic.clone(),
-                        })
+                        .send(ToEngineActor::SyncHandshakeSuccess { peer, topic })
                         .await
                         .map_err(|err| {
                             SyncError::Critical(format!(
@@ -122,16 +207,32 @@ where
 
                 // 2. Data Sync Phase.
                 // ~~~~~~~~~~~~~~~~~~~
-                let FromSync::Data { header, payload } = message else {
-                    return Err(SyncError::Critical("expected to receive only data messages from sync session in data sync phase".into()));
+                let (topic, header, payload) = match message {
+                    // Data of single-topic sessions belongs to the session's topic.
+                    FromSync::Data { header, payload } if topics.len() == 1 => {
+                        (topics[0].clone(), header, payload)
+                    }
+                    // Data of batch sessions is tagged with one of the session's topics.
+                    FromSync::TopicData {
+                        topic,
+                        header,
+                        payload,
+                    } if handshake_topics.contains(&topic) => (topic, header, payload),
+                    _ => {
+                        return Err(SyncError::Critical("expected to receive only data messages of session topics from sync session in data sync phase".into()));
+                    }
                 };
 
+                if let Some(transcript) = &transcript {
+                    transcript.data(&header, payload.as_ref());
+                }
+
                 engine_actor_tx
                     .send(ToEngineActor::SyncMessage {
                         header,
                         payload,
                         delivered_from: peer,
-                        topic: topic.clone(),
+                        topic,
                     })
                     .await
                     .map_err(|err| {
@@ -142,19 +243,33 @@ where
             }
 
             Ok(())
-        })
+        }.in_current_span())
     };
 
     // Run the "initiating peer" side of the sync protocol.
-    let result = sync_protocol
-        .initiate(
-            topic.clone(),
-            Box::new(&mut send),
-            Box::new(&mut recv),
-            Box::new(&mut sink),
-        )
-        .await;
-
+    let result = match <[T; 1]>::try_from(topics.clone()) {
+        Ok([topic]) => {
+            sync_protocol
+                .initiate_with_filter(
+                    topic,
+                    filter,
+                    Box::new(&mut send),
+                    Box::new(&mut recv),
+                    Box::new(&mut sink),
+                )
+                .await
+        }
+        Err(topics) => {
+            sync_protocol
+                .initiate_batch(
+                    topics,
+                    Box::new(&mut send),
+                    Box::new(&mut recv),
+                    Box::new(&mut sink),
+                )
+                .await
+        }
+    };
     // Drop the tx, so the rx in the glue task receives the closing event.
     drop(sink);
 
@@ -187,12 +302,14 @@ where
     // On a failure we're _not_ sending a `SyncDone` but `SyncFailed` event. This is handled by the
     // sync manager which drives this "initiator" session with additional re-attempt logic.
 
-    engine_actor_tx
-        .send(ToEngineActor::SyncDone { peer, topic })
-        .await
-        .map_err(|err| {
-            SyncError::Critical(format!("engine_actor_tx failed sending sync done: {err}"))
-        })?;
+    for topic in topics {
+        engine_actor_tx
+            .send(ToEngineActor::SyncDone { peer, topic })
+            .await
+            .map_err(|err| {
+                SyncError::Critical(format!("engine_actor_tx failed sending sync done: {err}"))
+            })?;
+    }
 
     Ok(())
 }
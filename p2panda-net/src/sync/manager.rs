@@ -3,33 +3,51 @@
 use std::collections::hash_map::Entry as HashMapEntry;
 use std::collections::{HashMap, VecDeque};
 
-use anyhow::{Context, Error, Result};
+use anyhow::{Context, Error, Result, anyhow};
 use iroh::Endpoint;
+use iroh::endpoint::Connection;
 use p2panda_core::PublicKey;
-use p2panda_sync::{SyncError, TopicQuery};
+use p2panda_sync::{SyncError, SyncFilter, TopicQuery};
 use thiserror::Error;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio::time::{Duration, Instant, interval};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, warn};
+use tracing::{Instrument, Span, debug, error, warn};
 
+use crate::compression::Compressed;
 use crate::engine::ToEngineActor;
-use crate::from_public_key;
+use crate::faults::{Delayed, FaultInjector};
+use crate::relay_usage::{self, RelayRefused};
+use crate::reputation::Misbehaviour;
+use crate::roles::{NodeRoles, is_deprioritised_for_sync};
 use crate::sync::config::FALLBACK_RESYNC_INTERVAL_SEC;
-use crate::sync::{self, SYNC_CONNECTION_ALPN, SyncConfiguration};
+use crate::sync::scheduler::FairQueue;
+use crate::sync::{self, Counted, SyncConfiguration, SyncRole, Throttled, TranscriptRecorder};
+use crate::telemetry::sync_session_span;
+use crate::{TopicId, from_public_key};
 
 /// Events sent to the sync manager.
 #[derive(Debug)]
 pub enum ToSyncActor<T> {
-    /// A new peer-topic combination was discovered.
-    Discovery { peer: PublicKey, topic: T },
+    /// A new peer-topic combination was discovered, including the roles the peer advertised for
+    /// that topic.
+    Discovery {
+        peer: PublicKey,
+        topic: T,
+        roles: NodeRoles,
+    },
+    /// A gossip message announced log heights showing that the peer holds operations we're
+    /// missing for that topic.
+    Behind { peer: PublicKey, topic: T },
+    /// Data requested during sync sessions over a topic should be narrowed down by a filter.
+    Filter { topic: T, filter: SyncFilter },
     /// A major network interface change was detected.
     Reset,
 }
 
 impl<T> ToSyncActor<T> {
-    pub(crate) fn new_discovery(peer: PublicKey, topic: T) -> Self {
-        Self::Discovery { peer, topic }
+    pub(crate) fn new_discovery(peer: PublicKey, topic: T, roles: NodeRoles) -> Self {
+        Self::Discovery { peer, topic, roles }
     }
 }
 
@@ -83,6 +101,11 @@ enum SyncAttemptError {
     #[error("sync attempt failed due to connection or stream error")]
     Connection,
 
+    /// The connection to the peer is relayed and the relay policy refuses sync sessions over
+    /// relayed paths.
+    #[error(transparent)]
+    Relayed(#[from] RelayRefused),
+
     /// Error occurred while initiating or accepting a sync session.
     #[error(transparent)]
     Sync(#[from] SyncError),
@@ -93,18 +116,19 @@ enum SyncAttemptError {
 pub(crate) struct SyncActor<T> {
     config: SyncConfiguration<T>,
     sessions: HashMap<Scope<T>, Attempt>,
+    deferred_queue: VecDeque<Scope<T>>,
     endpoint: Endpoint,
     engine_actor_tx: Sender<ToEngineActor<T>>,
+    filters: HashMap<T, SyncFilter>,
     inbox: Receiver<ToSyncActor<T>>,
     resync_queue: VecDeque<Scope<T>>,
     retry_queue: VecDeque<Scope<T>>,
-    sync_queue_tx: Sender<Scope<T>>,
-    sync_queue_rx: Receiver<Scope<T>>,
+    sync_queue: FairQueue<T, Scope<T>>,
 }
 
 impl<T> SyncActor<T>
 where
-    T: TopicQuery + 'static,
+    T: TopicQuery + TopicId + 'static,
 {
     /// Create a new instance of the `SyncActor` and return it along with a channel sender.
     pub(crate) fn new(
@@ -112,19 +136,20 @@ where
         endpoint: Endpoint,
         engine_actor_tx: Sender<ToEngineActor<T>>,
     ) -> (Self, Sender<ToSyncActor<T>>) {
-        let (sync_queue_tx, sync_queue_rx) = mpsc::channel(config.max_concurrent_sync_sessions);
+        let sync_queue = FairQueue::new(config.topic_priorities.clone());
         let (sync_manager_tx, sync_manager_rx) = mpsc::channel(256);
 
         let sync_manager = Self {
             config,
             sessions: HashMap::new(),
+            deferred_queue: VecDeque::new(),
             endpoint,
             engine_actor_tx,
+            filters: HashMap::new(),
             inbox: sync_manager_rx,
             resync_queue: VecDeque::new(),
             retry_queue: VecDeque::new(),
-            sync_queue_tx,
-            sync_queue_rx,
+            sync_queue,
         };
 
         (sync_manager, sync_manager_tx)
@@ -136,10 +161,12 @@ where
     ///
     /// - A shutdown signal from the engine
     /// - A new peer-topic combination received from the engine
-    /// - A sync attempt pulled from the queue, resulting in a call to `connect_and_sync()`
+    /// - A sync attempt pulled from the queue, resulting in a call to `connect_and_sync()`;
+    ///   attempts are pulled round-robin across topics
     /// - A tick of the resync poll interval, resulting in a resync attempt if one is in the queue
     /// - A tick of the retry poll interval, resulting in a retry attempt if one is in the queue
-    pub async fn run(mut self, token: CancellationToken) -> Result<()> {
+    ///   or otherwise a deferred attempt with a deprioritised peer
+    pub async fn run(&mut self, token: CancellationToken) -> Result<()> {
         // Define the resync intervals based on supplied configuration parameters if resync has
         // been enabled. Otherwise create long-duration fallback values; this is mostly just
         // necessary for the resync poll interval tick.
@@ -168,7 +195,11 @@ where
                     let msg = msg.context("sync manager inbox closed")?;
                     match msg {
                         // A peer-topic announcement has been received from the discovery layer.
-                        ToSyncActor::Discovery { peer, topic } => {
+                        ToSyncActor::Discovery { peer, topic, roles } => {
+                            if self.config.reputation.is_banned(&peer) {
+                                continue;
+                            }
+
                             let scope = Scope::new(peer, topic);
 
                             // Only schedule an attempt if we're not already tracking sessions for this
@@ -177,6 +208,17 @@ where
                                 let attempt = Attempt::new();
                                 entry.insert(attempt);
 
+                                // Peers which are unlikely to hold the data we're after or which
+                                // misbehaved in earlier sessions are deferred, giving more
+                                // appropriate peers (for example archives) the chance to be synced
+                                // with first.
+                                if is_deprioritised_for_sync(&roles)
+                                    || self.config.reputation.is_deprioritised(&peer)
+                                {
+                                    self.deferred_queue.push_back(scope);
+                                    continue;
+                                }
+
                                 if let Err(err) = self.schedule_attempt(scope).await {
                                     // The attempt will fail if the sync queue is full, indicating that a high
                                     // volume of sync sessions are underway. In that case, we drop the attempt
@@ -187,6 +229,45 @@ where
                                 }
                             }
                         },
+                        // A delta announcement showed that we're behind the peer. Sync right away
+                        // unless a session is already queued, running or waiting for a retry.
+                        ToSyncActor::Behind { peer, topic } => {
+                            if self.config.reputation.is_banned(&peer) {
+                                continue;
+                            }
+
+                            let scope = Scope::new(peer, topic);
+                            let attempt = self
+                                .sessions
+                                .entry(scope.clone())
+                                .or_insert_with(Attempt::new);
+                            match attempt.status {
+                                Status::Complete(_) => attempt.reset(),
+                                Status::Pending => (),
+                                Status::Active | Status::Failed(_) => continue,
+                            }
+
+                            if self.sync_queue.contains(&scope.topic, &scope) {
+                                continue;
+                            }
+
+                            // The attempt replaces the next resync of this scope and takes
+                            // precedence over deferring deprioritised peers.
+                            self.resync_queue.retain(|queued| queued != &scope);
+
+                            if let Err(err) = self.schedule_attempt(scope).await {
+                                error!("failed to schedule sync attempt: {}", err)
+                            }
+                        }
+                        // Remember the filter to be sent during all future sync sessions over this
+                        // topic, an empty filter clears it.
+                        ToSyncActor::Filter { topic, filter } => {
+                            if filter.is_empty() {
+                                self.filters.remove(&topic);
+                            } else {
+                                self.filters.insert(topic, filter);
+                            }
+                        }
                         // In the event of a disconnection, two peers who had previously synced may
                         // fall back out of sync. In order to invoke resync upon reconnection, we
                         // reset the status of all sessions and schedule an attempt for each one.
@@ -196,20 +277,27 @@ where
                                 attempt.reset();
                             }
 
-                            for scope in self.sessions.keys() {
-                                self.schedule_attempt(scope.clone()).await?;
+                            let scopes: Vec<Scope<T>> = self.sessions.keys().cloned().collect();
+                            for scope in scopes {
+                                if let Err(err) = self.schedule_attempt(scope).await {
+                                    error!("failed to schedule sync attempt: {}", err)
+                                }
                             }
                         }
                     }
                 }
-                Some(scope) = self.sync_queue_rx.recv() => {
-                    match self
-                       .connect_and_sync(scope.clone())
-                       .await
-                   {
-                       Ok(()) => self.complete_successful_sync(scope).await?,
-                       Err(err) => self.complete_failed_sync(scope, err).await?,
-                   }
+                _ = std::future::ready(()), if !self.sync_queue.is_empty() => {
+                    let scope = self.sync_queue.pop().expect("sync queue is not empty");
+
+                    // Other attempts with the same peer might get batched into this session.
+                    let mut scopes = vec![scope];
+                    let result = self.connect_and_sync(&mut scopes).await;
+                    for scope in scopes {
+                        match &result {
+                            Ok(()) => self.complete_successful_sync(scope).await?,
+                            Err(err) => self.complete_failed_sync(scope, err).await?,
+                        }
+                    }
                 },
                  _ = resync_poll_interval.tick() => {
                     if let Some(scope) = self.resync_queue.pop_front() {
@@ -227,19 +315,7 @@ where
                     }
                 }
                 _ = retry_poll_interval.tick() => {
-                    if let Some(scope) = self.retry_queue.pop_front() {
-                        if let Some(attempt) = self.sessions.get(&scope) {
-                            if let Status::Failed(failure) = attempt.status {
-                                if failure.elapsed() >= retry_interval {
-                                    if let Err(err) = self.schedule_attempt(scope).await {
-                                        error!("failed to schedule resync attempt: {}", err)
-                                    }
-                                } else {
-                                    self.retry_queue.push_back(scope)
-                                }
-                            }
-                        }
-                    }
+                    self.schedule_retry(retry_interval).await;
                 }
             }
         }
@@ -247,54 +323,228 @@ where
         Ok(())
     }
 
+    /// Schedule the next failed attempt once the retry interval has passed since its failure.
+    ///
+    /// Deferred attempts are only scheduled while no failed attempts are waiting for a retry.
+    async fn schedule_retry(&mut self, retry_interval: Duration) {
+        if self.retry_queue.is_empty()
+            && let Some(scope) = self.deferred_queue.pop_front()
+            && let Err(err) = self.schedule_attempt(scope).await
+        {
+            error!("failed to schedule deferred attempt: {}", err)
+        }
+
+        if let Some(scope) = self.retry_queue.pop_front()
+            && let Some(attempt) = self.sessions.get(&scope)
+            && let Status::Failed(failure) = attempt.status
+        {
+            if failure.elapsed() >= retry_interval {
+                if let Err(err) = self.schedule_attempt(scope).await {
+                    error!("failed to schedule resync attempt: {}", err)
+                }
+            } else {
+                self.retry_queue.push_back(scope)
+            }
+        }
+    }
+
     /// Schedule a sync attempt for the given scope (peer-topic combination).
-    async fn schedule_attempt(&self, scope: Scope<T>) -> Result<()> {
-        // Only send if the queue is not full; this prevents the possibility of blocking on send.
-        if self.sync_queue_tx.capacity() < self.sync_queue_tx.max_capacity() {
-            self.sync_queue_tx.send(scope).await?;
-        } else {
-            self.sync_queue_tx
-                .send_timeout(scope, self.config.sync_queue_send_timeout)
-                .await?;
+    ///
+    /// Fails if the maximum number of queued sync attempts has been reached. Attempts with banned
+    /// peers are dropped.
+    async fn schedule_attempt(&mut self, scope: Scope<T>) -> Result<()> {
+        if self.config.reputation.is_banned(&scope.peer) {
+            debug!("drop sync attempt with banned peer {}", scope.peer);
+            return Ok(());
         }
 
+        if self.sync_queue.len() >= self.config.max_concurrent_sync_sessions {
+            return Err(anyhow!("sync queue is full"));
+        }
+
+        // Deferred attempts which get scheduled otherwise, for example after a reset, are not
+        // scheduled a second time.
+        self.deferred_queue.retain(|queued| queued != &scope);
+        self.sync_queue.push(scope.topic.clone(), scope);
+
         Ok(())
     }
 
     /// Attempt to connect with the given peer and initiate a sync session.
-    async fn connect_and_sync(&mut self, scope: Scope<T>) -> Result<()> {
-        if let Some(attempt) = self.sessions.get_mut(&scope) {
-            attempt.status = Status::Active
+    ///
+    /// If batching is enabled and supported by the negotiated sync protocol, further queued
+    /// attempts with the same peer are added to the given scopes and synced in the same session.
+    async fn connect_and_sync(&mut self, scopes: &mut Vec<Scope<T>>) -> Result<()> {
+        for scope in scopes.iter() {
+            if let Some(attempt) = self.sessions.get_mut(scope) {
+                attempt.status = Status::Active
+            }
         }
 
-        let peer = scope.peer;
-        let topic = scope.topic;
+        let peer = scopes[0].peer;
 
         let connection = self
             .endpoint
-            .connect(from_public_key(peer), SYNC_CONNECTION_ALPN)
+            .connect(from_public_key(peer), &self.config.alpn)
             .await
             .map_err(|_| SyncAttemptError::Connection)?;
 
+        if let Some(faults) = &self.config.faults {
+            faults
+                .register_connection(peer, &connection)
+                .map_err(|_| SyncAttemptError::Connection)?;
+        }
+
+        let span = sync_session_span(&connection, peer, SyncRole::Initiator);
+        self.run_session(connection, scopes).instrument(span).await
+    }
+
+    /// Run a sync session as the initiator over the established connection.
+    ///
+    /// Runs inside the `sync_session` span of the connection.
+    async fn run_session(
+        &mut self,
+        connection: Connection,
+        scopes: &mut Vec<Scope<T>>,
+    ) -> Result<()> {
+        let peer = scopes[0].peer;
+        let topic = scopes[0].topic.clone();
+
+        // The path of the connection is known by now, the relay policy might refuse the session
+        // or cap its bandwidth.
+        let relay_session = relay_usage::admit_session(
+            self.config.relay_usage.as_ref(),
+            peer,
+            self.config.max_session_bandwidth,
+        )
+        .map_err(SyncAttemptError::from)?;
+
         let (mut send, mut recv) = connection
             .open_bi()
             .await
             .map_err(|_| SyncAttemptError::Connection)?;
 
-        let sync_protocol = self.config.protocol();
+        // Agree with the acceptor on the sync protocol and compression for this session before it
+        // begins.
+        let negotiated = match sync::negotiate_initiator(
+            &mut send,
+            &mut recv,
+            &self.config.protocols(),
+            self.config.compression_algorithms(),
+        )
+        .await
+        {
+            Ok(negotiated) => negotiated,
+            Err(err) => {
+                // Finish the stream, the acceptor would otherwise wait for the session to begin
+                // until it times out.
+                send.finish().ok();
+                return Err(err.into());
+            }
+        };
+        let sync_protocol = negotiated.protocol;
+        let compressor = self.config.compressor(negotiated.compression);
+        Span::current().record("protocol", sync_protocol.name());
         let engine_actor_tx = self.engine_actor_tx.clone();
+        let filter = self.filters.get(&topic).cloned().unwrap_or_default();
+        let transcript = self.config.transcripts.clone().map(|sink| {
+            TranscriptRecorder::new(sink, peer, SyncRole::Initiator, sync_protocol.name())
+        });
 
-        // Run a sync session as the initiator.
-        sync::initiate_sync(
+        // Batch queued attempts with the same peer into this session. Filters are sent per
+        // session, so only topics without a filter can be batched.
+        if self.config.max_batch_topics > 1 && sync_protocol.supports_batch() && filter.is_empty() {
+            let filters = &self.filters;
+            let batched =
+                self.sync_queue
+                    .take_matching(self.config.max_batch_topics - 1, |scope| {
+                        scope.peer == peer
+                            && filters
+                                .get(&scope.topic)
+                                .is_none_or(|filter| filter.is_empty())
+                    });
+            for scope in batched {
+                if let Some(attempt) = self.sessions.get_mut(&scope) {
+                    attempt.status = Status::Active
+                }
+                scopes.push(scope);
+            }
+        }
+
+        // Prove our access to the private topics of the session and make sure the acceptor is
+        // allowed to sync them as well.
+        let topic_ids: Vec<[u8; 32]> = scopes.iter().map(|scope| scope.topic.id()).collect();
+        sync::authenticate_initiator(
             &mut send,
             &mut recv,
+            self.config.topic_auth.as_ref(),
             peer,
-            topic.clone(),
-            sync_protocol,
-            engine_actor_tx,
+            &topic_ids,
         )
         .await?;
 
+        // Run a sync session as the initiator.
+        let delay = self
+            .config
+            .faults
+            .as_ref()
+            .and_then(FaultInjector::sync_frame_delay);
+        let result = {
+            let mut send = Delayed::new(&mut send, delay);
+            let mut send = Throttled::new(&mut send, relay_session.bandwidth);
+            let mut send = Counted::new(&mut send, relay_session.bytes.clone());
+            let mut send = Counted::new(
+                &mut send,
+                transcript.as_ref().map(TranscriptRecorder::bytes_sent),
+            );
+            let mut recv = Delayed::new(&mut recv, delay);
+            let mut recv = Throttled::new(&mut recv, relay_session.bandwidth);
+            let mut recv = Counted::new(&mut recv, relay_session.bytes);
+            let mut recv = Counted::new(
+                &mut recv,
+                transcript.as_ref().map(TranscriptRecorder::bytes_received),
+            );
+            let mut send = Compressed::new(&mut send, compressor.clone());
+            let mut recv = Compressed::new(&mut recv, compressor);
+
+            let result = if scopes.len() > 1 {
+                let topics = scopes.iter().map(|scope| scope.topic.clone()).collect();
+                sync::initiate_batch_sync(
+                    &mut send,
+                    &mut recv,
+                    peer,
+                    topics,
+                    sync_protocol,
+                    engine_actor_tx,
+                    transcript.clone(),
+                )
+                .await
+            } else {
+                sync::initiate_sync(
+                    &mut send,
+                    &mut recv,
+                    peer,
+                    topic,
+                    filter,
+                    sync_protocol,
+                    engine_actor_tx,
+                    transcript.clone(),
+                )
+                .await
+            };
+
+            // Send the last buffered frame of a compressed session.
+            match result {
+                Ok(()) => send.flush().await.map_err(SyncError::from),
+                Err(err) => Err(err),
+            }
+        };
+
+        if let Some(transcript) = transcript {
+            transcript.finish(&result);
+        }
+        result?;
+
         // Clean-up the streams.
         send.finish()?;
         send.stopped().await?;
@@ -311,6 +561,9 @@ where
             attempt.status = Status::Complete(Instant::now())
         }
 
+        // The peer is behaving well (again).
+        self.config.reputation.reward(scope.peer);
+
         if self.config.is_resync() {
             self.resync_queue.push_back(scope);
         }
@@ -321,11 +574,24 @@ where
     /// Mark the status of the attempt as `Failed`, increment the attempts counter and inform the
     /// engine of the failure.
     ///
-    /// The attempt is pushed to the back of the retry queue if the maximum number of retry
-    /// attempts has not been exceeded.
-    async fn complete_failed_sync(&mut self, scope: Scope<T>, err: Error) -> Result<()> {
+    /// The attempt is pushed to the back of the retry queue if the failure is transient and the
+    /// maximum number of retry attempts has not been exceeded. Failures caused by the peer are
+    /// reported as misbehaviour, lowering its reputation.
+    async fn complete_failed_sync(&mut self, scope: Scope<T>, err: &Error) -> Result<()> {
         warn!("sync attempt failed for scope {:?}: {}", scope, err);
 
+        // Errors which are not sync errors stem from the connection, these are always considered
+        // to be transient.
+        let sync_error = match err.downcast_ref::<SyncAttemptError>() {
+            Some(SyncAttemptError::Sync(err)) => Some(err),
+            Some(SyncAttemptError::Connection | SyncAttemptError::Relayed(_)) => None,
+            None => err.downcast_ref::<SyncError>(),
+        };
+        let is_transient = sync_error.is_none_or(SyncError::is_transient);
+        if let Some(misbehaviour) = sync_error.and_then(Misbehaviour::from_sync_error) {
+            self.config.reputation.report(scope.peer, misbehaviour);
+        }
+
         // Inform the engine of the failed attempt so that the gossip buffer counter
         // can be decremented (if one exists).
         self.engine_actor_tx
@@ -339,7 +605,7 @@ where
             attempt.status = Status::Failed(Instant::now());
             attempt.attempts += 1;
 
-            if attempt.attempts <= self.config.max_retry_attempts {
+            if is_transient && attempt.attempts <= self.config.max_retry_attempts {
                 self.retry_queue.push_back(scope);
             }
         }
@@ -356,20 +622,21 @@ mod tests {
     use futures_util::FutureExt;
     use iroh::{Endpoint, RelayMode};
     use iroh_quinn::TransportConfig;
-    use p2panda_core::PublicKey;
-    use p2panda_sync::SyncProtocol;
+    use p2panda_core::{PrivateKey, PublicKey};
     use p2panda_sync::test_protocols::{PingPongProtocol, SyncTestTopic as TestTopic};
+    use p2panda_sync::{SyncError, SyncProtocol};
     use tokio::sync::mpsc;
     use tokio::time::{Duration, sleep};
     use tokio_util::sync::CancellationToken;
     use tracing::warn;
 
+    use crate::NodeRoles;
     use crate::engine::ToEngineActor;
     use crate::protocols::ProtocolMap;
     use crate::sync::{SYNC_CONNECTION_ALPN, SyncConnection};
     use crate::{ResyncConfiguration, SyncConfiguration, to_public_key};
 
-    use super::{SyncActor, ToSyncActor};
+    use super::{Attempt, Scope, SyncActor, SyncAttemptError, ToSyncActor};
 
     async fn build_endpoint(port: u16) -> Endpoint {
         let mut transport_config = TransportConfig::default();
@@ -431,14 +698,13 @@ mod tests {
         let endpoint_b = build_endpoint(2024).await;
 
         let mut protocols_a = ProtocolMap::default();
-        let sync_handler_a =
-            SyncConnection::new(Arc::new(protocol.clone()), engine_actor_tx_a.clone());
+        let sync_handler_a = SyncConnection::new(&config_a, engine_actor_tx_a.clone());
         protocols_a.insert(SYNC_CONNECTION_ALPN, Arc::new(sync_handler_a));
         let alpns_a = protocols_a.alpns();
         endpoint_a.set_alpns(alpns_a).unwrap();
 
         let mut protocols_b = ProtocolMap::default();
-        let sync_handler_b = SyncConnection::new(Arc::new(protocol), engine_actor_tx_b.clone());
+        let sync_handler_b = SyncConnection::new(&config_b, engine_actor_tx_b.clone());
         protocols_b.insert(SYNC_CONNECTION_ALPN, Arc::new(sync_handler_b));
         let alpns_b = protocols_b.alpns();
         endpoint_b.set_alpns(alpns_b).unwrap();
@@ -505,14 +771,14 @@ mod tests {
         let (
             test_topic,
             peer_a,
-            sync_actor_a,
+            mut sync_actor_a,
             sync_actor_tx_a,
             endpoint_a,
             mut engine_actor_rx_a,
             protocols_a,
             shutdown_token_a,
             peer_b,
-            sync_actor_b,
+            mut sync_actor_b,
             endpoint_b,
             mut engine_actor_rx_b,
             protocols_b,
@@ -549,7 +815,11 @@ mod tests {
 
         // Trigger sync session initiation by peer A.
         sync_actor_tx_a
-            .send(ToSyncActor::new_discovery(peer_b, test_topic.clone()))
+            .send(ToSyncActor::new_discovery(
+                peer_b,
+                test_topic.clone(),
+                NodeRoles::new(),
+            ))
             .await
             .unwrap();
 
@@ -629,14 +899,14 @@ mod tests {
         let (
             test_topic,
             peer_a,
-            sync_actor_a,
+            mut sync_actor_a,
             sync_actor_tx_a,
             endpoint_a,
             mut engine_actor_rx_a,
             protocols_a,
             shutdown_token_a,
             peer_b,
-            sync_actor_b,
+            mut sync_actor_b,
             endpoint_b,
             mut engine_actor_rx_b,
             protocols_b,
@@ -676,7 +946,11 @@ mod tests {
         // This would occur when the next peer-topic announcement arrived via the network-wide
         // gossip overlay.
         sync_actor_tx_a
-            .send(ToSyncActor::new_discovery(peer_b, test_topic.clone()))
+            .send(ToSyncActor::new_discovery(
+                peer_b,
+                test_topic.clone(),
+                NodeRoles::new(),
+            ))
             .await
             .unwrap();
 
@@ -753,7 +1027,11 @@ mod tests {
         // This emulates the scope being sent to the sync manager via the peer discovery
         // announcement mechanism.
         sync_actor_tx_a
-            .send(ToSyncActor::new_discovery(peer_b, test_topic.clone()))
+            .send(ToSyncActor::new_discovery(
+                peer_b,
+                test_topic.clone(),
+                NodeRoles::new(),
+            ))
             .await
             .unwrap();
 
@@ -775,14 +1053,14 @@ mod tests {
         let (
             test_topic,
             peer_a,
-            sync_actor_a,
+            mut sync_actor_a,
             sync_actor_tx_a,
             endpoint_a,
             mut engine_actor_rx_a,
             protocols_a,
             shutdown_token_a,
             peer_b,
-            sync_actor_b,
+            mut sync_actor_b,
             endpoint_b,
             mut engine_actor_rx_b,
             protocols_b,
@@ -821,7 +1099,11 @@ mod tests {
 
         // Trigger sync session initiation by peer A.
         sync_actor_tx_a
-            .send(ToSyncActor::new_discovery(peer_b, test_topic.clone()))
+            .send(ToSyncActor::new_discovery(
+                peer_b,
+                test_topic.clone(),
+                NodeRoles::new(),
+            ))
             .await
             .unwrap();
 
@@ -874,7 +1156,11 @@ mod tests {
 
         // Trigger sync session initiation by peer A.
         sync_actor_tx_a
-            .send(ToSyncActor::new_discovery(peer_b, test_topic.clone()))
+            .send(ToSyncActor::new_discovery(
+                peer_b,
+                test_topic.clone(),
+                NodeRoles::new(),
+            ))
             .await
             .unwrap();
 
@@ -925,14 +1211,14 @@ mod tests {
         let (
             test_topic,
             peer_a,
-            sync_actor_a,
+            mut sync_actor_a,
             sync_actor_tx_a,
             endpoint_a,
             mut engine_actor_rx_a,
             protocols_a,
             shutdown_token_a,
             peer_b,
-            sync_actor_b,
+            mut sync_actor_b,
             endpoint_b,
             mut engine_actor_rx_b,
             protocols_b,
@@ -971,7 +1257,11 @@ mod tests {
 
         // Trigger sync session initiation by peer A.
         sync_actor_tx_a
-            .send(ToSyncActor::new_discovery(peer_b, test_topic.clone()))
+            .send(ToSyncActor::new_discovery(
+                peer_b,
+                test_topic.clone(),
+                NodeRoles::new(),
+            ))
             .await
             .unwrap();
 
@@ -1056,4 +1346,97 @@ mod tests {
             panic!("expected to receive SyncDone on engine actor receiver for peer a")
         };
     }
+
+    async fn failing_sync_actor(
+        config: SyncConfiguration<TestTopic>,
+    ) -> (
+        SyncActor<TestTopic>,
+        mpsc::Receiver<ToEngineActor<TestTopic>>,
+    ) {
+        let endpoint = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind_addr_v4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .bind_addr_v6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0))
+            .bind()
+            .await
+            .unwrap();
+        let (engine_actor_tx, engine_actor_rx) = mpsc::channel(64);
+        let (sync_actor, _sync_actor_tx) = SyncActor::new(config, endpoint, engine_actor_tx);
+        (sync_actor, engine_actor_rx)
+    }
+
+    #[tokio::test]
+    async fn retry_transient_failures() {
+        let config = SyncConfiguration::new(PingPongProtocol {});
+        let (mut sync_actor, mut engine_actor_rx) = failing_sync_actor(config).await;
+        let topic = TestTopic::new("retry");
+
+        let failures = [
+            (SyncError::Disconnected("broken pipe".into()), true, 0),
+            (SyncError::Timeout("idle".into()), true, 0),
+            (SyncError::UnexpectedBehaviour("bang".into()), true, -20),
+            (SyncError::Validation("invalid".into()), false, -20),
+            (SyncError::Critical("bug".into()), false, 0),
+        ];
+
+        for (err, retried, score) in failures {
+            let peer = PrivateKey::new().public_key();
+            let scope = Scope::new(peer, topic.clone());
+            sync_actor.sessions.insert(scope.clone(), Attempt::new());
+
+            let err = SyncAttemptError::Sync(err).into();
+            sync_actor
+                .complete_failed_sync(scope.clone(), &err)
+                .await
+                .unwrap();
+
+            assert!(matches!(
+                engine_actor_rx.recv().await,
+                Some(ToEngineActor::SyncFailed { .. })
+            ));
+            assert_eq!(sync_actor.retry_queue.contains(&scope), retried, "{err}");
+
+            // Disconnects are not held against the remote peer.
+            assert_eq!(sync_actor.config.reputation.score(&peer), score, "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_after_interval() {
+        let config = SyncConfiguration::new(PingPongProtocol {})
+            .retry_interval(5)
+            .max_retry_attempts(1);
+        let (mut sync_actor, mut engine_actor_rx) = failing_sync_actor(config).await;
+        let retry_interval = sync_actor.config.retry_interval;
+
+        tokio::time::pause();
+
+        let scope = Scope::new(PrivateKey::new().public_key(), TestTopic::new("retry"));
+        sync_actor.sessions.insert(scope.clone(), Attempt::new());
+        let err = SyncAttemptError::Sync(SyncError::Disconnected("broken pipe".into())).into();
+        sync_actor
+            .complete_failed_sync(scope.clone(), &err)
+            .await
+            .unwrap();
+        engine_actor_rx.recv().await.unwrap();
+
+        // The attempt waits for the retry interval to pass.
+        sync_actor.schedule_retry(retry_interval).await;
+        assert!(sync_actor.sync_queue.is_empty());
+        assert!(sync_actor.retry_queue.contains(&scope));
+
+        tokio::time::advance(retry_interval).await;
+        sync_actor.schedule_retry(retry_interval).await;
+        assert!(sync_actor.sync_queue.contains(&scope.topic, &scope));
+        assert!(sync_actor.retry_queue.is_empty());
+
+        // The maximum number of retry attempts was reached with the next failure.
+        sync_actor.sync_queue.pop();
+        sync_actor
+            .complete_failed_sync(scope.clone(), &err)
+            .await
+            .unwrap();
+        engine_actor_rx.recv().await.unwrap();
+        assert!(sync_actor.retry_queue.is_empty());
+    }
 }
@@ -1,14 +1,32 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 mod accept;
+mod auth;
 mod config;
+mod delta;
+mod diff;
 mod handler;
 mod initiate;
 pub(crate) mod manager;
+mod negotiation;
+mod quota;
+mod scheduler;
 #[cfg(test)]
 mod tests;
+mod throttle;
+mod transcript;
 
 pub use accept::accept_sync;
-pub use config::{ResyncConfiguration, SyncConfiguration};
+pub use auth::{TopicGrants, authenticate_acceptor, authenticate_initiator};
+pub use config::{ResyncConfiguration, SyncConfiguration, SyncQuotas};
+pub(crate) use delta::{DeltaAnnouncement, is_behind};
+pub use delta::{LogHeights, LogHeightsProvider};
+pub(crate) use diff::sync_diff;
 pub use handler::{SYNC_CONNECTION_ALPN, SyncConnection};
-pub use initiate::initiate_sync;
+pub use initiate::{initiate_batch_sync, initiate_sync};
+pub use negotiation::{negotiate_acceptor, negotiate_initiator};
+pub use quota::QuotaExemptions;
+pub(crate) use quota::{Metered, QuotaTracker};
+pub(crate) use throttle::Throttled;
+pub(crate) use transcript::{Counted, TranscriptRecorder};
+pub use transcript::{SyncOutcome, SyncRole, SyncTranscript, TranscriptEntry, TranscriptSink};
@@ -0,0 +1,356 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::sync::Arc;
+
+use futures_util::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use p2panda_core::cbor::{decode_cbor, encode_cbor};
+use p2panda_sync::{SyncError, SyncProtocol, TopicQuery};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::compression::CompressionAlgorithm;
+
+/// Maximum size in bytes of a single negotiation message.
+const MAX_NEGOTIATION_MESSAGE_SIZE: usize = 4096;
+
+/// Messages exchanged before a sync session begins to agree on the sync protocol and
+/// compression.
+#[derive(Debug, Serialize, Deserialize)]
+enum NegotiationMessage {
+    /// Names of all sync protocols and compression algorithms supported by the initiator, ordered
+    /// by preference.
+    Propose(Vec<String>, Vec<CompressionAlgorithm>),
+
+    /// Name of the sync protocol and the compression algorithm selected by the acceptor, `None`
+    /// if no mutually supported protocol or algorithm was found.
+    Select(Option<String>, Option<CompressionAlgorithm>),
+}
+
+/// Sync protocol and compression algorithm agreed on for a session.
+pub struct Negotiated<T> {
+    pub protocol: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
+
+    /// Compression of the session's data, `None` if the session is not compressed.
+    pub compression: Option<CompressionAlgorithm>,
+}
+
+/// Negotiate the sync protocol and compression for this session as the "initiator".
+///
+/// We propose all our supported protocols and compression algorithms in order of preference and
+/// wait for the acceptor to select one of each.
+pub async fn negotiate_initiator<T, S, R>(
+    send: &mut S,
+    recv: &mut R,
+    protocols: &[Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>],
+    compression: &[CompressionAlgorithm],
+) -> Result<Negotiated<T>, SyncError>
+where
+    T: TopicQuery + 'static,
+    S: AsyncWrite + Send + Unpin,
+    R: AsyncRead + Send + Unpin,
+{
+    let names = protocols
+        .iter()
+        .map(|protocol| protocol.name().to_string())
+        .collect();
+    write_message(
+        send,
+        &NegotiationMessage::Propose(names, compression.to_vec()),
+    )
+    .await?;
+
+    let NegotiationMessage::Select(selected, selected_compression) = read_message(recv).await?
+    else {
+        return Err(SyncError::UnexpectedBehaviour(
+            "expected protocol selection message during negotiation".into(),
+        ));
+    };
+
+    let Some(selected) = selected else {
+        return Err(SyncError::UnsupportedProtocol(
+            "remote peer does not support any of our sync protocols".into(),
+        ));
+    };
+
+    // The acceptor is only allowed to pick one of the protocols we've proposed.
+    let protocol = protocols
+        .iter()
+        .find(|protocol| protocol.name() == selected)
+        .ok_or_else(|| {
+            SyncError::UnexpectedBehaviour(format!(
+                "remote peer selected unknown sync protocol {selected}"
+            ))
+        })?;
+
+    // The same applies to the compression algorithm.
+    if let Some(algorithm) = selected_compression
+        && !compression.contains(&algorithm)
+    {
+        return Err(SyncError::UnexpectedBehaviour(format!(
+            "remote peer selected unknown compression {algorithm:?}"
+        )));
+    }
+
+    debug!(
+        "negotiated sync protocol {} with compression {:?} as initiator",
+        protocol.name(),
+        selected_compression
+    );
+
+    Ok(Negotiated {
+        protocol: protocol.clone(),
+        compression: selected_compression,
+    })
+}
+
+/// Negotiate the sync protocol and compression for this session as the "acceptor".
+///
+/// We select the first protocol and compression algorithm of the initiator's proposal which we
+/// support as well. This respects the initiator's order of preference.
+pub async fn negotiate_acceptor<T, S, R>(
+    send: &mut S,
+    recv: &mut R,
+    protocols: &[Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>],
+    compression: &[CompressionAlgorithm],
+) -> Result<Negotiated<T>, SyncError>
+where
+    T: TopicQuery + 'static,
+    S: AsyncWrite + Send + Unpin,
+    R: AsyncRead + Send + Unpin,
+{
+    let NegotiationMessage::Propose(proposed, proposed_compression) = read_message(recv).await?
+    else {
+        return Err(SyncError::UnexpectedBehaviour(
+            "expected protocol proposal message during negotiation".into(),
+        ));
+    };
+
+    let protocol = proposed.iter().find_map(|name| {
+        protocols
+            .iter()
+            .find(|protocol| protocol.name() == name)
+            .cloned()
+    });
+
+    let selected_compression = proposed_compression
+        .into_iter()
+        .find(|algorithm| compression.contains(algorithm));
+
+    write_message(
+        send,
+        &NegotiationMessage::Select(
+            protocol
+                .as_ref()
+                .map(|protocol| protocol.name().to_string()),
+            selected_compression,
+        ),
+    )
+    .await?;
+
+    match protocol {
+        Some(protocol) => {
+            debug!(
+                "negotiated sync protocol {} with compression {:?} as acceptor",
+                protocol.name(),
+                selected_compression
+            );
+            Ok(Negotiated {
+                protocol,
+                compression: selected_compression,
+            })
+        }
+        None => Err(SyncError::UnsupportedProtocol(format!(
+            "no mutually supported sync protocol found in {proposed:?}"
+        ))),
+    }
+}
+
+/// Write a length-prefixed negotiation message.
+///
+/// We're not using a buffered, framed writer here to make sure that no bytes of the following
+/// sync session get consumed during negotiation. Also used for the messages authenticating the
+/// peers of a session.
+pub(super) async fn write_message<S, M>(send: &mut S, message: &M) -> Result<(), SyncError>
+where
+    S: AsyncWrite + Send + Unpin,
+    M: Serialize,
+{
+    let bytes = encode_cbor(message).map_err(|err| {
+        SyncError::Critical(format!("failed encoding negotiation message, {err}"))
+    })?;
+    if bytes.len() > MAX_NEGOTIATION_MESSAGE_SIZE {
+        return Err(SyncError::Critical(
+            "negotiation message exceeds maximum size".into(),
+        ));
+    }
+
+    send.write_all(&(bytes.len() as u16).to_be_bytes()).await?;
+    send.write_all(&bytes).await?;
+    send.flush().await?;
+
+    Ok(())
+}
+
+/// Read a length-prefixed negotiation message.
+pub(super) async fn read_message<R, M>(recv: &mut R) -> Result<M, SyncError>
+where
+    R: AsyncRead + Send + Unpin,
+    M: for<'a> Deserialize<'a>,
+{
+    let mut len = [0u8; 2];
+    recv.read_exact(&mut len).await?;
+    let len = u16::from_be_bytes(len) as usize;
+    if len > MAX_NEGOTIATION_MESSAGE_SIZE {
+        return Err(SyncError::UnexpectedBehaviour(
+            "negotiation message exceeds maximum size".into(),
+        ));
+    }
+
+    let mut bytes = vec![0u8; len];
+    recv.read_exact(&mut bytes).await?;
+
+    decode_cbor(&bytes[..]).map_err(|err| SyncError::InvalidEncoding(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use p2panda_sync::SyncProtocol;
+    use p2panda_sync::test_protocols::{DummyProtocol, PingPongProtocol, SyncTestTopic};
+    use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+    use crate::compression::CompressionAlgorithm;
+
+    use super::{negotiate_acceptor, negotiate_initiator};
+
+    type Protocols = Vec<Arc<dyn for<'a> SyncProtocol<'a, SyncTestTopic> + 'static>>;
+
+    type Negotiated = Option<(String, Option<CompressionAlgorithm>)>;
+
+    async fn negotiate(
+        initiator: Protocols,
+        acceptor: Protocols,
+    ) -> (Option<String>, Option<String>) {
+        let (initiator_result, acceptor_result) =
+            negotiate_with_compression((initiator, vec![]), (acceptor, vec![])).await;
+        (
+            initiator_result.map(|(protocol, _)| protocol),
+            acceptor_result.map(|(protocol, _)| protocol),
+        )
+    }
+
+    async fn negotiate_with_compression(
+        initiator: (Protocols, Vec<CompressionAlgorithm>),
+        acceptor: (Protocols, Vec<CompressionAlgorithm>),
+    ) -> (Negotiated, Negotiated) {
+        let (initiator_stream, acceptor_stream) = tokio::io::duplex(64 * 1024);
+        let (initiator_read, initiator_write) = tokio::io::split(initiator_stream);
+        let (acceptor_read, acceptor_write) = tokio::io::split(acceptor_stream);
+
+        let initiator_handle = tokio::spawn(async move {
+            negotiate_initiator(
+                &mut initiator_write.compat_write(),
+                &mut initiator_read.compat(),
+                &initiator.0,
+                &initiator.1,
+            )
+            .await
+            .ok()
+            .map(|negotiated| {
+                (
+                    negotiated.protocol.name().to_string(),
+                    negotiated.compression,
+                )
+            })
+        });
+
+        let acceptor_handle = tokio::spawn(async move {
+            negotiate_acceptor(
+                &mut acceptor_write.compat_write(),
+                &mut acceptor_read.compat(),
+                &acceptor.0,
+                &acceptor.1,
+            )
+            .await
+            .ok()
+            .map(|negotiated| {
+                (
+                    negotiated.protocol.name().to_string(),
+                    negotiated.compression,
+                )
+            })
+        });
+
+        (
+            initiator_handle.await.unwrap(),
+            acceptor_handle.await.unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn agree_on_preferred_protocol() {
+        let initiator: Protocols = vec![Arc::new(PingPongProtocol {}), Arc::new(DummyProtocol {})];
+        let acceptor: Protocols = vec![Arc::new(DummyProtocol {}), Arc::new(PingPongProtocol {})];
+
+        // The initiator's order of preference is respected.
+        let (initiator_result, acceptor_result) = negotiate(initiator, acceptor).await;
+        assert_eq!(
+            initiator_result,
+            Some(PingPongProtocol {}.name().to_string())
+        );
+        assert_eq!(initiator_result, acceptor_result);
+    }
+
+    #[tokio::test]
+    async fn fall_back_to_mutually_supported_protocol() {
+        let initiator: Protocols = vec![Arc::new(PingPongProtocol {}), Arc::new(DummyProtocol {})];
+        let acceptor: Protocols = vec![Arc::new(DummyProtocol {})];
+
+        let (initiator_result, acceptor_result) = negotiate(initiator, acceptor).await;
+        assert_eq!(initiator_result, Some(DummyProtocol {}.name().to_string()));
+        assert_eq!(initiator_result, acceptor_result);
+    }
+
+    #[tokio::test]
+    async fn no_mutually_supported_protocol() {
+        let initiator: Protocols = vec![Arc::new(PingPongProtocol {})];
+        let acceptor: Protocols = vec![Arc::new(DummyProtocol {})];
+
+        let (initiator_result, acceptor_result) = negotiate(initiator, acceptor).await;
+        assert!(initiator_result.is_none());
+        assert!(acceptor_result.is_none());
+    }
+
+    #[tokio::test]
+    async fn agree_on_compression() {
+        let protocols = || -> Protocols { vec![Arc::new(DummyProtocol {})] };
+
+        // The initiator's order of preference is respected.
+        let (initiator_result, acceptor_result) = negotiate_with_compression(
+            (
+                protocols(),
+                vec![CompressionAlgorithm::Zstd, CompressionAlgorithm::Lz4],
+            ),
+            (
+                protocols(),
+                vec![CompressionAlgorithm::Lz4, CompressionAlgorithm::Zstd],
+            ),
+        )
+        .await;
+        assert_eq!(
+            initiator_result.unwrap().1,
+            Some(CompressionAlgorithm::Zstd)
+        );
+        assert_eq!(acceptor_result.unwrap().1, Some(CompressionAlgorithm::Zstd));
+
+        // Sessions are not compressed without a mutually supported algorithm.
+        let (initiator_result, acceptor_result) = negotiate_with_compression(
+            (protocols(), vec![CompressionAlgorithm::Zstd]),
+            (protocols(), vec![]),
+        )
+        .await;
+        assert_eq!(initiator_result.unwrap().1, None);
+        assert_eq!(acceptor_result.unwrap().1, None);
+    }
+}
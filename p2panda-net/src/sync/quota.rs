@@ -0,0 +1,351 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Limits for sync sessions accepted from other peers.
+//!
+//! Public nodes serve data to everyone who asks for it. Quotas protect them from being drained by
+//! scrapers by limiting the number of concurrently accepted sync sessions and the number of bytes
+//! served to every peer per day. Applications can exempt trusted peers from these limits, sync
+//! protocols then also lift their own limits for these peers (see
+//! `SyncProtocol::accept_exempt`).
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+
+use futures_util::AsyncWrite;
+use p2panda_core::PublicKey;
+use thiserror::Error;
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::sync::SyncQuotas;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Decides which peers are not subject to sync quotas.
+///
+/// Implementations are called at the beginning of every accepted sync session and should not
+/// block.
+pub trait QuotaExemptions: Debug + Send + Sync {
+    /// Returns `true` if sync sessions with the given peer should not be limited.
+    fn is_exempt(&self, peer: &PublicKey) -> bool;
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub(crate) enum QuotaError {
+    #[error("maximum number of {0} accepted sync sessions reached")]
+    TooManySessions(usize),
+
+    #[error("daily sync quota of {0} bytes exceeded")]
+    DailyQuotaExceeded(u64),
+}
+
+/// Bytes served to every peer on the current day.
+#[derive(Debug, Default)]
+struct DailyUsage {
+    day: u64,
+    served: HashMap<PublicKey, u64>,
+}
+
+impl DailyUsage {
+    /// Returns the bytes served to the peer on the given day.
+    fn served(&mut self, peer: &PublicKey, day: u64) -> u64 {
+        self.roll_over(day);
+        self.served.get(peer).copied().unwrap_or_default()
+    }
+
+    /// Adds bytes served to the peer on the given day.
+    fn record(&mut self, peer: &PublicKey, day: u64, bytes: u64) {
+        self.roll_over(day);
+        let served = self.served.entry(*peer).or_default();
+        *served = served.saturating_add(bytes);
+    }
+
+    /// Forgets the usage of past days.
+    fn roll_over(&mut self, day: u64) {
+        if self.day != day {
+            self.day = day;
+            self.served.clear();
+        }
+    }
+}
+
+/// Daily quota of a single peer.
+///
+/// The day is determined whenever usage is checked or recorded, sessions running past midnight
+/// count the bytes sent afterwards against the new day.
+#[derive(Clone, Debug)]
+struct DailyQuota {
+    usage: Arc<Mutex<DailyUsage>>,
+    peer: PublicKey,
+    limit: u64,
+}
+
+impl DailyQuota {
+    /// Returns the number of bytes which can still be sent to the peer on the given day.
+    fn remaining(&self, day: u64) -> u64 {
+        let served = lock(&self.usage).served(&self.peer, day);
+        self.limit.saturating_sub(served)
+    }
+
+    /// Records bytes sent to the peer on the given day.
+    fn record(&self, day: u64, bytes: u64) {
+        lock(&self.usage).record(&self.peer, day, bytes);
+    }
+}
+
+/// Keeps track of accepted sessions and bytes served to every peer.
+#[derive(Debug)]
+pub(crate) struct QuotaTracker {
+    quotas: SyncQuotas,
+    accepted_sessions: Arc<AtomicUsize>,
+    usage: Arc<Mutex<DailyUsage>>,
+}
+
+impl QuotaTracker {
+    pub fn new(quotas: SyncQuotas) -> Self {
+        Self {
+            quotas,
+            accepted_sessions: Arc::new(AtomicUsize::new(0)),
+            usage: Arc::new(Mutex::new(DailyUsage::default())),
+        }
+    }
+
+    /// Admits a new sync session with the given peer if it is within the quotas.
+    ///
+    /// The returned permit needs to be held for the duration of the session.
+    pub fn admit(&self, peer: &PublicKey) -> Result<QuotaPermit, QuotaError> {
+        self.admit_on(peer, today())
+    }
+
+    /// Admits a new sync session with the given peer on the given day.
+    fn admit_on(&self, peer: &PublicKey, day: u64) -> Result<QuotaPermit, QuotaError> {
+        if let Some(exemptions) = &self.quotas.exemptions
+            && exemptions.is_exempt(peer)
+        {
+            return Ok(QuotaPermit {
+                exempt: true,
+                accepted_sessions: None,
+                daily_quota: None,
+            });
+        }
+
+        let daily_quota = match self.quotas.daily_bytes_per_peer {
+            Some(limit) => {
+                let daily_quota = DailyQuota {
+                    usage: self.usage.clone(),
+                    peer: *peer,
+                    limit,
+                };
+                if daily_quota.remaining(day) == 0 {
+                    return Err(QuotaError::DailyQuotaExceeded(limit));
+                }
+                Some(daily_quota)
+            }
+            None => None,
+        };
+
+        let accepted_sessions = match self.quotas.max_accepted_sessions {
+            Some(max) => {
+                let admitted = self
+                    .accepted_sessions
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |sessions| {
+                        (sessions < max).then_some(sessions + 1)
+                    })
+                    .is_ok();
+                if !admitted {
+                    return Err(QuotaError::TooManySessions(max));
+                }
+                Some(self.accepted_sessions.clone())
+            }
+            None => None,
+        };
+
+        Ok(QuotaPermit {
+            exempt: false,
+            accepted_sessions,
+            daily_quota,
+        })
+    }
+}
+
+/// Permit of an admitted sync session, releasing its slot when dropped.
+#[derive(Debug, Default)]
+pub(crate) struct QuotaPermit {
+    exempt: bool,
+    accepted_sessions: Option<Arc<AtomicUsize>>,
+    daily_quota: Option<DailyQuota>,
+}
+
+impl QuotaPermit {
+    /// Returns `true` if the peer is exempt from all quotas.
+    pub fn is_exempt(&self) -> bool {
+        self.exempt
+    }
+}
+
+impl Drop for QuotaPermit {
+    fn drop(&mut self) {
+        if let Some(accepted_sessions) = &self.accepted_sessions {
+            accepted_sessions.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Wrapper around a send stream failing all writes once the peer's daily quota is exhausted.
+pub(crate) struct Metered<'a, S> {
+    inner: &'a mut S,
+    daily_quota: Option<DailyQuota>,
+}
+
+impl<'a, S> Metered<'a, S> {
+    pub fn new(inner: &'a mut S, permit: &QuotaPermit) -> Self {
+        Self {
+            inner,
+            daily_quota: permit.daily_quota.clone(),
+        }
+    }
+}
+
+impl<S> AsyncWrite for Metered<'_, S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let Some(daily_quota) = self.daily_quota.clone() else {
+            return Pin::new(&mut *self.inner).poll_write(cx, buf);
+        };
+
+        let remaining = daily_quota.remaining(today());
+        if remaining == 0 {
+            return Poll::Ready(Err(std::io::Error::other("daily sync quota exceeded")));
+        }
+
+        let len = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let result = Pin::new(&mut *self.inner).poll_write(cx, &buf[..len]);
+        if let Poll::Ready(Ok(bytes)) = result {
+            daily_quota.record(today(), bytes as u64);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_close(cx)
+    }
+}
+
+/// Locks the usage shared by all sessions.
+fn lock(usage: &Mutex<DailyUsage>) -> MutexGuard<'_, DailyUsage> {
+    usage.lock().expect("quota usage lock is not poisoned")
+}
+
+/// Number of days since the unix epoch.
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / SECONDS_PER_DAY)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::AsyncWriteExt;
+    use p2panda_core::{PrivateKey, PublicKey};
+
+    use crate::sync::SyncQuotas;
+
+    use super::{Metered, QuotaError, QuotaExemptions, QuotaTracker};
+
+    #[derive(Debug)]
+    struct Exempt(PublicKey);
+
+    impl QuotaExemptions for Exempt {
+        fn is_exempt(&self, peer: &PublicKey) -> bool {
+            &self.0 == peer
+        }
+    }
+
+    #[test]
+    fn limit_accepted_sessions() {
+        let peer = PrivateKey::new().public_key();
+        let tracker = QuotaTracker::new(SyncQuotas::new().max_accepted_sessions(1));
+
+        let permit = tracker.admit(&peer).unwrap();
+        assert_eq!(
+            tracker.admit(&peer).unwrap_err(),
+            QuotaError::TooManySessions(1)
+        );
+
+        // Sessions are released when the permit is dropped.
+        drop(permit);
+        assert!(tracker.admit(&peer).is_ok());
+    }
+
+    #[tokio::test]
+    async fn limit_daily_bytes() {
+        let peer = PrivateKey::new().public_key();
+        let other_peer = PrivateKey::new().public_key();
+        let tracker = QuotaTracker::new(SyncQuotas::new().daily_bytes_per_peer(4));
+
+        let permit = tracker.admit(&peer).unwrap();
+        let mut buf = Vec::new();
+        let mut send = Metered::new(&mut buf, &permit);
+        assert!(send.write_all(b"hello").await.is_err());
+        assert_eq!(buf, b"hell");
+
+        assert_eq!(
+            tracker.admit(&peer).unwrap_err(),
+            QuotaError::DailyQuotaExceeded(4)
+        );
+        assert!(tracker.admit(&other_peer).is_ok());
+    }
+
+    #[test]
+    fn record_usage_on_day_of_write() {
+        let peer = PrivateKey::new().public_key();
+        let tracker = QuotaTracker::new(SyncQuotas::new().daily_bytes_per_peer(4));
+
+        let permit = tracker.admit_on(&peer, 1).unwrap();
+        let daily_quota = permit.daily_quota.clone().unwrap();
+        daily_quota.record(1, 4);
+        assert_eq!(
+            tracker.admit_on(&peer, 1).unwrap_err(),
+            QuotaError::DailyQuotaExceeded(4)
+        );
+
+        // The session keeps running past midnight, bytes sent afterwards count against the new
+        // day.
+        assert_eq!(daily_quota.remaining(2), 4);
+        daily_quota.record(2, 3);
+        assert_eq!(daily_quota.remaining(2), 1);
+        assert!(tracker.admit_on(&peer, 2).is_ok());
+
+        daily_quota.record(2, 1);
+        assert_eq!(
+            tracker.admit_on(&peer, 2).unwrap_err(),
+            QuotaError::DailyQuotaExceeded(4)
+        );
+    }
+
+    #[test]
+    fn exempt_peers() {
+        let peer = PrivateKey::new().public_key();
+        let tracker = QuotaTracker::new(
+            SyncQuotas::new()
+                .max_accepted_sessions(0)
+                .exemptions(Exempt(peer)),
+        );
+
+        assert!(tracker.admit(&peer).unwrap().is_exempt());
+        assert!(tracker.admit(&PrivateKey::new().public_key()).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Queue scheduling items fairly across topics using weighted round-robin.
+///
+/// Items are queued per topic. When popping, topics take turns and every topic may hand out as
+/// many consecutive items as its weight allows before it's the next topic's turn. This makes sure
+/// that topics with only a few pending sync attempts are served quickly, even while many attempts
+/// for another topic are waiting.
+///
+/// Topics without a configured weight have a weight of 1.
+#[derive(Debug)]
+pub(crate) struct FairQueue<K, V> {
+    weights: HashMap<K, u32>,
+    queues: HashMap<K, VecDeque<V>>,
+    order: VecDeque<K>,
+    served: u32,
+    len: usize,
+}
+
+impl<K, V> FairQueue<K, V>
+where
+    K: Clone + Eq + Hash,
+{
+    pub fn new(weights: HashMap<K, u32>) -> Self {
+        Self {
+            weights,
+            queues: HashMap::new(),
+            order: VecDeque::new(),
+            served: 0,
+            len: 0,
+        }
+    }
+
+    /// Adds an item to the back of the queue of the given topic.
+    pub fn push(&mut self, key: K, value: V) {
+        let queue = self.queues.entry(key.clone()).or_default();
+        if queue.is_empty() {
+            self.order.push_back(key);
+        }
+        queue.push_back(value);
+        self.len += 1;
+    }
+
+    /// Removes the next item, following the round-robin order of topics.
+    pub fn pop(&mut self) -> Option<V> {
+        let key = self.order.front()?.clone();
+        let queue = self.queues.get_mut(&key)?;
+        let value = queue.pop_front()?;
+        self.len -= 1;
+        self.served += 1;
+
+        let weight = self.weights.get(&key).copied().unwrap_or(1).max(1);
+        if queue.is_empty() {
+            self.queues.remove(&key);
+            self.order.pop_front();
+            self.served = 0;
+        } else if self.served >= weight {
+            self.order.rotate_left(1);
+            self.served = 0;
+        }
+
+        Some(value)
+    }
+
+    /// Returns `true` if the item is queued for the given topic.
+    pub fn contains(&self, key: &K, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        self.queues
+            .get(key)
+            .is_some_and(|queue| queue.contains(value))
+    }
+
+    /// Removes up to `limit` items matching the predicate, regardless of the round-robin order.
+    pub fn take_matching(&mut self, limit: usize, mut predicate: impl FnMut(&V) -> bool) -> Vec<V> {
+        let mut taken = Vec::new();
+        for key in self.order.clone() {
+            if taken.len() >= limit {
+                break;
+            }
+
+            let queue = self
+                .queues
+                .get_mut(&key)
+                .expect("queue exists for ordered topic");
+            let mut index = 0;
+            while index < queue.len() && taken.len() < limit {
+                if predicate(&queue[index]) {
+                    taken.push(queue.remove(index).expect("index is in bounds"));
+                } else {
+                    index += 1;
+                }
+            }
+
+            if queue.is_empty() {
+                self.queues.remove(&key);
+                if self.order.front() == Some(&key) {
+                    self.served = 0;
+                }
+                self.order.retain(|ordered| ordered != &key);
+            }
+        }
+
+        self.len -= taken.len();
+        taken
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::FairQueue;
+
+    #[test]
+    fn round_robin_across_topics() {
+        let mut queue = FairQueue::new(HashMap::new());
+        for i in 0..4 {
+            queue.push("large", i);
+        }
+        queue.push("small", 10);
+        queue.push("tiny", 20);
+        assert_eq!(queue.len(), 6);
+        assert!(queue.contains(&"small", &10));
+        assert!(!queue.contains(&"small", &20));
+
+        let popped: Vec<u32> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(popped, vec![0, 10, 20, 1, 2, 3]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn weighted_topics() {
+        let mut queue = FairQueue::new(HashMap::from([("important", 2)]));
+        for i in 0..3 {
+            queue.push("other", i);
+        }
+        for i in 10..14 {
+            queue.push("important", i);
+        }
+
+        let popped: Vec<u32> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(popped, vec![0, 10, 11, 1, 12, 13, 2]);
+    }
+
+    #[test]
+    fn take_matching_items() {
+        let mut queue = FairQueue::new(HashMap::new());
+        queue.push("a", 1);
+        queue.push("a", 2);
+        queue.push("b", 3);
+        queue.push("c", 4);
+
+        assert_eq!(queue.take_matching(2, |value| value % 2 == 0), vec![2, 4]);
+        assert_eq!(queue.len(), 2);
+        assert!(!queue.contains(&"c", &4));
+
+        let popped: Vec<u32> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(popped, vec![1, 3]);
+    }
+}
@@ -4,14 +4,22 @@ use std::sync::Arc;
 
 use futures_util::FutureExt;
 use p2panda_core::PrivateKey;
-use p2panda_sync::SyncError;
 use p2panda_sync::test_protocols::{FailingProtocol, SyncTestTopic};
+use p2panda_sync::{SyncError, SyncFilter};
 use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
 
+use crate::TopicId;
 use crate::engine::ToEngineActor;
-use crate::sync;
+use crate::sync::{self, TopicGrants};
+
+// Test topics are identified by their id in sync sessions and tests of the sync manager.
+impl TopicId for SyncTestTopic {
+    fn id(&self) -> [u8; 32] {
+        self.1
+    }
+}
 
 /// Helper method to establish a sync session between the initiator and acceptor.
 async fn run_sync_impl(
@@ -49,8 +57,10 @@ async fn run_sync_impl(
                 &mut initiator_read.compat(),
                 acceptor_node_id,
                 topic.clone(),
+                SyncFilter::default(),
                 sync_protocol,
                 initiator_tx,
+                None,
             )
             .await
         })
@@ -62,8 +72,11 @@ async fn run_sync_impl(
                 &mut acceptor_write.compat_write(),
                 &mut acceptor_read.compat(),
                 initiator_node_id,
+                TopicGrants::default(),
+                false,
                 sync_protocol_clone,
                 acceptor_tx,
+                None,
             )
             .await
         })
@@ -111,9 +124,9 @@ async fn initiator_fails_critical() {
     );
     assert_eq!(
         acceptor_handle.await.unwrap(),
-        // The acceptor failed as well, but only with an "unexpected behaviour" error over the
-        // unexpectedly closed pipe.
-        Err(SyncError::UnexpectedBehaviour("broken pipe".into()))
+        // The acceptor failed as well, but only with a "disconnected" error over the unexpectedly
+        // closed pipe.
+        Err(SyncError::Disconnected("broken pipe".into()))
     );
 }
 
@@ -154,7 +167,7 @@ async fn initiator_fails_unexpected() {
     );
     assert_eq!(
         acceptor_handle.await.unwrap(),
-        Err(SyncError::UnexpectedBehaviour("broken pipe".into()))
+        Err(SyncError::Disconnected("broken pipe".into()))
     );
 }
 
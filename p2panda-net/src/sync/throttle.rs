@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use futures_util::{AsyncRead, AsyncWrite};
+use tokio::time::{Duration, Instant, Sleep, sleep_until};
+
+/// Length of the window in which the bandwidth limit applies.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Wrapper around a send or receive stream limiting the bandwidth to the given number of bytes
+/// per second, if a limit is given.
+pub(crate) struct Throttled<'a, S> {
+    inner: &'a mut S,
+    limit: Option<u64>,
+    window_start: Instant,
+    window_bytes: u64,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<'a, S> Throttled<'a, S> {
+    pub fn new(inner: &'a mut S, limit: Option<u64>) -> Self {
+        Self {
+            inner,
+            limit,
+            window_start: Instant::now(),
+            window_bytes: 0,
+            delay: None,
+        }
+    }
+
+    /// Waits until the current window has budget left and returns the number of bytes which can
+    /// be transferred.
+    fn poll_budget(&mut self, cx: &mut Context<'_>, requested: usize) -> Poll<usize> {
+        let Some(limit) = self.limit else {
+            return Poll::Ready(requested);
+        };
+
+        loop {
+            if let Some(delay) = &mut self.delay {
+                ready!(delay.as_mut().poll(cx));
+                self.delay = None;
+            }
+
+            if self.window_start.elapsed() >= WINDOW {
+                self.window_start = Instant::now();
+                self.window_bytes = 0;
+            }
+
+            let remaining = limit.saturating_sub(self.window_bytes);
+            if remaining > 0 {
+                return Poll::Ready(requested.min(remaining as usize));
+            }
+
+            // The budget of this window is exhausted, wait for the next one.
+            self.delay = Some(Box::pin(sleep_until(self.window_start + WINDOW)));
+        }
+    }
+
+    fn consume(&mut self, bytes: usize) {
+        self.window_bytes += bytes as u64;
+    }
+}
+
+impl<S> AsyncWrite for Throttled<'_, S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let budget = ready!(self.poll_budget(cx, buf.len()));
+        let result = Pin::new(&mut *self.inner).poll_write(cx, &buf[..budget]);
+        if let Poll::Ready(Ok(bytes)) = result {
+            self.consume(bytes);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_close(cx)
+    }
+}
+
+impl<S> AsyncRead for Throttled<'_, S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let budget = ready!(self.poll_budget(cx, buf.len()));
+        let result = Pin::new(&mut *self.inner).poll_read(cx, &mut buf[..budget]);
+        if let Poll::Ready(Ok(bytes)) = result {
+            self.consume(bytes);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::AsyncWriteExt;
+    use tokio::time::{Duration, Instant};
+
+    use super::Throttled;
+
+    #[tokio::test(start_paused = true)]
+    async fn limit_bandwidth() {
+        let mut buf = Vec::new();
+        let mut throttled = Throttled::new(&mut buf, Some(100));
+
+        let started = Instant::now();
+        throttled.write_all(&[0; 150]).await.unwrap();
+
+        // 150 bytes with a limit of 100 bytes per second need two windows.
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(buf.len(), 150);
+    }
+
+    #[tokio::test]
+    async fn no_limit() {
+        let mut buf = Vec::new();
+        let mut throttled = Throttled::new(&mut buf, None);
+
+        let started = Instant::now();
+        throttled.write_all(&[0; 250]).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
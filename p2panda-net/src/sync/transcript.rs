@@ -0,0 +1,329 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Structured transcripts of sync sessions for debugging and auditing.
+//!
+//! When a `TranscriptSink` is configured via `SyncConfiguration::transcripts`, every sync session
+//! is recorded, including the messages which were exchanged, the number of bytes sent and
+//! received, timings and the final outcome. The transcript is handed over to the sink as soon as
+//! the session ended.
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::{AsyncRead, AsyncWrite};
+use p2panda_core::PublicKey;
+use web_time::{Instant, SystemTime};
+
+/// Role of the local node in a sync session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncRole {
+    /// We've initiated the session.
+    Initiator,
+
+    /// The remote peer initiated the session.
+    Acceptor,
+}
+
+/// Message passed from the sync protocol to the node during a session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TranscriptEntry<T> {
+    /// Handshake phase completed and the topic of the session is known.
+    HandshakeSuccess {
+        topic: T,
+
+        /// Time since the session started.
+        elapsed: Duration,
+    },
+
+    /// Application data was received from the remote peer.
+    Data {
+        /// Size of the header in bytes.
+        header_size: usize,
+
+        /// Size of the payload in bytes, zero if no payload was given.
+        payload_size: usize,
+
+        /// Time since the session started.
+        elapsed: Duration,
+    },
+}
+
+/// Outcome of a sync session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyncOutcome {
+    /// The session completed successfully.
+    Done,
+
+    /// The session failed with the given error.
+    Failed(String),
+}
+
+/// Structured record of a single sync session.
+#[derive(Clone, Debug)]
+pub struct SyncTranscript<T> {
+    /// Public key of the remote peer.
+    pub peer: PublicKey,
+
+    /// Role of the local node in this session.
+    pub role: SyncRole,
+
+    /// Name of the negotiated sync protocol.
+    pub protocol: &'static str,
+
+    /// Topic of the session, `None` if the handshake phase was never completed.
+    pub topic: Option<T>,
+
+    /// Time when the session started.
+    pub started_at: SystemTime,
+
+    /// Duration of the whole session.
+    pub duration: Duration,
+
+    /// Number of bytes sent to the remote peer.
+    pub bytes_sent: u64,
+
+    /// Number of bytes received from the remote peer.
+    pub bytes_received: u64,
+
+    /// Messages passed from the sync protocol to the node, in order of arrival.
+    pub entries: Vec<TranscriptEntry<T>>,
+
+    /// Outcome of the session.
+    pub outcome: SyncOutcome,
+}
+
+/// Receiver of sync session transcripts.
+///
+/// Implementations are called from within the sync tasks of the node and should not block, for
+/// example by forwarding transcripts into a channel or writing them to a log.
+pub trait TranscriptSink<T>: Debug + Send + Sync {
+    fn record(&self, transcript: SyncTranscript<T>);
+}
+
+/// Topic and entries of a transcript which are recorded while the session is running.
+type TranscriptState<T> = (Option<T>, Vec<TranscriptEntry<T>>);
+
+/// Records the transcript of a single sync session.
+#[derive(Clone, Debug)]
+pub(crate) struct TranscriptRecorder<T> {
+    sink: Arc<dyn TranscriptSink<T>>,
+    peer: PublicKey,
+    role: SyncRole,
+    protocol: &'static str,
+    started_at: SystemTime,
+    started: Instant,
+    bytes_sent: Arc<AtomicU64>,
+    bytes_received: Arc<AtomicU64>,
+    state: Arc<Mutex<TranscriptState<T>>>,
+}
+
+impl<T> TranscriptRecorder<T>
+where
+    T: Clone,
+{
+    pub fn new(
+        sink: Arc<dyn TranscriptSink<T>>,
+        peer: PublicKey,
+        role: SyncRole,
+        protocol: &'static str,
+    ) -> Self {
+        Self {
+            sink,
+            peer,
+            role,
+            protocol,
+            started_at: SystemTime::now(),
+            started: Instant::now(),
+            bytes_sent: Arc::new(AtomicU64::new(0)),
+            bytes_received: Arc::new(AtomicU64::new(0)),
+            state: Arc::new(Mutex::new((None, Vec::new()))),
+        }
+    }
+
+    /// Counter of bytes sent to the remote peer, to be used with `Counted`.
+    pub fn bytes_sent(&self) -> Arc<AtomicU64> {
+        self.bytes_sent.clone()
+    }
+
+    /// Counter of bytes received from the remote peer, to be used with `Counted`.
+    pub fn bytes_received(&self) -> Arc<AtomicU64> {
+        self.bytes_received.clone()
+    }
+
+    /// Records the successful completion of the handshake phase.
+    pub fn handshake_success(&self, topic: &T) {
+        let mut state = self.state.lock().expect("acquire lock on transcript");
+        state.0 = Some(topic.clone());
+        state.1.push(TranscriptEntry::HandshakeSuccess {
+            topic: topic.clone(),
+            elapsed: self.started.elapsed(),
+        });
+    }
+
+    /// Records application data received from the remote peer.
+    pub fn data(&self, header: &[u8], payload: Option<&Vec<u8>>) {
+        let mut state = self.state.lock().expect("acquire lock on transcript");
+        state.1.push(TranscriptEntry::Data {
+            header_size: header.len(),
+            payload_size: payload.map_or(0, Vec::len),
+            elapsed: self.started.elapsed(),
+        });
+    }
+
+    /// Completes the transcript with the outcome of the session and hands it over to the sink.
+    pub fn finish<E>(self, result: &Result<(), E>)
+    where
+        E: ToString,
+    {
+        let (topic, entries) = {
+            let mut state = self.state.lock().expect("acquire lock on transcript");
+            (state.0.take(), std::mem::take(&mut state.1))
+        };
+
+        let outcome = match result {
+            Ok(()) => SyncOutcome::Done,
+            Err(err) => SyncOutcome::Failed(err.to_string()),
+        };
+
+        self.sink.record(SyncTranscript {
+            peer: self.peer,
+            role: self.role,
+            protocol: self.protocol,
+            topic,
+            started_at: self.started_at,
+            duration: self.started.elapsed(),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            entries,
+            outcome,
+        });
+    }
+}
+
+/// Wrapper around a send or receive stream counting the transferred bytes, if a counter is given.
+pub(crate) struct Counted<'a, S> {
+    inner: &'a mut S,
+    counter: Option<Arc<AtomicU64>>,
+}
+
+impl<'a, S> Counted<'a, S> {
+    pub fn new(inner: &'a mut S, counter: Option<Arc<AtomicU64>>) -> Self {
+        Self { inner, counter }
+    }
+
+    fn count(&self, bytes: usize) {
+        if let Some(counter) = &self.counter {
+            counter.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<S> AsyncWrite for Counted<'_, S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(bytes)) = result {
+            self.count(bytes);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_close(cx)
+    }
+}
+
+impl<S> AsyncRead for Counted<'_, S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(bytes)) = result {
+            self.count(bytes);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures_util::{AsyncReadExt, AsyncWriteExt};
+    use p2panda_core::PrivateKey;
+
+    use super::{
+        Counted, SyncOutcome, SyncRole, SyncTranscript, TranscriptEntry, TranscriptRecorder,
+        TranscriptSink,
+    };
+
+    #[derive(Debug, Default)]
+    struct TestSink(Mutex<Vec<SyncTranscript<String>>>);
+
+    impl TranscriptSink<String> for TestSink {
+        fn record(&self, transcript: SyncTranscript<String>) {
+            self.0.lock().unwrap().push(transcript);
+        }
+    }
+
+    #[tokio::test]
+    async fn record_transcript() {
+        let sink = Arc::new(TestSink::default());
+        let peer = PrivateKey::new().public_key();
+        let recorder =
+            TranscriptRecorder::new(sink.clone(), peer, SyncRole::Initiator, "test-protocol");
+
+        let mut send = Vec::new();
+        let mut counted_send = Counted::new(&mut send, Some(recorder.bytes_sent()));
+        counted_send.write_all(&[1, 2, 3]).await.unwrap();
+
+        let mut recv: &[u8] = &[4, 5];
+        let mut counted_recv = Counted::new(&mut recv, Some(recorder.bytes_received()));
+        let mut buf = Vec::new();
+        counted_recv.read_to_end(&mut buf).await.unwrap();
+
+        recorder.handshake_success(&"chat".to_string());
+        recorder.data(&[0; 10], Some(&vec![0; 20]));
+        recorder.finish::<String>(&Ok(()));
+
+        let transcripts = sink.0.lock().unwrap();
+        assert_eq!(transcripts.len(), 1);
+
+        let transcript = &transcripts[0];
+        assert_eq!(transcript.peer, peer);
+        assert_eq!(transcript.protocol, "test-protocol");
+        assert_eq!(transcript.topic, Some("chat".to_string()));
+        assert_eq!(transcript.bytes_sent, 3);
+        assert_eq!(transcript.bytes_received, 2);
+        assert_eq!(transcript.outcome, SyncOutcome::Done);
+        assert!(matches!(
+            transcript.entries[..],
+            [
+                TranscriptEntry::HandshakeSuccess { .. },
+                TranscriptEntry::Data {
+                    header_size: 10,
+                    payload_size: 20,
+                    ..
+                }
+            ]
+        ));
+    }
+}
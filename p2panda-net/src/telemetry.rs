@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Structured tracing of sync sessions and the engine.
+//!
+//! Every sync session runs inside a `sync_session` span carrying the session id, the public key of
+//! the remote peer, the role of the local node and the negotiated sync protocol. The session id is
+//! derived from the TLS keying material of the QUIC connection, both peers compute the same id
+//! without exchanging any further messages, which allows correlating the logs of a session across
+//! two nodes. Engine handlers run inside spans carrying the topic id and peer they are handling.
+//!
+//! ## OpenTelemetry
+//!
+//! With the `opentelemetry` feature enabled, spans can be exported to an OpenTelemetry collector
+//! using the tracing layer returned by [`otlp_layer`]. The trace id of every `sync_session` span is
+//! derived from the session id, so the spans of both peers end up in the same distributed trace.
+//!
+//! ```rust,ignore
+//! use tracing_subscriber::prelude::*;
+//!
+//! let (layer, provider) = p2panda_net::telemetry::otlp_layer(
+//!     "my-app",
+//!     "http://localhost:4318/v1/traces",
+//! )?;
+//! tracing_subscriber::registry().with(layer).init();
+//!
+//! // .. run the node
+//!
+//! // Flush all remaining spans before exiting.
+//! provider.shutdown()?;
+//! ```
+use std::fmt;
+
+use iroh::endpoint::Connection;
+use p2panda_core::PublicKey;
+use rand::random;
+use tracing::{Span, field, info_span};
+
+use crate::sync::SyncRole;
+
+/// Label used to export keying material from the TLS session of a sync connection.
+const SESSION_ID_LABEL: &[u8] = b"p2panda-net sync session id";
+
+/// Identifier of a sync session, equal on both peers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct SessionId([u8; 16]);
+
+impl SessionId {
+    /// Derives the session id from the keying material of the connection.
+    ///
+    /// Falls back to a random id, which is only known to the local node, if the keying material
+    /// can not be exported.
+    pub(crate) fn from_connection(connection: &Connection) -> Self {
+        let mut bytes = [0; 16];
+        match connection.export_keying_material(&mut bytes, SESSION_ID_LABEL, &[]) {
+            Ok(()) => Self(bytes),
+            Err(_) => Self(random()),
+        }
+    }
+
+    #[cfg_attr(not(feature = "opentelemetry"), allow(dead_code))]
+    pub(crate) fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", HexId(&self.0))
+    }
+}
+
+/// Formats ids, like topic ids, as hexadecimal strings in span fields.
+pub(crate) struct HexId<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Display for HexId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the span a sync session over the given connection runs in.
+///
+/// The `protocol` field is recorded after the sync protocol was negotiated.
+pub(crate) fn sync_session_span(connection: &Connection, peer: PublicKey, role: SyncRole) -> Span {
+    let session_id = SessionId::from_connection(connection);
+    let role = match role {
+        SyncRole::Initiator => "initiator",
+        SyncRole::Acceptor => "acceptor",
+    };
+    let span = info_span!(
+        "sync_session",
+        session_id = %session_id,
+        %peer,
+        role,
+        connection_id = connection.stable_id() as u64,
+        protocol = field::Empty,
+    );
+
+    #[cfg(feature = "opentelemetry")]
+    otel::link_session(&span, &session_id);
+
+    span
+}
+
+#[cfg(feature = "opentelemetry")]
+pub use otel::otlp_layer;
+
+#[cfg(feature = "opentelemetry")]
+mod otel {
+    use anyhow::Result;
+    use opentelemetry::Context;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState, TracerProvider,
+    };
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+    use tracing::{Span, Subscriber};
+    use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+    use tracing_subscriber::registry::LookupSpan;
+
+    use super::SessionId;
+
+    /// Returns a tracing layer exporting spans via OTLP over HTTP to the given collector
+    /// endpoint, for example `http://localhost:4318/v1/traces`.
+    ///
+    /// Spans are exported in batches in the background. The returned provider needs to be shut
+    /// down before the application exits to flush remaining spans.
+    pub fn otlp_layer<S>(
+        service_name: &str,
+        endpoint: &str,
+    ) -> Result<(OpenTelemetryLayer<S, Tracer>, SdkTracerProvider)>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()?;
+
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(service_name.to_owned())
+                    .build(),
+            )
+            .build();
+
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("p2panda-net"));
+        Ok((layer, provider))
+    }
+
+    /// Sets a remote parent derived from the session id on the span.
+    ///
+    /// Both peers derive the same parent, placing their spans of the session in one trace.
+    pub(super) fn link_session(span: &Span, session_id: &SessionId) {
+        let bytes = session_id.as_bytes();
+        let mut span_id = [0; 8];
+        span_id.copy_from_slice(&bytes[..8]);
+
+        let parent = SpanContext::new(
+            TraceId::from_bytes(*bytes),
+            SpanId::from_bytes(span_id),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        span.set_parent(Context::new().with_remote_span_context(parent));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HexId, SessionId};
+
+    #[test]
+    fn format_ids() {
+        assert_eq!(HexId(&[0, 1, 171, 255]).to_string(), "0001abff");
+
+        let session_id = SessionId([7; 16]);
+        assert_eq!(session_id.to_string(), "07".repeat(16));
+    }
+}
@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Authentication of peers joining private topics.
+//!
+//! By default every peer can join the gossip overlay of any topic and sync its data. Applications
+//! can restrict this by handing a [`TopicAuthenticator`] to `NetworkBuilder::topic_authenticator`
+//! which marks topic ids as private. Peers need to present a proof to join a private topic, for
+//! example a signature of the topic owner or a group membership proof:
+//!
+//! 1. Proofs are attached to the signed "topic discovery" announcements. Peers announcing a
+//!    private topic without a valid proof are ignored for that topic, we don't join a gossip
+//!    overlay or start sync sessions with them.
+//! 2. Both sides of a sync session exchange their proofs for the private topics of the session
+//!    before any data flows. The initiator aborts the session if the acceptor can't prove access,
+//!    the acceptor rejects the handshake of every private topic the initiator did not prove.
+//! 3. Gossip messages on private topics are signed by their author and carry the author's proof.
+//!    Messages are forwarded by other peers of the overlay, they are only accepted if the
+//!    signature is valid and the proof allows the author to join the topic, no matter who
+//!    delivered them.
+//! 4. Gossip neighbours of a private topic which did not announce a valid proof in time are
+//!    disconnected by leaving the overlay and joining it again through authorised peers only.
+//!
+//! Topic ids are still announced to the whole network and proofs are bound to the public key of
+//! the peer presenting them. Authentication does not encrypt any data, applications should
+//! additionally encrypt the content of private topics.
+//!
+//! [`TopicOwners`] implements proofs signed by the key of a topic owner:
+//!
+//! ```rust
+//! use p2panda_core::PrivateKey;
+//! use p2panda_net::{TopicOwners, sign_subscribe_proof};
+//!
+//! let topic_id = [1; 32];
+//! let owner = PrivateKey::new();
+//! let member = PrivateKey::new();
+//!
+//! // The owner hands out a proof to every member of the topic ..
+//! let proof = sign_subscribe_proof(&owner, &topic_id, &member.public_key());
+//!
+//! // .. which the member presents to other peers of the topic.
+//! let authenticator = TopicOwners::new()
+//!     .private_topic(topic_id, owner.public_key())
+//!     .own_proof(topic_id, proof);
+//! ```
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use anyhow::{Result, bail};
+use p2panda_core::{Hash, PrivateKey, PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+
+use crate::bytes::FromBytes;
+
+/// Domain separation of subscribe proof signatures.
+const SUBSCRIBE_PROOF_CONTEXT: &[u8] = b"p2panda-net subscribe proof";
+
+/// Domain separation of private topic message signatures.
+const PRIVATE_MESSAGE_CONTEXT: &[u8] = b"p2panda-net private message";
+
+/// Decides which topics are private and verifies the proofs of peers joining them.
+pub trait TopicAuthenticator: Debug + Send + Sync + 'static {
+    /// Returns `true` if peers need to present a proof to join the topic.
+    fn is_private(&self, topic_id: &[u8; 32]) -> bool;
+
+    /// Returns our own proof for joining the topic, `None` if we don't have one.
+    fn proof(&self, topic_id: &[u8; 32]) -> Option<Vec<u8>>;
+
+    /// Returns `true` if the proof allows the peer to join the topic.
+    fn verify(&self, topic_id: &[u8; 32], peer: &PublicKey, proof: &[u8]) -> bool;
+}
+
+/// Private topics with members authorised by the signature of a topic owner.
+///
+/// Proofs are created by the owner with [`sign_subscribe_proof`], the owner needs to sign a proof
+/// for its own public key as well to join the topic.
+#[derive(Clone, Debug, Default)]
+pub struct TopicOwners {
+    owners: HashMap<[u8; 32], PublicKey>,
+    proofs: HashMap<[u8; 32], Signature>,
+}
+
+impl TopicOwners {
+    /// Returns an authenticator without any private topics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks the topic id as private, owned by the given public key.
+    pub fn private_topic(mut self, topic_id: [u8; 32], owner: PublicKey) -> Self {
+        self.owners.insert(topic_id, owner);
+        self
+    }
+
+    /// Sets our own proof for joining the topic, signed by its owner.
+    pub fn own_proof(mut self, topic_id: [u8; 32], proof: Signature) -> Self {
+        self.proofs.insert(topic_id, proof);
+        self
+    }
+}
+
+impl TopicAuthenticator for TopicOwners {
+    fn is_private(&self, topic_id: &[u8; 32]) -> bool {
+        self.owners.contains_key(topic_id)
+    }
+
+    fn proof(&self, topic_id: &[u8; 32]) -> Option<Vec<u8>> {
+        self.proofs
+            .get(topic_id)
+            .map(|proof| proof.to_bytes().to_vec())
+    }
+
+    fn verify(&self, topic_id: &[u8; 32], peer: &PublicKey, proof: &[u8]) -> bool {
+        let Some(owner) = self.owners.get(topic_id) else {
+            return false;
+        };
+        let Ok(signature) = Signature::try_from(proof) else {
+            return false;
+        };
+        owner.verify(&subscribe_proof_bytes(topic_id, peer), &signature)
+    }
+}
+
+/// Signs a proof allowing the peer to join the topic, to be verified with [`TopicOwners`].
+pub fn sign_subscribe_proof(
+    owner: &PrivateKey,
+    topic_id: &[u8; 32],
+    peer: &PublicKey,
+) -> Signature {
+    owner.sign(&subscribe_proof_bytes(topic_id, peer))
+}
+
+/// Bytes covered by the signature of a subscribe proof.
+fn subscribe_proof_bytes(topic_id: &[u8; 32], peer: &PublicKey) -> Vec<u8> {
+    [SUBSCRIBE_PROOF_CONTEXT, topic_id, peer.as_bytes()].concat()
+}
+
+/// Gossip message on a private topic, signed by its author.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PrivateTopicMessage {
+    pub author: PublicKey,
+    pub proof: Vec<u8>,
+    pub bytes: Vec<u8>,
+    signature: Signature,
+}
+
+impl PrivateTopicMessage {
+    /// Returns the message to broadcast on the topic, signed with our private key.
+    pub fn new(
+        private_key: &PrivateKey,
+        topic_id: &[u8; 32],
+        proof: Vec<u8>,
+        bytes: Vec<u8>,
+    ) -> Self {
+        let author = private_key.public_key();
+        let signature = private_key.sign(&private_message_bytes(topic_id, &author, &proof, &bytes));
+        Self {
+            author,
+            proof,
+            bytes,
+            signature,
+        }
+    }
+
+    /// Parses a message and checks that it was signed by an author with access to the topic.
+    pub fn decode(
+        authenticator: &dyn TopicAuthenticator,
+        topic_id: &[u8; 32],
+        bytes: &[u8],
+    ) -> Result<Self> {
+        let message = Self::from_bytes(bytes)?;
+        if !message.author.verify(
+            &private_message_bytes(topic_id, &message.author, &message.proof, &message.bytes),
+            &message.signature,
+        ) {
+            bail!("invalid private topic message signature");
+        }
+        if !authenticator.verify(topic_id, &message.author, &message.proof) {
+            bail!(
+                "author {} can't prove access to private topic",
+                message.author
+            );
+        }
+        Ok(message)
+    }
+}
+
+/// Bytes covered by the signature of a private topic message.
+fn private_message_bytes(
+    topic_id: &[u8; 32],
+    author: &PublicKey,
+    proof: &[u8],
+    bytes: &[u8],
+) -> Vec<u8> {
+    [
+        PRIVATE_MESSAGE_CONTEXT,
+        topic_id,
+        author.as_bytes(),
+        Hash::new(proof).as_bytes(),
+        Hash::new(bytes).as_bytes(),
+    ]
+    .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::PrivateKey;
+
+    use crate::bytes::ToBytes;
+
+    use super::{PrivateTopicMessage, TopicAuthenticator, TopicOwners, sign_subscribe_proof};
+
+    #[test]
+    fn verify_owner_signed_proofs() {
+        let topic_id = [1; 32];
+        let owner = PrivateKey::new();
+        let member = PrivateKey::new().public_key();
+        let stranger = PrivateKey::new().public_key();
+
+        let proof = sign_subscribe_proof(&owner, &topic_id, &member);
+        let authenticator = TopicOwners::new()
+            .private_topic(topic_id, owner.public_key())
+            .own_proof(topic_id, proof);
+
+        assert!(authenticator.is_private(&topic_id));
+        assert!(!authenticator.is_private(&[2; 32]));
+        assert_eq!(
+            authenticator.proof(&topic_id),
+            Some(proof.to_bytes().to_vec())
+        );
+
+        let proof = proof.to_bytes();
+        assert!(authenticator.verify(&topic_id, &member, &proof));
+
+        // Proofs are bound to the peer and topic they were signed for.
+        assert!(!authenticator.verify(&topic_id, &stranger, &proof));
+        assert!(!authenticator.verify(&[2; 32], &member, &proof));
+
+        // Proofs need to be signed by the owner.
+        let forged = sign_subscribe_proof(&PrivateKey::new(), &topic_id, &stranger);
+        assert!(!authenticator.verify(&topic_id, &stranger, &forged.to_bytes()));
+        assert!(!authenticator.verify(&topic_id, &member, b"invalid"));
+    }
+
+    #[test]
+    fn verify_private_topic_messages() {
+        let topic_id = [1; 32];
+        let owner = PrivateKey::new();
+        let member = PrivateKey::new();
+        let stranger = PrivateKey::new();
+
+        let proof = sign_subscribe_proof(&owner, &topic_id, &member.public_key());
+        let authenticator = TopicOwners::new().private_topic(topic_id, owner.public_key());
+
+        let message = PrivateTopicMessage::new(
+            &member,
+            &topic_id,
+            proof.to_bytes().to_vec(),
+            b"hi".to_vec(),
+        );
+        let decoded =
+            PrivateTopicMessage::decode(&authenticator, &topic_id, &message.to_bytes()).unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(decoded.author, member.public_key());
+
+        // Messages are bound to the topic they were signed for.
+        assert!(
+            PrivateTopicMessage::decode(&authenticator, &[2; 32], &message.to_bytes()).is_err()
+        );
+
+        // Peers can't present the proof of another author.
+        let forged = PrivateTopicMessage::new(
+            &stranger,
+            &topic_id,
+            proof.to_bytes().to_vec(),
+            b"hi".to_vec(),
+        );
+        assert!(
+            PrivateTopicMessage::decode(&authenticator, &topic_id, &forged.to_bytes()).is_err()
+        );
+
+        // Forwarding peers can't change the message.
+        let mut tampered = message.clone();
+        tampered.bytes = b"bye".to_vec();
+        assert!(
+            PrivateTopicMessage::decode(&authenticator, &topic_id, &tampered.to_bytes()).is_err()
+        );
+
+        // Unsigned messages are rejected.
+        assert!(PrivateTopicMessage::decode(&authenticator, &topic_id, b"hi").is_err());
+    }
+}
@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Hierarchical topics, with child topics sharing the gossip overlay of their parent.
+//!
+//! Applications with many small channels, for example one per chat room of a community, would
+//! otherwise need to join one gossip overlay per channel. Instead, channels can be expressed as
+//! child topics of a parent topic, with ids derived from the parent id by
+//! [`derive_child_topic_id`] and the parent id returned by `TopicId::parent_id`:
+//!
+//! 1. All child topics of a parent share one gossip overlay. Messages are tagged with the id of
+//!    their child topic and only delivered to its subscribers.
+//! 2. Child topics are announced together with their parent during "topic discovery". Nodes
+//!    subscribed to the parent topic learn about all child topics in use, they are listed by
+//!    `Network::child_topics` and reported as `SystemEvent::ChildTopicDiscovered` events.
+//! 3. Sync sessions still take place per child topic, only peers interested in the same child
+//!    topic sync its data.
+//!
+//! ```rust
+//! use p2panda_net::derive_child_topic_id;
+//!
+//! let community = [1; 32];
+//! let general = derive_child_topic_id(&community, b"general");
+//! let random = derive_child_topic_id(&community, b"random");
+//! assert_ne!(general, random);
+//! ```
+use std::collections::{BTreeSet, HashMap};
+
+use p2panda_core::Hash;
+use serde::{Deserialize, Serialize};
+
+/// Domain separation of derived child topic ids.
+const CHILD_TOPIC_CONTEXT: &[u8] = b"p2panda-net child topic";
+
+/// Domain separation of the gossip overlay shared by all child topics of a parent.
+const CHILD_OVERLAY_CONTEXT: &[u8] = b"p2panda-net child overlay";
+
+/// Maximum number of child topics tracked per parent topic.
+pub const MAX_CHILD_TOPICS: usize = 1024;
+
+/// Derives the id of a named child topic from the id of its parent.
+pub fn derive_child_topic_id(parent_id: &[u8; 32], name: &[u8]) -> [u8; 32] {
+    *Hash::new([CHILD_TOPIC_CONTEXT, parent_id, name].concat()).as_bytes()
+}
+
+/// Returns the id of the gossip overlay shared by all child topics of the parent.
+///
+/// The overlay is distinct from the overlay of the parent topic itself, every message sent on it
+/// is a [`ChildTopicMessage`].
+pub(crate) fn child_overlay_id(parent_id: &[u8; 32]) -> [u8; 32] {
+    *Hash::new([CHILD_OVERLAY_CONTEXT, parent_id].concat()).as_bytes()
+}
+
+/// Gossip message on the shared overlay of child topics, tagged with the child topic id.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ChildTopicMessage {
+    pub topic_id: [u8; 32],
+    pub bytes: Vec<u8>,
+}
+
+/// Child topics announced by other peers, per parent topic id.
+#[derive(Debug, Default)]
+pub(crate) struct ChildTopics {
+    parents: HashMap<[u8; 32], BTreeSet<[u8; 32]>>,
+}
+
+impl ChildTopics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an announced child topic, returns `true` if it was not known before.
+    ///
+    /// Children beyond `MAX_CHILD_TOPICS` per parent are ignored.
+    pub fn insert(&mut self, parent_id: [u8; 32], topic_id: [u8; 32]) -> bool {
+        let children = self.parents.entry(parent_id).or_default();
+        if children.len() >= MAX_CHILD_TOPICS {
+            return false;
+        }
+        children.insert(topic_id)
+    }
+
+    /// Returns all known child topic ids of the parent.
+    pub fn children(&self, parent_id: &[u8; 32]) -> Vec<[u8; 32]> {
+        self.parents
+            .get(parent_id)
+            .map(|children| children.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChildTopics, MAX_CHILD_TOPICS, child_overlay_id, derive_child_topic_id};
+
+    #[test]
+    fn derive_ids() {
+        let parent_id = [1; 32];
+        let child_id = derive_child_topic_id(&parent_id, b"general");
+        assert_eq!(child_id, derive_child_topic_id(&parent_id, b"general"));
+        assert_ne!(child_id, derive_child_topic_id(&parent_id, b"random"));
+        assert_ne!(child_id, derive_child_topic_id(&[2; 32], b"general"));
+
+        // The shared overlay doesn't collide with the parent or any of its children.
+        let overlay_id = child_overlay_id(&parent_id);
+        assert_ne!(overlay_id, parent_id);
+        assert_ne!(overlay_id, child_id);
+    }
+
+    #[test]
+    fn track_child_topics() {
+        let parent_id = [1; 32];
+        let mut child_topics = ChildTopics::new();
+        assert!(child_topics.insert(parent_id, [3; 32]));
+        assert!(child_topics.insert(parent_id, [2; 32]));
+        assert!(!child_topics.insert(parent_id, [2; 32]));
+        assert_eq!(child_topics.children(&parent_id), vec![[2; 32], [3; 32]]);
+        assert!(child_topics.children(&[2; 32]).is_empty());
+
+        for i in 0..MAX_CHILD_TOPICS {
+            child_topics.insert([4; 32], derive_child_topic_id(&[4; 32], &i.to_be_bytes()));
+        }
+        assert!(!child_topics.insert([4; 32], [5; 32]));
+        assert_eq!(child_topics.children(&[4; 32]).len(), MAX_CHILD_TOPICS);
+    }
+}
@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Transports carrying the connections between peers.
+//!
+//! Gossip overlays and sync sessions run on top of QUIC connections managed by iroh and are
+//! independent of the transport underneath. Native nodes send QUIC packets over UDP, with direct
+//! connections established via holepunching and the relay as a fallback. Browsers can not open
+//! UDP sockets: nodes compiled to WebAssembly tunnel all packets through a relay server over a
+//! WebSocket instead.
+//!
+//! Relay-only nodes join the same networks as native peers, as long as both use the same relay.
+//! Native nodes can use the relay-only transport as well, for example in networks which block UDP
+//! traffic. Their QUIC socket is only bound to the loopback interface then, so no direct paths to
+//! other peers can be discovered or holepunched, and direct addresses of peers are ignored.
+use serde::{Deserialize, Serialize};
+
+use crate::NodeAddress;
+
+/// Transport used by a node to reach other peers, see `NetworkBuilder::transport`.
+///
+/// More transports, like WebRTC data channels for direct connections between browsers, might be
+/// added in the future.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Transport {
+    /// QUIC over UDP with direct connections between peers, falling back to the relay when a
+    /// direct connection can't be established.
+    ///
+    /// This is the default on native platforms.
+    Direct,
+
+    /// All connections are relayed over a WebSocket to the relay server.
+    ///
+    /// This is the default and only supported transport when compiling to WebAssembly. A relay
+    /// needs to be configured with `NetworkBuilder::relay`.
+    ///
+    /// Native nodes bind their socket to the loopback interface and never connect to peers
+    /// directly.
+    RelayOnly,
+}
+
+impl Transport {
+    /// Returns `true` if peers are reached through their direct addresses.
+    pub fn is_direct(&self) -> bool {
+        matches!(self, Self::Direct)
+    }
+
+    /// Returns `true` if the transport can't reach any peer without a relay.
+    pub fn requires_relay(&self) -> bool {
+        matches!(self, Self::RelayOnly)
+    }
+
+    /// Removes the addresses of a peer which can't be used with this transport.
+    pub(crate) fn peer_address(&self, mut node_addr: NodeAddress) -> NodeAddress {
+        if !self.is_direct() {
+            node_addr.direct_addresses.clear();
+        }
+        node_addr
+    }
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        if cfg!(target_arch = "wasm32") {
+            Self::RelayOnly
+        } else {
+            Self::Direct
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::PrivateKey;
+
+    use crate::NodeAddress;
+
+    use super::Transport;
+
+    #[test]
+    fn peer_address() {
+        let node_addr = NodeAddress {
+            public_key: PrivateKey::new().public_key(),
+            direct_addresses: vec!["192.168.1.5:2022".parse().unwrap()],
+            relay_url: Some("https://example.net".parse().unwrap()),
+        };
+
+        assert_eq!(Transport::Direct.peer_address(node_addr.clone()), node_addr);
+
+        let relayed = Transport::RelayOnly.peer_address(node_addr.clone());
+        assert!(relayed.direct_addresses.is_empty());
+        assert_eq!(relayed.relay_url, node_addr.relay_url);
+    }
+}
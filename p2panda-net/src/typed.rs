@@ -0,0 +1,211 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Typed gossip messages, encoded as CBOR.
+//!
+//! `Network::subscribe` sends and receives raw bytes, leaving encoding to the application.
+//! `Network::subscribe_typed` wraps both ends of a subscription instead: messages of type `M` are
+//! encoded before they are broadcast and gossip messages are decoded into `M` again when they
+//! arrive. Messages which can't be decoded, for example sent by peers running an incompatible
+//! version of the application, are reported as `TypedFromNetwork::DecodeError`.
+//!
+//! Operations received via sync are handed over unchanged, they are usually decoded by the
+//! ingest stream of `p2panda-stream`.
+use std::fmt;
+use std::marker::PhantomData;
+
+use anyhow::Result;
+use p2panda_core::PublicKey;
+use p2panda_core::cbor::{decode_cbor, encode_cbor};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc;
+
+use crate::network::{FromNetwork, ToNetwork};
+
+/// Sender of typed messages to all peers subscribed to a topic.
+pub struct TypedSender<M> {
+    tx: mpsc::Sender<ToNetwork>,
+    _marker: PhantomData<fn(M)>,
+}
+
+impl<M> TypedSender<M>
+where
+    M: Serialize,
+{
+    pub(crate) fn new(tx: mpsc::Sender<ToNetwork>) -> Self {
+        Self {
+            tx,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Encodes the message and broadcasts it to the topic.
+    pub async fn send(&self, message: &M) -> Result<()> {
+        let bytes = encode_cbor(message)?;
+        self.tx.send(ToNetwork::Message { bytes }).await?;
+        Ok(())
+    }
+}
+
+impl<M> Clone for TypedSender<M> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<M> fmt::Debug for TypedSender<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedSender").finish_non_exhaustive()
+    }
+}
+
+/// A typed event received from the network.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TypedFromNetwork<M> {
+    /// Decoded gossip message.
+    Message {
+        message: M,
+        delivered_from: PublicKey,
+    },
+
+    /// Operation received via sync, handed over unchanged.
+    SyncMessage {
+        header: Vec<u8>,
+        payload: Option<Vec<u8>>,
+        delivered_from: PublicKey,
+    },
+
+    /// Gossip message which could not be decoded into `M`.
+    DecodeError {
+        bytes: Vec<u8>,
+        error: String,
+        delivered_from: PublicKey,
+    },
+}
+
+impl<M> From<FromNetwork> for TypedFromNetwork<M>
+where
+    M: DeserializeOwned,
+{
+    fn from(event: FromNetwork) -> Self {
+        match event {
+            FromNetwork::GossipMessage {
+                bytes,
+                delivered_from,
+            } => match decode_cbor(&bytes[..]) {
+                Ok(message) => TypedFromNetwork::Message {
+                    message,
+                    delivered_from,
+                },
+                Err(err) => TypedFromNetwork::DecodeError {
+                    bytes,
+                    error: err.to_string(),
+                    delivered_from,
+                },
+            },
+            FromNetwork::SyncMessage {
+                header,
+                payload,
+                delivered_from,
+            } => TypedFromNetwork::SyncMessage {
+                header,
+                payload,
+                delivered_from,
+            },
+        }
+    }
+}
+
+/// Receiver of typed events from a subscribed topic.
+pub struct TypedReceiver<M> {
+    rx: mpsc::Receiver<FromNetwork>,
+    _marker: PhantomData<fn() -> M>,
+}
+
+impl<M> TypedReceiver<M>
+where
+    M: DeserializeOwned,
+{
+    pub(crate) fn new(rx: mpsc::Receiver<FromNetwork>) -> Self {
+        Self {
+            rx,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Receives the next event, `None` if the subscription was closed.
+    pub async fn recv(&mut self) -> Option<TypedFromNetwork<M>> {
+        self.rx.recv().await.map(TypedFromNetwork::from)
+    }
+}
+
+impl<M> fmt::Debug for TypedReceiver<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedReceiver").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::PrivateKey;
+    use serde::{Deserialize, Serialize};
+    use tokio::sync::mpsc;
+
+    use crate::network::{FromNetwork, ToNetwork};
+
+    use super::{TypedFromNetwork, TypedReceiver, TypedSender};
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    struct ChatMessage {
+        text: String,
+    }
+
+    #[tokio::test]
+    async fn encode_and_decode_messages() {
+        let delivered_from = PrivateKey::new().public_key();
+        let message = ChatMessage {
+            text: "Hello, Panda!".into(),
+        };
+
+        let (to_network_tx, mut to_network_rx) = mpsc::channel(8);
+        let sender = TypedSender::<ChatMessage>::new(to_network_tx);
+        sender.send(&message).await.unwrap();
+        let ToNetwork::Message { bytes } = to_network_rx.recv().await.unwrap();
+
+        let (from_network_tx, from_network_rx) = mpsc::channel(8);
+        let mut receiver = TypedReceiver::<ChatMessage>::new(from_network_rx);
+        from_network_tx
+            .send(FromNetwork::GossipMessage {
+                bytes,
+                delivered_from,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            receiver.recv().await,
+            Some(TypedFromNetwork::Message {
+                message,
+                delivered_from,
+            })
+        );
+
+        // Messages of another type are reported as decode errors.
+        from_network_tx
+            .send(FromNetwork::GossipMessage {
+                bytes: vec![1, 2, 3],
+                delivered_from,
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            receiver.recv().await,
+            Some(TypedFromNetwork::DecodeError { bytes, .. }) if bytes == vec![1, 2, 3]
+        ));
+
+        drop(from_network_tx);
+        assert_eq!(receiver.recv().await, None);
+    }
+}
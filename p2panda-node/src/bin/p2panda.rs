@@ -0,0 +1,420 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Command line tool to inspect p2panda nodes and debug operations.
+//!
+//! `cargo run -p p2panda-node --features cli --bin p2panda -- --help`
+//!
+//! # Examples
+//!
+//! Generate a private key and a ticket other peers can use to join the network through this node:
+//!
+//! `p2panda keygen`
+//!
+//! `p2panda ticket --private-key <PRIVATE_KEY> --relay https://relay.example.org`
+//!
+//! Start a relay-only node which forwards gossip of the given topics:
+//!
+//! `p2panda node --relay https://relay.example.org --relay-only --topic my_chat`
+//!
+//! Print all gossip messages of a topic:
+//!
+//! `p2panda tail my_chat --mdns`
+//!
+//! Decode a hex-encoded operation header or query the store of a node:
+//!
+//! `p2panda decode <HEX>`
+//!
+//! `p2panda query --data-dir ./data --topic my_chat`
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use clap::{Args, Parser, Subcommand};
+use p2panda_core::cbor::{decode_cbor, encode_cbor};
+use p2panda_core::{Body, Hash, Header, PrivateKey, PublicKey, validate_header};
+use p2panda_discovery::mdns::LocalDiscovery;
+use p2panda_net::network::FromNetwork;
+use p2panda_net::{Network, NetworkBuilder, NodeAddress, RelayUrl, TopicId, Transport};
+use p2panda_node::{DEFAULT_MAX_CONNECTIONS, DEFAULT_STUN_PORT, log_id, open_store};
+use p2panda_store::QueryStore;
+use p2panda_store::query::OperationQuery;
+use p2panda_sync::TopicQuery;
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::prelude::*;
+
+#[derive(Parser)]
+#[command(
+    name = "p2panda",
+    version,
+    about = "Inspect p2panda nodes and debug operations"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a new private key.
+    Keygen,
+
+    /// Create a ticket other peers can use to connect to a node.
+    Ticket {
+        /// Hex-encoded private key of the node.
+        #[arg(long)]
+        private_key: String,
+
+        /// Relay the node is reachable through.
+        #[arg(long)]
+        relay: Option<RelayUrl>,
+
+        /// Direct address the node is reachable at, can be given multiple times.
+        #[arg(long)]
+        address: Vec<std::net::SocketAddr>,
+    },
+
+    /// Decode and print an operation header.
+    Decode {
+        /// Hex-encoded header, reads raw CBOR from `--file` when not given.
+        hex: Option<String>,
+
+        /// File containing the CBOR-encoded header.
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Query the operations in the store of a node.
+    Query {
+        /// Data directory of the node.
+        #[arg(long)]
+        data_dir: PathBuf,
+
+        /// Only show operations of this topic.
+        #[arg(long)]
+        topic: Option<String>,
+
+        /// Only show operations of this author.
+        #[arg(long)]
+        author: Option<PublicKey>,
+
+        /// Maximum number of operations to show.
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+    },
+
+    /// Start a node and keep it running until it is stopped.
+    Node {
+        #[command(flatten)]
+        network: NetworkArgs,
+
+        /// Topic to join and forward gossip for, can be given multiple times.
+        #[arg(long)]
+        topic: Vec<String>,
+    },
+
+    /// Print all gossip and sync messages of a topic.
+    Tail {
+        /// Name of the topic.
+        topic: String,
+
+        #[command(flatten)]
+        network: NetworkArgs,
+    },
+}
+
+#[derive(Args)]
+struct NetworkArgs {
+    /// Name of the network to join.
+    #[arg(long, default_value = "p2panda")]
+    network: String,
+
+    /// Hex-encoded private key, a random key is used when not given.
+    #[arg(long)]
+    private_key: Option<String>,
+
+    /// Relay to connect to.
+    #[arg(long)]
+    relay: Option<RelayUrl>,
+
+    /// Connect to other peers only via the relay.
+    #[arg(long, requires = "relay")]
+    relay_only: bool,
+
+    /// Discover peers on the local network via mDNS.
+    #[arg(long)]
+    mdns: bool,
+
+    /// Ticket of a peer to connect to, can be given multiple times.
+    #[arg(long)]
+    ticket: Vec<String>,
+}
+
+/// Topic identified by the hash of its name.
+#[derive(Clone, Debug, PartialEq, Eq, std::hash::Hash, Serialize, Deserialize)]
+struct CliTopic(Hash);
+
+impl CliTopic {
+    fn new(name: &str) -> Self {
+        Self(Hash::new(name.as_bytes()))
+    }
+}
+
+impl TopicQuery for CliTopic {}
+
+impl TopicId for CliTopic {
+    fn id(&self) -> [u8; 32] {
+        self.0.into()
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(EnvFilter::from_default_env())
+        .try_init()
+        .ok();
+
+    match Cli::parse().command {
+        Command::Keygen => {
+            let private_key = PrivateKey::new();
+            println!("private key:\t{}", private_key.to_hex());
+            println!("public key:\t{}", private_key.public_key());
+        }
+        Command::Ticket {
+            private_key,
+            relay,
+            address,
+        } => {
+            let private_key = parse_private_key(&private_key)?;
+            let ticket = encode_ticket(&NodeAddress {
+                public_key: private_key.public_key(),
+                direct_addresses: address,
+                relay_url: relay,
+            })?;
+            println!("{ticket}");
+        }
+        Command::Decode { hex, file } => {
+            let bytes = match (hex, file) {
+                (Some(hex), None) => hex::decode(hex.trim()).context("invalid hex")?,
+                (None, Some(file)) => tokio::fs::read(file).await?,
+                _ => bail!("either a hex-encoded header or --file needs to be given"),
+            };
+            print_header(&bytes)?;
+        }
+        Command::Query {
+            data_dir,
+            topic,
+            author,
+            limit,
+        } => {
+            if !tokio::fs::try_exists(&data_dir).await? {
+                bail!("data directory {} does not exist", data_dir.display());
+            }
+
+            let store = open_store(&data_dir, DEFAULT_MAX_CONNECTIONS).await?;
+            let mut query = OperationQuery::new().limit(limit);
+            if let Some(topic) = topic {
+                query = query.log_ids([log_id(&CliTopic::new(&topic))]);
+            }
+            if let Some(author) = author {
+                query = query.author(author);
+            }
+
+            let page = store.query(&query).await?;
+            for (header, body) in page.operations {
+                println!(
+                    "{}\t{}\tseq_num={}\ttimestamp={}\tpayload={}",
+                    header.hash(),
+                    header.public_key,
+                    header.seq_num,
+                    header.timestamp,
+                    format_payload(header.payload_size, body.as_ref()),
+                );
+            }
+            if page.next.is_some() {
+                println!("... more operations available, increase --limit to show them");
+            }
+        }
+        Command::Node { network, topic } => {
+            let network = spawn_network(network).await?;
+            for name in topic {
+                let (tx, mut rx, _ready) = network.subscribe(CliTopic::new(&name)).await?;
+                tokio::spawn(async move {
+                    // Keep the subscription alive, the node forwards gossip of the topic.
+                    let _tx = tx;
+                    while rx.recv().await.is_some() {}
+                });
+                println!("joined topic:\t{name}");
+            }
+
+            tokio::signal::ctrl_c().await?;
+            network.shutdown().await?;
+        }
+        Command::Tail { topic, network } => {
+            let network = spawn_network(network).await?;
+            let (_tx, mut rx, _ready) = network.subscribe(CliTopic::new(&topic)).await?;
+
+            loop {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => break,
+                    Some(event) = rx.recv() => print_event(event),
+                }
+            }
+
+            network.shutdown().await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn spawn_network(args: NetworkArgs) -> Result<Network<CliTopic>> {
+    let private_key = match args.private_key {
+        Some(private_key) => parse_private_key(&private_key)?,
+        None => PrivateKey::new(),
+    };
+
+    let mut builder = NetworkBuilder::<CliTopic>::new(Hash::new(args.network.as_bytes()).into())
+        .private_key(private_key);
+
+    if let Some(relay) = args.relay {
+        let port = relay.port().unwrap_or(DEFAULT_STUN_PORT);
+        builder = builder.relay(relay, false, port);
+    }
+
+    if args.relay_only {
+        builder = builder.transport(Transport::RelayOnly);
+    }
+
+    if args.mdns {
+        builder = builder.discovery(LocalDiscovery::new());
+    }
+
+    for ticket in &args.ticket {
+        let address = decode_ticket(ticket)?;
+        builder = builder.direct_address(
+            address.public_key,
+            address.direct_addresses,
+            address.relay_url,
+        );
+    }
+
+    let network = builder.build().await?;
+
+    let mut address = NodeAddress {
+        public_key: network.node_id(),
+        direct_addresses: network.direct_addresses().await.unwrap_or_default(),
+        relay_url: None,
+    };
+    if let Some(relay) = network.endpoint().home_relay().get().ok().flatten() {
+        address.relay_url = relay.to_string().parse().ok();
+    }
+
+    println!("node id:\t{}", network.node_id());
+    println!("ticket:\t\t{}", encode_ticket(&address)?);
+
+    Ok(network)
+}
+
+fn parse_private_key(value: &str) -> Result<PrivateKey> {
+    let bytes = hex::decode(value.trim()).context("invalid hex")?;
+    PrivateKey::try_from(&bytes[..]).context("invalid private key")
+}
+
+/// Tickets are hex-encoded node addresses.
+fn encode_ticket(address: &NodeAddress) -> Result<String> {
+    Ok(hex::encode(encode_cbor(address)?))
+}
+
+fn decode_ticket(ticket: &str) -> Result<NodeAddress> {
+    let bytes = hex::decode(ticket.trim()).context("invalid ticket")?;
+    decode_cbor(&bytes[..]).context("invalid ticket")
+}
+
+fn print_header(bytes: &[u8]) -> Result<()> {
+    // Extensions are application-specific, print them as generic CBOR values.
+    let header: Header<ciborium::Value> = decode_cbor(bytes).context("invalid header")?;
+
+    println!("hash:\t\t{}", Hash::new(bytes));
+    println!("version:\t{}", header.version);
+    println!("public key:\t{}", header.public_key);
+    match &header.signature {
+        Some(signature) => println!(
+            "signature:\t{signature} ({})",
+            match validate_header(&header) {
+                Ok(()) => "valid".to_string(),
+                Err(err) => format!("invalid: {err}"),
+            }
+        ),
+        None => println!("signature:\t-"),
+    }
+    println!("payload size:\t{}", header.payload_size);
+    println!("payload hash:\t{}", format_hash(header.payload_hash));
+    println!("timestamp:\t{}", header.timestamp);
+    println!("seq num:\t{}", header.seq_num);
+    println!("backlink:\t{}", format_hash(header.backlink));
+    println!(
+        "previous:\t{}",
+        if header.previous.is_empty() {
+            "-".to_string()
+        } else {
+            header
+                .previous
+                .iter()
+                .map(Hash::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    );
+    match &header.extensions {
+        Some(extensions) => println!("extensions:\t{extensions:?}"),
+        None => println!("extensions:\t-"),
+    }
+
+    Ok(())
+}
+
+fn print_event(event: FromNetwork) {
+    match event {
+        FromNetwork::GossipMessage {
+            bytes,
+            delivered_from,
+        } => {
+            println!("gossip from {delivered_from} ({} bytes)", bytes.len());
+            match decode_cbor::<ciborium::Value, _>(&bytes[..]) {
+                Ok(value) => println!("\t{value:?}"),
+                Err(_) => println!("\t{}", hex::encode(&bytes)),
+            }
+        }
+        FromNetwork::SyncMessage {
+            header,
+            payload,
+            delivered_from,
+        } => {
+            println!(
+                "sync from {delivered_from} ({} header bytes, {} payload bytes)",
+                header.len(),
+                payload.as_ref().map_or(0, Vec::len)
+            );
+            if let Err(err) = print_header(&header) {
+                println!("\t{err}");
+            }
+        }
+    }
+}
+
+fn format_hash(hash: Option<Hash>) -> String {
+    hash.map_or("-".to_string(), |hash| hash.to_string())
+}
+
+fn format_payload(size: u64, body: Option<&Body>) -> String {
+    match body {
+        Some(body) => match std::str::from_utf8(body.to_bytes().as_slice()) {
+            Ok(text) => format!("{text:?}"),
+            Err(_) => format!("{size} bytes"),
+        },
+        None if size > 0 => format!("{size} bytes (missing)"),
+        None => "-".to_string(),
+    }
+}
@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Configuration of a node.
+use std::path::{Path, PathBuf};
+
+use p2panda_core::{PrivateKey, PublicKey};
+use p2panda_net::{NetworkId, RelayUrl};
+
+/// Default maximum number of connections to the SQLite database.
+pub const DEFAULT_MAX_CONNECTIONS: u32 = 16;
+
+/// Default port of the STUN server offered by relays.
+pub const DEFAULT_STUN_PORT: u16 = 3478;
+
+/// Default number of out-of-order operations buffered per subscription.
+pub const DEFAULT_OOO_BUFFER_SIZE: usize = 128;
+
+/// Configuration of a node, see `Node::spawn`.
+///
+/// Only the network id and a data directory are required, everything else has defaults which
+/// work for most applications: peers are discovered on the local network via mDNS, operations are
+/// stored in a SQLite database and blobs on the filesystem inside the data directory.
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    pub(crate) network_id: NetworkId,
+    pub(crate) data_dir: PathBuf,
+    pub(crate) private_key: Option<PrivateKey>,
+    pub(crate) mdns: bool,
+    pub(crate) relay: Option<(RelayUrl, u16)>,
+    pub(crate) bootstrap: bool,
+    pub(crate) bootstrap_peers: Vec<PublicKey>,
+    pub(crate) max_connections: u32,
+    pub(crate) ooo_buffer_size: usize,
+}
+
+impl NodeConfig {
+    /// Returns a configuration for a node in the given network, keeping all data in `data_dir`.
+    pub fn new(network_id: NetworkId, data_dir: impl AsRef<Path>) -> Self {
+        Self {
+            network_id,
+            data_dir: data_dir.as_ref().to_path_buf(),
+            private_key: None,
+            mdns: true,
+            relay: None,
+            bootstrap: false,
+            bootstrap_peers: Vec::new(),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            ooo_buffer_size: DEFAULT_OOO_BUFFER_SIZE,
+        }
+    }
+
+    /// Sets the private key of the node.
+    ///
+    /// When no key is given the node generates one on first start and keeps it in the data
+    /// directory, the node's identity therefore stays the same across restarts.
+    pub fn private_key(mut self, private_key: PrivateKey) -> Self {
+        self.private_key = Some(private_key);
+        self
+    }
+
+    /// Enables or disables discovery of peers on the local network via mDNS.
+    ///
+    /// mDNS discovery is enabled by default.
+    pub fn mdns(mut self, enabled: bool) -> Self {
+        self.mdns = enabled;
+        self
+    }
+
+    /// Connects to a relay server to reach peers outside of the local network.
+    pub fn relay(mut self, url: RelayUrl) -> Self {
+        self.relay = Some((url, DEFAULT_STUN_PORT));
+        self
+    }
+
+    /// Connects to a relay server which offers STUN on a non-default port.
+    pub fn relay_with_stun_port(mut self, url: RelayUrl, stun_port: u16) -> Self {
+        self.relay = Some((url, stun_port));
+        self
+    }
+
+    /// Runs the node as a bootstrap node other peers can join the network through.
+    pub fn bootstrap(mut self) -> Self {
+        self.bootstrap = true;
+        self
+    }
+
+    /// Adds a known peer to join the network through.
+    ///
+    /// Peers are reached via the configured relay, a relay is therefore required to use bootstrap
+    /// peers outside of the local network.
+    pub fn bootstrap_peer(mut self, public_key: PublicKey) -> Self {
+        self.bootstrap_peers.push(public_key);
+        self
+    }
+
+    /// Sets the maximum number of connections to the SQLite database.
+    pub fn max_connections(mut self, max_connections: u32) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Sets the number of out-of-order operations buffered per subscription until the missing
+    /// operations arrived.
+    pub fn ooo_buffer_size(mut self, size: usize) -> Self {
+        self.ooo_buffer_size = size;
+        self
+    }
+}
@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Header extensions used by operations published through a node.
+use p2panda_core::prune::PruneFlag;
+use p2panda_core::{Extension, Hash, Header};
+use p2panda_net::TopicId;
+use serde::{Deserialize, Serialize};
+
+/// Identifier of the log an operation belongs to.
+///
+/// Every author keeps one log per topic, the log id is derived from the topic id.
+pub type LogId = Hash;
+
+/// Returns the log id used for operations published to the given topic.
+pub fn log_id<T: TopicId>(topic: &T) -> LogId {
+    Hash::from(topic.id())
+}
+
+/// Extensions of operations published through a node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeExtensions {
+    #[serde(rename = "l")]
+    pub log_id: LogId,
+
+    #[serde(
+        rename = "p",
+        skip_serializing_if = "PruneFlag::is_not_set",
+        default = "PruneFlag::default"
+    )]
+    pub prune_flag: PruneFlag,
+}
+
+impl Extension<LogId> for NodeExtensions {
+    fn extract(header: &Header<Self>) -> Option<LogId> {
+        header
+            .extensions
+            .as_ref()
+            .map(|extensions| extensions.log_id)
+    }
+}
+
+impl Extension<PruneFlag> for NodeExtensions {
+    fn extract(header: &Header<Self>) -> Option<PruneFlag> {
+        header
+            .extensions
+            .as_ref()
+            .map(|extensions| extensions.prune_flag.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::cbor::decode_cbor;
+    use p2panda_core::prune::PruneFlag;
+    use p2panda_core::{Header, PrivateKey};
+    use p2panda_net::TopicId;
+
+    use super::{LogId, NodeExtensions, log_id};
+
+    struct Topic([u8; 32]);
+
+    impl TopicId for Topic {
+        fn id(&self) -> [u8; 32] {
+            self.0
+        }
+    }
+
+    #[test]
+    fn extract_log_id() {
+        let private_key = PrivateKey::new();
+        let topic = Topic([7; 32]);
+
+        let mut header = Header::<NodeExtensions> {
+            public_key: private_key.public_key(),
+            extensions: Some(NodeExtensions {
+                log_id: log_id(&topic),
+                prune_flag: PruneFlag::default(),
+            }),
+            ..Default::default()
+        };
+        header.sign(&private_key);
+
+        let header: Header<NodeExtensions> = decode_cbor(&header.to_bytes()[..]).unwrap();
+        let extracted: LogId = header.extension().unwrap();
+        assert_eq!(extracted, log_id(&topic));
+
+        let prune_flag: PruneFlag = header.extension().unwrap();
+        assert!(prune_flag.is_not_set());
+    }
+}
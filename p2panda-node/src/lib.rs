@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Batteries-included p2panda node.
+//!
+//! `p2panda-node` wires the other p2panda crates together into a single `Node` with sensible
+//! defaults, so applications can get started without assembling networking, storage, sync and
+//! blobs themselves:
+//!
+//! 1. `p2panda-net` connects to peers, discovered on the local network via mDNS or reached through
+//!    an optional relay and bootstrap peers
+//! 2. `p2panda-store` persists all operations in a SQLite database
+//! 3. `p2panda-sync` catches up with peers via `LogSyncProtocol` when subscribing to a topic
+//! 4. `p2panda-stream` validates incoming operations and buffers them when they arrive out of
+//!    order
+//! 5. `p2panda-blobs` stores and syncs large files on the filesystem
+//!
+//! Every author publishes to one log per topic, operations are signed with the node's private key
+//! which is generated on first start and kept in the data directory.
+//!
+//! With the `cli` feature the crate ships a `p2panda` command line tool to inspect nodes and debug
+//! operations.
+//!
+//! Applications which need more control, for example custom header extensions or another store,
+//! can use the underlying crates directly.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use p2panda_core::Hash;
+//! use p2panda_net::TopicId;
+//! use p2panda_node::{Node, NodeConfig, log_id};
+//! use p2panda_store::query::OperationQuery;
+//! use p2panda_sync::TopicQuery;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+//! struct ChatGroup(Hash);
+//!
+//! impl TopicQuery for ChatGroup {}
+//!
+//! impl TopicId for ChatGroup {
+//!     fn id(&self) -> [u8; 32] {
+//!         self.0.into()
+//!     }
+//! }
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let config = NodeConfig::new([1; 32], "./data");
+//! let node = Node::<ChatGroup>::spawn(config).await?;
+//!
+//! let group = ChatGroup(Hash::new(b"me-and-my-friends"));
+//! let mut rx = node.subscribe(group.clone()).await?;
+//!
+//! node.publish(&group, b"Hello, Panda!").await?;
+//!
+//! // Receive operations of other peers
+//! while let Some(operation) = rx.recv().await {
+//!     println!("{}: {:?}", operation.header.public_key, operation.body);
+//! }
+//!
+//! // Query all persisted operations of the group
+//! let page = node
+//!     .query(&OperationQuery::new().log_ids([log_id(&group)]))
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+mod config;
+mod extensions;
+mod node;
+
+pub use config::{DEFAULT_MAX_CONNECTIONS, DEFAULT_OOO_BUFFER_SIZE, DEFAULT_STUN_PORT, NodeConfig};
+pub use extensions::{LogId, NodeExtensions, log_id};
+pub use node::{Node, NodeOperation, NodeStore, NodeTopicMap, open_store};
@@ -0,0 +1,384 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use p2panda_blobs::{Blobs, FilesystemStore, FilesystemStoreOptions, open_filesystem_store};
+use p2panda_core::cbor::{decode_cbor, encode_cbor};
+use p2panda_core::prune::PruneFlag;
+use p2panda_core::{Body, Header, Operation, PrivateKey, PublicKey};
+use p2panda_discovery::mdns::LocalDiscovery;
+use p2panda_net::{
+    FromNetwork, LogSyncProtocol, Network, NetworkBuilder, SyncConfiguration, ToNetwork, TopicId,
+};
+use p2panda_store::query::{OperationQuery, QueryPage};
+use p2panda_store::sqlite::store::{connection_pool, create_database, run_pending_migrations};
+use p2panda_store::{LogStore, QueryStore, SqliteStore};
+use p2panda_stream::IngestExt;
+use p2panda_stream::operation::{IngestResult, ingest_operation};
+use p2panda_sync::TopicQuery;
+use p2panda_sync::log_sync::TopicLogMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, warn};
+
+use crate::config::NodeConfig;
+use crate::extensions::{LogId, NodeExtensions, log_id};
+
+/// Name of the SQLite database file inside the data directory.
+const DATABASE_FILE: &str = "operations.db";
+
+/// Name of the blobs directory inside the data directory.
+const BLOBS_DIR: &str = "blobs";
+
+/// Name of the file holding the generated private key inside the data directory.
+const PRIVATE_KEY_FILE: &str = "private-key";
+
+/// Store used by nodes to persist operations.
+pub type NodeStore = SqliteStore<LogId, NodeExtensions>;
+
+/// Operation published or received by a node.
+pub type NodeOperation = Operation<NodeExtensions>;
+
+/// Running p2panda node.
+///
+/// A node bundles the network, a SQLite operation store, the log sync protocol and the blobs
+/// service. Operations published to a topic are signed, persisted and broadcast to all peers
+/// subscribed to the same topic, peers joining later catch up on them via sync.
+#[derive(Clone, Debug)]
+pub struct Node<T>
+where
+    T: TopicQuery + TopicId + 'static,
+{
+    private_key: PrivateKey,
+    network: Network<T>,
+    blobs: Blobs<T, FilesystemStore>,
+    store: NodeStore,
+    ooo_buffer_size: usize,
+    topics: Arc<RwLock<HashMap<[u8; 32], mpsc::Sender<ToNetwork>>>>,
+    publish_lock: Arc<Mutex<()>>,
+}
+
+impl<T> Node<T>
+where
+    T: TopicQuery + TopicId + 'static,
+{
+    /// Spawns a node with the given configuration.
+    ///
+    /// The data directory is created if it doesn't exist yet, pending database migrations are
+    /// applied before the node connects to the network.
+    pub async fn spawn(config: NodeConfig) -> Result<Self> {
+        tokio::fs::create_dir_all(&config.data_dir).await?;
+
+        let private_key = match config.private_key {
+            Some(private_key) => private_key,
+            None => load_or_create_private_key(&config.data_dir).await?,
+        };
+
+        let store = open_store(&config.data_dir, config.max_connections).await?;
+
+        let sync_protocol = LogSyncProtocol::new(NodeTopicMap::new(store.clone()), store.clone());
+
+        let mut builder = NetworkBuilder::<T>::new(config.network_id)
+            .private_key(private_key.clone())
+            .sync(SyncConfiguration::new(sync_protocol));
+
+        if config.mdns {
+            builder = builder.discovery(LocalDiscovery::new());
+        }
+
+        let relay_url = config.relay.as_ref().map(|(url, _)| url.clone());
+        if let Some((url, stun_port)) = config.relay {
+            builder = builder.relay(url, false, stun_port);
+        }
+
+        for public_key in config.bootstrap_peers {
+            builder = builder.direct_address(public_key, vec![], relay_url.clone());
+        }
+
+        if config.bootstrap {
+            builder = builder.bootstrap();
+        }
+
+        let blobs_store = open_filesystem_store(
+            config.data_dir.join(BLOBS_DIR),
+            FilesystemStoreOptions::new(),
+        )
+        .await?;
+        let (network, blobs) = Blobs::from_builder(builder, blobs_store).await?;
+
+        debug!(node_id = %network.node_id(), "spawned node");
+
+        Ok(Self {
+            private_key,
+            network,
+            blobs,
+            store,
+            ooo_buffer_size: config.ooo_buffer_size,
+            topics: Arc::new(RwLock::new(HashMap::new())),
+            publish_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Returns the public key of this node, it is used to sign all published operations.
+    pub fn public_key(&self) -> PublicKey {
+        self.private_key.public_key()
+    }
+
+    /// Returns the underlying network.
+    pub fn network(&self) -> &Network<T> {
+        &self.network
+    }
+
+    /// Returns the blobs service, for example to import files and share their hashes in
+    /// published operations.
+    pub fn blobs(&self) -> &Blobs<T, FilesystemStore> {
+        &self.blobs
+    }
+
+    /// Returns the operation store.
+    pub fn store(&self) -> &NodeStore {
+        &self.store
+    }
+
+    /// Subscribes to a topic and returns a channel receiving all new operations of it.
+    ///
+    /// Operations received from other peers, via gossip or sync, are validated and persisted
+    /// before they are passed on. Operations arriving out of order are buffered until the missing
+    /// operations of their log arrived.
+    pub async fn subscribe(&self, topic: T) -> Result<mpsc::Receiver<NodeOperation>> {
+        let topic_id = topic.id();
+        let log_id = log_id(&topic);
+
+        let (network_tx, network_rx, _ready) = self.network.subscribe(topic).await?;
+        self.topics.write().await.insert(topic_id, network_tx);
+
+        let (tx, rx) = mpsc::channel(128);
+        let stream = ReceiverStream::new(network_rx)
+            .filter_map(move |event| async move { decode_operation(event, &log_id) })
+            .ingest(self.store.clone(), self.ooo_buffer_size);
+
+        tokio::spawn(async move {
+            let mut stream = pin!(stream);
+            while let Some(result) = stream.next().await {
+                match result {
+                    Ok(operation) => {
+                        if tx.send(operation).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) => warn!("could not ingest operation: {err}"),
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+
+    /// Signs and persists a new operation with the given payload and broadcasts it to the topic.
+    ///
+    /// Operations published to topics the node is not subscribed to are only persisted, they
+    /// reach other peers via sync as soon as the node subscribes to the topic.
+    pub async fn publish(&self, topic: &T, payload: &[u8]) -> Result<NodeOperation> {
+        let log_id = log_id(topic);
+        let body = Body::new(payload);
+
+        // Hold the lock until the operation was persisted to not create two operations with the
+        // same sequence number.
+        let _guard = self.publish_lock.lock().await;
+
+        let latest_operation = self
+            .store
+            .latest_operation(&self.public_key(), &log_id)
+            .await?;
+        let (seq_num, backlink) = match latest_operation {
+            Some((header, _)) => (header.seq_num + 1, Some(header.hash())),
+            None => (0, None),
+        };
+
+        let mut header = Header {
+            version: 1,
+            public_key: self.public_key(),
+            signature: None,
+            payload_size: body.size(),
+            payload_hash: Some(body.hash()),
+            timestamp: timestamp(),
+            seq_num,
+            backlink,
+            previous: vec![],
+            extensions: Some(NodeExtensions {
+                log_id,
+                prune_flag: PruneFlag::default(),
+            }),
+        };
+        header.sign(&self.private_key);
+        let header_bytes = header.to_bytes();
+
+        let mut store = self.store.clone();
+        let operation = match ingest_operation(
+            &mut store,
+            header,
+            Some(body),
+            header_bytes.clone(),
+            &log_id,
+            false,
+        )
+        .await?
+        {
+            IngestResult::Complete(operation) => operation,
+            IngestResult::Retry(..) => bail!("log of local node is incomplete"),
+        };
+
+        if let Some(network_tx) = self.topics.read().await.get(&topic.id()) {
+            let bytes = encode_cbor(&GossipOperation {
+                header: header_bytes,
+                body: operation.body.as_ref().map(|body| body.to_bytes()),
+            })?;
+            network_tx.send(ToNetwork::Message { bytes }).await?;
+        }
+
+        Ok(operation)
+    }
+
+    /// Queries persisted operations, see `OperationQuery`.
+    ///
+    /// Use `log_id` to query the operations of a topic.
+    pub async fn query(&self, query: &OperationQuery<LogId>) -> Result<QueryPage<NodeExtensions>> {
+        Ok(self.store.query(query).await?)
+    }
+
+    /// Gracefully shuts the node down.
+    pub async fn shutdown(self) -> Result<()> {
+        self.network.shutdown().await
+    }
+}
+
+/// Maps topics to the logs of all authors who published to them.
+///
+/// Every author writes to one log per topic, the logs of a topic are therefore all logs in the
+/// store with the topic's log id.
+#[derive(Clone, Debug)]
+pub struct NodeTopicMap {
+    store: NodeStore,
+}
+
+impl NodeTopicMap {
+    pub fn new(store: NodeStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<T> TopicLogMap<T, LogId> for NodeTopicMap
+where
+    T: TopicQuery + TopicId,
+{
+    async fn get(&self, topic: &T) -> Option<HashMap<PublicKey, Vec<LogId>>> {
+        let log_id = log_id(topic);
+        let heights = match self.store.get_log_heights(&log_id).await {
+            Ok(heights) => heights,
+            Err(err) => {
+                warn!("could not look up logs of topic: {err}");
+                return None;
+            }
+        };
+
+        Some(
+            heights
+                .into_iter()
+                .map(|(public_key, _)| (public_key, vec![log_id]))
+                .collect(),
+        )
+    }
+}
+
+/// Operation broadcast to other peers via gossip.
+#[derive(Serialize, Deserialize)]
+struct GossipOperation {
+    #[serde(with = "serde_bytes")]
+    header: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    body: Option<Vec<u8>>,
+}
+
+/// Decodes operations received from the network, ignoring operations of other topics.
+fn decode_operation(
+    event: FromNetwork,
+    log_id: &LogId,
+) -> Option<(Header<NodeExtensions>, Option<Body>, Vec<u8>)> {
+    let (header_bytes, body_bytes) = match event {
+        FromNetwork::GossipMessage { bytes, .. } => {
+            match decode_cbor::<GossipOperation, _>(&bytes[..]) {
+                Ok(operation) => (operation.header, operation.body),
+                Err(err) => {
+                    warn!("could not decode gossip message: {err}");
+                    return None;
+                }
+            }
+        }
+        FromNetwork::SyncMessage {
+            header, payload, ..
+        } => (header, payload),
+    };
+
+    let header: Header<NodeExtensions> = match decode_cbor(&header_bytes[..]) {
+        Ok(header) => header,
+        Err(err) => {
+            warn!("could not decode header: {err}");
+            return None;
+        }
+    };
+
+    if header.extension::<LogId>().as_ref() != Some(log_id) {
+        debug!(hash = %header.hash(), "ignoring operation of another topic");
+        return None;
+    }
+
+    Some((header, body_bytes.map(Body::from), header_bytes))
+}
+
+/// Opens the operation store in the given data directory of a node.
+///
+/// The database is created if it doesn't exist yet and pending migrations are applied. This is
+/// useful to inspect the operations of a node which is not running.
+pub async fn open_store(data_dir: &Path, max_connections: u32) -> Result<NodeStore> {
+    let database_url = format!(
+        "sqlite://{}",
+        data_dir.join(DATABASE_FILE).to_string_lossy()
+    );
+    create_database(&database_url).await?;
+    let pool = connection_pool(&database_url, max_connections).await?;
+    run_pending_migrations(&pool).await?;
+    Ok(NodeStore::new(pool))
+}
+
+/// Loads the private key from the data directory or generates and stores a new one.
+async fn load_or_create_private_key(data_dir: &Path) -> Result<PrivateKey> {
+    let path = data_dir.join(PRIVATE_KEY_FILE);
+
+    if tokio::fs::try_exists(&path).await? {
+        let bytes = tokio::fs::read(&path).await?;
+        let private_key =
+            PrivateKey::try_from(&bytes[..]).context("invalid private key in data directory")?;
+        return Ok(private_key);
+    }
+
+    let private_key = PrivateKey::new();
+    tokio::fs::write(&path, private_key.as_bytes()).await?;
+    Ok(private_key)
+}
+
+/// Returns the current time in microseconds since the UNIX epoch.
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is after the UNIX epoch")
+        .as_micros() as u64
+}
@@ -0,0 +1,18 @@
+-- SPDX-License-Identifier: MIT OR Apache-2.0
+
+CREATE TABLE IF NOT EXISTS payloads_v1 (
+    body_hash               TEXT            NOT NULL    PRIMARY KEY,
+    bytes                   BLOB            NOT NULL
+);
+
+ALTER TABLE operations_v1 ADD COLUMN body_hash TEXT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_operations_v1_body_hash ON operations_v1 (body_hash);
+
+-- Move bodies of existing operations into the shared payloads table.
+INSERT OR IGNORE INTO payloads_v1 (body_hash, bytes)
+    SELECT payload_hash, body FROM operations_v1
+    WHERE body IS NOT NULL AND payload_hash IS NOT NULL;
+
+UPDATE operations_v1 SET body_hash = payload_hash, body = NULL
+    WHERE body IS NOT NULL AND payload_hash IS NOT NULL;
@@ -0,0 +1,92 @@
+-- SPDX-License-Identifier: MIT OR Apache-2.0
+
+CREATE TABLE IF NOT EXISTS log_heads_v1 (
+    public_key              TEXT            NOT NULL,
+    log_id                  TEXT            NOT NULL,
+    seq_num                 TEXT            NOT NULL,
+    hash                    TEXT            NOT NULL,
+    operations              INTEGER         NOT NULL,
+    size                    INTEGER         NOT NULL,
+    PRIMARY KEY (public_key, log_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_operations_v1_log ON operations_v1 (public_key, log_id);
+
+-- Calculate heads of logs which were created before heads were maintained.
+INSERT INTO log_heads_v1 (public_key, log_id, seq_num, hash, operations, size)
+    SELECT
+        operations_v1.public_key,
+        operations_v1.log_id,
+        '0',
+        '',
+        COUNT(*),
+        SUM(LENGTH(header_bytes) + COALESCE(LENGTH(payloads_v1.bytes), 0))
+    FROM
+        operations_v1
+        LEFT JOIN payloads_v1 ON payloads_v1.body_hash = operations_v1.body_hash
+    GROUP BY
+        operations_v1.public_key,
+        operations_v1.log_id;
+
+UPDATE log_heads_v1 SET (seq_num, hash) = (
+    SELECT seq_num, hash FROM operations_v1
+    WHERE
+        operations_v1.public_key = log_heads_v1.public_key
+        AND operations_v1.log_id = log_heads_v1.log_id
+    ORDER BY CAST(seq_num AS NUMERIC) DESC LIMIT 1
+);
+
+-- Keep heads up-to-date on every write. Payloads are inserted before and removed only after the
+-- operations referencing them, their size can therefore always be looked up.
+CREATE TRIGGER IF NOT EXISTS log_heads_v1_insert AFTER INSERT ON operations_v1
+BEGIN
+    INSERT INTO log_heads_v1 (public_key, log_id, seq_num, hash, operations, size)
+    VALUES (
+        NEW.public_key,
+        NEW.log_id,
+        NEW.seq_num,
+        NEW.hash,
+        1,
+        LENGTH(NEW.header_bytes)
+            + COALESCE((SELECT LENGTH(bytes) FROM payloads_v1 WHERE body_hash = NEW.body_hash), 0)
+    )
+    ON CONFLICT (public_key, log_id) DO UPDATE SET
+        seq_num = CASE
+            WHEN CAST(excluded.seq_num AS NUMERIC) >= CAST(seq_num AS NUMERIC)
+            THEN excluded.seq_num ELSE seq_num
+        END,
+        hash = CASE
+            WHEN CAST(excluded.seq_num AS NUMERIC) >= CAST(seq_num AS NUMERIC)
+            THEN excluded.hash ELSE hash
+        END,
+        operations = operations + 1,
+        size = size + excluded.size;
+END;
+
+CREATE TRIGGER IF NOT EXISTS log_heads_v1_delete AFTER DELETE ON operations_v1
+BEGIN
+    UPDATE log_heads_v1 SET
+        operations = operations - 1,
+        size = size - LENGTH(OLD.header_bytes)
+            - COALESCE((SELECT LENGTH(bytes) FROM payloads_v1 WHERE body_hash = OLD.body_hash), 0)
+    WHERE public_key = OLD.public_key AND log_id = OLD.log_id;
+
+    DELETE FROM log_heads_v1
+    WHERE public_key = OLD.public_key AND log_id = OLD.log_id AND operations <= 0;
+
+    UPDATE log_heads_v1 SET (seq_num, hash) = (
+        SELECT seq_num, hash FROM operations_v1
+        WHERE public_key = OLD.public_key AND log_id = OLD.log_id
+        ORDER BY CAST(seq_num AS NUMERIC) DESC LIMIT 1
+    )
+    WHERE public_key = OLD.public_key AND log_id = OLD.log_id AND hash = OLD.hash;
+END;
+
+CREATE TRIGGER IF NOT EXISTS log_heads_v1_update_body AFTER UPDATE OF body_hash ON operations_v1
+BEGIN
+    UPDATE log_heads_v1 SET
+        size = size
+            - COALESCE((SELECT LENGTH(bytes) FROM payloads_v1 WHERE body_hash = OLD.body_hash), 0)
+            + COALESCE((SELECT LENGTH(bytes) FROM payloads_v1 WHERE body_hash = NEW.body_hash), 0)
+    WHERE public_key = NEW.public_key AND log_id = NEW.log_id;
+END;
@@ -0,0 +1,346 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Export and import operations to and from a portable archive.
+//!
+//! Archives are self-contained and independent of the store backend, they can be used for
+//! backups or to transfer data between nodes without a live sync session, for example by copying
+//! the file on a USB stick ("sneakernet").
+//!
+//! An archive is a sequence of records, each prefixed with its length as a big-endian `u32` and
+//! encoded as CBOR. The first record states the archive version, then one record follows for
+//! every operation with its log id, header bytes and optional body. The last record contains the
+//! number of operations and a digest over all operation hashes, it detects truncated or
+//! reordered archives.
+//!
+//! During import every operation is validated (hash, signature and payload) and the digest is
+//! checked before anything is written to the store.
+use std::io::{ErrorKind, Read, Write};
+
+use p2panda_core::cbor::{DecodeError, EncodeError, decode_cbor, encode_cbor};
+use p2panda_core::{Body, Extensions, Hash, Header, Operation, OperationError, validate_operation};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{BatchOperation, LogId, LogStore, OperationStore};
+
+/// Version of the archive format written by `export`.
+pub const ARCHIVE_VERSION: u64 = 1;
+
+/// Summary of an export or import.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveReport {
+    /// Number of operations contained in the archive.
+    pub operations: usize,
+
+    /// Number of operations which were inserted into the store during import.
+    pub imported: usize,
+}
+
+/// Errors which can occur during export or import.
+#[derive(Debug, Error)]
+pub enum ArchiveError<S> {
+    /// Reading from or writing to the store failed.
+    #[error("error accessing store: {0}")]
+    Store(S),
+
+    /// Reading from or writing to the archive failed.
+    #[error("error accessing archive: {0}")]
+    Io(std::io::Error),
+
+    #[error("failed to encode archive record: {0}")]
+    Encode(EncodeError),
+
+    #[error("failed to decode archive record: {0}")]
+    Decode(DecodeError),
+
+    #[error("archive version {0} is not supported")]
+    UnsupportedVersion(u64),
+
+    /// Operation in the archive is invalid, for example because of a wrong signature.
+    #[error("invalid operation {0} in archive: {1}")]
+    InvalidOperation(Hash, OperationError),
+
+    /// Archive ended unexpectedly, records are missing or in the wrong order.
+    #[error("archive is incomplete or corrupted")]
+    Corrupted,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Record<L> {
+    Start {
+        version: u64,
+    },
+    Operation {
+        log_id: L,
+        #[serde(with = "serde_bytes")]
+        header: Vec<u8>,
+        body: Option<Body>,
+    },
+    End {
+        operations: u64,
+        digest: Hash,
+    },
+}
+
+/// Digest over the hashes of all operations in the order they appear in the archive.
+fn digest(hashes: &[Hash]) -> Hash {
+    let bytes: Vec<u8> = hashes
+        .iter()
+        .flat_map(|hash| hash.as_bytes().to_owned())
+        .collect();
+    Hash::new(bytes)
+}
+
+fn write_record<L, W, S>(writer: &mut W, record: &Record<L>) -> Result<(), ArchiveError<S>>
+where
+    L: Serialize,
+    W: Write,
+{
+    let bytes = encode_cbor(record).map_err(ArchiveError::Encode)?;
+    let len = u32::try_from(bytes.len()).map_err(|_| ArchiveError::Corrupted)?;
+    writer
+        .write_all(&len.to_be_bytes())
+        .map_err(ArchiveError::Io)?;
+    writer.write_all(&bytes).map_err(ArchiveError::Io)?;
+    Ok(())
+}
+
+fn read_record<L, R, S>(reader: &mut R) -> Result<Record<L>, ArchiveError<S>>
+where
+    L: DeserializeOwned,
+    R: Read,
+{
+    let mut len = [0; 4];
+    reader
+        .read_exact(&mut len)
+        .map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => ArchiveError::Corrupted,
+            _ => ArchiveError::Io(err),
+        })?;
+
+    let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => ArchiveError::Corrupted,
+            _ => ArchiveError::Io(err),
+        })?;
+
+    decode_cbor(&bytes[..]).map_err(ArchiveError::Decode)
+}
+
+/// Write all operations of the given logs, by any author, into an archive.
+pub async fn export<L, E, S, W>(
+    store: &S,
+    log_ids: &[L],
+    mut writer: W,
+) -> Result<ArchiveReport, ArchiveError<S::Error>>
+where
+    L: LogId + Serialize,
+    E: Extensions,
+    S: LogStore<L, E>,
+    W: Write,
+{
+    write_record::<L, _, _>(
+        &mut writer,
+        &Record::Start {
+            version: ARCHIVE_VERSION,
+        },
+    )?;
+
+    let mut hashes = Vec::new();
+    for log_id in log_ids {
+        let log_heights = store
+            .get_log_heights(log_id)
+            .await
+            .map_err(ArchiveError::Store)?;
+
+        for (public_key, _) in log_heights {
+            let Some(log) = store
+                .get_raw_log(&public_key, log_id, None)
+                .await
+                .map_err(ArchiveError::Store)?
+            else {
+                continue;
+            };
+
+            for (header, body) in log {
+                hashes.push(Hash::new(&header));
+                write_record(
+                    &mut writer,
+                    &Record::Operation {
+                        log_id: log_id.to_owned(),
+                        header,
+                        body: body.map(Body::from),
+                    },
+                )?;
+            }
+        }
+    }
+
+    write_record::<L, _, _>(
+        &mut writer,
+        &Record::End {
+            operations: hashes.len() as u64,
+            digest: digest(&hashes),
+        },
+    )?;
+    writer.flush().map_err(ArchiveError::Io)?;
+
+    Ok(ArchiveReport {
+        operations: hashes.len(),
+        imported: 0,
+    })
+}
+
+/// Validate all operations of an archive and insert the ones missing in the store.
+///
+/// Nothing is written to the store if any operation is invalid or the archive is corrupted.
+pub async fn import<L, E, S, R>(
+    store: &mut S,
+    mut reader: R,
+) -> Result<ArchiveReport, ArchiveError<<S as OperationStore<L, E>>::Error>>
+where
+    L: LogId + DeserializeOwned,
+    E: Extensions,
+    S: OperationStore<L, E>,
+    R: Read,
+{
+    match read_record::<L, _, _>(&mut reader)? {
+        Record::Start { version } if version == ARCHIVE_VERSION => (),
+        Record::Start { version } => return Err(ArchiveError::UnsupportedVersion(version)),
+        _ => return Err(ArchiveError::Corrupted),
+    }
+
+    let mut operations: Vec<(L, Operation<E>, Vec<u8>)> = Vec::new();
+    loop {
+        match read_record(&mut reader)? {
+            Record::Operation {
+                log_id,
+                header: header_bytes,
+                body,
+            } => {
+                let hash = Hash::new(&header_bytes);
+                let header: Header<E> =
+                    decode_cbor(&header_bytes[..]).map_err(ArchiveError::Decode)?;
+                let operation = Operation { hash, header, body };
+                validate_operation(&operation)
+                    .map_err(|err| ArchiveError::InvalidOperation(hash, err))?;
+                operations.push((log_id, operation, header_bytes));
+            }
+            Record::End {
+                operations: count,
+                digest: expected,
+            } => {
+                let hashes: Vec<Hash> = operations
+                    .iter()
+                    .map(|(_, operation, _)| operation.hash)
+                    .collect();
+                if count != hashes.len() as u64 || digest(&hashes) != expected {
+                    return Err(ArchiveError::Corrupted);
+                }
+                break;
+            }
+            Record::Start { .. } => return Err(ArchiveError::Corrupted),
+        }
+    }
+
+    // Only insert operations which are not in the store yet.
+    let mut batch = Vec::new();
+    for (log_id, operation, header_bytes) in &operations {
+        if !store
+            .has_operation(operation.hash)
+            .await
+            .map_err(ArchiveError::Store)?
+        {
+            batch.push(BatchOperation {
+                hash: operation.hash,
+                header: &operation.header,
+                body: operation.body.as_ref(),
+                header_bytes,
+                log_id,
+            });
+        }
+    }
+
+    let imported = store
+        .insert_operations(&batch)
+        .await
+        .map_err(ArchiveError::Store)?;
+
+    Ok(ArchiveReport {
+        operations: operations.len(),
+        imported,
+    })
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use p2panda_core::PrivateKey;
+
+    use crate::conformance::insert_log;
+    use crate::migrate::verify;
+    use crate::{LogStore, MemoryStore};
+
+    use super::{ArchiveError, ArchiveReport, export, import};
+
+    #[tokio::test]
+    async fn export_and_import() {
+        let mut source = MemoryStore::<u64>::new();
+        insert_log(&mut source, &PrivateKey::new(), 0, 3).await;
+        insert_log(&mut source, &PrivateKey::new(), 0, 2).await;
+        insert_log(&mut source, &PrivateKey::new(), 1, 4).await;
+
+        let mut archive = Vec::new();
+        let report = export(&source, &[0], &mut archive).await.unwrap();
+        assert_eq!(report.operations, 5);
+
+        let mut target = MemoryStore::<u64>::new();
+        let report = import(&mut target, &archive[..]).await.unwrap();
+        assert_eq!(
+            report,
+            ArchiveReport {
+                operations: 5,
+                imported: 5
+            }
+        );
+        verify(&source, &target, &[0]).await.unwrap();
+        assert!(target.get_log_heights(&1).await.unwrap().is_empty());
+
+        // Importing the same archive again doesn't insert anything.
+        let report = import(&mut target, &archive[..]).await.unwrap();
+        assert_eq!(report.imported, 0);
+    }
+
+    #[tokio::test]
+    async fn reject_corrupted_archives() {
+        let mut source = MemoryStore::<u64>::new();
+        insert_log(&mut source, &PrivateKey::new(), 0, 3).await;
+
+        let mut archive = Vec::new();
+        export(&source, &[0], &mut archive).await.unwrap();
+
+        // Archive was cut off.
+        let mut target = MemoryStore::<u64>::new();
+        assert!(matches!(
+            import(&mut target, &archive[..archive.len() - 10]).await,
+            Err(ArchiveError::Corrupted)
+        ));
+
+        // A byte of a body was flipped.
+        let position = archive
+            .windows(9)
+            .position(|window| window == b"hello 0 1")
+            .unwrap();
+        let mut tampered = archive.clone();
+        tampered[position] ^= 1;
+        assert!(matches!(
+            import(&mut target, &tampered[..]).await,
+            Err(ArchiveError::InvalidOperation(_, _))
+        ));
+
+        // Nothing was written to the store.
+        assert!(target.get_log_heights(&0).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,802 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Conformance test suite shared across store backends.
+//!
+//! Every backend is expected to behave the same for the cases covered here. Implementers of
+//! custom backends can run the suite against their own store with [`run`].
+//!
+//! Each check uses a fresh author and log id, all checks can hence run against the same store
+//! instance.
+use std::collections::HashMap;
+
+use p2panda_core::{Body, Hash, Header, PrivateKey, PublicKey};
+
+use crate::query::{LogCut, OperationQuery};
+use crate::{BatchOperation, LogStore, OperationStore, PayloadStore, QueryStore};
+
+/// Operation hash, header, body and encoded header.
+type TestOperation = (Hash, Header<()>, Body, Vec<u8>);
+
+/// Number of concurrent writers appending to their own log in [`concurrent_writers`].
+const WRITERS: u64 = 8;
+
+/// Number of operations every writer appends in [`concurrent_writers`].
+const OPERATIONS_PER_WRITER: u64 = 25;
+
+/// Create a signed operation with the given body, sequence number, timestamp and backlink.
+pub fn create_operation(
+    private_key: &PrivateKey,
+    body: &Body,
+    seq_num: u64,
+    timestamp: u64,
+    backlink: Option<Hash>,
+) -> (Hash, Header<()>, Vec<u8>) {
+    let mut header = Header {
+        version: 1,
+        public_key: private_key.public_key(),
+        signature: None,
+        payload_size: body.size(),
+        payload_hash: Some(body.hash()),
+        timestamp,
+        seq_num,
+        backlink,
+        previous: vec![],
+        extensions: None,
+    };
+    header.sign(private_key);
+    let header_bytes = header.to_bytes();
+    (header.hash(), header, header_bytes)
+}
+
+/// Insert a log of `len` operations for the given author and return their hashes.
+pub async fn insert_log<S>(
+    store: &mut S,
+    private_key: &PrivateKey,
+    log_id: u64,
+    len: u64,
+) -> Vec<Hash>
+where
+    S: OperationStore<u64, ()>,
+{
+    let mut hashes: Vec<Hash> = Vec::new();
+    for seq_num in 0..len {
+        let body = Body::new(format!("hello {log_id} {seq_num}").as_bytes());
+        let (hash, header, header_bytes) =
+            create_operation(private_key, &body, seq_num, seq_num, hashes.last().cloned());
+        let inserted = store
+            .insert_operation(hash, &header, Some(&body), &header_bytes, &log_id)
+            .await
+            .expect("no errors");
+        assert!(inserted);
+        hashes.push(hash);
+    }
+    hashes
+}
+
+/// Run all conformance checks against the given store.
+pub async fn run<S>(mut store: S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()> + QueryStore<u64, ()>,
+{
+    insert_get_operation(&mut store).await;
+    delete_operation(&mut store).await;
+    delete_payload(&mut store).await;
+    get_log(&mut store).await;
+    latest_operation(&mut store).await;
+    delete_operations(&mut store).await;
+    delete_payloads(&mut store).await;
+    get_log_heights(&mut store).await;
+    query_operations(&mut store).await;
+    historical_queries(&mut store).await;
+    insert_operations(&mut store).await;
+    shared_payloads(&mut store).await;
+    log_heads(&mut store).await;
+}
+
+/// Inserted operations can be retrieved by their hash.
+pub async fn insert_get_operation<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()>,
+{
+    let private_key = PrivateKey::new();
+    let body = Body::new("hello!".as_bytes());
+    let (hash, header, header_bytes) = create_operation(&private_key, &body, 0, 0, None);
+
+    assert!(!store.has_operation(hash).await.expect("no errors"));
+    assert!(
+        store
+            .insert_operation(hash, &header, Some(&body), &header_bytes, &1)
+            .await
+            .expect("no errors")
+    );
+    assert!(store.has_operation(hash).await.expect("no errors"));
+
+    let (header_again, body_again) = store
+        .get_operation(hash)
+        .await
+        .expect("no errors")
+        .expect("operation exists");
+    assert_eq!(header_again, header);
+    assert_eq!(body_again, Some(body.clone()));
+
+    let (header_bytes_again, body_bytes_again) = store
+        .get_raw_operation(hash)
+        .await
+        .expect("no errors")
+        .expect("operation exists");
+    assert_eq!(header_bytes_again, header_bytes);
+    assert_eq!(body_bytes_again, Some(body.to_bytes()));
+
+    let unknown = Hash::new(b"unknown");
+    assert!(
+        store
+            .get_operation(unknown)
+            .await
+            .expect("no errors")
+            .is_none()
+    );
+    assert!(
+        store
+            .get_raw_operation(unknown)
+            .await
+            .expect("no errors")
+            .is_none()
+    );
+}
+
+/// Deleted operations are removed from the store and their log.
+pub async fn delete_operation<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()>,
+{
+    let private_key = PrivateKey::new();
+    let hashes = insert_log(store, &private_key, 2, 2).await;
+
+    assert!(store.delete_operation(hashes[1]).await.expect("no errors"));
+    assert!(!store.has_operation(hashes[1]).await.expect("no errors"));
+    assert!(!store.delete_operation(hashes[1]).await.expect("no errors"));
+
+    let log = store
+        .get_log(&private_key.public_key(), &2, None)
+        .await
+        .expect("no errors")
+        .expect("log exists");
+    assert_eq!(log.len(), 1);
+}
+
+/// Deleting a payload keeps the header around.
+pub async fn delete_payload<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()>,
+{
+    let private_key = PrivateKey::new();
+    let hashes = insert_log(store, &private_key, 3, 1).await;
+
+    assert!(store.delete_payload(hashes[0]).await.expect("no errors"));
+    let (_, body) = store
+        .get_operation(hashes[0])
+        .await
+        .expect("no errors")
+        .expect("operation exists");
+    assert!(body.is_none());
+    let (_, body) = store
+        .get_raw_operation(hashes[0])
+        .await
+        .expect("no errors")
+        .expect("operation exists");
+    assert!(body.is_none());
+
+    assert!(
+        !store
+            .delete_payload(Hash::new(b"unknown"))
+            .await
+            .expect("no errors")
+    );
+}
+
+/// Logs are returned in sequence number order, optionally starting at a given sequence number.
+pub async fn get_log<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()>,
+{
+    let private_key = PrivateKey::new();
+    let public_key = private_key.public_key();
+    let hashes = insert_log(store, &private_key, 4, 3).await;
+
+    let log = store
+        .get_log(&public_key, &4, None)
+        .await
+        .expect("no errors")
+        .expect("log exists");
+    let log_hashes: Vec<Hash> = log.iter().map(|(header, _)| header.hash()).collect();
+    assert_eq!(log_hashes, hashes);
+
+    let log = store
+        .get_log(&public_key, &4, Some(1))
+        .await
+        .expect("no errors")
+        .expect("log exists");
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0].0.seq_num, 1);
+
+    let raw_log = store
+        .get_raw_log(&public_key, &4, None)
+        .await
+        .expect("no errors")
+        .expect("log exists");
+    assert_eq!(raw_log.len(), 3);
+    let (header_bytes, _) = store
+        .get_raw_operation(hashes[0])
+        .await
+        .expect("no errors")
+        .expect("operation exists");
+    assert_eq!(raw_log[0].0, header_bytes);
+
+    let raw_log = store
+        .get_raw_log(&public_key, &4, Some(2))
+        .await
+        .expect("no errors")
+        .expect("log exists");
+    assert_eq!(raw_log.len(), 1);
+
+    // Unknown logs are not found.
+    let other_key = PrivateKey::new().public_key();
+    assert!(
+        store
+            .get_log(&other_key, &4, None)
+            .await
+            .expect("no errors")
+            .is_none()
+    );
+    assert!(
+        store
+            .get_log(&public_key, &5, None)
+            .await
+            .expect("no errors")
+            .is_none()
+    );
+    assert!(
+        store
+            .get_raw_log(&other_key, &4, None)
+            .await
+            .expect("no errors")
+            .is_none()
+    );
+}
+
+/// The latest operation is the one with the highest sequence number.
+pub async fn latest_operation<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()>,
+{
+    let private_key = PrivateKey::new();
+    let public_key = private_key.public_key();
+    assert!(
+        store
+            .latest_operation(&public_key, &6)
+            .await
+            .expect("no errors")
+            .is_none()
+    );
+
+    let hashes = insert_log(store, &private_key, 6, 3).await;
+    let (header, _) = store
+        .latest_operation(&public_key, &6)
+        .await
+        .expect("no errors")
+        .expect("log exists");
+    assert_eq!(header.hash(), hashes[2]);
+}
+
+/// Operations before a sequence number are removed from the log.
+pub async fn delete_operations<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()>,
+{
+    let private_key = PrivateKey::new();
+    let public_key = private_key.public_key();
+    let hashes = insert_log(store, &private_key, 7, 3).await;
+
+    assert!(
+        store
+            .delete_operations(&public_key, &7, 2)
+            .await
+            .expect("no errors")
+    );
+    assert!(!store.has_operation(hashes[0]).await.expect("no errors"));
+    assert!(!store.has_operation(hashes[1]).await.expect("no errors"));
+    assert!(store.has_operation(hashes[2]).await.expect("no errors"));
+
+    let log = store
+        .get_log(&public_key, &7, None)
+        .await
+        .expect("no errors")
+        .expect("log exists");
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].0.hash(), hashes[2]);
+
+    // Nothing left to delete.
+    assert!(
+        !store
+            .delete_operations(&public_key, &7, 2)
+            .await
+            .expect("no errors")
+    );
+}
+
+/// Payloads in the range `[from, to)` are removed while headers are kept.
+pub async fn delete_payloads<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()>,
+{
+    let private_key = PrivateKey::new();
+    let public_key = private_key.public_key();
+    insert_log(store, &private_key, 8, 4).await;
+
+    assert!(
+        store
+            .delete_payloads(&public_key, &8, 1, 3)
+            .await
+            .expect("no errors")
+    );
+
+    let log = store
+        .get_log(&public_key, &8, None)
+        .await
+        .expect("no errors")
+        .expect("log exists");
+    let bodies: Vec<bool> = log.iter().map(|(_, body)| body.is_some()).collect();
+    assert_eq!(bodies, vec![true, false, false, true]);
+
+    assert!(
+        !store
+            .delete_payloads(&public_key, &8, 10, 20)
+            .await
+            .expect("no errors")
+    );
+}
+
+/// Log heights are reported for every author of a log.
+pub async fn get_log_heights<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()>,
+{
+    assert!(
+        store
+            .get_log_heights(&9)
+            .await
+            .expect("no errors")
+            .is_empty()
+    );
+
+    let private_key_a = PrivateKey::new();
+    let private_key_b = PrivateKey::new();
+    insert_log(store, &private_key_a, 9, 3).await;
+    insert_log(store, &private_key_b, 9, 1).await;
+    insert_log(store, &private_key_b, 10, 5).await;
+
+    let mut log_heights = store.get_log_heights(&9).await.expect("no errors");
+    log_heights.sort();
+    let mut expected = vec![
+        (private_key_a.public_key(), 2),
+        (private_key_b.public_key(), 0),
+    ];
+    expected.sort();
+    assert_eq!(log_heights, expected);
+}
+
+/// Queries filter operations and return them in pages ordered by timestamp.
+pub async fn query_operations<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + QueryStore<u64, ()>,
+{
+    let private_key_a = PrivateKey::new();
+    let private_key_b = PrivateKey::new();
+    let hashes_a = insert_log(store, &private_key_a, 11, 5).await;
+    insert_log(store, &private_key_b, 12, 2).await;
+
+    let query = OperationQuery::new().author(private_key_a.public_key());
+    let page = store.query(&query).await.expect("no errors");
+    let hashes: Vec<Hash> = page
+        .operations
+        .iter()
+        .map(|(header, _)| header.hash())
+        .collect();
+    assert_eq!(hashes, hashes_a);
+    assert!(page.next.is_none());
+
+    let page = store
+        .query(&query.clone().seq_num(1..3))
+        .await
+        .expect("no errors");
+    let seq_nums: Vec<u64> = page
+        .operations
+        .iter()
+        .map(|(header, _)| header.seq_num)
+        .collect();
+    assert_eq!(seq_nums, vec![1, 2]);
+
+    let page = store
+        .query(&query.clone().timestamp(2..4))
+        .await
+        .expect("no errors");
+    let timestamps: Vec<u64> = page
+        .operations
+        .iter()
+        .map(|(header, _)| header.timestamp)
+        .collect();
+    assert_eq!(timestamps, vec![2, 3]);
+
+    let query_b = OperationQuery::new().author(private_key_b.public_key());
+    let page = store
+        .query(&query_b.clone().log_ids([11]))
+        .await
+        .expect("no errors");
+    assert!(page.operations.is_empty());
+    let page = store
+        .query(&query_b.log_ids([11, 12]))
+        .await
+        .expect("no errors");
+    assert_eq!(page.operations.len(), 2);
+
+    // Page through all operations of the first author.
+    let mut hashes = Vec::new();
+    let mut pages = 0;
+    let mut query = query.limit(2);
+    loop {
+        let page = store.query(&query).await.expect("no errors");
+        assert!(page.operations.len() <= 2);
+        hashes.extend(page.operations.iter().map(|(header, _)| header.hash()));
+        pages += 1;
+        match page.next {
+            Some(cursor) => query = query.after(cursor),
+            None => break,
+        }
+    }
+    assert_eq!(pages, 3);
+    assert_eq!(hashes, hashes_a);
+
+    // A limit of zero returns all operations in one page.
+    let query = OperationQuery::new()
+        .author(private_key_a.public_key())
+        .limit(0);
+    let page = store.query(&query).await.expect("no errors");
+    assert_eq!(page.operations.len(), 5);
+    assert!(page.next.is_none());
+}
+
+/// Queries return the operations visible at a point in time or in a log cut.
+pub async fn historical_queries<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()> + QueryStore<u64, ()>,
+{
+    let private_key_a = PrivateKey::new();
+    let private_key_b = PrivateKey::new();
+    let hashes_a = insert_log(store, &private_key_a, 21, 3).await;
+    let cut = LogCut::from_heads(21, &store.log_heads(&21).await.expect("no errors"));
+    let body = Body::new(b"hello 21 3");
+    let (hash, header, header_bytes) =
+        create_operation(&private_key_a, &body, 3, 3, hashes_a.last().cloned());
+    store
+        .insert_operation(hash, &header, Some(&body), &header_bytes, &21)
+        .await
+        .expect("no errors");
+    let hashes_b = insert_log(store, &private_key_b, 22, 4).await;
+
+    // Operations added later to the same or other logs are not visible in the cut.
+    let mut query = OperationQuery::new().log_ids([21, 22]).cut(cut.clone());
+    let page = store.query(&query).await.expect("no errors");
+    let hashes: Vec<Hash> = page
+        .operations
+        .iter()
+        .map(|(header, _)| header.hash())
+        .collect();
+    assert_eq!(hashes, hashes_a);
+
+    let mut cut = cut;
+    cut.insert(private_key_a.public_key(), 21, 0);
+    cut.insert(private_key_b.public_key(), 22, 1);
+    query = query.cut(cut);
+    let page = store.query(&query).await.expect("no errors");
+    let mut hashes: Vec<Hash> = page
+        .operations
+        .iter()
+        .map(|(header, _)| header.hash())
+        .collect();
+    hashes.sort();
+    let mut expected = vec![hashes_a[0], hashes_b[0], hashes_b[1]];
+    expected.sort();
+    assert_eq!(hashes, expected);
+
+    // Operations are visible from their timestamp on.
+    let query = OperationQuery::new()
+        .author(private_key_b.public_key())
+        .as_of(2);
+    let page = store.query(&query).await.expect("no errors");
+    let seq_nums: Vec<u64> = page
+        .operations
+        .iter()
+        .map(|(header, _)| header.seq_num)
+        .collect();
+    assert_eq!(seq_nums, vec![0, 1, 2]);
+}
+
+/// Batches of operations are inserted at once.
+pub async fn insert_operations<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()>,
+{
+    let private_key = PrivateKey::new();
+    let mut operations = Vec::new();
+    for seq_num in 0..3 {
+        let body = Body::new(format!("batch {seq_num}").as_bytes());
+        let backlink = operations.last().map(|(hash, _, _, _)| *hash);
+        let (hash, header, header_bytes) =
+            create_operation(&private_key, &body, seq_num, seq_num, backlink);
+        operations.push((hash, header, body, header_bytes));
+    }
+
+    let batch: Vec<BatchOperation<u64, ()>> = operations
+        .iter()
+        .map(|(hash, header, body, header_bytes)| BatchOperation {
+            hash: *hash,
+            header,
+            body: Some(body),
+            header_bytes,
+            log_id: &13,
+        })
+        .collect();
+    assert_eq!(store.insert_operations(&batch).await.expect("no errors"), 3);
+
+    let log = store
+        .get_log(&private_key.public_key(), &13, None)
+        .await
+        .expect("no errors")
+        .expect("log exists");
+    let hashes: Vec<Hash> = log.iter().map(|(header, _)| header.hash()).collect();
+    let expected: Vec<Hash> = operations.iter().map(|(hash, _, _, _)| *hash).collect();
+    assert_eq!(hashes, expected);
+
+    assert_eq!(store.insert_operations(&[]).await.expect("no errors"), 0);
+}
+
+/// Operations with the same body keep their payload when another one of them is deleted.
+pub async fn shared_payloads<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()>,
+{
+    let private_key_a = PrivateKey::new();
+    let private_key_b = PrivateKey::new();
+    let body = Body::new("shared payload".as_bytes());
+    let (hash_a, header_a, header_bytes_a) = create_operation(&private_key_a, &body, 0, 0, None);
+    let (hash_b, header_b, header_bytes_b) = create_operation(&private_key_b, &body, 0, 0, None);
+
+    store
+        .insert_operation(hash_a, &header_a, Some(&body), &header_bytes_a, &14)
+        .await
+        .expect("no errors");
+    store
+        .insert_operation(hash_b, &header_b, Some(&body), &header_bytes_b, &15)
+        .await
+        .expect("no errors");
+
+    assert!(
+        store
+            .delete_operations(&private_key_a.public_key(), &14, 1)
+            .await
+            .expect("no errors")
+    );
+    let (_, body_b) = store
+        .get_operation(hash_b)
+        .await
+        .expect("no errors")
+        .expect("operation exists");
+    assert_eq!(body_b, Some(body.clone()));
+
+    assert!(store.delete_payload(hash_b).await.expect("no errors"));
+    let (_, body_b) = store
+        .get_operation(hash_b)
+        .await
+        .expect("no errors")
+        .expect("operation exists");
+    assert!(body_b.is_none());
+}
+
+/// Heads of logs are updated on every write.
+pub async fn log_heads<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()>,
+{
+    let private_key = PrivateKey::new();
+    let public_key = private_key.public_key();
+    let hashes = insert_log(store, &private_key, 18, 3).await;
+    let log = store
+        .get_raw_log(&public_key, &18, None)
+        .await
+        .expect("no errors")
+        .expect("log exists");
+    let header_sizes: Vec<u64> = log
+        .iter()
+        .map(|(header_bytes, _)| header_bytes.len() as u64)
+        .collect();
+    let body_sizes: u64 = log
+        .iter()
+        .map(|(_, body)| body.as_ref().map_or(0, |body| body.len() as u64))
+        .sum();
+
+    let heads = store.log_heads(&18).await.expect("no errors");
+    assert_eq!(heads.len(), 1);
+    assert_eq!(heads[0].public_key, public_key);
+    assert_eq!(heads[0].seq_num, 2);
+    assert_eq!(heads[0].hash, hashes[2]);
+    assert_eq!(heads[0].operations, 3);
+    // Stores might add some overhead to stored bodies, for example when encrypting them.
+    assert!(heads[0].size >= header_sizes.iter().sum::<u64>() + body_sizes);
+    assert_eq!(
+        store.get_log_heights(&18).await.expect("no errors"),
+        vec![(public_key, 2)]
+    );
+
+    store
+        .delete_payloads(&public_key, &18, 0, 3)
+        .await
+        .expect("no errors");
+    let heads = store.log_heads(&18).await.expect("no errors");
+    assert_eq!(heads[0].size, header_sizes.iter().sum::<u64>());
+
+    store
+        .delete_operations(&public_key, &18, 1)
+        .await
+        .expect("no errors");
+    let heads = store.log_heads(&18).await.expect("no errors");
+    assert_eq!(heads[0].operations, 2);
+    assert_eq!(heads[0].size, header_sizes[1] + header_sizes[2]);
+
+    // Deleting the latest operation moves the head back.
+    store.delete_operation(hashes[2]).await.expect("no errors");
+    let heads = store.log_heads(&18).await.expect("no errors");
+    assert_eq!(heads[0].seq_num, 1);
+    assert_eq!(heads[0].hash, hashes[1]);
+    assert_eq!(heads[0].operations, 1);
+    assert_eq!(heads[0].size, header_sizes[1]);
+
+    store.delete_operation(hashes[1]).await.expect("no errors");
+    assert!(store.log_heads(&18).await.expect("no errors").is_empty());
+    assert!(
+        store
+            .get_log_heights(&18)
+            .await
+            .expect("no errors")
+            .is_empty()
+    );
+}
+
+/// Payloads are only removed by garbage collection once no operation references them anymore.
+///
+/// This check is not part of [`run`] as not every store shares payloads between operations.
+pub async fn collect_garbage<S>(store: &mut S)
+where
+    S: OperationStore<u64, ()> + PayloadStore,
+{
+    // Remove leftovers of previous checks first.
+    store.gc().await.expect("no errors");
+
+    let private_key_a = PrivateKey::new();
+    let private_key_b = PrivateKey::new();
+    let body = Body::new("collect me".as_bytes());
+    let (hash_a, header_a, header_bytes_a) = create_operation(&private_key_a, &body, 0, 0, None);
+    let (hash_b, header_b, header_bytes_b) = create_operation(&private_key_b, &body, 0, 0, None);
+
+    store
+        .insert_operation(hash_a, &header_a, Some(&body), &header_bytes_a, &16)
+        .await
+        .expect("no errors");
+    store
+        .insert_operation(hash_b, &header_b, Some(&body), &header_bytes_b, &17)
+        .await
+        .expect("no errors");
+    assert_eq!(store.gc().await.expect("no errors"), 0);
+
+    // The payload is still referenced by the second operation.
+    assert!(store.delete_operation(hash_a).await.expect("no errors"));
+    assert_eq!(store.gc().await.expect("no errors"), 0);
+    let (_, body_b) = store
+        .get_operation(hash_b)
+        .await
+        .expect("no errors")
+        .expect("operation exists");
+    assert_eq!(body_b, Some(body.clone()));
+
+    assert!(store.delete_payload(hash_b).await.expect("no errors"));
+    assert_eq!(store.gc().await.expect("no errors"), body.size());
+    assert_eq!(store.gc().await.expect("no errors"), 0);
+}
+
+/// Concurrent writers and readers sharing clones of the same store.
+///
+/// Writers append to their own logs while others insert the same operations at the same time,
+/// like a sync session and the gossip ingester receiving identical data. Readers check that logs
+/// never contain gaps while they are being written. Run this with a multi-threaded runtime.
+///
+/// This check is not part of [`run`] as it needs a store which can be shared between tasks.
+pub async fn concurrent_writers<S>(store: S)
+where
+    S: OperationStore<u64, ()> + LogStore<u64, ()> + Clone + Send + Sync + 'static,
+{
+    let private_keys: Vec<PrivateKey> = (0..WRITERS).map(|_| PrivateKey::new()).collect();
+    let logs: Vec<Vec<TestOperation>> = private_keys
+        .iter()
+        .map(|private_key| {
+            let mut log: Vec<TestOperation> = Vec::new();
+            for seq_num in 0..OPERATIONS_PER_WRITER {
+                let body = Body::new(format!("concurrent {seq_num}").as_bytes());
+                let backlink = log.last().map(|(hash, _, _, _)| *hash);
+                let (hash, header, header_bytes) =
+                    create_operation(private_key, &body, seq_num, seq_num, backlink);
+                log.push((hash, header, body, header_bytes));
+            }
+            log
+        })
+        .collect();
+
+    let mut handles = Vec::new();
+
+    // Two writers insert every log, each operation is expected to be inserted exactly once.
+    for _ in 0..2 {
+        for log in logs.clone() {
+            let mut store = store.clone();
+            handles.push(tokio::spawn(async move {
+                let mut inserted = 0;
+                for (hash, header, body, header_bytes) in &log {
+                    if store
+                        .insert_operation(*hash, header, Some(body), header_bytes, &20)
+                        .await
+                        .expect("no errors")
+                    {
+                        inserted += 1;
+                    }
+                }
+                inserted
+            }));
+        }
+    }
+
+    // Readers check that logs only ever grow from the start, without any gaps.
+    for private_key in private_keys.clone() {
+        let store = store.clone();
+        handles.push(tokio::spawn(async move {
+            for _ in 0..OPERATIONS_PER_WRITER {
+                if let Some(log) = store
+                    .get_log(&private_key.public_key(), &20, None)
+                    .await
+                    .expect("no errors")
+                {
+                    for (seq_num, (header, body)) in log.iter().enumerate() {
+                        assert_eq!(header.seq_num, seq_num as u64);
+                        assert!(body.is_some());
+                    }
+                }
+                tokio::task::yield_now().await;
+            }
+            0
+        }));
+    }
+
+    let mut inserted = 0;
+    for handle in handles {
+        inserted += handle.await.expect("task did not panic");
+    }
+    assert_eq!(inserted, WRITERS * OPERATIONS_PER_WRITER);
+
+    let log_heights: HashMap<PublicKey, u64> = store
+        .get_log_heights(&20)
+        .await
+        .expect("no errors")
+        .into_iter()
+        .collect();
+    let expected: HashMap<PublicKey, u64> = private_keys
+        .iter()
+        .map(|private_key| (private_key.public_key(), OPERATIONS_PER_WRITER - 1))
+        .collect();
+    assert_eq!(log_heights, expected);
+}
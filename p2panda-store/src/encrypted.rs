@@ -0,0 +1,498 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Encryption at rest for operation payloads.
+//!
+//! `EncryptedStore` wraps any store backend and transparently encrypts operation bodies before
+//! they are written and decrypts them when they are read again. This protects sensitive
+//! application data from other users of a shared device or anyone getting hold of the database
+//! file.
+//!
+//! Bodies are encrypted with XChaCha20-Poly1305 using a random nonce per body. The hash of the
+//! operation is used as associated data, an encrypted body can hence not be moved to another
+//! operation without being detected.
+//!
+//! Headers are stored unencrypted, store backends rely on their fields (author, log, sequence
+//! number, timestamp) to organise and query operations.
+//!
+//! The `EncryptionKey` is either derived from a user passphrase or directly given as 32 bytes of
+//! secret key material, for example a secret shared within a group.
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use thiserror::Error;
+
+use p2panda_core::{Body, Extensions, Hash, Header, PublicKey, RawOperation};
+
+use crate::query::{OperationQuery, QueryPage};
+use crate::{BatchOperation, LogHead, LogId, LogStore, OperationStore, PayloadStore, QueryStore};
+
+/// Length of the nonce prepended to every encrypted body.
+const NONCE_LEN: usize = 24;
+
+/// Minimum length of the salt used for deriving keys from passphrases.
+pub const MIN_SALT_LEN: usize = 8;
+
+#[derive(Debug, Error)]
+pub enum EncryptedStoreError<E> {
+    /// Error of the wrapped store.
+    #[error(transparent)]
+    Store(E),
+
+    /// Body could not be encrypted.
+    #[error("failed to encrypt operation body")]
+    EncryptionFailed,
+
+    /// Body could not be decrypted, it was encrypted with another key or was tampered with.
+    #[error("failed to decrypt body of operation {0}")]
+    DecryptionFailed(Hash),
+}
+
+/// Errors which can occur when deriving a key from a passphrase.
+#[derive(Debug, Error)]
+pub enum KeyDerivationError {
+    #[error("salt needs to be at least {MIN_SALT_LEN} bytes long")]
+    SaltTooShort,
+
+    #[error("failed to derive key from passphrase: {0}")]
+    Argon2(argon2::Error),
+}
+
+/// Symmetric key used to encrypt operation bodies.
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    /// Use 32 bytes of secret key material, for example a group secret, as encryption key.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Derive an encryption key from a passphrase with Argon2id.
+    ///
+    /// The salt does not need to be secret but needs to be stored alongside the database, the
+    /// same passphrase and salt always result in the same key.
+    pub fn from_passphrase(passphrase: &[u8], salt: &[u8]) -> Result<Self, KeyDerivationError> {
+        if salt.len() < MIN_SALT_LEN {
+            return Err(KeyDerivationError::SaltTooShort);
+        }
+
+        let mut key = [0; 32];
+        argon2::Argon2::default()
+            .hash_password_into(passphrase, salt, &mut key)
+            .map_err(KeyDerivationError::Argon2)?;
+        Ok(Self(key))
+    }
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print secret key material.
+        f.debug_tuple("EncryptionKey").field(&"***").finish()
+    }
+}
+
+/// Store wrapper encrypting operation bodies at rest.
+#[derive(Clone)]
+pub struct EncryptedStore<S> {
+    store: S,
+    cipher: XChaCha20Poly1305,
+}
+
+impl<S> std::fmt::Debug for EncryptedStore<S>
+where
+    S: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedStore")
+            .field("store", &self.store)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> EncryptedStore<S> {
+    /// Wrap a store to encrypt all bodies with the given key.
+    pub fn new(store: S, key: &EncryptionKey) -> Self {
+        Self {
+            store,
+            cipher: XChaCha20Poly1305::new(&key.0.into()),
+        }
+    }
+
+    /// Access the wrapped store, bodies returned by it are encrypted.
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    fn encrypt<E>(&self, hash: &Hash, plaintext: &[u8]) -> Result<Vec<u8>, EncryptedStoreError<E>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: hash.as_bytes(),
+                },
+            )
+            .map_err(|_| EncryptedStoreError::EncryptionFailed)?;
+
+        let mut bytes = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&ciphertext);
+        Ok(bytes)
+    }
+
+    fn decrypt<E>(&self, hash: &Hash, bytes: &[u8]) -> Result<Vec<u8>, EncryptedStoreError<E>> {
+        if bytes.len() < NONCE_LEN {
+            return Err(EncryptedStoreError::DecryptionFailed(*hash));
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: hash.as_bytes(),
+                },
+            )
+            .map_err(|_| EncryptedStoreError::DecryptionFailed(*hash))
+    }
+
+    fn encrypt_body<E>(&self, hash: &Hash, body: &Body) -> Result<Body, EncryptedStoreError<E>> {
+        Ok(Body::from(self.encrypt(hash, &body.to_bytes())?))
+    }
+
+    fn decrypt_operation<X, E>(
+        &self,
+        (header, body): (Header<X>, Option<Body>),
+    ) -> Result<(Header<X>, Option<Body>), EncryptedStoreError<E>>
+    where
+        X: Extensions,
+    {
+        let body = match body {
+            Some(body) => Some(Body::from(self.decrypt(&header.hash(), &body.to_bytes())?)),
+            None => None,
+        };
+        Ok((header, body))
+    }
+
+    fn decrypt_raw_operation<E>(
+        &self,
+        (header_bytes, body): RawOperation,
+    ) -> Result<RawOperation, EncryptedStoreError<E>> {
+        let body = match body {
+            Some(body) => Some(self.decrypt(&Hash::new(&header_bytes), &body)?),
+            None => None,
+        };
+        Ok((header_bytes, body))
+    }
+}
+
+impl<S, L, E> OperationStore<L, E> for EncryptedStore<S>
+where
+    S: OperationStore<L, E> + Sync,
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = EncryptedStoreError<<S as OperationStore<L, E>>::Error>;
+
+    async fn insert_operation(
+        &mut self,
+        hash: Hash,
+        header: &Header<E>,
+        body: Option<&Body>,
+        header_bytes: &[u8],
+        log_id: &L,
+    ) -> Result<bool, Self::Error> {
+        let body = body
+            .map(|body| self.encrypt_body(&hash, body))
+            .transpose()?;
+        self.store
+            .insert_operation(hash, header, body.as_ref(), header_bytes, log_id)
+            .await
+            .map_err(EncryptedStoreError::Store)
+    }
+
+    async fn insert_operations(
+        &mut self,
+        operations: &[BatchOperation<'_, L, E>],
+    ) -> Result<usize, Self::Error> {
+        let bodies = operations
+            .iter()
+            .map(|operation| {
+                operation
+                    .body
+                    .map(|body| self.encrypt_body(&operation.hash, body))
+                    .transpose()
+            })
+            .collect::<Result<Vec<Option<Body>>, Self::Error>>()?;
+
+        let operations: Vec<BatchOperation<'_, L, E>> = operations
+            .iter()
+            .zip(bodies.iter())
+            .map(|(operation, body)| BatchOperation {
+                hash: operation.hash,
+                header: operation.header,
+                body: body.as_ref(),
+                header_bytes: operation.header_bytes,
+                log_id: operation.log_id,
+            })
+            .collect();
+
+        self.store
+            .insert_operations(&operations)
+            .await
+            .map_err(EncryptedStoreError::Store)
+    }
+
+    async fn get_operation(
+        &self,
+        hash: Hash,
+    ) -> Result<Option<(Header<E>, Option<Body>)>, Self::Error> {
+        self.store
+            .get_operation(hash)
+            .await
+            .map_err(EncryptedStoreError::Store)?
+            .map(|operation| self.decrypt_operation(operation))
+            .transpose()
+    }
+
+    async fn get_raw_operation(&self, hash: Hash) -> Result<Option<RawOperation>, Self::Error> {
+        self.store
+            .get_raw_operation(hash)
+            .await
+            .map_err(EncryptedStoreError::Store)?
+            .map(|operation| self.decrypt_raw_operation(operation))
+            .transpose()
+    }
+
+    async fn has_operation(&self, hash: Hash) -> Result<bool, Self::Error> {
+        self.store
+            .has_operation(hash)
+            .await
+            .map_err(EncryptedStoreError::Store)
+    }
+
+    async fn delete_operation(&mut self, hash: Hash) -> Result<bool, Self::Error> {
+        self.store
+            .delete_operation(hash)
+            .await
+            .map_err(EncryptedStoreError::Store)
+    }
+
+    async fn delete_payload(&mut self, hash: Hash) -> Result<bool, Self::Error> {
+        self.store
+            .delete_payload(hash)
+            .await
+            .map_err(EncryptedStoreError::Store)
+    }
+}
+
+impl<S, L, E> LogStore<L, E> for EncryptedStore<S>
+where
+    S: LogStore<L, E> + Send + Sync,
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = EncryptedStoreError<<S as LogStore<L, E>>::Error>;
+
+    async fn get_log(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: Option<u64>,
+    ) -> Result<Option<Vec<(Header<E>, Option<Body>)>>, Self::Error> {
+        self.store
+            .get_log(public_key, log_id, from)
+            .await
+            .map_err(EncryptedStoreError::Store)?
+            .map(|log| {
+                log.into_iter()
+                    .map(|operation| self.decrypt_operation(operation))
+                    .collect()
+            })
+            .transpose()
+    }
+
+    async fn get_raw_log(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: Option<u64>,
+    ) -> Result<Option<Vec<RawOperation>>, Self::Error> {
+        self.store
+            .get_raw_log(public_key, log_id, from)
+            .await
+            .map_err(EncryptedStoreError::Store)?
+            .map(|log| {
+                log.into_iter()
+                    .map(|operation| self.decrypt_raw_operation(operation))
+                    .collect()
+            })
+            .transpose()
+    }
+
+    async fn get_log_heights(&self, log_id: &L) -> Result<Vec<(PublicKey, u64)>, Self::Error> {
+        self.store
+            .get_log_heights(log_id)
+            .await
+            .map_err(EncryptedStoreError::Store)
+    }
+
+    /// Sizes of the returned heads include the encryption overhead of every stored body.
+    async fn log_heads(&self, log_id: &L) -> Result<Vec<LogHead>, Self::Error> {
+        self.store
+            .log_heads(log_id)
+            .await
+            .map_err(EncryptedStoreError::Store)
+    }
+
+    async fn latest_operation(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+    ) -> Result<Option<(Header<E>, Option<Body>)>, Self::Error> {
+        self.store
+            .latest_operation(public_key, log_id)
+            .await
+            .map_err(EncryptedStoreError::Store)?
+            .map(|operation| self.decrypt_operation(operation))
+            .transpose()
+    }
+
+    async fn delete_operations(
+        &mut self,
+        public_key: &PublicKey,
+        log_id: &L,
+        before: u64,
+    ) -> Result<bool, Self::Error> {
+        self.store
+            .delete_operations(public_key, log_id, before)
+            .await
+            .map_err(EncryptedStoreError::Store)
+    }
+
+    async fn delete_payloads(
+        &mut self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: u64,
+        to: u64,
+    ) -> Result<bool, Self::Error> {
+        self.store
+            .delete_payloads(public_key, log_id, from, to)
+            .await
+            .map_err(EncryptedStoreError::Store)
+    }
+}
+
+impl<S, L, E> QueryStore<L, E> for EncryptedStore<S>
+where
+    S: QueryStore<L, E> + Sync,
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = EncryptedStoreError<<S as QueryStore<L, E>>::Error>;
+
+    async fn query(&self, query: &OperationQuery<L>) -> Result<QueryPage<E>, Self::Error> {
+        let page = self
+            .store
+            .query(query)
+            .await
+            .map_err(EncryptedStoreError::Store)?;
+
+        let operations = page
+            .operations
+            .into_iter()
+            .map(|operation| self.decrypt_operation(operation))
+            .collect::<Result<Vec<_>, Self::Error>>()?;
+
+        Ok(QueryPage {
+            operations,
+            next: page.next,
+        })
+    }
+}
+
+impl<S> PayloadStore for EncryptedStore<S>
+where
+    S: PayloadStore + Send + Sync,
+{
+    type Error = EncryptedStoreError<<S as PayloadStore>::Error>;
+
+    async fn gc(&mut self) -> Result<u64, Self::Error> {
+        self.store.gc().await.map_err(EncryptedStoreError::Store)
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use p2panda_core::{Body, PrivateKey};
+
+    use crate::conformance::{self, create_operation};
+    use crate::{MemoryStore, OperationStore};
+
+    use super::{EncryptedStore, EncryptedStoreError, EncryptionKey, KeyDerivationError};
+
+    fn key() -> EncryptionKey {
+        EncryptionKey::from_passphrase(b"correct horse battery staple", b"p2panda-salt").unwrap()
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        conformance::run(EncryptedStore::new(MemoryStore::<u64>::new(), &key())).await;
+    }
+
+    #[tokio::test]
+    async fn bodies_are_encrypted_at_rest() {
+        let mut store = EncryptedStore::new(MemoryStore::<u64>::new(), &key());
+        let private_key = PrivateKey::new();
+        let body = Body::new("secret message".as_bytes());
+        let (hash, header, header_bytes) = create_operation(&private_key, &body, 0, 0, None);
+
+        store
+            .insert_operation(hash, &header, Some(&body), &header_bytes, &0)
+            .await
+            .unwrap();
+
+        // The wrapped store only sees the ciphertext.
+        let (_, stored_body) = store.inner().get_operation(hash).await.unwrap().unwrap();
+        let stored_body = stored_body.unwrap();
+        assert_ne!(stored_body, body);
+        assert!(
+            !stored_body
+                .to_bytes()
+                .windows(6)
+                .any(|window| window == b"secret")
+        );
+
+        // Reading through the wrapper returns the plaintext.
+        let (_, plaintext) = store.get_operation(hash).await.unwrap().unwrap();
+        assert_eq!(plaintext, Some(body.clone()));
+        let (_, raw_plaintext) = store.get_raw_operation(hash).await.unwrap().unwrap();
+        assert_eq!(raw_plaintext, Some(body.to_bytes()));
+
+        // Another key can't decrypt the body.
+        let other_key = EncryptionKey::from_bytes([1; 32]);
+        let other_store = EncryptedStore::new(store.inner().clone(), &other_key);
+        assert!(matches!(
+            other_store.get_operation(hash).await,
+            Err(EncryptedStoreError::DecryptionFailed(_))
+        ));
+    }
+
+    #[test]
+    fn derive_keys() {
+        assert!(matches!(
+            EncryptionKey::from_passphrase(b"passphrase", b"short"),
+            Err(KeyDerivationError::SaltTooShort)
+        ));
+
+        let key_a = EncryptionKey::from_passphrase(b"passphrase", b"salt-a-123").unwrap();
+        let key_b = EncryptionKey::from_passphrase(b"passphrase", b"salt-a-123").unwrap();
+        let key_c = EncryptionKey::from_passphrase(b"passphrase", b"salt-b-123").unwrap();
+        assert_eq!(key_a.0, key_b.0);
+        assert_ne!(key_a.0, key_c.0);
+        assert_eq!(format!("{key_a:?}"), "EncryptionKey(\"***\")");
+    }
+}
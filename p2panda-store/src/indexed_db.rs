@@ -0,0 +1,890 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Persistence for p2panda operations and logs in the browser, backed by IndexedDB.
+//!
+//! The store is only available when compiling for `wasm32-unknown-unknown` with the `wasm`
+//! feature flag. Every method call runs in one IndexedDB transaction, writes are therefore
+//! applied atomically and serialised by the browser.
+//!
+//! IndexedDB handles can't be moved to another thread. Browsers run WebAssembly on a single
+//! thread, so the store and its futures are wrapped with `SendWrapper` to implement the
+//! thread-safe store traits as well. Using a store from another thread than the one it was opened
+//! on, for example from a web worker, panics.
+//!
+//! Tests run in a headless browser with `wasm-pack test --headless --firefox --no-default-features
+//! --features wasm`.
+use std::hash::{DefaultHasher, Hash as StdHash, Hasher};
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use js_sys::Array;
+use rexie::{Index, KeyRange, ObjectStore, Rexie, Store, Transaction, TransactionMode};
+use send_wrapper::SendWrapper;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use wasm_bindgen::JsValue;
+
+use p2panda_core::cbor::{DecodeError, decode_cbor};
+use p2panda_core::{Body, Extensions, Hash, Header, PublicKey, RawOperation};
+
+use crate::query::{OperationQuery, QueryPage};
+use crate::{BatchOperation, LogHead, LogId, LogStore, OperationStore, PayloadStore, QueryStore};
+
+/// Version of the database schema, increase it when object stores or indexes change.
+const DATABASE_VERSION: u32 = 1;
+
+/// Operations by hash.
+const OPERATIONS: &str = "operations_v1";
+
+/// Index of all operations per log, ordered by sequence number.
+const LOGS_INDEX: &str = "logs_v1";
+
+/// Index of all operations by timestamp and hash, this is the order of query results.
+const TIMESTAMPS_INDEX: &str = "timestamps_v1";
+
+/// Payloads by body hash, shared by all operations with the same body.
+const PAYLOADS: &str = "payloads_v1";
+
+/// Heads of all logs, updated with every write.
+const LOG_HEADS: &str = "log_heads_v1";
+
+/// Index of all log heads per log id.
+const LOG_HEADS_INDEX: &str = "log_heads_by_log_id_v1";
+
+/// Stored operation.
+///
+/// Numbers are stored as zero-padded hex strings, IndexedDB compares them in numerical order
+/// without losing precision in JavaScript numbers.
+#[derive(Debug, Serialize, Deserialize)]
+struct OperationRecord {
+    hash: String,
+    log_id: String,
+    public_key: String,
+    seq_num: String,
+    timestamp: String,
+    #[serde(with = "serde_bytes")]
+    header: Vec<u8>,
+    payload: Option<String>,
+}
+
+/// Stored payload with the number of operations referencing it.
+#[derive(Debug, Serialize, Deserialize)]
+struct PayloadRecord {
+    hash: String,
+    #[serde(with = "serde_bytes")]
+    bytes: Vec<u8>,
+    refs: u64,
+}
+
+/// Head of an author's log.
+#[derive(Debug, Serialize, Deserialize)]
+struct HeadRecord {
+    log_id: String,
+    public_key: String,
+    seq_num: String,
+    hash: String,
+    operations: u64,
+    size: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum IndexedDbStoreError {
+    #[error("failed to decode operation header: {0}")]
+    DecodingFailed(#[from] DecodeError),
+
+    #[error("an error occurred with the IndexedDB database: {0}")]
+    Database(#[from] rexie::Error),
+
+    #[error("failed to convert stored value: {0}")]
+    Conversion(#[from] serde_wasm_bindgen::Error),
+
+    #[error("stored value is invalid: {0}")]
+    InvalidValue(String),
+}
+
+/// IndexedDB-based persistent store for browsers.
+#[derive(Clone)]
+pub struct IndexedDbStore<L, E = ()> {
+    db: SendWrapper<Rc<Rexie>>,
+    _marker: PhantomData<(L, E)>,
+}
+
+impl<L, E> IndexedDbStore<L, E>
+where
+    L: LogId,
+    E: Extensions,
+{
+    /// Open the IndexedDB database with the given name or create it if it doesn't already exist.
+    ///
+    /// Missing object stores and indexes are created on the first start.
+    pub async fn open(name: &str) -> Result<Self, IndexedDbStoreError> {
+        let db = Rexie::builder(name)
+            .version(DATABASE_VERSION)
+            .add_object_store(
+                ObjectStore::new(OPERATIONS)
+                    .key_path("hash")
+                    .add_index(Index::new_array(
+                        LOGS_INDEX,
+                        ["log_id", "public_key", "seq_num"],
+                    ))
+                    .add_index(Index::new_array(TIMESTAMPS_INDEX, ["timestamp", "hash"])),
+            )
+            .add_object_store(ObjectStore::new(PAYLOADS).key_path("hash"))
+            .add_object_store(
+                ObjectStore::new(LOG_HEADS)
+                    .key_path_array(["log_id", "public_key"])
+                    .add_index(Index::new(LOG_HEADS_INDEX, "log_id")),
+            )
+            .build()
+            .await?;
+
+        Ok(Self {
+            db: SendWrapper::new(Rc::new(db)),
+            _marker: PhantomData {},
+        })
+    }
+
+    /// Delete the IndexedDB database with the given name and all operations in it.
+    pub async fn delete(name: &str) -> Result<(), IndexedDbStoreError> {
+        Ok(Rexie::delete(name).await?)
+    }
+
+    fn transaction(&self, mode: TransactionMode) -> Result<Transaction, IndexedDbStoreError> {
+        Ok(self
+            .db
+            .transaction(&[OPERATIONS, PAYLOADS, LOG_HEADS], mode)?)
+    }
+}
+
+fn calculate_hash<T: StdHash>(t: &T) -> u64 {
+    let mut s = DefaultHasher::new();
+    t.hash(&mut s);
+    s.finish()
+}
+
+/// Encode a number as a string which sorts in numerical order.
+fn sortable(value: u64) -> String {
+    format!("{value:016x}")
+}
+
+fn parse_sortable(value: &str) -> Result<u64, IndexedDbStoreError> {
+    u64::from_str_radix(value, 16).map_err(|err| IndexedDbStoreError::InvalidValue(err.to_string()))
+}
+
+fn log_id_key<L: LogId>(log_id: &L) -> String {
+    sortable(calculate_hash(log_id))
+}
+
+/// Compound IndexedDB key of the given parts.
+fn key(parts: &[&str]) -> JsValue {
+    parts
+        .iter()
+        .map(|part| JsValue::from_str(part))
+        .collect::<Array>()
+        .into()
+}
+
+/// Range over the operations of one log, starting at `from` and ending before `to` when given.
+fn log_range(
+    log_id: &str,
+    public_key: &str,
+    from: u64,
+    to: Option<u64>,
+) -> Result<KeyRange, IndexedDbStoreError> {
+    let lower = key(&[log_id, public_key, &sortable(from)]);
+    let upper = key(&[log_id, public_key, &sortable(to.unwrap_or(u64::MAX))]);
+    Ok(KeyRange::bound(
+        &lower,
+        &upper,
+        Some(false),
+        Some(to.is_some()),
+    )?)
+}
+
+fn from_value<T: DeserializeOwned>(value: JsValue) -> Result<T, IndexedDbStoreError> {
+    Ok(serde_wasm_bindgen::from_value(value)?)
+}
+
+fn to_value<T: Serialize>(value: &T) -> Result<JsValue, IndexedDbStoreError> {
+    Ok(serde_wasm_bindgen::to_value(value)?)
+}
+
+/// All object stores opened within one transaction.
+struct Stores {
+    operations: Store,
+    payloads: Store,
+    heads: Store,
+}
+
+impl Stores {
+    fn open(tx: &Transaction) -> Result<Self, IndexedDbStoreError> {
+        Ok(Self {
+            operations: tx.store(OPERATIONS)?,
+            payloads: tx.store(PAYLOADS)?,
+            heads: tx.store(LOG_HEADS)?,
+        })
+    }
+
+    async fn operation(&self, hash: &Hash) -> Result<Option<OperationRecord>, IndexedDbStoreError> {
+        self.operations
+            .get(JsValue::from_str(&hash.to_hex()))
+            .await?
+            .map(from_value)
+            .transpose()
+    }
+
+    /// Operations of one log ordered by sequence number.
+    async fn log(&self, range: KeyRange) -> Result<Vec<OperationRecord>, IndexedDbStoreError> {
+        self.operations
+            .index(LOGS_INDEX)?
+            .get_all(Some(range), None)
+            .await?
+            .into_iter()
+            .map(from_value)
+            .collect()
+    }
+
+    async fn body(&self, payload: Option<&String>) -> Result<Option<Vec<u8>>, IndexedDbStoreError> {
+        let Some(payload) = payload else {
+            return Ok(None);
+        };
+        let record = self.payloads.get(JsValue::from_str(payload)).await?;
+        Ok(record
+            .map(from_value::<PayloadRecord>)
+            .transpose()?
+            .map(|record| record.bytes))
+    }
+
+    async fn decode<E: Extensions>(
+        &self,
+        record: &OperationRecord,
+    ) -> Result<(Header<E>, Option<Body>), IndexedDbStoreError> {
+        let header: Header<E> = decode_cbor(&record.header[..])?;
+        let body = self.body(record.payload.as_ref()).await?;
+        Ok((header, body.map(Body::from)))
+    }
+
+    /// Store the body or increase the reference count if it already exists.
+    async fn retain_payload(&self, body: &Body) -> Result<String, IndexedDbStoreError> {
+        let hash = body.hash().to_hex();
+        let record = match self.payloads.get(JsValue::from_str(&hash)).await? {
+            Some(value) => {
+                let mut record: PayloadRecord = from_value(value)?;
+                record.refs += 1;
+                record
+            }
+            None => PayloadRecord {
+                hash: hash.clone(),
+                bytes: body.to_bytes(),
+                refs: 1,
+            },
+        };
+        self.payloads.put(&to_value(&record)?, None).await?;
+        Ok(hash)
+    }
+
+    /// Decrease the reference count of a payload, unreferenced payloads are removed by `gc`.
+    async fn release_payload(&self, payload: &str) -> Result<(), IndexedDbStoreError> {
+        if let Some(value) = self.payloads.get(JsValue::from_str(payload)).await? {
+            let mut record: PayloadRecord = from_value(value)?;
+            record.refs = record.refs.saturating_sub(1);
+            self.payloads.put(&to_value(&record)?, None).await?;
+        }
+        Ok(())
+    }
+
+    /// Insert an operation, returns `false` if it already existed.
+    async fn insert_operation<E>(
+        &self,
+        hash: Hash,
+        header: &Header<E>,
+        body: Option<&Body>,
+        header_bytes: &[u8],
+        log_id: String,
+    ) -> Result<bool, IndexedDbStoreError> {
+        if self.operation(&hash).await?.is_some() {
+            return Ok(false);
+        }
+
+        let payload = match body {
+            Some(body) => Some(self.retain_payload(body).await?),
+            None => None,
+        };
+        let record = OperationRecord {
+            hash: hash.to_hex(),
+            log_id,
+            public_key: header.public_key.to_hex(),
+            seq_num: sortable(header.seq_num),
+            timestamp: sortable(header.timestamp),
+            header: header_bytes.to_vec(),
+            payload,
+        };
+        self.operations.put(&to_value(&record)?, None).await?;
+
+        let size = header_bytes.len() as u64 + body.map_or(0, |body| body.size());
+        let head_key = key(&[&record.log_id, &record.public_key]);
+        let head = match self.heads.get(head_key).await? {
+            Some(value) => {
+                let mut head: HeadRecord = from_value(value)?;
+                if record.seq_num >= head.seq_num {
+                    head.seq_num = record.seq_num;
+                    head.hash = record.hash;
+                }
+                head.operations += 1;
+                head.size += size;
+                head
+            }
+            None => HeadRecord {
+                log_id: record.log_id,
+                public_key: record.public_key,
+                seq_num: record.seq_num,
+                hash: record.hash,
+                operations: 1,
+                size,
+            },
+        };
+        self.heads.put(&to_value(&head)?, None).await?;
+
+        Ok(true)
+    }
+
+    /// Recalculate the head of a log after operations or payloads were deleted.
+    async fn refresh_head(
+        &self,
+        log_id: &str,
+        public_key: &str,
+    ) -> Result<(), IndexedDbStoreError> {
+        let log = self.log(log_range(log_id, public_key, 0, None)?).await?;
+        let Some(latest) = log.last() else {
+            self.heads.delete(key(&[log_id, public_key])).await?;
+            return Ok(());
+        };
+
+        let mut size = 0;
+        for record in &log {
+            size += record.header.len() as u64;
+            if let Some(body) = self.body(record.payload.as_ref()).await? {
+                size += body.len() as u64;
+            }
+        }
+
+        let head = HeadRecord {
+            log_id: log_id.to_owned(),
+            public_key: public_key.to_owned(),
+            seq_num: latest.seq_num.clone(),
+            hash: latest.hash.clone(),
+            operations: log.len() as u64,
+            size,
+        };
+        self.heads.put(&to_value(&head)?, None).await?;
+
+        Ok(())
+    }
+}
+
+impl<L, E> OperationStore<L, E> for IndexedDbStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = IndexedDbStoreError;
+
+    async fn insert_operation(
+        &mut self,
+        hash: Hash,
+        header: &Header<E>,
+        body: Option<&Body>,
+        header_bytes: &[u8],
+        log_id: &L,
+    ) -> Result<bool, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadWrite)?;
+            let stores = Stores::open(&tx)?;
+            let insertion_occured = stores
+                .insert_operation(hash, header, body, header_bytes, log_id_key(log_id))
+                .await?;
+            tx.done().await?;
+
+            Ok(insertion_occured)
+        })
+        .await
+    }
+
+    async fn insert_operations(
+        &mut self,
+        operations: &[BatchOperation<'_, L, E>],
+    ) -> Result<usize, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadWrite)?;
+            let stores = Stores::open(&tx)?;
+            let mut inserted = 0;
+            for operation in operations {
+                if stores
+                    .insert_operation(
+                        operation.hash,
+                        operation.header,
+                        operation.body,
+                        operation.header_bytes,
+                        log_id_key(operation.log_id),
+                    )
+                    .await?
+                {
+                    inserted += 1;
+                }
+            }
+            tx.done().await?;
+
+            Ok(inserted)
+        })
+        .await
+    }
+
+    async fn get_operation(
+        &self,
+        hash: Hash,
+    ) -> Result<Option<(Header<E>, Option<Body>)>, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadOnly)?;
+            let stores = Stores::open(&tx)?;
+            match stores.operation(&hash).await? {
+                Some(record) => Ok(Some(stores.decode(&record).await?)),
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    async fn get_raw_operation(&self, hash: Hash) -> Result<Option<RawOperation>, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadOnly)?;
+            let stores = Stores::open(&tx)?;
+            match stores.operation(&hash).await? {
+                Some(record) => {
+                    let body = stores.body(record.payload.as_ref()).await?;
+                    Ok(Some((record.header, body)))
+                }
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    async fn has_operation(&self, hash: Hash) -> Result<bool, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadOnly)?;
+            let stores = Stores::open(&tx)?;
+            Ok(stores.operation(&hash).await?.is_some())
+        })
+        .await
+    }
+
+    async fn delete_operation(&mut self, hash: Hash) -> Result<bool, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadWrite)?;
+            let stores = Stores::open(&tx)?;
+            let Some(record) = stores.operation(&hash).await? else {
+                return Ok(false);
+            };
+
+            stores
+                .operations
+                .delete(JsValue::from_str(&record.hash))
+                .await?;
+            if let Some(payload) = &record.payload {
+                stores.release_payload(payload).await?;
+            }
+            stores
+                .refresh_head(&record.log_id, &record.public_key)
+                .await?;
+            tx.done().await?;
+
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn delete_payload(&mut self, hash: Hash) -> Result<bool, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadWrite)?;
+            let stores = Stores::open(&tx)?;
+            let Some(mut record) = stores.operation(&hash).await? else {
+                return Ok(false);
+            };
+            let Some(payload) = record.payload.take() else {
+                return Ok(false);
+            };
+
+            stores.release_payload(&payload).await?;
+            stores.operations.put(&to_value(&record)?, None).await?;
+            stores
+                .refresh_head(&record.log_id, &record.public_key)
+                .await?;
+            tx.done().await?;
+
+            Ok(true)
+        })
+        .await
+    }
+}
+
+impl<L, E> LogStore<L, E> for IndexedDbStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = IndexedDbStoreError;
+
+    async fn get_log(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: Option<u64>,
+    ) -> Result<Option<Vec<(Header<E>, Option<Body>)>>, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadOnly)?;
+            let stores = Stores::open(&tx)?;
+            let range = log_range(
+                &log_id_key(log_id),
+                &public_key.to_hex(),
+                from.unwrap_or(0),
+                None,
+            )?;
+
+            let mut log = Vec::new();
+            for record in stores.log(range).await? {
+                log.push(stores.decode(&record).await?);
+            }
+
+            if log.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(log))
+            }
+        })
+        .await
+    }
+
+    async fn get_raw_log(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: Option<u64>,
+    ) -> Result<Option<Vec<RawOperation>>, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadOnly)?;
+            let stores = Stores::open(&tx)?;
+            let range = log_range(
+                &log_id_key(log_id),
+                &public_key.to_hex(),
+                from.unwrap_or(0),
+                None,
+            )?;
+
+            let mut log = Vec::new();
+            for record in stores.log(range).await? {
+                let body = stores.body(record.payload.as_ref()).await?;
+                log.push((record.header, body));
+            }
+
+            if log.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(log))
+            }
+        })
+        .await
+    }
+
+    async fn latest_operation(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+    ) -> Result<Option<(Header<E>, Option<Body>)>, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadOnly)?;
+            let stores = Stores::open(&tx)?;
+            let head_key = key(&[&log_id_key(log_id), &public_key.to_hex()]);
+            let Some(value) = stores.heads.get(head_key).await? else {
+                return Ok(None);
+            };
+            let head: HeadRecord = from_value(value)?;
+            let hash = Hash::from_str(&head.hash)
+                .map_err(|err| IndexedDbStoreError::InvalidValue(err.to_string()))?;
+
+            match stores.operation(&hash).await? {
+                Some(record) => Ok(Some(stores.decode(&record).await?)),
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    async fn delete_operations(
+        &mut self,
+        public_key: &PublicKey,
+        log_id: &L,
+        before: u64,
+    ) -> Result<bool, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadWrite)?;
+            let stores = Stores::open(&tx)?;
+            let log_id = log_id_key(log_id);
+            let public_key = public_key.to_hex();
+
+            let log = stores
+                .log(log_range(&log_id, &public_key, 0, Some(before))?)
+                .await?;
+            for record in &log {
+                stores
+                    .operations
+                    .delete(JsValue::from_str(&record.hash))
+                    .await?;
+                if let Some(payload) = &record.payload {
+                    stores.release_payload(payload).await?;
+                }
+            }
+
+            if !log.is_empty() {
+                stores.refresh_head(&log_id, &public_key).await?;
+            }
+            tx.done().await?;
+
+            Ok(!log.is_empty())
+        })
+        .await
+    }
+
+    async fn delete_payloads(
+        &mut self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: u64,
+        to: u64,
+    ) -> Result<bool, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadWrite)?;
+            let stores = Stores::open(&tx)?;
+            let log_id = log_id_key(log_id);
+            let public_key = public_key.to_hex();
+
+            let mut deleted = false;
+            let log = stores
+                .log(log_range(&log_id, &public_key, from, Some(to))?)
+                .await?;
+            for mut record in log {
+                if let Some(payload) = record.payload.take() {
+                    stores.release_payload(&payload).await?;
+                    stores.operations.put(&to_value(&record)?, None).await?;
+                    deleted = true;
+                }
+            }
+
+            if deleted {
+                stores.refresh_head(&log_id, &public_key).await?;
+            }
+            tx.done().await?;
+
+            Ok(deleted)
+        })
+        .await
+    }
+
+    async fn get_log_heights(&self, log_id: &L) -> Result<Vec<(PublicKey, u64)>, Self::Error> {
+        let heads = self.log_heads(log_id).await?;
+        Ok(heads
+            .into_iter()
+            .map(|head| (head.public_key, head.seq_num))
+            .collect())
+    }
+
+    async fn log_heads(&self, log_id: &L) -> Result<Vec<LogHead>, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadOnly)?;
+            let stores = Stores::open(&tx)?;
+            let range = KeyRange::only(&JsValue::from_str(&log_id_key(log_id)))?;
+
+            let mut heads = Vec::new();
+            for value in stores
+                .heads
+                .index(LOG_HEADS_INDEX)?
+                .get_all(Some(range), None)
+                .await?
+            {
+                let head: HeadRecord = from_value(value)?;
+                heads.push(LogHead {
+                    public_key: PublicKey::from_str(&head.public_key)
+                        .map_err(|err| IndexedDbStoreError::InvalidValue(err.to_string()))?,
+                    seq_num: parse_sortable(&head.seq_num)?,
+                    hash: Hash::from_str(&head.hash)
+                        .map_err(|err| IndexedDbStoreError::InvalidValue(err.to_string()))?,
+                    operations: head.operations,
+                    size: head.size,
+                });
+            }
+
+            Ok(heads)
+        })
+        .await
+    }
+}
+
+impl<L, E> QueryStore<L, E> for IndexedDbStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = IndexedDbStoreError;
+
+    async fn query(&self, query: &OperationQuery<L>) -> Result<QueryPage<E>, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadOnly)?;
+            let stores = Stores::open(&tx)?;
+
+            // Narrow down the scanned range of the timestamp index as far as possible, remaining
+            // filters are checked against every operation in that range. The empty string sorts
+            // before all hashes.
+            let timestamp_range = query.timestamp_filter();
+            let start = timestamp_range.map_or(0, |range| range.start);
+            let (lower, lower_open) = match query.cursor() {
+                Some(cursor) if cursor.timestamp >= start => (
+                    key(&[&sortable(cursor.timestamp), &cursor.hash.to_hex()]),
+                    true,
+                ),
+                _ => (key(&[&sortable(start), ""]), false),
+            };
+            let range = match timestamp_range {
+                Some(range) => KeyRange::bound(
+                    &lower,
+                    &key(&[&sortable(range.end), ""]),
+                    Some(lower_open),
+                    Some(true),
+                )?,
+                None => KeyRange::lower_bound(&lower, Some(lower_open))?,
+            };
+
+            let log_ids: Vec<String> = query.log_ids_filter().iter().map(log_id_key).collect();
+            let limit = query.page_limit().map(|limit| limit.saturating_add(1));
+
+            let mut result = Vec::new();
+            for value in stores
+                .operations
+                .index(TIMESTAMPS_INDEX)?
+                .get_all(Some(range), None)
+                .await?
+            {
+                if limit.is_some_and(|limit| result.len() >= limit) {
+                    break;
+                }
+
+                let record: OperationRecord = from_value(value)?;
+                if !log_ids.is_empty() && !log_ids.contains(&record.log_id) {
+                    continue;
+                }
+
+                let hash = Hash::from_str(&record.hash)
+                    .map_err(|err| IndexedDbStoreError::InvalidValue(err.to_string()))?;
+                let (header, body) = stores.decode(&record).await?;
+                if let Some(cut) = query.cut_filter()
+                    && !cut.contains_with(&header.public_key, header.seq_num, |cut_log_id| {
+                        log_id_key(cut_log_id) == record.log_id
+                    })
+                {
+                    continue;
+                }
+
+                if query.matches_header(&hash, &header) {
+                    result.push((header, body));
+                }
+            }
+
+            Ok(query.paginate(result))
+        })
+        .await
+    }
+}
+
+impl<L, E> PayloadStore for IndexedDbStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = IndexedDbStoreError;
+
+    async fn gc(&mut self) -> Result<u64, Self::Error> {
+        SendWrapper::new(async {
+            let tx = self.transaction(TransactionMode::ReadWrite)?;
+            let stores = Stores::open(&tx)?;
+
+            let mut reclaimed = 0;
+            for value in stores.payloads.get_all(None, None).await? {
+                let record: PayloadRecord = from_value(value)?;
+                if record.refs == 0 {
+                    stores
+                        .payloads
+                        .delete(JsValue::from_str(&record.hash))
+                        .await?;
+                    reclaimed += record.bytes.len() as u64;
+                }
+            }
+            tx.done().await?;
+
+            Ok(reclaimed)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::{Body, PrivateKey};
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    use crate::conformance;
+    use crate::{LogStore, OperationStore};
+
+    use super::IndexedDbStore;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    async fn store(name: &str) -> IndexedDbStore<u64> {
+        IndexedDbStore::<u64>::delete(name).await.unwrap();
+        IndexedDbStore::open(name).await.unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn conformance() {
+        conformance::run(store("conformance").await).await;
+    }
+
+    #[wasm_bindgen_test]
+    async fn collect_garbage() {
+        let mut store = store("collect_garbage").await;
+        conformance::collect_garbage(&mut store).await;
+    }
+
+    #[wasm_bindgen_test]
+    async fn persisted_across_instances() {
+        let mut store = store("persisted_across_instances").await;
+
+        let private_key = PrivateKey::new();
+        let body = Body::new("hello!".as_bytes());
+        let (hash, header, header_bytes) =
+            conformance::create_operation(&private_key, &body, 0, 0, None);
+        store
+            .insert_operation(hash, &header, Some(&body), &header_bytes, &0)
+            .await
+            .unwrap();
+        drop(store);
+
+        let store = IndexedDbStore::<u64>::open("persisted_across_instances")
+            .await
+            .unwrap();
+        assert!(store.has_operation(hash).await.unwrap());
+        assert_eq!(
+            store.get_log_heights(&0).await.unwrap(),
+            vec![(private_key.public_key(), 0)]
+        );
+    }
+}
@@ -0,0 +1,323 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Verify the integrity of stored operations.
+//!
+//! Stores trust the operations they were given and don't re-check them on reads. Corruption on
+//! disk, for example caused by faulty hardware, would hence go unnoticed and be passed on to
+//! peers during sync. `verify` walks through the given logs and checks for every operation that
+//!
+//! 1. the header can be decoded and is stored under its hash,
+//! 2. the signature is valid and the body matches the payload hash and size of the header, and
+//! 3. the backlink points at the operation before it.
+//!
+//! Corrupted operations are reported and can optionally be quarantined: operations with a
+//! corrupted body keep their header and only lose their payload, which can be fetched again from
+//! other peers, all other corrupted operations are deleted.
+//!
+//! A full verification reads every operation and can take a while on large stores. For a quick
+//! check on every startup only the latest two operations of every log are verified, since they
+//! are sent to peers first.
+use p2panda_core::cbor::{DecodeError, decode_cbor};
+use p2panda_core::{
+    Extensions, Hash, Header, Operation, OperationError, PublicKey, validate_backlink,
+    validate_operation,
+};
+
+use crate::{LogId, LogStore, OperationStore};
+
+/// Options for `verify`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyOptions {
+    quick: bool,
+    quarantine: bool,
+}
+
+impl VerifyOptions {
+    /// Verify all operations and only report corrupted ones.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only verify the latest two operations of every log.
+    pub fn quick(mut self) -> Self {
+        self.quick = true;
+        self
+    }
+
+    /// Remove corrupted operations or their payloads from the store.
+    pub fn quarantine(mut self) -> Self {
+        self.quarantine = true;
+        self
+    }
+}
+
+/// Reason why a stored operation is considered corrupted.
+#[derive(Debug)]
+pub enum Corruption {
+    /// Header bytes can't be decoded.
+    InvalidHeader(DecodeError),
+
+    /// Header bytes don't match the hash the operation is stored under.
+    HashMismatch,
+
+    /// Signature, payload or backlink of the operation is invalid.
+    InvalidOperation(OperationError),
+}
+
+/// Corrupted operation found during verification.
+#[derive(Debug)]
+pub struct CorruptedOperation<L> {
+    pub log_id: L,
+
+    pub public_key: PublicKey,
+
+    /// Hash the operation is stored under, `None` if it couldn't be determined.
+    pub hash: Option<Hash>,
+
+    pub corruption: Corruption,
+
+    /// `true` if the operation or its payload was removed from the store.
+    pub quarantined: bool,
+}
+
+/// Summary of a verification run.
+#[derive(Debug)]
+pub struct VerifyReport<L> {
+    /// Number of verified operations.
+    pub operations: usize,
+
+    /// Operations which failed verification.
+    pub corrupted: Vec<CorruptedOperation<L>>,
+}
+
+impl<L> VerifyReport<L> {
+    /// Returns `true` if no corrupted operations were found.
+    pub fn is_ok(&self) -> bool {
+        self.corrupted.is_empty()
+    }
+}
+
+/// Verify hashes, signatures, payloads and backlinks of all operations in the given logs.
+///
+/// Since stores do not offer a way to enumerate all log ids, the caller needs to pass in all log
+/// ids which should be verified.
+pub async fn verify<L, E, S>(
+    store: &mut S,
+    log_ids: &[L],
+    options: &VerifyOptions,
+) -> Result<VerifyReport<L>, <S as OperationStore<L, E>>::Error>
+where
+    L: LogId,
+    E: Extensions,
+    S: OperationStore<L, E> + LogStore<L, E, Error = <S as OperationStore<L, E>>::Error>,
+{
+    let mut report = VerifyReport {
+        operations: 0,
+        corrupted: Vec::new(),
+    };
+
+    for log_id in log_ids {
+        for head in store.log_heads(log_id).await? {
+            let from = options.quick.then(|| head.seq_num.saturating_sub(1));
+            let Some(log) = store.get_raw_log(&head.public_key, log_id, from).await? else {
+                continue;
+            };
+
+            let (headers, mut errors): (Vec<Option<Header<E>>>, Vec<Option<DecodeError>>) = log
+                .iter()
+                .map(|(header_bytes, _)| match decode_cbor(&header_bytes[..]) {
+                    Ok(header) => (Some(header), None),
+                    Err(err) => (None, Some(err)),
+                })
+                .unzip();
+
+            for (index, (header_bytes, body)) in log.into_iter().enumerate() {
+                report.operations += 1;
+
+                // The hash the operation is stored under is referenced by the backlink of the
+                // following operation or, for the latest operation, by the log head.
+                let stored_hash = match headers.get(index + 1) {
+                    Some(Some(next)) => next.backlink,
+                    Some(None) => None,
+                    None => Some(head.hash),
+                };
+
+                let hash = Hash::new(&header_bytes);
+                let corruption = match &headers[index] {
+                    None => errors[index].take().map(Corruption::InvalidHeader),
+                    Some(_) if !store.has_operation(hash).await? => Some(Corruption::HashMismatch),
+                    Some(header) => {
+                        let operation = Operation {
+                            hash,
+                            header: header.to_owned(),
+                            body: body.map(Into::into),
+                        };
+                        let previous = index
+                            .checked_sub(1)
+                            .and_then(|previous| headers[previous].as_ref());
+                        validate_operation(&operation)
+                            .and_then(|_| match previous {
+                                Some(previous) => validate_backlink(previous, header),
+                                None => Ok(()),
+                            })
+                            .err()
+                            .map(Corruption::InvalidOperation)
+                    }
+                };
+
+                let Some(corruption) = corruption else {
+                    continue;
+                };
+
+                let hash = match &corruption {
+                    Corruption::InvalidHeader(_) | Corruption::HashMismatch => stored_hash,
+                    Corruption::InvalidOperation(_) => Some(hash),
+                };
+                let quarantined = match hash {
+                    Some(hash) if options.quarantine => match &corruption {
+                        Corruption::InvalidOperation(OperationError::PayloadMismatch) => {
+                            store.delete_payload(hash).await?
+                        }
+                        _ => store.delete_operation(hash).await?,
+                    },
+                    _ => false,
+                };
+
+                report.corrupted.push(CorruptedOperation {
+                    log_id: log_id.to_owned(),
+                    public_key: head.public_key,
+                    hash,
+                    corruption,
+                    quarantined,
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use p2panda_core::{Body, Hash, OperationError, PrivateKey};
+
+    use crate::conformance::{create_operation, insert_log};
+    use crate::{LogStore, MemoryStore, OperationStore};
+
+    use super::{Corruption, VerifyOptions, verify};
+
+    #[tokio::test]
+    async fn intact_logs() {
+        let mut store = MemoryStore::<u64>::new();
+        insert_log(&mut store, &PrivateKey::new(), 0, 5).await;
+        insert_log(&mut store, &PrivateKey::new(), 0, 3).await;
+
+        let report = verify(&mut store, &[0], &VerifyOptions::new())
+            .await
+            .unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.operations, 8);
+
+        let report = verify(&mut store, &[0], &VerifyOptions::new().quick())
+            .await
+            .unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.operations, 4);
+    }
+
+    #[tokio::test]
+    async fn report_corrupted_operations() {
+        let private_key = PrivateKey::new();
+        let mut store = MemoryStore::<u64>::new();
+        let hashes = insert_log(&mut store, &private_key, 0, 2).await;
+
+        // Body doesn't match the header anymore.
+        let body = Body::new("operation 2".as_bytes());
+        let (hash, header, header_bytes) =
+            create_operation(&private_key, &body, 2, 2, Some(hashes[1]));
+        store
+            .insert_operation(
+                hash,
+                &header,
+                Some(&Body::new("corrupted".as_bytes())),
+                &header_bytes,
+                &0,
+            )
+            .await
+            .unwrap();
+
+        // Header was changed after signing.
+        let body = Body::new("operation 3".as_bytes());
+        let (_, mut header, _) = create_operation(&private_key, &body, 3, 3, Some(hash));
+        header.timestamp = 100;
+        let header_bytes = header.to_bytes();
+        store
+            .insert_operation(header.hash(), &header, Some(&body), &header_bytes, &0)
+            .await
+            .unwrap();
+
+        let report = verify(&mut store, &[0], &VerifyOptions::new())
+            .await
+            .unwrap();
+        assert_eq!(report.operations, 4);
+        assert_eq!(report.corrupted.len(), 2);
+        assert_eq!(report.corrupted[0].hash, Some(hash));
+        assert!(matches!(
+            report.corrupted[0].corruption,
+            Corruption::InvalidOperation(OperationError::PayloadMismatch)
+        ));
+        assert_eq!(report.corrupted[1].hash, Some(header.hash()));
+        assert!(matches!(
+            report.corrupted[1].corruption,
+            Corruption::InvalidOperation(OperationError::SignatureMismatch)
+        ));
+        assert!(!report.corrupted[0].quarantined);
+
+        // Nothing was removed from the store.
+        assert_eq!(
+            store
+                .get_log(&private_key.public_key(), &0, None)
+                .await
+                .unwrap()
+                .unwrap()
+                .len(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn quarantine_corrupted_operations() {
+        let private_key = PrivateKey::new();
+        let mut store = MemoryStore::<u64>::new();
+        let hashes = insert_log(&mut store, &private_key, 0, 2).await;
+
+        // Operation is stored under a different hash than its header.
+        let body = Body::new("operation 2".as_bytes());
+        let (_, header, header_bytes) =
+            create_operation(&private_key, &body, 2, 2, Some(hashes[1]));
+        let stored_hash = Hash::new("something else");
+        store
+            .insert_operation(stored_hash, &header, Some(&body), &header_bytes, &0)
+            .await
+            .unwrap();
+
+        let report = verify(&mut store, &[0], &VerifyOptions::new().quarantine())
+            .await
+            .unwrap();
+        assert_eq!(report.corrupted.len(), 1);
+        assert_eq!(report.corrupted[0].hash, Some(stored_hash));
+        assert!(matches!(
+            report.corrupted[0].corruption,
+            Corruption::HashMismatch
+        ));
+        assert!(report.corrupted[0].quarantined);
+        assert!(!store.has_operation(stored_hash).await.unwrap());
+
+        // After quarantining the store is intact again.
+        let report = verify(&mut store, &[0], &VerifyOptions::new())
+            .await
+            .unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.operations, 2);
+    }
+}
@@ -26,11 +26,85 @@
 //! A SQLite storage solution is provided in the form of a `SqliteStore` which implements both
 //! `OperationStore` and `LogStore`. The store is gated by the `sqlite` feature flag and is
 //! disabled by default.
+//!
+//! An embedded key-value storage solution is provided in the form of a `RedbStore` which
+//! implements both `OperationStore` and `LogStore`. It is written in pure Rust and can be used on
+//! platforms where shipping SQLite is awkward. The store is gated by the `redb` feature flag and
+//! is disabled by default.
+//!
+//! Browser nodes can persist operations in IndexedDB with the `IndexedDbStore`, which implements
+//! the same traits. It is only compiled for `wasm32-unknown-unknown`, gated by the `wasm` feature
+//! flag and disabled by default.
+//!
+//! All backends are checked against the same suite of tests in the `conformance` module, which is
+//! available with the `test_utils` feature flag for custom store implementations.
+//!
+//! All stores can be cloned and shared between concurrent writers, for example the sync handler,
+//! the gossip ingester and the application itself. Every method call is atomic: its writes are
+//! applied all at once or not at all and its reads see a consistent snapshot of the store. Writes
+//! are serialised, while readers never see partially applied writes. Inserting the same operation
+//! from several writers at once is safe, it is stored only once and only one of the calls returns
+//! `true`. No consistency is guaranteed across multiple calls, sequences like "look up the latest
+//! operation, then append to the log" need to be coordinated by the caller.
+//!
+//! Besides lookups by hash and log, stores implementing `QueryStore` answer range and filter
+//! queries over operations by author, log ids, timestamp or sequence number, see the `query`
+//! module.
+//!
+//! Operations with the same body share one reference counted payload in all provided stores.
+//! Payloads which are not referenced anymore are removed with `PayloadStore::gc`.
+//!
+//! Old history can be removed from logs with the utilities of the `prune` module, which keep a
+//! checkpoint header to preserve backlink integrity.
+//!
+//! Stores can be wrapped in a `WatchedStore` to subscribe to inserted and deleted operations, see
+//! the `watch` module.
+//!
+//! Operation bodies can be encrypted at rest by wrapping any store in an `EncryptedStore`, which is
+//! gated by the `encryption` feature flag, see the `encrypted` module.
+//!
+//! Stored operations can be checked for corruption with `integrity::verify`, which re-checks
+//! hashes, signatures and backlinks and optionally quarantines corrupted entries.
+//!
+//! Nodes serving untrusted light clients can prove that stored operations are part of their
+//! author's log with `proof::log_proof`.
+//!
+//! Operations and logs can be copied from one store backend to another with the utilities of the
+//! `migrate` module, for example when moving from a `MemoryStore` to a `SqliteStore`.
+//!
+//! Logs can be exported to and imported from a portable, self-contained archive for backups or
+//! offline transfer between nodes with the utilities of the `archive` module, which is gated by
+//! the `archive` feature flag.
+//!
+//! Stores can be wrapped in a `MeteredStore` to measure the latency of inserts and reads and the
+//! size of the store, and to log slow queries, see the `metrics` module.
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(any(test, feature = "test_utils"))]
+pub mod conformance;
+#[cfg(feature = "encryption")]
+pub mod encrypted;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod indexed_db;
+pub mod integrity;
 #[cfg(feature = "memory")]
 pub mod memory;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod metrics;
+pub mod migrate;
+pub mod proof;
+pub mod prune;
+pub mod query;
+#[cfg(feature = "redb")]
+pub mod redb;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
+pub mod watch;
 
+#[cfg(feature = "redb")]
+pub use crate::redb::{RedbStore, RedbStoreError};
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub use indexed_db::{IndexedDbStore, IndexedDbStoreError};
 #[cfg(feature = "memory")]
 pub use memory::MemoryStore;
 #[cfg(feature = "sqlite")]
@@ -40,6 +114,8 @@ use std::fmt::{Debug, Display};
 
 use p2panda_core::{Body, Hash, Header, PublicKey, RawOperation};
 
+use crate::query::{OperationQuery, QueryPage};
+
 /// Uniquely identify a single-author log.
 ///
 /// The `LogId` exists purely to group a set of operations and is intended to be implemented for
@@ -69,6 +145,111 @@ pub trait LogId: Clone + Debug + Eq + std::hash::Hash {}
 
 impl<T> LogId for T where T: Clone + Debug + Eq + std::hash::Hash {}
 
+/// Operation to be inserted as part of a batch, see `OperationStore::insert_operations`.
+#[derive(Debug)]
+pub struct BatchOperation<'a, LogId, Extensions> {
+    pub hash: Hash,
+    pub header: &'a Header<Extensions>,
+    pub body: Option<&'a Body>,
+    pub header_bytes: &'a [u8],
+    pub log_id: &'a LogId,
+}
+
+/// Trade-off between write throughput and durability of persistent stores.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Every write is persisted before it returns.
+    #[default]
+    Immediate,
+
+    /// Writes are persisted eventually.
+    ///
+    /// A crash might lose the latest writes, but it never corrupts the store. Stores offer a
+    /// `flush` method to explicitly persist all previous writes, for example after a sync session
+    /// ended.
+    Eventual,
+}
+
+/// Metadata of an author's log, maintained by the store on every write.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogHead {
+    pub public_key: PublicKey,
+
+    /// Sequence number of the latest operation.
+    pub seq_num: u64,
+
+    /// Hash of the latest operation.
+    pub hash: Hash,
+
+    /// Number of operations in the log.
+    pub operations: u64,
+
+    /// Size of all headers and stored bodies of the log in bytes.
+    pub size: u64,
+}
+
+/// Get the heads of all logs stored under the passed log id by scanning them.
+///
+/// Built on `get_log_heights` and `get_raw_log` for stores which don't maintain log heads
+/// themselves, every log is read to count its operations and bytes.
+pub async fn scan_log_heads<S, L, E>(store: &S, log_id: &L) -> Result<Vec<LogHead>, S::Error>
+where
+    S: LocalLogStore<L, E>,
+{
+    let mut heads = Vec::new();
+    for (public_key, seq_num) in store.get_log_heights(log_id).await? {
+        let log = store
+            .get_raw_log(&public_key, log_id, None)
+            .await?
+            .unwrap_or_default();
+        let Some((header_bytes, _)) = log.last() else {
+            continue;
+        };
+        heads.push(LogHead {
+            public_key,
+            seq_num,
+            hash: Hash::new(header_bytes),
+            operations: log.len() as u64,
+            size: log
+                .iter()
+                .map(|(header_bytes, body)| {
+                    (header_bytes.len() + body.as_ref().map_or(0, Vec::len)) as u64
+                })
+                .sum(),
+        });
+    }
+    Ok(heads)
+}
+
+/// Insert a batch of operations by inserting them one by one.
+///
+/// Built on `insert_operation` for stores which can't insert many operations with a single
+/// transaction. Operations inserted before a failing one remain in the store.
+pub async fn insert_operations_sequentially<S, L, E>(
+    store: &mut S,
+    operations: &[BatchOperation<'_, L, E>],
+) -> Result<usize, S::Error>
+where
+    S: LocalOperationStore<L, E>,
+{
+    let mut inserted = 0;
+    for operation in operations {
+        if store
+            .insert_operation(
+                operation.hash,
+                operation.header,
+                operation.body,
+                operation.header_bytes,
+                operation.log_id,
+            )
+            .await?
+        {
+            inserted += 1;
+        }
+    }
+    Ok(inserted)
+}
+
 /// Interface for storing, deleting and querying operations.
 ///
 /// Two variants of the trait are provided: one which is thread-safe (implementing `Sync`) and one
@@ -90,6 +271,21 @@ pub trait LocalOperationStore<LogId, Extensions>: Clone {
         log_id: &LogId,
     ) -> Result<bool, Self::Error>;
 
+    /// Insert many operations at once.
+    ///
+    /// Implementations insert the whole batch with a single lock acquisition or transaction,
+    /// which is considerably faster than inserting operations one by one, for example when
+    /// persisting the result of a large sync session.
+    ///
+    /// Returns the number of operations which were inserted.
+    ///
+    /// This method is required since `0.4.0`, which is a breaking change for custom stores. Stores
+    /// without transactions can implement it with [`insert_operations_sequentially`].
+    async fn insert_operations(
+        &mut self,
+        operations: &[BatchOperation<'_, LogId, Extensions>],
+    ) -> Result<usize, Self::Error>;
+
     /// Get an operation.
     async fn get_operation(
         &self,
@@ -154,6 +350,15 @@ pub trait LocalLogStore<LogId, Extensions> {
     /// Get the log heights of all logs, by any author, which are stored under the passed log id.
     async fn get_log_heights(&self, log_id: &LogId) -> Result<Vec<(PublicKey, u64)>, Self::Error>;
 
+    /// Get the heads of all logs, by any author, which are stored under the passed log id.
+    ///
+    /// Heads are kept up-to-date by the store on every write, looking them up doesn't require
+    /// scanning the logs.
+    ///
+    /// This method is required since `0.4.0`, which is a breaking change for custom stores. Stores
+    /// which don't maintain heads can implement it with [`scan_log_heads`].
+    async fn log_heads(&self, log_id: &LogId) -> Result<Vec<LogHead>, Self::Error>;
+
     /// Get only the latest operation from an authors' log.
     ///
     /// Returns None when the author or a log with the requested id was not found.
@@ -189,3 +394,56 @@ pub trait LocalLogStore<LogId, Extensions> {
         to: u64,
     ) -> Result<bool, Self::Error>;
 }
+
+/// Interface for range and filter queries over operations.
+///
+/// Two variants of the trait are provided: one which is thread-safe (implementing `Sync`) and one
+/// which is purely intended for single-threaded execution contexts.
+#[trait_variant::make(QueryStore: Send)]
+pub trait LocalQueryStore<LogId, Extensions> {
+    type Error: Display + Debug;
+
+    /// Get a page of operations matching the query, ordered by timestamp and hash.
+    ///
+    /// The returned page contains a cursor to request the next page with when more operations
+    /// match the query.
+    async fn query(
+        &self,
+        query: &OperationQuery<LogId>,
+    ) -> Result<QueryPage<Extensions>, Self::Error>;
+}
+
+/// Interface for stores which share payloads between operations with the same body.
+///
+/// Payloads are stored once per body hash and reference counted by the operations using them.
+/// Deleting an operation or its payload only releases the reference, other operations with the
+/// same body keep access to it. Payloads without any references remain in the store until they
+/// are removed with `gc`.
+///
+/// Two variants of the trait are provided: one which is thread-safe (implementing `Sync`) and one
+/// which is purely intended for single-threaded execution contexts.
+#[trait_variant::make(PayloadStore: Send)]
+pub trait LocalPayloadStore {
+    type Error: Display + Debug;
+
+    /// Remove all payloads which are not referenced by any operation anymore.
+    ///
+    /// Returns the number of reclaimed payload bytes.
+    async fn gc(&mut self) -> Result<u64, Self::Error>;
+}
+
+/// Interface for stores reporting how much space they occupy.
+///
+/// Two variants of the trait are provided: one which is thread-safe (implementing `Sync`) and one
+/// which is purely intended for single-threaded execution contexts.
+#[trait_variant::make(SizedStore: Send)]
+pub trait LocalSizedStore {
+    type Error: Display + Debug;
+
+    /// Returns the size of the store in bytes.
+    ///
+    /// Persistent stores return the size of their database, including free space which was not
+    /// returned to the file system yet. The in-memory store returns the size of all stored
+    /// headers and payloads.
+    async fn size(&self) -> Result<u64, Self::Error>;
+}
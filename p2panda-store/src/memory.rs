@@ -8,20 +8,31 @@ use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use p2panda_core::{Body, Extensions, Hash, Header, PublicKey, RawOperation};
 
-use crate::{LogId, LogStore, OperationStore};
+use crate::query::{Cursor, OperationQuery, QueryPage};
+use crate::{
+    BatchOperation, LogHead, LogId, LogStore, OperationStore, PayloadStore, QueryStore, SizedStore,
+};
 
 type SeqNum = u64;
 type Timestamp = u64;
 type RawHeader = Vec<u8>;
 
 type LogMeta = (SeqNum, Timestamp, Hash);
-type StoredOperation<L, E> = (L, Header<E>, Option<Body>, RawHeader);
+type StoredOperation<L, E> = (L, Header<E>, Option<PayloadHash>, RawHeader);
+
+/// Hash of a body, used as the key of a payload shared by operations.
+type PayloadHash = Hash;
+
+/// Payload and the number of operations referencing it.
+type StoredPayload = (Body, usize);
 
 /// An in-memory store for core p2panda data types: `Operation` and `Log`.
 #[derive(Clone, Debug)]
 pub struct InnerMemoryStore<L, E> {
     operations: HashMap<Hash, StoredOperation<L, E>>,
     logs: HashMap<(PublicKey, L), BTreeSet<LogMeta>>,
+    payloads: HashMap<PayloadHash, StoredPayload>,
+    heads: HashMap<(PublicKey, L), LogHead>,
 }
 
 /// An in-memory store for core p2panda data types: `Operation` and log.
@@ -40,6 +51,8 @@ impl<L, E> MemoryStore<L, E> {
         let inner = InnerMemoryStore {
             operations: HashMap::new(),
             logs: HashMap::new(),
+            payloads: HashMap::new(),
+            heads: HashMap::new(),
         };
 
         Self {
@@ -48,6 +61,117 @@ impl<L, E> MemoryStore<L, E> {
     }
 }
 
+impl<L, E> InnerMemoryStore<L, E>
+where
+    L: LogId,
+    E: Extensions,
+{
+    fn insert_operation(
+        &mut self,
+        hash: Hash,
+        header: &Header<E>,
+        body: Option<&Body>,
+        header_bytes: &[u8],
+        log_id: &L,
+    ) -> bool {
+        let key = (header.public_key, log_id.to_owned());
+        let log_meta = (header.seq_num, header.timestamp, hash);
+        let log = self.logs.entry(key.clone()).or_default();
+        let insertion_occured = log.insert(log_meta);
+
+        if insertion_occured {
+            let (seq_num, _, latest_hash) = *log.last().expect("log contains inserted operation");
+            let head = self.heads.entry(key).or_insert(LogHead {
+                public_key: header.public_key,
+                seq_num,
+                hash: latest_hash,
+                operations: 0,
+                size: 0,
+            });
+            head.seq_num = seq_num;
+            head.hash = latest_hash;
+            head.operations += 1;
+            head.size += header_bytes.len() as u64 + body.map_or(0, |body| body.size());
+
+            let payload_hash = body.map(|body| self.retain_payload(body));
+            let entry = (
+                log_id.to_owned(),
+                header.to_owned(),
+                payload_hash,
+                header_bytes.to_vec(),
+            );
+            self.operations.insert(hash, entry);
+        }
+
+        insertion_occured
+    }
+
+    /// Recalculate the head of a log after operations or payloads were deleted from it.
+    fn refresh_head(&mut self, public_key: &PublicKey, log_id: &L) {
+        let key = (*public_key, log_id.to_owned());
+        let Some((seq_num, _, hash)) = self.logs.get(&key).and_then(|log| log.last()).copied()
+        else {
+            self.heads.remove(&key);
+            return;
+        };
+
+        let log = self.logs.get(&key).expect("log exists");
+        let size = log
+            .iter()
+            .filter_map(|(_, _, hash)| self.operations.get(hash))
+            .map(|(_, _, body, header_bytes)| {
+                header_bytes.len() as u64 + self.body(body).map_or(0, |body| body.size())
+            })
+            .sum();
+
+        self.heads.insert(
+            key,
+            LogHead {
+                public_key: *public_key,
+                seq_num,
+                hash,
+                operations: log.len() as u64,
+                size,
+            },
+        );
+    }
+}
+
+impl<L, E> InnerMemoryStore<L, E> {
+    /// Store a payload or increase the reference count if it already exists.
+    fn retain_payload(&mut self, body: &Body) -> PayloadHash {
+        let payload_hash = body.hash();
+        self.payloads
+            .entry(payload_hash)
+            .or_insert_with(|| (body.to_owned(), 0))
+            .1 += 1;
+        payload_hash
+    }
+
+    /// Decrease the reference count of a payload, it is only removed during garbage collection.
+    fn release_payload(&mut self, payload_hash: Option<PayloadHash>) {
+        if let Some(payload_hash) = payload_hash
+            && let Some((_, refs)) = self.payloads.get_mut(&payload_hash)
+        {
+            *refs = refs.saturating_sub(1);
+        }
+    }
+
+    fn body(&self, payload_hash: &Option<PayloadHash>) -> Option<Body> {
+        payload_hash
+            .as_ref()
+            .and_then(|payload_hash| self.payloads.get(payload_hash))
+            .map(|(body, _)| body.to_owned())
+    }
+
+    fn raw_body(&self, payload_hash: &Option<PayloadHash>) -> Option<Vec<u8>> {
+        payload_hash
+            .as_ref()
+            .and_then(|payload_hash| self.payloads.get(payload_hash))
+            .map(|(body, _)| body.to_bytes())
+    }
+}
+
 impl<T> Default for MemoryStore<T, ()> {
     fn default() -> Self {
         Self::new()
@@ -85,44 +209,49 @@ where
         header_bytes: &[u8],
         log_id: &L,
     ) -> Result<bool, Self::Error> {
-        let mut store = self.write_store();
-
-        let log_meta = (header.seq_num, header.timestamp, hash);
-        let insertion_occured = store
-            .logs
-            .entry((header.public_key, log_id.to_owned()))
-            .or_default()
-            .insert(log_meta);
+        Ok(self
+            .write_store()
+            .insert_operation(hash, header, body, header_bytes, log_id))
+    }
 
-        if insertion_occured {
-            let entry = (
-                log_id.to_owned(),
-                header.to_owned(),
-                body.cloned(),
-                header_bytes.to_vec(),
-            );
-            store.operations.insert(hash, entry);
-        }
+    async fn insert_operations(
+        &mut self,
+        operations: &[BatchOperation<'_, L, E>],
+    ) -> Result<usize, Self::Error> {
+        let mut store = self.write_store();
+        let inserted = operations
+            .iter()
+            .filter(|operation| {
+                store.insert_operation(
+                    operation.hash,
+                    operation.header,
+                    operation.body,
+                    operation.header_bytes,
+                    operation.log_id,
+                )
+            })
+            .count();
 
-        Ok(insertion_occured)
+        Ok(inserted)
     }
 
     async fn get_operation(
         &self,
         hash: Hash,
     ) -> Result<Option<(Header<E>, Option<Body>)>, Self::Error> {
-        match self.read_store().operations.get(&hash) {
-            Some((_, header, body, _)) => Ok(Some((header.clone(), body.clone()))),
+        let store = self.read_store();
+        match store.operations.get(&hash) {
+            Some((_, header, body, _)) => Ok(Some((header.clone(), store.body(body)))),
             None => Ok(None),
         }
     }
 
     async fn get_raw_operation(&self, hash: Hash) -> Result<Option<RawOperation>, Self::Error> {
-        match self.read_store().operations.get(&hash) {
-            Some((_, _, body, header_bytes)) => Ok(Some((
-                header_bytes.clone(),
-                body.as_ref().map(|body| body.to_bytes()),
-            ))),
+        let store = self.read_store();
+        match store.operations.get(&hash) {
+            Some((_, _, body, header_bytes)) => {
+                Ok(Some((header_bytes.clone(), store.raw_body(body))))
+            }
             None => Ok(None),
         }
     }
@@ -133,9 +262,10 @@ where
 
     async fn delete_operation(&mut self, hash: Hash) -> Result<bool, Self::Error> {
         let mut store = self.write_store();
-        let Some((_, header, _, _)) = store.operations.remove(&hash) else {
+        let Some((log_id, header, body, _)) = store.operations.remove(&hash) else {
             return Ok(false);
         };
+        store.release_payload(body);
         store.logs = store
             .logs
             .clone()
@@ -149,13 +279,18 @@ where
                 }
             })
             .collect();
+        store.refresh_head(&header.public_key, &log_id);
 
         Ok(true)
     }
 
     async fn delete_payload(&mut self, hash: Hash) -> Result<bool, Self::Error> {
-        if let Some(operation) = self.write_store().operations.get_mut(&hash) {
-            operation.2 = None;
+        let mut store = self.write_store();
+        if let Some(operation) = store.operations.get_mut(&hash) {
+            let body = operation.2.take();
+            let (log_id, public_key) = (operation.0.clone(), operation.1.public_key);
+            store.release_payload(body);
+            store.refresh_head(&public_key, &log_id);
             Ok(true)
         } else {
             Ok(false)
@@ -185,14 +320,14 @@ where
                         if *seq_num >= from {
                             let (_, header, body, _) =
                                 store.operations.get(hash).expect("exists in hash map");
-                            result.push((header.to_owned(), body.to_owned()));
+                            result.push((header.to_owned(), store.body(body)));
                         }
                     });
                 } else {
                     log.iter().for_each(|(_, _, hash)| {
                         let (_, header, body, _) =
                             store.operations.get(hash).expect("exists in hash map");
-                        result.push((header.to_owned(), body.to_owned()));
+                        result.push((header.to_owned(), store.body(body)));
                     });
                 }
                 Ok(Some(result))
@@ -216,20 +351,14 @@ where
                         if *seq_num >= from {
                             let (_, _, body, header_bytes) =
                                 store.operations.get(hash).expect("exists in hash map");
-                            result.push((
-                                header_bytes.clone(),
-                                body.as_ref().map(|body| body.to_bytes()),
-                            ));
+                            result.push((header_bytes.clone(), store.raw_body(body)));
                         }
                     });
                 } else {
                     log.iter().for_each(|(_, _, hash)| {
                         let (_, _, body, header_bytes) =
                             store.operations.get(hash).expect("exists in hash map");
-                        result.push((
-                            header_bytes.clone(),
-                            body.as_ref().map(|body| body.to_bytes()),
-                        ));
+                        result.push((header_bytes.clone(), store.raw_body(body)));
                     });
                 }
                 Ok(Some(result))
@@ -257,7 +386,7 @@ where
             return Ok(None);
         };
 
-        Ok(Some((header.to_owned(), body.to_owned())))
+        Ok(Some((header.to_owned(), store.body(body))))
     }
 
     async fn delete_operations(
@@ -277,7 +406,12 @@ where
                 !remove
             });
         };
-        store.operations.retain(|hash, _| !deleted.contains(hash));
+        for hash in &deleted {
+            if let Some((_, _, body, _)) = store.operations.remove(hash) {
+                store.release_payload(body);
+            }
+        }
+        store.refresh_head(public_key, log_id);
         Ok(!deleted.is_empty())
     }
 
@@ -305,30 +439,105 @@ where
                 .operations
                 .get_mut(hash)
                 .expect("operation exists in store");
-            operation.2 = None;
+            let body = operation.2.take();
+            store.release_payload(body);
         }
+        store.refresh_head(public_key, log_id);
         Ok(!deleted.is_empty())
     }
 
     async fn get_log_heights(&self, log_id: &L) -> Result<Vec<(PublicKey, SeqNum)>, Self::Error> {
         let log_heights = self
             .read_store()
-            .logs
+            .heads
             .iter()
-            .filter_map(|((public_key, inner_log_id), log)| {
-                if inner_log_id == log_id {
-                    let log_height = log
-                        .last()
-                        .expect("all logs contain at least one operation")
-                        .0;
-                    Some((*public_key, log_height))
-                } else {
-                    None
-                }
-            })
+            .filter(|((_, inner_log_id), _)| inner_log_id == log_id)
+            .map(|((public_key, _), head)| (*public_key, head.seq_num))
             .collect();
         Ok(log_heights)
     }
+
+    async fn log_heads(&self, log_id: &L) -> Result<Vec<LogHead>, Self::Error> {
+        let log_heads = self
+            .read_store()
+            .heads
+            .iter()
+            .filter(|((_, inner_log_id), _)| inner_log_id == log_id)
+            .map(|(_, head)| head.to_owned())
+            .collect();
+        Ok(log_heads)
+    }
+}
+
+impl<L, E> QueryStore<L, E> for MemoryStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn query(&self, query: &OperationQuery<L>) -> Result<QueryPage<E>, Self::Error> {
+        let store = self.read_store();
+
+        let mut operations: Vec<(Cursor, &Header<E>, &Option<PayloadHash>)> = store
+            .operations
+            .iter()
+            .filter(|(hash, (log_id, header, _, _))| query.matches(hash, header, log_id))
+            .map(|(hash, (_, header, body, _))| {
+                (Cursor::new(header.timestamp, *hash), header, body)
+            })
+            .collect();
+        operations.sort_by_key(|(cursor, _, _)| *cursor);
+
+        // Keep one more operation than requested to know if there's a next page.
+        if let Some(limit) = query.page_limit() {
+            operations.truncate(limit.saturating_add(1));
+        }
+
+        let operations = operations
+            .into_iter()
+            .map(|(_, header, body)| (header.to_owned(), store.body(body)))
+            .collect();
+        Ok(query.paginate(operations))
+    }
+}
+
+impl<L, E> PayloadStore for MemoryStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn gc(&mut self) -> Result<u64, Self::Error> {
+        let mut reclaimed = 0;
+        self.write_store().payloads.retain(|_, (body, refs)| {
+            if *refs == 0 {
+                reclaimed += body.size();
+            }
+            *refs > 0
+        });
+        Ok(reclaimed)
+    }
+}
+
+impl<L, E> SizedStore for MemoryStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn size(&self) -> Result<u64, Self::Error> {
+        let store = self.read_store();
+        let headers: u64 = store
+            .operations
+            .values()
+            .map(|(_, _, _, header_bytes)| header_bytes.len() as u64)
+            .sum();
+        let payloads: u64 = store.payloads.values().map(|(body, _)| body.size()).sum();
+        Ok(headers + payloads)
+    }
 }
 
 #[cfg(test)]
@@ -336,7 +545,8 @@ mod tests {
     use p2panda_core::{Body, Hash, Header, PrivateKey};
     use serde::{Deserialize, Serialize};
 
-    use crate::{LogStore, OperationStore};
+    use crate::conformance;
+    use crate::{BatchOperation, LogStore, OperationStore};
 
     use super::MemoryStore;
 
@@ -364,6 +574,23 @@ mod tests {
         (header.hash(), header, header_bytes)
     }
 
+    #[tokio::test]
+    async fn conformance() {
+        conformance::run(MemoryStore::<u64>::new()).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writers() {
+        conformance::concurrent_writers(MemoryStore::<u64>::new()).await;
+    }
+
+    #[tokio::test]
+    async fn collect_garbage() {
+        let mut store = MemoryStore::<u64>::new();
+        conformance::collect_garbage(&mut store).await;
+        assert!(store.read_store().payloads.is_empty());
+    }
+
     #[tokio::test]
     async fn default_memory_store() {
         let mut store = MemoryStore::default();
@@ -875,4 +1102,65 @@ mod tests {
         assert_eq!(log[1].1, None);
         assert_eq!(log[2].1, Some(body_2));
     }
+
+    #[tokio::test]
+    async fn scan_log_heads() {
+        let mut store = MemoryStore::<u64>::new();
+        let private_key_a = PrivateKey::new();
+        let private_key_b = PrivateKey::new();
+        conformance::insert_log(&mut store, &private_key_a, 0, 4).await;
+        conformance::insert_log(&mut store, &private_key_b, 0, 2).await;
+        store
+            .delete_payloads(&private_key_a.public_key(), &0, 0, 2)
+            .await
+            .expect("no errors");
+
+        // Scanning the logs results in the same heads as maintained by the store.
+        let mut heads = store.log_heads(&0).await.expect("no errors");
+        let mut scanned = crate::scan_log_heads(&store, &0).await.expect("no errors");
+        heads.sort_by_key(|head| head.public_key.to_hex());
+        scanned.sort_by_key(|head| head.public_key.to_hex());
+        assert_eq!(heads.len(), 2);
+        assert_eq!(scanned, heads);
+    }
+
+    #[tokio::test]
+    async fn insert_operations_sequentially() {
+        let mut store = MemoryStore::<u64>::new();
+        let private_key = PrivateKey::new();
+        let body = Body::new("hello!".as_bytes());
+        let (hash_0, header_0, header_bytes_0) = create_operation(&private_key, &body, 0, 0, None);
+        let (hash_1, header_1, header_bytes_1) =
+            create_operation(&private_key, &body, 1, 1, Some(hash_0));
+
+        let batch = [
+            BatchOperation {
+                hash: hash_0,
+                header: &header_0,
+                body: Some(&body),
+                header_bytes: &header_bytes_0,
+                log_id: &0,
+            },
+            BatchOperation {
+                hash: hash_1,
+                header: &header_1,
+                body: Some(&body),
+                header_bytes: &header_bytes_1,
+                log_id: &0,
+            },
+        ];
+        let inserted = crate::insert_operations_sequentially(&mut store, &batch)
+            .await
+            .expect("no errors");
+        assert_eq!(inserted, 2);
+
+        // Operations which already exist are not counted.
+        let inserted = crate::insert_operations_sequentially(&mut store, &batch[1..])
+            .await
+            .expect("no errors");
+        assert_eq!(inserted, 0);
+
+        let heights = store.get_log_heights(&0).await.expect("no errors");
+        assert_eq!(heights, vec![(private_key.public_key(), 1)]);
+    }
 }
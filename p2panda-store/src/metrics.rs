@@ -0,0 +1,513 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Metrics and slow query log of stores.
+//!
+//! `MeteredStore` wraps any store backend and measures the latency of all inserts and reads going
+//! through it. Numbers are collected in `StoreMetrics`, a handle which can be cloned and read from
+//! anywhere, like the metrics of `p2panda-net`, for example to export them to a monitoring system:
+//!
+//! 1. Insert latency, measured for `OperationStore::insert_operation` and
+//!    `OperationStore::insert_operations`.
+//! 2. Query latency, measured for all reads of operations and logs, including `QueryStore::query`.
+//! 3. Size of the store, measured with `MeteredStore::measure_size` for backends implementing
+//!    `SizedStore`.
+//!
+//! Inserts and reads taking longer than the configured threshold are kept in a slow query log,
+//! see `MeteredStore::slow_query_threshold`. Sync sessions stalling because of storage show up
+//! there with the method and log they were waiting for.
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use p2panda_store::MemoryStore;
+//! use p2panda_store::metrics::MeteredStore;
+//!
+//! let store = MeteredStore::new(MemoryStore::<u64>::new())
+//!     .slow_query_threshold(Duration::from_millis(50));
+//! let metrics = store.metrics();
+//!
+//! // .. hand the store to the sync protocol and ingest streams
+//!
+//! for slow_query in metrics.slow_queries() {
+//!     println!("{} took {:?}", slow_query.method, slow_query.elapsed);
+//! }
+//! ```
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use p2panda_core::{Body, Extensions, Hash, Header, PublicKey, RawOperation};
+
+use crate::query::{OperationQuery, QueryPage};
+use crate::{
+    BatchOperation, LogHead, LogId, LogStore, OperationStore, PayloadStore, QueryStore, SizedStore,
+};
+
+/// Maximum number of entries kept in the slow query log, older entries are dropped.
+pub const MAX_SLOW_QUERIES: usize = 128;
+
+/// Insert or read which took longer than the slow query threshold.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlowQuery {
+    /// Name of the store method, for example `get_log`.
+    pub method: &'static str,
+
+    /// Arguments of the call, for example the author and log id.
+    pub details: String,
+
+    /// Time the call took.
+    pub elapsed: Duration,
+}
+
+/// Metrics of inserts, reads and size of a store.
+#[derive(Clone, Debug, Default)]
+pub struct StoreMetrics(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    inserts: AtomicU64,
+    insert_nanos: AtomicU64,
+    queries: AtomicU64,
+    query_nanos: AtomicU64,
+    size: AtomicU64,
+    size_measured: AtomicBool,
+    slow_queries: Mutex<VecDeque<SlowQuery>>,
+}
+
+impl StoreMetrics {
+    /// Returns new, empty metrics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of insert calls.
+    pub fn inserts(&self) -> u64 {
+        self.0.inserts.load(Ordering::Relaxed)
+    }
+
+    /// Returns the average latency of insert calls, zero if nothing was inserted yet.
+    pub fn insert_latency(&self) -> Duration {
+        average(&self.0.insert_nanos, self.inserts())
+    }
+
+    /// Returns the number of read calls.
+    pub fn queries(&self) -> u64 {
+        self.0.queries.load(Ordering::Relaxed)
+    }
+
+    /// Returns the average latency of read calls, zero if nothing was read yet.
+    pub fn query_latency(&self) -> Duration {
+        average(&self.0.query_nanos, self.queries())
+    }
+
+    /// Returns the size of the store in bytes at the last measurement, `None` if it was never
+    /// measured.
+    pub fn size(&self) -> Option<u64> {
+        self.0
+            .size_measured
+            .load(Ordering::Relaxed)
+            .then(|| self.0.size.load(Ordering::Relaxed))
+    }
+
+    /// Returns the latest slow inserts and reads, oldest first.
+    pub fn slow_queries(&self) -> Vec<SlowQuery> {
+        self.slow_query_log().iter().cloned().collect()
+    }
+
+    fn record_insert(&self, elapsed: Duration) {
+        self.0.inserts.fetch_add(1, Ordering::Relaxed);
+        self.0
+            .insert_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn record_query(&self, elapsed: Duration) {
+        self.0.queries.fetch_add(1, Ordering::Relaxed);
+        self.0
+            .query_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn record_size(&self, size: u64) {
+        self.0.size.store(size, Ordering::Relaxed);
+        self.0.size_measured.store(true, Ordering::Relaxed);
+    }
+
+    fn record_slow_query(&self, slow_query: SlowQuery) {
+        let mut slow_queries = self.slow_query_log();
+        if slow_queries.len() >= MAX_SLOW_QUERIES {
+            slow_queries.pop_front();
+        }
+        slow_queries.push_back(slow_query);
+    }
+
+    fn slow_query_log(&self) -> MutexGuard<'_, VecDeque<SlowQuery>> {
+        self.0
+            .slow_queries
+            .lock()
+            .expect("slow query log lock was poisoned")
+    }
+}
+
+fn average(total_nanos: &AtomicU64, count: u64) -> Duration {
+    if count == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_nanos(total_nanos.load(Ordering::Relaxed) / count)
+}
+
+/// Store wrapper measuring the latency of all inserts and reads.
+#[derive(Clone, Debug)]
+pub struct MeteredStore<S> {
+    store: S,
+    metrics: StoreMetrics,
+    slow_query_threshold: Option<Duration>,
+}
+
+impl<S> MeteredStore<S> {
+    /// Wrap a store to measure its inserts and reads.
+    pub fn new(store: S) -> Self {
+        Self::with_metrics(store, StoreMetrics::default())
+    }
+
+    /// Wrap a store, collecting measurements in the given metrics.
+    ///
+    /// This allows sharing one set of metrics between the store and caches in front of it.
+    pub fn with_metrics(store: S, metrics: StoreMetrics) -> Self {
+        Self {
+            store,
+            metrics,
+            slow_query_threshold: None,
+        }
+    }
+
+    /// Keep inserts and reads taking at least `threshold` in the slow query log.
+    ///
+    /// Default: no slow query log.
+    pub fn slow_query_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_query_threshold = Some(threshold);
+        self
+    }
+
+    /// Returns the metrics of this store.
+    pub fn metrics(&self) -> StoreMetrics {
+        self.metrics.clone()
+    }
+
+    /// Access the wrapped store.
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    /// Measures the size of the store and records it in the metrics.
+    pub async fn measure_size(&self) -> Result<u64, <S as SizedStore>::Error>
+    where
+        S: SizedStore + Sync,
+    {
+        let size = self.store.size().await?;
+        self.metrics.record_size(size);
+        Ok(size)
+    }
+
+    fn check_slow(
+        &self,
+        method: &'static str,
+        elapsed: Duration,
+        details: impl FnOnce() -> String,
+    ) {
+        if self
+            .slow_query_threshold
+            .is_some_and(|threshold| elapsed >= threshold)
+        {
+            self.metrics.record_slow_query(SlowQuery {
+                method,
+                details: details(),
+                elapsed,
+            });
+        }
+    }
+
+    fn insert_done(
+        &self,
+        method: &'static str,
+        started: Instant,
+        details: impl FnOnce() -> String,
+    ) {
+        let elapsed = started.elapsed();
+        self.metrics.record_insert(elapsed);
+        self.check_slow(method, elapsed, details);
+    }
+
+    fn query_done(&self, method: &'static str, started: Instant, details: impl FnOnce() -> String) {
+        let elapsed = started.elapsed();
+        self.metrics.record_query(elapsed);
+        self.check_slow(method, elapsed, details);
+    }
+}
+
+impl<S, L, E> OperationStore<L, E> for MeteredStore<S>
+where
+    S: OperationStore<L, E> + Sync,
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = <S as OperationStore<L, E>>::Error;
+
+    async fn insert_operation(
+        &mut self,
+        hash: Hash,
+        header: &Header<E>,
+        body: Option<&Body>,
+        header_bytes: &[u8],
+        log_id: &L,
+    ) -> Result<bool, Self::Error> {
+        let started = Instant::now();
+        let result = self
+            .store
+            .insert_operation(hash, header, body, header_bytes, log_id)
+            .await;
+        self.insert_done("insert_operation", started, || format!("hash={hash}"));
+        result
+    }
+
+    async fn insert_operations(
+        &mut self,
+        operations: &[BatchOperation<'_, L, E>],
+    ) -> Result<usize, Self::Error> {
+        let started = Instant::now();
+        let result = self.store.insert_operations(operations).await;
+        self.insert_done("insert_operations", started, || {
+            format!("operations={}", operations.len())
+        });
+        result
+    }
+
+    async fn get_operation(
+        &self,
+        hash: Hash,
+    ) -> Result<Option<(Header<E>, Option<Body>)>, Self::Error> {
+        let started = Instant::now();
+        let result = self.store.get_operation(hash).await;
+        self.query_done("get_operation", started, || format!("hash={hash}"));
+        result
+    }
+
+    async fn get_raw_operation(&self, hash: Hash) -> Result<Option<RawOperation>, Self::Error> {
+        let started = Instant::now();
+        let result = self.store.get_raw_operation(hash).await;
+        self.query_done("get_raw_operation", started, || format!("hash={hash}"));
+        result
+    }
+
+    async fn has_operation(&self, hash: Hash) -> Result<bool, Self::Error> {
+        let started = Instant::now();
+        let result = self.store.has_operation(hash).await;
+        self.query_done("has_operation", started, || format!("hash={hash}"));
+        result
+    }
+
+    async fn delete_operation(&mut self, hash: Hash) -> Result<bool, Self::Error> {
+        self.store.delete_operation(hash).await
+    }
+
+    async fn delete_payload(&mut self, hash: Hash) -> Result<bool, Self::Error> {
+        self.store.delete_payload(hash).await
+    }
+}
+
+impl<S, L, E> LogStore<L, E> for MeteredStore<S>
+where
+    S: LogStore<L, E> + Send + Sync,
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = <S as LogStore<L, E>>::Error;
+
+    async fn get_log(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: Option<u64>,
+    ) -> Result<Option<Vec<(Header<E>, Option<Body>)>>, Self::Error> {
+        let started = Instant::now();
+        let result = self.store.get_log(public_key, log_id, from).await;
+        self.query_done("get_log", started, || {
+            format!("public_key={public_key} log_id={log_id:?} from={from:?}")
+        });
+        result
+    }
+
+    async fn get_raw_log(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: Option<u64>,
+    ) -> Result<Option<Vec<RawOperation>>, Self::Error> {
+        let started = Instant::now();
+        let result = self.store.get_raw_log(public_key, log_id, from).await;
+        self.query_done("get_raw_log", started, || {
+            format!("public_key={public_key} log_id={log_id:?} from={from:?}")
+        });
+        result
+    }
+
+    async fn get_log_heights(&self, log_id: &L) -> Result<Vec<(PublicKey, u64)>, Self::Error> {
+        let started = Instant::now();
+        let result = self.store.get_log_heights(log_id).await;
+        self.query_done("get_log_heights", started, || format!("log_id={log_id:?}"));
+        result
+    }
+
+    async fn log_heads(&self, log_id: &L) -> Result<Vec<LogHead>, Self::Error> {
+        let started = Instant::now();
+        let result = self.store.log_heads(log_id).await;
+        self.query_done("log_heads", started, || format!("log_id={log_id:?}"));
+        result
+    }
+
+    async fn latest_operation(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+    ) -> Result<Option<(Header<E>, Option<Body>)>, Self::Error> {
+        let started = Instant::now();
+        let result = self.store.latest_operation(public_key, log_id).await;
+        self.query_done("latest_operation", started, || {
+            format!("public_key={public_key} log_id={log_id:?}")
+        });
+        result
+    }
+
+    async fn delete_operations(
+        &mut self,
+        public_key: &PublicKey,
+        log_id: &L,
+        before: u64,
+    ) -> Result<bool, Self::Error> {
+        self.store
+            .delete_operations(public_key, log_id, before)
+            .await
+    }
+
+    async fn delete_payloads(
+        &mut self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: u64,
+        to: u64,
+    ) -> Result<bool, Self::Error> {
+        self.store
+            .delete_payloads(public_key, log_id, from, to)
+            .await
+    }
+}
+
+impl<S, L, E> QueryStore<L, E> for MeteredStore<S>
+where
+    S: QueryStore<L, E> + Sync,
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = <S as QueryStore<L, E>>::Error;
+
+    async fn query(&self, query: &OperationQuery<L>) -> Result<QueryPage<E>, Self::Error> {
+        let started = Instant::now();
+        let result = self.store.query(query).await;
+        self.query_done("query", started, || format!("{query:?}"));
+        result
+    }
+}
+
+impl<S> PayloadStore for MeteredStore<S>
+where
+    S: PayloadStore + Send + Sync,
+{
+    type Error = <S as PayloadStore>::Error;
+
+    async fn gc(&mut self) -> Result<u64, Self::Error> {
+        self.store.gc().await
+    }
+}
+
+impl<S> SizedStore for MeteredStore<S>
+where
+    S: SizedStore + Sync,
+{
+    type Error = <S as SizedStore>::Error;
+
+    async fn size(&self) -> Result<u64, Self::Error> {
+        self.measure_size().await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use std::time::Duration;
+
+    use p2panda_core::{Body, PrivateKey};
+
+    use crate::conformance::{self, create_operation};
+    use crate::{LogStore, MemoryStore, OperationStore};
+
+    use super::{MAX_SLOW_QUERIES, MeteredStore, SlowQuery};
+
+    #[tokio::test]
+    async fn conformance() {
+        conformance::run(MeteredStore::new(MemoryStore::<u64>::new())).await;
+    }
+
+    #[tokio::test]
+    async fn measure_inserts_and_queries() {
+        let mut store =
+            MeteredStore::new(MemoryStore::<u64>::new()).slow_query_threshold(Duration::ZERO);
+        let metrics = store.metrics();
+        assert_eq!(metrics.size(), None);
+
+        let private_key = PrivateKey::new();
+        let public_key = private_key.public_key();
+        let body = Body::new("hello!".as_bytes());
+        let (hash, header, header_bytes) = create_operation(&private_key, &body, 0, 0, None);
+
+        store
+            .insert_operation(hash, &header, Some(&body), &header_bytes, &0)
+            .await
+            .unwrap();
+        store.get_log(&public_key, &0, None).await.unwrap();
+        store.get_log_heights(&0).await.unwrap();
+
+        assert_eq!(metrics.inserts(), 1);
+        assert_eq!(metrics.queries(), 2);
+
+        let slow_queries = metrics.slow_queries();
+        assert_eq!(slow_queries.len(), 3);
+        assert_eq!(slow_queries[0].method, "insert_operation");
+        assert_eq!(
+            slow_queries[2],
+            SlowQuery {
+                method: "get_log_heights",
+                details: "log_id=0".into(),
+                elapsed: slow_queries[2].elapsed,
+            }
+        );
+
+        let size = store.measure_size().await.unwrap();
+        assert_eq!(size, (header_bytes.len() as u64) + body.size());
+        assert_eq!(metrics.size(), Some(size));
+    }
+
+    #[tokio::test]
+    async fn bounded_slow_query_log() {
+        let store =
+            MeteredStore::new(MemoryStore::<u64>::new()).slow_query_threshold(Duration::ZERO);
+        for _ in 0..MAX_SLOW_QUERIES + 1 {
+            store.get_log_heights(&0).await.unwrap();
+        }
+        assert_eq!(store.metrics().slow_queries().len(), MAX_SLOW_QUERIES);
+
+        // Without a threshold no slow queries are logged.
+        let store = MeteredStore::new(MemoryStore::<u64>::new());
+        store.get_log_heights(&0).await.unwrap();
+        assert!(store.metrics().slow_queries().is_empty());
+    }
+}
@@ -0,0 +1,325 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Copy operations and logs from one store backend to another.
+//!
+//! Migrations are incremental: for every author's log only operations missing in the target store
+//! are copied over. This allows running `migrate` repeatedly while the node keeps writing to the
+//! source store, without interrupting it.
+//!
+//! A zero-downtime migration usually follows these steps:
+//!
+//! 1. Run `migrate` in the background until it reports no (or only a few) copied operations.
+//! 2. Briefly pause writes to the source store.
+//! 3. Run `migrate` a final time to copy the remaining operations.
+//! 4. Run `verify` to make sure both stores contain the same logs.
+//! 5. Swap the source store handle for the target store and resume writes.
+//!
+//! Since stores do not offer a way to enumerate all log ids, the caller needs to pass in all log
+//! ids which should be migrated.
+//!
+//! Indices like log heads are maintained by the target store itself while operations are
+//! inserted. Not covered here are:
+//!
+//! - Blobs, they are kept by `p2panda-blobs` in a separate store and need to be moved with its
+//!   own tooling.
+//! - Switching over from one store to the other, stores are handed to the node by the
+//!   application which needs to pause writes and swap the handles itself (steps 2 and 5).
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Debug, Display};
+
+use p2panda_core::{Body, Extensions, Hash, Header, PublicKey};
+use thiserror::Error;
+
+use crate::{LogId, LogStore, OperationStore};
+
+/// Summary of a migration run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Number of authors' logs which received new operations.
+    pub logs: usize,
+
+    /// Number of operations which were copied to the target store.
+    pub operations: usize,
+}
+
+/// Errors which can occur during migration or verification.
+#[derive(Debug, Error)]
+pub enum MigrationError<S, T>
+where
+    S: Display + Debug,
+    T: Display + Debug,
+{
+    /// Reading from the source store failed.
+    #[error("error reading from source store: {0}")]
+    Source(S),
+
+    /// Reading from or writing to the target store failed.
+    #[error("error accessing target store: {0}")]
+    Target(T),
+
+    /// An operation or payload of an author's log differs between both stores.
+    #[error("{0}")]
+    Mismatch(Box<LogMismatch>),
+}
+
+/// First differing operation of an author's log in source and target store.
+#[derive(Debug, Error)]
+#[error(
+    "log of {public_key} differs at sequence number {seq_num}, expected {expected:?} but found \
+     {found:?}"
+)]
+pub struct LogMismatch {
+    /// Author of the log.
+    pub public_key: PublicKey,
+
+    /// Sequence number of the differing operation.
+    pub seq_num: u64,
+
+    /// Hash of the operation and its payload in the source store, `None` if it is missing.
+    pub expected: Option<(Hash, Option<Hash>)>,
+
+    /// Hash of the operation and its payload in the target store, `None` if it is missing.
+    pub found: Option<(Hash, Option<Hash>)>,
+}
+
+/// Copy all operations of the given logs which are missing in the target store.
+///
+/// Operations are inserted with their original header bytes, which keeps their hashes and
+/// signatures intact.
+pub async fn migrate<L, E, S, T>(
+    source: &S,
+    target: &mut T,
+    log_ids: &[L],
+) -> Result<
+    MigrationReport,
+    MigrationError<<S as LogStore<L, E>>::Error, <T as OperationStore<L, E>>::Error>,
+>
+where
+    L: LogId,
+    E: Extensions,
+    S: LogStore<L, E>,
+    T: OperationStore<L, E> + LogStore<L, E, Error = <T as OperationStore<L, E>>::Error>,
+{
+    let mut report = MigrationReport::default();
+
+    for log_id in log_ids {
+        let target_heights: HashMap<PublicKey, u64> = target
+            .get_log_heights(log_id)
+            .await
+            .map_err(MigrationError::Target)?
+            .into_iter()
+            .collect();
+
+        let source_heights = source
+            .get_log_heights(log_id)
+            .await
+            .map_err(MigrationError::Source)?;
+
+        for (public_key, height) in source_heights {
+            // Only copy operations which are not in the target store yet.
+            let from = match target_heights.get(&public_key) {
+                Some(target_height) if *target_height >= height => continue,
+                Some(target_height) => Some(target_height + 1),
+                None => None,
+            };
+
+            let Some(log) = source
+                .get_log(&public_key, log_id, from)
+                .await
+                .map_err(MigrationError::Source)?
+            else {
+                continue;
+            };
+
+            let Some(raw_log) = source
+                .get_raw_log(&public_key, log_id, from)
+                .await
+                .map_err(MigrationError::Source)?
+            else {
+                continue;
+            };
+
+            // New operations might have been appended to the source log in the meantime, zipping
+            // both logs only considers operations present in both of them.
+            let mut inserted_any = false;
+            for ((header, body), (header_bytes, _)) in log.iter().zip(raw_log.iter()) {
+                let inserted = target
+                    .insert_operation(
+                        Hash::new(header_bytes),
+                        header,
+                        body.as_ref(),
+                        header_bytes,
+                        log_id,
+                    )
+                    .await
+                    .map_err(MigrationError::Target)?;
+
+                if inserted {
+                    report.operations += 1;
+                    inserted_any = true;
+                }
+            }
+
+            if inserted_any {
+                report.logs += 1;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Verify that both stores contain the same logs for the given log ids.
+///
+/// The full logs of every author are compared, including the hashes of all operations and their
+/// payloads. Writes to the source store should be paused during verification, otherwise new
+/// operations will be reported as a mismatch.
+pub async fn verify<L, E, S, T>(
+    source: &S,
+    target: &T,
+    log_ids: &[L],
+) -> Result<(), MigrationError<<S as LogStore<L, E>>::Error, <T as LogStore<L, E>>::Error>>
+where
+    L: LogId,
+    E: Extensions,
+    S: LogStore<L, E>,
+    T: LogStore<L, E>,
+{
+    for log_id in log_ids {
+        let source_heights: HashMap<PublicKey, u64> = source
+            .get_log_heights(log_id)
+            .await
+            .map_err(MigrationError::Source)?
+            .into_iter()
+            .collect();
+
+        let target_heights: HashMap<PublicKey, u64> = target
+            .get_log_heights(log_id)
+            .await
+            .map_err(MigrationError::Target)?
+            .into_iter()
+            .collect();
+
+        let public_keys: BTreeSet<&PublicKey> =
+            source_heights.keys().chain(target_heights.keys()).collect();
+        for public_key in public_keys {
+            let source_log = source
+                .get_log(public_key, log_id, None)
+                .await
+                .map_err(MigrationError::Source)?
+                .unwrap_or_default();
+
+            let target_log = target
+                .get_log(public_key, log_id, None)
+                .await
+                .map_err(MigrationError::Target)?
+                .unwrap_or_default();
+
+            for index in 0..source_log.len().max(target_log.len()) {
+                let expected = source_log.get(index);
+                let found = target_log.get(index);
+                if expected.map(operation_hashes) == found.map(operation_hashes) {
+                    continue;
+                }
+
+                let (header, _) = expected.or(found).expect("one of both logs has an entry");
+                return Err(MigrationError::Mismatch(Box::new(LogMismatch {
+                    public_key: *public_key,
+                    seq_num: header.seq_num,
+                    expected: expected.map(operation_hashes),
+                    found: found.map(operation_hashes),
+                })));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn operation_hashes<E>((header, body): &(Header<E>, Option<Body>)) -> (Hash, Option<Hash>)
+where
+    E: Extensions,
+{
+    (header.hash(), body.as_ref().map(Body::hash))
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use p2panda_core::{Body, PrivateKey};
+
+    use crate::conformance::{create_operation, insert_log};
+    use crate::{LogStore, MemoryStore, OperationStore};
+
+    use super::{MigrationError, MigrationReport, migrate, verify};
+
+    #[tokio::test]
+    async fn incremental_migration() {
+        let mut source = MemoryStore::<u64>::default();
+        let mut target = MemoryStore::<u64>::default();
+        let private_key = PrivateKey::new();
+
+        let hashes = insert_log(&mut source, &private_key, 0, 5).await;
+        insert_log(&mut source, &PrivateKey::new(), 1, 1).await;
+
+        let report = migrate(&source, &mut target, &[0, 1]).await.unwrap();
+        assert_eq!(
+            report,
+            MigrationReport {
+                logs: 2,
+                operations: 6
+            }
+        );
+        verify(&source, &target, &[0, 1]).await.unwrap();
+
+        // The node keeps writing to the source store, a second run only copies new operations.
+        let body = Body::new("operation 5".as_bytes());
+        let (hash, header, header_bytes) =
+            create_operation(&private_key, &body, 5, 5, hashes.last().cloned());
+        source
+            .insert_operation(hash, &header, Some(&body), &header_bytes, &0)
+            .await
+            .unwrap();
+        assert!(matches!(
+            verify(&source, &target, &[0]).await,
+            Err(MigrationError::Mismatch(_))
+        ));
+
+        let report = migrate(&source, &mut target, &[0, 1]).await.unwrap();
+        assert_eq!(
+            report,
+            MigrationReport {
+                logs: 1,
+                operations: 1
+            }
+        );
+        verify(&source, &target, &[0, 1]).await.unwrap();
+
+        let raw_log = target
+            .get_raw_log(&private_key.public_key(), &0, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(raw_log.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn verify_full_logs() {
+        let mut source = MemoryStore::<u64>::default();
+        let mut target = MemoryStore::<u64>::default();
+        let private_key = PrivateKey::new();
+        let public_key = private_key.public_key();
+
+        insert_log(&mut source, &private_key, 0, 5).await;
+        migrate(&source, &mut target, &[0]).await.unwrap();
+        verify(&source, &target, &[0]).await.unwrap();
+
+        // Differences in the middle of a log are detected, even if the latest operations match.
+        target.delete_payloads(&public_key, &0, 2, 3).await.unwrap();
+        let Err(MigrationError::Mismatch(mismatch)) = verify(&source, &target, &[0]).await else {
+            panic!("expected mismatch");
+        };
+        assert_eq!(mismatch.seq_num, 2);
+        assert!(mismatch.expected.unwrap().1.is_some());
+        assert!(mismatch.found.unwrap().1.is_none());
+    }
+}
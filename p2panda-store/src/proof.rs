@@ -0,0 +1,134 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Create proofs that stored operations are part of their author's log.
+//!
+//! Nodes answering queries of light clients can attach a `LogProof` (see `p2panda-core`) to every
+//! returned operation. The proof contains the headers from the operation up to the log head and
+//! can be verified by the client without trusting the node.
+use p2panda_core::Hash;
+use p2panda_core::proof::LogProof;
+
+use crate::{LogId, LogStore, OperationStore};
+
+/// Create a proof that the operation with the given hash is part of its author's log.
+///
+/// The proof ends with the operation at sequence number `head`, or with the latest operation of
+/// the log when no head is given, for example when the client asks for inclusion up to a head it
+/// received from another peer.
+///
+/// Returns `None` when the operation, or any header between it and the head, is not in the store.
+pub async fn log_proof<L, E, S>(
+    store: &S,
+    log_id: &L,
+    hash: Hash,
+    head: Option<u64>,
+) -> Result<Option<LogProof>, <S as OperationStore<L, E>>::Error>
+where
+    L: LogId,
+    S: OperationStore<L, E> + LogStore<L, E, Error = <S as OperationStore<L, E>>::Error>,
+{
+    let Some((header, _)) = store.get_operation(hash).await? else {
+        return Ok(None);
+    };
+
+    if head.is_some_and(|head| head < header.seq_num) {
+        return Ok(None);
+    }
+
+    let Some(log) = store
+        .get_raw_log(&header.public_key, log_id, Some(header.seq_num))
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let len = match head {
+        Some(head) => (head - header.seq_num + 1) as usize,
+        None => log.len(),
+    };
+
+    // Logs can have gaps after pruning, the proof needs to start exactly with the operation.
+    if log.len() < len
+        || log
+            .first()
+            .is_none_or(|(header_bytes, _)| Hash::new(header_bytes) != hash)
+    {
+        return Ok(None);
+    }
+
+    let headers = log
+        .into_iter()
+        .take(len)
+        .map(|(header_bytes, _)| header_bytes)
+        .collect();
+
+    Ok(Some(LogProof::new(headers)))
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::{Body, Hash, PrivateKey};
+
+    use crate::conformance::create_operation;
+    use crate::{MemoryStore, OperationStore};
+
+    use super::log_proof;
+
+    #[tokio::test]
+    async fn prove_stored_operations() {
+        let mut store = MemoryStore::<u64>::new();
+        let private_key = PrivateKey::new();
+
+        let mut hashes: Vec<Hash> = Vec::new();
+        for seq_num in 0..5 {
+            let body = Body::new(format!("operation {seq_num}").as_bytes());
+            let (hash, header, header_bytes) = create_operation(
+                &private_key,
+                &body,
+                seq_num,
+                seq_num,
+                hashes.last().cloned(),
+            );
+            store
+                .insert_operation(hash, &header, Some(&body), &header_bytes, &0)
+                .await
+                .unwrap();
+            hashes.push(hash);
+        }
+
+        let proof = log_proof(&store, &0, hashes[1], None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(proof.len(), 4);
+        let head = proof.verify::<()>(&hashes[1]).unwrap();
+        assert_eq!(head.hash, hashes[4]);
+
+        let proof = log_proof(&store, &0, hashes[1], Some(2))
+            .await
+            .unwrap()
+            .unwrap();
+        let head = proof.verify::<()>(&hashes[1]).unwrap();
+        assert_eq!(head.hash, hashes[2]);
+
+        // Heads before the operation or beyond the log can't be proven.
+        assert!(
+            log_proof(&store, &0, hashes[3], Some(2))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            log_proof(&store, &0, hashes[3], Some(7))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            log_proof(&store, &0, Hash::new(b"unknown"), None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
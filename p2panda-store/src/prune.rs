@@ -0,0 +1,210 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Prune old operations from authors' logs.
+//!
+//! Long-running nodes can reclaim space by removing history which is not needed anymore, for
+//! example for topics where only recent data is of interest. Logs are cut before a given sequence
+//! number or timestamp.
+//!
+//! To keep the backlink of the first remaining operation verifiable, the header of the operation
+//! directly before the cut is kept in the store as a "checkpoint". Only its payload is removed.
+//! Since the checkpoint stays part of the log, log heights and the latest operation are not
+//! affected by pruning and new operations can still be appended.
+//!
+//! Stores sharing payloads between operations only release them when pruning, unreferenced
+//! payloads are removed with `PayloadStore::gc`. Space freed in persistent stores might only be
+//! returned to the file system after compacting the database, see `SqliteStore::compact` and
+//! `RedbStore::compact`.
+use p2panda_core::{Extensions, Header, PublicKey};
+
+use crate::{LogId, LogStore};
+
+/// Position in a log before which operations get pruned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PruneBefore {
+    /// Prune all operations with a lower sequence number.
+    SeqNum(u64),
+
+    /// Prune all operations up to the first one with an equal or later timestamp.
+    ///
+    /// Timestamps are not guaranteed to increase within a log, pruning stops at the first
+    /// operation which is not older than the given timestamp.
+    Timestamp(u64),
+}
+
+/// Result of pruning a log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PruneReport<E> {
+    /// Header kept at the beginning of the pruned log, its payload was removed.
+    pub checkpoint: Header<E>,
+
+    /// Number of operations which were deleted from the log.
+    pub deleted: usize,
+}
+
+/// Prune an author's log, keeping a checkpoint header directly before the cut.
+///
+/// Returns `None` if the log doesn't exist or nothing was pruned.
+pub async fn prune<L, E, S>(
+    store: &mut S,
+    public_key: &PublicKey,
+    log_id: &L,
+    before: PruneBefore,
+) -> Result<Option<PruneReport<E>>, S::Error>
+where
+    L: LogId,
+    E: Extensions,
+    S: LogStore<L, E>,
+{
+    let Some(log) = store.get_log(public_key, log_id, None).await? else {
+        return Ok(None);
+    };
+
+    // Sequence number of the first operation which stays untouched. If all operations are
+    // affected we still keep the latest header, otherwise new operations couldn't be appended.
+    let first_kept = match before {
+        PruneBefore::SeqNum(seq_num) => {
+            log.iter().position(|(header, _)| header.seq_num >= seq_num)
+        }
+        PruneBefore::Timestamp(timestamp) => log
+            .iter()
+            .position(|(header, _)| header.timestamp >= timestamp),
+    }
+    .unwrap_or(log.len());
+
+    // The checkpoint is the operation directly before the cut, everything before it is deleted.
+    let Some(checkpoint_index) = first_kept.checked_sub(1) else {
+        return Ok(None);
+    };
+    let (checkpoint, body) = &log[checkpoint_index];
+    if checkpoint_index == 0 && body.is_none() {
+        return Ok(None);
+    }
+
+    store
+        .delete_operations(public_key, log_id, checkpoint.seq_num)
+        .await?;
+    store
+        .delete_payloads(
+            public_key,
+            log_id,
+            checkpoint.seq_num,
+            checkpoint.seq_num + 1,
+        )
+        .await?;
+
+    Ok(Some(PruneReport {
+        checkpoint: checkpoint.to_owned(),
+        deleted: checkpoint_index,
+    }))
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use p2panda_core::PrivateKey;
+
+    use crate::conformance::insert_log;
+    use crate::{LogStore, MemoryStore, OperationStore};
+
+    use super::{PruneBefore, prune};
+
+    #[tokio::test]
+    async fn prune_before_seq_num() {
+        let mut store = MemoryStore::<u64>::default();
+        let private_key = PrivateKey::new();
+        let public_key = private_key.public_key();
+        let hashes = insert_log(&mut store, &private_key, 0, 5).await;
+
+        let report = prune(&mut store, &public_key, &0, PruneBefore::SeqNum(3))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.checkpoint.hash(), hashes[2]);
+        assert_eq!(report.deleted, 2);
+
+        // The checkpoint stays in the log without its payload and the first remaining operation
+        // still points at it.
+        let log = store.get_log(&public_key, &0, None).await.unwrap().unwrap();
+        assert_eq!(log.len(), 3);
+        assert_eq!(log[0].0.hash(), hashes[2]);
+        assert!(log[0].1.is_none());
+        assert_eq!(log[1].0.backlink, Some(hashes[2]));
+        assert!(log[1].1.is_some());
+        assert!(!store.has_operation(hashes[1]).await.unwrap());
+
+        // Log height is not affected.
+        assert_eq!(
+            store.get_log_heights(&0).await.unwrap(),
+            vec![(public_key, 4)]
+        );
+
+        // Pruning again at the same position is a no-op.
+        assert!(
+            prune(&mut store, &public_key, &0, PruneBefore::SeqNum(3))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn prune_before_timestamp() {
+        let mut store = MemoryStore::<u64>::default();
+        let private_key = PrivateKey::new();
+        let public_key = private_key.public_key();
+        let hashes = insert_log(&mut store, &private_key, 0, 5).await;
+
+        let report = prune(&mut store, &public_key, &0, PruneBefore::Timestamp(3))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.checkpoint.hash(), hashes[2]);
+
+        // Pruning everything keeps the latest header.
+        let report = prune(&mut store, &public_key, &0, PruneBefore::Timestamp(1_000))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.checkpoint.hash(), hashes[4]);
+        assert_eq!(report.deleted, 2);
+
+        let (latest, body) = store
+            .latest_operation(&public_key, &0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.hash(), hashes[4]);
+        assert!(body.is_none());
+        assert_eq!(
+            store
+                .get_log(&public_key, &0, None)
+                .await
+                .unwrap()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing_to_prune() {
+        let mut store = MemoryStore::<u64>::default();
+        let private_key = PrivateKey::new();
+        let public_key = private_key.public_key();
+
+        assert!(
+            prune(&mut store, &public_key, &0, PruneBefore::SeqNum(3))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        insert_log(&mut store, &private_key, 0, 5).await;
+        assert!(
+            prune(&mut store, &public_key, &0, PruneBefore::SeqNum(0))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,342 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Range and filter queries over operations.
+//!
+//! An `OperationQuery` selects operations by author, log ids, timestamp range and sequence number
+//! range. All filters are optional and combined, operations need to match every given filter.
+//!
+//! Historical states of the store can be queried "as of" a timestamp or a `LogCut`, which holds
+//! the sequence number of the latest visible operation per log. Cuts are usually taken from the
+//! log heads at some point in time and allow applications to render earlier document states, for
+//! example to implement undo across the whole dataset.
+//!
+//! Results are ordered by timestamp and operation hash and returned in pages. Every page contains
+//! a `Cursor` pointing at the last returned operation when more results are available, the next
+//! page is requested by passing that cursor into the query again.
+//!
+//! ```
+//! use p2panda_core::PrivateKey;
+//! use p2panda_store::query::OperationQuery;
+//!
+//! let public_key = PrivateKey::new().public_key();
+//! let query = OperationQuery::<u64>::new()
+//!     .author(public_key)
+//!     .log_ids([1, 2])
+//!     .timestamp(1_000..2_000)
+//!     .limit(50);
+//! ```
+use std::ops::Range;
+
+use p2panda_core::{Body, Extensions, Hash, Header, PublicKey};
+
+use crate::LogHead;
+
+/// Position of an operation in the query result order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cursor {
+    /// Timestamp of the last returned operation.
+    pub timestamp: u64,
+
+    /// Hash of the last returned operation.
+    pub hash: Hash,
+}
+
+impl Cursor {
+    pub fn new(timestamp: u64, hash: Hash) -> Self {
+        Self { timestamp, hash }
+    }
+}
+
+/// Sequence numbers of the latest visible operation per log.
+///
+/// Operations of logs which are not part of the cut are not visible.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogCut<L> {
+    positions: Vec<(PublicKey, L, u64)>,
+}
+
+impl<L> Default for LogCut<L> {
+    fn default() -> Self {
+        Self {
+            positions: Vec::new(),
+        }
+    }
+}
+
+impl<L> LogCut<L>
+where
+    L: PartialEq,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cut of the current state of logs, see `LogStore::log_heads`.
+    pub fn from_heads(log_id: L, heads: &[LogHead]) -> Self
+    where
+        L: Clone,
+    {
+        let mut cut = Self::new();
+        for head in heads {
+            cut.insert(head.public_key, log_id.clone(), head.seq_num);
+        }
+        cut
+    }
+
+    /// Set the sequence number of the latest visible operation of a log.
+    pub fn insert(&mut self, public_key: PublicKey, log_id: L, seq_num: u64) {
+        match self
+            .positions
+            .iter_mut()
+            .find(|(cut_public_key, cut_log_id, _)| {
+                *cut_public_key == public_key && *cut_log_id == log_id
+            }) {
+            Some(position) => position.2 = seq_num,
+            None => self.positions.push((public_key, log_id, seq_num)),
+        }
+    }
+
+    /// Returns the sequence number of the latest visible operation of a log.
+    pub fn get(&self, public_key: &PublicKey, log_id: &L) -> Option<u64> {
+        self.positions
+            .iter()
+            .find(|(cut_public_key, cut_log_id, _)| {
+                cut_public_key == public_key && cut_log_id == log_id
+            })
+            .map(|(_, _, seq_num)| *seq_num)
+    }
+
+    /// Returns `true` if the operation of the given log is visible in this cut.
+    pub fn contains(&self, public_key: &PublicKey, log_id: &L, seq_num: u64) -> bool {
+        self.contains_with(public_key, seq_num, |cut_log_id| cut_log_id == log_id)
+    }
+
+    /// Same as `contains` but identifying the log with a predicate, for stores which only keep a
+    /// hash of the log id around.
+    pub fn contains_with(
+        &self,
+        public_key: &PublicKey,
+        seq_num: u64,
+        is_log: impl Fn(&L) -> bool,
+    ) -> bool {
+        self.positions
+            .iter()
+            .any(|(cut_public_key, log_id, cut_seq_num)| {
+                cut_public_key == public_key && seq_num <= *cut_seq_num && is_log(log_id)
+            })
+    }
+
+    /// Iterate over all logs and their latest visible sequence number.
+    pub fn iter(&self) -> impl Iterator<Item = (&PublicKey, &L, u64)> {
+        self.positions
+            .iter()
+            .map(|(public_key, log_id, seq_num)| (public_key, log_id, *seq_num))
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+/// Filters and pagination for a query over operations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OperationQuery<L> {
+    public_key: Option<PublicKey>,
+    log_ids: Vec<L>,
+    timestamp: Option<Range<u64>>,
+    seq_num: Option<Range<u64>>,
+    cut: Option<LogCut<L>>,
+    after: Option<Cursor>,
+    limit: Option<usize>,
+}
+
+impl<L> Default for OperationQuery<L> {
+    fn default() -> Self {
+        Self {
+            public_key: None,
+            log_ids: Vec::new(),
+            timestamp: None,
+            seq_num: None,
+            cut: None,
+            after: None,
+            limit: None,
+        }
+    }
+}
+
+impl<L> OperationQuery<L>
+where
+    L: PartialEq,
+{
+    /// Create a query matching all operations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match operations by this author.
+    pub fn author(mut self, public_key: PublicKey) -> Self {
+        self.public_key = Some(public_key);
+        self
+    }
+
+    /// Only match operations stored under any of these log ids.
+    pub fn log_ids(mut self, log_ids: impl IntoIterator<Item = L>) -> Self {
+        self.log_ids = log_ids.into_iter().collect();
+        self
+    }
+
+    /// Only match operations with a timestamp in this range, the upper bound is excluded.
+    pub fn timestamp(mut self, range: Range<u64>) -> Self {
+        self.timestamp = Some(range);
+        self
+    }
+
+    /// Only match operations with a sequence number in this range, the upper bound is excluded.
+    pub fn seq_num(mut self, range: Range<u64>) -> Self {
+        self.seq_num = Some(range);
+        self
+    }
+
+    /// Only match operations which existed at the given time, that is with a timestamp equal to
+    /// or before it.
+    ///
+    /// This narrows down a timestamp range given before. Timestamps are set by authors and not
+    /// verified, use a `LogCut` for historical states which need to be consistent per log.
+    pub fn as_of(mut self, timestamp: u64) -> Self {
+        let end = timestamp.saturating_add(1);
+        self.timestamp = Some(match self.timestamp {
+            Some(range) => range.start..range.end.min(end),
+            None => 0..end,
+        });
+        self
+    }
+
+    /// Only match operations which are visible in the given cut.
+    pub fn cut(mut self, cut: LogCut<L>) -> Self {
+        self.cut = Some(cut);
+        self
+    }
+
+    /// Continue after the position of a previous page.
+    pub fn after(mut self, cursor: Cursor) -> Self {
+        self.after = Some(cursor);
+        self
+    }
+
+    /// Return at most this many operations per page, a limit of `0` means no limit.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = (limit > 0).then_some(limit);
+        self
+    }
+
+    pub fn public_key_filter(&self) -> Option<&PublicKey> {
+        self.public_key.as_ref()
+    }
+
+    /// Log ids to match, an empty list matches all log ids.
+    pub fn log_ids_filter(&self) -> &[L] {
+        &self.log_ids
+    }
+
+    pub fn timestamp_filter(&self) -> Option<&Range<u64>> {
+        self.timestamp.as_ref()
+    }
+
+    pub fn seq_num_filter(&self) -> Option<&Range<u64>> {
+        self.seq_num.as_ref()
+    }
+
+    /// Cut to match, stores without the log id need to check it with `LogCut::contains_with`.
+    pub fn cut_filter(&self) -> Option<&LogCut<L>> {
+        self.cut.as_ref()
+    }
+
+    pub fn cursor(&self) -> Option<&Cursor> {
+        self.after.as_ref()
+    }
+
+    pub fn page_limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Returns `true` if the operation matches all filters of this query and is positioned after
+    /// the cursor.
+    pub fn matches<E>(&self, hash: &Hash, header: &Header<E>, log_id: &L) -> bool {
+        if !self.log_ids.is_empty() && !self.log_ids.contains(log_id) {
+            return false;
+        }
+
+        if let Some(cut) = &self.cut
+            && !cut.contains(&header.public_key, log_id, header.seq_num)
+        {
+            return false;
+        }
+
+        self.matches_header(hash, header)
+    }
+
+    /// Same as `matches` but ignoring the log id and cut filters, for stores which do not keep
+    /// the log id itself around.
+    pub fn matches_header<E>(&self, hash: &Hash, header: &Header<E>) -> bool {
+        if let Some(public_key) = &self.public_key
+            && header.public_key != *public_key
+        {
+            return false;
+        }
+
+        if let Some(range) = &self.timestamp
+            && !range.contains(&header.timestamp)
+        {
+            return false;
+        }
+
+        if let Some(range) = &self.seq_num
+            && !range.contains(&header.seq_num)
+        {
+            return false;
+        }
+
+        if let Some(cursor) = &self.after
+            && Cursor::new(header.timestamp, *hash) <= *cursor
+        {
+            return false;
+        }
+
+        true
+    }
+
+    /// Build a page from matching operations in result order.
+    ///
+    /// Stores can pass in one more operation than the limit to signal that more results are
+    /// available, it is removed from the page.
+    pub fn paginate<E: Extensions>(
+        &self,
+        mut operations: Vec<(Header<E>, Option<Body>)>,
+    ) -> QueryPage<E> {
+        let next = match self.limit {
+            Some(limit) if operations.len() > limit => {
+                operations.truncate(limit);
+                operations
+                    .last()
+                    .map(|(header, _)| Cursor::new(header.timestamp, header.hash()))
+            }
+            _ => None,
+        };
+
+        QueryPage { operations, next }
+    }
+}
+
+/// One page of query results.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryPage<E> {
+    /// Matching operations ordered by timestamp and hash.
+    pub operations: Vec<(Header<E>, Option<Body>)>,
+
+    /// Cursor to request the next page with, `None` if there are no more results.
+    pub next: Option<Cursor>,
+}
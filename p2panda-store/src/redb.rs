@@ -0,0 +1,1093 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Embedded key-value persistence for p2panda operations and logs, backed by `redb`.
+//!
+//! `redb` is written in pure Rust and does not require linking against a C library, which makes
+//! it a good fit for platforms where SQLite is awkward to ship, for example mobile targets.
+//!
+//! Write transactions are serialised by `redb`, while read transactions work on a consistent
+//! snapshot of the database and are never blocked by writers.
+use std::hash::{DefaultHasher, Hash as StdHash, Hasher};
+use std::marker::PhantomData;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+
+use redb::backends::InMemoryBackend;
+use redb::{
+    CommitError, CompactionError, DatabaseError, ReadableTable, ReadableTableMetadata,
+    StorageError, Table, TableDefinition, TableError, TransactionError, WriteTransaction,
+};
+use thiserror::Error;
+
+use p2panda_core::cbor::{DecodeError, decode_cbor};
+use p2panda_core::{Body, Extensions, Hash, Header, PublicKey, RawOperation};
+
+use crate::query::{OperationQuery, QueryPage};
+use crate::{
+    BatchOperation, Durability, LogHead, LogId, LogStore, OperationStore, PayloadStore, QueryStore,
+    SizedStore,
+};
+
+type SeqNum = u64;
+type Timestamp = u64;
+type LogIdHash = u64;
+
+/// Position of an operation in a log: log id hash, public key, sequence number, timestamp and
+/// operation hash.
+///
+/// Keys are ordered lexicographically, all operations of one author's log are therefore stored
+/// next to each other and sorted by sequence number.
+type LogKey = (LogIdHash, [u8; 32], SeqNum, Timestamp, [u8; 32]);
+
+type PayloadHash = [u8; 32];
+
+/// Stored operation: log id hash, header bytes and hash of the optional body.
+type OperationValue = (LogIdHash, &'static [u8], Option<PayloadHash>);
+
+/// Stored payload: body bytes and number of operations referencing it.
+type PayloadValue = (&'static [u8], u64);
+
+/// Author's log: log id hash and public key.
+type HeadKey = (LogIdHash, [u8; 32]);
+
+/// Head of a log: latest sequence number and operation hash, number of operations and size.
+type HeadValue = (SeqNum, [u8; 32], u64, u64);
+
+type Operation<E> = (Header<E>, Option<Body>);
+
+/// Operations by hash.
+const OPERATIONS: TableDefinition<[u8; 32], OperationValue> = TableDefinition::new("operations_v1");
+
+/// Index of all operations per log.
+const LOGS: TableDefinition<LogKey, ()> = TableDefinition::new("logs_v1");
+
+/// Index of all operations by timestamp and hash, this is the order of query results.
+const TIMESTAMPS: TableDefinition<(Timestamp, [u8; 32]), ()> =
+    TableDefinition::new("timestamps_v1");
+
+/// Payloads by body hash, shared by all operations with the same body.
+const PAYLOADS: TableDefinition<PayloadHash, PayloadValue> = TableDefinition::new("payloads_v1");
+
+/// Heads of all logs, updated with every write.
+const LOG_HEADS: TableDefinition<HeadKey, HeadValue> = TableDefinition::new("log_heads_v1");
+
+#[derive(Debug, Error)]
+pub enum RedbStoreError {
+    #[error("failed to decode operation header: {0}")]
+    DecodingFailed(#[from] DecodeError),
+
+    #[error("an error occurred with the redb database: {0}")]
+    Database(Box<redb::Error>),
+}
+
+macro_rules! impl_from_redb_error {
+    ($($error:ty),*) => {
+        $(
+            impl From<$error> for RedbStoreError {
+                fn from(error: $error) -> Self {
+                    Self::Database(Box::new(error.into()))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_redb_error!(
+    CommitError,
+    CompactionError,
+    DatabaseError,
+    redb::Error,
+    StorageError,
+    TableError,
+    TransactionError
+);
+
+/// Re-export of the `redb` database type.
+pub type Database = redb::Database;
+
+/// Open the database file at the given path or create it if it doesn't already exist.
+pub fn open_database(path: impl AsRef<Path>) -> Result<Database, RedbStoreError> {
+    Ok(Database::create(path)?)
+}
+
+/// Create a database which is only held in memory.
+pub fn in_memory_database() -> Result<Database, RedbStoreError> {
+    Ok(Database::builder().create_with_backend(InMemoryBackend::new())?)
+}
+
+/// `redb`-based persistent store.
+#[derive(Clone, Debug)]
+pub struct RedbStore<L, E = ()> {
+    db: Arc<Database>,
+    durability: Durability,
+    _marker: PhantomData<(L, E)>,
+}
+
+impl<L, E> RedbStore<L, E>
+where
+    L: LogId,
+    E: Extensions,
+{
+    /// Create a new `RedbStore` using the provided database.
+    ///
+    /// Missing tables are created on the first start.
+    pub fn new(db: Database) -> Result<Self, RedbStoreError> {
+        let tx = db.begin_write()?;
+        tx.open_table(OPERATIONS)?;
+        tx.open_table(LOGS)?;
+        tx.open_table(TIMESTAMPS)?;
+        tx.open_table(PAYLOADS)?;
+        rebuild_heads(&tx)?;
+        tx.commit()?;
+
+        Ok(Self {
+            db: Arc::new(db),
+            durability: Durability::default(),
+            _marker: PhantomData {},
+        })
+    }
+
+    /// Set the durability of all following writes.
+    ///
+    /// With `Durability::Eventual` writes are committed without syncing them to disk. All
+    /// previous writes are persisted with the next `Durability::Immediate` write or by calling
+    /// `flush`.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Persist all previous writes to disk.
+    pub fn flush(&self) -> Result<(), RedbStoreError> {
+        let mut tx = self.db.begin_write()?;
+        tx.set_durability(redb::Durability::Immediate);
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn begin_write(&self) -> Result<WriteTransaction, RedbStoreError> {
+        let mut tx = self.db.begin_write()?;
+        tx.set_durability(match self.durability {
+            Durability::Immediate => redb::Durability::Immediate,
+            Durability::Eventual => redb::Durability::Eventual,
+        });
+        Ok(tx)
+    }
+
+    /// Compact the database file to return space freed by deleted operations.
+    ///
+    /// Compaction needs exclusive access to the database. Returns `false` if other clones of
+    /// this store exist or there was nothing to compact.
+    pub fn compact(&mut self) -> Result<bool, RedbStoreError> {
+        match Arc::get_mut(&mut self.db) {
+            Some(db) => Ok(db.compact()?),
+            None => Ok(false),
+        }
+    }
+}
+
+fn calculate_hash<T: StdHash>(t: &T) -> u64 {
+    let mut s = DefaultHasher::new();
+    t.hash(&mut s);
+    s.finish()
+}
+
+/// Range over the operations of one log, starting at `from` and ending before `to` when given.
+fn log_range<L: LogId>(
+    public_key: &PublicKey,
+    log_id: &L,
+    from: SeqNum,
+    to: Option<SeqNum>,
+) -> (Bound<LogKey>, Bound<LogKey>) {
+    log_key_range(calculate_hash(log_id), *public_key.as_bytes(), from, to)
+}
+
+fn log_key_range(
+    log_id: LogIdHash,
+    public_key: [u8; 32],
+    from: SeqNum,
+    to: Option<SeqNum>,
+) -> (Bound<LogKey>, Bound<LogKey>) {
+    let start = Bound::Included((log_id, public_key, from, 0, [0; 32]));
+    let end = match to {
+        Some(to) => Bound::Excluded((log_id, public_key, to, 0, [0; 32])),
+        None => Bound::Included((
+            log_id,
+            public_key,
+            SeqNum::MAX,
+            Timestamp::MAX,
+            [u8::MAX; 32],
+        )),
+    };
+    (start, end)
+}
+
+fn log_key<E>(log_id: LogIdHash, hash: &Hash, header: &Header<E>) -> LogKey {
+    (
+        log_id,
+        *header.public_key.as_bytes(),
+        header.seq_num,
+        header.timestamp,
+        *hash.as_bytes(),
+    )
+}
+
+/// Collect the keys of all operations in the given range of a log.
+fn log_entries<T>(
+    table: &T,
+    range: (Bound<LogKey>, Bound<LogKey>),
+) -> Result<Vec<LogKey>, RedbStoreError>
+where
+    T: ReadableTable<LogKey, ()>,
+{
+    let mut entries = Vec::new();
+    for entry in table.range(range)? {
+        let (key, _) = entry?;
+        entries.push(key.value());
+    }
+    Ok(entries)
+}
+
+/// Look up the body bytes of an operation in the payloads table.
+fn get_body<P>(
+    payloads: &P,
+    payload_hash: Option<PayloadHash>,
+) -> Result<Option<Vec<u8>>, RedbStoreError>
+where
+    P: ReadableTable<PayloadHash, PayloadValue>,
+{
+    let Some(payload_hash) = payload_hash else {
+        return Ok(None);
+    };
+    Ok(payloads
+        .get(payload_hash)?
+        .map(|payload| payload.value().0.to_vec()))
+}
+
+fn get_operation<T, P, E>(
+    operations: &T,
+    payloads: &P,
+    hash: &Hash,
+) -> Result<Option<Operation<E>>, RedbStoreError>
+where
+    T: ReadableTable<[u8; 32], OperationValue>,
+    P: ReadableTable<PayloadHash, PayloadValue>,
+    E: Extensions,
+{
+    let Some(operation) = operations.get(hash.as_bytes())? else {
+        return Ok(None);
+    };
+    let (_, header_bytes, payload_hash) = operation.value();
+    let header = decode_cbor(header_bytes)?;
+    let body = get_body(payloads, payload_hash)?;
+    Ok(Some((header, body.map(Body::from))))
+}
+
+fn get_raw_operation<T, P>(
+    operations: &T,
+    payloads: &P,
+    hash: &Hash,
+) -> Result<Option<RawOperation>, RedbStoreError>
+where
+    T: ReadableTable<[u8; 32], OperationValue>,
+    P: ReadableTable<PayloadHash, PayloadValue>,
+{
+    let Some(operation) = operations.get(hash.as_bytes())? else {
+        return Ok(None);
+    };
+    let (_, header_bytes, payload_hash) = operation.value();
+    Ok(Some((
+        header_bytes.to_vec(),
+        get_body(payloads, payload_hash)?,
+    )))
+}
+
+/// Store a payload or increase its reference count if it already exists.
+fn retain_payload(
+    payloads: &mut Table<PayloadHash, PayloadValue>,
+    body: &Body,
+) -> Result<PayloadHash, RedbStoreError> {
+    let payload_hash = *body.hash().as_bytes();
+    let refs = payloads
+        .get(payload_hash)?
+        .map_or(0, |payload| payload.value().1);
+    payloads.insert(payload_hash, (body.to_bytes().as_slice(), refs + 1))?;
+    Ok(payload_hash)
+}
+
+/// Decrease the reference count of a payload, it is only removed during garbage collection.
+///
+/// Returns the size of the released payload.
+fn release_payload(
+    payloads: &mut Table<PayloadHash, PayloadValue>,
+    payload_hash: Option<PayloadHash>,
+) -> Result<u64, RedbStoreError> {
+    let Some(payload_hash) = payload_hash else {
+        return Ok(0);
+    };
+    let Some((bytes, refs)) = payloads.get(payload_hash)?.map(|payload| {
+        let (bytes, refs) = payload.value();
+        (bytes.to_vec(), refs)
+    }) else {
+        return Ok(0);
+    };
+    payloads.insert(payload_hash, (bytes.as_slice(), refs.saturating_sub(1)))?;
+    Ok(bytes.len() as u64)
+}
+
+/// Remove the body of an operation.
+///
+/// Returns the log id hash and header bytes of the operation and the size of the removed body, or
+/// `None` if the operation doesn't exist.
+fn remove_body(
+    operations: &mut Table<[u8; 32], OperationValue>,
+    payloads: &mut Table<PayloadHash, PayloadValue>,
+    hash: &Hash,
+) -> Result<Option<(LogIdHash, Vec<u8>, u64)>, RedbStoreError> {
+    let Some((log_id, header_bytes, payload_hash)) =
+        operations.get(hash.as_bytes())?.map(|operation| {
+            let (log_id, header_bytes, payload_hash) = operation.value();
+            (log_id, header_bytes.to_vec(), payload_hash)
+        })
+    else {
+        return Ok(None);
+    };
+    operations.insert(hash.as_bytes(), (log_id, header_bytes.as_slice(), None))?;
+    let removed = release_payload(payloads, payload_hash)?;
+    Ok(Some((log_id, header_bytes, removed)))
+}
+
+/// Apply changes of the operation count and size to the head of a log and look up its latest
+/// operation again. The head is removed when the log is empty.
+fn update_head<T>(
+    heads: &mut Table<HeadKey, HeadValue>,
+    logs: &T,
+    (log_id, public_key): HeadKey,
+    operations: i64,
+    size: i64,
+) -> Result<(), RedbStoreError>
+where
+    T: ReadableTable<LogKey, ()>,
+{
+    let Some(latest) = logs
+        .range(log_key_range(log_id, public_key, 0, None))?
+        .next_back()
+    else {
+        heads.remove((log_id, public_key))?;
+        return Ok(());
+    };
+    let (_, _, seq_num, _, hash) = latest?.0.value();
+
+    let (count, total_size) = heads.get((log_id, public_key))?.map_or((0, 0), |head| {
+        let (_, _, count, size) = head.value();
+        (count, size)
+    });
+    heads.insert(
+        (log_id, public_key),
+        (
+            seq_num,
+            hash,
+            count.saturating_add_signed(operations),
+            total_size.saturating_add_signed(size),
+        ),
+    )?;
+    Ok(())
+}
+
+/// Calculate the heads of all logs if they are missing, for example in databases created before
+/// heads were maintained.
+fn rebuild_heads(tx: &WriteTransaction) -> Result<(), RedbStoreError> {
+    let mut heads = tx.open_table(LOG_HEADS)?;
+    let logs = tx.open_table(LOGS)?;
+    if !heads.is_empty()? || logs.is_empty()? {
+        return Ok(());
+    }
+
+    let operations = tx.open_table(OPERATIONS)?;
+    let payloads = tx.open_table(PAYLOADS)?;
+
+    // Log entries are sorted by log and sequence number, the last entry of every log is its head.
+    let mut current: Option<(HeadKey, HeadValue)> = None;
+    for entry in logs.iter()? {
+        let (log_id, public_key, seq_num, _, hash) = entry?.0.value();
+        let size = match operations.get(hash)? {
+            Some(operation) => {
+                let (_, header_bytes, payload_hash) = operation.value();
+                let body = get_body(&payloads, payload_hash)?;
+                header_bytes.len() as u64 + body.map_or(0, |body| body.len() as u64)
+            }
+            None => 0,
+        };
+
+        match current.as_mut() {
+            Some((key, (latest_seq_num, latest_hash, count, total_size)))
+                if *key == (log_id, public_key) =>
+            {
+                *latest_seq_num = seq_num;
+                *latest_hash = hash;
+                *count += 1;
+                *total_size += size;
+            }
+            _ => {
+                if let Some((key, value)) = current.take() {
+                    heads.insert(key, value)?;
+                }
+                current = Some(((log_id, public_key), (seq_num, hash, 1, size)));
+            }
+        }
+    }
+    if let Some((key, value)) = current {
+        heads.insert(key, value)?;
+    }
+
+    Ok(())
+}
+
+/// All tables opened for writing within one transaction.
+struct WriteTables<'txn> {
+    operations: Table<'txn, [u8; 32], OperationValue>,
+    logs: Table<'txn, LogKey, ()>,
+    timestamps: Table<'txn, (Timestamp, [u8; 32]), ()>,
+    payloads: Table<'txn, PayloadHash, PayloadValue>,
+    heads: Table<'txn, HeadKey, HeadValue>,
+}
+
+impl<'txn> WriteTables<'txn> {
+    fn open(tx: &'txn WriteTransaction) -> Result<Self, RedbStoreError> {
+        Ok(Self {
+            operations: tx.open_table(OPERATIONS)?,
+            logs: tx.open_table(LOGS)?,
+            timestamps: tx.open_table(TIMESTAMPS)?,
+            payloads: tx.open_table(PAYLOADS)?,
+            heads: tx.open_table(LOG_HEADS)?,
+        })
+    }
+
+    /// Insert an operation, returns `false` if it already existed in the log.
+    fn insert_operation<E>(
+        &mut self,
+        hash: Hash,
+        header: &Header<E>,
+        body: Option<&Body>,
+        header_bytes: &[u8],
+        log_id: LogIdHash,
+    ) -> Result<bool, RedbStoreError> {
+        let existing = self
+            .logs
+            .insert(log_key(log_id, &hash, header), ())?
+            .is_some();
+        if existing {
+            return Ok(false);
+        }
+
+        let payload_hash = match body {
+            Some(body) => Some(retain_payload(&mut self.payloads, body)?),
+            None => None,
+        };
+        self.operations
+            .insert(hash.as_bytes(), (log_id, header_bytes, payload_hash))?;
+        self.timestamps
+            .insert((header.timestamp, *hash.as_bytes()), ())?;
+
+        let size = header_bytes.len() + body.map_or(0, |body| body.size() as usize);
+        update_head(
+            &mut self.heads,
+            &self.logs,
+            (log_id, *header.public_key.as_bytes()),
+            1,
+            size as i64,
+        )?;
+        Ok(true)
+    }
+}
+
+impl<L, E> OperationStore<L, E> for RedbStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = RedbStoreError;
+
+    async fn insert_operation(
+        &mut self,
+        hash: Hash,
+        header: &Header<E>,
+        body: Option<&Body>,
+        header_bytes: &[u8],
+        log_id: &L,
+    ) -> Result<bool, Self::Error> {
+        let tx = self.begin_write()?;
+        let insertion_occured = {
+            let mut tables = WriteTables::open(&tx)?;
+            tables.insert_operation(hash, header, body, header_bytes, calculate_hash(log_id))?
+        };
+        tx.commit()?;
+
+        Ok(insertion_occured)
+    }
+
+    async fn insert_operations(
+        &mut self,
+        operations: &[BatchOperation<'_, L, E>],
+    ) -> Result<usize, Self::Error> {
+        let tx = self.begin_write()?;
+        let mut inserted = 0;
+        {
+            let mut tables = WriteTables::open(&tx)?;
+            for operation in operations {
+                if tables.insert_operation(
+                    operation.hash,
+                    operation.header,
+                    operation.body,
+                    operation.header_bytes,
+                    calculate_hash(operation.log_id),
+                )? {
+                    inserted += 1;
+                }
+            }
+        }
+        tx.commit()?;
+
+        Ok(inserted)
+    }
+
+    async fn get_operation(
+        &self,
+        hash: Hash,
+    ) -> Result<Option<(Header<E>, Option<Body>)>, Self::Error> {
+        let tx = self.db.begin_read()?;
+        let operations = tx.open_table(OPERATIONS)?;
+        let payloads = tx.open_table(PAYLOADS)?;
+        get_operation(&operations, &payloads, &hash)
+    }
+
+    async fn get_raw_operation(&self, hash: Hash) -> Result<Option<RawOperation>, Self::Error> {
+        let tx = self.db.begin_read()?;
+        let operations = tx.open_table(OPERATIONS)?;
+        let payloads = tx.open_table(PAYLOADS)?;
+        get_raw_operation(&operations, &payloads, &hash)
+    }
+
+    async fn has_operation(&self, hash: Hash) -> Result<bool, Self::Error> {
+        let tx = self.db.begin_read()?;
+        let operations = tx.open_table(OPERATIONS)?;
+        Ok(operations.get(hash.as_bytes())?.is_some())
+    }
+
+    async fn delete_operation(&mut self, hash: Hash) -> Result<bool, Self::Error> {
+        let tx = self.begin_write()?;
+        let deleted = {
+            let mut operations = tx.open_table(OPERATIONS)?;
+            let removed = operations.remove(hash.as_bytes())?.map(|operation| {
+                let (log_id, header_bytes, payload_hash) = operation.value();
+                (log_id, header_bytes.to_vec(), payload_hash)
+            });
+            match removed {
+                Some((log_id, header_bytes, payload_hash)) => {
+                    let header: Header<E> = decode_cbor(&header_bytes[..])?;
+                    let mut payloads = tx.open_table(PAYLOADS)?;
+                    let released = release_payload(&mut payloads, payload_hash)?;
+                    let mut logs = tx.open_table(LOGS)?;
+                    logs.remove(log_key(log_id, &hash, &header))?;
+                    let mut timestamps = tx.open_table(TIMESTAMPS)?;
+                    timestamps.remove((header.timestamp, *hash.as_bytes()))?;
+                    let mut heads = tx.open_table(LOG_HEADS)?;
+                    update_head(
+                        &mut heads,
+                        &logs,
+                        (log_id, *header.public_key.as_bytes()),
+                        -1,
+                        -((header_bytes.len() as u64 + released) as i64),
+                    )?;
+                    true
+                }
+                None => false,
+            }
+        };
+        tx.commit()?;
+
+        Ok(deleted)
+    }
+
+    async fn delete_payload(&mut self, hash: Hash) -> Result<bool, Self::Error> {
+        let tx = self.begin_write()?;
+        let deleted = {
+            let mut operations = tx.open_table(OPERATIONS)?;
+            let mut payloads = tx.open_table(PAYLOADS)?;
+            match remove_body(&mut operations, &mut payloads, &hash)? {
+                Some((log_id, header_bytes, removed)) => {
+                    let header: Header<E> = decode_cbor(&header_bytes[..])?;
+                    let logs = tx.open_table(LOGS)?;
+                    let mut heads = tx.open_table(LOG_HEADS)?;
+                    update_head(
+                        &mut heads,
+                        &logs,
+                        (log_id, *header.public_key.as_bytes()),
+                        0,
+                        -(removed as i64),
+                    )?;
+                    true
+                }
+                None => false,
+            }
+        };
+        tx.commit()?;
+
+        Ok(deleted)
+    }
+}
+
+impl<L, E> LogStore<L, E> for RedbStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = RedbStoreError;
+
+    async fn get_log(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: Option<u64>,
+    ) -> Result<Option<Vec<(Header<E>, Option<Body>)>>, Self::Error> {
+        let tx = self.db.begin_read()?;
+        let logs = tx.open_table(LOGS)?;
+        let operations = tx.open_table(OPERATIONS)?;
+        let payloads = tx.open_table(PAYLOADS)?;
+
+        let range = log_range(public_key, log_id, from.unwrap_or(0), None);
+        let mut log = Vec::new();
+        for (_, _, _, _, hash) in log_entries(&logs, range)? {
+            let operation = get_operation(&operations, &payloads, &Hash::from(hash))?
+                .expect("operation exists in store");
+            log.push(operation);
+        }
+
+        if log.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(log))
+        }
+    }
+
+    async fn get_raw_log(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: Option<u64>,
+    ) -> Result<Option<Vec<RawOperation>>, Self::Error> {
+        let tx = self.db.begin_read()?;
+        let logs = tx.open_table(LOGS)?;
+        let operations = tx.open_table(OPERATIONS)?;
+        let payloads = tx.open_table(PAYLOADS)?;
+
+        let range = log_range(public_key, log_id, from.unwrap_or(0), None);
+        let mut log = Vec::new();
+        for (_, _, _, _, hash) in log_entries(&logs, range)? {
+            let operation = get_raw_operation(&operations, &payloads, &Hash::from(hash))?
+                .expect("operation exists in store");
+            log.push(operation);
+        }
+
+        if log.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(log))
+        }
+    }
+
+    async fn latest_operation(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+    ) -> Result<Option<(Header<E>, Option<Body>)>, Self::Error> {
+        let tx = self.db.begin_read()?;
+        let logs = tx.open_table(LOGS)?;
+        let operations = tx.open_table(OPERATIONS)?;
+        let payloads = tx.open_table(PAYLOADS)?;
+
+        let Some(entry) = logs
+            .range(log_range(public_key, log_id, 0, None))?
+            .next_back()
+        else {
+            return Ok(None);
+        };
+        let (key, _) = entry?;
+
+        get_operation(&operations, &payloads, &Hash::from(key.value().4))
+    }
+
+    async fn delete_operations(
+        &mut self,
+        public_key: &PublicKey,
+        log_id: &L,
+        before: u64,
+    ) -> Result<bool, Self::Error> {
+        let tx = self.begin_write()?;
+        let deleted = {
+            let mut logs = tx.open_table(LOGS)?;
+            let mut operations = tx.open_table(OPERATIONS)?;
+            let mut timestamps = tx.open_table(TIMESTAMPS)?;
+            let mut payloads = tx.open_table(PAYLOADS)?;
+            let mut heads = tx.open_table(LOG_HEADS)?;
+
+            let entries = log_entries(&logs, log_range(public_key, log_id, 0, Some(before)))?;
+            let mut removed = 0;
+            for key in &entries {
+                let (_, _, _, timestamp, hash) = key;
+                logs.remove(key)?;
+                if let Some(operation) = operations.remove(hash)? {
+                    let (_, header_bytes, payload_hash) = operation.value();
+                    removed += header_bytes.len() as u64;
+                    removed += release_payload(&mut payloads, payload_hash)?;
+                }
+                timestamps.remove((*timestamp, *hash))?;
+            }
+            if !entries.is_empty() {
+                update_head(
+                    &mut heads,
+                    &logs,
+                    (calculate_hash(log_id), *public_key.as_bytes()),
+                    -(entries.len() as i64),
+                    -(removed as i64),
+                )?;
+            }
+            !entries.is_empty()
+        };
+        tx.commit()?;
+
+        Ok(deleted)
+    }
+
+    async fn delete_payloads(
+        &mut self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: u64,
+        to: u64,
+    ) -> Result<bool, Self::Error> {
+        let tx = self.begin_write()?;
+        let deleted = {
+            let logs = tx.open_table(LOGS)?;
+            let mut operations = tx.open_table(OPERATIONS)?;
+            let mut payloads = tx.open_table(PAYLOADS)?;
+            let mut heads = tx.open_table(LOG_HEADS)?;
+
+            let entries = log_entries(&logs, log_range(public_key, log_id, from, Some(to)))?;
+            let mut removed = 0;
+            for (_, _, _, _, hash) in &entries {
+                if let Some((_, _, size)) =
+                    remove_body(&mut operations, &mut payloads, &Hash::from(hash))?
+                {
+                    removed += size;
+                }
+            }
+            if !entries.is_empty() {
+                update_head(
+                    &mut heads,
+                    &logs,
+                    (calculate_hash(log_id), *public_key.as_bytes()),
+                    0,
+                    -(removed as i64),
+                )?;
+            }
+            !entries.is_empty()
+        };
+        tx.commit()?;
+
+        Ok(deleted)
+    }
+
+    async fn get_log_heights(&self, log_id: &L) -> Result<Vec<(PublicKey, SeqNum)>, Self::Error> {
+        Ok(LogStore::<L, E>::log_heads(self, log_id)
+            .await?
+            .into_iter()
+            .map(|head| (head.public_key, head.seq_num))
+            .collect())
+    }
+
+    async fn log_heads(&self, log_id: &L) -> Result<Vec<LogHead>, Self::Error> {
+        let tx = self.db.begin_read()?;
+        let heads = tx.open_table(LOG_HEADS)?;
+
+        let log_id = calculate_hash(log_id);
+        let mut log_heads = Vec::new();
+        for entry in heads.range((log_id, [0; 32])..=(log_id, [u8::MAX; 32]))? {
+            let (key, value) = entry?;
+            let (_, public_key) = key.value();
+            let (seq_num, hash, operations, size) = value.value();
+            log_heads.push(LogHead {
+                public_key: PublicKey::try_from(public_key).expect("stored public keys are valid"),
+                seq_num,
+                hash: Hash::from(hash),
+                operations,
+                size,
+            });
+        }
+
+        Ok(log_heads)
+    }
+}
+
+impl<L, E> QueryStore<L, E> for RedbStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = RedbStoreError;
+
+    async fn query(&self, query: &OperationQuery<L>) -> Result<QueryPage<E>, Self::Error> {
+        let tx = self.db.begin_read()?;
+        let timestamps = tx.open_table(TIMESTAMPS)?;
+        let operations = tx.open_table(OPERATIONS)?;
+        let payloads = tx.open_table(PAYLOADS)?;
+
+        // Narrow down the scanned range of the timestamp index as far as possible, remaining
+        // filters are checked against every operation in that range.
+        let timestamp_range = query.timestamp_filter();
+        let start = timestamp_range.map_or(0, |range| range.start);
+        let start = match query.cursor() {
+            Some(cursor) if cursor.timestamp >= start => {
+                Bound::Excluded((cursor.timestamp, *cursor.hash.as_bytes()))
+            }
+            _ => Bound::Included((start, [0; 32])),
+        };
+        let end = match timestamp_range {
+            Some(range) => Bound::Excluded((range.end, [0; 32])),
+            None => Bound::Unbounded,
+        };
+
+        let log_ids: Vec<LogIdHash> = query.log_ids_filter().iter().map(calculate_hash).collect();
+        let limit = query.page_limit().map(|limit| limit.saturating_add(1));
+
+        let mut result = Vec::new();
+        for entry in timestamps.range::<(Timestamp, [u8; 32])>((start, end))? {
+            if limit.is_some_and(|limit| result.len() >= limit) {
+                break;
+            }
+
+            let (key, _) = entry?;
+            let hash = Hash::from(key.value().1);
+            let Some(operation) = operations.get(hash.as_bytes())? else {
+                continue;
+            };
+            let (log_id, header_bytes, payload_hash) = operation.value();
+            if !log_ids.is_empty() && !log_ids.contains(&log_id) {
+                continue;
+            }
+
+            let header: Header<E> = decode_cbor(header_bytes)?;
+            if let Some(cut) = query.cut_filter()
+                && !cut.contains_with(&header.public_key, header.seq_num, |cut_log_id| {
+                    calculate_hash(cut_log_id) == log_id
+                })
+            {
+                continue;
+            }
+
+            if query.matches_header(&hash, &header) {
+                let body = get_body(&payloads, payload_hash)?;
+                result.push((header, body.map(Body::from)));
+            }
+        }
+
+        Ok(query.paginate(result))
+    }
+}
+
+impl<L, E> PayloadStore for RedbStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = RedbStoreError;
+
+    async fn gc(&mut self) -> Result<u64, Self::Error> {
+        let tx = self.begin_write()?;
+        let mut reclaimed = 0;
+        {
+            let mut payloads = tx.open_table(PAYLOADS)?;
+            let mut unreferenced = Vec::new();
+            for entry in payloads.iter()? {
+                let (key, value) = entry?;
+                let (bytes, refs) = value.value();
+                if refs == 0 {
+                    unreferenced.push(key.value());
+                    reclaimed += bytes.len() as u64;
+                }
+            }
+            for payload_hash in unreferenced {
+                payloads.remove(payload_hash)?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(reclaimed)
+    }
+}
+
+impl<L, E> SizedStore for RedbStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = RedbStoreError;
+
+    async fn size(&self) -> Result<u64, Self::Error> {
+        // Statistics are only available in write transactions, nothing is written.
+        let tx = self.db.begin_write()?;
+        let stats = tx.stats()?;
+        tx.abort()?;
+
+        Ok(stats.stored_bytes() + stats.metadata_bytes() + stats.fragmented_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_core::{Body, PrivateKey};
+
+    use crate::Durability;
+    use crate::conformance;
+    use crate::prune::{PruneBefore, prune};
+    use crate::{LogStore, OperationStore, PayloadStore, SizedStore};
+
+    use super::{RedbStore, in_memory_database};
+
+    fn store() -> RedbStore<u64> {
+        RedbStore::new(in_memory_database().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        conformance::run(store()).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writers() {
+        conformance::concurrent_writers(store()).await;
+    }
+
+    #[tokio::test]
+    async fn collect_garbage() {
+        conformance::collect_garbage(&mut store()).await;
+    }
+
+    #[tokio::test]
+    async fn eventual_durability() {
+        let store = store().with_durability(Durability::Eventual);
+        conformance::run(store.clone()).await;
+        store.flush().unwrap();
+    }
+
+    #[tokio::test]
+    async fn measure_size() {
+        let mut store = store();
+        assert!(store.size().await.unwrap() > 0);
+
+        let private_key = PrivateKey::new();
+        let body = Body::new(&[0; 4096]);
+        let (hash, header, header_bytes) =
+            conformance::create_operation(&private_key, &body, 0, 0, None);
+        store
+            .insert_operation(hash, &header, Some(&body), &header_bytes, &0)
+            .await
+            .unwrap();
+        // The database grows in larger steps, free space is included in its size.
+        assert!(store.size().await.unwrap() >= 4096);
+    }
+
+    #[tokio::test]
+    async fn prune_and_compact() {
+        let mut store = store();
+        let private_key = PrivateKey::new();
+        let mut backlink = None;
+        for seq_num in 0..3 {
+            let body = Body::new(&[seq_num as u8; 1024]);
+            let (hash, header, header_bytes) =
+                conformance::create_operation(&private_key, &body, seq_num, seq_num, backlink);
+            store
+                .insert_operation(hash, &header, Some(&body), &header_bytes, &0)
+                .await
+                .unwrap();
+            backlink = Some(hash);
+        }
+
+        let report = prune(
+            &mut store,
+            &private_key.public_key(),
+            &0,
+            PruneBefore::SeqNum(2),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(report.deleted, 1);
+
+        // Payloads of the deleted operation and the checkpoint are not referenced anymore.
+        assert_eq!(store.gc().await.unwrap(), 2048);
+        assert!(store.compact().is_ok());
+
+        // Compaction requires exclusive access to the database.
+        let _other = store.clone();
+        assert!(!store.compact().unwrap());
+    }
+
+    #[tokio::test]
+    async fn rebuild_log_heads() {
+        let mut store = store();
+        let private_key = PrivateKey::new();
+        let mut backlink = None;
+        for seq_num in 0..3 {
+            let body = Body::new(format!("operation {seq_num}").as_bytes());
+            let (hash, header, header_bytes) =
+                conformance::create_operation(&private_key, &body, seq_num, seq_num, backlink);
+            store
+                .insert_operation(hash, &header, Some(&body), &header_bytes, &0)
+                .await
+                .unwrap();
+            backlink = Some(hash);
+        }
+        let heads = store.log_heads(&0).await.unwrap();
+
+        // Heads are calculated again for databases which don't contain them yet.
+        let tx = store.db.begin_write().unwrap();
+        tx.delete_table(super::LOG_HEADS).unwrap();
+        super::rebuild_heads(&tx).unwrap();
+        tx.commit().unwrap();
+
+        assert_eq!(store.log_heads(&0).await.unwrap(), heads);
+    }
+
+    #[tokio::test]
+    async fn persisted_across_instances() {
+        let dir = std::env::temp_dir().join(format!("p2panda-redb-{}", rand::random::<u32>()));
+        let private_key = PrivateKey::new();
+        let body = Body::new("hello!".as_bytes());
+        let (hash, header, header_bytes) =
+            conformance::create_operation(&private_key, &body, 0, 0, None);
+
+        {
+            let mut store: RedbStore<u64> =
+                RedbStore::new(super::open_database(&dir).unwrap()).unwrap();
+            assert!(
+                store
+                    .insert_operation(hash, &header, Some(&body), &header_bytes, &0)
+                    .await
+                    .unwrap()
+            );
+        }
+
+        let store: RedbStore<u64> = RedbStore::new(super::open_database(&dir).unwrap()).unwrap();
+        assert!(store.has_operation(hash).await.unwrap());
+        let log = store
+            .get_log(&private_key.public_key(), &0, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(log, vec![(header, Some(body))]);
+
+        std::fs::remove_file(dir).unwrap();
+    }
+}
@@ -5,6 +5,8 @@ use std::str::FromStr;
 
 use p2panda_core::cbor::decode_cbor;
 use p2panda_core::{Extensions, Hash, Header, PublicKey, RawOperation, Signature};
+
+use crate::LogHead;
 use sqlx::FromRow;
 
 /// A single "raw" operation row as it is inserted in the database.
@@ -86,3 +88,25 @@ impl From<LogHeightRow> for (PublicKey, u64) {
         )
     }
 }
+
+/// A single log head row as it is queried from the database.
+#[derive(FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct LogHeadRow {
+    public_key: String,
+    seq_num: String,
+    hash: String,
+    operations: i64,
+    size: i64,
+}
+
+impl From<LogHeadRow> for LogHead {
+    fn from(row: LogHeadRow) -> Self {
+        LogHead {
+            public_key: row.public_key.parse().unwrap(),
+            seq_num: row.seq_num.parse().unwrap(),
+            hash: row.hash.parse().unwrap(),
+            operations: row.operations as u64,
+            size: row.size as u64,
+        }
+    }
+}
@@ -3,18 +3,27 @@
 //! SQLite persistent storage.
 use std::hash::{DefaultHasher, Hash as StdHash, Hasher};
 use std::marker::PhantomData;
+use std::time::Duration;
 
 use sqlx::migrate;
 use sqlx::migrate::{MigrateDatabase, MigrateError};
-use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
-use sqlx::{Error as SqlxError, Sqlite, query, query_as};
+use sqlx::query::Query;
+use sqlx::sqlite::{
+    SqliteArguments, SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions,
+    SqliteSynchronous,
+};
+use sqlx::{Error as SqlxError, QueryBuilder, Sqlite, query, query_as};
 use thiserror::Error;
 
 use p2panda_core::cbor::{DecodeError, EncodeError, encode_cbor};
 use p2panda_core::{Body, Extensions, Hash, Header, PublicKey, RawOperation};
 
-use crate::sqlite::models::{LogHeightRow, OperationRow, RawOperationRow};
-use crate::{LogId, LogStore, OperationStore};
+use crate::query::{OperationQuery, QueryPage};
+use crate::sqlite::models::{LogHeadRow, LogHeightRow, OperationRow, RawOperationRow};
+use crate::{
+    BatchOperation, Durability, LogHead, LogId, LogStore, OperationStore, PayloadStore, QueryStore,
+    SizedStore,
+};
 
 #[derive(Debug, Error)]
 pub enum SqliteStoreError {
@@ -34,6 +43,9 @@ impl From<MigrateError> for SqliteStoreError {
     }
 }
 
+/// Time a connection waits for the write lock held by another connection.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Re-export of SQLite connection pool type.
 pub type Pool = SqlitePool;
 
@@ -56,6 +68,24 @@ where
             _marker: PhantomData {},
         }
     }
+
+    /// Persist all previous writes to disk.
+    ///
+    /// This is only required for pools created with `Durability::Eventual`.
+    pub async fn flush(&self) -> Result<(), SqliteStoreError> {
+        query("PRAGMA wal_checkpoint(FULL)")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Rebuild the database file to return space freed by deleted operations.
+    pub async fn compact(&self) -> Result<(), SqliteStoreError> {
+        query("VACUUM").execute(&self.pool).await?;
+
+        Ok(())
+    }
 }
 
 /// Create the database if it doesn't already exist.
@@ -78,9 +108,34 @@ pub async fn drop_database(url: &str) -> Result<(), SqliteStoreError> {
 
 /// Create a connection pool.
 pub async fn connection_pool(url: &str, max_connections: u32) -> Result<Pool, SqliteStoreError> {
+    connection_pool_with_durability(url, max_connections, Durability::Immediate).await
+}
+
+/// Create a connection pool with the given durability of writes.
+///
+/// The database uses write-ahead logging, readers therefore don't block writers and the other way
+/// around. Writers are serialised by SQLite, connections wait for the write lock instead of
+/// failing with a "database is locked" error.
+///
+/// With `Durability::Eventual` the database only syncs to disk at checkpoints. Call
+/// `SqliteStore::flush` to persist all previous writes explicitly.
+pub async fn connection_pool_with_durability(
+    url: &str,
+    max_connections: u32,
+    durability: Durability,
+) -> Result<Pool, SqliteStoreError> {
+    let options: SqliteConnectOptions = url.parse()?;
+    let options = options
+        .journal_mode(SqliteJournalMode::Wal)
+        .busy_timeout(BUSY_TIMEOUT)
+        .synchronous(match durability {
+            Durability::Immediate => SqliteSynchronous::Full,
+            Durability::Eventual => SqliteSynchronous::Normal,
+        });
+
     let pool: Pool = SqlitePoolOptions::new()
         .max_connections(max_connections)
-        .connect(url)
+        .connect_with(options)
         .await?;
 
     Ok(pool)
@@ -99,6 +154,87 @@ fn calculate_hash<T: StdHash>(t: &T) -> u64 {
     s.finish()
 }
 
+/// Query inserting a single operation.
+fn insert_operation_query<'q, L, E>(
+    hash: Hash,
+    header: &Header<E>,
+    body: Option<&Body>,
+    header_bytes: &'q [u8],
+    log_id: &L,
+) -> Query<'q, Sqlite, SqliteArguments<'q>>
+where
+    L: LogId,
+    E: Extensions,
+{
+    query(
+        "
+        INSERT INTO
+            operations_v1 (
+                hash,
+                log_id,
+                version,
+                public_key,
+                signature,
+                payload_size,
+                payload_hash,
+                timestamp,
+                seq_num,
+                backlink,
+                previous,
+                extensions,
+                body_hash,
+                header_bytes
+            )
+        VALUES
+            (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (hash) DO NOTHING
+        ",
+    )
+    .bind(hash.to_string())
+    .bind(calculate_hash(log_id).to_string())
+    .bind(header.version.to_string())
+    .bind(header.public_key.to_hex())
+    .bind(header.signature.map(|sig| sig.to_hex()))
+    .bind(header.payload_size.to_string())
+    .bind(header.payload_hash.map(|hash| hash.to_hex()))
+    .bind(header.timestamp.to_string())
+    .bind(header.seq_num.to_string())
+    .bind(header.backlink.map(|backlink| backlink.to_hex()))
+    .bind(
+        header
+            .previous
+            .iter()
+            .map(|previous| previous.to_hex())
+            .collect::<Vec<String>>()
+            .concat(),
+    )
+    .bind(
+        header
+            .extensions
+            .as_ref()
+            .map(|extensions| encode_cbor(extensions).expect("extenions are serializable")),
+    )
+    .bind(body.map(|body| body.hash().to_hex()))
+    .bind(header_bytes)
+}
+
+/// Query inserting a payload if it doesn't exist yet.
+fn insert_payload_query(body: &Body) -> Query<'_, Sqlite, SqliteArguments<'_>> {
+    query(
+        "
+        INSERT OR IGNORE INTO
+            payloads_v1 (
+                body_hash,
+                bytes
+            )
+        VALUES
+            (?, ?)
+        ",
+    )
+    .bind(body.hash().to_hex())
+    .bind(body.to_bytes())
+}
+
 impl<L, E> OperationStore<L, E> for SqliteStore<L, E>
 where
     L: LogId + Send + Sync,
@@ -114,59 +250,44 @@ where
         header_bytes: &[u8],
         log_id: &L,
     ) -> Result<bool, Self::Error> {
-        query(
-            "
-            INSERT INTO
-                operations_v1 (
-                    hash,
-                    log_id,
-                    version,
-                    public_key,
-                    signature,
-                    payload_size,
-                    payload_hash,
-                    timestamp,
-                    seq_num,
-                    backlink,
-                    previous,
-                    extensions,
-                    body,
-                    header_bytes
-                )
-            VALUES
-                (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-            ",
-        )
-        .bind(hash.to_string())
-        .bind(calculate_hash(log_id).to_string())
-        .bind(header.version.to_string())
-        .bind(header.public_key.to_hex())
-        .bind(header.signature.map(|sig| sig.to_hex()))
-        .bind(header.payload_size.to_string())
-        .bind(header.payload_hash.map(|hash| hash.to_hex()))
-        .bind(header.timestamp.to_string())
-        .bind(header.seq_num.to_string())
-        .bind(header.backlink.map(|backlink| backlink.to_hex()))
-        .bind(
-            header
-                .previous
-                .iter()
-                .map(|previous| previous.to_hex())
-                .collect::<Vec<String>>()
-                .concat(),
-        )
-        .bind(
-            header
-                .extensions
-                .as_ref()
-                .map(|extensions| encode_cbor(extensions).expect("extenions are serializable")),
-        )
-        .bind(body.map(|body| body.to_bytes()))
-        .bind(header_bytes)
-        .execute(&self.pool)
-        .await?;
+        let mut tx = self.pool.begin().await?;
+        if let Some(body) = body {
+            insert_payload_query(body).execute(&mut *tx).await?;
+        }
+        let result = insert_operation_query(hash, header, body, header_bytes, log_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn insert_operations(
+        &mut self,
+        operations: &[BatchOperation<'_, L, E>],
+    ) -> Result<usize, Self::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut inserted = 0;
+        for operation in operations {
+            if let Some(body) = operation.body {
+                insert_payload_query(body).execute(&mut *tx).await?;
+            }
+            let result = insert_operation_query(
+                operation.hash,
+                operation.header,
+                operation.body,
+                operation.header_bytes,
+                operation.log_id,
+            )
+            .execute(&mut *tx)
+            .await?;
+            if result.rows_affected() > 0 {
+                inserted += 1;
+            }
+        }
+        tx.commit().await?;
 
-        Ok(true)
+        Ok(inserted)
     }
 
     async fn get_operation(
@@ -188,10 +309,11 @@ where
                 backlink,
                 previous,
                 extensions,
-                body,
+                payloads_v1.bytes AS body,
                 header_bytes
             FROM
                 operations_v1
+                LEFT JOIN payloads_v1 ON payloads_v1.body_hash = operations_v1.body_hash
             WHERE
                 hash = ?
             ",
@@ -214,10 +336,11 @@ where
             "
             SELECT
                 hash,
-                body,
+                payloads_v1.bytes AS body,
                 header_bytes
             FROM
                 operations_v1
+                LEFT JOIN payloads_v1 ON payloads_v1.body_hash = operations_v1.body_hash
             WHERE
                 hash = ?
             ",
@@ -275,7 +398,7 @@ where
             UPDATE
                 operations_v1
             SET
-                body = NULL
+                body_hash = NULL
             WHERE
                 operations_v1.hash = ?
             ",
@@ -316,10 +439,11 @@ where
                 backlink,
                 previous,
                 extensions,
-                body,
+                payloads_v1.bytes AS body,
                 header_bytes
             FROM
                 operations_v1
+                LEFT JOIN payloads_v1 ON payloads_v1.body_hash = operations_v1.body_hash
             WHERE
                 public_key = ?
                 AND log_id = ?
@@ -361,10 +485,11 @@ where
             "
             SELECT
                 hash,
-                body,
+                payloads_v1.bytes AS body,
                 header_bytes
             FROM
                 operations_v1
+                LEFT JOIN payloads_v1 ON payloads_v1.body_hash = operations_v1.body_hash
             WHERE
                 public_key = ?
                 AND log_id = ?
@@ -411,10 +536,11 @@ where
                 backlink,
                 previous,
                 extensions,
-                body,
+                payloads_v1.bytes AS body,
                 header_bytes
             FROM
                 operations_v1
+                LEFT JOIN payloads_v1 ON payloads_v1.body_hash = operations_v1.body_hash
             WHERE
                 public_key = ?
                 AND log_id = ?
@@ -474,7 +600,7 @@ where
             UPDATE
                 operations_v1
             SET
-                body = NULL
+                body_hash = NULL
             WHERE
                 public_key = ?
                 AND log_id = ?
@@ -497,13 +623,11 @@ where
             "
             SELECT
                 public_key,
-                CAST(MAX(CAST(seq_num AS NUMERIC)) AS TEXT) as seq_num
+                seq_num
             FROM
-                operations_v1
+                log_heads_v1
             WHERE
                 log_id = ?
-            GROUP BY
-                public_key
             ",
         )
         .bind(calculate_hash(log_id).to_string())
@@ -517,6 +641,204 @@ where
 
         Ok(log_heights)
     }
+
+    async fn log_heads(&self, log_id: &L) -> Result<Vec<LogHead>, Self::Error> {
+        let log_heads = query_as::<_, LogHeadRow>(
+            "
+            SELECT
+                public_key,
+                seq_num,
+                hash,
+                operations,
+                size
+            FROM
+                log_heads_v1
+            WHERE
+                log_id = ?
+            ",
+        )
+        .bind(calculate_hash(log_id).to_string())
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.into())
+        .collect();
+
+        Ok(log_heads)
+    }
+}
+
+impl<L, E> QueryStore<L, E> for SqliteStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = SqliteStoreError;
+
+    async fn query(&self, query: &OperationQuery<L>) -> Result<QueryPage<E>, Self::Error> {
+        let mut builder = QueryBuilder::<Sqlite>::new(
+            "
+            SELECT
+                hash,
+                log_id,
+                version,
+                public_key,
+                signature,
+                payload_size,
+                payload_hash,
+                timestamp,
+                seq_num,
+                backlink,
+                previous,
+                extensions,
+                payloads_v1.bytes AS body,
+                header_bytes
+            FROM
+                operations_v1
+                LEFT JOIN payloads_v1 ON payloads_v1.body_hash = operations_v1.body_hash
+            WHERE
+                1 = 1
+            ",
+        );
+
+        if let Some(public_key) = query.public_key_filter() {
+            builder
+                .push(" AND public_key = ")
+                .push_bind(public_key.to_hex());
+        }
+
+        if !query.log_ids_filter().is_empty() {
+            builder.push(" AND log_id IN (");
+            let mut separated = builder.separated(", ");
+            for log_id in query.log_ids_filter() {
+                separated.push_bind(calculate_hash(log_id).to_string());
+            }
+            separated.push_unseparated(")");
+        }
+
+        if let Some(range) = query.timestamp_filter() {
+            builder
+                .push(" AND CAST(timestamp AS NUMERIC) >= CAST(")
+                .push_bind(range.start.to_string())
+                .push(" AS NUMERIC) AND CAST(timestamp AS NUMERIC) < CAST(")
+                .push_bind(range.end.to_string())
+                .push(" AS NUMERIC)");
+        }
+
+        if let Some(range) = query.seq_num_filter() {
+            builder
+                .push(" AND CAST(seq_num AS NUMERIC) >= CAST(")
+                .push_bind(range.start.to_string())
+                .push(" AS NUMERIC) AND CAST(seq_num AS NUMERIC) < CAST(")
+                .push_bind(range.end.to_string())
+                .push(" AS NUMERIC)");
+        }
+
+        if let Some(cut) = query.cut_filter() {
+            // Logs which are not part of the cut are not visible.
+            builder.push(" AND (0 = 1");
+            for (public_key, log_id, seq_num) in cut.iter() {
+                builder
+                    .push(" OR (public_key = ")
+                    .push_bind(public_key.to_hex())
+                    .push(" AND log_id = ")
+                    .push_bind(calculate_hash(log_id).to_string())
+                    .push(" AND CAST(seq_num AS NUMERIC) <= CAST(")
+                    .push_bind(seq_num.to_string())
+                    .push(" AS NUMERIC))");
+            }
+            builder.push(")");
+        }
+
+        if let Some(cursor) = query.cursor() {
+            builder
+                .push(" AND (CAST(timestamp AS NUMERIC) > CAST(")
+                .push_bind(cursor.timestamp.to_string())
+                .push(" AS NUMERIC) OR (CAST(timestamp AS NUMERIC) = CAST(")
+                .push_bind(cursor.timestamp.to_string())
+                .push(" AS NUMERIC) AND hash > ")
+                .push_bind(cursor.hash.to_hex())
+                .push("))");
+        }
+
+        builder.push(" ORDER BY CAST(timestamp AS NUMERIC), hash");
+
+        // Fetch one more operation than requested to know if there's a next page.
+        if let Some(limit) = query.page_limit() {
+            builder
+                .push(" LIMIT ")
+                .push_bind(i64::try_from(limit.saturating_add(1)).unwrap_or(i64::MAX));
+        }
+
+        let operations = builder
+            .build_query_as::<OperationRow>()
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|operation| {
+                (
+                    operation.clone().into(),
+                    operation.body.map(|body| body.into()),
+                )
+            })
+            .collect();
+
+        Ok(query.paginate(operations))
+    }
+}
+
+impl<L, E> PayloadStore for SqliteStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = SqliteStoreError;
+
+    async fn gc(&mut self) -> Result<u64, Self::Error> {
+        // Payloads are referenced by all operations with the same body hash, which is indexed.
+        // Selecting and deleting them with a single statement avoids upgrading a read transaction
+        // to a write transaction, which fails when another connection is writing at that moment.
+        let reclaimed: Vec<(i64,)> = query_as(
+            "
+            DELETE
+            FROM
+                payloads_v1
+            WHERE
+                NOT EXISTS (
+                    SELECT
+                        1
+                    FROM
+                        operations_v1
+                    WHERE
+                        operations_v1.body_hash = payloads_v1.body_hash
+                )
+            RETURNING
+                LENGTH(bytes)
+            ",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let reclaimed: i64 = reclaimed.into_iter().map(|(bytes,)| bytes).sum();
+
+        Ok(reclaimed as u64)
+    }
+}
+
+impl<L, E> SizedStore for SqliteStore<L, E>
+where
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = SqliteStoreError;
+
+    async fn size(&self) -> Result<u64, Self::Error> {
+        let (size,): (i64,) =
+            query_as("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()")
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(size as u64)
+    }
 }
 
 #[cfg(test)]
@@ -524,10 +846,15 @@ mod tests {
     use p2panda_core::{Body, Hash, Header, PrivateKey};
     use serde::{Deserialize, Serialize};
 
-    use crate::sqlite::test_utils::initialize_sqlite_db;
-    use crate::{LogStore, OperationStore};
+    use crate::Durability;
+    use crate::conformance;
+    use crate::sqlite::test_utils::{db_test_url, initialize_sqlite_db};
+    use crate::{LogStore, OperationStore, PayloadStore, SizedStore};
 
-    use super::SqliteStore;
+    use super::{
+        SqliteStore, connection_pool, connection_pool_with_durability, create_database,
+        drop_database, run_pending_migrations,
+    };
 
     fn create_operation(
         private_key: &PrivateKey,
@@ -553,6 +880,63 @@ mod tests {
         (header.hash(), header, header_bytes)
     }
 
+    #[tokio::test]
+    async fn conformance() {
+        conformance::run(SqliteStore::<u64, ()>::new(initialize_sqlite_db().await)).await;
+    }
+
+    #[tokio::test]
+    async fn collect_garbage() {
+        let mut store = SqliteStore::<u64, ()>::new(initialize_sqlite_db().await);
+        conformance::collect_garbage(&mut store).await;
+        assert_eq!(store.gc().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn measure_size() {
+        let mut store = SqliteStore::<u64, ()>::new(initialize_sqlite_db().await);
+        let empty = store.size().await.unwrap();
+        assert!(empty > 0);
+
+        let private_key = PrivateKey::new();
+        let body = Body::new(&[0; 8192]);
+        let (hash, header, header_bytes) = create_operation(&private_key, &body, 0, 0, None);
+        store
+            .insert_operation(hash, &header, Some(&body), &header_bytes, &0)
+            .await
+            .unwrap();
+        assert!(store.size().await.unwrap() > empty);
+    }
+
+    #[tokio::test]
+    async fn eventual_durability() {
+        let url = db_test_url();
+        create_database(&url).await.unwrap();
+        let pool = connection_pool_with_durability(&url, 1, Durability::Eventual)
+            .await
+            .unwrap();
+        run_pending_migrations(&pool).await.unwrap();
+
+        let store = SqliteStore::<u64, ()>::new(pool);
+        conformance::run(store.clone()).await;
+        store.flush().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writers() {
+        // In-memory databases don't support write-ahead logging, use a file instead.
+        let path = std::env::temp_dir().join(format!("p2panda-{}.sqlite", rand::random::<u32>()));
+        let url = format!("sqlite://{}", path.display());
+        create_database(&url).await.unwrap();
+        let pool = connection_pool(&url, 8).await.unwrap();
+        run_pending_migrations(&pool).await.unwrap();
+
+        conformance::concurrent_writers(SqliteStore::<u64, ()>::new(pool.clone())).await;
+
+        pool.close().await;
+        drop_database(&url).await.unwrap();
+    }
+
     #[tokio::test]
     async fn default_sqlite_store() {
         let db_pool = initialize_sqlite_db().await;
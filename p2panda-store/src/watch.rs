@@ -0,0 +1,369 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Change notifications for stores.
+//!
+//! `WatchedStore` wraps any store backend and broadcasts a `StoreEvent` whenever operations are
+//! inserted into or deleted from it. Higher layers, like materialisers or user interfaces, can
+//! subscribe with `watch` and react to new data without polling the store.
+//!
+//! Events are only emitted for writes going through the wrapper, all writers should therefore
+//! share (clones of) the same `WatchedStore`. Slow subscribers which fall behind more than the
+//! channel capacity miss events and receive a `RecvError::Lagged` error instead.
+use p2panda_core::{Body, Extensions, Hash, Header, PublicKey, RawOperation};
+use tokio::sync::broadcast;
+
+use crate::query::{OperationQuery, QueryPage};
+use crate::{BatchOperation, LogHead, LogId, LogStore, OperationStore, PayloadStore, QueryStore};
+
+/// Default number of events buffered for every subscriber.
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Change of the store contents.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StoreEvent<L> {
+    /// Operation was inserted into an author's log.
+    Inserted {
+        hash: Hash,
+        public_key: PublicKey,
+        log_id: L,
+    },
+
+    /// Operation was deleted.
+    ///
+    /// The log id is only known when operations were deleted from a log with
+    /// `LogStore::delete_operations`, not when deleting a single operation by hash.
+    Deleted {
+        hash: Hash,
+        public_key: PublicKey,
+        log_id: Option<L>,
+    },
+}
+
+/// Store wrapper broadcasting events for all inserted and deleted operations.
+#[derive(Clone, Debug)]
+pub struct WatchedStore<S, L> {
+    store: S,
+    tx: broadcast::Sender<StoreEvent<L>>,
+}
+
+impl<S, L> WatchedStore<S, L>
+where
+    L: Clone,
+{
+    /// Wrap a store to emit change events.
+    pub fn new(store: S) -> Self {
+        Self::with_capacity(store, DEFAULT_CAPACITY)
+    }
+
+    /// Wrap a store to emit change events, buffering up to `capacity` events for every
+    /// subscriber.
+    pub fn with_capacity(store: S, capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self { store, tx }
+    }
+
+    /// Subscribe to all following changes of the store.
+    pub fn watch(&self) -> broadcast::Receiver<StoreEvent<L>> {
+        self.tx.subscribe()
+    }
+
+    /// Access the wrapped store.
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    fn emit(&self, event: StoreEvent<L>) {
+        // Sending only fails if there are no subscribers, which is fine.
+        let _ = self.tx.send(event);
+    }
+}
+
+impl<S, L, E> OperationStore<L, E> for WatchedStore<S, L>
+where
+    S: OperationStore<L, E> + Sync,
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = <S as OperationStore<L, E>>::Error;
+
+    async fn insert_operation(
+        &mut self,
+        hash: Hash,
+        header: &Header<E>,
+        body: Option<&Body>,
+        header_bytes: &[u8],
+        log_id: &L,
+    ) -> Result<bool, Self::Error> {
+        let inserted = self
+            .store
+            .insert_operation(hash, header, body, header_bytes, log_id)
+            .await?;
+
+        if inserted {
+            self.emit(StoreEvent::Inserted {
+                hash,
+                public_key: header.public_key,
+                log_id: log_id.to_owned(),
+            });
+        }
+
+        Ok(inserted)
+    }
+
+    async fn insert_operations(
+        &mut self,
+        operations: &[BatchOperation<'_, L, E>],
+    ) -> Result<usize, Self::Error> {
+        // Remember which operations are new to only announce them after the batch was written.
+        let mut new_operations = Vec::new();
+        for operation in operations {
+            if !self.store.has_operation(operation.hash).await? {
+                new_operations.push(StoreEvent::Inserted {
+                    hash: operation.hash,
+                    public_key: operation.header.public_key,
+                    log_id: operation.log_id.to_owned(),
+                });
+            }
+        }
+
+        let inserted = self.store.insert_operations(operations).await?;
+
+        for event in new_operations {
+            self.emit(event);
+        }
+
+        Ok(inserted)
+    }
+
+    async fn get_operation(
+        &self,
+        hash: Hash,
+    ) -> Result<Option<(Header<E>, Option<Body>)>, Self::Error> {
+        self.store.get_operation(hash).await
+    }
+
+    async fn get_raw_operation(&self, hash: Hash) -> Result<Option<RawOperation>, Self::Error> {
+        self.store.get_raw_operation(hash).await
+    }
+
+    async fn has_operation(&self, hash: Hash) -> Result<bool, Self::Error> {
+        self.store.has_operation(hash).await
+    }
+
+    async fn delete_operation(&mut self, hash: Hash) -> Result<bool, Self::Error> {
+        let Some((header, _)) = self.store.get_operation(hash).await? else {
+            return Ok(false);
+        };
+
+        let deleted = self.store.delete_operation(hash).await?;
+
+        if deleted {
+            self.emit(StoreEvent::Deleted {
+                hash,
+                public_key: header.public_key,
+                log_id: None,
+            });
+        }
+
+        Ok(deleted)
+    }
+
+    async fn delete_payload(&mut self, hash: Hash) -> Result<bool, Self::Error> {
+        self.store.delete_payload(hash).await
+    }
+}
+
+impl<S, L, E> LogStore<L, E> for WatchedStore<S, L>
+where
+    S: LogStore<L, E> + Send + Sync,
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = <S as LogStore<L, E>>::Error;
+
+    async fn get_log(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: Option<u64>,
+    ) -> Result<Option<Vec<(Header<E>, Option<Body>)>>, Self::Error> {
+        self.store.get_log(public_key, log_id, from).await
+    }
+
+    async fn get_raw_log(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: Option<u64>,
+    ) -> Result<Option<Vec<RawOperation>>, Self::Error> {
+        self.store.get_raw_log(public_key, log_id, from).await
+    }
+
+    async fn get_log_heights(&self, log_id: &L) -> Result<Vec<(PublicKey, u64)>, Self::Error> {
+        self.store.get_log_heights(log_id).await
+    }
+
+    async fn log_heads(&self, log_id: &L) -> Result<Vec<LogHead>, Self::Error> {
+        self.store.log_heads(log_id).await
+    }
+
+    async fn latest_operation(
+        &self,
+        public_key: &PublicKey,
+        log_id: &L,
+    ) -> Result<Option<(Header<E>, Option<Body>)>, Self::Error> {
+        self.store.latest_operation(public_key, log_id).await
+    }
+
+    async fn delete_operations(
+        &mut self,
+        public_key: &PublicKey,
+        log_id: &L,
+        before: u64,
+    ) -> Result<bool, Self::Error> {
+        let hashes: Vec<Hash> = self
+            .store
+            .get_log(public_key, log_id, None)
+            .await?
+            .unwrap_or_default()
+            .iter()
+            .filter(|(header, _)| header.seq_num < before)
+            .map(|(header, _)| header.hash())
+            .collect();
+
+        let deleted = self
+            .store
+            .delete_operations(public_key, log_id, before)
+            .await?;
+
+        if deleted {
+            for hash in hashes {
+                self.emit(StoreEvent::Deleted {
+                    hash,
+                    public_key: *public_key,
+                    log_id: Some(log_id.to_owned()),
+                });
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    async fn delete_payloads(
+        &mut self,
+        public_key: &PublicKey,
+        log_id: &L,
+        from: u64,
+        to: u64,
+    ) -> Result<bool, Self::Error> {
+        self.store
+            .delete_payloads(public_key, log_id, from, to)
+            .await
+    }
+}
+
+impl<S, L, E> QueryStore<L, E> for WatchedStore<S, L>
+where
+    S: QueryStore<L, E> + Sync,
+    L: LogId + Send + Sync,
+    E: Extensions + Send + Sync,
+{
+    type Error = <S as QueryStore<L, E>>::Error;
+
+    async fn query(&self, query: &OperationQuery<L>) -> Result<QueryPage<E>, Self::Error> {
+        self.store.query(query).await
+    }
+}
+
+impl<S, L> PayloadStore for WatchedStore<S, L>
+where
+    S: PayloadStore + Send + Sync,
+    L: Send + Sync,
+{
+    type Error = <S as PayloadStore>::Error;
+
+    async fn gc(&mut self) -> Result<u64, Self::Error> {
+        self.store.gc().await
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use p2panda_core::{Body, PrivateKey};
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    use crate::conformance::{self, create_operation};
+    use crate::{LogStore, MemoryStore, OperationStore};
+
+    use super::{StoreEvent, WatchedStore};
+
+    #[tokio::test]
+    async fn conformance() {
+        conformance::run(WatchedStore::new(MemoryStore::<u64>::new())).await;
+    }
+
+    #[tokio::test]
+    async fn emit_events() {
+        let mut store = WatchedStore::new(MemoryStore::<u64>::new());
+        let mut rx = store.watch();
+
+        let private_key = PrivateKey::new();
+        let public_key = private_key.public_key();
+        let body = Body::new("hello!".as_bytes());
+        let (hash_0, header_0, header_bytes_0) = create_operation(&private_key, &body, 0, 0, None);
+        let (hash_1, header_1, header_bytes_1) =
+            create_operation(&private_key, &body, 1, 1, Some(hash_0));
+
+        store
+            .insert_operation(hash_0, &header_0, Some(&body), &header_bytes_0, &0)
+            .await
+            .unwrap();
+        store
+            .insert_operation(hash_1, &header_1, Some(&body), &header_bytes_1, &0)
+            .await
+            .unwrap();
+
+        // Inserting the same operation again doesn't emit an event.
+        store
+            .insert_operation(hash_1, &header_1, Some(&body), &header_bytes_1, &0)
+            .await
+            .unwrap();
+
+        store.delete_operations(&public_key, &0, 1).await.unwrap();
+        store.delete_operation(hash_1).await.unwrap();
+
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            StoreEvent::Inserted {
+                hash: hash_0,
+                public_key,
+                log_id: 0
+            }
+        );
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            StoreEvent::Inserted {
+                hash: hash_1,
+                public_key,
+                log_id: 0
+            }
+        );
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            StoreEvent::Deleted {
+                hash: hash_0,
+                public_key,
+                log_id: Some(0)
+            }
+        );
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            StoreEvent::Deleted {
+                hash: hash_1,
+                public_key,
+                log_id: None
+            }
+        );
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+}
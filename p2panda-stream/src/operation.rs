@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 //! Methods to handle p2panda operations.
+use p2panda_core::tombstone::validate_tombstone;
 use p2panda_core::{
     Body, Extensions, Header, Operation, OperationError, validate_backlink, validate_operation,
 };
@@ -107,6 +108,30 @@ where
     Ok(IngestResult::Complete(operation))
 }
 
+/// Deletes the payloads of all operations before a tombstone in its log.
+///
+/// Call this after ingesting an operation with a tombstone flag set. The headers of the
+/// tombstoned operations stay in the store, sync protocols will transfer them without their
+/// payloads from now on.
+///
+/// Returns `true` when any payloads were deleted.
+pub async fn apply_tombstone<S, L, E>(
+    store: &mut S,
+    header: &Header<E>,
+    log_id: &L,
+) -> Result<bool, IngestError>
+where
+    S: LogStore<L, E>,
+    E: Extensions,
+{
+    validate_tombstone(header, true).map_err(IngestError::InvalidOperation)?;
+
+    store
+        .delete_payloads(&header.public_key, log_id, 0, header.seq_num)
+        .await
+        .map_err(|err| IngestError::StoreError(err.to_string()))
+}
+
 /// Operations can be ingested directly or need to be re-tried if they arrived out-of-order.
 #[derive(Debug)]
 pub enum IngestResult<E> {
@@ -145,10 +170,10 @@ pub enum IngestError {
 
 #[cfg(test)]
 mod tests {
-    use p2panda_core::{Hash, Header, PrivateKey};
-    use p2panda_store::MemoryStore;
+    use p2panda_core::{Body, Hash, Header, OperationError, PrivateKey};
+    use p2panda_store::{LogStore, MemoryStore};
 
-    use crate::operation::{IngestResult, ingest_operation};
+    use crate::operation::{IngestError, IngestResult, apply_tombstone, ingest_operation};
     use crate::test_utils::Extensions;
 
     #[tokio::test]
@@ -196,4 +221,67 @@ mod tests {
         let result = ingest_operation(&mut store, header, None, header_bytes, &log_id, false).await;
         assert!(matches!(result, Ok(IngestResult::Retry(_, None, _, 11))));
     }
+
+    #[tokio::test]
+    async fn tombstone() {
+        let mut store = MemoryStore::<usize, Extensions>::new();
+        let private_key = PrivateKey::new();
+        let log_id = 1;
+        let body = Body::new(b"Hello, Sloth!");
+
+        // 1. Create a log with two operations and a tombstone.
+        let mut backlink = None;
+        let mut headers = Vec::new();
+        for seq_num in 0..3 {
+            let payload = (seq_num < 2).then_some(&body);
+            let mut header = Header {
+                public_key: private_key.public_key(),
+                version: 1,
+                signature: None,
+                payload_size: payload.map_or(0, |body| body.size()),
+                payload_hash: payload.map(|body| body.hash()),
+                timestamp: 0,
+                seq_num,
+                backlink,
+                previous: vec![],
+                extensions: None,
+            };
+            header.sign(&private_key);
+            backlink = Some(header.hash());
+
+            let header_bytes = header.to_bytes();
+            let result = ingest_operation(
+                &mut store,
+                header.clone(),
+                payload.cloned(),
+                header_bytes,
+                &log_id,
+                false,
+            )
+            .await;
+            assert!(matches!(result, Ok(IngestResult::Complete(_))));
+            headers.push(header);
+        }
+
+        // 2. Operations with a payload can't be tombstones.
+        let result = apply_tombstone(&mut store, &headers[1], &log_id).await;
+        assert!(matches!(
+            result,
+            Err(IngestError::InvalidOperation(
+                OperationError::TombstoneWithPayload
+            ))
+        ));
+
+        // 3. Payloads before the tombstone are removed, the headers stay.
+        let result = apply_tombstone(&mut store, &headers[2], &log_id).await;
+        assert!(matches!(result, Ok(true)));
+
+        let log = store
+            .get_raw_log(&private_key.public_key(), &log_id, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(log.len(), 3);
+        assert!(log.iter().all(|(_, body)| body.is_none()));
+    }
 }
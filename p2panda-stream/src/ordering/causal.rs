@@ -0,0 +1,404 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Causal delivery of operations.
+//!
+//! Operations received from the network can arrive in any order: gossip and sync deliver them
+//! concurrently and peers forward them as they see them. `CausalOrderer` buffers operations until
+//! all their dependencies, the `backlink` and all `previous` operations, were delivered and only
+//! then hands them out. Like this every operation is delivered after all operations it depends on.
+//!
+//! The buffer is bounded. If dependencies are missing for longer than the configured timeout they
+//! are reported, so the application can request them from other peers.
+//!
+//! Only the most recently delivered operations are remembered, see
+//! `CausalOrderer::set_max_delivered`, so long-running streams don't grow in memory with every
+//! operation they have ever seen.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use p2panda_core::{Hash, Operation};
+use thiserror::Error;
+
+/// Number of delivered operations remembered by default.
+pub const DEFAULT_MAX_DELIVERED: usize = 100_000;
+
+/// Error types which may be returned from `CausalOrderer` methods.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum CausalOrderError {
+    /// Too many operations are waiting for their dependencies.
+    #[error("causal order buffer is full ({0} operations waiting for dependencies)")]
+    BufferFull(usize),
+}
+
+/// Dependency which has not been delivered yet.
+#[derive(Debug)]
+struct MissingDependency {
+    /// Time since when the dependency is missing or when it was last reported.
+    since: Instant,
+
+    /// Operations waiting for this dependency.
+    dependents: Vec<Hash>,
+}
+
+/// Buffers operations until their dependencies have been delivered.
+///
+/// Dependencies of an operation are its `backlink` and all operations referenced in `previous`.
+/// Operations without dependencies or with all dependencies delivered are ready immediately.
+///
+/// Operations which were delivered in an earlier session, for example because they are already
+/// persisted, can be marked as delivered with `mark_delivered`.
+///
+/// At most `DEFAULT_MAX_DELIVERED` delivered operations are remembered, older ones are forgotten
+/// first. Operations depending on a forgotten operation wait for it like for any other missing
+/// dependency and are reported by `missing_dependencies`, the application can then mark it as
+/// delivered again if it is persisted already.
+#[derive(Debug)]
+pub struct CausalOrderer<E> {
+    max_buffer_size: usize,
+    dependency_timeout: Duration,
+    max_delivered: usize,
+    delivered: HashSet<Hash>,
+    delivered_order: VecDeque<Hash>,
+    pending: HashMap<Hash, Operation<E>>,
+    missing: HashMap<Hash, MissingDependency>,
+    ready: VecDeque<Operation<E>>,
+}
+
+impl<E> CausalOrderer<E> {
+    /// Returns a new orderer buffering at most `max_buffer_size` operations and reporting
+    /// dependencies which are missing for longer than `dependency_timeout`.
+    pub fn new(max_buffer_size: usize, dependency_timeout: Duration) -> Self {
+        Self {
+            max_buffer_size,
+            dependency_timeout,
+            max_delivered: DEFAULT_MAX_DELIVERED,
+            delivered: HashSet::new(),
+            delivered_order: VecDeque::new(),
+            pending: HashMap::new(),
+            missing: HashMap::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Set how many delivered operations are remembered, older ones are forgotten first.
+    pub fn set_max_delivered(&mut self, max_delivered: usize) {
+        self.max_delivered = max_delivered;
+        self.forget_oldest();
+    }
+
+    /// Mark an operation as delivered without handing it out again.
+    ///
+    /// Buffered operations which were only waiting for this operation become ready.
+    pub fn mark_delivered(&mut self, hash: Hash) {
+        if self.remember(hash) {
+            self.release(hash);
+        }
+    }
+
+    /// Returns `true` if the operation was delivered already.
+    pub fn is_delivered(&self, hash: &Hash) -> bool {
+        self.delivered.contains(hash)
+    }
+
+    /// Process an operation which may be ready or needs to wait for its dependencies.
+    ///
+    /// Operations which were delivered or are buffered already are ignored.
+    pub fn push(&mut self, operation: Operation<E>) -> Result<(), CausalOrderError> {
+        let hash = operation.hash;
+        if self.delivered.contains(&hash) || self.pending.contains_key(&hash) {
+            return Ok(());
+        }
+
+        let missing: Vec<Hash> = dependencies(&operation)
+            .filter(|dependency| !self.delivered.contains(dependency))
+            .collect();
+        if missing.is_empty() {
+            self.deliver(operation);
+            return Ok(());
+        }
+
+        if self.pending.len() >= self.max_buffer_size {
+            return Err(CausalOrderError::BufferFull(self.pending.len()));
+        }
+
+        let now = Instant::now();
+        for dependency in missing {
+            self.missing
+                .entry(dependency)
+                .or_insert_with(|| MissingDependency {
+                    since: now,
+                    dependents: Vec::new(),
+                })
+                .dependents
+                .push(hash);
+        }
+        self.pending.insert(hash, operation);
+
+        Ok(())
+    }
+
+    /// Take the next operation which has all its dependencies delivered.
+    pub fn next_ready(&mut self) -> Option<Operation<E>> {
+        self.ready.pop_front()
+    }
+
+    /// Returns the dependencies which are missing for longer than the configured timeout.
+    ///
+    /// Dependencies which are buffered themselves are not missing, they are waiting for their own
+    /// dependencies. Returned dependencies are reported again if they are still missing after
+    /// another timeout.
+    pub fn missing_dependencies(&mut self, now: Instant) -> Vec<Hash> {
+        let mut timed_out = Vec::new();
+        for (hash, missing) in self.missing.iter_mut() {
+            if self.pending.contains_key(hash) {
+                continue;
+            }
+            if now.saturating_duration_since(missing.since) >= self.dependency_timeout {
+                missing.since = now;
+                timed_out.push(*hash);
+            }
+        }
+        timed_out.sort();
+        timed_out
+    }
+
+    /// Number of operations waiting for their dependencies.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Number of delivered operations which are remembered.
+    pub fn delivered_len(&self) -> usize {
+        self.delivered.len()
+    }
+
+    fn deliver(&mut self, operation: Operation<E>) {
+        let hash = operation.hash;
+        self.remember(hash);
+        self.ready.push_back(operation);
+        self.release(hash);
+    }
+
+    /// Deliver all buffered operations which were waiting for the given operation and have no
+    /// other missing dependencies.
+    fn release(&mut self, hash: Hash) {
+        let mut released = vec![hash];
+        while let Some(hash) = released.pop() {
+            let Some(missing) = self.missing.remove(&hash) else {
+                continue;
+            };
+
+            for dependent in missing.dependents {
+                let is_ready = self.pending.get(&dependent).is_some_and(|operation| {
+                    dependencies(operation).all(|dependency| self.delivered.contains(&dependency))
+                });
+                if !is_ready {
+                    continue;
+                }
+
+                let operation = self.pending.remove(&dependent).expect("pending operation");
+                self.remember(dependent);
+                self.ready.push_back(operation);
+                released.push(dependent);
+            }
+        }
+    }
+
+    /// Remember a delivered operation, returns `false` if it was remembered already.
+    fn remember(&mut self, hash: Hash) -> bool {
+        if !self.delivered.insert(hash) {
+            return false;
+        }
+        self.delivered_order.push_back(hash);
+        self.forget_oldest();
+        true
+    }
+
+    fn forget_oldest(&mut self) {
+        while self.delivered_order.len() > self.max_delivered {
+            if let Some(hash) = self.delivered_order.pop_front() {
+                self.delivered.remove(&hash);
+            }
+        }
+    }
+}
+
+fn dependencies<E>(operation: &Operation<E>) -> impl Iterator<Item = Hash> + '_ {
+    operation
+        .header
+        .backlink
+        .iter()
+        .chain(operation.header.previous.iter())
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use p2panda_core::{Body, Hash, Header, Operation, PrivateKey};
+
+    use super::{CausalOrderError, CausalOrderer};
+
+    fn operation(
+        private_key: &PrivateKey,
+        seq_num: u64,
+        backlink: Option<Hash>,
+        previous: Vec<Hash>,
+    ) -> Operation<()> {
+        let body = Body::new(&seq_num.to_be_bytes());
+        let mut header = Header {
+            public_key: private_key.public_key(),
+            version: 1,
+            signature: None,
+            payload_size: body.size(),
+            payload_hash: Some(body.hash()),
+            timestamp: seq_num,
+            seq_num,
+            backlink,
+            previous,
+            extensions: None,
+        };
+        header.sign(private_key);
+        Operation {
+            hash: header.hash(),
+            header,
+            body: Some(body),
+        }
+    }
+
+    fn log(private_key: &PrivateKey, len: u64) -> Vec<Operation<()>> {
+        let mut operations: Vec<Operation<()>> = Vec::new();
+        for seq_num in 0..len {
+            let backlink = operations.last().map(|operation| operation.hash);
+            operations.push(operation(private_key, seq_num, backlink, vec![]));
+        }
+        operations
+    }
+
+    fn drain(orderer: &mut CausalOrderer<()>) -> Vec<Hash> {
+        std::iter::from_fn(|| orderer.next_ready())
+            .map(|operation| operation.hash)
+            .collect()
+    }
+
+    #[test]
+    fn deliver_after_backlinks() {
+        let operations = log(&PrivateKey::new(), 5);
+        let mut orderer = CausalOrderer::new(16, Duration::from_secs(1));
+
+        for operation in operations.iter().rev() {
+            orderer.push(operation.clone()).unwrap();
+        }
+        assert_eq!(orderer.pending_len(), 0);
+        assert_eq!(
+            drain(&mut orderer),
+            operations
+                .iter()
+                .map(|operation| operation.hash)
+                .collect::<Vec<Hash>>()
+        );
+    }
+
+    #[test]
+    fn deliver_after_previous() {
+        let private_key_a = PrivateKey::new();
+        let private_key_b = PrivateKey::new();
+        let a = operation(&private_key_a, 0, None, vec![]);
+        let b = operation(&private_key_b, 0, None, vec![]);
+        let c = operation(&private_key_a, 1, Some(a.hash), vec![a.hash, b.hash]);
+
+        let mut orderer = CausalOrderer::new(16, Duration::from_secs(1));
+        orderer.push(c.clone()).unwrap();
+        orderer.push(a.clone()).unwrap();
+        assert_eq!(drain(&mut orderer), vec![a.hash]);
+
+        // C is still waiting for B.
+        assert_eq!(orderer.pending_len(), 1);
+        orderer.push(b.clone()).unwrap();
+        assert_eq!(drain(&mut orderer), vec![b.hash, c.hash]);
+
+        // Duplicates are ignored.
+        orderer.push(c).unwrap();
+        assert!(orderer.next_ready().is_none());
+    }
+
+    #[test]
+    fn mark_delivered() {
+        let operations = log(&PrivateKey::new(), 3);
+        let mut orderer = CausalOrderer::new(16, Duration::from_secs(1));
+
+        orderer.push(operations[1].clone()).unwrap();
+        orderer.mark_delivered(operations[0].hash);
+        assert!(orderer.is_delivered(&operations[0].hash));
+        assert_eq!(drain(&mut orderer), vec![operations[1].hash]);
+    }
+
+    #[test]
+    fn bounded_buffer() {
+        let operations = log(&PrivateKey::new(), 4);
+        let mut orderer = CausalOrderer::new(2, Duration::from_secs(1));
+
+        orderer.push(operations[3].clone()).unwrap();
+        orderer.push(operations[2].clone()).unwrap();
+        assert_eq!(
+            orderer.push(operations[1].clone()),
+            Err(CausalOrderError::BufferFull(2))
+        );
+
+        // Ready operations are never rejected.
+        orderer.push(operations[0].clone()).unwrap();
+        assert_eq!(drain(&mut orderer), vec![operations[0].hash]);
+    }
+
+    #[test]
+    fn bounded_delivered() {
+        let operations = log(&PrivateKey::new(), 4);
+        let mut orderer = CausalOrderer::new(16, Duration::from_secs(1));
+        orderer.set_max_delivered(2);
+
+        for operation in &operations[..3] {
+            orderer.push(operation.clone()).unwrap();
+        }
+        assert_eq!(drain(&mut orderer).len(), 3);
+        assert_eq!(orderer.delivered_len(), 2);
+        assert!(!orderer.is_delivered(&operations[0].hash));
+        assert!(orderer.is_delivered(&operations[2].hash));
+
+        // Operations depending on forgotten ones wait until they are marked as delivered again.
+        let late = operation(&PrivateKey::new(), 0, None, vec![operations[0].hash]);
+        orderer.push(late.clone()).unwrap();
+        assert_eq!(orderer.pending_len(), 1);
+        orderer.mark_delivered(operations[0].hash);
+        assert_eq!(drain(&mut orderer), vec![late.hash]);
+    }
+
+    #[test]
+    fn report_missing_dependencies() {
+        let operations = log(&PrivateKey::new(), 3);
+        let timeout = Duration::from_secs(5);
+        let mut orderer = CausalOrderer::new(16, timeout);
+
+        orderer.push(operations[2].clone()).unwrap();
+        let start = Instant::now();
+        assert!(orderer.missing_dependencies(start).is_empty());
+
+        // Missing dependencies are reported after the timeout and again after another one.
+        let later = start + timeout;
+        assert_eq!(
+            orderer.missing_dependencies(later),
+            vec![operations[1].hash]
+        );
+        assert!(orderer.missing_dependencies(later).is_empty());
+        assert_eq!(
+            orderer.missing_dependencies(later + timeout),
+            vec![operations[1].hash]
+        );
+
+        orderer.push(operations[1].clone()).unwrap();
+        assert_eq!(
+            orderer.missing_dependencies(later + timeout * 2),
+            vec![operations[0].hash]
+        );
+    }
+}
@@ -78,7 +78,7 @@ pub enum PartialOrderError {
 /// ```
 ///
 /// Note that no checks are made for cycles occurring in the graph, this should be validated on
-/// another layer.
+/// another layer, for example with `OperationGraph` in `p2panda-core`.
 #[derive(Debug)]
 pub struct PartialOrder<K, S> {
     /// Store for managing "ready" and "pending" items.
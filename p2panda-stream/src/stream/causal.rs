@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use futures_util::stream::{Fuse, FusedStream};
+use futures_util::task::{Context, Poll};
+use futures_util::{Sink, Stream, StreamExt, ready};
+use p2panda_core::{Hash, Operation};
+use pin_project::pin_project;
+
+use crate::causal::{CausalOrderError, CausalOrderer};
+use crate::macros::{delegate_access_inner, delegate_sink};
+
+/// An extension trait for `Stream`s that provides a convenient
+/// [`causal_order`](CausalOrderExt::causal_order) method.
+pub trait CausalOrderExt<E>: Stream<Item = Operation<E>> {
+    /// Delivers operations only after all their dependencies, the `backlink` and all `previous`
+    /// operations, have been delivered.
+    ///
+    /// Messages received from the network (`FromNetwork` in `p2panda-net`) can arrive in any
+    /// order, decode them into operations first, for example with
+    /// [`decode`](crate::DecodeExt::decode).
+    ///
+    /// At most `max_buffer_size` operations are held back while waiting for their dependencies.
+    /// Dependencies which are missing for longer than `dependency_timeout` are reported with a
+    /// `CausalOrderEvent::MissingDependencies` event, so they can be requested from other peers.
+    /// Timeouts are checked whenever a new operation arrives.
+    fn causal_order(
+        self,
+        max_buffer_size: usize,
+        dependency_timeout: Duration,
+    ) -> CausalOrder<Self, E>
+    where
+        Self: Sized,
+    {
+        CausalOrder::new(
+            self,
+            CausalOrderer::new(max_buffer_size, dependency_timeout),
+        )
+    }
+}
+
+impl<T: ?Sized, E> CausalOrderExt<E> for T where T: Stream<Item = Operation<E>> {}
+
+/// Events of the [`causal_order`](CausalOrderExt::causal_order) method.
+#[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum CausalOrderEvent<E> {
+    /// Operation with all its dependencies delivered.
+    Operation(Operation<E>),
+
+    /// Dependencies which are missing for longer than the configured timeout.
+    MissingDependencies(Vec<Hash>),
+}
+
+/// Stream for the [`causal_order`](CausalOrderExt::causal_order) method.
+#[derive(Debug)]
+#[pin_project]
+#[must_use = "streams do nothing unless polled"]
+pub struct CausalOrder<St, E>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    #[pin]
+    stream: Fuse<St>,
+    orderer: CausalOrderer<E>,
+    missing: Option<Vec<Hash>>,
+}
+
+impl<St, E> CausalOrder<St, E>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    pub(super) fn new(stream: St, orderer: CausalOrderer<E>) -> CausalOrder<St, E> {
+        CausalOrder {
+            stream: stream.fuse(),
+            orderer,
+            missing: None,
+        }
+    }
+
+    /// Acquires a mutable reference to the orderer, for example to mark already persisted
+    /// operations as delivered.
+    pub fn orderer_mut(&mut self) -> &mut CausalOrderer<E> {
+        &mut self.orderer
+    }
+
+    delegate_access_inner!(stream, St, (.));
+}
+
+impl<St, E> Stream for CausalOrder<St, E>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    type Item = Result<CausalOrderEvent<E>, CausalOrderError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            // 1. Hand out all operations which are ready, then report missing dependencies.
+            if let Some(operation) = this.orderer.next_ready() {
+                return Poll::Ready(Some(Ok(CausalOrderEvent::Operation(operation))));
+            }
+
+            if let Some(missing) = this.missing.take() {
+                return Poll::Ready(Some(Ok(CausalOrderEvent::MissingDependencies(missing))));
+            }
+
+            // 2. Pull in the next operation from the external stream. Buffered operations which
+            //    are still waiting when the stream ended will never be delivered.
+            let Some(operation) = ready!(this.stream.as_mut().poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+
+            if let Err(err) = this.orderer.push(operation) {
+                return Poll::Ready(Some(Err(err)));
+            }
+
+            let missing = this.orderer.missing_dependencies(Instant::now());
+            if !missing.is_empty() {
+                this.missing.replace(missing);
+            }
+        }
+    }
+}
+
+impl<St: FusedStream, E> FusedStream for CausalOrder<St, E>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+impl<St, E> Sink<Operation<E>> for CausalOrder<St, E>
+where
+    St: Stream<Item = Operation<E>> + Sink<Operation<E>>,
+{
+    type Error = St::Error;
+
+    delegate_sink!(stream, Operation<E>);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::stream::iter;
+    use futures_util::{StreamExt, TryStreamExt};
+    use p2panda_core::{Hash, Operation};
+
+    use crate::stream::decode::DecodeExt;
+    use crate::test_utils::{Extensions, mock_stream};
+
+    use super::{CausalOrderEvent, CausalOrderExt};
+
+    #[tokio::test]
+    async fn causal_order() {
+        let operations: Vec<Operation<Extensions>> = mock_stream()
+            .take(10)
+            .decode()
+            .map(|item| {
+                let (header, body, _) = item.expect("valid operation");
+                Operation {
+                    hash: header.hash(),
+                    header,
+                    body,
+                }
+            })
+            .collect()
+            .await;
+        let expected: Vec<Hash> = operations.iter().map(|operation| operation.hash).collect();
+
+        let mut shuffled = operations;
+        shuffled.reverse();
+        shuffled.swap(2, 7);
+
+        let events: Vec<CausalOrderEvent<Extensions>> = iter(shuffled)
+            .causal_order(16, Duration::from_secs(60))
+            .try_collect()
+            .await
+            .expect("not fail");
+        let delivered: Vec<Hash> = events
+            .into_iter()
+            .map(|event| match event {
+                CausalOrderEvent::Operation(operation) => operation.hash,
+                CausalOrderEvent::MissingDependencies(_) => panic!("unexpected timeout"),
+            })
+            .collect();
+        assert_eq!(delivered, expected);
+    }
+}
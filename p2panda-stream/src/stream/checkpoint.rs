@@ -0,0 +1,288 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::collections::HashMap;
+use std::hash::Hash as StdHash;
+use std::marker::PhantomData;
+use std::pin::Pin;
+
+use futures_util::stream::{Fuse, FusedStream};
+use futures_util::task::{Context, Poll};
+use futures_util::{Sink, Stream, StreamExt, ready};
+use p2panda_core::cbor::{DecodeError, EncodeError, decode_cbor, encode_cbor};
+use p2panda_core::{Extension, Extensions, Hash, Operation, PublicKey};
+use pin_project::pin_project;
+use serde::{Deserialize, Serialize};
+
+use crate::macros::{delegate_access_inner, delegate_sink};
+
+/// Last delivered operation of a log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogPosition {
+    pub seq_num: u64,
+    pub hash: Hash,
+}
+
+/// Resume token holding the last delivered operation of every log.
+///
+/// Applications persist the latest checkpoint together with the results of processing the
+/// operations, for example in the same database transaction, and pass it in again on startup to
+/// not process the same operations twice.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "L: Serialize + StdHash + Eq",
+    deserialize = "L: Deserialize<'de> + StdHash + Eq"
+))]
+pub struct Checkpoint<L> {
+    positions: HashMap<(PublicKey, L), LogPosition>,
+}
+
+impl<L> Default for Checkpoint<L> {
+    fn default() -> Self {
+        Self {
+            positions: HashMap::new(),
+        }
+    }
+}
+
+impl<L> Checkpoint<L>
+where
+    L: Clone + StdHash + Eq,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the last delivered operation of a log.
+    pub fn position(&self, public_key: &PublicKey, log_id: &L) -> Option<&LogPosition> {
+        self.positions.get(&(*public_key, log_id.clone()))
+    }
+
+    /// Returns `true` if the operation was delivered before this checkpoint was taken.
+    pub fn contains<E>(&self, operation: &Operation<E>) -> bool
+    where
+        E: Extension<L>,
+    {
+        let Some(log_id) = operation.header.extension() else {
+            return false;
+        };
+        self.position(&operation.header.public_key, &log_id)
+            .is_some_and(|position| operation.header.seq_num <= position.seq_num)
+    }
+
+    /// Remember the operation as the last delivered one of its log.
+    pub fn advance<E>(&mut self, operation: &Operation<E>)
+    where
+        E: Extension<L>,
+    {
+        let Some(log_id) = operation.header.extension() else {
+            return;
+        };
+        let position = LogPosition {
+            seq_num: operation.header.seq_num,
+            hash: operation.hash,
+        };
+        self.positions
+            .entry((operation.header.public_key, log_id))
+            .and_modify(|current| {
+                if position.seq_num > current.seq_num {
+                    *current = position;
+                }
+            })
+            .or_insert(position);
+    }
+}
+
+impl<L> Checkpoint<L>
+where
+    L: Serialize + for<'de> Deserialize<'de> + StdHash + Eq,
+{
+    /// Encode the checkpoint to bytes in CBOR format.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodeError> {
+        encode_cbor(self)
+    }
+
+    /// Decode a checkpoint from bytes in CBOR format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        decode_cbor(bytes)
+    }
+}
+
+/// An extension trait for `Stream`s that provides a convenient
+/// [`checkpoint`](CheckpointExt::checkpoint) method.
+pub trait CheckpointExt<L, E>: Stream<Item = Operation<E>> {
+    /// Emits a checkpoint every `interval` operations and skips operations which were delivered
+    /// before the given checkpoint.
+    ///
+    /// Checkpoints hold the last delivered operation of every log, see `Checkpoint`. A last
+    /// checkpoint is emitted when the stream ends, so no progress gets lost.
+    fn checkpoint(self, checkpoint: Checkpoint<L>, interval: usize) -> Checkpoints<Self, L, E>
+    where
+        L: Clone + StdHash + Eq,
+        E: Extension<L> + Extensions,
+        Self: Sized,
+    {
+        Checkpoints::new(self, checkpoint, interval)
+    }
+}
+
+impl<T: ?Sized, L, E> CheckpointExt<L, E> for T where T: Stream<Item = Operation<E>> {}
+
+/// Events of the [`checkpoint`](CheckpointExt::checkpoint) method.
+#[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum CheckpointEvent<L, E> {
+    /// Operation which was not delivered before.
+    Operation(Operation<E>),
+
+    /// Checkpoint including all operations handed out so far.
+    Checkpoint(Checkpoint<L>),
+}
+
+/// Stream for the [`checkpoint`](CheckpointExt::checkpoint) method.
+#[derive(Debug)]
+#[pin_project]
+#[must_use = "streams do nothing unless polled"]
+pub struct Checkpoints<St, L, E>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    #[pin]
+    stream: Fuse<St>,
+    checkpoint: Checkpoint<L>,
+    interval: usize,
+    since_checkpoint: usize,
+    _marker: PhantomData<E>,
+}
+
+impl<St, L, E> Checkpoints<St, L, E>
+where
+    St: Stream<Item = Operation<E>>,
+    L: Clone + StdHash + Eq,
+    E: Extension<L> + Extensions,
+{
+    pub(super) fn new(stream: St, checkpoint: Checkpoint<L>, interval: usize) -> Self {
+        Checkpoints {
+            stream: stream.fuse(),
+            checkpoint,
+            interval: interval.max(1),
+            since_checkpoint: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    delegate_access_inner!(stream, St, (.));
+}
+
+impl<St, L, E> Stream for Checkpoints<St, L, E>
+where
+    St: Stream<Item = Operation<E>>,
+    L: Clone + StdHash + Eq,
+    E: Extension<L> + Extensions,
+{
+    type Item = CheckpointEvent<L, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            // Checkpoints are taken after the operations they include were handed out.
+            if *this.since_checkpoint >= *this.interval {
+                *this.since_checkpoint = 0;
+                return Poll::Ready(Some(CheckpointEvent::Checkpoint(this.checkpoint.clone())));
+            }
+
+            let Some(operation) = ready!(this.stream.as_mut().poll_next(cx)) else {
+                if *this.since_checkpoint > 0 {
+                    *this.since_checkpoint = 0;
+                    return Poll::Ready(Some(CheckpointEvent::Checkpoint(this.checkpoint.clone())));
+                }
+                return Poll::Ready(None);
+            };
+
+            if this.checkpoint.contains(&operation) {
+                continue;
+            }
+
+            this.checkpoint.advance(&operation);
+            *this.since_checkpoint += 1;
+            return Poll::Ready(Some(CheckpointEvent::Operation(operation)));
+        }
+    }
+}
+
+impl<St: FusedStream, L, E> FusedStream for Checkpoints<St, L, E>
+where
+    St: Stream<Item = Operation<E>>,
+    L: Clone + StdHash + Eq,
+    E: Extension<L> + Extensions,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated() && self.since_checkpoint == 0
+    }
+}
+
+impl<St, L, E> Sink<Operation<E>> for Checkpoints<St, L, E>
+where
+    St: Stream<Item = Operation<E>> + Sink<Operation<E>>,
+{
+    type Error = St::Error;
+
+    delegate_sink!(stream, Operation<E>);
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream::iter;
+    use futures_util::{StreamExt, TryStreamExt};
+    use p2panda_core::Operation;
+
+    use crate::stream::validate::ValidateExt;
+    use crate::test_utils::{Extensions, StreamName, mock_stream};
+
+    use super::{Checkpoint, CheckpointEvent, CheckpointExt};
+
+    #[tokio::test]
+    async fn resume_from_checkpoint() {
+        let operations: Vec<Operation<Extensions>> = mock_stream()
+            .take(10)
+            .validate()
+            .try_collect()
+            .await
+            .expect("valid operations");
+
+        // First session processes six operations and emits checkpoints after four and at the end.
+        let events: Vec<CheckpointEvent<StreamName, Extensions>> = iter(operations[..6].to_vec())
+            .checkpoint(Checkpoint::new(), 4)
+            .collect()
+            .await;
+        assert_eq!(events.len(), 8);
+        assert!(matches!(events[4], CheckpointEvent::Checkpoint(_)));
+        let CheckpointEvent::Checkpoint(checkpoint) = events.last().unwrap() else {
+            panic!("expected checkpoint at the end");
+        };
+
+        // The token survives a restart.
+        let checkpoint = Checkpoint::from_bytes(&checkpoint.to_bytes().unwrap()).unwrap();
+        let position = checkpoint
+            .position(
+                &operations[5].header.public_key,
+                &operations[5].header.extension().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(position.hash, operations[5].hash);
+
+        // Second session receives all operations again but only delivers the new ones.
+        let events: Vec<CheckpointEvent<StreamName, Extensions>> = iter(operations.clone())
+            .checkpoint(checkpoint, 100)
+            .collect()
+            .await;
+        let seq_nums: Vec<u64> = events
+            .iter()
+            .filter_map(|event| match event {
+                CheckpointEvent::Operation(operation) => Some(operation.header.seq_num),
+                CheckpointEvent::Checkpoint(_) => None,
+            })
+            .collect();
+        assert_eq!(seq_nums, vec![6, 7, 8, 9]);
+    }
+}
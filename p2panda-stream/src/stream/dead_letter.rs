@@ -0,0 +1,276 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::marker::PhantomData;
+use std::pin::Pin;
+
+use futures_util::stream::{Fuse, FusedStream};
+use futures_util::task::{Context, Poll};
+use futures_util::{Sink, Stream, StreamExt, ready};
+use p2panda_core::{Extensions, Operation, OperationLimits, RawOperation};
+use pin_project::pin_project;
+
+use crate::macros::delegate_access_inner;
+use crate::stream::validate::{ValidationError, validate_raw_operation};
+
+/// Operation which failed validation, together with the reason.
+///
+/// The header and body bytes are kept as they were received, so the operation can be inspected
+/// or processed again, for example after a bug was fixed.
+#[derive(Debug)]
+pub struct DeadLetter<R> {
+    pub operation: RawOperation,
+    pub reason: R,
+}
+
+/// An extension trait for `Stream`s that provides a convenient
+/// [`validate_with_dead_letters`](DeadLetterExt::validate_with_dead_letters) method.
+pub trait DeadLetterExt<E>: Stream<Item = RawOperation> {
+    /// Decode byte streams into p2panda operations and validate them, like
+    /// [`validate`](crate::ValidateExt::validate), but send invalid operations into a dead-letter
+    /// sink instead of returning them as errors.
+    ///
+    /// The stream waits until the sink is ready to accept a dead letter. When the sink fails it
+    /// is not used anymore, the dead letter which could not be sent and all following invalid
+    /// operations are returned as errors again.
+    fn validate_with_dead_letters<Si>(self, sink: Si) -> ValidateWithDeadLetters<Self, Si, E>
+    where
+        Si: Sink<DeadLetter<ValidationError>>,
+        E: Extensions,
+        Self: Sized,
+    {
+        ValidateWithDeadLetters::new(self, sink)
+    }
+}
+
+impl<T: ?Sized, E> DeadLetterExt<E> for T where T: Stream<Item = RawOperation> {}
+
+/// Stream for the [`validate_with_dead_letters`](DeadLetterExt::validate_with_dead_letters)
+/// method.
+#[derive(Debug)]
+#[pin_project]
+#[must_use = "streams do nothing unless polled"]
+pub struct ValidateWithDeadLetters<St, Si, E>
+where
+    St: Stream<Item = RawOperation>,
+{
+    #[pin]
+    stream: Fuse<St>,
+    #[pin]
+    sink: Option<Si>,
+    pending: Option<DeadLetter<ValidationError>>,
+    flushing: bool,
+    _marker: PhantomData<E>,
+}
+
+impl<St, Si, E> ValidateWithDeadLetters<St, Si, E>
+where
+    St: Stream<Item = RawOperation>,
+    Si: Sink<DeadLetter<ValidationError>>,
+    E: Extensions,
+{
+    pub(super) fn new(stream: St, sink: Si) -> ValidateWithDeadLetters<St, Si, E> {
+        ValidateWithDeadLetters {
+            stream: stream.fuse(),
+            sink: Some(sink),
+            pending: None,
+            flushing: false,
+            _marker: PhantomData,
+        }
+    }
+
+    delegate_access_inner!(stream, St, (.));
+}
+
+impl<St, Si, E> Stream for ValidateWithDeadLetters<St, Si, E>
+where
+    St: Stream<Item = RawOperation>,
+    Si: Sink<DeadLetter<ValidationError>>,
+    E: Extensions,
+{
+    type Item = Result<Operation<E>, ValidationError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            // 1. Hand over the last invalid operation to the sink and flush it.
+            if let Some(dead_letter) = this.pending.take() {
+                let Some(mut sink) = this.sink.as_mut().as_pin_mut() else {
+                    return Poll::Ready(Some(Err(dead_letter.reason)));
+                };
+
+                match sink.as_mut().poll_ready(cx) {
+                    Poll::Pending => {
+                        this.pending.replace(dead_letter);
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(Ok(())) => {
+                        // The dead letter is gone when sending it failed, keep the raw operation
+                        // to recover the reason by validating it again.
+                        let (header_bytes, body_bytes) = dead_letter.operation.clone();
+                        if sink.start_send(dead_letter).is_ok() {
+                            *this.flushing = true;
+                        } else {
+                            this.sink.set(None);
+                            if let Err(reason) = validate_raw_operation::<E>(
+                                &header_bytes,
+                                body_bytes,
+                                &OperationLimits::default(),
+                            ) {
+                                return Poll::Ready(Some(Err(reason)));
+                            }
+                        }
+                    }
+                    Poll::Ready(Err(_)) => {
+                        this.sink.set(None);
+                        return Poll::Ready(Some(Err(dead_letter.reason)));
+                    }
+                }
+            }
+
+            if *this.flushing {
+                if let Some(sink) = this.sink.as_mut().as_pin_mut() {
+                    let result = ready!(sink.poll_flush(cx));
+                    if result.is_err() {
+                        this.sink.set(None);
+                    }
+                }
+                *this.flushing = false;
+            }
+
+            // 2. Validate the next operation.
+            let Some((header_bytes, body_bytes)) = ready!(this.stream.as_mut().poll_next(cx))
+            else {
+                return Poll::Ready(None);
+            };
+
+            if this.sink.is_none() {
+                return Poll::Ready(Some(validate_raw_operation(
+                    &header_bytes,
+                    body_bytes,
+                    &OperationLimits::default(),
+                )));
+            }
+
+            match validate_raw_operation(
+                &header_bytes,
+                body_bytes.clone(),
+                &OperationLimits::default(),
+            ) {
+                Ok(operation) => return Poll::Ready(Some(Ok(operation))),
+                Err(reason) => {
+                    this.pending.replace(DeadLetter {
+                        operation: (header_bytes, body_bytes),
+                        reason,
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl<St: FusedStream, Si, E> FusedStream for ValidateWithDeadLetters<St, Si, E>
+where
+    St: Stream<Item = RawOperation>,
+    Si: Sink<DeadLetter<ValidationError>>,
+    E: Extensions,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated() && self.pending.is_none() && !self.flushing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures_channel::mpsc;
+    use futures_util::stream::iter;
+    use futures_util::{Sink, StreamExt};
+    use p2panda_core::{Operation, RawOperation};
+
+    use crate::stream::validate::ValidationError;
+    use crate::test_utils::{Extensions, mock_stream};
+
+    use super::{DeadLetter, DeadLetterExt};
+
+    #[tokio::test]
+    async fn dead_letters() {
+        let mut items: Vec<RawOperation> = mock_stream().take(4).collect().await;
+        items[1].1 = Some(b"Hello, Walrus!".to_vec());
+        items[3].0 = vec![1, 2, 3];
+
+        let (tx, rx) = mpsc::unbounded();
+        let result: Vec<Result<Operation<Extensions>, ValidationError>> = iter(items.clone())
+            .validate_with_dead_letters(tx)
+            .collect()
+            .await;
+        assert_eq!(result.len(), 2);
+        assert!(result.iter().all(|item| item.is_ok()));
+
+        let dead_letters: Vec<DeadLetter<ValidationError>> = rx.collect().await;
+        assert_eq!(dead_letters.len(), 2);
+        assert_eq!(dead_letters[0].operation, items[1]);
+        assert!(matches!(
+            dead_letters[0].reason,
+            ValidationError::InvalidOperation { .. }
+        ));
+        assert_eq!(dead_letters[1].operation, items[3]);
+        assert!(matches!(dead_letters[1].reason, ValidationError::Decode(_)));
+    }
+
+    #[tokio::test]
+    async fn closed_sink() {
+        let mut items: Vec<RawOperation> = mock_stream().take(2).collect().await;
+        items[1].0 = vec![1, 2, 3];
+
+        // Invalid operations are returned as errors when nobody listens for dead letters.
+        let (tx, rx) = mpsc::unbounded();
+        drop(rx);
+        let result: Vec<Result<Operation<Extensions>, ValidationError>> =
+            iter(items).validate_with_dead_letters(tx).collect().await;
+        assert!(result[0].is_ok());
+        assert!(matches!(result[1], Err(ValidationError::Decode(_))));
+    }
+
+    /// Sink which is always ready but fails accepting items.
+    struct FailingSink;
+
+    impl<T> Sink<T> for FailingSink {
+        type Error = ();
+
+        fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(self: Pin<&mut Self>, _item: T) -> Result<(), ()> {
+            Err(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn failing_sink() {
+        let mut items: Vec<RawOperation> = mock_stream().take(3).collect().await;
+        items[1].0 = vec![1, 2, 3];
+        items[2].0 = vec![4, 5, 6];
+
+        // The dead letter which could not be sent is returned as an error and not lost.
+        let result: Vec<Result<Operation<Extensions>, ValidationError>> = iter(items)
+            .validate_with_dead_letters(FailingSink)
+            .collect()
+            .await;
+        assert_eq!(result.len(), 3);
+        assert!(result[0].is_ok());
+        assert!(matches!(result[1], Err(ValidationError::Decode(_))));
+        assert!(matches!(result[2], Err(ValidationError::Decode(_))));
+    }
+}
@@ -0,0 +1,369 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::collections::HashMap;
+use std::hash::Hash as StdHash;
+use std::marker::PhantomData;
+use std::pin::Pin;
+
+use futures_util::stream::{Fuse, FusedStream};
+use futures_util::task::{Context, Poll};
+use futures_util::{Sink, Stream, StreamExt, ready};
+use p2panda_core::{Extension, Operation};
+use pin_project::pin_project;
+use tokio::sync::mpsc;
+
+use crate::macros::{delegate_access_inner, delegate_sink};
+
+/// An extension trait for `Stream`s that provides convenient methods to filter, key and split
+/// operations by the values of their header extensions.
+///
+/// Header extensions are decoded once together with the header, these methods only extract the
+/// typed values from them, see `Extension` in `p2panda-core`.
+pub trait ExtensionExt<E>: Stream<Item = Operation<E>> {
+    /// Keeps only operations with an extension value of type `T` matching the predicate.
+    ///
+    /// Operations without the extension are dropped.
+    fn filter_extension<T, F>(self, predicate: F) -> FilterExtension<Self, T, E, F>
+    where
+        E: Extension<T>,
+        F: FnMut(&T) -> bool,
+        Self: Sized,
+    {
+        FilterExtension::new(self, predicate)
+    }
+
+    /// Pairs every operation with its extension value of type `T`, for example a document id.
+    ///
+    /// Operations without the extension are dropped.
+    fn key_by_extension<T>(self) -> KeyByExtension<Self, T, E>
+    where
+        E: Extension<T>,
+        Self: Sized,
+    {
+        KeyByExtension::new(self)
+    }
+
+    /// Splits the stream into separate streams per extension value of type `T`.
+    ///
+    /// This can be used to split one topic subscription into streams per document. Streams for
+    /// single values are requested with [`SplitByExtension::subscribe`], all other operations are
+    /// passed on by the returned stream itself, which needs to be polled to drive all of them.
+    fn split_by_extension<T>(self) -> SplitByExtension<Self, T, E>
+    where
+        T: StdHash + Eq,
+        E: Extension<T>,
+        Self: Sized,
+    {
+        SplitByExtension::new(self)
+    }
+}
+
+impl<T: ?Sized, E> ExtensionExt<E> for T where T: Stream<Item = Operation<E>> {}
+
+/// Stream for the [`filter_extension`](ExtensionExt::filter_extension) method.
+#[derive(Debug)]
+#[pin_project]
+#[must_use = "streams do nothing unless polled"]
+pub struct FilterExtension<St, T, E, F>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    #[pin]
+    stream: Fuse<St>,
+    predicate: F,
+    _marker: PhantomData<T>,
+}
+
+impl<St, T, E, F> FilterExtension<St, T, E, F>
+where
+    St: Stream<Item = Operation<E>>,
+    E: Extension<T>,
+    F: FnMut(&T) -> bool,
+{
+    pub(super) fn new(stream: St, predicate: F) -> FilterExtension<St, T, E, F> {
+        FilterExtension {
+            stream: stream.fuse(),
+            predicate,
+            _marker: PhantomData,
+        }
+    }
+
+    delegate_access_inner!(stream, St, (.));
+}
+
+impl<St, T, E, F> Stream for FilterExtension<St, T, E, F>
+where
+    St: Stream<Item = Operation<E>>,
+    E: Extension<T>,
+    F: FnMut(&T) -> bool,
+{
+    type Item = Operation<E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            let Some(operation) = ready!(this.stream.as_mut().poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+
+            let matches = operation
+                .header
+                .extension::<T>()
+                .is_some_and(|value| (this.predicate)(&value));
+            if matches {
+                return Poll::Ready(Some(operation));
+            }
+        }
+    }
+}
+
+impl<St: FusedStream, T, E, F> FusedStream for FilterExtension<St, T, E, F>
+where
+    St: Stream<Item = Operation<E>>,
+    E: Extension<T>,
+    F: FnMut(&T) -> bool,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+impl<St, T, E, F> Sink<Operation<E>> for FilterExtension<St, T, E, F>
+where
+    St: Stream<Item = Operation<E>> + Sink<Operation<E>>,
+{
+    type Error = St::Error;
+
+    delegate_sink!(stream, Operation<E>);
+}
+
+/// Stream for the [`key_by_extension`](ExtensionExt::key_by_extension) method.
+#[derive(Debug)]
+#[pin_project]
+#[must_use = "streams do nothing unless polled"]
+pub struct KeyByExtension<St, T, E>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    #[pin]
+    stream: Fuse<St>,
+    _marker: PhantomData<T>,
+}
+
+impl<St, T, E> KeyByExtension<St, T, E>
+where
+    St: Stream<Item = Operation<E>>,
+    E: Extension<T>,
+{
+    pub(super) fn new(stream: St) -> KeyByExtension<St, T, E> {
+        KeyByExtension {
+            stream: stream.fuse(),
+            _marker: PhantomData,
+        }
+    }
+
+    delegate_access_inner!(stream, St, (.));
+}
+
+impl<St, T, E> Stream for KeyByExtension<St, T, E>
+where
+    St: Stream<Item = Operation<E>>,
+    E: Extension<T>,
+{
+    type Item = (T, Operation<E>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            let Some(operation) = ready!(this.stream.as_mut().poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+
+            if let Some(value) = operation.header.extension::<T>() {
+                return Poll::Ready(Some((value, operation)));
+            }
+        }
+    }
+}
+
+impl<St: FusedStream, T, E> FusedStream for KeyByExtension<St, T, E>
+where
+    St: Stream<Item = Operation<E>>,
+    E: Extension<T>,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+impl<St, T, E> Sink<Operation<E>> for KeyByExtension<St, T, E>
+where
+    St: Stream<Item = Operation<E>> + Sink<Operation<E>>,
+{
+    type Error = St::Error;
+
+    delegate_sink!(stream, Operation<E>);
+}
+
+/// Stream for the [`split_by_extension`](ExtensionExt::split_by_extension) method.
+///
+/// Yields all operations which are not routed to a subscribed stream.
+#[derive(Debug)]
+#[pin_project]
+#[must_use = "streams do nothing unless polled"]
+pub struct SplitByExtension<St, T, E>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    #[pin]
+    stream: Fuse<St>,
+    subscriptions: HashMap<T, mpsc::UnboundedSender<Operation<E>>>,
+}
+
+impl<St, T, E> SplitByExtension<St, T, E>
+where
+    St: Stream<Item = Operation<E>>,
+    T: StdHash + Eq,
+    E: Extension<T>,
+{
+    pub(super) fn new(stream: St) -> SplitByExtension<St, T, E> {
+        SplitByExtension {
+            stream: stream.fuse(),
+            subscriptions: HashMap::new(),
+        }
+    }
+
+    /// Returns a stream of all following operations with the given extension value.
+    ///
+    /// Subscribing again to the same value replaces the previous stream. When a subscribed
+    /// stream is dropped its operations are passed on by this stream again.
+    pub fn subscribe(&mut self, value: T) -> ExtensionStream<E> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.subscriptions.insert(value, tx);
+        ExtensionStream { rx }
+    }
+
+    delegate_access_inner!(stream, St, (.));
+}
+
+impl<St, T, E> Stream for SplitByExtension<St, T, E>
+where
+    St: Stream<Item = Operation<E>>,
+    T: StdHash + Eq,
+    E: Extension<T>,
+{
+    type Item = Operation<E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            let Some(operation) = ready!(this.stream.as_mut().poll_next(cx)) else {
+                // Dropping the senders ends all subscribed streams.
+                this.subscriptions.clear();
+                return Poll::Ready(None);
+            };
+
+            let Some(value) = operation.header.extension::<T>() else {
+                return Poll::Ready(Some(operation));
+            };
+
+            let Some(tx) = this.subscriptions.get(&value) else {
+                return Poll::Ready(Some(operation));
+            };
+
+            if let Err(mpsc::error::SendError(operation)) = tx.send(operation) {
+                this.subscriptions.remove(&value);
+                return Poll::Ready(Some(operation));
+            }
+        }
+    }
+}
+
+impl<St: FusedStream, T, E> FusedStream for SplitByExtension<St, T, E>
+where
+    St: Stream<Item = Operation<E>>,
+    T: StdHash + Eq,
+    E: Extension<T>,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+/// Stream of operations with one extension value, see
+/// [`SplitByExtension::subscribe`].
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct ExtensionStream<E> {
+    rx: mpsc::UnboundedReceiver<Operation<E>>,
+}
+
+impl<E> Stream for ExtensionStream<E> {
+    type Item = Operation<E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream::{iter, select};
+    use futures_util::{StreamExt, TryStreamExt};
+    use p2panda_core::Operation;
+
+    use crate::stream::validate::ValidateExt;
+    use crate::test_utils::{Extensions, StreamName, mock_stream};
+
+    use super::ExtensionExt;
+
+    /// Operations of two different streams, interleaved.
+    async fn operations() -> Vec<Operation<Extensions>> {
+        select(mock_stream().take(5), mock_stream().take(5))
+            .validate()
+            .try_collect()
+            .await
+            .expect("valid operations")
+    }
+
+    #[tokio::test]
+    async fn filter_and_key() {
+        let operations = operations().await;
+        let stream_name: StreamName = operations[0].header.extension().unwrap();
+
+        let filtered: Vec<Operation<Extensions>> = iter(operations.clone())
+            .filter_extension(|value: &StreamName| value == &stream_name)
+            .collect()
+            .await;
+        assert_eq!(filtered.len(), 5);
+
+        let keyed: Vec<(StreamName, Operation<Extensions>)> = iter(operations)
+            .key_by_extension::<StreamName>()
+            .collect()
+            .await;
+        assert_eq!(keyed.len(), 10);
+        assert_eq!(keyed[0].0, stream_name);
+        assert_ne!(keyed[1].0, stream_name);
+    }
+
+    #[tokio::test]
+    async fn split() {
+        let operations = operations().await;
+        let stream_name: StreamName = operations[0].header.extension().unwrap();
+
+        let mut split = iter(operations).split_by_extension::<StreamName>();
+        let subscribed = split.subscribe(stream_name.clone());
+
+        let rest: Vec<Operation<Extensions>> = split.collect().await;
+        assert_eq!(rest.len(), 5);
+
+        let subscribed: Vec<Operation<Extensions>> = subscribed.collect().await;
+        assert_eq!(subscribed.len(), 5);
+        assert!(subscribed.iter().all(|operation| {
+            operation.header.extension::<StreamName>() == Some(stream_name.clone())
+        }));
+    }
+}
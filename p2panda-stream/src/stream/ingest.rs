@@ -1,10 +1,10 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+use std::collections::VecDeque;
 use std::future::Future;
 use std::marker::PhantomData;
 use std::pin::Pin;
 
-use futures_channel::mpsc::{self};
 use futures_util::stream::{Fuse, FusedStream};
 use futures_util::task::{Context, Poll};
 use futures_util::{FutureExt, Sink, Stream, StreamExt, ready};
@@ -30,6 +30,10 @@ pub trait IngestExt<S, L, E>: Stream<Item = (Header<E>, Option<Body>, Vec<u8>)>
     /// order". The buffer size determines the maximum number of out-of-order operations in a row
     /// this method can handle. This means that given a buffer size of for example 100, we can
     /// handle a worst-case unordered, fully reversed log with 100 items without problem.
+    ///
+    /// Buffered operations are re-attempted in the order they were buffered in, so every
+    /// operation gets its turn before any operation is re-attempted again. A reversed log (for
+    /// example when syncing newest-first) is handled like any other unordered stream.
     fn ingest(self, store: S, ooo_buffer_size: usize) -> Ingest<Self, S, L, E>
     where
         S: OperationStore<L, E> + LogStore<L, E>,
@@ -58,9 +62,7 @@ where
     stream: Fuse<St>,
     store: S,
     ooo_buffer_size: usize,
-    ooo_buffer_tx: mpsc::Sender<IngestAttempt<E>>,
-    #[pin]
-    ooo_buffer_rx: mpsc::Receiver<IngestAttempt<E>>,
+    ooo_buffer: OutOfOrderBuffer<E>,
     ingest_fut: Option<Pin<IngestFut<E>>>,
     _marker: PhantomData<L>,
 }
@@ -72,21 +74,11 @@ where
     E: Extension<L> + Extension<PruneFlag> + Extensions,
 {
     pub(super) fn new(stream: St, store: S, ooo_buffer_size: usize) -> Ingest<St, S, L, E> {
-        // @TODO(adz): We can optimize for the internal out-of-order buffer even more as it's FIFO
-        // nature is not optimal. A sorted list (by seq num, maybe even grouped by public key)
-        // might be more efficient, though I'm not sure about optimal implementations yet, so
-        // benchmarks and more real-world experience might make sense before we attempt any of
-        // this.
-        //
-        // Also, using an mpsc for the internal buffer seems overkill.
-        let (ooo_buffer_tx, ooo_buffer_rx) = mpsc::channel::<IngestAttempt<E>>(ooo_buffer_size);
-
         Ingest {
             store,
             stream: stream.fuse(),
             ooo_buffer_size,
-            ooo_buffer_tx,
-            ooo_buffer_rx,
+            ooo_buffer: OutOfOrderBuffer::new(),
             ingest_fut: None,
             _marker: PhantomData,
         }
@@ -130,20 +122,13 @@ where
                             ))));
                         }
 
-                        // Push operation back into the internal queue, if something goes wrong here
-                        // this must be an critical failure.
-                        let Ok(_) = ready!(this.ooo_buffer_tx.poll_ready(cx)) else {
-                            break Poll::Ready(None);
-                        };
-
-                        let Ok(_) = this.ooo_buffer_tx.start_send(IngestAttempt(
+                        // Push operation back into the internal buffer.
+                        this.ooo_buffer.push(IngestAttempt(
                             header,
                             body,
                             header_bytes,
                             counter + 1,
-                        )) else {
-                            break Poll::Ready(None);
-                        };
+                        ));
 
                         // In the next iteration we should prioritize the stream again.
                         park_buffer = true;
@@ -164,8 +149,8 @@ where
             let res = {
                 // If the buffer ran full we prioritize pulling from it first, re-attempting
                 // ingest. This avoids clogging up the pipeline.
-                if this.ooo_buffer_rx.size_hint().0 == *this.ooo_buffer_size {
-                    ready!(this.ooo_buffer_rx.as_mut().poll_next(cx))
+                if !this.ooo_buffer.is_empty() && this.ooo_buffer.len() >= *this.ooo_buffer_size {
+                    this.ooo_buffer.pop()
                 } else {
                     // Otherwise prefer pulling from the external stream first as freshly incoming
                     // data should be prioritized.
@@ -184,15 +169,14 @@ where
                             if park_buffer {
                                 return Poll::Pending;
                             }
-                            ready!(this.ooo_buffer_rx.as_mut().poll_next(cx))
+                            match this.ooo_buffer.pop() {
+                                Some(attempt) => Some(attempt),
+                                None => return Poll::Pending,
+                            }
                         }
-                        Poll::Ready(None) => match this.ooo_buffer_rx.as_mut().poll_next(cx) {
-                            Poll::Ready(Some(attempt)) => Some(attempt),
-                            // If there's no value coming from the buffer _and_ the external stream is
-                            // terminated, we can be sure nothing will come anymore.
-                            Poll::Pending => None,
-                            Poll::Ready(None) => None,
-                        },
+                        // If there's no value coming from the buffer _and_ the external stream is
+                        // terminated, we can be sure nothing will come anymore.
+                        Poll::Ready(None) => this.ooo_buffer.pop(),
                     }
                 }
             };
@@ -239,7 +223,7 @@ where
     L: Send + Sync,
 {
     fn is_terminated(&self) -> bool {
-        self.stream.is_terminated() && self.ooo_buffer_rx.is_terminated()
+        self.stream.is_terminated() && self.ooo_buffer.is_empty()
     }
 }
 
@@ -263,12 +247,47 @@ type IngestFut<E> =
 #[derive(Debug)]
 struct IngestAttempt<E>(Header<E>, Option<Body>, Vec<u8>, AttemptCounter);
 
+/// FIFO buffer for operations which arrived out-of-order.
+///
+/// Re-attempting operations in the order they were buffered in makes sure that an operation
+/// which can't be ingested yet does not use up its attempts while other buffered operations
+/// wait.
+#[derive(Debug)]
+struct OutOfOrderBuffer<E> {
+    attempts: VecDeque<IngestAttempt<E>>,
+}
+
+impl<E> OutOfOrderBuffer<E> {
+    fn new() -> Self {
+        Self {
+            attempts: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, attempt: IngestAttempt<E>) {
+        self.attempts.push_back(attempt);
+    }
+
+    fn pop(&mut self) -> Option<IngestAttempt<E>> {
+        self.attempts.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.attempts.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.attempts.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::pin::pin;
     use std::time::Duration;
 
     use futures_util::stream::iter;
-    use futures_util::{StreamExt, TryStreamExt};
+    use futures_util::{StreamExt, TryStreamExt, poll};
     use p2panda_core::{Operation, RawOperation};
     use p2panda_store::MemoryStore;
     use p2panda_store::sqlite::store::SqliteStore;
@@ -328,6 +347,65 @@ mod tests {
         assert_eq!(res.len(), items_num);
     }
 
+    #[tokio::test]
+    async fn reversed_log() {
+        let items_num = 100;
+        let store = MemoryStore::<StreamName, Extensions>::new();
+
+        // Logs synced "newest-first" arrive fully reversed.
+        let mut items: Vec<RawOperation> = mock_stream().take(items_num).collect().await;
+        items.reverse();
+
+        let stream = iter(items)
+            .decode()
+            .filter_map(|item| async { item.ok() })
+            .ingest(store, items_num);
+
+        // As soon as the first operation arrived, all buffered operations get ingested in the
+        // order of their log, even though they're re-attempted in the order they were buffered in.
+        let res: Vec<Operation<Extensions>> = stream.try_collect().await.expect("not fail");
+        let seq_nums: Vec<u64> = res
+            .iter()
+            .map(|operation| operation.header.seq_num)
+            .collect();
+        assert_eq!(seq_nums, (0..items_num as u64).collect::<Vec<u64>>());
+    }
+
+    #[tokio::test]
+    async fn re_attempt_in_fifo_order() {
+        let store = MemoryStore::<StreamName, Extensions>::new();
+        let (tx, rx) = mpsc::channel::<RawOperation>(10);
+
+        // Two logs whose first operations arrive last.
+        let log_a: Vec<RawOperation> = mock_stream().take(2).collect().await;
+        let log_b: Vec<RawOperation> = mock_stream().take(3).collect().await;
+
+        let mut stream = pin!(
+            ReceiverStream::new(rx)
+                .decode()
+                .filter_map(|item| async { item.ok() })
+                .ingest(store, 4)
+        );
+
+        tx.send(log_a[1].clone()).await.unwrap();
+        tx.send(log_b[2].clone()).await.unwrap();
+
+        // While no new operations arrive, buffered operations are re-attempted one at a time.
+        // Both operations take turns, the operation with the lower sequence number does not use
+        // up all of its attempts on its own.
+        for _ in 0..5 {
+            assert!(poll!(stream.next()).is_pending());
+        }
+
+        tx.send(log_a[0].clone()).await.unwrap();
+        tx.send(log_b[0].clone()).await.unwrap();
+        tx.send(log_b[1].clone()).await.unwrap();
+        drop(tx);
+
+        let res: Vec<Operation<Extensions>> = stream.try_collect().await.expect("not fail");
+        assert_eq!(res.len(), 5);
+    }
+
     #[tokio::test]
     async fn ingest_async_store_bug() {
         // Related issue: https://github.com/p2panda/p2panda/issues/694
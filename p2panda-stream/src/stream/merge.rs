@@ -0,0 +1,270 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use futures_util::stream::{Fuse, FusedStream};
+use futures_util::task::{Context, Poll};
+use futures_util::{Stream, StreamExt, ready};
+use p2panda_core::{Hash, Operation, PublicKey};
+use pin_project::pin_project;
+
+use crate::causal::{CausalOrderError, CausalOrderer};
+use crate::macros::delegate_access_inner;
+
+/// Operations and sync events of a topic, as received from the network.
+#[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum TopicMessage<E> {
+    /// Operation received during a sync session with a peer (`FromNetwork::SyncMessage` in
+    /// `p2panda-net`).
+    Sync {
+        operation: Operation<E>,
+        peer: PublicKey,
+    },
+
+    /// Operation received via gossip (`FromNetwork::GossipMessage` in `p2panda-net`).
+    Gossip {
+        operation: Operation<E>,
+        peer: PublicKey,
+    },
+
+    /// Sync session with a peer ended (`SystemEvent::SyncDone` in `p2panda-net`).
+    SyncDone { peer: PublicKey },
+}
+
+/// An extension trait for `Stream`s that provides a convenient
+/// [`merge_sources`](MergeSourcesExt::merge_sources) method.
+pub trait MergeSourcesExt<E>: Stream<Item = TopicMessage<E>> {
+    /// Merges operations received via sync and gossip into one causally ordered stream.
+    ///
+    /// Sync sessions backfill older operations while newer ones keep arriving via gossip. Gossip
+    /// operations of a peer are held back while a sync session with that peer is running and are
+    /// released after the session ended, so backfilled operations are delivered first. All
+    /// operations are delivered after their dependencies, see
+    /// [`causal_order`](crate::CausalOrderExt::causal_order) for the meaning of `max_buffer_size`
+    /// and `dependency_timeout`.
+    ///
+    /// Operations arriving via both paths are delivered only once. When a sync session ended a
+    /// `MergeEvent::Backfilled` watermark follows all operations of that session which could be
+    /// delivered.
+    fn merge_sources(
+        self,
+        max_buffer_size: usize,
+        dependency_timeout: Duration,
+    ) -> MergeSources<Self, E>
+    where
+        Self: Sized,
+    {
+        MergeSources::new(
+            self,
+            CausalOrderer::new(max_buffer_size, dependency_timeout),
+        )
+    }
+}
+
+impl<T: ?Sized, E> MergeSourcesExt<E> for T where T: Stream<Item = TopicMessage<E>> {}
+
+/// Events of the [`merge_sources`](MergeSourcesExt::merge_sources) method.
+#[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum MergeEvent<E> {
+    /// Operation with all its dependencies delivered.
+    Operation(Operation<E>),
+
+    /// Dependencies which are missing for longer than the configured timeout.
+    MissingDependencies(Vec<Hash>),
+
+    /// All operations received during the sync session with this peer were delivered, apart
+    /// from those still waiting for missing dependencies.
+    Backfilled { peer: PublicKey },
+}
+
+/// Stream for the [`merge_sources`](MergeSourcesExt::merge_sources) method.
+#[derive(Debug)]
+#[pin_project]
+#[must_use = "streams do nothing unless polled"]
+pub struct MergeSources<St, E>
+where
+    St: Stream<Item = TopicMessage<E>>,
+{
+    #[pin]
+    stream: Fuse<St>,
+    orderer: CausalOrderer<E>,
+
+    /// Gossip operations held back per peer while a sync session with them is running.
+    syncing: HashMap<PublicKey, Vec<Operation<E>>>,
+
+    events: VecDeque<MergeEvent<E>>,
+}
+
+impl<St, E> MergeSources<St, E>
+where
+    St: Stream<Item = TopicMessage<E>>,
+{
+    pub(super) fn new(stream: St, orderer: CausalOrderer<E>) -> MergeSources<St, E> {
+        MergeSources {
+            stream: stream.fuse(),
+            orderer,
+            syncing: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Acquires a mutable reference to the orderer, for example to mark already persisted
+    /// operations as delivered.
+    pub fn orderer_mut(&mut self) -> &mut CausalOrderer<E> {
+        &mut self.orderer
+    }
+
+    delegate_access_inner!(stream, St, (.));
+}
+
+impl<St, E> Stream for MergeSources<St, E>
+where
+    St: Stream<Item = TopicMessage<E>>,
+{
+    type Item = Result<MergeEvent<E>, CausalOrderError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            // 1. Hand out all operations which are ready, then watermarks and missing
+            //    dependencies in the order they occurred.
+            if let Some(operation) = this.orderer.next_ready() {
+                return Poll::Ready(Some(Ok(MergeEvent::Operation(operation))));
+            }
+
+            if let Some(event) = this.events.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+
+            // 2. Pull in the next message from the external stream.
+            let Some(message) = ready!(this.stream.as_mut().poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+
+            let result = match message {
+                TopicMessage::Sync { operation, peer } => {
+                    this.syncing.entry(peer).or_default();
+                    this.orderer.push(operation)
+                }
+                TopicMessage::Gossip { operation, peer } => match this.syncing.get_mut(&peer) {
+                    Some(held_back) => {
+                        held_back.push(operation);
+                        Ok(())
+                    }
+                    None => this.orderer.push(operation),
+                },
+                TopicMessage::SyncDone { peer } => {
+                    let held_back = this.syncing.remove(&peer).unwrap_or_default();
+                    this.events.push_back(MergeEvent::Backfilled { peer });
+                    held_back
+                        .into_iter()
+                        .try_for_each(|operation| this.orderer.push(operation))
+                }
+            };
+            if let Err(err) = result {
+                return Poll::Ready(Some(Err(err)));
+            }
+
+            let missing = this.orderer.missing_dependencies(Instant::now());
+            if !missing.is_empty() {
+                this.events
+                    .push_back(MergeEvent::MissingDependencies(missing));
+            }
+        }
+    }
+}
+
+impl<St: FusedStream, E> FusedStream for MergeSources<St, E>
+where
+    St: Stream<Item = TopicMessage<E>>,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::stream::iter;
+    use futures_util::{StreamExt, TryStreamExt};
+    use p2panda_core::{Operation, PrivateKey};
+
+    use crate::stream::validate::ValidateExt;
+    use crate::test_utils::{Extensions, mock_stream};
+
+    use super::{MergeEvent, MergeSourcesExt, TopicMessage};
+
+    #[tokio::test]
+    async fn merge_sync_and_gossip() {
+        let operations: Vec<Operation<Extensions>> = mock_stream()
+            .take(6)
+            .validate()
+            .try_collect()
+            .await
+            .expect("valid operations");
+        let peer = PrivateKey::new().public_key();
+
+        // Operations 0 to 3 are backfilled via sync, while 3 to 5 arrive via gossip at the same
+        // time.
+        let messages = vec![
+            TopicMessage::Sync {
+                operation: operations[0].clone(),
+                peer,
+            },
+            TopicMessage::Gossip {
+                operation: operations[4].clone(),
+                peer,
+            },
+            TopicMessage::Sync {
+                operation: operations[1].clone(),
+                peer,
+            },
+            TopicMessage::Gossip {
+                operation: operations[3].clone(),
+                peer,
+            },
+            TopicMessage::Sync {
+                operation: operations[2].clone(),
+                peer,
+            },
+            TopicMessage::Sync {
+                operation: operations[3].clone(),
+                peer,
+            },
+            TopicMessage::SyncDone { peer },
+            TopicMessage::Gossip {
+                operation: operations[5].clone(),
+                peer,
+            },
+        ];
+
+        let events: Vec<MergeEvent<Extensions>> = iter(messages)
+            .merge_sources(16, Duration::from_secs(60))
+            .try_collect()
+            .await
+            .expect("not fail");
+
+        let summary: Vec<Option<u64>> = events
+            .iter()
+            .map(|event| match event {
+                MergeEvent::Operation(operation) => Some(operation.header.seq_num),
+                MergeEvent::Backfilled { .. } => None,
+                MergeEvent::MissingDependencies(_) => panic!("unexpected timeout"),
+            })
+            .collect();
+
+        // Every operation is delivered once and in order. The watermark follows the backfilled
+        // operations, gossip operations which were held back are released with it.
+        assert_eq!(
+            summary,
+            vec![Some(0), Some(1), Some(2), Some(3), Some(4), None, Some(5)]
+        );
+    }
+}
@@ -1,7 +1,25 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+mod causal;
+mod checkpoint;
+mod dead_letter;
 mod decode;
+mod extension;
 mod ingest;
+mod merge;
+mod persist;
+mod validate;
+mod window;
 
+pub use causal::{CausalOrder, CausalOrderEvent, CausalOrderExt};
+pub use checkpoint::{Checkpoint, CheckpointEvent, CheckpointExt, Checkpoints, LogPosition};
+pub use dead_letter::{DeadLetter, DeadLetterExt, ValidateWithDeadLetters};
 pub use decode::{Decode, DecodeExt};
+pub use extension::{
+    ExtensionExt, ExtensionStream, FilterExtension, KeyByExtension, SplitByExtension,
+};
 pub use ingest::{Ingest, IngestExt};
+pub use merge::{MergeEvent, MergeSources, MergeSourcesExt, TopicMessage};
+pub use persist::{Persist, PersistError, PersistEvent, PersistExt};
+pub use validate::{Validate, ValidateExt, ValidationError};
+pub use window::{OperationWindow, Window, WindowExt, WindowSize};
@@ -0,0 +1,456 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::collections::{BTreeMap, VecDeque};
+use std::marker::PhantomData;
+use std::pin::Pin;
+
+use futures_util::future::poll_fn;
+use futures_util::sink::Drain;
+use futures_util::stream::{self, BoxStream};
+use futures_util::task::{Context, Poll};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use p2panda_core::prune::PruneFlag;
+use p2panda_core::{Extension, Extensions, Hash, Operation, OperationLimits, RawOperation};
+use p2panda_store::{LogStore, OperationStore};
+use thiserror::Error;
+
+use crate::operation::{IngestError, IngestResult, ingest_operation};
+use crate::stream::dead_letter::DeadLetter;
+use crate::stream::validate::{ValidationError, validate_raw_operation};
+
+/// An extension trait for `Stream`s that provides a convenient [`persist`](PersistExt::persist)
+/// method.
+pub trait PersistExt<S, L, E>: Stream<Item = RawOperation> {
+    /// Decodes, validates and persists incoming operations in the given store.
+    ///
+    /// This is the common "receive and persist" path of an application in one call: messages
+    /// received for a topic (`FromNetwork` in `p2panda-net`) contain the header and body bytes of
+    /// operations which can be passed in directly.
+    ///
+    /// Every operation is validated and checked against the integrity of its log before it is
+    /// persisted, logs are pruned according to the operation's prune flag. Operations which are
+    /// already in the store are reported as duplicates and not persisted again.
+    ///
+    /// Operations which arrived out-of-order wait in a buffer of the given size until the
+    /// operations before them in their log were persisted. Invalid operations are returned as
+    /// errors, the stream continues with the next item.
+    fn persist(self, store: S, ooo_buffer_size: usize) -> Persist<E>
+    where
+        S: OperationStore<L, E> + LogStore<L, E> + Send + 'static,
+        E: Extension<L> + Extension<PruneFlag> + Extensions + Send + Sync + 'static,
+        L: Send + Sync + 'static,
+        Self: Sized + Send + 'static,
+    {
+        Persist::new(self, store, ooo_buffer_size, None::<Drain<_>>)
+    }
+
+    /// Decodes, validates and persists incoming operations like [`persist`](PersistExt::persist),
+    /// but sends invalid operations into a dead-letter sink instead of returning them as errors.
+    ///
+    /// Operations which fail decoding, validation or the integrity checks of their log are sent
+    /// with the failure reason. Store failures and out-of-order operations which could not be
+    /// persisted are still returned as errors. When the sink fails it is not used anymore and
+    /// following invalid operations are returned as errors again.
+    fn persist_with_dead_letters<Si>(
+        self,
+        store: S,
+        ooo_buffer_size: usize,
+        dead_letters: Si,
+    ) -> Persist<E>
+    where
+        S: OperationStore<L, E> + LogStore<L, E> + Send + 'static,
+        E: Extension<L> + Extension<PruneFlag> + Extensions + Send + Sync + 'static,
+        L: Send + Sync + 'static,
+        Si: Sink<DeadLetter<PersistError>> + Send + Unpin + 'static,
+        Self: Sized + Send + 'static,
+    {
+        Persist::new(self, store, ooo_buffer_size, Some(dead_letters))
+    }
+}
+
+impl<T: ?Sized, S, L, E> PersistExt<S, L, E> for T where T: Stream<Item = RawOperation> {}
+
+/// Events of the [`persist`](PersistExt::persist) method.
+#[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum PersistEvent<E> {
+    /// Operation was validated and persisted.
+    Inserted(Operation<E>),
+
+    /// Operation was persisted already and ignored.
+    Duplicate(Hash),
+}
+
+/// Errors which can occur when persisting incoming operations.
+#[derive(Debug, Error)]
+pub enum PersistError {
+    /// Operation could not be decoded or is invalid.
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
+
+    /// Operation could not be ingested into its log or the store failed.
+    #[error(transparent)]
+    Ingest(#[from] IngestError),
+}
+
+/// Stream for the [`persist`](PersistExt::persist) method.
+#[must_use = "streams do nothing unless polled"]
+pub struct Persist<E> {
+    inner: BoxStream<'static, Result<PersistEvent<E>, PersistError>>,
+}
+
+impl<E> Persist<E>
+where
+    E: Extensions + Send + Sync + 'static,
+{
+    pub(super) fn new<St, S, L, Si>(
+        stream: St,
+        store: S,
+        ooo_buffer_size: usize,
+        dead_letters: Option<Si>,
+    ) -> Persist<E>
+    where
+        St: Stream<Item = RawOperation> + Send + 'static,
+        S: OperationStore<L, E> + LogStore<L, E> + Send + 'static,
+        E: Extension<L> + Extension<PruneFlag>,
+        L: Send + Sync + 'static,
+        Si: Sink<DeadLetter<PersistError>> + Send + Unpin + 'static,
+    {
+        let state = State {
+            stream: stream.boxed(),
+            store,
+            dead_letters,
+            ooo_buffer_size,
+            ooo_buffer: BTreeMap::new(),
+            next_id: 0,
+            events: VecDeque::new(),
+            terminated: false,
+            _marker: PhantomData,
+        };
+
+        let inner = stream::unfold(state, |mut state| async move {
+            loop {
+                if let Some(event) = state.events.pop_front() {
+                    return Some((event, state));
+                }
+                if state.terminated {
+                    return None;
+                }
+                state.pull().await;
+            }
+        })
+        .boxed();
+
+        Persist { inner }
+    }
+}
+
+impl<E> Stream for Persist<E> {
+    type Item = Result<PersistEvent<E>, PersistError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+impl<E> std::fmt::Debug for Persist<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Persist").finish_non_exhaustive()
+    }
+}
+
+/// Operation waiting for the operations before it in its log, with the number of missing ones.
+type Buffered<E> = (Operation<E>, u64);
+
+struct State<S, L, E, Si> {
+    stream: BoxStream<'static, RawOperation>,
+    store: S,
+    dead_letters: Option<Si>,
+    ooo_buffer_size: usize,
+
+    /// Out-of-order operations sorted by their sequence number, so operations earlier in their log
+    /// are re-attempted first.
+    ooo_buffer: BTreeMap<(u64, usize), Buffered<E>>,
+    next_id: usize,
+
+    events: VecDeque<Result<PersistEvent<E>, PersistError>>,
+    terminated: bool,
+    _marker: PhantomData<L>,
+}
+
+impl<S, L, E, Si> State<S, L, E, Si>
+where
+    S: OperationStore<L, E> + LogStore<L, E>,
+    E: Extension<L> + Extension<PruneFlag> + Extensions,
+    Si: Sink<DeadLetter<PersistError>> + Unpin,
+{
+    /// Pull in the next operation and queue the resulting events.
+    async fn pull(&mut self) {
+        let (header_bytes, body_bytes) = match self.stream.next().await {
+            Some(raw_operation) => raw_operation,
+            None => {
+                // Nothing will arrive anymore which could complete the logs of the buffered
+                // operations.
+                self.terminated = true;
+                for (_, (_, num_missing)) in std::mem::take(&mut self.ooo_buffer) {
+                    self.events
+                        .push_back(Err(IngestError::MaxAttemptsReached(num_missing).into()));
+                }
+                return;
+            }
+        };
+
+        let raw_operation = self
+            .dead_letters
+            .is_some()
+            .then(|| (header_bytes.clone(), body_bytes.clone()));
+        let operation =
+            match validate_raw_operation(&header_bytes, body_bytes, &OperationLimits::default()) {
+                Ok(operation) => operation,
+                Err(err) => {
+                    self.fail(raw_operation, err.into()).await;
+                    return;
+                }
+            };
+
+        let is_buffered = self
+            .ooo_buffer
+            .values()
+            .any(|(buffered, _)| buffered.hash == operation.hash);
+        match self.store.has_operation(operation.hash).await {
+            Ok(false) if !is_buffered => (),
+            Ok(_) => {
+                self.events
+                    .push_back(Ok(PersistEvent::Duplicate(operation.hash)));
+                return;
+            }
+            Err(err) => {
+                self.events
+                    .push_back(Err(IngestError::StoreError(err.to_string()).into()));
+                return;
+            }
+        }
+
+        let raw_operation = self.raw_operation(&operation);
+        match self.ingest(operation).await {
+            Ok(IngestResult::Complete(operation)) => {
+                self.events.push_back(Ok(PersistEvent::Inserted(operation)));
+                self.retry_buffered().await;
+            }
+            Ok(IngestResult::Retry(header, body, _, num_missing)) => {
+                let operation = Operation {
+                    hash: header.hash(),
+                    header,
+                    body,
+                };
+                if self.ooo_buffer.len() >= self.ooo_buffer_size {
+                    self.events
+                        .push_back(Err(IngestError::MaxAttemptsReached(num_missing).into()));
+                    return;
+                }
+                self.buffer(operation, num_missing);
+            }
+            Err(err) => self.fail(raw_operation, err.into()).await,
+        }
+    }
+
+    /// Re-attempt buffered operations as long as some of them could be persisted.
+    async fn retry_buffered(&mut self) {
+        loop {
+            let mut progress = false;
+            for (_, (operation, _)) in std::mem::take(&mut self.ooo_buffer) {
+                let raw_operation = self.raw_operation(&operation);
+                match self.ingest(operation).await {
+                    Ok(IngestResult::Complete(operation)) => {
+                        self.events.push_back(Ok(PersistEvent::Inserted(operation)));
+                        progress = true;
+                    }
+                    Ok(IngestResult::Retry(header, body, _, num_missing)) => {
+                        let operation = Operation {
+                            hash: header.hash(),
+                            header,
+                            body,
+                        };
+                        self.buffer(operation, num_missing);
+                    }
+                    Err(err) => self.fail(raw_operation, err.into()).await,
+                }
+            }
+
+            if !progress {
+                break;
+            }
+        }
+    }
+
+    /// Encode the operation again for a possible dead letter, only if there's a sink for them.
+    fn raw_operation(&self, operation: &Operation<E>) -> Option<RawOperation> {
+        self.dead_letters.as_ref().map(|_| {
+            (
+                operation.header.to_bytes(),
+                operation.body.as_ref().map(|body| body.to_bytes()),
+            )
+        })
+    }
+
+    /// Send an invalid operation into the dead-letter sink or queue the error when there is none.
+    async fn fail(&mut self, raw_operation: Option<RawOperation>, err: PersistError) {
+        let is_invalid = matches!(
+            err,
+            PersistError::Validation(_)
+                | PersistError::Ingest(
+                    IngestError::InvalidOperation(_) | IngestError::MissingHeaderExtension(_)
+                )
+        );
+
+        if let (true, Some(sink), Some(operation)) =
+            (is_invalid, self.dead_letters.as_mut(), raw_operation)
+        {
+            if poll_fn(|cx| sink.poll_ready_unpin(cx)).await.is_err() {
+                self.dead_letters = None;
+                self.events.push_back(Err(err));
+                return;
+            }
+
+            let dead_letter = DeadLetter {
+                operation,
+                reason: err,
+            };
+            if sink.start_send_unpin(dead_letter).is_err() || sink.flush().await.is_err() {
+                self.dead_letters = None;
+            }
+            return;
+        }
+
+        self.events.push_back(Err(err));
+    }
+
+    fn buffer(&mut self, operation: Operation<E>, num_missing: u64) {
+        self.ooo_buffer.insert(
+            (operation.header.seq_num, self.next_id),
+            (operation, num_missing),
+        );
+        self.next_id = self.next_id.wrapping_add(1);
+    }
+
+    async fn ingest(&mut self, operation: Operation<E>) -> Result<IngestResult<E>, IngestError> {
+        let log_id: L = operation
+            .header
+            .extension()
+            .ok_or(IngestError::MissingHeaderExtension("log_id".into()))?;
+        let prune_flag: PruneFlag = operation
+            .header
+            .extension()
+            .ok_or(IngestError::MissingHeaderExtension("prune_flag".into()))?;
+        let header_bytes = operation.header.to_bytes();
+
+        ingest_operation::<S, L, E>(
+            &mut self.store,
+            operation.header,
+            operation.body,
+            header_bytes,
+            &log_id,
+            prune_flag.is_set(),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_channel::mpsc;
+    use futures_util::StreamExt;
+    use futures_util::stream::iter;
+    use p2panda_core::RawOperation;
+    use p2panda_store::{MemoryStore, OperationStore};
+
+    use crate::operation::IngestError;
+    use crate::stream::dead_letter::DeadLetter;
+    use crate::test_utils::{Extensions, StreamName, mock_stream};
+
+    use super::{PersistError, PersistEvent, PersistExt};
+
+    #[tokio::test]
+    async fn persist() {
+        let store = MemoryStore::<StreamName, Extensions>::new();
+
+        let mut items: Vec<RawOperation> = mock_stream().take(10).collect().await;
+        // Operations arrive out-of-order, twice and with one broken operation.
+        items.swap(2, 5);
+        items.push(items[3].clone());
+        items.push((vec![1, 2, 3], None));
+
+        let events: Vec<_> = iter(items).persist(store.clone(), 16).collect().await;
+
+        let inserted: Vec<u64> = events
+            .iter()
+            .filter_map(|event| match event {
+                Ok(PersistEvent::Inserted(operation)) => Some(operation.header.seq_num),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(inserted.len(), 10);
+        assert_eq!(&inserted[..2], &[0, 1]);
+
+        let duplicates = events
+            .iter()
+            .filter(|event| matches!(event, Ok(PersistEvent::Duplicate(_))))
+            .count();
+        assert_eq!(duplicates, 1);
+        assert!(matches!(
+            events.last(),
+            Some(Err(PersistError::Validation(_)))
+        ));
+
+        for event in events {
+            if let Ok(PersistEvent::Inserted(operation)) = event {
+                assert!(store.has_operation(operation.hash).await.unwrap());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn dead_letters() {
+        let store = MemoryStore::<StreamName, Extensions>::new();
+
+        let mut items: Vec<RawOperation> = mock_stream().take(3).collect().await;
+        items[1].1 = Some(b"Hello, Walrus!".to_vec());
+
+        let (tx, rx) = mpsc::unbounded();
+        let events: Vec<_> = iter(items.clone())
+            .persist_with_dead_letters(store, 16, tx)
+            .collect()
+            .await;
+
+        // The invalid operation is diverted, the following one can't be persisted without it.
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], Ok(PersistEvent::Inserted(_))));
+        assert!(matches!(
+            events[1],
+            Err(PersistError::Ingest(IngestError::MaxAttemptsReached(1)))
+        ));
+
+        let dead_letters: Vec<DeadLetter<PersistError>> = rx.collect().await;
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].operation, items[1]);
+        assert!(matches!(
+            dead_letters[0].reason,
+            PersistError::Validation(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_operations() {
+        let store = MemoryStore::<StreamName, Extensions>::new();
+
+        // The first operation of the log never arrives.
+        let items: Vec<RawOperation> = mock_stream().take(4).skip(1).collect().await;
+        let events: Vec<_> = iter(items).persist(store, 16).collect().await;
+        assert_eq!(events.len(), 3);
+        assert!(
+            events
+                .iter()
+                .all(|event| matches!(event, Err(PersistError::Ingest(_))))
+        );
+    }
+}
@@ -0,0 +1,220 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::marker::PhantomData;
+use std::pin::Pin;
+
+use futures_util::stream::{Fuse, FusedStream};
+use futures_util::task::{Context, Poll};
+use futures_util::{Sink, Stream, StreamExt, ready};
+use p2panda_core::cbor::{DecodeError, decode_cbor};
+use p2panda_core::{
+    Body, Extensions, Hash, Header, Operation, OperationError, OperationLimits, RawOperation,
+    validate,
+};
+use pin_project::pin_project;
+use thiserror::Error;
+
+use crate::macros::{delegate_access_inner, delegate_sink};
+
+/// An extension trait for `Stream`s that provides a convenient
+/// [`validate`](ValidateExt::validate) method.
+pub trait ValidateExt<E>: Stream<Item = RawOperation> {
+    /// Decode byte streams into p2panda operations and validate them.
+    ///
+    /// Header bytes are decoded, the signature is verified against the author's public key and
+    /// the body is checked against the payload hash and size claimed in the header. Messages
+    /// received from the network (`FromNetwork` in `p2panda-net`) contain the header and body
+    /// bytes of operations which can be passed in directly.
+    ///
+    /// Invalid operations are returned as errors, the stream continues with the next item.
+    fn validate(self) -> Validate<Self, E>
+    where
+        E: Extensions,
+        Self: Sized,
+    {
+        Validate::new(self, OperationLimits::default())
+    }
+
+    /// Decode and validate operations like [`validate`](ValidateExt::validate) and additionally
+    /// reject operations exceeding the given limits, see `OperationLimits` in `p2panda-core`.
+    ///
+    /// Limits are checked before the signature is verified, so oversized operations are rejected
+    /// early.
+    fn validate_with_limits(self, limits: OperationLimits) -> Validate<Self, E>
+    where
+        E: Extensions,
+        Self: Sized,
+    {
+        Validate::new(self, limits)
+    }
+}
+
+impl<T: ?Sized, E> ValidateExt<E> for T where T: Stream<Item = RawOperation> {}
+
+/// Errors which can occur when decoding and validating operations.
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    /// Header bytes could not be decoded.
+    #[error("could not decode header: {0}")]
+    Decode(#[from] DecodeError),
+
+    /// Operation is not signed by its author, its body doesn't match the header or it doesn't
+    /// follow the p2panda specification otherwise.
+    #[error("invalid operation {hash}: {error}")]
+    InvalidOperation { hash: Hash, error: OperationError },
+}
+
+/// Stream for the [`validate`](ValidateExt::validate) method.
+#[derive(Debug)]
+#[pin_project]
+#[must_use = "streams do nothing unless polled"]
+pub struct Validate<St, E>
+where
+    St: Stream<Item = RawOperation>,
+    E: Extensions,
+{
+    #[pin]
+    stream: Fuse<St>,
+    limits: OperationLimits,
+    _marker: PhantomData<E>,
+}
+
+impl<St, E> Validate<St, E>
+where
+    St: Stream<Item = RawOperation>,
+    E: Extensions,
+{
+    pub(super) fn new(stream: St, limits: OperationLimits) -> Validate<St, E> {
+        Validate {
+            stream: stream.fuse(),
+            limits,
+            _marker: PhantomData,
+        }
+    }
+
+    delegate_access_inner!(stream, St, (.));
+}
+
+impl<St, E> Stream for Validate<St, E>
+where
+    St: Stream<Item = RawOperation>,
+    E: Extensions,
+{
+    type Item = Result<Operation<E>, ValidationError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        let res = ready!(this.stream.as_mut().poll_next(cx));
+        Poll::Ready(res.map(|(header_bytes, body_bytes)| {
+            validate_raw_operation(&header_bytes, body_bytes, this.limits)
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+impl<St: FusedStream, E> FusedStream for Validate<St, E>
+where
+    St: Stream<Item = RawOperation>,
+    E: Extensions,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+impl<S, E> Sink<RawOperation> for Validate<S, E>
+where
+    S: Stream<Item = RawOperation> + Sink<RawOperation>,
+    E: Extensions,
+{
+    type Error = S::Error;
+
+    delegate_sink!(stream, RawOperation);
+}
+
+/// Decode header bytes into an operation and validate it against the given limits.
+pub(crate) fn validate_raw_operation<E>(
+    header_bytes: &[u8],
+    body_bytes: Option<Vec<u8>>,
+    limits: &OperationLimits,
+) -> Result<Operation<E>, ValidationError>
+where
+    E: Extensions,
+{
+    let header = decode_cbor::<Header<E>, _>(header_bytes)?;
+    let operation = Operation {
+        hash: header.hash(),
+        header,
+        body: body_bytes.map(Body::from),
+    };
+    validate(&operation.header, operation.body.as_ref(), limits).map_err(|error| {
+        ValidationError::InvalidOperation {
+            hash: operation.hash,
+            error,
+        }
+    })?;
+    Ok(operation)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream::iter;
+    use futures_util::{StreamExt, TryStreamExt};
+    use p2panda_core::{Operation, OperationError, OperationLimits, RawOperation};
+
+    use crate::test_utils::{Extensions, mock_stream};
+
+    use super::{ValidateExt, ValidationError};
+
+    #[tokio::test]
+    async fn validate() {
+        let stream = mock_stream().validate();
+        let result: Vec<Operation<Extensions>> =
+            stream.take(5).try_collect().await.expect("not fail");
+        assert_eq!(result.len(), 5);
+        assert!(result.iter().all(|operation| operation.body.is_some()));
+    }
+
+    #[tokio::test]
+    async fn invalid_operations() {
+        let mut items: Vec<RawOperation> = mock_stream().take(3).collect().await;
+
+        // Body doesn't match the payload hash in the header.
+        items[1].1 = Some(b"Hello, Walrus!".to_vec());
+
+        // Header bytes can't be decoded.
+        items[2].0 = vec![1, 2, 3];
+
+        let result: Vec<Result<Operation<Extensions>, ValidationError>> =
+            iter(items).validate().collect().await;
+        assert!(result[0].is_ok());
+        assert!(matches!(
+            result[1],
+            Err(ValidationError::InvalidOperation {
+                error: OperationError::PayloadMismatch,
+                ..
+            })
+        ));
+        assert!(matches!(result[2], Err(ValidationError::Decode(_))));
+    }
+
+    #[tokio::test]
+    async fn limits() {
+        let limits = OperationLimits::new().max_payload_size(8);
+        let result: Vec<Result<Operation<Extensions>, ValidationError>> = mock_stream()
+            .take(2)
+            .validate_with_limits(limits)
+            .collect()
+            .await;
+        assert!(result.iter().all(|item| matches!(
+            item,
+            Err(ValidationError::InvalidOperation {
+                error: OperationError::PayloadTooLarge(15, 8),
+                ..
+            })
+        )));
+    }
+}
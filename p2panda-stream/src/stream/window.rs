@@ -0,0 +1,243 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures_util::stream::{Fuse, FusedStream};
+use futures_util::task::{Context, Poll};
+use futures_util::{Sink, Stream, StreamExt};
+use p2panda_core::{Operation, PublicKey};
+use pin_project::pin_project;
+use tokio::time::{Sleep, sleep};
+
+use crate::macros::{delegate_access_inner, delegate_sink};
+
+/// Size of the windows created by the [`window`](WindowExt::window) method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowSize {
+    /// Window closes after the given number of operations.
+    Count(usize),
+
+    /// Window opens with the first operation and closes after the given duration.
+    Time(Duration),
+}
+
+/// An extension trait for `Stream`s that provides a convenient [`window`](WindowExt::window)
+/// method.
+pub trait WindowExt<E>: Stream<Item = Operation<E>> {
+    /// Groups operations into windows of a fixed number of operations or a fixed duration.
+    ///
+    /// This is useful to batch operations, for example to re-render a user interface at most
+    /// every 100 milliseconds, or to count operations per author. Windows are never empty, when
+    /// the stream ends the last window is emitted with all operations collected so far.
+    ///
+    /// Time windows require a running tokio runtime with the time driver enabled.
+    fn window(self, size: WindowSize) -> Window<Self, E>
+    where
+        Self: Sized,
+    {
+        Window::new(self, size)
+    }
+}
+
+impl<T: ?Sized, E> WindowExt<E> for T where T: Stream<Item = Operation<E>> {}
+
+/// Operations collected in one window.
+#[derive(Clone, Debug)]
+pub struct OperationWindow<E> {
+    pub operations: Vec<Operation<E>>,
+}
+
+impl<E> OperationWindow<E> {
+    /// Returns the number of operations in this window per author.
+    pub fn count_by_author(&self) -> HashMap<PublicKey, usize> {
+        let mut counts = HashMap::new();
+        for operation in &self.operations {
+            *counts.entry(operation.header.public_key).or_default() += 1;
+        }
+        counts
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+/// Stream for the [`window`](WindowExt::window) method.
+#[derive(Debug)]
+#[pin_project]
+#[must_use = "streams do nothing unless polled"]
+pub struct Window<St, E>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    #[pin]
+    stream: Fuse<St>,
+    size: WindowSize,
+    operations: Vec<Operation<E>>,
+
+    /// Closes the current time window, set when its first operation arrived.
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<St, E> Window<St, E>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    pub(super) fn new(stream: St, size: WindowSize) -> Window<St, E> {
+        let size = match size {
+            WindowSize::Count(count) => WindowSize::Count(count.max(1)),
+            size => size,
+        };
+
+        Window {
+            stream: stream.fuse(),
+            size,
+            operations: Vec::new(),
+            deadline: None,
+        }
+    }
+
+    delegate_access_inner!(stream, St, (.));
+}
+
+impl<St, E> Stream for Window<St, E>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    type Item = OperationWindow<E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        // 1. Collect all operations which are available right now.
+        loop {
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(operation)) => {
+                    if let WindowSize::Time(duration) = this.size
+                        && this.operations.is_empty()
+                    {
+                        this.deadline.replace(Box::pin(sleep(*duration)));
+                    }
+
+                    this.operations.push(operation);
+
+                    if let WindowSize::Count(count) = this.size
+                        && this.operations.len() >= *count
+                    {
+                        return Poll::Ready(Some(OperationWindow {
+                            operations: mem::take(this.operations),
+                        }));
+                    }
+                }
+                Poll::Ready(None) => {
+                    this.deadline.take();
+                    if this.operations.is_empty() {
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Ready(Some(OperationWindow {
+                        operations: mem::take(this.operations),
+                    }));
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        // 2. Close the current time window when its duration elapsed.
+        if let Some(deadline) = this.deadline.as_mut()
+            && deadline.as_mut().poll(cx).is_ready()
+        {
+            this.deadline.take();
+            return Poll::Ready(Some(OperationWindow {
+                operations: mem::take(this.operations),
+            }));
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<St: FusedStream, E> FusedStream for Window<St, E>
+where
+    St: Stream<Item = Operation<E>>,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated() && self.operations.is_empty()
+    }
+}
+
+impl<St, E> Sink<Operation<E>> for Window<St, E>
+where
+    St: Stream<Item = Operation<E>> + Sink<Operation<E>>,
+{
+    type Error = St::Error;
+
+    delegate_sink!(stream, Operation<E>);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::stream::iter;
+    use futures_util::{StreamExt, TryStreamExt};
+    use p2panda_core::Operation;
+
+    use crate::stream::validate::ValidateExt;
+    use crate::test_utils::{Extensions, mock_stream};
+
+    use super::{OperationWindow, WindowExt, WindowSize};
+
+    async fn operations(count: usize) -> Vec<Operation<Extensions>> {
+        mock_stream()
+            .take(count)
+            .validate()
+            .try_collect()
+            .await
+            .expect("valid operations")
+    }
+
+    #[tokio::test]
+    async fn count_windows() {
+        let operations = operations(10).await;
+        let author = operations[0].header.public_key;
+
+        let windows: Vec<OperationWindow<Extensions>> = iter(operations)
+            .window(WindowSize::Count(4))
+            .collect()
+            .await;
+        let sizes: Vec<usize> = windows.iter().map(|window| window.len()).collect();
+        assert_eq!(sizes, vec![4, 4, 2]);
+        assert_eq!(windows[0].count_by_author().get(&author), Some(&4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn time_windows() {
+        let operations = operations(5).await;
+
+        // Three operations arrive at once, two more after a pause which is longer than the
+        // window.
+        let stream = iter(operations)
+            .enumerate()
+            .then(|(index, operation)| async move {
+                if index == 3 {
+                    tokio::time::sleep(Duration::from_millis(250)).await;
+                }
+                operation
+            });
+
+        let windows: Vec<OperationWindow<Extensions>> = stream
+            .window(WindowSize::Time(Duration::from_millis(100)))
+            .collect()
+            .await;
+        let sizes: Vec<usize> = windows.iter().map(|window| window.len()).collect();
+        assert_eq!(sizes, vec![3, 2]);
+    }
+}
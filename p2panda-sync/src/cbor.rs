@@ -2,11 +2,15 @@
 
 //! Utility methods to encode or decode wire protocol messages in [CBOR] format.
 //!
+//! Decoding is guarded by `DecodeLimits`: frames larger than the maximum frame size or nested
+//! deeper than the maximum depth are rejected with `SyncError::MessageTooLarge`, which ends the
+//! session. This prevents malicious peers from exhausting our memory with huge frames.
+//!
 //! [CBOR]: https://cbor.io/
 use std::marker::PhantomData;
 
 use futures::{AsyncRead, AsyncWrite, Sink, Stream};
-use p2panda_core::cbor::{DecodeError, decode_cbor, encode_cbor};
+use p2panda_core::cbor::{DecodeError, decode_cbor_with_recursion_limit, encode_cbor};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tokio_util::bytes::{Buf, BytesMut};
@@ -16,6 +20,51 @@ use tokio_util::compat::{FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt};
 
 use crate::SyncError;
 
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+const MAX_DEPTH: usize = 64;
+
+/// Limits applied when decoding messages received from a remote peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum size of a single frame in bytes.
+    ///
+    /// Default: 16 MiB.
+    pub max_frame_size: usize,
+
+    /// Maximum nesting depth of arrays and maps in a single frame.
+    ///
+    /// Default: 64.
+    pub max_depth: usize,
+}
+
+impl DecodeLimits {
+    /// Return a default instance of `DecodeLimits`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define the maximum size of a single frame in bytes.
+    pub fn max_frame_size(mut self, bytes: usize) -> Self {
+        self.max_frame_size = bytes;
+        self
+    }
+
+    /// Define the maximum nesting depth of arrays and maps in a single frame.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_frame_size: MAX_FRAME_SIZE,
+            max_depth: MAX_DEPTH,
+        }
+    }
+}
+
 /// Implementation of the tokio codec traits to encode- and decode CBOR data as a stream.
 ///
 /// CBOR allows message framing based on initial "headers" for each "data item", which indicate the
@@ -26,12 +75,19 @@ use crate::SyncError;
 /// <https://www.rfc-editor.org/rfc/rfc8949.html#section-5.1>
 #[derive(Clone, Debug)]
 pub struct CborCodec<T> {
+    limits: DecodeLimits,
     _phantom: PhantomData<T>,
 }
 
 impl<M> CborCodec<M> {
     pub fn new() -> Self {
+        Self::with_limits(DecodeLimits::default())
+    }
+
+    /// Returns a codec rejecting frames which exceed the given limits during decoding.
+    pub fn with_limits(limits: DecodeLimits) -> Self {
         CborCodec {
+            limits,
             _phantom: PhantomData {},
         }
     }
@@ -83,10 +139,18 @@ where
         // Attempt decoding the buffer and remember how many bytes we've advanced it doing that.
         //
         // This will succeed in case 2. and 3.
-        let result: Result<Self::Item, _> = decode_cbor(&mut bytes);
+        let result: Result<Self::Item, _> =
+            decode_cbor_with_recursion_limit(&mut bytes, self.limits.max_depth);
         let ending = bytes.len();
+        let max_frame_size = self.limits.max_frame_size;
 
         match result {
+            Ok(_) if starting - ending > max_frame_size => {
+                Err(SyncError::MessageTooLarge(format!(
+                    "frame of {} bytes exceeds maximum of {max_frame_size} bytes",
+                    starting - ending
+                )))
+            }
             Ok(item) => {
                 // We've successfully read one full frame from the buffer. We're finally
                 // advancing it for the next decode iteration and yield the resulting data item to
@@ -98,6 +162,15 @@ where
             Err(ref error) => match error {
                 DecodeError::Io(err) => {
                     if err.kind() == std::io::ErrorKind::UnexpectedEof {
+                        // If the buffer already holds more bytes than allowed for a single frame
+                        // without containing a whole frame, the frame is too large. Stop here
+                        // before buffering even more data.
+                        if starting > max_frame_size {
+                            return Err(SyncError::MessageTooLarge(format!(
+                                "incomplete frame exceeds maximum of {max_frame_size} bytes"
+                            )));
+                        }
+
                         // EOF errors indicate that our buffer doesn't contain enough data to
                         // decode a whole CBOR frame. We're yielding no data item and re-try
                         // decoding in the next iteration.
@@ -112,6 +185,10 @@ where
                         )))
                     }
                 }
+                DecodeError::RecursionLimitExceeded => Err(SyncError::MessageTooLarge(format!(
+                    "frame exceeds maximum nesting depth of {}",
+                    self.limits.max_depth
+                ))),
                 err => Err(SyncError::InvalidEncoding(err.to_string())),
             },
         }
@@ -133,7 +210,19 @@ pub fn into_cbor_stream<'a, M>(
 where
     M: for<'de> Deserialize<'de> + Serialize + Send + 'a,
 {
-    FramedRead::new(rx.compat(), CborCodec::<M>::new())
+    into_cbor_stream_with_limits(rx, DecodeLimits::default())
+}
+
+/// Returns a reader for your data type like `into_cbor_stream`, rejecting frames which exceed the
+/// given limits.
+pub fn into_cbor_stream_with_limits<'a, M>(
+    rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+    limits: DecodeLimits,
+) -> impl Stream<Item = Result<M, SyncError>> + Send + Unpin + 'a
+where
+    M: for<'de> Deserialize<'de> + Serialize + Send + 'a,
+{
+    FramedRead::new(rx.compat(), CborCodec::<M>::with_limits(limits))
 }
 
 /// Returns a writer for your data type, automatically encoding it as CBOR for a framed
@@ -161,7 +250,9 @@ mod tests {
     use tokio_stream::StreamExt;
     use tokio_util::codec::FramedRead;
 
-    use super::CborCodec;
+    use crate::SyncError;
+
+    use super::{CborCodec, DecodeLimits};
 
     #[tokio::test]
     async fn decoding_exactly_one_frame() {
@@ -226,4 +317,31 @@ mod tests {
         let message = stream.next().await;
         assert_eq!(message, Some(Ok("hello".into())));
     }
+
+    #[tokio::test]
+    async fn decoding_too_large_frame() {
+        let (mut tx, rx) = tokio::io::duplex(64);
+        let limits = DecodeLimits::new().max_frame_size(4);
+        let mut stream = FramedRead::new(rx, CborCodec::<String>::with_limits(limits));
+
+        // CBOR header announcing a string with a length of 5 bytes, followed by the first bytes.
+        tx.write_all(&[101]).await.unwrap();
+        tx.write_all("hell".as_bytes()).await.unwrap();
+
+        let message = stream.next().await;
+        assert!(matches!(message, Some(Err(SyncError::MessageTooLarge(_)))));
+    }
+
+    #[tokio::test]
+    async fn decoding_too_deeply_nested_frame() {
+        let (mut tx, rx) = tokio::io::duplex(64);
+        let limits = DecodeLimits::new().max_depth(2);
+        let mut stream = FramedRead::new(rx, CborCodec::<Vec<Vec<Vec<u8>>>>::with_limits(limits));
+
+        // Three nested arrays with one element each: [[[0]]]
+        tx.write_all(&[0x81, 0x81, 0x81, 0x00]).await.unwrap();
+
+        let message = stream.next().await;
+        assert!(matches!(message, Some(Err(SyncError::MessageTooLarge(_)))));
+    }
 }
@@ -0,0 +1,253 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Length-prefixed framing of wire protocol messages for any serialization format.
+//!
+//! Every message is encoded with a `Format` and written as a frame, prefixed by the length of the
+//! encoded message as a 4-byte big-endian integer. This allows sync protocol implementations to
+//! choose their serialization format without re-implementing stream framing.
+//!
+//! Implementations for [JSON] (`json` feature) and [postcard] (`postcard` feature) are included,
+//! other formats can be supported by implementing the `Format` trait.
+//!
+//! [JSON]: https://www.json.org/
+//! [postcard]: https://postcard.jamesmunns.com/
+use std::marker::PhantomData;
+
+use futures::{AsyncRead, AsyncWrite, Sink, Stream};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio_util::bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder, FramedRead, FramedWrite};
+use tokio_util::compat::{FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt};
+
+use crate::SyncError;
+
+/// Number of bytes used for the length prefix of every frame.
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// Default maximum size of a single frame in bytes.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Serialization format of framed messages.
+pub trait Format {
+    /// Serializes a message into bytes.
+    fn encode<M: Serialize>(message: &M) -> Result<Vec<u8>, String>;
+
+    /// Deserializes a message from the bytes of exactly one frame.
+    fn decode<M: DeserializeOwned>(bytes: &[u8]) -> Result<M, String>;
+}
+
+/// Messages encoded as JSON.
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Format for Json {
+    fn encode<M: Serialize>(message: &M) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(message).map_err(|err| err.to_string())
+    }
+
+    fn decode<M: DeserializeOwned>(bytes: &[u8]) -> Result<M, String> {
+        serde_json::from_slice(bytes).map_err(|err| err.to_string())
+    }
+}
+
+/// Messages encoded with postcard.
+#[cfg(feature = "postcard")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Format for Postcard {
+    fn encode<M: Serialize>(message: &M) -> Result<Vec<u8>, String> {
+        postcard::to_allocvec(message).map_err(|err| err.to_string())
+    }
+
+    fn decode<M: DeserializeOwned>(bytes: &[u8]) -> Result<M, String> {
+        postcard::from_bytes(bytes).map_err(|err| err.to_string())
+    }
+}
+
+/// Implementation of the tokio codec traits to encode- and decode length-prefixed frames in the
+/// given format.
+#[derive(Clone, Debug)]
+pub struct FramedCodec<F, M> {
+    max_frame_size: usize,
+    _phantom: PhantomData<(F, M)>,
+}
+
+impl<F, M> FramedCodec<F, M> {
+    pub fn new() -> Self {
+        Self::with_max_frame_size(MAX_FRAME_SIZE)
+    }
+
+    /// Returns a codec rejecting frames larger than the given number of bytes.
+    pub fn with_max_frame_size(max_frame_size: usize) -> Self {
+        Self {
+            max_frame_size,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<F, M> Default for FramedCodec<F, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F, M> Encoder<M> for FramedCodec<F, M>
+where
+    F: Format,
+    M: Serialize,
+{
+    type Error = SyncError;
+
+    /// Encodes a message and adds it as a length-prefixed frame to the buffer.
+    fn encode(&mut self, item: M, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let bytes = F::encode(&item).map_err(|err| {
+            // When we've failed encoding our _own_ messages something seriously went wrong.
+            SyncError::Critical(format!("framed codec failed encoding message, {err}"))
+        })?;
+
+        if bytes.len() > self.max_frame_size {
+            return Err(SyncError::Critical(format!(
+                "encoded message of {} bytes exceeds maximum frame size",
+                bytes.len()
+            )));
+        }
+
+        dst.reserve(LENGTH_PREFIX_SIZE + bytes.len());
+        dst.put_u32(bytes.len() as u32);
+        dst.extend_from_slice(&bytes);
+        Ok(())
+    }
+}
+
+impl<F, M> Decoder for FramedCodec<F, M>
+where
+    F: Format,
+    M: DeserializeOwned,
+{
+    type Item = M;
+    type Error = SyncError;
+
+    /// Decodes the next frame from the buffer, if it contains enough bytes.
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < LENGTH_PREFIX_SIZE {
+            return Ok(None);
+        }
+
+        let mut length = [0u8; LENGTH_PREFIX_SIZE];
+        length.copy_from_slice(&src[..LENGTH_PREFIX_SIZE]);
+        let length = u32::from_be_bytes(length) as usize;
+
+        // Reject too large frames before buffering them.
+        if length > self.max_frame_size {
+            return Err(SyncError::MessageTooLarge(format!(
+                "frame of {length} bytes exceeds maximum of {} bytes",
+                self.max_frame_size
+            )));
+        }
+
+        if src.len() < LENGTH_PREFIX_SIZE + length {
+            src.reserve(LENGTH_PREFIX_SIZE + length - src.len());
+            return Ok(None);
+        }
+
+        src.advance(LENGTH_PREFIX_SIZE);
+        let bytes = src.split_to(length);
+        let item = F::decode(&bytes).map_err(SyncError::InvalidEncoding)?;
+        Ok(Some(item))
+    }
+}
+
+/// Returns a reader for your data type, decoding length-prefixed frames in the given format.
+///
+/// This is the equivalent of `into_cbor_stream` for other serialization formats.
+pub fn into_framed_stream<'a, F, M>(
+    rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+    max_frame_size: usize,
+) -> impl Stream<Item = Result<M, SyncError>> + Send + Unpin + 'a
+where
+    F: Format + Send + 'a,
+    M: DeserializeOwned + Send + 'a,
+{
+    FramedRead::new(
+        rx.compat(),
+        FramedCodec::<F, M>::with_max_frame_size(max_frame_size),
+    )
+}
+
+/// Returns a writer for your data type, encoding it as length-prefixed frames in the given
+/// format.
+///
+/// This is the equivalent of `into_cbor_sink` for other serialization formats.
+pub fn into_framed_sink<'a, F, M>(
+    tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
+) -> impl Sink<M, Error = SyncError> + Send + Unpin + 'a
+where
+    F: Format + Send + 'a,
+    M: Serialize + Send + 'a,
+{
+    FramedWrite::new(tx.compat_write(), FramedCodec::<F, M>::new())
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use futures::{FutureExt, SinkExt};
+    use serde::{Deserialize, Serialize};
+    use tokio::io::AsyncWriteExt;
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    use crate::SyncError;
+
+    use super::{FramedCodec, Json};
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Message {
+        Have(Vec<u64>),
+        Done,
+    }
+
+    #[tokio::test]
+    async fn encode_decode_frames() {
+        let (tx, rx) = tokio::io::duplex(64);
+        let mut sink = FramedWrite::new(tx, FramedCodec::<Json, Message>::new());
+        let mut stream = FramedRead::new(rx, FramedCodec::<Json, Message>::new());
+
+        sink.send(Message::Have(vec![1, 2, 3])).await.unwrap();
+        sink.send(Message::Done).await.unwrap();
+
+        assert_eq!(stream.next().await, Some(Ok(Message::Have(vec![1, 2, 3]))));
+        assert_eq!(stream.next().await, Some(Ok(Message::Done)));
+    }
+
+    #[tokio::test]
+    async fn decoding_incomplete_frame() {
+        let (mut tx, rx) = tokio::io::duplex(64);
+        let mut stream = FramedRead::new(rx, FramedCodec::<Json, String>::new());
+
+        // Length prefix announcing a frame of 7 bytes, followed by the first bytes.
+        tx.write_all(&[0, 0, 0, 7]).await.unwrap();
+        tx.write_all(b"\"hel").await.unwrap();
+        assert!(stream.next().now_or_never().is_none());
+
+        tx.write_all(b"lo\"").await.unwrap();
+        assert_eq!(stream.next().await, Some(Ok("hello".to_string())));
+    }
+
+    #[tokio::test]
+    async fn decoding_too_large_frame() {
+        let (mut tx, rx) = tokio::io::duplex(64);
+        let mut stream = FramedRead::new(rx, FramedCodec::<Json, String>::with_max_frame_size(4));
+
+        tx.write_all(&[0, 0, 0, 7]).await.unwrap();
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(SyncError::MessageTooLarge(_)))
+        ));
+    }
+}
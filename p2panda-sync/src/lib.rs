@@ -15,13 +15,17 @@
 //! In addition to the generic definition of the `SyncProtocol` trait, `p2panda-sync` includes
 //! optional implementations for efficient sync of append-only log-based data types. These optional
 //! implementations may be activated via feature flags. Finally, `p2panda-sync` provides helpers to
-//! encode wire messages in CBOR.
+//! encode wire messages in CBOR, or in any other format with length-prefixed framing.
 #[cfg(feature = "cbor")]
 pub mod cbor;
+#[cfg(feature = "framed")]
+pub mod framed;
 #[cfg(feature = "log-sync")]
 pub mod log_sync;
 #[cfg(feature = "test-protocols")]
 pub mod test_protocols;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
 
 use std::fmt::Debug;
 use std::hash::Hash;
@@ -129,6 +133,90 @@ where
         app_tx: Box<&'a mut (dyn Sink<FromSync<T>, Error = SyncError> + Send + Unpin)>,
     ) -> Result<(), SyncError>;
 
+    /// Initiate a sync protocol session for the given topic query, asking the remote peer to only
+    /// send data matching the given filter.
+    ///
+    /// Filters allow "light" clients to not download the full history of a topic. They are
+    /// transmitted to the remote peer during the "Handshake" phase.
+    ///
+    /// Protocols which don't support filters fall back to a regular, unfiltered session by
+    /// default.
+    async fn initiate_with_filter(
+        self: Arc<Self>,
+        topic_query: T,
+        filter: SyncFilter,
+        tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
+        rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+        app_tx: Box<&'a mut (dyn Sink<FromSync<T>, Error = SyncError> + Send + Unpin)>,
+    ) -> Result<(), SyncError> {
+        let _ = filter;
+        self.initiate(topic_query, tx, rx, app_tx).await
+    }
+
+    /// Returns `true` if this protocol can sync multiple topic queries in a single session.
+    ///
+    /// Protocols supporting batch sessions implement `initiate_batch` and handle batch requests
+    /// when accepting sessions.
+    fn supports_batch(&self) -> bool {
+        false
+    }
+
+    /// Returns `true` if this protocol sends the most recent data first.
+    ///
+    /// Gossip messages carry the most recent data as well, `p2panda-net` delivers them right away
+    /// during sync sessions with such protocols instead of holding them back until the session
+    /// finished.
+    fn newest_first(&self) -> bool {
+        false
+    }
+
+    /// Initiate a sync protocol session for multiple topic queries at once.
+    ///
+    /// Opening a new stream and running a handshake for every topic is wasteful when two peers
+    /// share many topics. Batch sessions negotiate all topic queries during one "Handshake"
+    /// phase. Implementations for `p2panda-net` are required to send a `SyncFrom::HandshakeSuccess`
+    /// message for every accepted topic query and forward synced data via the `SyncFrom::TopicData`
+    /// message, tagged with the topic query it belongs to.
+    ///
+    /// Protocols which don't support batch sessions return an `UnsupportedProtocol` error by
+    /// default.
+    async fn initiate_batch(
+        self: Arc<Self>,
+        topic_queries: Vec<T>,
+        tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
+        rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+        app_tx: Box<&'a mut (dyn Sink<FromSync<T>, Error = SyncError> + Send + Unpin)>,
+    ) -> Result<(), SyncError> {
+        let _ = (topic_queries, tx, rx, app_tx);
+        Err(SyncError::UnsupportedProtocol(format!(
+            "{} does not support batch sessions",
+            self.name()
+        )))
+    }
+
+    /// Estimate the data the remote peer would send us during a sync session for the given topic
+    /// query, without transferring it.
+    ///
+    /// This "dry run" only exchanges the information needed to calculate the difference between
+    /// both peers, for example log heights. No data is forwarded to the application layer.
+    ///
+    /// Protocols which don't support estimates return an `UnsupportedProtocol` error by default.
+    async fn estimate(
+        self: Arc<Self>,
+        topic_query: T,
+        tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
+        rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+    ) -> Result<SyncEstimate, SyncError>
+    where
+        T: 'a,
+    {
+        let _ = (topic_query, tx, rx);
+        Err(SyncError::UnsupportedProtocol(format!(
+            "{} does not support sync estimates",
+            self.name()
+        )))
+    }
+
     /// Accept a sync protocol session over the provided bi-directional stream.
     ///
     /// During the "Handshake" phase the "acceptor" usually responds to the access request and
@@ -151,11 +239,26 @@ where
         rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
         app_tx: Box<&'a mut (dyn Sink<FromSync<T>, Error = SyncError> + Send + Unpin)>,
     ) -> Result<(), SyncError>;
+
+    /// Accept a sync protocol session with a peer which is exempt from quotas.
+    ///
+    /// Applications exempt trusted peers from the limits they apply to other peers. Protocols
+    /// limiting what they serve per session, for example the number of entries, lift these
+    /// limits here. Falls back to a regular session by default.
+    async fn accept_exempt(
+        self: Arc<Self>,
+        tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
+        rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+        app_tx: Box<&'a mut (dyn Sink<FromSync<T>, Error = SyncError> + Send + Unpin)>,
+    ) -> Result<(), SyncError> {
+        self.accept(tx, rx, app_tx).await
+    }
 }
 
 /// Messages which can be sent to the higher application layers (for further validation or
 /// persistance) and the underlying transport layer (for managing the sync session).
 #[derive(Debug, PartialEq)]
+#[non_exhaustive]
 pub enum FromSync<T>
 where
     T: TopicQuery,
@@ -193,14 +296,123 @@ where
         /// types in the `header` field.
         payload: Option<Vec<u8>>,
     },
+
+    /// Application data we've received during a batch sync session, tagged with the topic query
+    /// it belongs to.
+    ///
+    /// Batch sessions exchange data for multiple topic queries over the same stream, see
+    /// `SyncProtocol::initiate_batch`.
+    TopicData {
+        /// Topic query this data was synced for.
+        topic: T,
+
+        /// Exchanged data from sync session.
+        header: Vec<u8>,
+
+        /// Optional "body" which can represent "off-chain" application data.
+        payload: Option<Vec<u8>>,
+    },
+}
+
+/// Predicates to narrow down the data a remote peer sends us during a sync session.
+///
+/// All given predicates need to match for data to be sent. An empty filter matches everything.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SyncFilter {
+    /// Only send data created at or after this timestamp.
+    ///
+    /// Please note that this can lead to receiving logs without their earlier entries, the
+    /// receiving end needs to be able to handle these "gaps", for example by treating them as
+    /// pruned.
+    pub since: Option<u64>,
+
+    /// Only send data of these authors, identified by the bytes of their public keys.
+    pub authors: Option<Vec<[u8; 32]>>,
+}
+
+impl SyncFilter {
+    /// Returns an empty filter, matching everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match data created at or after the given timestamp.
+    pub fn since(mut self, timestamp: u64) -> Self {
+        self.since = Some(timestamp);
+        self
+    }
+
+    /// Only match data of the given authors.
+    pub fn authors(mut self, authors: Vec<[u8; 32]>) -> Self {
+        self.authors = Some(authors);
+        self
+    }
+
+    /// Returns true if no predicates are set.
+    pub fn is_empty(&self) -> bool {
+        self.since.is_none() && self.authors.is_none()
+    }
+
+    /// Returns true if data of this author matches the filter.
+    pub fn matches_author(&self, author: &[u8; 32]) -> bool {
+        self.authors
+            .as_ref()
+            .is_none_or(|authors| authors.contains(author))
+    }
+
+    /// Returns true if data created at this timestamp matches the filter.
+    pub fn matches_timestamp(&self, timestamp: u64) -> bool {
+        self.since.is_none_or(|since| timestamp >= since)
+    }
+    /// Returns a filter matching all data matched by either of both filters.
+    ///
+    /// Not every combination of predicates can be expressed by a single filter, the returned
+    /// filter can match more data than both filters together.
+    pub fn union(&self, other: &SyncFilter) -> SyncFilter {
+        let since = self.since.zip(other.since).map(|(a, b)| a.min(b));
+        let authors = self
+            .authors
+            .as_ref()
+            .zip(other.authors.as_ref())
+            .map(|(a, b)| {
+                let mut authors = a.clone();
+                for author in b {
+                    if !authors.contains(author) {
+                        authors.push(*author);
+                    }
+                }
+                authors
+            });
+        SyncFilter { since, authors }
+    }
+}
+
+/// Estimated amount of data a sync session would transfer, without transferring it.
+///
+/// Applications can use estimates to ask users before large downloads, for example on metered
+/// connections.
+///
+/// Estimates are not necessarily exact, protocols can approximate them without loading the data
+/// they would send.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncEstimate {
+    /// Number of operations the remote peer would send us.
+    pub operations: u64,
+
+    /// Number of header and payload bytes of these operations.
+    pub bytes: u64,
 }
 
 /// Errors which can occur during sync sessions.
 ///
 /// 1. Critical system failures (ie. bug in p2panda code or sync implementation, sync
 ///    implementation did not follow "2. Phase Flow" requirements, lack of system resources, etc.)
-/// 2. Unexpected Behaviour (ie. remote peer abruptly disconnected, error which got correctly
-///    caught in sync implementation, etc.)
+/// 2. Unexpected Behaviour (ie. error which got correctly caught in sync implementation, etc.)
+/// 3. Disconnects, timeouts, storage failures, invalid data or unsupported protocols, which are
+///    reported with their own variants.
+///
+/// Use `is_transient` to decide if a failed session should be re-attempted and `is_remote_fault`
+/// to decide if the remote peer should be de-prioritised.
 #[derive(Debug, PartialEq, Error)]
 pub enum SyncError {
     /// Error due to unexpected (buggy or malicious) behaviour of the remote peer.
@@ -213,6 +425,13 @@ pub enum SyncError {
     #[error("sync session failed due to unexpected protocol behaviour of remote peer: {0}")]
     UnexpectedBehaviour(String),
 
+    /// The remote peer closed the connection or stream before the session was completed.
+    ///
+    /// Peers go offline or lose connectivity all the time, this is not considered a fault of the
+    /// remote peer.
+    #[error("sync session failed as remote peer disconnected: {0}")]
+    Disconnected(String),
+
     /// Error due to invalid encoding of a message sent by remote peer.
     ///
     /// Note that this error is intended for receiving messages from _remote_ peers which we can't
@@ -221,6 +440,26 @@ pub enum SyncError {
     #[error("sync session failed due to invalid encoding of message sent by remote peer: {0}")]
     InvalidEncoding(String),
 
+    /// Data sent by the remote peer failed validation, for example due to an invalid signature.
+    #[error("sync session failed due to invalid data sent by remote peer: {0}")]
+    Validation(String),
+
+    /// The remote peer did not respond in time.
+    #[error("sync session timed out: {0}")]
+    Timeout(String),
+
+    /// Reading from or writing to our local store failed.
+    #[error("sync session failed due to storage error: {0}")]
+    Storage(String),
+
+    /// Both peers do not support a common sync protocol.
+    #[error("sync session failed due to unsupported protocol: {0}")]
+    UnsupportedProtocol(String),
+
+    /// Message sent by the remote peer exceeds the configured size or nesting limits.
+    #[error("sync session failed due to too large message sent by remote peer: {0}")]
+    MessageTooLarge(String),
+
     /// Critical error due to system failure on our end.
     ///
     /// This indicates that our system is running out of resources (storage layer failure etc.) or
@@ -229,6 +468,34 @@ pub enum SyncError {
     Critical(String),
 }
 
+impl SyncError {
+    /// Returns `true` if the failure is likely temporary and the session can be re-attempted.
+    ///
+    /// Unexpected behaviour is re-attempted as well, the remote peer might have been interrupted
+    /// by a bug on its end. Use `is_remote_fault` to de-prioritise such peers.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::UnexpectedBehaviour(_)
+                | Self::Disconnected(_)
+                | Self::Timeout(_)
+                | Self::Storage(_)
+        )
+    }
+
+    /// Returns `true` if the failure was caused by the remote peer not following the protocol or
+    /// sending invalid data.
+    pub fn is_remote_fault(&self) -> bool {
+        matches!(
+            self,
+            Self::UnexpectedBehaviour(_)
+                | Self::InvalidEncoding(_)
+                | Self::Validation(_)
+                | Self::MessageTooLarge(_)
+        )
+    }
+}
+
 /// Converts critical I/O error (which occurs during codec stream handling) into [`SyncError`].
 ///
 /// This is usually a critical system failure indicating an implementation bug or lacking resources
@@ -239,10 +506,14 @@ pub enum SyncError {
 impl From<std::io::Error> for SyncError {
     fn from(err: std::io::Error) -> Self {
         match err.kind() {
-            // Broken pipes usually indicate that the remote peer closed the connection
-            // unexpectedly, this is why we're not treating it as a critical error but as
-            // "unexpected behaviour" instead.
-            std::io::ErrorKind::BrokenPipe => Self::UnexpectedBehaviour("broken pipe".into()),
+            // Broken pipes and resets usually indicate that the remote peer closed the connection
+            // unexpectedly, this is why we're not treating them as critical errors.
+            std::io::ErrorKind::BrokenPipe => Self::Disconnected("broken pipe".into()),
+            std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::NotConnected
+            | std::io::ErrorKind::UnexpectedEof => Self::Disconnected(err.to_string()),
+            std::io::ErrorKind::TimedOut => Self::Timeout(err.to_string()),
             _ => Self::Critical(format!("internal i/o stream error {err}")),
         }
     }
@@ -271,3 +542,62 @@ pub trait TopicQuery:
     Clone + Debug + Eq + Hash + Send + Sync + Serialize + for<'a> Deserialize<'a>
 {
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{SyncError, SyncFilter};
+
+    #[test]
+    fn transient_errors() {
+        assert!(SyncError::Disconnected("broken pipe".into()).is_transient());
+        assert!(SyncError::Timeout("idle".into()).is_transient());
+        assert!(SyncError::Storage("busy".into()).is_transient());
+        assert!(SyncError::UnexpectedBehaviour("bang".into()).is_transient());
+
+        assert!(!SyncError::Validation("invalid signature".into()).is_transient());
+        assert!(!SyncError::InvalidEncoding("garbage".into()).is_transient());
+        assert!(!SyncError::UnsupportedProtocol("none".into()).is_transient());
+        assert!(!SyncError::MessageTooLarge("too large".into()).is_transient());
+        assert!(!SyncError::Critical("bug".into()).is_transient());
+    }
+
+    #[test]
+    fn remote_faults() {
+        assert!(SyncError::UnexpectedBehaviour("bang".into()).is_remote_fault());
+        assert!(SyncError::Validation("invalid signature".into()).is_remote_fault());
+        assert!(SyncError::InvalidEncoding("garbage".into()).is_remote_fault());
+        assert!(SyncError::MessageTooLarge("too large".into()).is_remote_fault());
+
+        assert!(!SyncError::Disconnected("broken pipe".into()).is_remote_fault());
+        assert!(!SyncError::Timeout("idle".into()).is_remote_fault());
+        assert!(!SyncError::Critical("bug".into()).is_remote_fault());
+    }
+
+    #[test]
+    fn disconnects_from_io_errors() {
+        let err = SyncError::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+        assert_eq!(err, SyncError::Disconnected("broken pipe".into()));
+
+        let err = SyncError::from(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        assert!(matches!(err, SyncError::Disconnected(_)));
+
+        let err = SyncError::from(std::io::Error::from(std::io::ErrorKind::OutOfMemory));
+        assert!(matches!(err, SyncError::Critical(_)));
+    }
+    #[test]
+    fn filter_union() {
+        let a = SyncFilter::new().since(20).authors(vec![[1; 32]]);
+        let b = SyncFilter::new().since(10).authors(vec![[1; 32], [2; 32]]);
+        assert_eq!(
+            a.union(&b),
+            SyncFilter::new().since(10).authors(vec![[1; 32], [2; 32]])
+        );
+
+        // Unset predicates match everything.
+        assert_eq!(
+            a.union(&SyncFilter::new().since(30)),
+            SyncFilter::new().since(20)
+        );
+        assert!(a.union(&SyncFilter::new()).is_empty());
+    }
+}
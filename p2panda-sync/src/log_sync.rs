@@ -17,19 +17,68 @@
 //!
 //! To find out which logs to send matching the given "topic query" a `TopicLogMap` is provided. This
 //! interface aids the sync protocol in deciding which logs to transfer for each given topic.
-use std::collections::HashMap;
+//!
+//! The initiating peer can optionally send a `SyncFilter` in a "Filter" message right before their
+//! "Have" message. The accepting peer will then only send entries matching that filter, which
+//! allows light clients to not download the full history of a topic.
+//!
+//! Sessions can optionally be kept open in "live mode" after both peers caught up. The initiating
+//! peer requests this with a "Live" message right before their "Have" message. If the accepting
+//! peer has live mode enabled it keeps polling its store and sends new entries to the initiating
+//! peer until the session is closed, otherwise it ends the session with another "Done" message.
+//! This is an alternative to gossip overlays for topologies with only a few peers, for example a
+//! client connected to an always-on home server.
+//!
+//! Peers can act as relays for data of authors which are not part of their own topic mapping,
+//! for example when the author is offline. In "relay mode" the initiating peer lists all logs it
+//! associates with the topic in a "Relay" message right before their "Have" message, and an
+//! accepting peer in relay mode also sends entries of these logs if they're present in its store.
+//! Since these entries were not necessarily authored by the remote peer, the initiating peer
+//! verifies the signature and payload of every entry received in relay mode.
+//!
+//! Applications often want recent data first, for example to load a chat history backwards. The
+//! initiating peer can request entries to be delivered newest-first with an "Order" message right
+//! before their "Have" message, both peers then send their entries sorted by timestamp, most
+//! recent first. Logs arrive in reversed order in that case, receivers need to buffer entries
+//! until their predecessors arrived, for example with the out-of-order buffer of
+//! `p2panda-stream`.
+//!
+//! Peers sharing many topics can sync all of them in a single "batch" session. The initiating
+//! peer sends one "Batch" message listing the log heights of every topic query instead of a
+//! "Have" message. The accepting peer consults its topic mapping for each of them and announces
+//! every topic with a "Topic" message, followed by the data of that topic. Both peers then
+//! exchange roles as in regular sessions. Live and relay mode are not available in batch sessions.
+//!
+//! Applications can estimate how much data a sync session would transfer before running it, for
+//! example to ask users before large downloads on metered connections. In such a "dry run" the
+//! initiating peer sends a "DryRun" message with its log heights instead of a "Have" message, the
+//! accepting peer answers with an "Estimate" of the number of entries and bytes the initiating
+//! peer is missing and the session ends without transferring any entries. Estimates are
+//! calculated from the log heads, the accepting peer doesn't load any entries for them.
+//!
+//! Entries are sent with the payload which is present in the store. When payloads were deleted,
+//! for example by a tombstone (see `TombstoneFlag` in `p2panda-core`), only the header is sent
+//! and the log integrity can still be verified by the receiving peer.
+//!
+//! Local log heights are taken from the log heads maintained by the store (see
+//! `LogStore::log_heads`), they are looked up once per log id instead of once per author's log.
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use futures::{AsyncRead, AsyncWrite, Sink, SinkExt, StreamExt, stream};
-use p2panda_core::{Extensions, PublicKey};
-use p2panda_store::{LogId, LogStore};
+use p2panda_core::cbor::decode_cbor;
+use p2panda_core::{Body, Extensions, Header, Operation, PublicKey, validate_operation};
+use p2panda_store::{LogHead, LogId, LogStore};
 use serde::{Deserialize, Serialize};
+use tokio::time::timeout;
 
-use crate::cbor::{into_cbor_sink, into_cbor_stream};
-use crate::{FromSync, SyncError, SyncProtocol, TopicQuery};
+use crate::cbor::{DecodeLimits, into_cbor_sink, into_cbor_stream_with_limits};
+use crate::{FromSync, SyncError, SyncEstimate, SyncFilter, SyncProtocol, TopicQuery};
 
 type SeqNum = u64;
 
@@ -37,6 +86,9 @@ type LogHeights<T> = Vec<(T, SeqNum)>;
 
 type Logs<T> = HashMap<PublicKey, Vec<T>>;
 
+/// Log heights of all logs associated with a topic query, as sent in batch sessions.
+type TopicLogHeights<T, L> = (T, Vec<(PublicKey, LogHeights<L>)>);
+
 /// Maps a `TopicQuery` to the related logs being sent over the wire during sync.
 ///
 /// Each `SyncProtocol` implementation defines the type of data it is expecting to sync and how
@@ -87,6 +139,28 @@ enum Message<T, L = String> {
     Have(T, Vec<(PublicKey, LogHeights<L>)>),
     Data(Vec<u8>, Option<Vec<u8>>),
     Done,
+    Filter(SyncFilter),
+    Live,
+    Relay(Vec<(PublicKey, Vec<L>)>),
+    Order(DeliveryOrder),
+    Batch(Vec<TopicLogHeights<T, L>>),
+    Topic(T),
+    DryRun(T, Vec<(PublicKey, LogHeights<L>)>),
+    Estimate(SyncEstimate),
+}
+
+/// Order in which entries are sent during a sync session.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DeliveryOrder {
+    /// Entries are sent log by log, in ascending sequence number order.
+    #[default]
+    OldestFirst,
+
+    /// Entries are sent sorted by their timestamp, most recent first.
+    ///
+    /// Receivers get recent data earlier (for example to render the latest chat messages right
+    /// away), but need to handle logs arriving in reversed order.
+    NewestFirst,
 }
 
 /// Efficient sync protocol for append-only log data types.
@@ -94,6 +168,11 @@ enum Message<T, L = String> {
 pub struct LogSyncProtocol<TM, L, E, S: LogStore<L, E>> {
     topic_map: TM,
     store: S,
+    live: Option<Duration>,
+    relay: bool,
+    order: DeliveryOrder,
+    max_operations: Option<usize>,
+    limits: DecodeLimits,
     _marker: PhantomData<(L, E)>,
 }
 
@@ -107,9 +186,68 @@ where
         Self {
             topic_map,
             store,
+            live: None,
+            relay: false,
+            order: DeliveryOrder::default(),
+            max_operations: None,
+            limits: DecodeLimits::default(),
             _marker: PhantomData {},
         }
     }
+
+    /// Enables "live mode" for sync sessions.
+    ///
+    /// As an initiator we ask the remote peer to keep the session open after both peers caught up
+    /// and to continue sending new entries. As an acceptor we honour such requests by checking
+    /// the store for new entries in the given interval.
+    pub fn live_mode(mut self, poll_interval: Duration) -> Self {
+        self.live = Some(poll_interval);
+        self
+    }
+
+    /// Enables "relay mode" for sync sessions.
+    ///
+    /// As an initiator we ask the remote peer for all logs associated with the topic query, even
+    /// if they're not part of the remote's topic mapping, and verify every received entry. As an
+    /// acceptor we send entries of all requested logs present in our store.
+    ///
+    /// Acceptors in relay mode can be asked for any log in their store, it should only be enabled
+    /// if all data in the store can be shared with every peer it syncs with.
+    pub fn relay_mode(mut self) -> Self {
+        self.relay = true;
+        self
+    }
+
+    /// Requests entries to be delivered in the given order.
+    ///
+    /// As an initiator we ask the remote peer to send entries in this order and send our own
+    /// entries in the same order. Acceptors always follow the order requested by the initiator.
+    ///
+    /// Requesting an order other than `DeliveryOrder::OldestFirst` is not understood by peers
+    /// which don't support delivery orders. `p2panda-net` stops holding back gossip messages
+    /// during sync sessions when data is delivered newest-first.
+    pub fn delivery_order(mut self, order: DeliveryOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Limits the number of operations we send as an acceptor during a single session.
+    ///
+    /// Only the oldest missing entries of every log up to the limit are sent, remote peers catch
+    /// up on the following ones in later sessions as their log heights advance. Combined with
+    /// newest-first delivery, the entries sent during a session are still ordered newest-first.
+    ///
+    /// Peers exempt from quotas are not limited, see `SyncProtocol::accept_exempt`.
+    pub fn max_operations_per_session(mut self, operations: usize) -> Self {
+        self.max_operations = Some(operations);
+        self
+    }
+
+    /// Rejects messages from the remote peer which exceed the given limits.
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
 }
 
 // Bidirectional log sync protocol.
@@ -118,12 +256,39 @@ where
 //
 // [ Initiator ]        [ Acceptor ]
 // -------------        ------------
+//     filter ->        -> filter (optional)
+//       live ->        -> live (optional)
+//      relay ->        -> relay (optional)
+//      order ->        -> order (optional)
 //       have ->        -> have
 //       data <-        <- data
 //       done <-        <- done
 //       have <-        <- have
 //       data ->        -> data
 //       done ->        -> done
+//       data <-        <- data (live mode, repeated until the session is closed)
+//
+// Dry runs only estimate the data the initiator is missing.
+//
+// [ Initiator ]        [ Acceptor ]
+// -------------        ------------
+//    dry run ->        -> dry run
+//   estimate <-        <- estimate
+//
+// Batch sessions exchange multiple topic queries at once, data of every topic is preceded by a
+// "topic" message.
+//
+// [ Initiator ]        [ Acceptor ]
+// -------------        ------------
+//      order ->        -> order (optional)
+//      batch ->        -> batch
+//      topic <-        <- topic (for every topic query, followed by its data)
+//       data <-        <- data
+//       done <-        <- done
+//      batch <-        <- batch
+//      topic ->        -> topic
+//       data ->        -> data
+//       done ->        -> done
 //
 #[async_trait]
 impl<'a, T, TM, L, E, S> SyncProtocol<T, 'a> for LogSyncProtocol<TM, L, E, S>
@@ -143,18 +308,57 @@ where
         topic_query: T,
         tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
         rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+        app_tx: Box<&'a mut (dyn Sink<FromSync<T>, Error = SyncError> + Send + Unpin)>,
+    ) -> Result<(), SyncError> {
+        self.initiate_with_filter(topic_query, SyncFilter::default(), tx, rx, app_tx)
+            .await
+    }
+
+    async fn initiate_with_filter(
+        self: Arc<Self>,
+        topic_query: T,
+        filter: SyncFilter,
+        tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
+        rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
         mut app_tx: Box<&'a mut (dyn Sink<FromSync<T>, Error = SyncError> + Send + Unpin)>,
     ) -> Result<(), SyncError> {
         let mut sync_done_received = false;
         let mut sync_done_sent = false;
 
         let mut sink = into_cbor_sink(tx);
-        let mut stream = into_cbor_stream(rx);
+        let mut stream = into_cbor_stream_with_limits(rx, self.limits);
 
         // Retrieve the local log heights for all logs matching the topic query.
         let local_log_heights =
             local_log_heights(&self.store, &self.topic_map, &topic_query).await?;
 
+        // Ask the remote peer to only send us data matching the filter. Empty filters are not
+        // sent to stay compatible with peers which don't support filters.
+        if !filter.is_empty() {
+            sink.send(Message::<T, L>::Filter(filter)).await?;
+        }
+
+        // Ask the remote peer to keep the session open and send us new data after we've caught
+        // up.
+        let live = self.live.is_some();
+        if live {
+            sink.send(Message::<T, L>::Live).await?;
+        }
+
+        // Ask the remote peer to also send us entries of logs it doesn't associate with the topic
+        // query itself.
+        if self.relay {
+            let logs = self.topic_map.get(&topic_query).await.unwrap_or_default();
+            sink.send(Message::<T, L>::Relay(logs.into_iter().collect()))
+                .await?;
+        }
+
+        // Ask the remote peer to send entries in a different order. The default order is not sent
+        // to stay compatible with peers which don't support delivery orders.
+        if self.order != DeliveryOrder::default() {
+            sink.send(Message::<T, L>::Order(self.order)).await?;
+        }
+
         // Send our `Have` message to the remote peer.
         sink.send(Message::<T, L>::Have(
             topic_query.clone(),
@@ -173,12 +377,58 @@ where
 
             match message {
                 Message::Data(header, payload) => {
+                    // Entries received in relay mode might be authored by someone else than the
+                    // remote peer, make sure they're authentic before passing them on.
+                    if self.relay {
+                        validate_relayed::<E>(&header, payload.as_ref())?;
+                    }
+
                     // Forward data received from the remote to the app layer.
                     app_tx.send(FromSync::Data { header, payload }).await?;
                 }
                 Message::Done => {
+                    // In live mode the remote peer signals with a second "done" message that it
+                    // will not send any more data.
+                    if sync_done_received {
+                        break;
+                    }
                     sync_done_received = true;
                 }
+                Message::Filter(_) => {
+                    return Err(SyncError::UnexpectedBehaviour(
+                        "unexpected \"filter\" message received".to_string(),
+                    ));
+                }
+                Message::Live => {
+                    return Err(SyncError::UnexpectedBehaviour(
+                        "unexpected \"live\" message received".to_string(),
+                    ));
+                }
+                Message::Relay(_) => {
+                    return Err(SyncError::UnexpectedBehaviour(
+                        "unexpected \"relay\" message received".to_string(),
+                    ));
+                }
+                Message::Order(_) => {
+                    return Err(SyncError::UnexpectedBehaviour(
+                        "unexpected \"order\" message received".to_string(),
+                    ));
+                }
+                Message::Batch(_) => {
+                    return Err(SyncError::UnexpectedBehaviour(
+                        "unexpected \"batch\" message received".to_string(),
+                    ));
+                }
+                Message::Topic(_) => {
+                    return Err(SyncError::UnexpectedBehaviour(
+                        "unexpected \"topic\" message received".to_string(),
+                    ));
+                }
+                Message::DryRun(..) | Message::Estimate(_) => {
+                    return Err(SyncError::UnexpectedBehaviour(
+                        "unexpected \"dry run\" message received".to_string(),
+                    ));
+                }
                 Message::Have(remote_topic_query, remote_log_heights) => {
                     if !sync_done_received {
                         return Err(SyncError::UnexpectedBehaviour(
@@ -204,9 +454,15 @@ where
                         remote_log_heights.clone().into_iter().collect();
 
                     // Retrieve and send all messages needed by the remote peer.
-                    let messages: Vec<Message<T, L>> =
-                        messages_needed_by_remote(&self.store, &logs, remote_log_heights_map)
-                            .await?;
+                    let messages: Vec<Message<T, L>> = messages_needed_by_remote(
+                        &self.store,
+                        &logs,
+                        remote_log_heights_map,
+                        &SyncFilter::default(),
+                        self.order,
+                        None,
+                    )
+                    .await?;
                     sink.send_all(&mut stream::iter(messages.into_iter().map(Ok)))
                         .await?;
 
@@ -216,7 +472,8 @@ where
                 }
             };
 
-            if sync_done_received && sync_done_sent {
+            // In live mode we keep receiving data until the remote peer closes the session.
+            if sync_done_received && sync_done_sent && !live {
                 break;
             }
         }
@@ -228,22 +485,307 @@ where
         Ok(())
     }
 
-    async fn accept(
+    async fn estimate(
+        self: Arc<Self>,
+        topic_query: T,
+        tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
+        rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+    ) -> Result<SyncEstimate, SyncError>
+    where
+        T: 'a,
+    {
+        let mut sink = into_cbor_sink(tx);
+        let mut stream = into_cbor_stream_with_limits(rx, self.limits);
+
+        // Retrieve the local log heights for all logs matching the topic query.
+        let local_log_heights =
+            local_log_heights(&self.store, &self.topic_map, &topic_query).await?;
+
+        // Ask the remote peer for an estimate instead of the data.
+        sink.send(Message::<T, L>::DryRun(topic_query, local_log_heights))
+            .await?;
+        sink.flush().await?;
+
+        let message: Option<Result<Message<T, L>, SyncError>> = stream.next().await;
+        match message {
+            Some(result) => match result? {
+                Message::Estimate(estimate) => Ok(estimate),
+                _ => Err(SyncError::UnexpectedBehaviour(
+                    "expected \"estimate\" message".to_string(),
+                )),
+            },
+            None => Err(SyncError::Disconnected(
+                "remote peer closed session before sending estimate".to_string(),
+            )),
+        }
+    }
+
+    fn supports_batch(&self) -> bool {
+        true
+    }
+
+    fn newest_first(&self) -> bool {
+        self.order == DeliveryOrder::NewestFirst
+    }
+
+    async fn initiate_batch(
         self: Arc<Self>,
+        topic_queries: Vec<T>,
         tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
         rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
         mut app_tx: Box<&'a mut (dyn Sink<FromSync<T>, Error = SyncError> + Send + Unpin)>,
     ) -> Result<(), SyncError> {
-        let mut sync_done_sent = false;
         let mut sync_done_received = false;
+        let mut sync_done_sent = false;
+        let mut current_topic: Option<T> = None;
 
         let mut sink = into_cbor_sink(tx);
-        let mut stream = into_cbor_stream(rx);
+        let mut stream = into_cbor_stream_with_limits(rx, self.limits);
+
+        // Retrieve the local log heights for all logs matching each topic query.
+        let mut batch = Vec::with_capacity(topic_queries.len());
+        for topic_query in &topic_queries {
+            let local_log_heights =
+                local_log_heights(&self.store, &self.topic_map, topic_query).await?;
+            batch.push((topic_query.clone(), local_log_heights));
+        }
+
+        // Ask the remote peer to send entries in a different order.
+        if self.order != DeliveryOrder::default() {
+            sink.send(Message::<T, L>::Order(self.order)).await?;
+        }
+
+        // Send our `Batch` message to the remote peer.
+        sink.send(Message::<T, L>::Batch(batch)).await?;
+
+        // Announce all topic queries of the sync session to the app layer.
+        for topic_query in &topic_queries {
+            app_tx
+                .send(FromSync::HandshakeSuccess(topic_query.clone()))
+                .await?;
+        }
+
+        // Consume messages arriving on the receive stream.
+        while let Some(result) = stream.next().await {
+            let message: Message<T, L> = result?;
+
+            match message {
+                Message::Topic(topic_query) => {
+                    if sync_done_received || !topic_queries.contains(&topic_query) {
+                        return Err(SyncError::UnexpectedBehaviour(format!(
+                            "unexpected topic query {topic_query:?} received from remote peer"
+                        )));
+                    }
+                    current_topic = Some(topic_query);
+                }
+                Message::Data(header, payload) => {
+                    // Data needs to be tagged with a topic query of this session beforehand.
+                    let Some(topic) = current_topic.clone() else {
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "unexpected \"data\" message received".to_string(),
+                        ));
+                    };
+
+                    // Forward data received from the remote to the app layer.
+                    app_tx
+                        .send(FromSync::TopicData {
+                            topic,
+                            header,
+                            payload,
+                        })
+                        .await?;
+                }
+                Message::Done => {
+                    if sync_done_received {
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "unexpected \"done\" message received".to_string(),
+                        ));
+                    }
+                    sync_done_received = true;
+                }
+                Message::Batch(remote_batch) => {
+                    if !sync_done_received || sync_done_sent {
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "unexpected \"batch\" message received".to_string(),
+                        ));
+                    }
+
+                    // Topic queries must match the ones we've requested.
+                    if remote_batch.len() != topic_queries.len() {
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "incompatible topic queries requested from remote peer".to_string(),
+                        ));
+                    }
+
+                    for (topic_query, remote_log_heights) in remote_batch {
+                        if !topic_queries.contains(&topic_query) {
+                            return Err(SyncError::UnexpectedBehaviour(format!(
+                                "incompatible topic query {topic_query:?} requested from remote peer"
+                            )));
+                        }
+
+                        // Get the log ids which are associated with this topic query.
+                        let Some(logs) = self.topic_map.get(&topic_query).await else {
+                            return Err(SyncError::UnexpectedBehaviour(format!(
+                                "unsupported topic query {topic_query:?} requested from remote peer"
+                            )));
+                        };
+
+                        // Retrieve and send all messages needed by the remote peer for this topic
+                        // query.
+                        let messages: Vec<Message<T, L>> = messages_needed_by_remote(
+                            &self.store,
+                            &logs,
+                            remote_log_heights.into_iter().collect(),
+                            &SyncFilter::default(),
+                            self.order,
+                            None,
+                        )
+                        .await?;
+                        if messages.is_empty() {
+                            continue;
+                        }
+                        sink.send(Message::Topic(topic_query)).await?;
+                        sink.send_all(&mut stream::iter(messages.into_iter().map(Ok)))
+                            .await?;
+                    }
+
+                    // Signal to the remote peer that we have finished sending data.
+                    sink.send(Message::Done).await?;
+                    sync_done_sent = true;
+                }
+                Message::Have(..)
+                | Message::Filter(_)
+                | Message::Live
+                | Message::Relay(_)
+                | Message::Order(_)
+                | Message::DryRun(..)
+                | Message::Estimate(_) => {
+                    return Err(SyncError::UnexpectedBehaviour(
+                        "unexpected message received in batch session".to_string(),
+                    ));
+                }
+            };
+
+            if sync_done_received && sync_done_sent {
+                break;
+            }
+        }
+
+        // Flush all bytes so that no messages are lost.
+        sink.flush().await?;
+        app_tx.flush().await?;
+
+        Ok(())
+    }
+
+    async fn accept(
+        self: Arc<Self>,
+        tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
+        rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+        app_tx: Box<&'a mut (dyn Sink<FromSync<T>, Error = SyncError> + Send + Unpin)>,
+    ) -> Result<(), SyncError> {
+        let max_operations = self.max_operations;
+        self.accept_session(max_operations, *tx, *rx, *app_tx).await
+    }
+
+    async fn accept_exempt(
+        self: Arc<Self>,
+        tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
+        rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+        app_tx: Box<&'a mut (dyn Sink<FromSync<T>, Error = SyncError> + Send + Unpin)>,
+    ) -> Result<(), SyncError> {
+        // Exempt peers are served all entries they're missing.
+        self.accept_session(None, *tx, *rx, *app_tx).await
+    }
+}
+
+impl<TM, L, E, S> LogSyncProtocol<TM, L, E, S>
+where
+    S: LogStore<L, E>,
+{
+    /// Accept a sync session, sending at most `max_operations` entries to the remote peer.
+    async fn accept_session<'a, T>(
+        self: Arc<Self>,
+        max_operations: Option<usize>,
+        tx: &'a mut (dyn AsyncWrite + Send + Unpin),
+        rx: &'a mut (dyn AsyncRead + Send + Unpin),
+        app_tx: &'a mut (dyn Sink<FromSync<T>, Error = SyncError> + Send + Unpin),
+    ) -> Result<(), SyncError>
+    where
+        T: TopicQuery,
+        TM: TopicLogMap<T, L>,
+        L: LogId + Send + Sync + for<'de> Deserialize<'de> + Serialize + 'a,
+        E: Extensions + Send + Sync + 'a,
+        S: Debug + Sync,
+    {
+        let mut sync_done_sent = false;
+        let mut sync_done_received = false;
+        let mut have_received = false;
+        let mut live_requested = false;
+        let mut remote_filter = SyncFilter::default();
+        let mut relay_logs: Option<Logs<L>> = None;
+        let mut remote_order: Option<DeliveryOrder> = None;
+        let mut served = 0;
+        let mut session = None;
+        let mut batch_topics: Option<Vec<T>> = None;
+        let mut current_topic: Option<T> = None;
+
+        let mut sink = into_cbor_sink(Box::new(tx));
+        let mut stream = into_cbor_stream_with_limits(Box::new(rx), self.limits);
 
         while let Some(result) = stream.next().await {
             let message: Message<T, L> = result?;
             match message {
+                Message::Filter(filter) => {
+                    // Filters are only allowed once, before the "have" message.
+                    if have_received || !remote_filter.is_empty() {
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "unexpected \"filter\" message received".to_string(),
+                        ));
+                    }
+                    remote_filter = filter;
+                }
+                Message::Live => {
+                    // Live mode can only be requested once, before the "have" message.
+                    if have_received || live_requested {
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "unexpected \"live\" message received".to_string(),
+                        ));
+                    }
+                    live_requested = true;
+                }
+                Message::Relay(logs) => {
+                    // Relay requests are only allowed once, before the "have" message.
+                    if have_received || relay_logs.is_some() {
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "unexpected \"relay\" message received".to_string(),
+                        ));
+                    }
+
+                    // Requests are ignored if we're not acting as a relay, in that case we only
+                    // send the logs we associate with the topic query ourselves.
+                    if self.relay {
+                        relay_logs = Some(logs.into_iter().collect());
+                    }
+                }
+                Message::Order(order) => {
+                    // The order can only be requested once, before the "have" message.
+                    if have_received || remote_order.is_some() {
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "unexpected \"order\" message received".to_string(),
+                        ));
+                    }
+                    remote_order = Some(order);
+                }
                 Message::Have(topic_query, remote_log_heights) => {
+                    if have_received {
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "unexpected \"have\" message received".to_string(),
+                        ));
+                    }
+                    have_received = true;
+
                     // Signal that the "handshake" phase of this protocol is complete as we
                     // received the topic query.
                     app_tx
@@ -251,19 +793,31 @@ where
                         .await?;
 
                     // Get the log ids which are associated with this topic query.
-                    let Some(logs) = self.topic_map.get(&topic_query).await else {
+                    let Some(mut logs) = self.topic_map.get(&topic_query).await else {
                         return Err(SyncError::UnexpectedBehaviour(format!(
                             "unsupported topic query {topic_query:?} requested from remote peer"
                         )));
                     };
 
+                    // Include logs requested for relaying.
+                    if let Some(relay_logs) = &relay_logs {
+                        merge_logs(&mut logs, relay_logs);
+                    }
+
                     let remote_log_heights_map: HashMap<PublicKey, Vec<(L, u64)>> =
                         remote_log_heights.clone().into_iter().collect();
 
                     // Retrieve and send all messages needed by the remote peer.
-                    let messages: Vec<Message<T, L>> =
-                        messages_needed_by_remote(&self.store, &logs, remote_log_heights_map)
-                            .await?;
+                    let messages: Vec<Message<T, L>> = messages_needed_by_remote(
+                        &self.store,
+                        &logs,
+                        remote_log_heights_map.clone(),
+                        &remote_filter,
+                        remote_order.unwrap_or_default(),
+                        remaining(served, max_operations),
+                    )
+                    .await?;
+                    served += messages.len();
                     sink.send_all(&mut stream::iter(messages.into_iter().map(Ok)))
                         .await?;
 
@@ -281,10 +835,141 @@ where
                         local_log_heights.clone(),
                     ))
                     .await?;
+
+                    // After this exchange the remote peer knows about all of our entries as
+                    // well, remember these log heights for live mode.
+                    let mut known_log_heights = remote_log_heights_map;
+                    merge_log_heights(&mut known_log_heights, local_log_heights);
+                    session = Some((topic_query, known_log_heights));
+                }
+                Message::Batch(remote_batch) => {
+                    // Live and relay mode are not supported in batch sessions.
+                    if have_received || live_requested || relay_logs.is_some() {
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "unexpected \"batch\" message received".to_string(),
+                        ));
+                    }
+                    have_received = true;
+
+                    // Get the log ids which are associated with every topic query first, the
+                    // whole batch is rejected if any of them is not supported.
+                    let mut topic_logs = Vec::with_capacity(remote_batch.len());
+                    for (topic_query, remote_log_heights) in remote_batch {
+                        let Some(logs) = self.topic_map.get(&topic_query).await else {
+                            return Err(SyncError::UnexpectedBehaviour(format!(
+                                "unsupported topic query {topic_query:?} requested from remote peer"
+                            )));
+                        };
+                        topic_logs.push((topic_query, logs, remote_log_heights));
+                    }
+
+                    let mut topic_queries = Vec::with_capacity(topic_logs.len());
+                    for (topic_query, logs, remote_log_heights) in topic_logs {
+                        // Signal that the "handshake" phase of this protocol is complete for this
+                        // topic query.
+                        app_tx
+                            .send(FromSync::HandshakeSuccess(topic_query.clone()))
+                            .await?;
+
+                        // Retrieve and send all messages needed by the remote peer for this topic
+                        // query.
+                        let messages: Vec<Message<T, L>> = messages_needed_by_remote(
+                            &self.store,
+                            &logs,
+                            remote_log_heights.into_iter().collect(),
+                            &remote_filter,
+                            remote_order.unwrap_or_default(),
+                            remaining(served, max_operations),
+                        )
+                        .await?;
+                        served += messages.len();
+                        if !messages.is_empty() {
+                            sink.send(Message::Topic(topic_query.clone())).await?;
+                            sink.send_all(&mut stream::iter(messages.into_iter().map(Ok)))
+                                .await?;
+                        }
+
+                        topic_queries.push(topic_query);
+                    }
+
+                    // Signal to the remote peer that we have finished sending data.
+                    sink.send(Message::Done).await?;
+                    sync_done_sent = true;
+
+                    // Send our log heights for all topic queries to the remote peer.
+                    let mut batch = Vec::with_capacity(topic_queries.len());
+                    for topic_query in &topic_queries {
+                        let local_log_heights =
+                            local_log_heights(&self.store, &self.topic_map, topic_query).await?;
+                        batch.push((topic_query.clone(), local_log_heights));
+                    }
+                    sink.send(Message::<T, L>::Batch(batch)).await?;
+
+                    batch_topics = Some(topic_queries);
+                }
+                Message::DryRun(topic_query, remote_log_heights) => {
+                    if have_received {
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "unexpected \"dry run\" message received".to_string(),
+                        ));
+                    }
+
+                    // Get the log ids which are associated with this topic query.
+                    let Some(logs) = self.topic_map.get(&topic_query).await else {
+                        return Err(SyncError::UnexpectedBehaviour(format!(
+                            "unsupported topic query {topic_query:?} requested from remote peer"
+                        )));
+                    };
+
+                    // Estimate the messages we would send the remote peer in a sync session.
+                    let estimate = estimate_needed_by_remote(
+                        &self.store,
+                        &logs,
+                        remote_log_heights.into_iter().collect(),
+                        &remote_filter,
+                        max_operations,
+                    )
+                    .await?;
+
+                    // The session ends after answering a dry run.
+                    sink.send(Message::Estimate(estimate)).await?;
+                    sink.flush().await?;
+                    return Ok(());
+                }
+                Message::Estimate(_) => {
+                    return Err(SyncError::UnexpectedBehaviour(
+                        "unexpected \"estimate\" message received".to_string(),
+                    ));
+                }
+                Message::Topic(topic_query) => {
+                    let is_batch_topic = batch_topics
+                        .as_ref()
+                        .is_some_and(|topic_queries| topic_queries.contains(&topic_query));
+                    if !is_batch_topic {
+                        return Err(SyncError::UnexpectedBehaviour(format!(
+                            "unexpected topic query {topic_query:?} received from remote peer"
+                        )));
+                    }
+                    current_topic = Some(topic_query);
                 }
                 Message::Data(header, payload) => {
+                    // Data of batch sessions is tagged with the topic query announced beforehand.
+                    let message = match (&batch_topics, &current_topic) {
+                        (None, _) => FromSync::Data { header, payload },
+                        (Some(_), Some(topic)) => FromSync::TopicData {
+                            topic: topic.clone(),
+                            header,
+                            payload,
+                        },
+                        (Some(_), None) => {
+                            return Err(SyncError::UnexpectedBehaviour(
+                                "unexpected \"data\" message received".to_string(),
+                            ));
+                        }
+                    };
+
                     // Forward data received from the remote to the app layer.
-                    app_tx.send(FromSync::Data { header, payload }).await?;
+                    app_tx.send(message).await?;
                 }
                 Message::Done => {
                     sync_done_received = true;
@@ -296,6 +981,70 @@ where
             }
         }
 
+        if live_requested && let Some((topic_query, mut known_log_heights)) = session {
+            let Some(poll_interval) = self.live else {
+                // Signal to the remote peer that we don't support live mode and the session
+                // ends here.
+                sink.send(Message::Done).await?;
+                sink.flush().await?;
+                app_tx.flush().await?;
+                return Ok(());
+            };
+
+            loop {
+                match timeout(poll_interval, stream.next()).await {
+                    // The remote peer closed the session.
+                    Ok(None) => break,
+                    Ok(Some(result)) => {
+                        let message: Message<T, L> = result?;
+                        if let Message::Done = message {
+                            break;
+                        }
+                        return Err(SyncError::UnexpectedBehaviour(
+                            "unexpected message received in live mode".to_string(),
+                        ));
+                    }
+                    Err(_) => {
+                        // End the session when we've served the maximum number of operations.
+                        if max_operations.is_some_and(|max| served >= max) {
+                            sink.send(Message::Done).await?;
+                            break;
+                        }
+
+                        // Get the log ids which are associated with this topic query, they might
+                        // have changed since the session started.
+                        let Some(mut logs) = self.topic_map.get(&topic_query).await else {
+                            break;
+                        };
+                        if let Some(relay_logs) = &relay_logs {
+                            merge_logs(&mut logs, relay_logs);
+                        }
+
+                        // Retrieve the log heights before the messages, entries which get
+                        // inserted in between will be sent again during the next poll instead
+                        // of being missed.
+                        let local_log_heights =
+                            local_log_heights(&self.store, &self.topic_map, &topic_query).await?;
+
+                        let messages: Vec<Message<T, L>> = messages_needed_by_remote(
+                            &self.store,
+                            &logs,
+                            known_log_heights.clone(),
+                            &remote_filter,
+                            remote_order.unwrap_or_default(),
+                            remaining(served, max_operations),
+                        )
+                        .await?;
+                        served += messages.len();
+                        sink.send_all(&mut stream::iter(messages.into_iter().map(Ok)))
+                            .await?;
+
+                        merge_log_heights(&mut known_log_heights, local_log_heights);
+                    }
+                }
+            }
+        }
+
         // Flush all bytes so that no messages are lost.
         sink.flush().await?;
         app_tx.flush().await?;
@@ -304,7 +1053,67 @@ where
     }
 }
 
-/// Return the log heights and public keys for all authors who have published under log ids
+/// Returns the number of operations which can still be served in this session, if limited.
+fn remaining(served: usize, max: Option<usize>) -> Option<usize> {
+    max.map(|max| max.saturating_sub(served))
+}
+
+/// Merge the given log heights into a map of known log heights, keeping the highest sequence
+/// number for every log.
+fn merge_log_heights<L>(
+    known_log_heights: &mut HashMap<PublicKey, Vec<(L, u64)>>,
+    log_heights: Vec<(PublicKey, Vec<(L, u64)>)>,
+) where
+    L: LogId,
+{
+    for (public_key, log_heights) in log_heights {
+        let known = known_log_heights.entry(public_key).or_default();
+        for (log_id, seq_num) in log_heights {
+            match known.iter_mut().find(|(id, _)| *id == log_id) {
+                Some((_, known_seq_num)) => *known_seq_num = (*known_seq_num).max(seq_num),
+                None => known.push((log_id, seq_num)),
+            }
+        }
+    }
+}
+
+/// Merge the given logs into another set of logs, skipping log ids which are already present.
+fn merge_logs<L>(logs: &mut Logs<L>, other: &Logs<L>)
+where
+    L: LogId,
+{
+    for (public_key, log_ids) in other {
+        let known = logs.entry(*public_key).or_default();
+        for log_id in log_ids {
+            if !known.contains(log_id) {
+                known.push(log_id.clone());
+            }
+        }
+    }
+}
+
+/// Validate an entry received in relay mode.
+///
+/// Checks the signature of the header and that the payload matches the hash and size claimed in
+/// the header.
+fn validate_relayed<E>(header_bytes: &[u8], payload: Option<&Vec<u8>>) -> Result<(), SyncError>
+where
+    E: Extensions,
+{
+    let header: Header<E> = decode_cbor(header_bytes)
+        .map_err(|err| SyncError::Validation(format!("could not decode relayed header, {err}")))?;
+
+    let operation = Operation {
+        hash: header.hash(),
+        header,
+        body: payload.map(|payload| Body::new(payload)),
+    };
+
+    validate_operation(&operation)
+        .map_err(|err| SyncError::Validation(format!("invalid relayed operation, {err}")))
+}
+
+/// Return the log heights and public keys for all authors who have published under log ids
 /// which match the given topic query.
 async fn local_log_heights<T, L, E>(
     store: &impl LogStore<L, E>,
@@ -323,19 +1132,13 @@ where
     };
 
     // Get local log heights for all authors who have published under the requested log ids.
+    let heads = local_log_heads(store, &logs).await?;
     let mut local_log_heights = Vec::new();
     for (public_key, log_ids) in logs {
         let mut log_heights = Vec::new();
         for log_id in log_ids {
-            let latest = store
-                .latest_operation(&public_key, &log_id)
-                .await
-                .map_err(|err| {
-                    SyncError::Critical(format!("can't retrieve log heights from store, {err}"))
-                })?;
-
-            if let Some((header, _)) = latest {
-                log_heights.push((log_id.clone(), header.seq_num));
+            if let Some(head) = heads.get(&(public_key, log_id.clone())) {
+                log_heights.push((log_id, head.seq_num));
             };
         }
         local_log_heights.push((public_key, log_heights));
@@ -344,6 +1147,29 @@ where
     Ok(local_log_heights)
 }
 
+/// Return the heads of all given local logs, logs which don't exist are missing.
+///
+/// Heads are maintained by the store on every write, they are looked up once per log id.
+async fn local_log_heads<L, E>(
+    store: &impl LogStore<L, E>,
+    logs: &Logs<L>,
+) -> Result<HashMap<(PublicKey, L), LogHead>, SyncError>
+where
+    L: LogId,
+{
+    let log_ids: HashSet<&L> = logs.values().flatten().collect();
+    let mut heads = HashMap::new();
+    for log_id in log_ids {
+        let log_heads = store.log_heads(log_id).await.map_err(|err| {
+            SyncError::Storage(format!("can't retrieve log heights from store, {err}"))
+        })?;
+        for head in log_heads {
+            heads.insert((head.public_key, log_id.clone()), head);
+        }
+    }
+    Ok(heads)
+}
+
 /// Return all messages needed by a remote peer for the given log id and format them as data
 /// messages for transport over the wire.
 async fn remote_needs<T, L, E>(
@@ -351,6 +1177,7 @@ async fn remote_needs<T, L, E>(
     log_id: &L,
     public_key: &PublicKey,
     from: SeqNum,
+    filter: &SyncFilter,
 ) -> Result<Vec<Message<T, L>>, SyncError>
 where
     E: Extensions + Send + Sync,
@@ -358,23 +1185,38 @@ where
     let log = store
         .get_raw_log(public_key, log_id, Some(from))
         .await
-        .map_err(|err| SyncError::Critical(format!("could not retrieve log from store, {err}")))?;
-
-    let messages = log
-        .unwrap_or_default()
-        .into_iter()
-        .map(|(header, payload)| Message::Data(header, payload))
-        .collect();
+        .map_err(|err| SyncError::Storage(format!("could not retrieve log from store, {err}")))?;
+
+    let mut messages = Vec::new();
+    for (header_bytes, payload) in log.unwrap_or_default() {
+        if filter.since.is_some() {
+            let header: Header<E> = decode_cbor(&header_bytes[..]).map_err(|err| {
+                SyncError::Storage(format!("could not decode header from store, {err}"))
+            })?;
+            if !filter.matches_timestamp(header.timestamp) {
+                continue;
+            }
+        }
+        messages.push(Message::Data(header_bytes, payload));
+    }
 
     Ok(messages)
 }
 
 /// Compare the local log heights with the remote log heights for all given logs and return all
 /// messages needed by the remote peer.
+///
+/// If a limit is given, only the oldest missing entries of every log up to the limit are
+/// returned. The remote peer can ingest them, as their backlinks are known, and asks for the
+/// following entries in the next session. The order is applied afterwards, newest-first delivery
+/// only reorders the returned entries.
 async fn messages_needed_by_remote<T, L, E>(
     store: &impl LogStore<L, E>,
     logs: &Logs<L>,
     remote_log_heights_map: HashMap<PublicKey, Vec<(L, u64)>>,
+    filter: &SyncFilter,
+    order: DeliveryOrder,
+    limit: Option<usize>,
 ) -> Result<Vec<Message<T, L>>, SyncError>
 where
     L: LogId,
@@ -385,43 +1227,29 @@ where
     //
     // If our logs are more advanced for any log we should collect the entries for sending.
     let mut messages_for_remote = Vec::new();
+    let heads = local_log_heads(store, logs).await?;
 
     for (public_key, log_ids) in logs {
+        // Skip logs of authors the remote is not interested in.
+        if !filter.matches_author(public_key.as_bytes()) {
+            continue;
+        }
+
         for log_id in log_ids {
             // For all logs in this topic query scope get the local height.
-            let latest_operation =
-                store
-                    .latest_operation(public_key, log_id)
-                    .await
-                    .map_err(|err| {
-                        SyncError::Critical(format!("can't retreive log heights from store, {err}"))
-                    })?;
-
-            let log_height = match latest_operation {
-                Some((header, _)) => header.seq_num,
+            let log_height = match heads.get(&(*public_key, log_id.clone())) {
+                Some(head) => head.seq_num,
                 // If we don't have this log then continue onto the next without
                 // sending any messages.
                 None => continue,
             };
 
             // Calculate from which seq num in the log the remote needs operations.
-            let remote_needs_from = match remote_log_heights_map.get(public_key) {
-                Some(log_heights) => {
-                    match log_heights.iter().find(|(id, _)| *id == *log_id) {
-                        // The log is known by the remote, take their log height
-                        // and plus one.
-                        Some((_, log_height)) => log_height + 1,
-                        // The log is not known, they need from seq num 0
-                        None => 0,
-                    }
-                }
-                // The author is not known, they need from seq num 0.
-                None => 0,
-            };
+            let remote_needs_from = remote_needs_from(&remote_log_heights_map, public_key, log_id);
 
             if remote_needs_from <= log_height {
                 let messages: Vec<Message<T, L>> =
-                    remote_needs(store, log_id, public_key, remote_needs_from).await?;
+                    remote_needs(store, log_id, public_key, remote_needs_from, filter).await?;
                 for message in messages {
                     messages_for_remote.push(message);
                 }
@@ -429,27 +1257,140 @@ where
         }
     }
 
+    // Entries are collected log by log in ascending order, truncating keeps the oldest ones.
+    if let Some(limit) = limit {
+        messages_for_remote.truncate(limit);
+    }
+
+    if order == DeliveryOrder::NewestFirst {
+        sort_newest_first::<T, L, E>(&mut messages_for_remote)?;
+    }
+
     Ok(messages_for_remote)
 }
 
+/// Estimate the number of entries and their bytes needed by the remote peer for all given logs.
+///
+/// The estimate is calculated from the log heads maintained by the store, without loading any
+/// entries. The bytes of partially missing logs are approximated by the average size of their
+/// entries and the timestamp filter of the remote peer is not applied.
+async fn estimate_needed_by_remote<L, E>(
+    store: &impl LogStore<L, E>,
+    logs: &Logs<L>,
+    remote_log_heights_map: HashMap<PublicKey, Vec<(L, u64)>>,
+    filter: &SyncFilter,
+    max_operations: Option<usize>,
+) -> Result<SyncEstimate, SyncError>
+where
+    L: LogId,
+{
+    let mut estimate = SyncEstimate::default();
+    let mut remaining = max_operations.map_or(u64::MAX, |max| max as u64);
+    let heads = local_log_heads(store, logs).await?;
+
+    for (public_key, log_ids) in logs {
+        // Skip logs of authors the remote is not interested in.
+        if !filter.matches_author(public_key.as_bytes()) {
+            continue;
+        }
+
+        for log_id in log_ids {
+            let Some(head) = heads.get(&(*public_key, log_id.clone())) else {
+                continue;
+            };
+
+            // Operations before the remote log height are not sent, pruned operations are not
+            // counted by the head.
+            let remote_needs_from = remote_needs_from(&remote_log_heights_map, public_key, log_id);
+            let needed = (head.seq_num + 1)
+                .saturating_sub(remote_needs_from)
+                .min(head.operations)
+                .min(remaining);
+            if needed == 0 {
+                continue;
+            }
+
+            remaining -= needed;
+            estimate.operations += needed;
+            estimate.bytes += (head.size as u128 * needed as u128 / head.operations as u128) as u64;
+        }
+    }
+
+    Ok(estimate)
+}
+
+/// Return the sequence number from which the remote peer needs operations of the given log.
+fn remote_needs_from<L>(
+    remote_log_heights_map: &HashMap<PublicKey, Vec<(L, u64)>>,
+    public_key: &PublicKey,
+    log_id: &L,
+) -> u64
+where
+    L: LogId,
+{
+    match remote_log_heights_map.get(public_key) {
+        Some(log_heights) => {
+            match log_heights.iter().find(|(id, _)| *id == *log_id) {
+                // The log is known by the remote, take their log height and plus one.
+                Some((_, log_height)) => log_height + 1,
+                // The log is not known, they need from seq num 0
+                None => 0,
+            }
+        }
+        // The author is not known, they need from seq num 0.
+        None => 0,
+    }
+}
+
+/// Sort data messages by the timestamp of their entries, most recent first.
+///
+/// Entries with equal timestamps are sorted by their sequence number, so every log still arrives
+/// in exactly reversed order.
+fn sort_newest_first<T, L, E>(messages: &mut Vec<Message<T, L>>) -> Result<(), SyncError>
+where
+    E: Extensions,
+{
+    let mut keyed = Vec::with_capacity(messages.len());
+    for message in messages.drain(..) {
+        let key = match &message {
+            Message::Data(header_bytes, _) => {
+                let header: Header<E> = decode_cbor(&header_bytes[..]).map_err(|err| {
+                    SyncError::Storage(format!("could not decode header from store, {err}"))
+                })?;
+                (header.timestamp, header.seq_num)
+            }
+            _ => (0, 0),
+        };
+        keyed.push((Reverse(key), message));
+    }
+    keyed.sort_by_key(|(key, _)| *key);
+    messages.extend(keyed.into_iter().map(|(_, message)| message));
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
     use std::sync::Arc;
+    use std::time::Duration;
 
     use async_trait::async_trait;
-    use futures::SinkExt;
+    use futures::{AsyncRead, AsyncWrite, Sink, SinkExt};
+    use p2panda_core::cbor::decode_cbor;
     use p2panda_core::{Body, Hash, Header, PrivateKey};
-    use p2panda_store::{MemoryStore, OperationStore};
+    use p2panda_store::{LogStore, MemoryStore, OperationStore};
     use serde::{Deserialize, Serialize};
     use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf};
     use tokio::sync::mpsc;
     use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
     use tokio_util::sync::PollSender;
 
-    use crate::{FromSync, SyncError, SyncProtocol, TopicQuery};
+    use crate::{FromSync, SyncError, SyncEstimate, SyncFilter, SyncProtocol, TopicQuery};
 
-    use super::{LogSyncProtocol, Logs, Message, TopicLogMap};
+    use super::{
+        DeliveryOrder, LogSyncProtocol, Logs, Message, TopicLogMap, estimate_needed_by_remote,
+        local_log_heights,
+    };
 
     impl<T, L> Message<T, L>
     where
@@ -1230,4 +2171,953 @@ mod tests {
         peer_a_app_rx.recv_many(&mut peer_a_messages, 10).await;
         assert_eq!(peer_a_messages, peer_a_expected_messages);
     }
+
+    #[tokio::test]
+    async fn e2e_filtered_sync() {
+        let private_key = PrivateKey::new();
+        let log_id = 0;
+        let topic_query = LogHeightTopic::new("messages");
+        let logs = HashMap::from([(private_key.public_key(), vec![log_id])]);
+
+        let mut topic_map = LogHeightTopicMap::new();
+        topic_map.insert(&topic_query, logs);
+
+        // Create a store for peer b and populate it with 3 operations
+        let mut store = MemoryStore::default();
+        let body = Body::new("Hello, Sloth!".as_bytes());
+
+        let (hash_0, header_0, header_bytes_0) = create_operation(&private_key, &body, 0, 0, None);
+        let (hash_1, header_1, header_bytes_1) =
+            create_operation(&private_key, &body, 1, 100, Some(hash_0));
+        let (hash_2, header_2, header_bytes_2) =
+            create_operation(&private_key, &body, 2, 200, Some(hash_1));
+
+        store
+            .insert_operation(hash_0, &header_0, Some(&body), &header_bytes_0, &log_id)
+            .await
+            .unwrap();
+        store
+            .insert_operation(hash_1, &header_1, Some(&body), &header_bytes_1, &log_id)
+            .await
+            .unwrap();
+        store
+            .insert_operation(hash_2, &header_2, Some(&body), &header_bytes_2, &log_id)
+            .await
+            .unwrap();
+
+        // Run a sync session where peer a is only interested in recent data of the given authors
+        // and return everything it received.
+        let run = |filter: SyncFilter| {
+            let peer_a_protocol = Arc::new(LogSyncProtocol::new(
+                topic_map.clone(),
+                MemoryStore::<u64>::default(),
+            ));
+            let peer_b_protocol = Arc::new(LogSyncProtocol::new(topic_map.clone(), store.clone()));
+            let topic_query = topic_query.clone();
+
+            async move {
+                let (peer_a, peer_b) = tokio::io::duplex(64 * 1024);
+                let (peer_a_read, peer_a_write) = tokio::io::split(peer_a);
+                let (peer_b_read, peer_b_write) = tokio::io::split(peer_b);
+
+                let (peer_a_app_tx, mut peer_a_app_rx) = mpsc::channel(128);
+                let mut sink_a = PollSender::new(peer_a_app_tx)
+                    .sink_map_err(|err| SyncError::Critical(err.to_string()));
+                let handle_1 = tokio::spawn(async move {
+                    peer_a_protocol
+                        .initiate_with_filter(
+                            topic_query,
+                            filter,
+                            Box::new(&mut peer_a_write.compat_write()),
+                            Box::new(&mut peer_a_read.compat()),
+                            Box::new(&mut sink_a),
+                        )
+                        .await
+                        .unwrap();
+                });
+
+                let (peer_b_app_tx, _peer_b_app_rx) = mpsc::channel(128);
+                let mut sink_b = PollSender::new(peer_b_app_tx)
+                    .sink_map_err(|err| SyncError::Critical(err.to_string()));
+                let handle_2 = tokio::spawn(async move {
+                    peer_b_protocol
+                        .accept(
+                            Box::new(&mut peer_b_write.compat_write()),
+                            Box::new(&mut peer_b_read.compat()),
+                            Box::new(&mut sink_b),
+                        )
+                        .await
+                        .unwrap();
+                });
+
+                let (_, _) = tokio::join!(handle_1, handle_2);
+
+                let mut messages = Vec::new();
+                peer_a_app_rx.recv_many(&mut messages, 10).await;
+                messages
+            }
+        };
+
+        // Only operations at or after the given timestamp are sent.
+        let messages = run(SyncFilter::new().since(100)).await;
+        assert_eq!(
+            messages,
+            vec![
+                FromSync::HandshakeSuccess(topic_query.clone()),
+                FromSync::Data {
+                    header: header_bytes_1,
+                    payload: Some(body.to_bytes()),
+                },
+                FromSync::Data {
+                    header: header_bytes_2,
+                    payload: Some(body.to_bytes()),
+                },
+            ]
+        );
+
+        // Logs of other authors are not sent.
+        let other_author = PrivateKey::new().public_key();
+        let messages = run(SyncFilter::new().authors(vec![*other_author.as_bytes()])).await;
+        assert_eq!(messages, vec![FromSync::HandshakeSuccess(topic_query)]);
+    }
+
+    #[tokio::test]
+    async fn e2e_live_sync() {
+        let private_key = PrivateKey::new();
+        let log_id = 0;
+        let topic_query = LogHeightTopic::new("messages");
+        let logs = HashMap::from([(private_key.public_key(), vec![log_id])]);
+
+        let mut topic_map = LogHeightTopicMap::new();
+        topic_map.insert(&topic_query, logs);
+
+        // Create a store for peer b and populate it with 1 operation
+        let mut store = MemoryStore::default();
+        let body = Body::new("Hello, Sloth!".as_bytes());
+
+        let (hash_0, header_0, header_bytes_0) = create_operation(&private_key, &body, 0, 0, None);
+        store
+            .insert_operation(hash_0, &header_0, Some(&body), &header_bytes_0, &log_id)
+            .await
+            .unwrap();
+
+        // Both peers enable live mode
+        let peer_a_protocol = Arc::new(
+            LogSyncProtocol::new(topic_map.clone(), MemoryStore::<u64>::default())
+                .live_mode(Duration::from_millis(10)),
+        );
+        let peer_b_protocol = Arc::new(
+            LogSyncProtocol::new(topic_map.clone(), store.clone())
+                .live_mode(Duration::from_millis(10)),
+        );
+
+        let (peer_a, peer_b) = tokio::io::duplex(64 * 1024);
+        let (peer_a_read, peer_a_write) = tokio::io::split(peer_a);
+        let (peer_b_read, peer_b_write) = tokio::io::split(peer_b);
+
+        let (peer_a_app_tx, mut peer_a_app_rx) = mpsc::channel(128);
+        let mut sink_a =
+            PollSender::new(peer_a_app_tx).sink_map_err(|err| SyncError::Critical(err.to_string()));
+        let topic_query_clone = topic_query.clone();
+        let handle_1 = tokio::spawn(async move {
+            peer_a_protocol
+                .initiate(
+                    topic_query_clone,
+                    Box::new(&mut peer_a_write.compat_write()),
+                    Box::new(&mut peer_a_read.compat()),
+                    Box::new(&mut sink_a),
+                )
+                .await
+        });
+
+        let (peer_b_app_tx, _peer_b_app_rx) = mpsc::channel(128);
+        let mut sink_b =
+            PollSender::new(peer_b_app_tx).sink_map_err(|err| SyncError::Critical(err.to_string()));
+        let handle_2 = tokio::spawn(async move {
+            peer_b_protocol
+                .accept(
+                    Box::new(&mut peer_b_write.compat_write()),
+                    Box::new(&mut peer_b_read.compat()),
+                    Box::new(&mut sink_b),
+                )
+                .await
+        });
+
+        // Peer a catches up with peer b
+        assert_eq!(
+            peer_a_app_rx.recv().await,
+            Some(FromSync::HandshakeSuccess(topic_query))
+        );
+        assert_eq!(
+            peer_a_app_rx.recv().await,
+            Some(FromSync::Data {
+                header: header_bytes_0,
+                payload: Some(body.to_bytes()),
+            })
+        );
+
+        // Peer b writes a new operation which gets streamed to peer a over the open session
+        let (hash_1, header_1, header_bytes_1) =
+            create_operation(&private_key, &body, 1, 1, Some(hash_0));
+        store
+            .insert_operation(hash_1, &header_1, Some(&body), &header_bytes_1, &log_id)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            peer_a_app_rx.recv().await,
+            Some(FromSync::Data {
+                header: header_bytes_1,
+                payload: Some(body.to_bytes()),
+            })
+        );
+        assert!(!handle_1.is_finished());
+
+        // Closing the session on peer a ends the session on peer b as well
+        handle_1.abort();
+        assert!(handle_2.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn e2e_sync_after_append() {
+        let private_key = PrivateKey::new();
+        let public_key = private_key.public_key();
+        let log_id = 0;
+        let topic_query = LogHeightTopic::new("messages");
+        let mut topic_map = LogHeightTopicMap::new();
+        topic_map.insert(&topic_query, HashMap::from([(public_key, vec![log_id])]));
+
+        let mut store_a = MemoryStore::default();
+        let mut store_b = MemoryStore::default();
+        let body = Body::new("Hello, Sloth!".as_bytes());
+        let (hash_0, header_0, header_bytes_0) = create_operation(&private_key, &body, 0, 0, None);
+        store_b
+            .insert_operation(hash_0, &header_0, Some(&body), &header_bytes_0, &log_id)
+            .await
+            .unwrap();
+
+        let peer_a_protocol = Arc::new(LogSyncProtocol::new(topic_map.clone(), store_a.clone()));
+        let peer_b_protocol = Arc::new(LogSyncProtocol::new(topic_map.clone(), store_b.clone()));
+
+        let (peer_a_messages, _) = run_session(
+            peer_a_protocol.clone(),
+            peer_b_protocol.clone(),
+            vec![topic_query.clone()],
+        )
+        .await;
+        assert_eq!(
+            peer_a_messages,
+            vec![
+                FromSync::HandshakeSuccess(topic_query.clone()),
+                FromSync::Data {
+                    header: header_bytes_0.clone(),
+                    payload: Some(body.to_bytes()),
+                },
+            ]
+        );
+
+        // Peer a ingests the received operation while peer b appends a new one. Both are picked
+        // up by the next session of the same protocol instances.
+        store_a
+            .insert_operation(hash_0, &header_0, Some(&body), &header_bytes_0, &log_id)
+            .await
+            .unwrap();
+        let (hash_1, header_1, header_bytes_1) =
+            create_operation(&private_key, &body, 1, 1, Some(hash_0));
+        store_b
+            .insert_operation(hash_1, &header_1, Some(&body), &header_bytes_1, &log_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            local_log_heights(&store_b, &topic_map, &topic_query)
+                .await
+                .unwrap(),
+            vec![(public_key, vec![(log_id, 1)])]
+        );
+
+        let (peer_a_messages, _) =
+            run_session(peer_a_protocol, peer_b_protocol, vec![topic_query.clone()]).await;
+        assert_eq!(
+            peer_a_messages,
+            vec![
+                FromSync::HandshakeSuccess(topic_query),
+                FromSync::Data {
+                    header: header_bytes_1,
+                    payload: Some(body.to_bytes()),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn e2e_relay_sync() {
+        // Peer c authored operations and went offline, peer b holds a copy of them.
+        let private_key_c = PrivateKey::new();
+        let log_id = 0;
+        let topic_query = LogHeightTopic::new("messages");
+
+        // Peer a associates the logs of peer c with the topic, peer b does not.
+        let mut topic_map_a = LogHeightTopicMap::new();
+        topic_map_a.insert(
+            &topic_query,
+            HashMap::from([(private_key_c.public_key(), vec![log_id])]),
+        );
+        let mut topic_map_b = LogHeightTopicMap::new();
+        topic_map_b.insert(&topic_query, HashMap::new());
+
+        let mut store_b = MemoryStore::default();
+        let body = Body::new("Hello, Sloth!".as_bytes());
+        let (hash_0, header_0, header_bytes_0) =
+            create_operation(&private_key_c, &body, 0, 0, None);
+        store_b
+            .insert_operation(hash_0, &header_0, Some(&body), &header_bytes_0, &log_id)
+            .await
+            .unwrap();
+
+        // Run a sync session between peer a and b and return everything peer a received.
+        let run = |relay: bool, store_b: MemoryStore<u64>| {
+            let mut peer_a_protocol =
+                LogSyncProtocol::new(topic_map_a.clone(), MemoryStore::<u64>::default());
+            let mut peer_b_protocol = LogSyncProtocol::new(topic_map_b.clone(), store_b);
+            if relay {
+                peer_a_protocol = peer_a_protocol.relay_mode();
+                peer_b_protocol = peer_b_protocol.relay_mode();
+            }
+            let peer_a_protocol = Arc::new(peer_a_protocol);
+            let peer_b_protocol = Arc::new(peer_b_protocol);
+            let topic_query = topic_query.clone();
+
+            async move {
+                let (peer_a, peer_b) = tokio::io::duplex(64 * 1024);
+                let (peer_a_read, peer_a_write) = tokio::io::split(peer_a);
+                let (peer_b_read, peer_b_write) = tokio::io::split(peer_b);
+
+                let (peer_a_app_tx, mut peer_a_app_rx) = mpsc::channel(128);
+                let mut sink_a = PollSender::new(peer_a_app_tx)
+                    .sink_map_err(|err| SyncError::Critical(err.to_string()));
+                let handle_1 = tokio::spawn(async move {
+                    peer_a_protocol
+                        .initiate(
+                            topic_query,
+                            Box::new(&mut peer_a_write.compat_write()),
+                            Box::new(&mut peer_a_read.compat()),
+                            Box::new(&mut sink_a),
+                        )
+                        .await
+                });
+
+                let (peer_b_app_tx, _peer_b_app_rx) = mpsc::channel(128);
+                let mut sink_b = PollSender::new(peer_b_app_tx)
+                    .sink_map_err(|err| SyncError::Critical(err.to_string()));
+                let handle_2 = tokio::spawn(async move {
+                    peer_b_protocol
+                        .accept(
+                            Box::new(&mut peer_b_write.compat_write()),
+                            Box::new(&mut peer_b_read.compat()),
+                            Box::new(&mut sink_b),
+                        )
+                        .await
+                });
+
+                let (result, _) = tokio::join!(handle_1, handle_2);
+
+                let mut messages = Vec::new();
+                peer_a_app_rx.recv_many(&mut messages, 10).await;
+                (result.unwrap(), messages)
+            }
+        };
+
+        // Without relay mode peer b only sends the logs it associates with the topic.
+        let (result, messages) = run(false, store_b.clone()).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            messages,
+            vec![FromSync::HandshakeSuccess(topic_query.clone())]
+        );
+
+        // In relay mode peer b sends the operations of peer c.
+        let (result, messages) = run(true, store_b).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            messages,
+            vec![
+                FromSync::HandshakeSuccess(topic_query.clone()),
+                FromSync::Data {
+                    header: header_bytes_0.clone(),
+                    payload: Some(body.to_bytes()),
+                },
+            ]
+        );
+
+        // Relayed operations with a tampered payload are rejected.
+        let mut store_b = MemoryStore::default();
+        let tampered_body = Body::new("Hello, Panda!".as_bytes());
+        store_b
+            .insert_operation(
+                hash_0,
+                &header_0,
+                Some(&tampered_body),
+                &header_bytes_0,
+                &log_id,
+            )
+            .await
+            .unwrap();
+        let (result, _) = run(true, store_b).await;
+        assert!(matches!(result, Err(SyncError::Validation(_))));
+    }
+
+    /// Run a sync session between an initiating peer a and an accepting peer b and return the
+    /// messages both sent to their app layer.
+    ///
+    /// Sessions over more than one topic query are initiated as batch sessions.
+    async fn run_session<P>(
+        peer_a_protocol: Arc<P>,
+        peer_b_protocol: Arc<P>,
+        topic_queries: Vec<LogHeightTopic>,
+    ) -> (Vec<FromSync<LogHeightTopic>>, Vec<FromSync<LogHeightTopic>>)
+    where
+        P: for<'a> SyncProtocol<'a, LogHeightTopic> + 'static,
+    {
+        let ((result_a, peer_a_messages), (result_b, peer_b_messages)) =
+            try_run_session(peer_a_protocol, peer_b_protocol, topic_queries).await;
+        result_a.unwrap();
+        result_b.unwrap();
+        (peer_a_messages, peer_b_messages)
+    }
+
+    /// Run a sync session like `run_session` and return the results of both peers next to the
+    /// messages they sent to their app layer.
+    async fn try_run_session<P>(
+        peer_a_protocol: Arc<P>,
+        peer_b_protocol: Arc<P>,
+        mut topic_queries: Vec<LogHeightTopic>,
+    ) -> (
+        (Result<(), SyncError>, Vec<FromSync<LogHeightTopic>>),
+        (Result<(), SyncError>, Vec<FromSync<LogHeightTopic>>),
+    )
+    where
+        P: for<'a> SyncProtocol<'a, LogHeightTopic> + 'static,
+    {
+        let (peer_a, peer_b) = tokio::io::duplex(64 * 1024);
+        let (peer_a_read, peer_a_write) = tokio::io::split(peer_a);
+        let (peer_b_read, peer_b_write) = tokio::io::split(peer_b);
+
+        let (peer_a_app_tx, mut peer_a_app_rx) = mpsc::channel(128);
+        let mut sink_a =
+            PollSender::new(peer_a_app_tx).sink_map_err(|err| SyncError::Critical(err.to_string()));
+        let handle_1 = tokio::spawn(async move {
+            let mut peer_a_write = peer_a_write.compat_write();
+            let mut peer_a_read = peer_a_read.compat();
+            if topic_queries.len() == 1 {
+                peer_a_protocol
+                    .initiate(
+                        topic_queries.remove(0),
+                        Box::new(&mut peer_a_write),
+                        Box::new(&mut peer_a_read),
+                        Box::new(&mut sink_a),
+                    )
+                    .await
+            } else {
+                peer_a_protocol
+                    .initiate_batch(
+                        topic_queries,
+                        Box::new(&mut peer_a_write),
+                        Box::new(&mut peer_a_read),
+                        Box::new(&mut sink_a),
+                    )
+                    .await
+            }
+        });
+
+        let (peer_b_app_tx, mut peer_b_app_rx) = mpsc::channel(128);
+        let mut sink_b =
+            PollSender::new(peer_b_app_tx).sink_map_err(|err| SyncError::Critical(err.to_string()));
+        let handle_2 = tokio::spawn(async move {
+            peer_b_protocol
+                .accept(
+                    Box::new(&mut peer_b_write.compat_write()),
+                    Box::new(&mut peer_b_read.compat()),
+                    Box::new(&mut sink_b),
+                )
+                .await
+        });
+
+        let (result_1, result_2) = tokio::join!(handle_1, handle_2);
+
+        let mut peer_a_messages = Vec::new();
+        peer_a_app_rx.recv_many(&mut peer_a_messages, 128).await;
+        let mut peer_b_messages = Vec::new();
+        peer_b_app_rx.recv_many(&mut peer_b_messages, 128).await;
+        (
+            (result_1.unwrap(), peer_a_messages),
+            (result_2.unwrap(), peer_b_messages),
+        )
+    }
+
+    #[tokio::test]
+    async fn e2e_newest_first_sync() {
+        let private_key_a = PrivateKey::new();
+        let private_key_b = PrivateKey::new();
+        let log_id = 0;
+        let topic_query = LogHeightTopic::new("messages");
+        let logs = HashMap::from([
+            (private_key_a.public_key(), vec![log_id]),
+            (private_key_b.public_key(), vec![log_id]),
+        ]);
+        let mut topic_map = LogHeightTopicMap::new();
+        topic_map.insert(&topic_query, logs);
+
+        // Peer b holds two interleaved logs.
+        let mut store_b = MemoryStore::default();
+        let body = Body::new("Hello, Sloth!".as_bytes());
+        let mut operations = Vec::new();
+        let mut backlinks = [None, None];
+        for (index, timestamp) in [0, 50, 100, 150, 200].into_iter().enumerate() {
+            let private_key = [&private_key_a, &private_key_b][index % 2];
+            let seq_num = (index / 2) as u64;
+            let (hash, header, header_bytes) =
+                create_operation(private_key, &body, seq_num, timestamp, backlinks[index % 2]);
+            backlinks[index % 2] = Some(hash);
+            store_b
+                .insert_operation(hash, &header, Some(&body), &header_bytes, &log_id)
+                .await
+                .unwrap();
+            operations.push(header_bytes);
+        }
+
+        let peer_a_protocol = Arc::new(
+            LogSyncProtocol::new(topic_map.clone(), MemoryStore::<u64>::default())
+                .delivery_order(DeliveryOrder::NewestFirst),
+        );
+        let peer_b_protocol = Arc::new(LogSyncProtocol::new(topic_map, store_b));
+        assert!(peer_a_protocol.newest_first());
+        assert!(!peer_b_protocol.newest_first());
+
+        let (peer_a_messages, _) =
+            run_session(peer_a_protocol, peer_b_protocol, vec![topic_query.clone()]).await;
+
+        // Entries of both logs arrive sorted by timestamp, most recent first.
+        let mut expected_messages = vec![FromSync::HandshakeSuccess(topic_query.clone())];
+        for header_bytes in operations.into_iter().rev() {
+            expected_messages.push(FromSync::Data {
+                header: header_bytes,
+                payload: Some(body.to_bytes()),
+            });
+        }
+
+        assert_eq!(peer_a_messages, expected_messages);
+    }
+
+    #[tokio::test]
+    async fn e2e_max_operations_per_session() {
+        let private_key = PrivateKey::new();
+        let log_id = 0;
+        let topic_query = LogHeightTopic::new("messages");
+        let mut topic_map = LogHeightTopicMap::new();
+        topic_map.insert(
+            &topic_query,
+            HashMap::from([(private_key.public_key(), vec![log_id])]),
+        );
+
+        let mut store_b = MemoryStore::default();
+        let body = Body::new("Hello, Sloth!".as_bytes());
+        let mut operations = Vec::new();
+        let mut backlink = None;
+        for seq_num in 0..3 {
+            let (hash, header, header_bytes) =
+                create_operation(&private_key, &body, seq_num, seq_num * 100, backlink);
+            backlink = Some(hash);
+            store_b
+                .insert_operation(hash, &header, Some(&body), &header_bytes, &log_id)
+                .await
+                .unwrap();
+            operations.push(header_bytes);
+        }
+
+        let store_a = MemoryStore::<u64>::default();
+        let peer_b_protocol = Arc::new(
+            LogSyncProtocol::new(topic_map.clone(), store_b).max_operations_per_session(2),
+        );
+
+        // Peer b only serves the first two operations per session.
+        let peer_a_protocol = Arc::new(LogSyncProtocol::new(topic_map, store_a.clone()));
+        let (peer_a_messages, _) = run_session(
+            peer_a_protocol.clone(),
+            peer_b_protocol.clone(),
+            vec![topic_query.clone()],
+        )
+        .await;
+        let expected_messages: Vec<FromSync<LogHeightTopic>> =
+            std::iter::once(FromSync::HandshakeSuccess(topic_query.clone()))
+                .chain(operations[..2].iter().map(|header_bytes| FromSync::Data {
+                    header: header_bytes.clone(),
+                    payload: Some(body.to_bytes()),
+                }))
+                .collect();
+        assert_eq!(peer_a_messages, expected_messages);
+
+        // Peers exempt from quotas are served all operations.
+        let (peer_a_messages, _) = run_session(
+            Arc::new(Exempt(peer_a_protocol)),
+            Arc::new(Exempt(peer_b_protocol)),
+            vec![topic_query.clone()],
+        )
+        .await;
+        assert_eq!(peer_a_messages.len(), 1 + operations.len());
+    }
+
+    #[tokio::test]
+    async fn e2e_max_operations_per_session_newest_first() {
+        let private_key = PrivateKey::new();
+        let log_id = 0;
+        let topic_query = LogHeightTopic::new("messages");
+        let mut topic_map = LogHeightTopicMap::new();
+        topic_map.insert(
+            &topic_query,
+            HashMap::from([(private_key.public_key(), vec![log_id])]),
+        );
+
+        let mut store_b = MemoryStore::default();
+        let body = Body::new("Hello, Sloth!".as_bytes());
+        let mut backlink = None;
+        for seq_num in 0..5 {
+            let (hash, header, header_bytes) =
+                create_operation(&private_key, &body, seq_num, seq_num * 100, backlink);
+            backlink = Some(hash);
+            store_b
+                .insert_operation(hash, &header, Some(&body), &header_bytes, &log_id)
+                .await
+                .unwrap();
+        }
+
+        let mut store_a = MemoryStore::<u64>::default();
+        let peer_a_protocol = Arc::new(
+            LogSyncProtocol::new(topic_map.clone(), store_a.clone())
+                .delivery_order(DeliveryOrder::NewestFirst),
+        );
+        let peer_b_protocol = Arc::new(
+            LogSyncProtocol::new(topic_map, store_b)
+                .delivery_order(DeliveryOrder::NewestFirst)
+                .max_operations_per_session(2),
+        );
+
+        // Peer a can only ingest operations whose backlink it knows, it catches up over several
+        // sessions as peer b serves the oldest missing operations first.
+        let mut next_seq_num = 0;
+        for _ in 0..3 {
+            let (peer_a_messages, _) = run_session(
+                peer_a_protocol.clone(),
+                peer_b_protocol.clone(),
+                vec![topic_query.clone()],
+            )
+            .await;
+
+            let mut headers: Vec<(Header, Vec<u8>)> = peer_a_messages
+                .into_iter()
+                .filter_map(|message| match message {
+                    FromSync::Data { header, .. } => {
+                        Some((decode_cbor(&header[..]).unwrap(), header))
+                    }
+                    _ => None,
+                })
+                .collect();
+            assert!(!headers.is_empty() && headers.len() <= 2);
+
+            // Operations are still delivered newest-first within a session.
+            assert!(headers.is_sorted_by(|(a, _), (b, _)| a.seq_num > b.seq_num));
+
+            headers.reverse();
+            for (header, header_bytes) in headers {
+                assert_eq!(header.seq_num, next_seq_num);
+                store_a
+                    .insert_operation(header.hash(), &header, Some(&body), &header_bytes, &log_id)
+                    .await
+                    .unwrap();
+                next_seq_num += 1;
+            }
+        }
+        assert_eq!(next_seq_num, 5);
+    }
+
+    /// Protocol accepting all sessions as if the remote peer was exempt from quotas.
+    #[derive(Debug)]
+    struct Exempt<P>(Arc<P>);
+
+    #[async_trait]
+    impl<'a, P> SyncProtocol<'a, LogHeightTopic> for Exempt<P>
+    where
+        P: SyncProtocol<'a, LogHeightTopic> + 'static,
+    {
+        fn name(&self) -> &'static str {
+            self.0.name()
+        }
+
+        async fn initiate(
+            self: Arc<Self>,
+            topic_query: LogHeightTopic,
+            tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
+            rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+            app_tx: Box<
+                &'a mut (dyn Sink<FromSync<LogHeightTopic>, Error = SyncError> + Send + Unpin),
+            >,
+        ) -> Result<(), SyncError> {
+            self.0.clone().initiate(topic_query, tx, rx, app_tx).await
+        }
+
+        async fn accept(
+            self: Arc<Self>,
+            tx: Box<&'a mut (dyn AsyncWrite + Send + Unpin)>,
+            rx: Box<&'a mut (dyn AsyncRead + Send + Unpin)>,
+            app_tx: Box<
+                &'a mut (dyn Sink<FromSync<LogHeightTopic>, Error = SyncError> + Send + Unpin),
+            >,
+        ) -> Result<(), SyncError> {
+            self.0.clone().accept_exempt(tx, rx, app_tx).await
+        }
+    }
+
+    #[tokio::test]
+    async fn e2e_batch_sync() {
+        let private_key_a = PrivateKey::new();
+        let private_key_b = PrivateKey::new();
+        let messages = LogHeightTopic::new("messages");
+        let comments = LogHeightTopic::new("comments");
+        let logs = HashMap::from([
+            (private_key_a.public_key(), vec![0]),
+            (private_key_b.public_key(), vec![1]),
+        ]);
+        let mut topic_map = LogHeightTopicMap::new();
+        topic_map.insert(&messages, logs.clone());
+        topic_map.insert(&comments, logs);
+
+        // Peer a holds data of log 0, peer b of log 1.
+        let body = Body::new("Hello, Sloth!".as_bytes());
+        let mut store_a = MemoryStore::default();
+        let (hash, header, header_bytes_a) = create_operation(&private_key_a, &body, 0, 0, None);
+        store_a
+            .insert_operation(hash, &header, Some(&body), &header_bytes_a, &0)
+            .await
+            .unwrap();
+        let mut store_b = MemoryStore::default();
+        let (hash, header, header_bytes_b) = create_operation(&private_key_b, &body, 0, 0, None);
+        store_b
+            .insert_operation(hash, &header, Some(&body), &header_bytes_b, &1)
+            .await
+            .unwrap();
+
+        let peer_a_protocol = Arc::new(LogSyncProtocol::new(topic_map.clone(), store_a));
+        let peer_b_protocol = Arc::new(LogSyncProtocol::new(topic_map, store_b));
+
+        let (peer_a_messages, peer_b_messages) = run_session(
+            peer_a_protocol,
+            peer_b_protocol,
+            vec![messages.clone(), comments.clone()],
+        )
+        .await;
+
+        // Both peers receive the data of every topic query, tagged with the topic it was synced
+        // for.
+        let expected_messages = |header_bytes: &Vec<u8>| {
+            vec![
+                FromSync::HandshakeSuccess(messages.clone()),
+                FromSync::HandshakeSuccess(comments.clone()),
+                FromSync::TopicData {
+                    topic: messages.clone(),
+                    header: header_bytes.clone(),
+                    payload: Some(body.to_bytes()),
+                },
+                FromSync::TopicData {
+                    topic: comments.clone(),
+                    header: header_bytes.clone(),
+                    payload: Some(body.to_bytes()),
+                },
+            ]
+        };
+        assert_eq!(peer_a_messages, expected_messages(&header_bytes_b));
+        assert_eq!(peer_b_messages, expected_messages(&header_bytes_a));
+    }
+
+    #[tokio::test]
+    async fn batch_with_unsupported_topic() {
+        let private_key = PrivateKey::new();
+        let messages = LogHeightTopic::new("messages");
+        let comments = LogHeightTopic::new("comments");
+        let logs = HashMap::from([(private_key.public_key(), vec![0])]);
+
+        // Peer b only supports one of the requested topic queries.
+        let mut topic_map_a = LogHeightTopicMap::new();
+        topic_map_a.insert(&messages, logs.clone());
+        topic_map_a.insert(&comments, logs.clone());
+        let mut topic_map_b = LogHeightTopicMap::new();
+        topic_map_b.insert(&messages, logs);
+
+        let peer_a_protocol = Arc::new(LogSyncProtocol::new(topic_map_a, MemoryStore::default()));
+        let peer_b_protocol = Arc::new(LogSyncProtocol::new(topic_map_b, MemoryStore::default()));
+
+        let (_, (result_b, peer_b_messages)) =
+            try_run_session(peer_a_protocol, peer_b_protocol, vec![messages, comments]).await;
+
+        // The whole batch is rejected before the handshake succeeds for any of its topics.
+        assert!(matches!(result_b, Err(SyncError::UnexpectedBehaviour(_))));
+        assert!(peer_b_messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn e2e_sync_estimate() {
+        let private_key = PrivateKey::new();
+        let log_id = 0;
+        let topic_query = LogHeightTopic::new("messages");
+        let mut topic_map = LogHeightTopicMap::new();
+        topic_map.insert(
+            &topic_query,
+            HashMap::from([(private_key.public_key(), vec![log_id])]),
+        );
+
+        let mut store_b = MemoryStore::default();
+        let body = Body::new("Hello, Sloth!".as_bytes());
+        let mut bytes = 0;
+        let mut backlink = None;
+        for seq_num in 0..3 {
+            let (hash, header, header_bytes) =
+                create_operation(&private_key, &body, seq_num, seq_num * 100, backlink);
+            backlink = Some(hash);
+            store_b
+                .insert_operation(hash, &header, Some(&body), &header_bytes, &log_id)
+                .await
+                .unwrap();
+            bytes += (header_bytes.len() + body.to_bytes().len()) as u64;
+        }
+
+        let peer_a_protocol = Arc::new(LogSyncProtocol::new(
+            topic_map.clone(),
+            MemoryStore::<u64>::default(),
+        ));
+        let peer_b_protocol = Arc::new(LogSyncProtocol::new(topic_map, store_b));
+
+        let (peer_a, peer_b) = tokio::io::duplex(64 * 1024);
+        let (peer_a_read, peer_a_write) = tokio::io::split(peer_a);
+        let (peer_b_read, peer_b_write) = tokio::io::split(peer_b);
+
+        let handle_1 = tokio::spawn(async move {
+            peer_a_protocol
+                .estimate(
+                    topic_query,
+                    Box::new(&mut peer_a_write.compat_write()),
+                    Box::new(&mut peer_a_read.compat()),
+                )
+                .await
+        });
+
+        let (peer_b_app_tx, mut peer_b_app_rx) = mpsc::channel(128);
+        let mut sink_b =
+            PollSender::new(peer_b_app_tx).sink_map_err(|err| SyncError::Critical(err.to_string()));
+        let handle_2 = tokio::spawn(async move {
+            peer_b_protocol
+                .accept(
+                    Box::new(&mut peer_b_write.compat_write()),
+                    Box::new(&mut peer_b_read.compat()),
+                    Box::new(&mut sink_b),
+                )
+                .await
+        });
+
+        let (result_1, result_2) = tokio::join!(handle_1, handle_2);
+        assert_eq!(
+            result_1.unwrap(),
+            Ok(SyncEstimate {
+                operations: 3,
+                bytes
+            })
+        );
+        assert_eq!(result_2.unwrap(), Ok(()));
+
+        // No data was exchanged during the dry run.
+        let mut peer_b_messages = Vec::new();
+        peer_b_app_rx.recv_many(&mut peer_b_messages, 128).await;
+        assert!(peer_b_messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn estimate_from_log_heads() {
+        let private_key = PrivateKey::new();
+        let public_key = private_key.public_key();
+        let log_id = 0;
+        let logs = HashMap::from([(public_key, vec![log_id])]);
+
+        let mut store = MemoryStore::default();
+        let body = Body::new("Hello, Sloth!".as_bytes());
+        let mut backlink = None;
+        for seq_num in 0..4 {
+            let (hash, header, header_bytes) =
+                create_operation(&private_key, &body, seq_num, seq_num * 100, backlink);
+            backlink = Some(hash);
+            store
+                .insert_operation(hash, &header, Some(&body), &header_bytes, &log_id)
+                .await
+                .unwrap();
+        }
+        let size = store.log_heads(&log_id).await.unwrap()[0].size;
+
+        // The remote peer doesn't know the log.
+        let estimate =
+            estimate_needed_by_remote(&store, &logs, HashMap::new(), &SyncFilter::default(), None)
+                .await
+                .unwrap();
+        assert_eq!(
+            estimate,
+            SyncEstimate {
+                operations: 4,
+                bytes: size
+            }
+        );
+
+        // The remote peer knows the first operation, the bytes of the missing ones are
+        // approximated by the average entry size.
+        let estimate = estimate_needed_by_remote(
+            &store,
+            &logs,
+            HashMap::from([(public_key, vec![(log_id, 0)])]),
+            &SyncFilter::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            estimate,
+            SyncEstimate {
+                operations: 3,
+                bytes: size * 3 / 4
+            }
+        );
+
+        // The maximum number of operations served per session is respected.
+        let estimate = estimate_needed_by_remote(
+            &store,
+            &logs,
+            HashMap::new(),
+            &SyncFilter::default(),
+            Some(2),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            estimate,
+            SyncEstimate {
+                operations: 2,
+                bytes: size / 2
+            }
+        );
+
+        // The remote peer is up-to-date.
+        let estimate = estimate_needed_by_remote(
+            &store,
+            &logs,
+            HashMap::from([(public_key, vec![(log_id, 3)])]),
+            &SyncFilter::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(estimate, SyncEstimate::default());
+    }
 }
@@ -299,7 +299,7 @@ impl<'a> SyncProtocol<'a, SyncTestTopic> for FailingProtocol {
             ));
         }
 
-        // Simulate unexpected behaviour (such as a broken pipe due to disconnection).
+        // Simulate unexpected behaviour, caught inside the sync session.
         if let FailingProtocol::InitiatorFailsUnexpected = *self {
             return Err(SyncError::UnexpectedBehaviour("bang!".to_string()));
         }
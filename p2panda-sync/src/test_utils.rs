@@ -0,0 +1,474 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Harness to test any `SyncProtocol` implementation over in-memory streams with injected faults.
+//!
+//! A `SyncTestHarness` runs an "initiator" and "acceptor" session against each other over an
+//! in-memory duplex stream. `Faults` can be injected into the bytes sent by either side to
+//! simulate slow links (delays), connections which silently end mid-message (truncation) or
+//! connections which break mid-message (disconnects).
+//!
+//! Every run is bounded by a timeout, a session which does not terminate fails the test. The
+//! returned `SyncRun` holds the results and all messages both sides sent to their application
+//! layer and offers assertions to check if both peers converged on the expected data.
+//!
+//! ```ignore
+//! let harness = SyncTestHarness::new(Arc::new(protocol_a), Arc::new(protocol_b), topic_query)
+//!     .initiator_faults(Faults::new().disconnect_after(128));
+//! let run = harness.run().await;
+//! run.assert_consistent(&expected_by_initiator, &expected_by_acceptor);
+//! ```
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::future::join;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncWrite, DuplexStream, WriteHalf};
+use tokio::time::Sleep;
+use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+use crate::{FromSync, SyncError, SyncFilter, SyncProtocol, TopicQuery};
+
+/// Default maximum duration of a sync run before it is considered to hang.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Capacity of the in-memory duplex stream in bytes.
+const DUPLEX_CAPACITY: usize = 64 * 1024;
+
+/// Header and optional payload of data forwarded to the application layer.
+pub type ReceivedData = (Vec<u8>, Option<Vec<u8>>);
+
+/// Faults injected into the bytes sent by one side of a sync session.
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    delay: Option<Duration>,
+    cut: Option<Cut>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Cut {
+    /// Close the stream after the given number of bytes and silently drop everything afterwards.
+    Truncate(usize),
+
+    /// Close the stream after the given number of bytes and fail all further writes.
+    Disconnect(usize),
+}
+
+impl Faults {
+    /// Returns an empty set of faults, passing all bytes through untouched.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delays every write by the given duration.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Ends the stream after the given number of bytes, while the sending side keeps on writing
+    /// without noticing that the data never arrives.
+    pub fn truncate_after(mut self, bytes: usize) -> Self {
+        self.cut = Some(Cut::Truncate(bytes));
+        self
+    }
+
+    /// Ends the stream after the given number of bytes, all further writes of the sending side
+    /// fail with a "broken pipe" error.
+    pub fn disconnect_after(mut self, bytes: usize) -> Self {
+        self.cut = Some(Cut::Disconnect(bytes));
+        self
+    }
+}
+
+/// Writer injecting the configured faults into the underlying stream.
+struct FaultyWriter {
+    inner: WriteHalf<DuplexStream>,
+    faults: Faults,
+    written: usize,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl FaultyWriter {
+    fn new(inner: WriteHalf<DuplexStream>, faults: Faults) -> Self {
+        Self {
+            inner,
+            faults,
+            written: 0,
+            sleep: None,
+        }
+    }
+}
+
+impl AsyncWrite for FaultyWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if let Some(delay) = self.faults.delay {
+            let sleep = self
+                .sleep
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep(delay)));
+            ready!(sleep.as_mut().poll(cx));
+        }
+
+        let len = match self.faults.cut {
+            Some(Cut::Truncate(limit) | Cut::Disconnect(limit)) if self.written >= limit => {
+                ready!(Pin::new(&mut self.inner).poll_shutdown(cx))?;
+                self.sleep = None;
+                return match self.faults.cut {
+                    Some(Cut::Disconnect(_)) => Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "injected disconnect",
+                    ))),
+                    _ => Poll::Ready(Ok(buf.len())),
+                };
+            }
+            Some(Cut::Truncate(limit) | Cut::Disconnect(limit)) => {
+                buf.len().min(limit - self.written)
+            }
+            None => buf.len(),
+        };
+
+        let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &buf[..len]))?;
+        self.written += written;
+        self.sleep = None;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Runs a sync session between an "initiator" and an "acceptor" over in-memory streams.
+pub struct SyncTestHarness<T> {
+    initiator: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
+    acceptor: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
+    topic_query: T,
+    filter: Option<SyncFilter>,
+    initiator_faults: Faults,
+    acceptor_faults: Faults,
+    timeout: Duration,
+}
+
+impl<T> SyncTestHarness<T>
+where
+    T: TopicQuery,
+{
+    pub fn new(
+        initiator: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
+        acceptor: Arc<dyn for<'a> SyncProtocol<'a, T> + 'static>,
+        topic_query: T,
+    ) -> Self {
+        Self {
+            initiator,
+            acceptor,
+            topic_query,
+            filter: None,
+            initiator_faults: Faults::default(),
+            acceptor_faults: Faults::default(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Initiates the session with the given filter.
+    pub fn filter(mut self, filter: SyncFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Injects faults into the bytes sent by the initiator.
+    pub fn initiator_faults(mut self, faults: Faults) -> Self {
+        self.initiator_faults = faults;
+        self
+    }
+
+    /// Injects faults into the bytes sent by the acceptor.
+    pub fn acceptor_faults(mut self, faults: Faults) -> Self {
+        self.acceptor_faults = faults;
+        self
+    }
+
+    /// Maximum duration of a run before it is considered to hang.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Runs both sides of the sync session to completion.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the session did not terminate within the configured timeout.
+    pub async fn run(&self) -> SyncRun<T> {
+        let (initiator_stream, acceptor_stream) = tokio::io::duplex(DUPLEX_CAPACITY);
+        let (initiator_read, initiator_write) = tokio::io::split(initiator_stream);
+        let (acceptor_read, acceptor_write) = tokio::io::split(acceptor_stream);
+
+        let (initiator_app_tx, initiator_app_rx) = mpsc::unbounded();
+        let (acceptor_app_tx, acceptor_app_rx) = mpsc::unbounded();
+
+        // Both sides own their ends of the stream, they get closed as soon as a session
+        // terminates, just like a transport would close the connection.
+        let initiator = {
+            let protocol = self.initiator.clone();
+            let topic_query = self.topic_query.clone();
+            let filter = self.filter.clone();
+            let mut tx =
+                FaultyWriter::new(initiator_write, self.initiator_faults.clone()).compat_write();
+            let mut rx = initiator_read.compat();
+            let mut app_tx =
+                initiator_app_tx.sink_map_err(|err| SyncError::Critical(err.to_string()));
+
+            async move {
+                match filter {
+                    Some(filter) => {
+                        protocol
+                            .initiate_with_filter(
+                                topic_query,
+                                filter,
+                                Box::new(&mut tx),
+                                Box::new(&mut rx),
+                                Box::new(&mut app_tx),
+                            )
+                            .await
+                    }
+                    None => {
+                        protocol
+                            .initiate(
+                                topic_query,
+                                Box::new(&mut tx),
+                                Box::new(&mut rx),
+                                Box::new(&mut app_tx),
+                            )
+                            .await
+                    }
+                }
+            }
+        };
+
+        let acceptor = {
+            let protocol = self.acceptor.clone();
+            let mut tx =
+                FaultyWriter::new(acceptor_write, self.acceptor_faults.clone()).compat_write();
+            let mut rx = acceptor_read.compat();
+            let mut app_tx =
+                acceptor_app_tx.sink_map_err(|err| SyncError::Critical(err.to_string()));
+
+            async move {
+                protocol
+                    .accept(Box::new(&mut tx), Box::new(&mut rx), Box::new(&mut app_tx))
+                    .await
+            }
+        };
+
+        let (initiator_result, acceptor_result) =
+            tokio::time::timeout(self.timeout, join(initiator, acceptor))
+                .await
+                .expect("sync session did not terminate in time");
+
+        SyncRun {
+            initiator_result,
+            acceptor_result,
+            initiator_messages: initiator_app_rx.collect().await,
+            acceptor_messages: acceptor_app_rx.collect().await,
+        }
+    }
+}
+
+/// Outcome of a sync session run by the `SyncTestHarness`.
+#[derive(Debug)]
+pub struct SyncRun<T>
+where
+    T: TopicQuery,
+{
+    pub initiator_result: Result<(), SyncError>,
+    pub acceptor_result: Result<(), SyncError>,
+
+    /// Messages the initiator sent to its application layer.
+    pub initiator_messages: Vec<FromSync<T>>,
+
+    /// Messages the acceptor sent to its application layer.
+    pub acceptor_messages: Vec<FromSync<T>>,
+}
+
+impl<T> SyncRun<T>
+where
+    T: TopicQuery,
+{
+    /// Returns the data the initiator received from the acceptor.
+    pub fn received_by_initiator(&self) -> Vec<ReceivedData> {
+        received_data(&self.initiator_messages)
+    }
+
+    /// Returns the data the acceptor received from the initiator.
+    pub fn received_by_acceptor(&self) -> Vec<ReceivedData> {
+        received_data(&self.acceptor_messages)
+    }
+
+    /// Asserts that both sessions succeeded and both peers received exactly the expected data,
+    /// independent of the order it arrived in.
+    pub fn assert_converged(
+        &self,
+        expected_by_initiator: &[ReceivedData],
+        expected_by_acceptor: &[ReceivedData],
+    ) {
+        assert!(
+            self.initiator_result.is_ok(),
+            "initiator session failed: {:?}",
+            self.initiator_result
+        );
+        assert!(
+            self.acceptor_result.is_ok(),
+            "acceptor session failed: {:?}",
+            self.acceptor_result
+        );
+        assert_eq!(
+            sorted(self.received_by_initiator()),
+            sorted(expected_by_initiator.to_vec()),
+            "initiator did not converge"
+        );
+        assert_eq!(
+            sorted(self.received_by_acceptor()),
+            sorted(expected_by_acceptor.to_vec()),
+            "acceptor did not converge"
+        );
+    }
+
+    /// Asserts that both peers only received expected data and nothing twice, even if the
+    /// sessions failed half-way through.
+    ///
+    /// This is the invariant every protocol needs to uphold under injected faults: the sessions
+    /// terminate and whatever made it through is valid.
+    pub fn assert_consistent(
+        &self,
+        expected_by_initiator: &[ReceivedData],
+        expected_by_acceptor: &[ReceivedData],
+    ) {
+        assert_subset(
+            self.received_by_initiator(),
+            expected_by_initiator,
+            "initiator",
+        );
+        assert_subset(
+            self.received_by_acceptor(),
+            expected_by_acceptor,
+            "acceptor",
+        );
+    }
+}
+
+fn received_data<T>(messages: &[FromSync<T>]) -> Vec<ReceivedData>
+where
+    T: TopicQuery,
+{
+    messages
+        .iter()
+        .filter_map(|message| match message {
+            FromSync::HandshakeSuccess(_) => None,
+            FromSync::Data { header, payload }
+            | FromSync::TopicData {
+                header, payload, ..
+            } => Some((header.clone(), payload.clone())),
+        })
+        .collect()
+}
+
+fn sorted(mut data: Vec<ReceivedData>) -> Vec<ReceivedData> {
+    data.sort();
+    data
+}
+
+fn assert_subset(received: Vec<ReceivedData>, expected: &[ReceivedData], role: &str) {
+    let received = sorted(received);
+    for window in received.windows(2) {
+        assert_ne!(window[0], window[1], "{role} received data twice");
+    }
+    for item in &received {
+        assert!(expected.contains(item), "{role} received unexpected data");
+    }
+}
+
+#[cfg(all(test, feature = "test-protocols"))]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::test_protocols::{PingPongProtocol, SyncTestTopic};
+
+    use super::{Faults, ReceivedData, SyncTestHarness};
+
+    fn harness() -> SyncTestHarness<SyncTestTopic> {
+        SyncTestHarness::new(
+            Arc::new(PingPongProtocol {}),
+            Arc::new(PingPongProtocol {}),
+            SyncTestTopic::new("ping_pong"),
+        )
+    }
+
+    fn expected() -> (Vec<ReceivedData>, Vec<ReceivedData>) {
+        (
+            vec![(b"PONG".to_vec(), None)],
+            vec![(b"PING".to_vec(), None)],
+        )
+    }
+
+    #[tokio::test]
+    async fn converge_without_faults() {
+        let (by_initiator, by_acceptor) = expected();
+        harness()
+            .run()
+            .await
+            .assert_converged(&by_initiator, &by_acceptor);
+    }
+
+    #[tokio::test]
+    async fn converge_with_delays() {
+        let (by_initiator, by_acceptor) = expected();
+        harness()
+            .initiator_faults(Faults::new().delay(Duration::from_millis(5)))
+            .acceptor_faults(Faults::new().delay(Duration::from_millis(5)))
+            .run()
+            .await
+            .assert_converged(&by_initiator, &by_acceptor);
+    }
+
+    #[tokio::test]
+    async fn terminate_on_truncation_and_disconnect() {
+        let (by_initiator, by_acceptor) = expected();
+
+        for bytes in 0..32 {
+            let run = harness()
+                .initiator_faults(Faults::new().truncate_after(bytes))
+                .run()
+                .await;
+            run.assert_consistent(&by_initiator, &by_acceptor);
+
+            let run = harness()
+                .acceptor_faults(Faults::new().disconnect_after(bytes))
+                .run()
+                .await;
+            run.assert_consistent(&by_initiator, &by_acceptor);
+        }
+    }
+
+    #[tokio::test]
+    async fn disconnect_mid_message_fails_session() {
+        // The topic query alone is longer than a single byte.
+        let run = harness()
+            .initiator_faults(Faults::new().disconnect_after(1))
+            .run()
+            .await;
+        assert!(run.initiator_result.is_err());
+        assert!(run.received_by_initiator().is_empty());
+    }
+}